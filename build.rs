@@ -0,0 +1,11 @@
+//! Build script; generates the gRPC service code when the `grpc` feature is on
+
+fn main() {
+    // The gRPC service is generated from proto/omni.proto; building it needs `protoc`
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/omni.proto");
+        tonic_build::compile_protos("proto/omni.proto")
+            .expect("Failed to compile proto/omni.proto");
+    }
+}
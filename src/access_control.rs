@@ -0,0 +1,401 @@
+use crate::distro;
+use crate::error_handling::OmniError;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+use tracing::{debug, warn};
+
+/// Linux security module confining processes on this host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MacSystem {
+    SELinux,
+    AppArmor,
+}
+
+impl MacSystem {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MacSystem::SELinux => "SELinux",
+            MacSystem::AppArmor => "AppArmor",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MacMode {
+    /// Denials are blocked
+    Enforcing,
+    /// Denials are only logged (SELinux permissive, AppArmor complain)
+    Permissive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MacStatus {
+    pub system: MacSystem,
+    pub mode: MacMode,
+}
+
+/// Active security module, detected once per process
+pub fn status() -> Option<&'static MacStatus> {
+    static STATUS: OnceLock<Option<MacStatus>> = OnceLock::new();
+    STATUS.get_or_init(detect).as_ref()
+}
+
+pub fn is_enforcing() -> bool {
+    status().is_some_and(|s| s.mode == MacMode::Enforcing)
+}
+
+fn detect() -> Option<MacStatus> {
+    if let Ok(enforce) = fs::read_to_string("/sys/fs/selinux/enforce") {
+        let mode = if enforce.trim() == "1" {
+            MacMode::Enforcing
+        } else {
+            MacMode::Permissive
+        };
+        return Some(MacStatus {
+            system: MacSystem::SELinux,
+            mode,
+        });
+    }
+
+    let enabled = fs::read_to_string("/sys/module/apparmor/parameters/enabled").ok()?;
+    if enabled.trim() != "Y" {
+        return None;
+    }
+    // The profile list is root-only; assume enforcing when it cannot be read
+    let mode = match fs::read_to_string("/sys/kernel/security/apparmor/profiles") {
+        Ok(profiles) if !profiles.lines().any(|l| l.ends_with("(enforce)")) => MacMode::Permissive,
+        _ => MacMode::Enforcing,
+    };
+    Some(MacStatus {
+        system: MacSystem::AppArmor,
+        mode,
+    })
+}
+
+/// Give `path` the SELinux label policy expects for its location. Files written
+/// elsewhere and moved keep their old label, which confined package managers
+/// are then denied access to.
+pub fn relabel(path: &Path) {
+    if status().map(|s| s.system) != Some(MacSystem::SELinux)
+        || !distro::command_exists("restorecon")
+    {
+        return;
+    }
+
+    match Command::new("restorecon").arg("-R").arg(path).output() {
+        Ok(output) if !output.status.success() => debug!(
+            "restorecon {} failed: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => debug!("Could not run restorecon: {}", e),
+        _ => {}
+    }
+}
+
+/// Refuse operations the active policy is known to block, and warn about ones
+/// it may break, before anything is downloaded or changed
+pub fn preflight(box_type: &str) -> Result<()> {
+    let Some(status) = status().filter(|s| s.mode == MacMode::Enforcing) else {
+        return Ok(());
+    };
+
+    match (status.system, box_type) {
+        // Without user_exec_content, confined users cannot execute anything
+        // they wrote to their home directory
+        (MacSystem::SELinux, "appimage") if selinux_boolean("user_exec_content") == Some(false) => {
+            Err(OmniError::AccessControlDenied {
+                system: status.system.as_str().to_string(),
+                operation: "running AppImages from your home directory".to_string(),
+                suggestions: vec![
+                    "Allow it: sudo setsebool -P user_exec_content on".to_string(),
+                    "Or install the application with flatpak instead".to_string(),
+                ],
+            }
+            .into())
+        }
+        (MacSystem::AppArmor, "appimage") if restricts_user_namespaces() => {
+            warn!(
+                "AppArmor restricts unprivileged user namespaces; Electron-based AppImages \
+                 may need --no-sandbox or an AppArmor profile granting `userns`"
+            );
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn selinux_boolean(name: &str) -> Option<bool> {
+    let value = fs::read_to_string(format!("/sys/fs/selinux/booleans/{}", name)).ok()?;
+    // Current and pending values, e.g. "0 0"
+    Some(value.split_whitespace().next()? == "1")
+}
+
+fn restricts_user_namespaces() -> bool {
+    fs::read_to_string("/proc/sys/kernel/apparmor_restrict_unprivileged_userns")
+        .is_ok_and(|v| v.trim() == "1")
+}
+
+/// One access denial from the audit or kernel log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Denial {
+    pub system: MacSystem,
+    /// Denied permission or operation, e.g. `write` or `open`
+    pub permission: String,
+    pub process: Option<String>,
+    /// File name or path the process tried to access
+    pub target: Option<String>,
+    /// SELinux type of the target, e.g. `user_home_t`
+    pub target_type: Option<String>,
+    /// SELinux type of the process, e.g. `rpm_t`
+    pub source_type: Option<String>,
+    /// SELinux object class, e.g. `file` or `tcp_socket`
+    pub class: Option<String>,
+    /// AppArmor profile that denied the access
+    pub profile: Option<String>,
+}
+
+/// SELinux types of files that landed somewhere unexpected and need relabelling
+const MISLABELED_TYPES: [&str; 6] = [
+    "user_home_t",
+    "user_tmp_t",
+    "cache_home_t",
+    "tmp_t",
+    "unlabeled_t",
+    "default_t",
+];
+
+/// Booleans that grant commonly denied accesses: (source type, target class, boolean)
+const SELINUX_BOOLEANS: [(&str, &str, &str); 4] = [
+    ("httpd_t", "tcp_socket", "httpd_can_network_connect"),
+    ("httpd_t", "file", "httpd_read_user_content"),
+    ("user_t", "file", "user_exec_content"),
+    ("staff_t", "file", "user_exec_content"),
+];
+
+impl Denial {
+    pub fn suggestions(&self) -> Vec<String> {
+        let mut suggestions = Vec::new();
+        let target = self.target.as_deref().unwrap_or("<path>");
+
+        match self.system {
+            MacSystem::SELinux => {
+                if self
+                    .target_type
+                    .as_deref()
+                    .is_some_and(|t| MISLABELED_TYPES.contains(&t))
+                {
+                    suggestions.push(format!(
+                        "Restore the default label: sudo restorecon -Rv {}",
+                        target
+                    ));
+                }
+                for (source, _, boolean) in SELINUX_BOOLEANS.iter().filter(|(source, class, _)| {
+                    self.source_type.as_deref() == Some(*source)
+                        && self.class.as_deref() == Some(*class)
+                }) {
+                    suggestions.push(format!(
+                        "If {} should be allowed this access: sudo setsebool -P {} on",
+                        source, boolean
+                    ));
+                }
+                suggestions.push(
+                    "See why it was denied: sudo ausearch -m AVC -ts recent | audit2why"
+                        .to_string(),
+                );
+                suggestions.push(
+                    "As a last resort, build a local policy module: sudo ausearch -m AVC -ts recent | audit2allow -M omni-local && sudo semodule -i omni-local.pp"
+                        .to_string(),
+                );
+            }
+            MacSystem::AppArmor => {
+                let profile = self.profile.as_deref().unwrap_or("<profile>");
+                // Path-named profiles live in files named after the path
+                let profile_file = profile.trim_start_matches('/').replace('/', ".");
+                suggestions.push(format!(
+                    "Allow the access in /etc/apparmor.d/local/{} and reload with: sudo apparmor_parser -r /etc/apparmor.d/{}",
+                    profile_file, profile_file
+                ));
+                suggestions.push(format!(
+                    "Or log instead of blocking while you investigate: sudo aa-complain {}",
+                    profile
+                ));
+            }
+        }
+
+        suggestions
+    }
+
+    pub fn to_error(&self) -> OmniError {
+        let mut operation = format!(
+            "{} {}",
+            self.process.as_deref().unwrap_or("process"),
+            self.permission
+        );
+        if let Some(target) = &self.target {
+            operation.push_str(&format!(" on {}", target));
+        }
+        if let Some(target_type) = &self.target_type {
+            operation.push_str(&format!(" ({})", target_type));
+        }
+
+        OmniError::AccessControlDenied {
+            system: self.system.as_str().to_string(),
+            operation,
+            suggestions: self.suggestions(),
+        }
+    }
+}
+
+/// Parse an SELinux AVC record (`avc:  denied  { write } for ... tcontext=...`) or
+/// an AppArmor audit line (`apparmor="DENIED" operation="open" profile=...`)
+pub fn parse_denial(line: &str) -> Option<Denial> {
+    if line.contains("avc:") && line.contains("denied") {
+        let start = line.find("{ ")? + 2;
+        let end = start + line[start..].find(" }")?;
+        let context_type =
+            |key: &str| field(line, key).and_then(|c| c.split(':').nth(2).map(str::to_string));
+
+        return Some(Denial {
+            system: MacSystem::SELinux,
+            permission: line[start..end].trim().to_string(),
+            process: field(line, "comm"),
+            target: field(line, "path").or_else(|| field(line, "name")),
+            target_type: context_type("tcontext"),
+            source_type: context_type("scontext"),
+            class: field(line, "tclass"),
+            profile: None,
+        });
+    }
+
+    if line.contains("apparmor=\"DENIED\"") {
+        return Some(Denial {
+            system: MacSystem::AppArmor,
+            permission: field(line, "operation")?,
+            process: field(line, "comm"),
+            target: field(line, "name"),
+            target_type: None,
+            source_type: None,
+            class: field(line, "class"),
+            profile: field(line, "profile"),
+        });
+    }
+
+    None
+}
+
+/// Value of ` key=value` or ` key="value"` in an audit record
+fn field(line: &str, key: &str) -> Option<String> {
+    let pattern = format!(" {}=", key);
+    let start = line.find(&pattern)? + pattern.len();
+    let rest = &line[start..];
+    let value = match rest.strip_prefix('"') {
+        Some(quoted) => &quoted[..quoted.find('"')?],
+        None => rest.split_whitespace().next()?,
+    };
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Denials logged in the last few minutes, oldest first. `ausearch` needs root;
+/// the kernel log is the fallback and also covers hosts without auditd.
+pub fn recent_denials() -> Vec<Denial> {
+    let sources: [(&str, &[&str]); 3] = [
+        (
+            "ausearch",
+            &["-m", "AVC,USER_AVC", "-ts", "recent", "--raw"],
+        ),
+        ("journalctl", &["-k", "-o", "cat", "--since", "-5min"]),
+        ("dmesg", &[]),
+    ];
+
+    for (command, args) in sources {
+        if !distro::command_exists(command) {
+            continue;
+        }
+        let Ok(output) = Command::new(command).args(args).output() else {
+            continue;
+        };
+        let denials: Vec<Denial> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(parse_denial)
+            .collect();
+        if !denials.is_empty() {
+            return denials;
+        }
+    }
+
+    Vec::new()
+}
+
+/// When the security module is enforcing and `err` looks like a permission
+/// failure, attach the matching denial so the user sees what was blocked and
+/// how to allow it instead of a bare "Permission denied"
+pub fn explain(err: anyhow::Error) -> anyhow::Error {
+    if !is_enforcing() {
+        return err;
+    }
+
+    let text = format!("{:#}", err);
+    // Package managers sometimes echo the denial themselves
+    if let Some(denial) = text.lines().find_map(parse_denial) {
+        return err.context(denial.to_error());
+    }
+
+    let permission_failure = text.contains("Permission denied")
+        || text.contains("Operation not permitted")
+        || matches!(
+            OmniError::classify(&err),
+            OmniError::PermissionDenied { .. }
+        );
+    if !permission_failure {
+        return err;
+    }
+
+    match recent_denials().pop() {
+        Some(denial) => err.context(denial.to_error()),
+        None => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selinux_denial() {
+        let line = r#"type=AVC msg=audit(1700000000.123:456): avc:  denied  { read } for  pid=4242 comm="rpm" name="foo.rpm" dev="dm-0" ino=1234 scontext=unconfined_u:unconfined_r:rpm_t:s0 tcontext=unconfined_u:object_r:user_home_t:s0 tclass=file permissive=0"#;
+        let denial = parse_denial(line).unwrap();
+        assert_eq!(denial.system, MacSystem::SELinux);
+        assert_eq!(denial.permission, "read");
+        assert_eq!(denial.process.as_deref(), Some("rpm"));
+        assert_eq!(denial.target.as_deref(), Some("foo.rpm"));
+        assert_eq!(denial.target_type.as_deref(), Some("user_home_t"));
+        assert_eq!(denial.source_type.as_deref(), Some("rpm_t"));
+        assert!(denial.suggestions()[0].contains("restorecon -Rv foo.rpm"));
+
+        let error = denial.to_error();
+        assert_eq!(
+            error.to_string(),
+            "SELinux denied rpm read on foo.rpm (user_home_t)"
+        );
+        assert_eq!(error.error_code(), "OMNI_SEC_003");
+    }
+
+    #[test]
+    fn test_parse_apparmor_denial() {
+        let line = r#"audit: type=1400 audit(1700000000.123:77): apparmor="DENIED" operation="open" class="file" profile="/usr/sbin/cupsd" name="/opt/driver/ppd" pid=900 comm="cupsd" requested_mask="r" denied_mask="r" fsuid=0 ouid=0"#;
+        let denial = parse_denial(line).unwrap();
+        assert_eq!(denial.system, MacSystem::AppArmor);
+        assert_eq!(denial.permission, "open");
+        assert_eq!(denial.profile.as_deref(), Some("/usr/sbin/cupsd"));
+        assert!(denial.suggestions()[0].contains("/etc/apparmor.d/local/usr.sbin.cupsd"));
+
+        assert!(parse_denial("apparmor=\"STATUS\" operation=\"profile_load\"").is_none());
+        assert!(parse_denial("Permission denied").is_none());
+    }
+}
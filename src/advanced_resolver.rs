@@ -1,8 +1,15 @@
 use crate::database::Database;
+use crate::distro;
+use crate::downgrade::compare_versions;
 use crate::error_handling::OmniError;
-use anyhow::Result;
+use crate::input_validation::InputValidator;
+use crate::sat::{Lit, Outcome, Solver};
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::process::Command;
 use tracing::{info, warn};
 
 /// Advanced dependency resolver with conflict resolution
@@ -10,6 +17,7 @@ use tracing::{info, warn};
 pub struct AdvancedDependencyResolver {
     db: Database,
     strategy: ResolutionStrategy,
+    box_type: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,7 +46,7 @@ pub struct PackageAction {
     pub dependencies: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ActionType {
     Install,
     Upgrade,
@@ -69,145 +77,258 @@ pub enum Impact {
     Critical,
 }
 
-impl AdvancedDependencyResolver {
-    pub async fn new() -> Result<Self> {
-        let db = Database::new().await?;
-        Ok(Self {
-            db,
-            strategy: ResolutionStrategy::Conservative,
-        })
-    }
+/// A dependency on a package, or on a name packages provide, optionally at
+/// some versions only
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Requirement {
+    pub name: String,
+    pub constraint: Option<(Comparison, String)>,
+}
 
-    pub fn with_strategy(mut self, strategy: ResolutionStrategy) -> Self {
-        self.strategy = strategy;
-        self
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
 
-    /// Create a comprehensive resolution plan
-    pub async fn create_resolution_plan(&self, packages: &[String]) -> Result<ResolutionPlan> {
-        info!("Creating resolution plan for {} packages", packages.len());
+/// One installable version of a package
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub name: String,
+    pub version: String,
+    pub installed: bool,
+    /// Each entry is met by any one of its alternatives (`a | b`)
+    pub depends: Vec<Vec<Requirement>>,
+    pub conflicts: Vec<Requirement>,
+    /// Other names this version answers to, with the version it provides
+    /// them at, if any
+    pub provides: Vec<Requirement>,
+}
 
-        let mut plan = ResolutionPlan {
-            packages: Vec::new(),
-            conflicts: Vec::new(),
-            recommendations: Vec::new(),
-            total_size: 0,
-            estimated_time: std::time::Duration::from_secs(60),
-        };
+/// A change asked of the resolver
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Job {
+    Install(Requirement),
+    Upgrade(String),
+    Remove(String),
+}
 
-        // Analyze each requested package
-        let mut analyzed_packages = HashSet::new();
-        let mut dependency_graph = HashMap::new();
+impl Comparison {
+    pub fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+            Comparison::Greater => ordering == Ordering::Greater,
+        }
+    }
 
-        for package in packages {
-            self.analyze_package_recursive(package, &mut analyzed_packages, &mut dependency_graph)
-                .await?;
-        }
-
-        // Build package actions based on analysis
-        for (package, deps) in dependency_graph.iter() {
-            let action = PackageAction {
-                package: package.clone(),
-                action: ActionType::Install,
-                version: None,
-                reason: "User requested".to_string(),
-                dependencies: deps.clone(),
-            };
-            plan.packages.push(action);
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Greater => ">",
         }
+    }
+}
 
-        // Detect conflicts
-        plan.conflicts = self.detect_conflicts(&plan.packages).await?;
+impl Requirement {
+    pub fn any(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            constraint: None,
+        }
+    }
 
-        // Generate recommendations based on strategy
-        plan.recommendations = self.generate_recommendations(&plan.packages).await?;
+    /// Parse `name`, `name>=1.2`, `name >= 1.2` (rpm) or `name (>= 1.2)`
+    /// (Debian); an `:arch` qualifier on the name is dropped
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (name, constraint) = match text.find(['(', '<', '>', '=']) {
+            Some(at) => (&text[..at], text[at..].trim_matches(['(', ')', ' '])),
+            None => (text, ""),
+        };
+        let name = name.trim();
+        let name = name.split_once(':').map_or(name, |(name, _)| name);
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return None;
+        }
 
-        // Calculate estimates
-        plan.total_size = self.estimate_total_size(&plan.packages).await?;
-        plan.estimated_time = self.estimate_installation_time(&plan.packages).await?;
+        let constraint = if constraint.is_empty() {
+            None
+        } else {
+            let operators = [
+                (">=", Comparison::GreaterOrEqual),
+                ("<=", Comparison::LessOrEqual),
+                (">>", Comparison::Greater),
+                ("<<", Comparison::Less),
+                ("==", Comparison::Equal),
+                ("=", Comparison::Equal),
+                (">", Comparison::Greater),
+                ("<", Comparison::Less),
+            ];
+            let (comparison, version) = operators.iter().find_map(|(symbol, comparison)| {
+                constraint
+                    .strip_prefix(symbol)
+                    .map(|version| (*comparison, version.trim()))
+            })?;
+            if version.is_empty() {
+                return None;
+            }
+            Some((comparison, version.to_string()))
+        };
 
-        info!(
-            "Resolution plan created with {} packages, {} conflicts",
-            plan.packages.len(),
-            plan.conflicts.len()
-        );
+        Some(Self {
+            name: name.to_string(),
+            constraint,
+        })
+    }
 
-        Ok(plan)
+    fn allows(&self, version: &str) -> bool {
+        self.constraint
+            .as_ref()
+            .is_none_or(|(comparison, wanted)| comparison.holds(compare_versions(version, wanted)))
     }
 
-    /// Recursively analyze a package and its dependencies
-    fn analyze_package_recursive<'a>(
-        &'a self,
-        package: &'a str,
-        analyzed: &'a mut HashSet<String>,
-        dependency_graph: &'a mut HashMap<String, Vec<String>>,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>> {
-        Box::pin(async move {
-            if analyzed.contains(package) {
-                return Ok(());
-            }
+    /// Whether `candidate` is, or provides, a package this accepts; a
+    /// versioned requirement is only met by a versioned provide
+    pub fn matches(&self, candidate: &Candidate) -> bool {
+        if candidate.name == self.name {
+            return self.allows(&candidate.version);
+        }
+        candidate.provides.iter().any(|provided| {
+            provided.name == self.name
+                && match (&self.constraint, &provided.constraint) {
+                    (None, _) => true,
+                    (Some(_), Some((Comparison::Equal, version))) => self.allows(version),
+                    _ => false,
+                }
+        })
+    }
+}
 
-            analyzed.insert(package.to_string());
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.constraint {
+            Some((comparison, version)) => {
+                write!(f, "{} {} {}", self.name, comparison.symbol(), version)
+            }
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
 
-            // Get package dependencies (simplified - would integrate with package manager)
-            let deps = self.get_package_dependencies(package).await?;
-            dependency_graph.insert(package.to_string(), deps.clone());
+impl Job {
+    fn package(&self) -> &str {
+        match self {
+            Job::Install(requirement) => &requirement.name,
+            Job::Upgrade(name) | Job::Remove(name) => name,
+        }
+    }
+}
 
-            // Recursively analyze dependencies
-            for dep in deps {
-                self.analyze_package_recursive(&dep, analyzed, dependency_graph)
-                    .await?;
-            }
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Job::Install(requirement) => write!(f, "installing {}", requirement),
+            Job::Upgrade(name) => write!(f, "upgrading {}", name),
+            Job::Remove(name) => write!(f, "removing {}", name),
+        }
+    }
+}
 
-            Ok(())
+impl AdvancedDependencyResolver {
+    pub async fn new() -> Result<Self> {
+        let db = Database::new().await?;
+        Ok(Self {
+            db,
+            strategy: ResolutionStrategy::Conservative,
+            box_type: distro::detect_distro(),
         })
     }
 
-    /// Get dependencies for a package (simplified implementation)
-    async fn get_package_dependencies(&self, package: &str) -> Result<Vec<String>> {
-        // This would integrate with actual package managers to get real dependencies
-        // For now, return common dependencies based on package patterns
-        match package {
-            p if p.contains("python") => Ok(vec![
-                "python3-pip".to_string(),
-                "python3-setuptools".to_string(),
-            ]),
-            p if p.contains("node") => Ok(vec!["npm".to_string()]),
-            p if p.contains("docker") => Ok(vec!["containerd".to_string(), "runc".to_string()]),
-            p if p.contains("git") => Ok(vec!["curl".to_string(), "ca-certificates".to_string()]),
-            _ => Ok(vec![]),
+    pub fn with_strategy(mut self, strategy: ResolutionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Resolve against another box's packages than the system's own
+    pub fn with_box(mut self, box_type: &str) -> Self {
+        self.box_type = box_type.to_string();
+        self
+    }
+
+    /// Create a comprehensive resolution plan
+    pub async fn create_resolution_plan(&self, packages: &[String]) -> Result<ResolutionPlan> {
+        let mut jobs = Vec::new();
+        for package in packages {
+            let spec = InputValidator::parse_package_spec(package)?;
+            match spec.constraint {
+                Some(constraint) => jobs.extend(constraint.bounds.into_iter().map(|bound| {
+                    Job::Install(Requirement {
+                        name: spec.name.clone(),
+                        constraint: Some(bound),
+                    })
+                })),
+                None => jobs.push(Job::Install(Requirement::any(&spec.name))),
+            }
         }
+        self.plan(&jobs).await
     }
 
-    /// Detect conflicts between packages
-    async fn detect_conflicts(&self, packages: &[PackageAction]) -> Result<Vec<Conflict>> {
-        let mut conflicts = Vec::new();
+    /// Plan upgrading `packages` to their newest installable versions
+    pub async fn create_upgrade_plan(&self, packages: &[String]) -> Result<ResolutionPlan> {
+        let jobs: Vec<Job> = packages.iter().cloned().map(Job::Upgrade).collect();
+        self.plan(&jobs).await
+    }
 
-        // Check for common conflict patterns
-        let package_names: HashSet<_> = packages.iter().map(|p| &p.package).collect();
+    /// Plan carrying out `jobs` while changing as little else as possible
+    pub async fn plan(&self, jobs: &[Job]) -> Result<ResolutionPlan> {
+        info!("Creating resolution plan for {} jobs", jobs.len());
 
-        // Example conflicts
-        if package_names.contains(&"python2".to_string())
-            && package_names.contains(&"python3".to_string())
-        {
-            conflicts.push(Conflict {
-                packages: vec!["python2".to_string(), "python3".to_string()],
-                reason: "Python 2 and Python 3 may conflict in some configurations".to_string(),
-                suggestions: vec!["Consider using only Python 3".to_string()],
-            });
-        }
+        let candidates = load_candidates(&self.box_type, jobs)?;
+        let (packages, conflicts) = resolve(&candidates, jobs);
 
-        if package_names.contains(&"docker".to_string())
-            && package_names.contains(&"podman".to_string())
-        {
-            conflicts.push(Conflict {
-                packages: vec!["docker".to_string(), "podman".to_string()],
-                reason: "Docker and Podman may conflict over container runtime".to_string(),
-                suggestions: vec!["Choose one container runtime".to_string()],
+        let mut plan = ResolutionPlan {
+            recommendations: self.generate_recommendations(&packages).await?,
+            total_size: self.estimate_total_size(&packages).await?,
+            estimated_time: self.estimate_installation_time(&packages).await?,
+            packages,
+            conflicts,
+        };
+        let removed: Vec<&str> = plan
+            .packages
+            .iter()
+            .filter(|action| {
+                action.action == ActionType::Remove
+                    && !jobs.contains(&Job::Remove(action.package.clone()))
+            })
+            .map(|action| action.package.as_str())
+            .collect();
+        if !removed.is_empty() {
+            plan.recommendations.push(Recommendation {
+                message: format!(
+                    "The plan removes installed packages you did not ask to remove: {}",
+                    removed.join(", ")
+                ),
+                confidence: 1.0,
+                impact: Impact::High,
             });
         }
 
-        Ok(conflicts)
+        info!(
+            "Resolution plan created with {} packages, {} conflicts",
+            plan.packages.len(),
+            plan.conflicts.len()
+        );
+
+        Ok(plan)
     }
 
     /// Generate recommendations based on strategy
@@ -308,17 +429,6 @@ impl AdvancedDependencyResolver {
         Ok(std::time::Duration::from_secs(total_seconds.max(base_time)))
     }
 
-    /// Analyze a single package (legacy method for compatibility)
-    async fn analyze_package(&self, package: &str) -> Result<PackageAction> {
-        Ok(PackageAction {
-            package: package.to_string(),
-            action: ActionType::Install,
-            version: None,
-            reason: "User requested".to_string(),
-            dependencies: self.get_package_dependencies(package).await?,
-        })
-    }
-
     /// Execute a resolution plan
     pub async fn execute_plan(&self, plan: &ResolutionPlan) -> Result<()> {
         info!(
@@ -334,3 +444,1000 @@ impl AdvancedDependencyResolver {
         Ok(())
     }
 }
+
+/// Solve `jobs` against `candidates`: the changes to make, or why the jobs
+/// cannot be carried out together.
+///
+/// Each candidate is a variable; at most one version of a package is
+/// selected, a selected version needs one of the alternatives of each of its
+/// dependencies and excludes what it conflicts with. Of the plans that
+/// satisfy the jobs, the one chosen keeps installed packages wherever it can
+/// and installs nothing more than needed: dropping any of its changes would
+/// break a job or a dependency.
+pub fn resolve(candidates: &[Candidate], jobs: &[Job]) -> (Vec<PackageAction>, Vec<Conflict>) {
+    let mut solver = Solver::new();
+    for candidate in candidates {
+        solver.new_var(candidate.installed);
+    }
+
+    // Candidates under each name they go by, their own or a provided one
+    let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        by_name.entry(&candidate.name).or_default().push(i);
+        for provided in &candidate.provides {
+            by_name.entry(&provided.name).or_default().push(i);
+        }
+    }
+    let providers = |requirement: &Requirement| -> Vec<usize> {
+        let mut found: Vec<usize> = by_name
+            .get(requirement.name.as_str())
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|&i| requirement.matches(&candidates[i]))
+            .collect();
+        found.dedup();
+        found
+    };
+    let versions = |name: &str| -> Vec<usize> {
+        by_name
+            .get(name)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|&i| candidates[i].name == name)
+            .collect()
+    };
+
+    for (i, candidate) in candidates.iter().enumerate() {
+        for &other in &versions(&candidate.name) {
+            if other > i {
+                solver.add_clause(&[Lit::negative(i), Lit::negative(other)]);
+            }
+        }
+        for alternatives in &candidate.depends {
+            let mut clause = vec![Lit::negative(i)];
+            clause.extend(alternatives.iter().flat_map(&providers).map(Lit::positive));
+            solver.add_clause(&clause);
+        }
+        for conflict in &candidate.conflicts {
+            // A package may conflict with a name it provides itself
+            for other in providers(conflict) {
+                if candidates[other].name != candidate.name {
+                    solver.add_clause(&[Lit::negative(i), Lit::negative(other)]);
+                }
+            }
+        }
+    }
+
+    // Each job is switched on by a selector variable, so a failed solve
+    // reports which jobs clash
+    let mut selectors = Vec::new();
+    for job in jobs {
+        let selector = solver.new_var(false);
+        selectors.push(Lit::positive(selector));
+        match job {
+            Job::Install(requirement) => {
+                let mut clause = vec![Lit::negative(selector)];
+                clause.extend(providers(requirement).into_iter().map(Lit::positive));
+                solver.add_clause(&clause);
+            }
+            Job::Upgrade(name) => {
+                let mut clause = vec![Lit::negative(selector)];
+                clause.extend(upgrade_targets(candidates, &versions(name)).map(Lit::positive));
+                solver.add_clause(&clause);
+            }
+            Job::Remove(name) => {
+                for version in versions(name) {
+                    solver.add_clause(&[Lit::negative(selector), Lit::negative(version)]);
+                }
+            }
+        }
+    }
+
+    // Whether each installed package stays installed at some version
+    let mut kept = Vec::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        if candidate.installed && !kept.iter().any(|&(name, _)| name == candidate.name) {
+            let keep = solver.new_var(false);
+            let mut clause = vec![Lit::negative(keep)];
+            clause.extend(versions(&candidate.name).into_iter().map(Lit::positive));
+            solver.add_clause(&clause);
+            kept.push((candidates[i].name.as_str(), keep));
+        }
+    }
+
+    let mut model = match solver.solve(&selectors) {
+        Outcome::Satisfiable(model) => model,
+        Outcome::Unsatisfiable(core) => {
+            let failed: Vec<&Job> = jobs
+                .iter()
+                .zip(&selectors)
+                .filter(|(_, selector)| core.contains(selector))
+                .map(|(job, _)| job)
+                .collect();
+            return (Vec::new(), vec![explain(candidates, &failed, &providers)]);
+        }
+    };
+
+    // Settle preferences one at a time, most important first, keeping each
+    // one the jobs still allow
+    let mut fixed = selectors;
+    let mut prefer = |solver: &mut Solver, model: &mut Vec<bool>, lit: Lit| -> bool {
+        fixed.push(lit);
+        if model[lit.var()] != lit.is_negative() {
+            return true;
+        }
+        match solver.solve(&fixed) {
+            Outcome::Satisfiable(found) => {
+                *model = found;
+                true
+            }
+            Outcome::Unsatisfiable(_) => {
+                fixed.pop();
+                false
+            }
+        }
+    };
+
+    // The requested packages at the installed version if it will do,
+    // otherwise the newest that can be installed
+    for job in jobs {
+        let mut targets: Vec<usize> = match job {
+            Job::Install(requirement) => providers(requirement),
+            Job::Upgrade(name) => upgrade_targets(candidates, &versions(name)).collect(),
+            Job::Remove(_) => continue,
+        };
+        targets.sort_by(|&a, &b| {
+            candidates[b]
+                .installed
+                .cmp(&candidates[a].installed)
+                .then_with(|| compare_versions(&candidates[b].version, &candidates[a].version))
+        });
+        for target in targets {
+            if prefer(&mut solver, &mut model, Lit::positive(target)) {
+                break;
+            }
+        }
+    }
+
+    // Remove as little as possible, then keep installed versions, then
+    // install as little as possible; older versions are ruled out first so a
+    // package that is needed comes in at its newest version
+    for &(_, keep) in &kept {
+        prefer(&mut solver, &mut model, Lit::positive(keep));
+    }
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&candidates[a], &candidates[b]);
+        b.installed
+            .cmp(&a.installed)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| compare_versions(&a.version, &b.version))
+    });
+    for i in order {
+        let lit = if candidates[i].installed {
+            Lit::positive(i)
+        } else {
+            Lit::negative(i)
+        };
+        prefer(&mut solver, &mut model, lit);
+    }
+
+    (
+        plan_actions(candidates, jobs, &model, &providers),
+        Vec::new(),
+    )
+}
+
+/// Versions an upgrade may move to: the ones newer than the installed
+/// version, or just the installed one when it is the newest
+fn upgrade_targets<'a>(
+    candidates: &'a [Candidate],
+    versions: &'a [usize],
+) -> impl Iterator<Item = usize> + 'a {
+    let installed = versions
+        .iter()
+        .find(|&&i| candidates[i].installed)
+        .map(|&i| candidates[i].version.as_str());
+    let newer = move |i: &usize| {
+        installed.is_none_or(|installed| {
+            compare_versions(&candidates[*i].version, installed) == Ordering::Greater
+        })
+    };
+    let any_newer = versions.iter().any(newer);
+    versions
+        .iter()
+        .copied()
+        .filter(move |i| newer(i) || (!any_newer && candidates[*i].installed))
+}
+
+/// Actions taking the system from what is installed to `model`, removals
+/// first and every package after the ones it depends on
+fn plan_actions(
+    candidates: &[Candidate],
+    jobs: &[Job],
+    model: &[bool],
+    providers: &dyn Fn(&Requirement) -> Vec<usize>,
+) -> Vec<PackageAction> {
+    let selected = |i: usize| model[i];
+    let requested = |name: &str| jobs.iter().any(|job| job.package() == name);
+
+    let mut names: Vec<&str> = Vec::new();
+    for candidate in candidates {
+        if !names.contains(&candidate.name.as_str()) {
+            names.push(&candidate.name);
+        }
+    }
+
+    let mut removals = Vec::new();
+    let mut changes: Vec<(usize, PackageAction)> = Vec::new();
+    for name in names {
+        let versions: Vec<usize> = (0..candidates.len())
+            .filter(|&i| candidates[i].name == name)
+            .collect();
+        let before = versions.iter().copied().find(|&i| candidates[i].installed);
+        let after = versions.iter().copied().find(|&i| selected(i));
+
+        let needed_by = |i: usize| {
+            (0..candidates.len())
+                .filter(|&j| selected(j) && j != i)
+                .find(|&j| {
+                    candidates[j]
+                        .depends
+                        .iter()
+                        .flatten()
+                        .any(|requirement| requirement.matches(&candidates[i]))
+                })
+                .map(|j| format!("Needed by {}", candidates[j].name))
+        };
+
+        let (action, reason) = match (before, after) {
+            (None, None) => continue,
+            (Some(old), None) => {
+                let reason = if requested(name) {
+                    "Requested".to_string()
+                } else {
+                    (0..candidates.len())
+                        .filter(|&j| selected(j))
+                        .find(|&j| {
+                            conflicts_with(&candidates[j], &candidates[old])
+                                || conflicts_with(&candidates[old], &candidates[j])
+                        })
+                        .map(|j| format!("Conflicts with {}", candidates[j].name))
+                        .unwrap_or_else(|| "Its dependencies can no longer be met".to_string())
+                };
+                removals.push(PackageAction {
+                    package: name.to_string(),
+                    action: ActionType::Remove,
+                    version: Some(candidates[old].version.clone()),
+                    reason,
+                    dependencies: Vec::new(),
+                });
+                continue;
+            }
+            (None, Some(new)) => (
+                ActionType::Install,
+                if requested(name) {
+                    "Requested".to_string()
+                } else {
+                    needed_by(new).unwrap_or_else(|| "Needed by the requested packages".to_string())
+                },
+            ),
+            (Some(old), Some(new)) if old == new => {
+                if !requested(name) {
+                    continue;
+                }
+                (ActionType::Keep, "Already installed".to_string())
+            }
+            (Some(old), Some(new)) => {
+                let action =
+                    match compare_versions(&candidates[new].version, &candidates[old].version) {
+                        Ordering::Less => ActionType::Downgrade,
+                        _ => ActionType::Upgrade,
+                    };
+                let reason = if requested(name) {
+                    "Requested".to_string()
+                } else {
+                    needed_by(new).unwrap_or_else(|| "Needed by the other changes".to_string())
+                };
+                (action, reason)
+            }
+        };
+
+        let new = after.expect("installed after the change");
+        let dependencies = candidates[new]
+            .depends
+            .iter()
+            .filter_map(|alternatives| {
+                alternatives
+                    .iter()
+                    .flat_map(providers)
+                    .find(|&j| selected(j))
+                    .map(|j| candidates[j].name.clone())
+            })
+            .collect();
+        changes.push((
+            new,
+            PackageAction {
+                package: name.to_string(),
+                action,
+                version: Some(candidates[new].version.clone()),
+                reason,
+                dependencies,
+            },
+        ));
+    }
+
+    // Dependencies before their dependents
+    let mut ordered = removals;
+    let mut placed = HashSet::new();
+    fn place(
+        index: usize,
+        changes: &[(usize, PackageAction)],
+        placed: &mut HashSet<usize>,
+        ordered: &mut Vec<PackageAction>,
+    ) {
+        if !placed.insert(index) {
+            return;
+        }
+        for dependency in &changes[index].1.dependencies {
+            if let Some(next) = changes.iter().position(|(_, a)| &a.package == dependency) {
+                place(next, changes, placed, ordered);
+            }
+        }
+        ordered.push(changes[index].1.clone());
+    }
+    for index in 0..changes.len() {
+        place(index, &changes, &mut placed, &mut ordered);
+    }
+    ordered
+}
+
+fn conflicts_with(candidate: &Candidate, other: &Candidate) -> bool {
+    candidate.name != other.name
+        && candidate
+            .conflicts
+            .iter()
+            .any(|conflict| conflict.matches(other))
+}
+
+/// Why `failed` jobs cannot be carried out together
+fn explain(
+    candidates: &[Candidate],
+    failed: &[&Job],
+    providers: &dyn Fn(&Requirement) -> Vec<usize>,
+) -> Conflict {
+    let packages: Vec<String> = failed.iter().map(|job| job.package().to_string()).collect();
+    let suggestions = failed
+        .iter()
+        .map(|job| format!("Leave out {}", job))
+        .collect();
+
+    // Everything each job could pull in
+    let closure = |job: &Job| -> Vec<usize> {
+        let mut stack: Vec<usize> = match job {
+            Job::Install(requirement) => providers(requirement),
+            Job::Upgrade(name) => providers(&Requirement::any(name)),
+            Job::Remove(_) => Vec::new(),
+        };
+        let mut reached = HashSet::new();
+        while let Some(i) = stack.pop() {
+            if reached.insert(i) {
+                stack.extend(candidates[i].depends.iter().flatten().flat_map(providers));
+            }
+        }
+        let mut reached: Vec<usize> = reached.into_iter().collect();
+        reached.sort_unstable();
+        reached
+    };
+
+    if let [job] = failed {
+        let reached = closure(job);
+        if reached.is_empty() {
+            return Conflict {
+                packages,
+                reason: match job {
+                    Job::Install(requirement) => {
+                        format!("No available package provides {}", requirement)
+                    }
+                    _ => format!("{} is not available", job.package()),
+                },
+                suggestions: vec![format!(
+                    "Search for the package: omni search {}",
+                    job.package()
+                )],
+            };
+        }
+
+        let unmet: Vec<String> = reached
+            .iter()
+            .flat_map(|&i| {
+                candidates[i]
+                    .depends
+                    .iter()
+                    .filter(|alternatives| {
+                        alternatives
+                            .iter()
+                            .all(|requirement| providers(requirement).is_empty())
+                    })
+                    .map(move |alternatives| {
+                        let needed: Vec<String> =
+                            alternatives.iter().map(ToString::to_string).collect();
+                        format!(
+                            "{} {} needs {}",
+                            candidates[i].name,
+                            candidates[i].version,
+                            needed.join(" | ")
+                        )
+                    })
+            })
+            .collect();
+        let reason = if unmet.is_empty() {
+            format!("Cannot carry out {}: its dependencies conflict", job)
+        } else {
+            format!(
+                "Cannot carry out {}: {}, which no available package provides",
+                job,
+                unmet.join("; ")
+            )
+        };
+        return Conflict {
+            packages,
+            reason,
+            suggestions,
+        };
+    }
+
+    let closures: Vec<Vec<usize>> = failed.iter().map(|job| closure(job)).collect();
+    let mut clashes = Vec::new();
+    for (a, first) in closures.iter().enumerate() {
+        for second in &closures[a + 1..] {
+            for &i in first {
+                for &j in second {
+                    if conflicts_with(&candidates[i], &candidates[j])
+                        || conflicts_with(&candidates[j], &candidates[i])
+                    {
+                        let clash = format!(
+                            "{} conflicts with {}",
+                            candidates[i].name, candidates[j].name
+                        );
+                        if !clashes.contains(&clash) {
+                            clashes.push(clash);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let jobs: Vec<String> = failed.iter().map(ToString::to_string).collect();
+    let mut reason = format!("Cannot carry out {} together", jobs.join(" and "));
+    if !clashes.is_empty() {
+        reason = format!("{}: {}", reason, clashes.join("; "));
+    }
+    Conflict {
+        packages,
+        reason,
+        suggestions,
+    }
+}
+
+/// Every version of the packages the jobs name and of everything those
+/// could depend on or conflict with, fetched from the box's package manager
+fn load_candidates(box_type: &str, jobs: &[Job]) -> Result<Vec<Candidate>> {
+    if !matches!(box_type, "apt" | "dnf" | "pacman") {
+        return Err(anyhow!(
+            "Dependency solving is not supported for {} packages",
+            box_type
+        ));
+    }
+
+    let installed = installed_versions(box_type);
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut pending: Vec<String> = jobs.iter().map(|job| job.package().to_string()).collect();
+    let mut candidates: Vec<Candidate> = Vec::new();
+
+    // One query per level of the dependency graph
+    while !pending.is_empty() {
+        pending.retain(|name| seen.insert(name.clone()));
+        if pending.is_empty() {
+            break;
+        }
+
+        let mut found = query_candidates(box_type, &pending);
+        for name in &pending {
+            if !found.iter().any(|c| &c.name == name) {
+                // A virtual name: whatever provides it joins the next level
+                found.extend(query_candidates(box_type, &query_providers(box_type, name)));
+            }
+        }
+
+        let mut next = Vec::new();
+        for candidate in &mut found {
+            candidate.installed = installed.get(&candidate.name).is_some_and(|version| {
+                compare_versions(version, &candidate.version) == Ordering::Equal
+            });
+            seen.insert(candidate.name.clone());
+            for requirement in candidate
+                .depends
+                .iter()
+                .flatten()
+                .chain(&candidate.conflicts)
+            {
+                if !seen.contains(&requirement.name) && !next.contains(&requirement.name) {
+                    next.push(requirement.name.clone());
+                }
+            }
+        }
+        for candidate in found {
+            let duplicate = candidates
+                .iter()
+                .any(|c| c.name == candidate.name && c.version == candidate.version);
+            if !duplicate {
+                candidates.push(candidate);
+            }
+        }
+        pending = next;
+    }
+
+    Ok(candidates)
+}
+
+fn installed_versions(box_type: &str) -> HashMap<String, String> {
+    let listing = match box_type {
+        "apt" => output(
+            "dpkg-query",
+            &["-W", "-f=${Package}\t${Version}\t${db:Status-Abbrev}\n"],
+        ),
+        "dnf" => output(
+            "rpm",
+            &["-qa", "--qf", "%{NAME}\t%{VERSION}-%{RELEASE}\tii\n"],
+        ),
+        "pacman" => output("pacman", &["-Q"])
+            .replace(' ', "\t")
+            .replace('\n', "\tii\n"),
+        _ => String::new(),
+    };
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?.split(':').next()?;
+            let version = fields.next()?;
+            fields
+                .next()?
+                .starts_with("ii")
+                .then(|| (name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+fn query_candidates(box_type: &str, names: &[String]) -> Vec<Candidate> {
+    if names.is_empty() {
+        return Vec::new();
+    }
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    match box_type {
+        "apt" => {
+            let mut args = vec!["show"];
+            args.extend(&names);
+            parse_apt_show(&output("apt-cache", &args))
+        }
+        "dnf" => {
+            let format = "Package: %{name}\nVersion: %{evr}\nRequires:\n%{requires}\n\
+                          Conflicts:\n%{conflicts}\nProvides:\n%{provides}\n\n";
+            let mut args = vec!["repoquery", "--quiet", "--qf", format];
+            args.extend(&names);
+            parse_dnf_query(&output("dnf", &args))
+        }
+        "pacman" => {
+            let mut sync = vec!["-Si"];
+            sync.extend(&names);
+            let mut local = vec!["-Qi"];
+            local.extend(&names);
+            let mut found = parse_pacman_info(&output("pacman", &sync));
+            found.extend(parse_pacman_info(&output("pacman", &local)));
+            found
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Providers of `name` when `box_type` only knows it as a virtual package
+/// (`mta`, `java-runtime`); None for a real package or an unknown name
+pub fn virtual_providers(box_type: &str, name: &str) -> Option<Vec<String>> {
+    let real = match box_type {
+        // apt-cache show fails for a purely virtual package
+        "apt" => Command::new("apt-cache")
+            .args(["show", "--no-all-versions", name])
+            .output()
+            .is_ok_and(|output| output.status.success() && !output.stdout.is_empty()),
+        "dnf" => !output("dnf", &["repoquery", "--quiet", "--qf", "%{name}\n", name])
+            .trim()
+            .is_empty(),
+        _ => return None,
+    };
+    if real {
+        return None;
+    }
+    let providers = query_providers(box_type, name);
+    (!providers.is_empty()).then_some(providers)
+}
+
+/// Packages that provide the virtual name `name`
+pub(crate) fn query_providers(box_type: &str, name: &str) -> Vec<String> {
+    let mut providers: Vec<String> = match box_type {
+        "apt" => output("apt-cache", &["showpkg", name])
+            .lines()
+            .skip_while(|line| !line.starts_with("Reverse Provides:"))
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().next().map(str::to_string))
+            .collect(),
+        "dnf" => output(
+            "dnf",
+            &[
+                "repoquery",
+                "--quiet",
+                "--whatprovides",
+                name,
+                "--qf",
+                "%{name}\n",
+            ],
+        )
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect(),
+        _ => Vec::new(),
+    };
+    providers.dedup();
+    providers
+}
+
+/// Standard output of a command that exits non-zero when some of the names
+/// it was given are unknown, which still lists the others
+fn output(command: &str, args: &[&str]) -> String {
+    if !distro::command_exists(command) {
+        return String::new();
+    }
+    match Command::new(command).args(args).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(e) => {
+            warn!("Could not run {}: {}", command, e);
+            String::new()
+        }
+    }
+}
+
+fn candidate(name: &str, version: &str) -> Candidate {
+    Candidate {
+        name: name.to_string(),
+        version: version.to_string(),
+        installed: false,
+        depends: Vec::new(),
+        conflicts: Vec::new(),
+        provides: Vec::new(),
+    }
+}
+
+fn requirements(list: &str, separator: char) -> Vec<Requirement> {
+    list.split(separator)
+        .filter_map(Requirement::parse)
+        .collect()
+}
+
+/// `apt-cache show` stanzas, one per available version
+fn parse_apt_show(output: &str) -> Vec<Candidate> {
+    let mut found = Vec::new();
+    for stanza in output.split("\n\n") {
+        let mut fields: HashMap<&str, &str> = HashMap::new();
+        for line in stanza.lines() {
+            if let Some((key, value)) = line.split_once(": ") {
+                if !line.starts_with(' ') {
+                    fields.insert(key, value.trim());
+                }
+            }
+        }
+        let (Some(name), Some(version)) = (fields.get("Package"), fields.get("Version")) else {
+            continue;
+        };
+
+        let mut package = candidate(name, version);
+        for key in ["Pre-Depends", "Depends"] {
+            if let Some(list) = fields.get(key) {
+                package
+                    .depends
+                    .extend(list.split(',').map(|group| requirements(group, '|')));
+            }
+        }
+        for key in ["Conflicts", "Breaks"] {
+            if let Some(list) = fields.get(key) {
+                package.conflicts.extend(requirements(list, ','));
+            }
+        }
+        if let Some(list) = fields.get("Provides") {
+            package.provides = requirements(list, ',');
+        }
+        package.depends.retain(|group| !group.is_empty());
+        found.push(package);
+    }
+    found
+}
+
+/// `pacman -Si` or `pacman -Qi` output
+fn parse_pacman_info(output: &str) -> Vec<Candidate> {
+    let mut found = Vec::new();
+    for stanza in output.split("\n\n") {
+        let mut fields: HashMap<&str, &str> = HashMap::new();
+        for line in stanza.lines() {
+            if let Some((key, value)) = line.split_once(" : ") {
+                if !line.starts_with(' ') {
+                    fields.insert(key.trim(), value.trim());
+                }
+            }
+        }
+        let (Some(name), Some(version)) = (fields.get("Name"), fields.get("Version")) else {
+            continue;
+        };
+
+        let list = |key: &str| -> Vec<Requirement> {
+            match fields.get(key) {
+                Some(&"None") | None => Vec::new(),
+                Some(list) => requirements(list, ' '),
+            }
+        };
+        let mut package = candidate(name, version);
+        package.depends = list("Depends On").into_iter().map(|r| vec![r]).collect();
+        package.conflicts = list("Conflicts With");
+        package.provides = list("Provides");
+        found.push(package);
+    }
+    found
+}
+
+/// Output of `dnf repoquery` with the query format `query_candidates` uses
+fn parse_dnf_query(output: &str) -> Vec<Candidate> {
+    let mut found: Vec<Candidate> = Vec::new();
+    let mut section = "";
+    for line in output.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix("Package: ") {
+            found.push(candidate(name, ""));
+            section = "";
+            continue;
+        }
+        let Some(package) = found.last_mut() else {
+            continue;
+        };
+        if let Some(version) = line.strip_prefix("Version: ") {
+            package.version = version.to_string();
+        } else if matches!(line, "Requires:" | "Conflicts:" | "Provides:") {
+            section = line;
+        } else if line.is_empty()
+            || line.starts_with('/')
+            || line.starts_with("rpmlib(")
+            || line.contains('(')
+        {
+            // Files, rpm features, sonames and rich dependencies are left to dnf
+        } else if let Some(requirement) = Requirement::parse(line) {
+            match section {
+                "Requires:" => package.depends.push(vec![requirement]),
+                "Conflicts:" => package.conflicts.push(requirement),
+                "Provides:" => package.provides.push(requirement),
+                _ => {}
+            }
+        }
+    }
+    found.retain(|package| !package.version.is_empty());
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, version: &str, installed: bool, depends: &[&str]) -> Candidate {
+        let mut package = candidate(name, version);
+        package.installed = installed;
+        package.depends = depends
+            .iter()
+            .map(|group| requirements(group, '|'))
+            .collect();
+        package
+    }
+
+    fn summary(actions: &[PackageAction]) -> Vec<String> {
+        actions
+            .iter()
+            .map(|a| {
+                format!(
+                    "{:?} {} {}",
+                    a.action,
+                    a.package,
+                    a.version.as_deref().unwrap_or("")
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_requirement() {
+        assert_eq!(
+            Requirement::parse("libc6 (>= 2.34)"),
+            Some(Requirement {
+                name: "libc6".to_string(),
+                constraint: Some((Comparison::GreaterOrEqual, "2.34".to_string())),
+            })
+        );
+        assert_eq!(
+            Requirement::parse("glibc>=2.38"),
+            Requirement::parse("glibc >= 2.38")
+        );
+        assert_eq!(
+            Requirement::parse("python3:any (<< 3.13)")
+                .unwrap()
+                .constraint,
+            Some((Comparison::Less, "3.13".to_string()))
+        );
+        assert_eq!(Requirement::parse("nginx"), Some(Requirement::any("nginx")));
+        assert_eq!(Requirement::parse("nginx >="), None);
+        assert_eq!(Requirement::parse(""), None);
+    }
+
+    #[test]
+    fn test_alternatives_and_provides() {
+        let mut mta = package("postfix", "3.8", false, &[]);
+        mta.provides = vec![Requirement::any("mail-transport-agent")];
+        let mut exim = package("exim4", "4.97", true, &[]);
+        exim.provides = vec![Requirement::any("mail-transport-agent")];
+        let candidates = vec![
+            package(
+                "mutt",
+                "2.2",
+                false,
+                &["mail-transport-agent", "libc | musl"],
+            ),
+            mta,
+            exim,
+            package("libc", "2.38", false, &[]),
+            package("musl", "1.2", true, &[]),
+        ];
+
+        // The installed provider and the installed alternative are used
+        let (actions, conflicts) = resolve(&candidates, &[Job::Install(Requirement::any("mutt"))]);
+        assert!(conflicts.is_empty());
+        assert_eq!(summary(&actions), vec!["Install mutt 2.2"]);
+        assert_eq!(actions[0].dependencies, vec!["exim4", "musl"]);
+
+        // Removing the provider brings in the other one
+        let (actions, _) = resolve(
+            &candidates,
+            &[
+                Job::Install(Requirement::any("mutt")),
+                Job::Remove("exim4".to_string()),
+            ],
+        );
+        assert_eq!(
+            summary(&actions),
+            vec![
+                "Remove exim4 4.97",
+                "Install postfix 3.8",
+                "Install mutt 2.2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_upgrade_is_minimal() {
+        let mut candidates = vec![
+            package("app", "1.0", true, &["libfoo (>= 1)"]),
+            package("app", "2.0", false, &["libfoo (>= 2)"]),
+            package("libfoo", "1.0", true, &[]),
+            package("libfoo", "2.0", false, &[]),
+            package("libfoo", "3.0", false, &[]),
+            package("tool", "1.0", true, &["libfoo (<< 2)"]),
+            package("tool", "1.1", false, &["libfoo (<< 4)"]),
+            package("unrelated", "1.0", true, &[]),
+            package("unrelated", "1.5", false, &[]),
+        ];
+
+        // The library moves to its newest version, the tool that pinned it
+        // to 1.x follows, and nothing else changes
+        let (actions, conflicts) = resolve(&candidates, &[Job::Upgrade("app".to_string())]);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            summary(&actions),
+            vec!["Upgrade libfoo 3.0", "Upgrade app 2.0", "Upgrade tool 1.1"]
+        );
+        assert_eq!(actions[0].reason, "Needed by app");
+
+        // Without a newer tool, it has to go
+        candidates.remove(6);
+        let (actions, _) = resolve(&candidates, &[Job::Upgrade("app".to_string())]);
+        assert_eq!(
+            summary(&actions),
+            vec!["Remove tool 1.0", "Upgrade libfoo 3.0", "Upgrade app 2.0"]
+        );
+        assert_eq!(actions[0].reason, "Its dependencies can no longer be met");
+    }
+
+    #[test]
+    fn test_conflicts_are_explained() {
+        let mut docker = package("docker-ce", "27.0", false, &["containerd.io"]);
+        docker.conflicts = vec![Requirement::any("podman-docker")];
+        let candidates = vec![
+            docker,
+            package("containerd.io", "1.7", false, &[]),
+            package("podman-docker", "5.0", true, &[]),
+            package("broken", "1.0", false, &["libmissing (>= 2)"]),
+        ];
+
+        // An installed package in the way is removed
+        let (actions, _) = resolve(&candidates, &[Job::Install(Requirement::any("docker-ce"))]);
+        assert_eq!(actions[0].action, ActionType::Remove);
+        assert_eq!(actions[0].reason, "Conflicts with docker-ce");
+
+        // Only the request that cannot work is reported
+        let (actions, conflicts) = resolve(
+            &candidates,
+            &[
+                Job::Install(Requirement::any("docker-ce")),
+                Job::Install(Requirement::any("broken")),
+                Job::Install(Requirement::any("podman-docker")),
+            ],
+        );
+        assert!(actions.is_empty());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].packages, vec!["broken"]);
+        assert_eq!(
+            conflicts[0].reason,
+            "Cannot carry out installing broken: broken 1.0 needs libmissing >= 2, \
+             which no available package provides"
+        );
+
+        // Asking for both at once cannot work
+        let (_, conflicts) = resolve(
+            &candidates,
+            &[
+                Job::Install(Requirement::any("docker-ce")),
+                Job::Install(Requirement::any("podman-docker")),
+            ],
+        );
+        assert_eq!(
+            conflicts[0].reason,
+            "Cannot carry out installing docker-ce and installing podman-docker together: \
+             docker-ce conflicts with podman-docker"
+        );
+    }
+
+    #[test]
+    fn test_parse_listings() {
+        let apt = "Package: nginx\nVersion: 1.24.0-2\nDepends: libc6 (>= 2.34), nginx-common (= 1.24.0-2), libssl3 | libssl1.1\nBreaks: nginx-core (<< 1.22)\nProvides: httpd\nDescription: web server\n small and fast\n\n\
+                   Package: nginx\nVersion: 1.22.1-9\nDepends: libc6\n";
+        let found = parse_apt_show(apt);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].depends.len(), 3);
+        assert_eq!(found[0].depends[2].len(), 2);
+        assert_eq!(found[0].conflicts[0].name, "nginx-core");
+        assert_eq!(found[0].provides, vec![Requirement::any("httpd")]);
+        assert_eq!(found[1].version, "1.22.1-9");
+
+        let pacman = "Repository      : extra\nName            : nginx\nVersion         : 1.26.1-1\nDepends On      : glibc  openssl>=3  pcre2\nOptional Deps   : None\nProvides        : None\nConflicts With  : nginx-mainline\n\n";
+        let found = parse_pacman_info(pacman);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].depends.len(), 3);
+        assert_eq!(
+            found[0].depends[1][0].constraint,
+            Some((Comparison::GreaterOrEqual, "3".to_string()))
+        );
+        assert!(found[0].provides.is_empty());
+
+        let dnf = "Package: nginx\nVersion: 2:1.24.0-1.fc39\nRequires:\n/bin/sh\nlibc.so.6()(64bit)\nnginx-filesystem = 2:1.24.0-1.fc39\nsystem-logos-httpd\nConflicts:\n\nProvides:\nwebserver\n\n";
+        let found = parse_dnf_query(dnf);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].version, "2:1.24.0-1.fc39");
+        assert_eq!(found[0].depends.len(), 2);
+        assert_eq!(found[0].provides, vec![Requirement::any("webserver")]);
+    }
+}
@@ -0,0 +1,495 @@
+//! gRPC control interface (`omni serve --grpc`) for orchestration tools that
+//! want typed calls and streamed progress. The service is defined in
+//! `proto/omni.proto` and shares the REST API's bearer tokens and roles.
+
+use crate::authorization::{self, Caller, Permission, UserStore};
+use crate::brain::OmniBrain;
+use crate::config::{self, OmniConfig};
+use crate::database::{Database, InstallRecord};
+use crate::lock::OperationLock;
+use crate::mock;
+use crate::search::SearchResult;
+use crate::server;
+use crate::updater::{UpdateCandidate, UpdateManager};
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("omni.v1");
+}
+
+use proto::package_service_server::{PackageService, PackageServiceServer};
+use proto::{
+    HistoryEntry, HistoryRequest, HistoryResponse, ListInstalledRequest, ListInstalledResponse,
+    Package, PackageRequest, Progress, SearchRequest, SearchResponse, Stage, UpdateRequest,
+};
+
+type ProgressSender = mpsc::Sender<Result<Progress, Status>>;
+
+#[derive(Clone)]
+pub struct OmniService {
+    brain: Arc<Mutex<OmniBrain>>,
+    config: OmniConfig,
+    mock: bool,
+}
+
+impl OmniService {
+    pub fn new(config: OmniConfig, mock: bool) -> Self {
+        Self {
+            brain: Arc::new(Mutex::new(OmniBrain::new_with_mock(mock))),
+            config,
+            mock,
+        }
+    }
+
+    /// Refuse changes in read-only mode and while another omni process is
+    /// busy, like the REST API
+    fn begin_change(&self, operation: &str) -> Result<Option<OperationLock>, Status> {
+        config::ensure_writable(operation).map_err(|e| Status::permission_denied(e.to_string()))?;
+        if self.mock {
+            return Ok(None);
+        }
+        match OperationLock::try_acquire().map_err(internal)? {
+            Some(lock) => Ok(Some(lock)),
+            None => Err(Status::unavailable(
+                "Another omni process is busy; try again when it finishes",
+            )),
+        }
+    }
+
+    /// Install or remove `request.package` in the background, streaming its progress
+    fn change(
+        &self,
+        request: PackageRequest,
+        remove: bool,
+    ) -> Result<Response<ReceiverStream<Result<Progress, Status>>>, Status> {
+        let lock = self.begin_change(if remove {
+            "removing packages"
+        } else {
+            "installing packages"
+        })?;
+        let (tx, rx) = mpsc::channel(16);
+        let brain = self.brain.clone();
+
+        tokio::spawn(async move {
+            let _lock = lock;
+            let package = request.package;
+            let verb = if remove { "Removing" } else { "Installing" };
+            send(
+                &tx,
+                Stage::Running,
+                &package,
+                format!("{} {}", verb, package),
+                0,
+            )
+            .await;
+
+            let result = {
+                let mut brain = brain.lock().await;
+                if remove {
+                    brain.remove(&package, request.box_type.as_deref()).await
+                } else {
+                    brain.install(&package, request.box_type.as_deref()).await
+                }
+            };
+            match result {
+                Ok(()) => {
+                    let done = if remove { "Removed" } else { "Installed" };
+                    send(
+                        &tx,
+                        Stage::Succeeded,
+                        &package,
+                        format!("{} {}", done, package),
+                        100,
+                    )
+                    .await;
+                }
+                Err(e) => send(&tx, Stage::Failed, &package, e.to_string(), 100).await,
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[tonic::async_trait]
+impl PackageService for OmniService {
+    type InstallStream = ReceiverStream<Result<Progress, Status>>;
+    type RemoveStream = ReceiverStream<Result<Progress, Status>>;
+    type UpdateStream = ReceiverStream<Result<Progress, Status>>;
+
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        authorize(&request, Permission::Search)?;
+        let query = request.into_inner().query;
+        let results = self
+            .brain
+            .lock()
+            .await
+            .search(&query)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(SearchResponse {
+            packages: results.into_iter().map(Package::from).collect(),
+        }))
+    }
+
+    async fn list_installed(
+        &self,
+        request: Request<ListInstalledRequest>,
+    ) -> Result<Response<ListInstalledResponse>, Status> {
+        authorize(&request, Permission::Read)?;
+        let records = if self.mock {
+            mock::backend().installed()
+        } else {
+            let db = Database::new().await.map_err(internal)?;
+            db.get_installed_packages().await.map_err(internal)?
+        };
+        Ok(Response::new(ListInstalledResponse {
+            packages: records.into_iter().map(Package::from).collect(),
+        }))
+    }
+
+    async fn install(
+        &self,
+        request: Request<PackageRequest>,
+    ) -> Result<Response<Self::InstallStream>, Status> {
+        authorize(&request, Permission::Install)?;
+        self.change(request.into_inner(), false)
+    }
+
+    async fn remove(
+        &self,
+        request: Request<PackageRequest>,
+    ) -> Result<Response<Self::RemoveStream>, Status> {
+        authorize(&request, Permission::Remove)?;
+        self.change(request.into_inner(), true)
+    }
+
+    async fn update(
+        &self,
+        request: Request<UpdateRequest>,
+    ) -> Result<Response<Self::UpdateStream>, Status> {
+        authorize(&request, Permission::Update)?;
+        let request = request.into_inner();
+        let lock = self.begin_change("updating packages")?;
+        let (tx, rx) = mpsc::channel(16);
+        let config = self.config.clone();
+        let mock = self.mock;
+
+        tokio::spawn(async move {
+            let _lock = lock;
+            if let Err(e) = run_update(&tx, config, mock, request).await {
+                send(&tx, Stage::Failed, "", e.to_string(), 100).await;
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn history(
+        &self,
+        request: Request<HistoryRequest>,
+    ) -> Result<Response<HistoryResponse>, Status> {
+        authorize(&request, Permission::Read)?;
+        let limit = match request.into_inner().limit {
+            0 => server::DEFAULT_HISTORY_LIMIT,
+            limit => i64::from(limit),
+        };
+        let history = if self.mock {
+            mock::backend().history(usize::try_from(limit).unwrap_or(0))
+        } else {
+            let db = Database::new().await.map_err(internal)?;
+            db.get_install_history(Some(limit))
+                .await
+                .map_err(internal)?
+        };
+        Ok(Response::new(HistoryResponse {
+            entries: history.into_iter().map(HistoryEntry::from).collect(),
+        }))
+    }
+}
+
+async fn run_update(
+    tx: &ProgressSender,
+    config: OmniConfig,
+    mock: bool,
+    request: UpdateRequest,
+) -> Result<()> {
+    let wanted = |c: &UpdateCandidate| {
+        request.packages.is_empty() || request.packages.contains(&c.package_name)
+    };
+
+    if mock {
+        let candidates: Vec<_> = mock::backend()
+            .updates()
+            .into_iter()
+            .filter(wanted)
+            .collect();
+        for (i, candidate) in candidates.iter().enumerate() {
+            update_one(tx, candidate, i, candidates.len(), async {
+                mock::backend().update(std::slice::from_ref(candidate))
+            })
+            .await;
+        }
+        return Ok(());
+    }
+
+    let manager = UpdateManager::new(config).await?;
+    let mut candidates: Vec<_> = manager
+        .check_updates()
+        .await?
+        .into_iter()
+        .filter(wanted)
+        .collect();
+    if candidates.is_empty() {
+        send(tx, Stage::Succeeded, "", "All packages are up to date", 100).await;
+        return Ok(());
+    }
+
+    // Nobody can answer the partial upgrade prompt, so the caller decides up front
+    if let Some(partial) = manager.partial_upgrade(&candidates)? {
+        if !request.full_sync {
+            return Err(anyhow::anyhow!(
+                "Updating {} alone would be a partial upgrade, leaving {} behind; set full_sync",
+                partial.requested.join(", "),
+                partial.left_behind.join(", ")
+            ));
+        }
+        send(
+            tx,
+            Stage::Running,
+            "",
+            "Upgrading the whole system with pacman -Syu",
+            0,
+        )
+        .await;
+        manager.full_sync(&candidates).await?;
+        candidates.retain(|c| c.box_type != "pacman");
+    }
+
+    for (i, candidate) in candidates.iter().enumerate() {
+        update_one(
+            tx,
+            candidate,
+            i,
+            candidates.len(),
+            manager.update_package(candidate),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+async fn update_one(
+    tx: &ProgressSender,
+    candidate: &UpdateCandidate,
+    index: usize,
+    total: usize,
+    update: impl std::future::Future<Output = Result<()>>,
+) {
+    let percent = |done: usize| u32::try_from(done * 100 / total.max(1)).unwrap_or(100);
+    let package = &candidate.package_name;
+    send(
+        tx,
+        Stage::Running,
+        package,
+        format!(
+            "Updating {} to {}",
+            package,
+            candidate.available_version.as_deref().unwrap_or("latest")
+        ),
+        percent(index),
+    )
+    .await;
+
+    match update.await {
+        Ok(()) => {
+            send(
+                tx,
+                Stage::Succeeded,
+                package,
+                format!("Updated {}", package),
+                percent(index + 1),
+            )
+            .await
+        }
+        Err(e) => {
+            send(
+                tx,
+                Stage::Failed,
+                package,
+                e.to_string(),
+                percent(index + 1),
+            )
+            .await
+        }
+    }
+}
+
+/// Report progress; a client that hung up just stops receiving it
+async fn send(
+    tx: &ProgressSender,
+    stage: Stage,
+    package: &str,
+    message: impl Into<String>,
+    percent: u32,
+) {
+    let _ = tx
+        .send(Ok(Progress {
+            stage: stage.into(),
+            package: package.to_string(),
+            message: message.into(),
+            percent,
+        }))
+        .await;
+}
+
+/// Refuse unless the caller the interceptor found may run `permission`
+fn authorize<T>(request: &Request<T>, permission: Permission) -> Result<(), Status> {
+    let caller = request
+        .extensions()
+        .get::<Caller>()
+        .ok_or_else(|| Status::unauthenticated("Missing or invalid API token"))?;
+    caller
+        .authorize(permission)
+        .map_err(|e| Status::permission_denied(e.to_string()))
+}
+
+fn internal(error: anyhow::Error) -> Status {
+    Status::internal(error.to_string())
+}
+
+impl From<SearchResult> for Package {
+    fn from(result: SearchResult) -> Self {
+        Self {
+            name: result.name,
+            version: result.version,
+            box_type: result.box_type,
+            description: result.description,
+            installed: result.installed,
+        }
+    }
+}
+
+impl From<InstallRecord> for Package {
+    fn from(record: InstallRecord) -> Self {
+        Self {
+            name: record.package_name,
+            version: record.version,
+            box_type: record.box_type,
+            description: None,
+            installed: true,
+        }
+    }
+}
+
+impl From<InstallRecord> for HistoryEntry {
+    fn from(record: InstallRecord) -> Self {
+        Self {
+            package: record.package_name,
+            box_type: record.box_type,
+            version: record.version,
+            status: format!("{:?}", record.status),
+            installed_at: record.installed_at.to_rfc3339(),
+        }
+    }
+}
+
+pub async fn serve(addr: SocketAddr, config: OmniConfig, mock: bool) -> Result<()> {
+    let (token, token_source) = server::load_or_create_token()?;
+    let token: Arc<str> = token.into();
+    let users = UserStore::open().ok();
+    let check_token = move |mut request: Request<()>| {
+        let caller = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|given| authorization::authenticate(&token, users.as_ref(), given.trim()));
+        match caller {
+            Some(caller) => {
+                request.extensions_mut().insert(caller);
+                Ok(request)
+            }
+            None => Err(Status::unauthenticated("Missing or invalid API token")),
+        }
+    };
+
+    println!("🚀 Omni gRPC interface listening on {}", addr);
+    println!(
+        "🔑 Send 'authorization: Bearer <token>' metadata; the token is in {}",
+        token_source
+    );
+    if !addr.ip().is_loopback() {
+        println!(
+            "⚠️  {} is reachable from other machines; put TLS in front of it",
+            addr.ip()
+        );
+    }
+
+    let service =
+        PackageServiceServer::with_interceptor(OmniService::new(config, mock), check_token);
+    tonic::transport::Server::builder()
+        .add_service(service)
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_entry_conversion() {
+        let record = mock::backend().installed().remove(0);
+        let entry = HistoryEntry::from(record.clone());
+        assert_eq!(entry.package, record.package_name);
+        assert_eq!(entry.status, format!("{:?}", record.status));
+        assert!(chrono::DateTime::parse_from_rfc3339(&entry.installed_at).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_install_streams_progress() {
+        let service = OmniService::new(OmniConfig::default(), true);
+        let mut request = Request::new(PackageRequest {
+            package: "htop".to_string(),
+            box_type: None,
+        });
+        request.extensions_mut().insert(Caller::owner());
+        let mut stream = service
+            .install(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .into_inner();
+
+        let mut stages = Vec::new();
+        while let Some(progress) = stream.recv().await {
+            stages.push(progress.unwrap().stage());
+        }
+        assert_eq!(stages.first(), Some(&Stage::Running));
+        assert!(matches!(
+            stages.last(),
+            Some(Stage::Succeeded | Stage::Failed)
+        ));
+
+        let mut denied = Request::new(PackageRequest {
+            package: "htop".to_string(),
+            box_type: None,
+        });
+        denied.extensions_mut().insert(Caller {
+            name: "ci".to_string(),
+            role: authorization::Role::Viewer,
+        });
+        let status = service.install(denied).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+}
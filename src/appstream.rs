@@ -0,0 +1,243 @@
+//! AppStream metadata of installed software (`/usr/share/metainfo`): the
+//! upstream repository and release notes of a package, shown before major
+//! upgrades of critical packages.
+
+use crate::downgrade::compare_versions;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+const METAINFO_DIRS: &[&str] = &["/usr/share/metainfo", "/usr/share/appdata"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Component {
+    pub id: String,
+    pub pkgname: Option<String>,
+    /// Source repository (`<url type="vcs-browser">`), or a homepage on GitHub
+    pub repository: Option<String>,
+    /// Newest first, as AppStream lists them
+    pub releases: Vec<Release>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Release {
+    pub version: String,
+    pub date: Option<String>,
+    /// The untranslated description as plain text
+    pub notes: String,
+}
+
+/// The component installed by `package`, matched on `<pkgname>` or the last
+/// part of its ID (`org.gnome.Nautilus` for nautilus)
+pub fn find(package: &str) -> Option<Component> {
+    METAINFO_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "xml"))
+        .filter_map(|path| read(&path))
+        .find(|component| component.provided_by(package))
+}
+
+fn read(path: &Path) -> Option<Component> {
+    parse(&fs::read_to_string(path).ok()?)
+}
+
+/// Parse a metainfo file; `None` when it has no component ID
+pub fn parse(xml: &str) -> Option<Component> {
+    let id = capture(xml, r"<id>\s*([^<]+?)\s*</id>")?;
+    let url = |kind: &str| {
+        capture(
+            xml,
+            &format!(r#"<url type="{}">\s*([^<]+?)\s*</url>"#, kind),
+        )
+    };
+    let repository = url("vcs-browser")
+        .or_else(|| url("homepage").filter(|homepage| homepage.starts_with("https://github.com/")));
+
+    let release_pattern = Regex::new(r"(?s)<release\b([^>]*?)(?:/>|>(.*?)</release>)")
+        .expect("valid release pattern");
+    let releases = release_pattern
+        .captures_iter(xml)
+        .filter_map(|release| {
+            let attributes = &release[1];
+            Some(Release {
+                version: capture(attributes, r#"version="([^"]+)""#)?,
+                date: capture(attributes, r#"date="([^"]+)""#),
+                notes: release
+                    .get(2)
+                    .and_then(|body| {
+                        capture(body.as_str(), r"(?s)<description>(.*?)</description>")
+                    })
+                    .map(|description| plain_text(&description))
+                    .unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    Some(Component {
+        id,
+        pkgname: capture(xml, r"<pkgname>\s*([^<]+?)\s*</pkgname>"),
+        repository,
+        releases,
+    })
+}
+
+fn capture(text: &str, pattern: &str) -> Option<String> {
+    Regex::new(pattern)
+        .ok()?
+        .captures(text)
+        .map(|c| c[1].to_string())
+}
+
+/// Description markup as text: paragraphs on their own lines, list items
+/// as "- " lines, translations left out
+fn plain_text(markup: &str) -> String {
+    let translated = Regex::new(r#"(?s)<(p|li)\b[^>]*xml:lang="[^"]*"[^>]*>.*?</(p|li)>"#)
+        .expect("valid translation pattern");
+    let text = translated.replace_all(markup, "");
+    let text = text
+        .replace("<li>", "\n- ")
+        .replace("<p>", "\n")
+        .replace("</p>", "\n");
+    let tags = Regex::new(r"<[^>]*>").expect("valid tag pattern");
+    let text = tags
+        .replace_all(&text, "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The upstream part of a distribution version: no epoch, no packaging revision
+pub fn upstream_version(version: &str) -> &str {
+    let version = version.split_once(':').map_or(version, |(_, rest)| rest);
+    version
+        .rsplit_once('-')
+        .map_or(version, |(upstream, _)| upstream)
+}
+
+impl Component {
+    fn provided_by(&self, package: &str) -> bool {
+        if let Some(pkgname) = &self.pkgname {
+            return pkgname == package;
+        }
+        let id = self.id.trim_end_matches(".desktop");
+        id.eq_ignore_ascii_case(package)
+            || id
+                .rsplit('.')
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case(package))
+    }
+
+    /// Releases after `from` up to and including `to`, newest first
+    pub fn releases_between(&self, from: &str, to: &str) -> Vec<&Release> {
+        let from = upstream_version(from);
+        let to = upstream_version(to);
+        self.releases
+            .iter()
+            .filter(|release| {
+                compare_versions(&release.version, from) == Ordering::Greater
+                    && compare_versions(&release.version, to) != Ordering::Greater
+            })
+            .collect()
+    }
+
+    /// GitHub page comparing the two releases, assuming `v`-prefixed tags
+    pub fn compare_url(&self, from: &str, to: &str) -> Option<String> {
+        let repository = self.repository.as_deref()?;
+        if !repository.starts_with("https://github.com/") {
+            return None;
+        }
+        Some(format!(
+            "{}/compare/v{}...v{}",
+            repository.trim_end_matches('/').trim_end_matches(".git"),
+            upstream_version(from),
+            upstream_version(to)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METAINFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<component type="desktop-application">
+  <id>org.example.Tool</id>
+  <pkgname>example-tool</pkgname>
+  <url type="homepage">https://example.org</url>
+  <url type="vcs-browser">https://github.com/example/tool/</url>
+  <releases>
+    <release version="3.0.1" date="2026-02-01">
+      <description>
+        <p>Fixes &amp; cleanups:</p>
+        <p xml:lang="de">Fehlerbehebungen:</p>
+        <ul>
+          <li>Crash on   start</li>
+          <li xml:lang="de">Absturz</li>
+        </ul>
+      </description>
+    </release>
+    <release version="3.0" date="2026-01-10"/>
+    <release version="2.9" date="2025-11-02"/>
+  </releases>
+</component>
+"#;
+
+    #[test]
+    fn test_parse() {
+        let component = parse(METAINFO).unwrap();
+        assert_eq!(component.id, "org.example.Tool");
+        assert_eq!(component.pkgname.as_deref(), Some("example-tool"));
+        assert_eq!(
+            component.repository.as_deref(),
+            Some("https://github.com/example/tool/")
+        );
+        assert_eq!(component.releases.len(), 3);
+        assert_eq!(
+            component.releases[0].notes,
+            "Fixes & cleanups:\n- Crash on start"
+        );
+        assert_eq!(component.releases[1].date.as_deref(), Some("2026-01-10"));
+        assert!(component.releases[1].notes.is_empty());
+        assert!(component.provided_by("example-tool"));
+        assert!(!component.provided_by("tool"));
+
+        assert!(parse("<component></component>").is_none());
+    }
+
+    #[test]
+    fn test_releases_between() {
+        let component = parse(METAINFO).unwrap();
+        let versions: Vec<_> = component
+            .releases_between("2.9-1ubuntu2", "1:3.0.1-1")
+            .iter()
+            .map(|r| r.version.as_str())
+            .collect();
+        assert_eq!(versions, ["3.0.1", "3.0"]);
+        assert_eq!(
+            component.compare_url("2.9-1", "3.0.1-1").as_deref(),
+            Some("https://github.com/example/tool/compare/v2.9...v3.0.1")
+        );
+
+        let gitlab = Component {
+            id: "org.gnome.Nautilus".to_string(),
+            repository: Some("https://gitlab.gnome.org/GNOME/nautilus".to_string()),
+            ..Default::default()
+        };
+        assert!(gitlab.provided_by("nautilus"));
+        assert!(gitlab.compare_url("46.0", "47.0").is_none());
+        assert_eq!(upstream_version("2:1.2.3-4"), "1.2.3");
+    }
+}
@@ -0,0 +1,303 @@
+//! Checksums, signature status and download URLs of what omni installs, kept so
+//! `omni verify --provenance` can check them again later
+
+use crate::boxes::{appimage, source};
+use crate::database::{ArtifactRecord, SignatureStatus};
+use crate::provenance;
+use anyhow::Result;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+/// What checking a recorded artifact again found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recheck {
+    /// Everything recorded still holds
+    Matches,
+    /// Nothing could be compared, and why
+    Unchecked(String),
+    Problems(Vec<String>),
+}
+
+/// `sha256:<hex>` of a file
+pub fn sha256_file(path: &Path) -> Result<String> {
+    Ok(format!(
+        "sha256:{}",
+        hex::encode(Sha256::digest(fs::read(path)?))
+    ))
+}
+
+/// What was installed for `package`, from the files omni wrote or the backend's
+/// metadata. Never fails: missing details are left empty.
+pub fn capture(
+    package: &str,
+    box_type: &str,
+    version: Option<&str>,
+    source_url: Option<&str>,
+) -> ArtifactRecord {
+    let mut artifact = ArtifactRecord {
+        package_name: package.to_string(),
+        box_type: box_type.to_string(),
+        version: version.map(str::to_string),
+        url: source_url.map(str::to_string),
+        path: None,
+        checksum: None,
+        signature: SignatureStatus::Repository,
+        recorded_at: Utc::now(),
+    };
+
+    match box_type {
+        "appimage" => {
+            artifact.signature = SignatureStatus::Unverified;
+            if let Ok(path) = appimage::appimage_path(package) {
+                artifact.checksum = sha256_file(&path).ok();
+                artifact.path = Some(path.display().to_string());
+            }
+        }
+        "source" => {
+            artifact.signature = SignatureStatus::Unverified;
+            if let Ok(Some(install)) = source::load_install(package) {
+                artifact.url = Some(install.repo);
+                artifact.version = install.rev;
+                artifact.checksum = install.commit.map(|c| format!("git:{}", c));
+            }
+        }
+        "apt" => {
+            if let Some(installed) = run("dpkg-query", &["-W", "-f=${Version}", package]) {
+                let show = run(
+                    "apt-cache",
+                    &["show", &format!("{}={}", package, installed)],
+                );
+                let (filename, sha256) = show.as_deref().map(parse_apt_show).unwrap_or_default();
+                let mirror = provenance::detect(package, "apt").mirror_url;
+                artifact.url = match (mirror, filename) {
+                    (Some(mirror), Some(filename)) => {
+                        Some(format!("{}/{}", mirror.trim_end_matches('/'), filename))
+                    }
+                    _ => artifact.url,
+                };
+                artifact.checksum = sha256.map(|h| format!("sha256:{}", h));
+                artifact.version = Some(installed);
+            }
+        }
+        "dnf" => {
+            artifact.url = run("dnf", &["repoquery", "--installed", "--location", package])
+                .and_then(|out| out.lines().find(|l| l.contains("://")).map(str::to_string))
+                .or(artifact.url);
+        }
+        "pacman" => {
+            artifact.url = run("pacman", &["-Sp", package])
+                .and_then(|out| out.lines().next().map(str::to_string))
+                .or(artifact.url);
+        }
+        _ => {}
+    }
+
+    debug!("Artifact for {} via {}: {:?}", package, box_type, artifact);
+    artifact
+}
+
+/// Check a recorded artifact against what is on disk and what its source
+/// publishes now
+pub fn recheck(artifact: &ArtifactRecord) -> Recheck {
+    let mut problems = Vec::new();
+    if artifact.signature == SignatureStatus::Failed {
+        problems.push("was installed although its signature check failed".to_string());
+    }
+
+    let outcome = match (artifact.box_type.as_str(), artifact.checksum.as_deref()) {
+        (_, None) => Recheck::Unchecked("no checksum was recorded".to_string()),
+        ("appimage", Some(recorded)) => match artifact.path.as_deref().map(Path::new) {
+            Some(path) if !path.exists() => {
+                problems.push(format!("{} is gone", path.display()));
+                Recheck::Matches
+            }
+            Some(path) => match sha256_file(path) {
+                Ok(current) if current != recorded => {
+                    problems.push(format!(
+                        "{} changed since it was installed ({} now, {} then)",
+                        path.display(),
+                        current,
+                        recorded
+                    ));
+                    Recheck::Matches
+                }
+                Ok(_) => Recheck::Matches,
+                Err(e) => Recheck::Unchecked(format!("cannot read {}: {}", path.display(), e)),
+            },
+            None => Recheck::Unchecked("no file was recorded".to_string()),
+        },
+        ("source", Some(recorded)) => {
+            let commit = recorded.trim_start_matches("git:");
+            match (artifact.url.as_deref(), artifact.version.as_deref()) {
+                (Some(repo), Some(rev)) if !commit.starts_with(rev) => {
+                    let current = run("git", &["ls-remote", repo, rev]);
+                    match current.as_deref().and_then(parse_ls_remote) {
+                        Some(current) if current != commit => {
+                            problems.push(format!(
+                                "{} now points at {} in {}, but {} was built",
+                                rev, current, repo, commit
+                            ));
+                            Recheck::Matches
+                        }
+                        Some(_) => Recheck::Matches,
+                        None => Recheck::Unchecked(format!("cannot look up {} in {}", rev, repo)),
+                    }
+                }
+                (Some(_), Some(_)) => Recheck::Matches,
+                _ => Recheck::Unchecked("built from the default branch".to_string()),
+            }
+        }
+        ("apt", Some(recorded)) => {
+            let version = artifact.version.as_deref().unwrap_or_default();
+            let show = run(
+                "apt-cache",
+                &["show", &format!("{}={}", artifact.package_name, version)],
+            );
+            match show
+                .as_deref()
+                .map(parse_apt_show)
+                .and_then(|(_, sha256)| sha256)
+            {
+                Some(current) if format!("sha256:{}", current) != recorded => {
+                    problems.push(format!(
+                        "the repositories now publish sha256:{} for {}, not {}",
+                        current, version, recorded
+                    ));
+                    Recheck::Matches
+                }
+                Some(_) => Recheck::Matches,
+                None => Recheck::Unchecked(format!("the repositories no longer offer {}", version)),
+            }
+        }
+        (_, Some(_)) => Recheck::Unchecked("omni cannot check this backend again".to_string()),
+    };
+
+    if problems.is_empty() {
+        outcome
+    } else {
+        Recheck::Problems(problems)
+    }
+}
+
+/// `Filename` and `SHA256` of the first stanza of `apt-cache show`
+pub fn parse_apt_show(output: &str) -> (Option<String>, Option<String>) {
+    let mut filename = None;
+    let mut sha256 = None;
+    for line in output.lines() {
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Filename:") {
+            filename = Some(value.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("SHA256:") {
+            sha256 = Some(value.trim().to_string());
+        }
+    }
+    (filename, sha256)
+}
+
+/// Commit of the first ref `git ls-remote` lists; annotated tags list the tag
+/// object first and the commit as `<tag>^{}`, which wins
+pub fn parse_ls_remote(output: &str) -> Option<String> {
+    let refs: Vec<(&str, &str)> = output
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .collect();
+    refs.iter()
+        .find(|(_, name)| name.ends_with("^{}"))
+        .or_else(|| refs.first())
+        .map(|(commit, _)| commit.to_string())
+}
+
+fn run(command: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(command).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn appimage(path: &Path, checksum: &str) -> ArtifactRecord {
+        ArtifactRecord {
+            package_name: "tool".to_string(),
+            box_type: "appimage".to_string(),
+            version: None,
+            url: Some("https://example.com/tool.AppImage".to_string()),
+            path: Some(path.display().to_string()),
+            checksum: Some(checksum.to_string()),
+            signature: SignatureStatus::Unverified,
+            recorded_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_recheck_appimage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tool.AppImage");
+        fs::write(&path, b"original").unwrap();
+        let artifact = appimage(&path, &sha256_file(&path).unwrap());
+        assert_eq!(recheck(&artifact), Recheck::Matches);
+
+        fs::write(&path, b"tampered").unwrap();
+        assert!(matches!(recheck(&artifact), Recheck::Problems(p) if p[0].contains("changed")));
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(recheck(&artifact), Recheck::Problems(p) if p[0].contains("gone")));
+
+        let unsigned = ArtifactRecord {
+            checksum: None,
+            signature: SignatureStatus::Failed,
+            ..artifact
+        };
+        assert!(matches!(recheck(&unsigned), Recheck::Problems(_)));
+    }
+
+    #[test]
+    fn test_parse_apt_show() {
+        let output = "Package: openssl
+Version: 3.0.13-0ubuntu3.4
+Filename: pool/main/o/openssl/openssl_3.0.13-0ubuntu3.4_amd64.deb
+SHA256: 5f2ab3c1e0d5bd1f6f0b6c7f6c1c5ad4a8e8a0f1b2c3d4e5f60718293a4b5c6d
+
+Package: openssl
+Version: 3.0.13-0ubuntu3.4
+Filename: pool/main/o/openssl/other.deb
+SHA256: 0000
+";
+        let (filename, sha256) = parse_apt_show(output);
+        assert_eq!(
+            filename.as_deref(),
+            Some("pool/main/o/openssl/openssl_3.0.13-0ubuntu3.4_amd64.deb")
+        );
+        assert_eq!(
+            sha256.as_deref(),
+            Some("5f2ab3c1e0d5bd1f6f0b6c7f6c1c5ad4a8e8a0f1b2c3d4e5f60718293a4b5c6d")
+        );
+        assert_eq!(parse_apt_show(""), (None, None));
+    }
+
+    #[test]
+    fn test_parse_ls_remote() {
+        let annotated = "1111111111111111111111111111111111111111\trefs/tags/v1.2.0
+2222222222222222222222222222222222222222\trefs/tags/v1.2.0^{}
+";
+        assert_eq!(
+            parse_ls_remote(annotated).as_deref(),
+            Some("2222222222222222222222222222222222222222")
+        );
+        let branch = "3333333333333333333333333333333333333333\trefs/heads/main\n";
+        assert_eq!(
+            parse_ls_remote(branch).as_deref(),
+            Some("3333333333333333333333333333333333333333")
+        );
+        assert_eq!(parse_ls_remote(""), None);
+    }
+}
@@ -1,10 +1,1232 @@
-// Simplified audit stub for compilation
-use anyhow::Result;
+use crate::config::{
+    AuditConfig, ChatAlertConfig, ChatFormat, EmailAlertConfig, SmtpTls, SyslogConfig,
+};
+use crate::credentials;
+use crate::database::Database;
+use crate::notifications::{self, Dispatcher, Notification};
+use crate::syslog;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, MessageBuilder, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
 
-pub struct AuditManager;
+const SMTP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Kinds of events recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[value(rename_all = "snake_case")]
+pub enum AuditEventType {
+    PackageInstall,
+    PackageRemove,
+    PackageUpdate,
+    LicenseAccepted,
+    LicenseDeclined,
+    SecurityViolation,
+    ConfigurationChange,
+}
+
+impl AuditEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventType::PackageInstall => "package_install",
+            AuditEventType::PackageRemove => "package_remove",
+            AuditEventType::PackageUpdate => "package_update",
+            AuditEventType::LicenseAccepted => "license_accepted",
+            AuditEventType::LicenseDeclined => "license_declined",
+            AuditEventType::SecurityViolation => "security_violation",
+            AuditEventType::ConfigurationChange => "configuration_change",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "package_install" => Some(AuditEventType::PackageInstall),
+            "package_remove" => Some(AuditEventType::PackageRemove),
+            "package_update" => Some(AuditEventType::PackageUpdate),
+            "license_accepted" => Some(AuditEventType::LicenseAccepted),
+            "license_declined" => Some(AuditEventType::LicenseDeclined),
+            "security_violation" => Some(AuditEventType::SecurityViolation),
+            "configuration_change" => Some(AuditEventType::ConfigurationChange),
+            _ => None,
+        }
+    }
+}
+
+/// Severity attached to every audit entry
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum AuditSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl AuditSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditSeverity::Info => "info",
+            AuditSeverity::Warning => "warning",
+            AuditSeverity::Critical => "critical",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "info" => Some(AuditSeverity::Info),
+            "warning" => Some(AuditSeverity::Warning),
+            "critical" => Some(AuditSeverity::Critical),
+            _ => None,
+        }
+    }
+}
+
+/// A single row of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub event_type: AuditEventType,
+    pub severity: AuditSeverity,
+    pub user: String,
+    pub package_name: Option<String>,
+    pub box_type: Option<String>,
+    pub details: String,
+    pub success: bool,
+    /// Trace of the operation, when spans are exported; see `logging`
+    pub trace_id: Option<String>,
+    /// Hash of the entry before this one in the hash chain; set when recorded
+    #[serde(default)]
+    pub prev_hash: Option<String>,
+    /// Hash over `prev_hash` and this entry's fields; see [`entry_hash`]
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(event_type: AuditEventType, severity: AuditSeverity, details: &str) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            event_type,
+            severity,
+            user: current_user(),
+            package_name: None,
+            box_type: None,
+            details: details.to_string(),
+            success: true,
+            trace_id: crate::logging::current_trace_id(),
+            prev_hash: None,
+            hash: None,
+        }
+    }
+
+    pub fn with_package(mut self, package_name: &str, box_type: Option<&str>) -> Self {
+        self.package_name = Some(package_name.to_string());
+        self.box_type = box_type.map(|b| b.to_string());
+        self
+    }
+
+    pub fn with_success(mut self, success: bool) -> Self {
+        self.success = success;
+        self
+    }
+}
+
+/// Filters accepted by `AuditManager::query_logs`
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub user: Option<String>,
+    pub event_type: Option<AuditEventType>,
+    pub severity: Option<AuditSeverity>,
+    pub package_name: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditStatistics {
+    pub total_events: usize,
+    pub failed_events: usize,
+    pub by_event_type: HashMap<String, usize>,
+    pub by_severity: HashMap<String, usize>,
+}
+
+/// Persists security-relevant events to the `audit_log` table, forwarding
+/// them to the configured syslog collectors and handing every one to the
+/// notification routes, which mail and chat alerts go through
+#[derive(Debug, Clone)]
+pub struct AuditManager {
+    db: Database,
+    alerts: AuditConfig,
+    notifier: Dispatcher,
+}
 
 impl AuditManager {
-    pub fn new() -> Result<Self> {
-        Ok(Self)
+    pub async fn new() -> Result<Self> {
+        let db = Database::new().await?;
+        Ok(Self::with_database(db))
+    }
+
+    pub fn with_database(db: Database) -> Self {
+        Self {
+            db,
+            alerts: AuditConfig::default(),
+            notifier: Dispatcher::default(),
+        }
+    }
+
+    pub fn with_alerts(mut self, alerts: AuditConfig) -> Self {
+        self.alerts = alerts;
+        self
+    }
+
+    /// Hand recorded events to `notifier`, for the `audit` notification routes
+    pub fn with_notifier(mut self, notifier: Dispatcher) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    pub async fn log_event(&self, entry: &AuditEntry) -> Result<()> {
+        let mut tx = self.db.pool.begin().await?;
+        // Writing first takes the write lock, so two processes cannot chain
+        // their entries onto the same predecessor
+        sqlx::query("UPDATE audit_chain SET last_hash = last_hash WHERE id = 1")
+            .execute(&mut *tx)
+            .await?;
+        let prev_hash: String =
+            sqlx::query_scalar("SELECT last_hash FROM audit_chain WHERE id = 1")
+                .fetch_one(&mut *tx)
+                .await?;
+        let hash = entry_hash(&prev_hash, entry);
+
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log
+            (id, timestamp, event_type, severity, user, package_name, box_type, details, success,
+             trace_id, prev_hash, hash)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            "#,
+        )
+        .bind(&entry.id)
+        .bind(entry.timestamp.to_rfc3339())
+        .bind(entry.event_type.as_str())
+        .bind(entry.severity.as_str())
+        .bind(&entry.user)
+        .bind(&entry.package_name)
+        .bind(&entry.box_type)
+        .bind(&entry.details)
+        .bind(entry.success)
+        .bind(&entry.trace_id)
+        .bind(&prev_hash)
+        .bind(&hash)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE audit_chain SET last_hash = ?1 WHERE id = 1")
+            .bind(&hash)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        info!(
+            "Audit: {} by {} - {}",
+            entry.event_type.as_str(),
+            entry.user,
+            entry.details
+        );
+
+        // The event is recorded either way; a mail server, chat service or
+        // syslog collector being down must not fail the audited operation
+        for target in &self.alerts.syslog {
+            if entry.severity >= target.min_severity {
+                if let Err(e) = send_syslog(target, entry).await {
+                    warn!("Failed to forward audit entry: {:#}", e);
+                }
+            }
+        }
+        self.notifier.dispatch(&Notification::from(entry)).await;
+
+        Ok(())
+    }
+
+    pub async fn query_logs(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        let mut sql = String::from("SELECT * FROM audit_log WHERE 1 = 1");
+        let mut binds: Vec<String> = Vec::new();
+
+        if let Some(user) = &filter.user {
+            sql.push_str(" AND user = ?");
+            binds.push(user.clone());
+        }
+        if let Some(event_type) = &filter.event_type {
+            sql.push_str(" AND event_type = ?");
+            binds.push(event_type.as_str().to_string());
+        }
+        if let Some(severity) = &filter.severity {
+            sql.push_str(" AND severity = ?");
+            binds.push(severity.as_str().to_string());
+        }
+        if let Some(package_name) = &filter.package_name {
+            sql.push_str(" AND package_name = ?");
+            binds.push(package_name.clone());
+        }
+        if let Some(since) = &filter.since {
+            sql.push_str(" AND timestamp >= ?");
+            binds.push(since.to_rfc3339());
+        }
+        if let Some(until) = &filter.until {
+            sql.push_str(" AND timestamp <= ?");
+            binds.push(until.to_rfc3339());
+        }
+        sql.push_str(" ORDER BY timestamp DESC LIMIT ?");
+
+        let mut query = sqlx::query(&sql);
+        for value in &binds {
+            query = query.bind(value);
+        }
+        let rows = query
+            .bind(filter.limit.unwrap_or(100))
+            .fetch_all(&self.db.pool)
+            .await?;
+
+        rows.iter().map(entry_from_row).collect()
+    }
+
+    /// Entries recorded at or after `since`, oldest first
+    pub async fn entries_since(&self, since: Option<DateTime<Utc>>) -> Result<Vec<AuditEntry>> {
+        let since = since.map(|t| t.to_rfc3339()).unwrap_or_default();
+        let rows = sqlx::query("SELECT * FROM audit_log WHERE timestamp >= ?1 ORDER BY rowid")
+            .bind(since)
+            .fetch_all(&self.db.pool)
+            .await?;
+        rows.iter().map(entry_from_row).collect()
+    }
+
+    /// Number of entries [`prune`](Self::prune) would delete
+    pub async fn count_before(&self, before: DateTime<Utc>) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM audit_log WHERE rowid <= \
+             (SELECT MAX(rowid) FROM audit_log WHERE timestamp < ?1)",
+        )
+        .bind(before.to_rfc3339())
+        .fetch_one(&self.db.pool)
+        .await?;
+        Ok(count as u64)
+    }
+
+    /// Delete the entries up to the last one recorded before `before`,
+    /// returning how many went. The hash chain then starts after them.
+    pub async fn prune(&self, before: DateTime<Utc>) -> Result<u64> {
+        crate::config::ensure_writable("pruning the audit log")?;
+        let mut tx = self.db.pool.begin().await?;
+        let last: Option<(i64, Option<String>)> = sqlx::query_as(
+            "SELECT rowid, hash FROM audit_log WHERE timestamp < ?1 ORDER BY rowid DESC LIMIT 1",
+        )
+        .bind(before.to_rfc3339())
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((rowid, hash)) = last else {
+            return Ok(0);
+        };
+
+        let result = sqlx::query("DELETE FROM audit_log WHERE rowid <= ?1")
+            .bind(rowid)
+            .execute(&mut *tx)
+            .await?;
+        if let Some(hash) = hash {
+            sqlx::query("UPDATE audit_chain SET anchor_hash = ?1 WHERE id = 1")
+                .bind(hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Walk the hash chain from its start to its head and report every place
+    /// where entries were changed, deleted or slipped in
+    pub async fn verify(&self) -> Result<ChainReport> {
+        let (anchor, last_hash): (String, String) =
+            sqlx::query_as("SELECT anchor_hash, last_hash FROM audit_chain WHERE id = 1")
+                .fetch_one(&self.db.pool)
+                .await?;
+        let rows = sqlx::query("SELECT * FROM audit_log ORDER BY rowid")
+            .fetch_all(&self.db.pool)
+            .await?;
+
+        let links: Vec<ChainLink> = rows
+            .iter()
+            .map(|row| {
+                let prev_hash: Option<String> = row.get("prev_hash");
+                ChainLink {
+                    id: row.get("id"),
+                    computed: entry_from_row(row)
+                        .ok()
+                        .map(|entry| entry_hash(prev_hash.as_deref().unwrap_or_default(), &entry)),
+                    prev_hash,
+                    hash: row.get("hash"),
+                }
+            })
+            .collect();
+        Ok(check_chain(&anchor, &last_hash, &links))
+    }
+
+    pub async fn get_statistics(&self, since: Option<DateTime<Utc>>) -> Result<AuditStatistics> {
+        let since = since
+            .map(|s| s.to_rfc3339())
+            .unwrap_or_else(|| DateTime::<Utc>::UNIX_EPOCH.to_rfc3339());
+
+        let total_events: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM audit_log WHERE timestamp >= ?1")
+                .bind(&since)
+                .fetch_one(&self.db.pool)
+                .await?;
+
+        let failed_events: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM audit_log WHERE timestamp >= ?1 AND success = 0",
+        )
+        .bind(&since)
+        .fetch_one(&self.db.pool)
+        .await?;
+
+        let mut by_event_type = HashMap::new();
+        let rows = sqlx::query(
+            "SELECT event_type, COUNT(*) AS count FROM audit_log WHERE timestamp >= ?1 GROUP BY event_type",
+        )
+        .bind(&since)
+        .fetch_all(&self.db.pool)
+        .await?;
+        for row in rows {
+            let count: i64 = row.get("count");
+            by_event_type.insert(row.get("event_type"), count as usize);
+        }
+
+        let mut by_severity = HashMap::new();
+        let rows = sqlx::query(
+            "SELECT severity, COUNT(*) AS count FROM audit_log WHERE timestamp >= ?1 GROUP BY severity",
+        )
+        .bind(&since)
+        .fetch_all(&self.db.pool)
+        .await?;
+        for row in rows {
+            let count: i64 = row.get("count");
+            by_severity.insert(row.get("severity"), count as usize);
+        }
+
+        Ok(AuditStatistics {
+            total_events: total_events as usize,
+            failed_events: failed_events as usize,
+            by_event_type,
+            by_severity,
+        })
+    }
+}
+
+fn entry_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<AuditEntry> {
+    let timestamp: String = row.get("timestamp");
+    let event_type: String = row.get("event_type");
+    let severity: String = row.get("severity");
+
+    Ok(AuditEntry {
+        id: row.get("id"),
+        timestamp: DateTime::parse_from_rfc3339(&timestamp)?.with_timezone(&Utc),
+        event_type: AuditEventType::parse(&event_type)
+            .ok_or_else(|| anyhow::anyhow!("Unknown audit event type: {}", event_type))?,
+        severity: AuditSeverity::parse(&severity).unwrap_or(AuditSeverity::Info),
+        user: row.get("user"),
+        package_name: row.get("package_name"),
+        box_type: row.get("box_type"),
+        details: row.get("details"),
+        success: row.get("success"),
+        trace_id: row.get("trace_id"),
+        prev_hash: row.get("prev_hash"),
+        hash: row.get("hash"),
+    })
+}
+
+/// A point in time given as a date (midnight UTC) or an RFC 3339 timestamp
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| anyhow!("Invalid date '{}': use YYYY-MM-DD or RFC 3339", value))
+}
+
+/// One line describing `entry` for `omni audit show`
+pub fn format_entry(entry: &AuditEntry) -> String {
+    let status = if entry.success { "✅" } else { "❌" };
+    let mut line = format!(
+        "{} {} {:<8} {:<20} {:<12}",
+        entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        status,
+        entry.severity.as_str(),
+        entry.event_type.as_str(),
+        entry.user
+    );
+    if let Some(package) = &entry.package_name {
+        match &entry.box_type {
+            Some(box_type) => line.push_str(&format!(" {} ({})", package, box_type)),
+            None => line.push_str(&format!(" {}", package)),
+        }
+    }
+    line.push_str(&format!(" - {}", entry.details));
+    line
+}
+
+/// Counts from `stats`, largest first
+pub fn print_statistics(stats: &AuditStatistics) {
+    println!("📊 Audit statistics:\n");
+    println!("Total events:  {}", stats.total_events);
+    println!("Failed events: {}", stats.failed_events);
+    for (title, counts) in [
+        ("By event type", &stats.by_event_type),
+        ("By severity", &stats.by_severity),
+    ] {
+        if counts.is_empty() {
+            continue;
+        }
+        println!("\n{}:", title);
+        let mut counts: Vec<_> = counts.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        for (name, count) in counts {
+            println!("  {:<22}{}", name, count);
+        }
+    }
+}
+
+/// SHA-256 over the previous entry's hash and the recorded fields of
+/// `entry`, so changing, removing or inserting an entry breaks the chain
+pub fn entry_hash(prev_hash: &str, entry: &AuditEntry) -> String {
+    let fields = [
+        prev_hash,
+        &entry.id,
+        &entry.timestamp.to_rfc3339(),
+        entry.event_type.as_str(),
+        entry.severity.as_str(),
+        &entry.user,
+        entry.package_name.as_deref().unwrap_or_default(),
+        entry.box_type.as_deref().unwrap_or_default(),
+        &entry.details,
+        if entry.success { "1" } else { "0" },
+        entry.trace_id.as_deref().unwrap_or_default(),
+    ];
+    let mut hasher = Sha256::new();
+    hasher.update(fields.join("\u{1f}"));
+    hex::encode(hasher.finalize())
+}
+
+/// One entry as the chain check sees it
+#[derive(Debug, Clone)]
+pub struct ChainLink {
+    pub id: String,
+    pub prev_hash: Option<String>,
+    pub hash: Option<String>,
+    /// The hash the entry's fields give now; `None` when they no longer parse
+    pub computed: Option<String>,
+}
+
+/// Where the chain does not hold
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChainBreak {
+    /// The entry's fields no longer give its hash
+    Modified { id: String },
+    /// Entries right before this one are gone
+    Deleted { before: String },
+    /// An entry without a hash among chained entries
+    Unchained { id: String },
+    /// The newest entries are gone
+    TailDeleted,
+}
+
+impl std::fmt::Display for ChainBreak {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainBreak::Modified { id } => write!(f, "entry {} was modified", id),
+            ChainBreak::Deleted { before } => write!(f, "entries before {} were deleted", before),
+            ChainBreak::Unchained { id } => {
+                write!(f, "entry {} was added outside of omni", id)
+            }
+            ChainBreak::TailDeleted => write!(f, "the newest entries were deleted"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ChainReport {
+    /// Entries covered by the chain
+    pub chained: usize,
+    /// Entries recorded before hash chaining, which it cannot vouch for
+    pub legacy: usize,
+    /// Hash of the newest entry, worth keeping elsewhere to catch a rewrite
+    /// of the whole chain
+    pub head: String,
+    pub breaks: Vec<ChainBreak>,
+}
+
+impl ChainReport {
+    pub fn is_intact(&self) -> bool {
+        self.breaks.is_empty()
+    }
+}
+
+/// Check `links`, in the order they were recorded, against the chain that
+/// starts at `anchor` (the last pruned entry) and ends at `last_hash`
+pub fn check_chain(anchor: &str, last_hash: &str, links: &[ChainLink]) -> ChainReport {
+    let mut report = ChainReport::default();
+    let mut expected_prev = anchor.to_string();
+
+    for link in links {
+        let Some(hash) = &link.hash else {
+            if report.chained == 0 && report.breaks.is_empty() {
+                report.legacy += 1;
+            } else {
+                report.breaks.push(ChainBreak::Unchained {
+                    id: link.id.clone(),
+                });
+            }
+            continue;
+        };
+
+        if link.prev_hash.as_deref() != Some(expected_prev.as_str()) {
+            report.breaks.push(ChainBreak::Deleted {
+                before: link.id.clone(),
+            });
+        }
+        if link.computed.as_deref() != Some(hash.as_str()) {
+            report.breaks.push(ChainBreak::Modified {
+                id: link.id.clone(),
+            });
+        }
+        report.chained += 1;
+        expected_prev = hash.clone();
+    }
+
+    if expected_prev != last_hash {
+        report.breaks.push(ChainBreak::TailDeleted);
+    }
+    report.head = last_hash.to_string();
+    report
+}
+
+/// e.g. "[omni] critical security_violation on web01"
+pub fn alert_subject(entry: &AuditEntry) -> String {
+    format!(
+        "[omni] {} {} on {}",
+        entry.severity.as_str(),
+        entry.event_type.as_str(),
+        host_name()
+    )
+}
+
+/// Name and value pairs describing `entry` in an alert
+pub fn alert_facts(entry: &AuditEntry) -> Vec<(&'static str, String)> {
+    let mut facts = vec![
+        ("Host", host_name()),
+        ("User", entry.user.clone()),
+        ("Event", entry.event_type.as_str().to_string()),
+        ("Severity", entry.severity.as_str().to_string()),
+        ("Success", entry.success.to_string()),
+    ];
+    if let Some(package) = &entry.package_name {
+        let package = match &entry.box_type {
+            Some(box_type) => format!("{} ({})", package, box_type),
+            None => package.clone(),
+        };
+        facts.push(("Package", package));
+    }
+    if let Some(trace_id) = &entry.trace_id {
+        facts.push(("Trace", trace_id.clone()));
+    }
+    facts
+}
+
+pub fn alert_body(entry: &AuditEntry) -> String {
+    let mut body = format!("Time:     {}\n", entry.timestamp.to_rfc3339());
+    for (name, value) in alert_facts(entry) {
+        body.push_str(&format!("{:<10}{}\n", format!("{}:", name), value));
+    }
+    body.push_str(&format!(
+        "\n{}\n\nAudit entry {}\n",
+        entry.details, entry.id
+    ));
+    body
+}
+
+/// The message a chat webhook in `format` expects for `entry`
+pub fn chat_payload(format: ChatFormat, entry: &AuditEntry) -> serde_json::Value {
+    chat_message(
+        format,
+        entry.severity,
+        &alert_subject(entry),
+        &alert_facts(entry),
+        &entry.details,
+        &format!("Audit entry {}", entry.id),
+        entry.timestamp,
+    )
+}
+
+/// The message a chat webhook in `format` expects for a notification that
+/// is not an audit entry
+pub fn notification_chat_payload(
+    format: ChatFormat,
+    notification: &Notification,
+) -> serde_json::Value {
+    let facts = [
+        ("Host", notification.host.clone()),
+        ("Category", notification.category.to_string()),
+        ("Severity", notification.severity.as_str().to_string()),
+    ];
+    chat_message(
+        format,
+        notification.severity,
+        &format!("[omni] {}", notification.title),
+        &facts,
+        &notification.body,
+        "omni",
+        notification.timestamp,
+    )
+}
+
+fn chat_message(
+    format: ChatFormat,
+    severity: AuditSeverity,
+    subject: &str,
+    facts: &[(&str, String)],
+    details: &str,
+    footer: &str,
+    timestamp: DateTime<Utc>,
+) -> serde_json::Value {
+    let icon = match severity {
+        AuditSeverity::Info => "ℹ️",
+        AuditSeverity::Warning => "⚠️",
+        AuditSeverity::Critical => "🚨",
+    };
+    let title = format!("{} {}", icon, subject);
+
+    match format {
+        ChatFormat::Slack => {
+            let fields: Vec<_> = facts
+                .iter()
+                .map(|(name, value)| json!({"type": "mrkdwn", "text": format!("*{}*\n{}", name, value)}))
+                .collect();
+            json!({
+                "text": title,
+                "blocks": [
+                    {"type": "header", "text": {"type": "plain_text", "text": title}},
+                    {"type": "section", "fields": fields},
+                    {"type": "section", "text": {"type": "plain_text", "text": details}},
+                    {"type": "context", "elements": [{"type": "mrkdwn", "text": footer}]},
+                ],
+            })
+        }
+        ChatFormat::Discord => {
+            let color = match severity {
+                AuditSeverity::Info => 0x1e88e5,
+                AuditSeverity::Warning => 0xfb8c00,
+                AuditSeverity::Critical => 0xe53935,
+            };
+            let fields: Vec<_> = facts
+                .iter()
+                .map(|(name, value)| json!({"name": name, "value": value, "inline": true}))
+                .collect();
+            json!({
+                "username": "omni",
+                "embeds": [{
+                    "title": title,
+                    "description": details,
+                    "color": color,
+                    "fields": fields,
+                    "timestamp": timestamp.to_rfc3339(),
+                    "footer": {"text": footer},
+                }],
+            })
+        }
+        ChatFormat::Teams => {
+            let color = match severity {
+                AuditSeverity::Info => "Default",
+                AuditSeverity::Warning => "Warning",
+                AuditSeverity::Critical => "Attention",
+            };
+            let facts: Vec<_> = facts
+                .iter()
+                .map(|(name, value)| json!({"title": name, "value": value}))
+                .collect();
+            json!({
+                "type": "message",
+                "attachments": [{
+                    "contentType": "application/vnd.microsoft.card.adaptive",
+                    "content": {
+                        "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                        "type": "AdaptiveCard",
+                        "version": "1.4",
+                        "body": [
+                            {"type": "TextBlock", "text": title, "weight": "Bolder", "size": "Medium", "color": color, "wrap": true},
+                            {"type": "FactSet", "facts": facts},
+                            {"type": "TextBlock", "text": details, "wrap": true},
+                            {"type": "TextBlock", "text": footer, "isSubtle": true, "size": "Small"},
+                        ],
+                    },
+                }],
+            })
+        }
+    }
+}
+
+/// Post `entry` to a chat webhook, retrying transient failures
+pub async fn send_chat_alert(chat: &ChatAlertConfig, entry: &AuditEntry) -> Result<()> {
+    post_chat(chat, &chat_payload(chat.format, entry)).await
+}
+
+/// Post a message built for `chat`'s format to its webhook, retrying
+/// transient failures
+pub async fn post_chat(chat: &ChatAlertConfig, payload: &serde_json::Value) -> Result<()> {
+    if chat.url.is_empty() {
+        return Err(anyhow!("Chat alert without a url (audit.chat)"));
+    }
+    let body = serde_json::to_vec(payload)?;
+    notifications::post_json(&chat.url, body, &BTreeMap::new(), None).await?;
+    Ok(())
+}
+
+/// `entry` as an RFC 5424 message for `target`
+pub fn syslog_message(target: &SyslogConfig, entry: &AuditEntry) -> String {
+    let severity = match entry.severity {
+        AuditSeverity::Info => syslog::Severity::Informational,
+        AuditSeverity::Warning => syslog::Severity::Warning,
+        AuditSeverity::Critical => syslog::Severity::Critical,
+    };
+    let mut params = vec![
+        ("id", entry.id.clone()),
+        ("user", entry.user.clone()),
+        ("success", entry.success.to_string()),
+    ];
+    if let Some(package) = &entry.package_name {
+        params.push(("package", package.clone()));
+    }
+    if let Some(box_type) = &entry.box_type {
+        params.push(("box", box_type.clone()));
+    }
+    if let Some(trace_id) = &entry.trace_id {
+        params.push(("trace_id", trace_id.clone()));
+    }
+
+    syslog::Message {
+        facility: target.facility,
+        severity,
+        timestamp: entry.timestamp,
+        msg_id: entry.event_type.as_str(),
+        params,
+        text: &entry.details,
+    }
+    .format()
+}
+
+/// Forward `entry` to a syslog collector
+pub async fn send_syslog(target: &SyslogConfig, entry: &AuditEntry) -> Result<()> {
+    syslog::send(target, &syslog_message(target, entry)).await
+}
+
+/// Send a plain-text mail to every recipient in `config` over SMTP
+pub async fn send_email(config: &EmailAlertConfig, subject: &str, body: &str) -> Result<()> {
+    let message = mail_builder(config, subject)?
+        .header(ContentType::TEXT_PLAIN)
+        .body(body.to_string())?;
+    deliver_mail(config, message).await
+}
+
+/// Send a mail with an HTML body and its plain-text alternative, e.g. a digest
+pub async fn send_html_email(
+    config: &EmailAlertConfig,
+    subject: &str,
+    text: &str,
+    html: &str,
+) -> Result<()> {
+    let message = mail_builder(config, subject)?.multipart(MultiPart::alternative_plain_html(
+        text.to_string(),
+        html.to_string(),
+    ))?;
+    deliver_mail(config, message).await
+}
+
+fn mail_builder(config: &EmailAlertConfig, subject: &str) -> Result<MessageBuilder> {
+    if config.smtp_host.is_empty() {
+        return Err(anyhow!("No SMTP server configured (audit.email.smtp_host)"));
+    }
+    if config.to.is_empty() {
+        return Err(anyhow!("No recipients configured (audit.email.to)"));
+    }
+
+    let from: Mailbox = config
+        .from
+        .parse()
+        .with_context(|| format!("Invalid sender address '{}'", config.from))?;
+    let mut message = Message::builder().from(from).subject(subject);
+    for recipient in &config.to {
+        let to: Mailbox = recipient
+            .parse()
+            .with_context(|| format!("Invalid recipient address '{}'", recipient))?;
+        message = message.to(to);
+    }
+    Ok(message)
+}
+
+async fn deliver_mail(config: &EmailAlertConfig, message: Message) -> Result<()> {
+    let host = config.smtp_host.as_str();
+    let mut transport = match config.tls {
+        SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+    }
+    .port(config.port())
+    .timeout(Some(SMTP_TIMEOUT));
+    if let Some(username) = &config.username {
+        let password = credentials::resolve(config.password.as_deref().unwrap_or_default())?;
+        transport = transport.credentials(Credentials::new(username.clone(), password));
+    }
+
+    transport
+        .build()
+        .send(message)
+        .await
+        .with_context(|| format!("Sending mail via {}:{} failed", host, config.port()))?;
+    Ok(())
+}
+
+/// Name of this machine as entries and alerts report it
+pub fn host_name() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Name of the user on whose behalf omni is running (the invoking user under sudo)
+pub fn current_user() -> String {
+    std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Accept one SMTP session and hand back the message data
+    async fn fake_smtp_server() -> (u16, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+            let mut data = String::new();
+            let mut in_data = false;
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        write.write_all(b"250 queued\r\n").await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let reply: &[u8] = match line.get(..4).unwrap_or_default() {
+                    "DATA" => {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    }
+                    "QUIT" => {
+                        write.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 ok\r\n",
+                };
+                write.write_all(reply).await.unwrap();
+            }
+            data
+        });
+        (port, server)
+    }
+
+    #[tokio::test]
+    async fn test_critical_events_are_mailed() {
+        let (port, server) = fake_smtp_server().await;
+        let email = EmailAlertConfig {
+            enabled: true,
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: Some(port),
+            tls: SmtpTls::None,
+            from: "omni@example.com".to_string(),
+            to: vec!["admin@example.com".to_string()],
+            ..EmailAlertConfig::default()
+        };
+        let db = Database::new_in_memory().await.unwrap();
+        let config = crate::config::OmniConfig {
+            audit: AuditConfig {
+                email,
+                ..AuditConfig::default()
+            },
+            ..Default::default()
+        };
+        let audit = AuditManager::with_database(db)
+            .with_alerts(config.audit.clone())
+            .with_notifier(Dispatcher::from_config(&config));
+
+        // Below the threshold: recorded, not mailed
+        let info = AuditEntry::new(AuditEventType::PackageInstall, AuditSeverity::Info, "ok");
+        audit.log_event(&info).await.unwrap();
+        let critical = AuditEntry::new(
+            AuditEventType::SecurityViolation,
+            AuditSeverity::Critical,
+            "Checksum mismatch",
+        )
+        .with_package("firefox", Some("apt"))
+        .with_success(false);
+        audit.log_event(&critical).await.unwrap();
+
+        let data = server.await.unwrap();
+        assert!(data.contains("Subject: [omni] critical security_violation on "));
+        assert!(data.contains("Package:  firefox (apt)"));
+        assert!(data.contains("Checksum mismatch"));
+        assert!(!data.contains("package_install"));
+
+        let logged = audit.query_logs(&AuditFilter::default()).await.unwrap();
+        assert_eq!(logged.len(), 2);
+    }
+
+    #[test]
+    fn test_chat_payloads() {
+        let entry = AuditEntry::new(
+            AuditEventType::SecurityViolation,
+            AuditSeverity::Critical,
+            "Checksum mismatch",
+        )
+        .with_package("firefox", Some("apt"));
+
+        let slack = chat_payload(ChatFormat::Slack, &entry);
+        let title = slack["text"].as_str().unwrap();
+        assert!(title.starts_with("🚨 [omni] critical security_violation"));
+        assert_eq!(slack["blocks"][0]["text"]["text"], title);
+        assert_eq!(
+            slack["blocks"][1]["fields"][5]["text"],
+            "*Package*\nfirefox (apt)"
+        );
+        assert_eq!(slack["blocks"][2]["text"]["text"], "Checksum mismatch");
+
+        let discord = chat_payload(ChatFormat::Discord, &entry);
+        let embed = &discord["embeds"][0];
+        assert_eq!(embed["title"], title);
+        assert_eq!(embed["color"], 0xe53935);
+        assert_eq!(embed["fields"][3]["value"], "critical");
+
+        let teams = chat_payload(ChatFormat::Teams, &entry);
+        let card = &teams["attachments"][0]["content"];
+        assert_eq!(card["type"], "AdaptiveCard");
+        assert_eq!(card["body"][0]["color"], "Attention");
+        assert_eq!(card["body"][1]["facts"][1]["title"], "User");
+    }
+
+    #[tokio::test]
+    async fn test_send_email_needs_a_server() {
+        let err = send_email(&EmailAlertConfig::default(), "subject", "body")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("audit.email.smtp_host"));
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let db = Database::new_in_memory().await.unwrap();
+        let audit = AuditManager::with_database(db);
+        let mut old = AuditEntry::new(AuditEventType::PackageInstall, AuditSeverity::Info, "old");
+        old.timestamp = Utc::now() - chrono::Duration::days(100);
+        audit.log_event(&old).await.unwrap();
+        let recent = AuditEntry::new(AuditEventType::PackageInstall, AuditSeverity::Info, "new");
+        audit.log_event(&recent).await.unwrap();
+
+        let cutoff = Utc::now() - chrono::Duration::days(90);
+        assert_eq!(audit.count_before(cutoff).await.unwrap(), 1);
+        assert_eq!(audit.prune(cutoff).await.unwrap(), 1);
+        assert_eq!(audit.count_before(Utc::now()).await.unwrap(), 1);
+        // The remaining entry chains onto the pruned one
+        assert!(audit.verify().await.unwrap().is_intact());
+    }
+
+    #[tokio::test]
+    async fn test_query_and_statistics() {
+        let db = Database::new_in_memory().await.unwrap();
+        let audit = AuditManager::with_database(db);
+        let mut old = AuditEntry::new(AuditEventType::PackageInstall, AuditSeverity::Info, "old")
+            .with_package("htop", Some("apt"));
+        old.timestamp = Utc::now() - chrono::Duration::days(10);
+        audit.log_event(&old).await.unwrap();
+        let mut denied = AuditEntry::new(
+            AuditEventType::SecurityViolation,
+            AuditSeverity::Critical,
+            "denied",
+        )
+        .with_success(false);
+        denied.user = "mallory".to_string();
+        audit.log_event(&denied).await.unwrap();
+
+        let filter = AuditFilter {
+            severity: Some(AuditSeverity::Critical),
+            ..Default::default()
+        };
+        let entries = audit.query_logs(&filter).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].user, "mallory");
+
+        let filter = AuditFilter {
+            until: Some(Utc::now() - chrono::Duration::days(1)),
+            ..Default::default()
+        };
+        let entries = audit.query_logs(&filter).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].details, "old");
+        assert!(format_entry(&entries[0]).contains("htop (apt) - old"));
+
+        let stats = audit
+            .get_statistics(Some(Utc::now() - chrono::Duration::days(1)))
+            .await
+            .unwrap();
+        assert_eq!(stats.total_events, 1);
+        assert_eq!(stats.failed_events, 1);
+        assert_eq!(stats.by_severity.get("critical"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_hash_chain() {
+        let db = Database::new_in_memory().await.unwrap();
+        let pool = db.pool.clone();
+        let audit = AuditManager::with_database(db);
+        let mut ids = Vec::new();
+        for details in ["first", "second", "third", "fourth"] {
+            let entry =
+                AuditEntry::new(AuditEventType::PackageInstall, AuditSeverity::Info, details);
+            ids.push(entry.id.clone());
+            audit.log_event(&entry).await.unwrap();
+        }
+        let report = audit.verify().await.unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.chained, 4);
+        assert_eq!(report.head.len(), 64);
+
+        sqlx::query("UPDATE audit_log SET details = 'edited' WHERE id = ?1")
+            .bind(&ids[1])
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM audit_log WHERE id = ?1 OR id = ?2")
+            .bind(&ids[2])
+            .bind(&ids[3])
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            audit.verify().await.unwrap().breaks,
+            [
+                ChainBreak::Modified { id: ids[1].clone() },
+                ChainBreak::TailDeleted
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_chain() {
+        let link = |id: &str, prev: Option<&str>, hash: Option<&str>| ChainLink {
+            id: id.to_string(),
+            prev_hash: prev.map(str::to_string),
+            hash: hash.map(str::to_string),
+            computed: hash.map(str::to_string),
+        };
+
+        // Entries from before chaining, then the chain from the empty start
+        let links = [
+            link("old", None, None),
+            link("a", Some(""), Some("h1")),
+            link("b", Some("h1"), Some("h2")),
+        ];
+        let report = check_chain("", "h2", &links);
+        assert!(report.is_intact());
+        assert_eq!((report.legacy, report.chained), (1, 2));
+
+        // "b" lost its predecessor, "x" has no hash
+        let links = [
+            link("a", Some(""), Some("h1")),
+            link("x", None, None),
+            link("b", Some("h9"), Some("h2")),
+        ];
+        assert_eq!(
+            check_chain("", "h2", &links).breaks,
+            [
+                ChainBreak::Unchained {
+                    id: "x".to_string()
+                },
+                ChainBreak::Deleted {
+                    before: "b".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2024-05-01").unwrap().to_rfc3339(),
+            "2024-05-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_time("2024-05-01T14:30:00+02:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2024-05-01T12:30:00+00:00"
+        );
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_cli_names_match_stored_names() {
+        use clap::ValueEnum;
+
+        for event_type in AuditEventType::value_variants() {
+            let value = event_type.to_possible_value().unwrap();
+            assert_eq!(value.get_name(), event_type.as_str());
+        }
+        for severity in AuditSeverity::value_variants() {
+            let value = severity.to_possible_value().unwrap();
+            assert_eq!(value.get_name(), severity.as_str());
+        }
     }
 }
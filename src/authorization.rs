@@ -0,0 +1,320 @@
+//! Roles for callers of `omni serve`. Each API user has a token of their own
+//! and a role that decides which operations it may run, so a shared server can
+//! let some users search without letting them install. The token from
+//! `server::load_or_create_token` belongs to the machine's owner and is admin.
+
+use crate::config::{self, OmniConfig};
+use crate::error_handling::OmniError;
+use crate::server::token_matches;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use tracing::warn;
+
+const USERS_FILE: &str = "api-users.yaml";
+/// Name reported for the owner's token
+pub const OWNER: &str = "owner";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything, including reverting snapshots
+    Admin,
+    /// Search, install, remove, update and take snapshots
+    Operator,
+    /// Search and look at installed packages, history and snapshots
+    Viewer,
+}
+
+/// An operation the API can run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// System info, installed packages, history and snapshots
+    Read,
+    Search,
+    Install,
+    Remove,
+    Update,
+    CreateSnapshot,
+    RevertSnapshot,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 7] = [
+        Permission::Read,
+        Permission::Search,
+        Permission::Install,
+        Permission::Remove,
+        Permission::Update,
+        Permission::CreateSnapshot,
+        Permission::RevertSnapshot,
+    ];
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Permission::Read => "view installed packages, history and snapshots",
+            Permission::Search => "search packages",
+            Permission::Install => "install packages",
+            Permission::Remove => "remove packages",
+            Permission::Update => "update packages",
+            Permission::CreateSnapshot => "create snapshots",
+            Permission::RevertSnapshot => "revert to snapshots",
+        }
+    }
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Admin, Role::Operator, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Operator => permission != Permission::RevertSnapshot,
+            Role::Viewer => matches!(permission, Permission::Read | Permission::Search),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An API user; only a hash of the token is kept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiUser {
+    pub name: String,
+    pub role: Role,
+    token_sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Who sent a request, once their token checked out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Caller {
+    pub fn owner() -> Self {
+        Self {
+            name: OWNER.to_string(),
+            role: Role::Admin,
+        }
+    }
+
+    /// Refuse `permission` unless the caller's role allows it
+    pub fn authorize(&self, permission: Permission) -> Result<()> {
+        if self.role.allows(permission) {
+            return Ok(());
+        }
+        warn!(
+            "API user {} ({}) tried to {}",
+            self.name,
+            self.role,
+            permission.describe()
+        );
+        Err(OmniError::PermissionDenied {
+            operation: format!(
+                "{} is a {} and may not {}",
+                self.name,
+                self.role,
+                permission.describe()
+            ),
+        }
+        .into())
+    }
+}
+
+/// API users, in a private file in omni's state directory. The file is read
+/// on every request, so changes apply to a running server at once.
+#[derive(Debug, Clone)]
+pub struct UserStore {
+    path: PathBuf,
+}
+
+impl UserStore {
+    pub fn open() -> Result<Self> {
+        Ok(Self::at(OmniConfig::state_dir()?.join(USERS_FILE)))
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn list(&self) -> Result<Vec<ApiUser>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        serde_yaml::from_str(&fs::read_to_string(&self.path)?)
+            .map_err(|e| anyhow!("Invalid API user file {}: {}", self.path.display(), e))
+    }
+
+    /// Add a user and return their token, which is not stored and cannot be
+    /// shown again
+    pub fn add(&self, name: &str, role: Role) -> Result<String> {
+        validate_name(name)?;
+        let mut users = self.list()?;
+        if users.iter().any(|u| u.name == name) {
+            return Err(anyhow!("API user {} already exists", name));
+        }
+
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        users.push(ApiUser {
+            name: name.to_string(),
+            role,
+            token_sha256: hash(&token),
+            created_at: Utc::now(),
+        });
+        self.save(&users)?;
+        Ok(token)
+    }
+
+    /// Remove a user; `false` when there was none by that name
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut users = self.list()?;
+        let before = users.len();
+        users.retain(|u| u.name != name);
+        if users.len() == before {
+            return Ok(false);
+        }
+        self.save(&users)?;
+        Ok(true)
+    }
+
+    /// Change a user's role; `false` when there was none by that name
+    pub fn set_role(&self, name: &str, role: Role) -> Result<bool> {
+        let mut users = self.list()?;
+        match users.iter_mut().find(|u| u.name == name) {
+            Some(user) => user.role = role,
+            None => return Ok(false),
+        }
+        self.save(&users)?;
+        Ok(true)
+    }
+
+    fn save(&self, users: &[ApiUser]) -> Result<()> {
+        config::ensure_writable("changing API users")?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        config::write_private_file(&self.path, serde_yaml::to_string(users)?.as_bytes())
+    }
+}
+
+/// The caller a bearer token belongs to: the owner for `owner_token`, else the
+/// API user with that token
+pub fn authenticate(owner_token: &str, users: Option<&UserStore>, given: &str) -> Option<Caller> {
+    if token_matches(owner_token, given) {
+        return Some(Caller::owner());
+    }
+
+    let users = match users.map(UserStore::list).transpose() {
+        Ok(users) => users.unwrap_or_default(),
+        Err(e) => {
+            warn!("Cannot read API users: {}", e);
+            return None;
+        }
+    };
+    let given = hash(given);
+    users
+        .into_iter()
+        .find(|u| token_matches(&u.token_sha256, &given))
+        .map(|u| Caller {
+            name: u.name,
+            role: u.role,
+        })
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid {
+        return Err(anyhow!(
+            "API user names use letters, digits, '.', '_' and '-' (up to 64)"
+        ));
+    }
+    if name == OWNER {
+        return Err(anyhow!("{} is reserved for the owner's token", OWNER));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(Role::Viewer.allows(Permission::Search));
+        assert!(!Role::Viewer.allows(Permission::Install));
+        assert!(Role::Operator.allows(Permission::Install));
+        assert!(!Role::Operator.allows(Permission::RevertSnapshot));
+        assert!(Permission::ALL.iter().all(|p| Role::Admin.allows(*p)));
+
+        let viewer = Caller {
+            name: "ci".to_string(),
+            role: Role::Viewer,
+        };
+        assert!(viewer.authorize(Permission::Read).is_ok());
+        let denied = viewer.authorize(Permission::Remove).unwrap_err();
+        assert!(denied.to_string().contains("ci is a viewer"));
+    }
+
+    #[test]
+    fn test_user_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UserStore::at(dir.path().join(USERS_FILE));
+        assert!(store.list().unwrap().is_empty());
+
+        let token = store.add("alice", Role::Viewer).unwrap();
+        assert!(store.add("alice", Role::Admin).is_err());
+        assert!(store.add(OWNER, Role::Admin).is_err());
+        assert!(store.add("bob smith", Role::Admin).is_err());
+        // Only the hash is written
+        assert!(!fs::read_to_string(dir.path().join(USERS_FILE))
+            .unwrap()
+            .contains(&token));
+
+        let caller = authenticate("owner-token", Some(&store), &token).unwrap();
+        assert_eq!(caller.name, "alice");
+        assert_eq!(caller.role, Role::Viewer);
+        assert_eq!(
+            authenticate("owner-token", Some(&store), "owner-token"),
+            Some(Caller::owner())
+        );
+        assert_eq!(authenticate("owner-token", Some(&store), "guess"), None);
+        assert_eq!(authenticate("owner-token", None, &token), None);
+
+        assert!(store.set_role("alice", Role::Operator).unwrap());
+        assert_eq!(
+            authenticate("owner-token", Some(&store), &token).map(|c| c.role),
+            Some(Role::Operator)
+        );
+        assert!(!store.set_role("carol", Role::Admin).unwrap());
+
+        assert!(store.remove("alice").unwrap());
+        assert!(!store.remove("alice").unwrap());
+        assert_eq!(authenticate("owner-token", Some(&store), &token), None);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::distro;
+use anyhow::{anyhow, Result};
+use std::process::Command;
+use tracing::debug;
+
+/// Automatically installed packages that nothing depends on any more, for one backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanSet {
+    pub box_type: String,
+    pub packages: Vec<String>,
+}
+
+/// Ask every available backend for orphaned dependencies
+pub fn find_orphans() -> Vec<OrphanSet> {
+    let mut sets = Vec::new();
+
+    for (box_type, command) in [("apt", "apt-get"), ("dnf", "dnf"), ("pacman", "pacman")] {
+        if !distro::command_exists(command) {
+            continue;
+        }
+        match list_orphans(box_type) {
+            Ok(packages) if !packages.is_empty() => sets.push(OrphanSet {
+                box_type: box_type.to_string(),
+                packages,
+            }),
+            Ok(_) => {}
+            Err(e) => debug!("Could not list orphans for {}: {}", box_type, e),
+        }
+    }
+
+    sets
+}
+
+pub fn list_orphans(box_type: &str) -> Result<Vec<String>> {
+    match box_type {
+        "apt" => {
+            let output = Command::new("apt-get")
+                .args(["--simulate", "autoremove"])
+                .output()?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "apt-get autoremove simulation failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            Ok(parse_apt_simulation(&String::from_utf8_lossy(
+                &output.stdout,
+            )))
+        }
+        "dnf" => {
+            let output = Command::new("dnf")
+                .args(["repoquery", "--unneeded", "--qf", "%{name}"])
+                .output()?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "dnf repoquery failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            Ok(parse_name_list(&String::from_utf8_lossy(&output.stdout)))
+        }
+        "pacman" => {
+            // pacman exits 1 with no output when there are no orphans
+            let output = Command::new("pacman").arg("-Qdtq").output()?;
+            Ok(parse_name_list(&String::from_utf8_lossy(&output.stdout)))
+        }
+        _ => Err(anyhow!(
+            "Autoremove is not supported for box type '{}'",
+            box_type
+        )),
+    }
+}
+
+/// Single native command that removes the whole set, so each backend resolves it in one transaction
+pub fn remove_command(set: &OrphanSet) -> Result<(&'static str, Vec<String>)> {
+    match set.box_type.as_str() {
+        "apt" => Ok(("apt-get", vec!["autoremove".to_string(), "-y".to_string()])),
+        "dnf" => Ok(("dnf", vec!["autoremove".to_string(), "-y".to_string()])),
+        "pacman" => {
+            let mut args = vec!["-Rns".to_string(), "--noconfirm".to_string()];
+            args.extend(set.packages.iter().cloned());
+            Ok(("pacman", args))
+        }
+        other => Err(anyhow!(
+            "Autoremove is not supported for box type '{}'",
+            other
+        )),
+    }
+}
+
+/// Package names from `Remv pkg [version]` lines of `apt-get --simulate autoremove`
+pub fn parse_apt_simulation(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix("Remv "))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn parse_name_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_apt_simulation() {
+        let output = "NOTE: This is only a simulation!
+Reading package lists...
+The following packages will be REMOVED:
+  libfoo1 linux-headers-5.15.0-88
+0 upgraded, 0 newly installed, 2 to remove and 0 not upgraded.
+Remv libfoo1 [1.2-3]
+Remv linux-headers-5.15.0-88 [5.15.0-88.98]
+";
+        assert_eq!(
+            parse_apt_simulation(output),
+            vec!["libfoo1", "linux-headers-5.15.0-88"]
+        );
+    }
+
+    #[test]
+    fn test_pacman_remove_command_lists_packages() {
+        let set = OrphanSet {
+            box_type: "pacman".to_string(),
+            packages: vec!["go".to_string(), "python-pip".to_string()],
+        };
+        let (command, args) = remove_command(&set).unwrap();
+        assert_eq!(command, "pacman");
+        assert_eq!(args, vec!["-Rns", "--noconfirm", "go", "python-pip"]);
+    }
+}
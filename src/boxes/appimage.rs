@@ -1,12 +1,13 @@
+use crate::ownership::{self, FileKind};
 use anyhow::Result;
 use dirs;
 use reqwest;
 use sha2::{Digest, Sha256};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tracing::info;
 
-const APPIMAGE_DIR: &str = ".local/share/applications/appimages";
+pub const APPIMAGE_DIR: &str = ".local/share/applications/appimages";
 
 pub async fn install_appimage(url: &str, name: &str) -> Result<()> {
     info!("Installing AppImage {} from {}", name, url);
@@ -15,9 +16,6 @@ pub async fn install_appimage(url: &str, name: &str) -> Result<()> {
         dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
     let appimage_dir = home_dir.join(APPIMAGE_DIR);
 
-    // Create AppImage directory if it doesn't exist
-    fs::create_dir_all(&appimage_dir)?;
-
     let filename = format!("{}.AppImage", name);
     let target_path = appimage_dir.join(&filename);
 
@@ -26,17 +24,9 @@ pub async fn install_appimage(url: &str, name: &str) -> Result<()> {
     let response = reqwest::get(url).await?;
     let bytes = response.bytes().await?;
 
-    // Write to file
-    fs::write(&target_path, &bytes)?;
-
-    // Make executable
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&target_path)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&target_path, perms)?;
-    }
+    // Write to file, executable and owned by the invoking user under sudo
+    ownership::write(&target_path, &bytes, FileKind::Executable)?;
+    crate::access_control::relabel(&target_path);
 
     info!(
         "✅ Successfully installed AppImage {} to {:?}",
@@ -49,13 +39,23 @@ pub async fn install_appimage(url: &str, name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Install an AppImage from a file on disk, such as one kept to undo a removal
+pub fn install_appimage_file(file: &Path, name: &str) -> Result<()> {
+    info!("Installing AppImage {} from {:?}", name, file);
+
+    let target_path = appimage_path(name)?;
+    ownership::write(&target_path, &fs::read(file)?, FileKind::Executable)?;
+    crate::access_control::relabel(&target_path);
+    create_desktop_entry(name, &target_path)?;
+
+    Ok(())
+}
+
 fn create_desktop_entry(name: &str, appimage_path: &Path) -> Result<()> {
     let home_dir =
         dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
     let desktop_dir = home_dir.join(".local/share/applications");
 
-    fs::create_dir_all(&desktop_dir)?;
-
     let desktop_file = desktop_dir.join(format!("{}.desktop", name.to_lowercase()));
     let desktop_content = format!(
         "[Desktop Entry]\n\
@@ -69,7 +69,7 @@ fn create_desktop_entry(name: &str, appimage_path: &Path) -> Result<()> {
         appimage_path.display()
     );
 
-    fs::write(&desktop_file, desktop_content)?;
+    ownership::write(&desktop_file, desktop_content.as_bytes(), FileKind::Regular)?;
     info!("Created desktop entry at {:?}", desktop_file);
 
     Ok(())
@@ -100,6 +100,15 @@ pub fn list_appimages() -> Result<Vec<String>> {
     Ok(appimages)
 }
 
+/// Where the AppImage installed as `name` lives
+pub fn appimage_path(name: &str) -> Result<PathBuf> {
+    let home_dir =
+        dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+    Ok(home_dir
+        .join(APPIMAGE_DIR)
+        .join(format!("{}.AppImage", name)))
+}
+
 pub fn remove_appimage(name: &str) -> Result<()> {
     info!("Removing AppImage: {}", name);
 
@@ -323,7 +323,10 @@ impl AptManager {
             .executor
             .execute_package_command(
                 "dpkg-query",
-                &["-W", "--showformat=${Package}\t${Version}\t${Status}\n"],
+                &[
+                    "-W",
+                    "--showformat=${Package}\t${Version}\t${Status}\t${Architecture}\n",
+                ],
                 config,
             )
             .await?;
@@ -335,11 +338,16 @@ impl AptManager {
                 .filter_map(|line| {
                     let parts: Vec<&str> = line.split('\t').collect();
                     if parts.len() >= 3 && parts[2].contains("installed") {
-                        Some(InstalledPackage::with_description(
+                        let package = InstalledPackage::with_description(
                             parts[0].to_string(),
                             parts[1].to_string(),
                             None,
-                        ))
+                        );
+                        // Multi-arch systems list a package once per architecture
+                        Some(match parts.get(3).filter(|arch| !arch.is_empty()) {
+                            Some(arch) => package.with_architecture(arch.to_string()),
+                            None => package,
+                        })
                     } else {
                         None
                     }
@@ -27,14 +27,22 @@ impl BrewBox {
             .map(|output| output.status.success())
             .unwrap_or(false)
     }
-}
 
-impl PackageManager for BrewBox {
-    fn install(&self, package: &str) -> Result<()> {
+    /// Install a cask (a macOS app) rather than a formula of the same name
+    pub fn install_cask(&self, package: &str) -> Result<()> {
+        self.install_package(package, true)
+    }
+
+    fn install_package(&self, package: &str, cask: bool) -> Result<()> {
         let package = package.to_string();
         let executor = self.executor.clone();
         RuntimeManager::block_on(async move {
             info!("Installing '{}' via brew", package);
+            let mut args = vec!["install"];
+            if cask {
+                args.push("--cask");
+            }
+            args.push(&package);
 
             let config = ExecutionConfig {
                 requires_sudo: false,
@@ -43,7 +51,7 @@ impl PackageManager for BrewBox {
             };
 
             let result = executor
-                .execute_package_command("brew", &["install", &package], config)
+                .execute_package_command("brew", &args, config)
                 .await?;
 
             if result.exit_code == 0 {
@@ -60,6 +68,12 @@ impl PackageManager for BrewBox {
             }
         })
     }
+}
+
+impl PackageManager for BrewBox {
+    fn install(&self, package: &str) -> Result<()> {
+        self.install_package(package, false)
+    }
 
     fn remove(&self, package: &str) -> Result<()> {
         let package = package.to_string();
@@ -492,14 +492,20 @@ impl DnfBox {
                 .skip(1) // Skip header line
                 .filter_map(|line| {
                     let parts: Vec<&str> = line.split_whitespace().collect();
-                    if parts.len() >= 3 && parts[0].contains(".") {
-                        Some(InstalledPackage::with_description(
-                            parts[0].split('.').next().unwrap_or(parts[0]).to_string(),
-                            parts[1].to_string(),
-                            None,
-                        ))
-                    } else {
-                        None
+                    // `name.arch`; the name itself may contain dots
+                    match parts[..] {
+                        [name_arch, version, _, ..] => {
+                            let (name, arch) = name_arch.rsplit_once('.')?;
+                            Some(
+                                InstalledPackage::with_description(
+                                    name.to_string(),
+                                    version.to_string(),
+                                    None,
+                                )
+                                .with_architecture(arch.to_string()),
+                            )
+                        }
+                        _ => None,
                     }
                 })
                 .collect();
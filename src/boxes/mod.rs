@@ -10,6 +10,7 @@ pub mod zypper; // openSUSE
 
 // Cross-platform package managers
 pub mod nix; // NixOS/Nix
+pub mod source; // Build from source recipes
 
 // Windows package managers
 pub mod chocolatey;
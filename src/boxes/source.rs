@@ -0,0 +1,379 @@
+use crate::config::{self, OmniConfig};
+use crate::distro;
+use crate::sandboxing::Sandbox;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+/// Recipes shared by every user of the machine
+const SYSTEM_RECIPES: &str = "/etc/omni/recipes.yaml";
+
+/// How to build a package from its source repository. Build steps run in the
+/// checkout and must install into `$PREFIX`, which omni then copies to `~/.local`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceRecipe {
+    /// Git repository to clone; a manifest entry may give it as `source` instead
+    #[serde(default)]
+    pub repo: String,
+    /// Tag, branch or commit to build
+    #[serde(default)]
+    pub rev: Option<String>,
+    pub build: Vec<String>,
+    /// Steps need no network once the repository is cloned
+    #[serde(default)]
+    pub offline: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RecipeFile {
+    #[serde(default)]
+    recipes: BTreeMap<String, SourceRecipe>,
+}
+
+/// Files a source build put below `~/.local`, kept so it can be removed again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceInstall {
+    pub package_name: String,
+    pub repo: String,
+    pub rev: Option<String>,
+    pub commit: Option<String>,
+    pub installed_at: DateTime<Utc>,
+    /// Paths relative to the install prefix
+    pub files: Vec<PathBuf>,
+}
+
+impl SourceInstall {
+    pub fn version(&self) -> String {
+        match (&self.rev, &self.commit) {
+            (Some(rev), _) => rev.clone(),
+            (None, Some(commit)) => commit.chars().take(12).collect(),
+            (None, None) => "unknown".to_string(),
+        }
+    }
+}
+
+/// Recipe for `package` from the user's `recipes.yaml`, falling back to
+/// `/etc/omni/recipes.yaml`
+pub fn find_recipe(package: &str) -> Result<Option<SourceRecipe>> {
+    let user = OmniConfig::config_path()?.with_file_name("recipes.yaml");
+    for path in [user, PathBuf::from(SYSTEM_RECIPES)] {
+        if let Some(recipe) = load_recipes(&path)?.remove(package) {
+            return Ok(Some(recipe));
+        }
+    }
+    Ok(None)
+}
+
+fn load_recipes(path: &Path) -> Result<BTreeMap<String, SourceRecipe>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let file: RecipeFile = serde_yaml::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| anyhow!("Invalid recipe file {}: {}", path.display(), e))?;
+    Ok(file.recipes)
+}
+
+/// Where source builds are installed
+pub fn install_prefix() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home.join(".local"))
+}
+
+/// Clone, build and install `package` into `~/.local`
+pub fn install(package: &str, recipe: &SourceRecipe) -> Result<SourceInstall> {
+    if recipe.repo.is_empty() {
+        return Err(anyhow!("The recipe for {} names no repository", package));
+    }
+    if recipe.build.is_empty() {
+        return Err(anyhow!("The recipe for {} has no build steps", package));
+    }
+    if !distro::command_exists("git") {
+        return Err(anyhow!("Building from source needs git"));
+    }
+
+    let build_dir = OmniConfig::cache_dir()?.join("source").join(package);
+    if build_dir.exists() {
+        fs::remove_dir_all(&build_dir)?;
+    }
+    let checkout = build_dir.join("src");
+    let stage = build_dir.join("stage");
+    let home = build_dir.join("home");
+    for dir in [&stage, &home] {
+        fs::create_dir_all(dir)?;
+    }
+
+    info!("Cloning {} for {}", recipe.repo, package);
+    git(
+        None,
+        &[
+            "clone",
+            "--recurse-submodules",
+            &recipe.repo,
+            &checkout.display().to_string(),
+        ],
+    )?;
+    if let Some(rev) = &recipe.rev {
+        git(Some(&checkout), &["checkout", "--quiet", rev])?;
+        git(
+            Some(&checkout),
+            &["submodule", "update", "--init", "--recursive"],
+        )?;
+    }
+    let commit = git(Some(&checkout), &["rev-parse", "HEAD"]).ok();
+
+    let mut sandbox = Sandbox::new()?;
+    sandbox.set_network_access(!recipe.offline);
+    sandbox.allow_write(&build_dir);
+
+    let prefix = stage.display().to_string();
+    let jobs = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .to_string();
+    let build_home = home.display().to_string();
+    let mut env = vec![
+        ("PREFIX", prefix.as_str()),
+        ("JOBS", jobs.as_str()),
+        ("HOME", build_home.as_str()),
+    ];
+    // Toolchains installed with rustup stay usable with the scratch HOME
+    let rustup_home = dirs::home_dir()
+        .map(|h| h.join(".rustup"))
+        .filter(|p| p.exists())
+        .map(|p| p.display().to_string());
+    if let Some(rustup_home) = &rustup_home {
+        env.push(("RUSTUP_HOME", rustup_home.as_str()));
+    }
+
+    for step in &recipe.build {
+        println!("🔨 {}", step);
+        sandbox.execute_script(&checkout, step, &env).map_err(|e| {
+            anyhow!(
+                "Building {} failed; the build is left in {}\n{}",
+                package,
+                build_dir.display(),
+                e
+            )
+        })?;
+    }
+
+    let files = staged_files(&stage)?;
+    if files.is_empty() {
+        return Err(anyhow!(
+            "The recipe for {} installed nothing into $PREFIX",
+            package
+        ));
+    }
+
+    let prefix = install_prefix()?;
+    let previous = load_install(package)?;
+    let owned: Vec<&PathBuf> = previous.iter().flat_map(|p| &p.files).collect();
+    if let Some(conflict) = files
+        .iter()
+        .find(|f| prefix.join(f).exists() && !owned.contains(&f))
+    {
+        return Err(anyhow!(
+            "{} already exists and does not belong to {}",
+            prefix.join(conflict).display(),
+            package
+        ));
+    }
+
+    if let Some(previous) = &previous {
+        remove_files(&prefix, &previous.files)?;
+    }
+    for file in &files {
+        let target = prefix.join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        copy_file(&stage.join(file), &target)?;
+        crate::access_control::relabel(&target);
+    }
+
+    let install = SourceInstall {
+        package_name: package.to_string(),
+        repo: recipe.repo.clone(),
+        rev: recipe.rev.clone(),
+        commit,
+        installed_at: Utc::now(),
+        files,
+    };
+    save_install(&install)?;
+    fs::remove_dir_all(&build_dir)?;
+
+    info!(
+        "Installed {} files of {} into {}",
+        install.files.len(),
+        package,
+        prefix.display()
+    );
+    Ok(install)
+}
+
+/// Delete the files a source build installed
+pub fn remove(package: &str) -> Result<()> {
+    let install = load_install(package)?
+        .ok_or_else(|| anyhow!("{} was not built from source by omni", package))?;
+
+    remove_files(&install_prefix()?, &install.files)?;
+    fs::remove_file(install_record_path(package)?)?;
+    info!("Removed {} files of {}", install.files.len(), package);
+    Ok(())
+}
+
+pub fn is_installed(package: &str) -> bool {
+    matches!(load_install(package), Ok(Some(_)))
+}
+
+pub fn load_install(package: &str) -> Result<Option<SourceInstall>> {
+    let path = install_record_path(package)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(&path)?)?))
+}
+
+fn save_install(install: &SourceInstall) -> Result<()> {
+    let path = install_record_path(&install.package_name)?;
+    if let Some(parent) = path.parent() {
+        config::ensure_private_dir(parent)?;
+    }
+    config::write_private_file(&path, serde_json::to_string_pretty(install)?.as_bytes())
+}
+
+fn install_record_path(package: &str) -> Result<PathBuf> {
+    Ok(OmniConfig::state_dir()?
+        .join("source")
+        .join(format!("{}.json", package)))
+}
+
+/// Regular files and symlinks below `stage`, relative to it
+pub fn staged_files(stage: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![stage.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() && !path.is_symlink() {
+                pending.push(path);
+            } else if let Ok(relative) = path.strip_prefix(stage) {
+                files.push(relative.to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Remove `files` below `prefix` and the directories they leave empty
+pub fn remove_files(prefix: &Path, files: &[PathBuf]) -> Result<()> {
+    for file in files {
+        let path = prefix.join(file);
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("{} was already removed", path.display());
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut dir = path.parent();
+        while let Some(parent) = dir.filter(|d| *d != prefix && d.starts_with(prefix)) {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+    }
+    Ok(())
+}
+
+/// Copy a staged file, recreating symlinks such as `libfoo.so -> libfoo.so.1`
+fn copy_file(from: &Path, to: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if from.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    Ok(())
+}
+
+fn git(dir: Option<&Path>, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    let output = command.args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.first().copied().unwrap_or_default(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recipe_file() {
+        let file: RecipeFile = serde_yaml::from_str(
+            r#"
+recipes:
+  ripgrep:
+    repo: https://github.com/BurntSushi/ripgrep
+    rev: 14.1.0
+    build:
+      - cargo install --locked --path . --root "$PREFIX"
+"#,
+        )
+        .unwrap();
+
+        let recipe = &file.recipes["ripgrep"];
+        assert_eq!(recipe.rev.as_deref(), Some("14.1.0"));
+        assert_eq!(recipe.build.len(), 1);
+        assert!(!recipe.offline);
+    }
+
+    #[test]
+    fn test_staged_files_are_tracked_and_removed() {
+        let stage = tempfile::tempdir().unwrap();
+        fs::create_dir_all(stage.path().join("bin")).unwrap();
+        fs::create_dir_all(stage.path().join("share/man/man1")).unwrap();
+        fs::write(stage.path().join("bin/rg"), "").unwrap();
+        fs::write(stage.path().join("share/man/man1/rg.1"), "").unwrap();
+
+        let files = staged_files(stage.path()).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("bin/rg"),
+                PathBuf::from("share/man/man1/rg.1")
+            ]
+        );
+
+        let prefix = tempfile::tempdir().unwrap();
+        fs::create_dir_all(prefix.path().join("bin")).unwrap();
+        fs::write(prefix.path().join("bin/other"), "").unwrap();
+        for file in &files {
+            let target = prefix.path().join(file);
+            fs::create_dir_all(target.parent().unwrap()).unwrap();
+            fs::copy(stage.path().join(file), target).unwrap();
+        }
+
+        remove_files(prefix.path(), &files).unwrap();
+        assert!(prefix.path().join("bin/other").exists());
+        assert!(!prefix.path().join("share").exists());
+        assert!(prefix.path().exists());
+    }
+}
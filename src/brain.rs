@@ -1,22 +1,50 @@
+use crate::advanced_resolver;
+use crate::artifacts;
+use crate::audit::{AuditEntry, AuditEventType, AuditManager, AuditSeverity};
+use crate::autoremove;
 use crate::boxes::appimage;
 use crate::boxes::apt::AptManager;
+use crate::boxes::brew::BrewBox;
 use crate::boxes::dnf::DnfBox;
 use crate::boxes::flatpak::FlatpakBox;
+use crate::boxes::mas::MasBox;
 use crate::boxes::pacman::PacmanBox;
 use crate::boxes::snap::SnapBox;
-use crate::database::{Database, InstallRecord, InstallStatus};
+use crate::boxes::source;
+use crate::boxes::winget::WingetBox;
+use crate::channels::{self, Channel};
+use crate::config::{NotifyEvent, OmniConfig};
+use crate::database::{ArtifactRecord, Database, InstallRecord, InstallStatus, PinnedPackage};
 use crate::distro::{self, PackageManager};
+use crate::downgrade;
+use crate::error_handling::OmniError;
 use crate::hardware::{detect_and_suggest_drivers, HardwareDetector};
-use crate::input_validation::InputValidator;
+use crate::history;
+use crate::input_validation::{InputValidator, VersionConstraint};
+use crate::interactive::InteractivePrompts;
+use crate::interop;
+use crate::keys::{self, KeyStore};
+use crate::licenses;
 use crate::manifest::OmniManifest;
+use crate::mock;
+use crate::notifications::{self, Dispatcher, PackageEvent};
+use crate::ownership::{self, FileKind};
+use crate::policy::{self, Operation, OrgPolicy};
+use crate::priority;
 use crate::privilege_manager::PrivilegeManager;
+use crate::provenance;
 use crate::sandboxing::Sandbox;
 use crate::search::SearchEngine;
 use crate::snapshot::SnapshotManager;
+use crate::transaction::{
+    OperationType as TransactionOperation, TransactionManager, TransactionType,
+};
+use crate::updater::UpdateManager;
 use anyhow::{anyhow, Result};
 use chrono::Utc;
 use indicatif::{ProgressBar, ProgressStyle};
-use tracing::{error, info, warn};
+use std::io::IsTerminal;
+use tracing::{error, info, instrument, warn};
 use uuid::Uuid;
 
 pub struct OmniBrain {
@@ -25,6 +53,10 @@ pub struct OmniBrain {
     snapshot_manager: Option<SnapshotManager>,
     privilege_manager: PrivilegeManager,
     search_engine: Option<SearchEngine>,
+    accept_licenses: bool,
+    channel: Option<Channel>,
+    /// Organisation policy checked by the running operation
+    policy: Option<OrgPolicy>,
 }
 
 impl OmniBrain {
@@ -38,6 +70,9 @@ impl OmniBrain {
             snapshot_manager: None,
             privilege_manager,
             search_engine: None,
+            accept_licenses: false,
+            channel: None,
+            policy: None,
         }
     }
 
@@ -51,9 +86,24 @@ impl OmniBrain {
             snapshot_manager: None,
             privilege_manager,
             search_engine: None,
+            accept_licenses: false,
+            channel: None,
+            policy: None,
         }
     }
 
+    /// Accept package EULAs without prompting (`--accept-licenses`)
+    pub fn with_license_acceptance(mut self, accept: bool) -> Self {
+        self.accept_licenses = accept;
+        self
+    }
+
+    /// Install from a release channel instead of the default one (`--channel`)
+    pub fn with_channel(mut self, channel: Option<Channel>) -> Self {
+        self.channel = channel;
+        self
+    }
+
     async fn ensure_initialized(&mut self) -> Result<()> {
         if self.db.is_none() {
             self.db = Some(Database::new().await?);
@@ -67,26 +117,67 @@ impl OmniBrain {
         Ok(())
     }
 
+    #[instrument(skip(self))]
     pub async fn install(&mut self, app: &str, box_type: Option<&str>) -> Result<()> {
-        // Validate inputs first
-        InputValidator::validate_package_name(app)?;
+        crate::config::ensure_writable("installing packages")?;
+        // Validate inputs first; `app` may carry a version constraint (nginx>=1.24,<2)
+        let spec = InputValidator::parse_package_spec(app)?;
+        let app = spec.name.as_str();
         if let Some(bt) = box_type {
             InputValidator::validate_box_type(bt)?;
         }
+        if spec.constraint.is_some() && self.channel.as_ref().is_some_and(|c| !c.is_stable()) {
+            return Err(anyhow!("A version constraint cannot be combined with --channel"));
+        }
 
         if self.mock_mode {
-            println!("🎭 [MOCK] Installing '{}'", app);
-            println!("✅ [MOCK] Successfully installed {} (simulated)", app);
-            return Ok(());
+            return mock_install(app, box_type, spec.constraint.as_ref());
         }
 
+        // A virtual name (mta, java-runtime) needs a concrete provider from
+        // the box the install goes to: the requested one, or else the first
+        // one auto-detection tries
+        let target_box = match box_type {
+            Some(box_type) => Some(box_type.to_string()),
+            None => priority::box_order(&OmniConfig::load().unwrap_or_default())
+                .into_iter()
+                .find(|bt| self.policy.as_ref().map_or(true, |p| p.allows_box(bt))),
+        };
+        let provider = match &target_box {
+            Some(target_box) => choose_provider(app, target_box)?,
+            None => None,
+        };
+        let (app, box_type) = match &provider {
+            Some(provider) => (provider.as_str(), target_box.as_deref()),
+            None => (app, box_type),
+        };
+
+        // `libc6:i386` goes to the box that can install it under its multi-arch name
+        let multiarch = spec
+            .arch
+            .as_deref()
+            .map(|arch| qualify_for_architecture(app, arch, box_type))
+            .transpose()?;
+        let (app, box_type) = match &multiarch {
+            Some((name, box_type)) => (name.as_str(), Some(box_type.as_str())),
+            None => (app, box_type),
+        };
+
         self.ensure_initialized().await?;
+        self.enforce(&Operation::Install {
+            package: app,
+            box_type,
+            source: None,
+        })
+        .await?;
+        self.handle_license_requirements(app, box_type).await?;
 
         // Create automatic snapshot before installation
         if let Some(snapshot_manager) = &self.snapshot_manager {
             let _ = snapshot_manager.auto_snapshot("install", app).await;
         }
 
+        let started = std::time::Instant::now();
         let pb = ProgressBar::new_spinner();
         pb.set_style(
             ProgressStyle::default_spinner()
@@ -96,10 +187,15 @@ impl OmniBrain {
         pb.set_message(format!("Installing {}...", app));
         pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
-        let result = if let Some(preferred_box) = box_type {
-            self.install_with_specific_box(app, preferred_box).await
-        } else {
-            self.install_with_auto_detection(app).await
+        let result = match (&self.channel, box_type, &spec.constraint) {
+            (_, _, Some(constraint)) => self.install_constrained(app, box_type, constraint).await,
+            (Some(channel), _, None) if !channel.is_stable() => {
+                self.install_from_channel(app, box_type, channel).await
+            }
+            (_, Some(preferred_box), None) => {
+                self.install_with_specific_box(app, preferred_box).await
+            }
+            (_, None, None) => self.install_with_auto_detection(app).await,
         };
 
         pb.finish_and_clear();
@@ -110,26 +206,66 @@ impl OmniBrain {
 
                 // Record the installation
                 if let Some(db) = &self.db {
+                    let provenance = provenance::detect(app, &box_type);
+                    let built = match box_type.as_str() {
+                        "source" => source::load_install(app).ok().flatten(),
+                        _ => None,
+                    };
                     let install_record = InstallRecord {
                         id: Uuid::new_v4().to_string(),
                         package_name: app.to_string(),
                         box_type: box_type.clone(),
-                        version: Some(version),
-                        source_url: None,
-                        install_path: None,
+                        version: Some(version.clone()),
+                        source_url: built.as_ref().map(|b| b.repo.clone()),
+                        install_path: built
+                            .and_then(|_| source::install_prefix().ok())
+                            .map(|p| p.display().to_string()),
                         installed_at: Utc::now(),
                         status: InstallStatus::Success,
+                        repository: provenance.repository,
+                        mirror_url: provenance.mirror_url,
+                        channel: self.channel.as_ref().map(|c| c.to_string()),
                         metadata: None,
                     };
 
                     let _ = db.record_install(&install_record).await;
+                    let artifact = artifacts::capture(app, &box_type, Some(&version), None);
+                    let _ = db.record_artifact(&artifact).await;
+
+                    // Updates stay within the constraint until the package is unpinned
+                    if let Some(constraint) = &spec.constraint {
+                        let pin = PinnedPackage {
+                            package_name: app.to_string(),
+                            box_type: box_type.clone(),
+                            version: Some(version.clone()),
+                            reason: Some(format!("Installed as {}{}", app, constraint)),
+                            pinned_at: Utc::now(),
+                            constraint: Some(constraint.to_string()),
+                        };
+                        if let Err(e) = db.pin_package(&pin).await {
+                            warn!("Could not record the version constraint of {}: {}", app, e);
+                        }
+                    }
                 }
 
+                notifications::notify(
+                    PackageEvent::new(NotifyEvent::Install, app)
+                        .with_box_type(Some(&box_type))
+                        .with_version(Some(version))
+                        .with_duration(started.elapsed()),
+                )
+                .await;
                 println!("✅ Successfully installed {}", app);
                 Ok(())
             }
             Err(e) => {
                 error!("❌ Failed to install {}: {}", app, e);
+                notifications::notify(
+                    PackageEvent::failed(NotifyEvent::Install, app, &e)
+                        .with_box_type(box_type)
+                        .with_duration(started.elapsed()),
+                )
+                .await;
 
                 // Record the failed installation
                 if let Some(db) = &self.db {
@@ -142,6 +278,9 @@ impl OmniBrain {
                         install_path: None,
                         installed_at: Utc::now(),
                         status: InstallStatus::Failed,
+                        repository: None,
+                        mirror_url: None,
+                        channel: None,
                         metadata: Some(format!("Error: {}", e)),
                     };
 
@@ -158,10 +297,143 @@ impl OmniBrain {
         app: &str,
         box_type: &str,
     ) -> Result<(String, String)> {
+        if box_type == "source" {
+            return self.install_from_source(app);
+        }
+
         // Use secure installation method
         self.install_securely(app, box_type).await
     }
 
+    /// Build `app` from its source recipe and install it into `~/.local`
+    fn install_from_source(&self, app: &str) -> Result<(String, String)> {
+        let recipe = source::find_recipe(app)?.ok_or_else(|| {
+            anyhow!(
+                "No source recipe for {}; add one to ~/.config/omni/recipes.yaml",
+                app
+            )
+        })?;
+
+        println!("🏗️  Building {} from {}", app, recipe.repo);
+        let install = source::install(app, &recipe)?;
+        Ok(("source".to_string(), install.version()))
+    }
+
+    /// Install the newest version of `app` that `constraint` allows, with the
+    /// requested box or the first box in priority order that has one
+    async fn install_constrained(
+        &self,
+        app: &str,
+        box_type: Option<&str>,
+        constraint: &VersionConstraint,
+    ) -> Result<(String, String)> {
+        let box_types = match box_type {
+            Some(box_type) => vec![box_type.to_string()],
+            None => priority::box_order(&OmniConfig::load().unwrap_or_default()),
+        };
+
+        let mut last_error = None;
+        for box_type in box_types {
+            if !downgrade::VERSIONED_BOXES.contains(&box_type.as_str())
+                || self.policy.as_ref().is_some_and(|p| !p.allows_box(&box_type))
+            {
+                continue;
+            }
+            let listing = match downgrade::query_versions(app, &box_type) {
+                Ok(listing) => listing,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            let Some(version) = constraint.newest(&listing.available) else {
+                last_error = Some(anyhow!(
+                    "No version of {} from {} satisfies {} (available: {})",
+                    app,
+                    box_type,
+                    constraint,
+                    if listing.available.is_empty() {
+                        "none".to_string()
+                    } else {
+                        listing.available.join(", ")
+                    }
+                ));
+                continue;
+            };
+
+            if listing.installed.as_deref() == Some(version.as_str()) {
+                info!("{} {} is already installed", app, version);
+            } else {
+                info!(
+                    "Installing {} {} via {} to satisfy {}",
+                    app, version, box_type, constraint
+                );
+                let (command, args) = downgrade::install_version_command(app, &box_type, version)?;
+                let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+                self.privilege_manager.execute_with_sudo(&command, &args)?;
+            }
+            return Ok((box_type, version.clone()));
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            anyhow!(
+                "Only {} can install a chosen version of {}",
+                downgrade::VERSIONED_BOXES.join(", "),
+                app
+            )
+        }))
+    }
+
+    /// Install from a non-default release channel with the requested box, or the
+    /// first box in priority order that offers the channel
+    async fn install_from_channel(
+        &self,
+        app: &str,
+        box_type: Option<&str>,
+        channel: &Channel,
+    ) -> Result<(String, String)> {
+        let box_types = match box_type {
+            Some(box_type) => vec![box_type.to_string()],
+            None => priority::box_order(&OmniConfig::load().unwrap_or_default()),
+        };
+
+        let mut last_error = None;
+        for box_type in box_types {
+            let (command, args) = match channels::install_command(&box_type, app, channel) {
+                Ok(command) => command,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            info!(
+                "Installing {} from the {} channel via {}",
+                app, channel, box_type
+            );
+
+            let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+            if channels::needs_privilege(&box_type) {
+                self.privilege_manager.execute_with_sudo(&command, &args)?;
+            } else {
+                let output = std::process::Command::new(&command).args(&args).output()?;
+                if !output.status.success() {
+                    return Err(OmniError::InstallationFailed {
+                        package: app.to_string(),
+                        box_type,
+                        reason: String::from_utf8_lossy(&output.stderr).to_string(),
+                    }
+                    .into());
+                }
+            }
+
+            let version = self.get_package_version(app, &box_type).await?;
+            return Ok((box_type, version));
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| anyhow!("No package manager offers the {} channel", channel)))
+    }
+
     async fn install_securely(&self, app: &str, box_type: &str) -> Result<(String, String)> {
         info!("Starting secure installation of {} via {}", app, box_type);
 
@@ -176,11 +448,16 @@ impl OmniBrain {
             "apt" if distro::command_exists("apt") => {
                 // Check if we need sudo
                 if !PrivilegeManager::is_root() && !PrivilegeManager::can_sudo() {
-                    return Err(anyhow!("sudo access required for apt installation"));
+                    return Err(OmniError::PermissionDenied {
+                        operation: "apt installation (sudo access required)".to_string(),
+                    }
+                    .into());
                 }
 
                 // Execute apt in sandbox with proper privilege management
-                let args = vec!["install", "-y", app];
+                let config = OmniConfig::load().unwrap_or_default();
+                let target = priority::install_target(&config, "apt", app);
+                let args = vec!["install", "-y", target.as_str()];
                 if PrivilegeManager::is_root() {
                     sandbox.execute("apt", &args)?;
                 } else {
@@ -194,10 +471,15 @@ impl OmniBrain {
             }
             "dnf" if distro::command_exists("dnf") => {
                 if !PrivilegeManager::is_root() && !PrivilegeManager::can_sudo() {
-                    return Err(anyhow!("sudo access required for dnf installation"));
+                    return Err(OmniError::PermissionDenied {
+                        operation: "dnf installation (sudo access required)".to_string(),
+                    }
+                    .into());
                 }
 
-                let args = vec!["install", "-y", app];
+                let config = OmniConfig::load().unwrap_or_default();
+                let target = priority::install_target(&config, "dnf", app);
+                let args = vec!["install", "-y", target.as_str()];
                 if PrivilegeManager::is_root() {
                     sandbox.execute("dnf", &args)?;
                 } else {
@@ -211,7 +493,10 @@ impl OmniBrain {
             }
             "pacman" if distro::command_exists("pacman") => {
                 if !PrivilegeManager::is_root() && !PrivilegeManager::can_sudo() {
-                    return Err(anyhow!("sudo access required for pacman installation"));
+                    return Err(OmniError::PermissionDenied {
+                        operation: "pacman installation (sudo access required)".to_string(),
+                    }
+                    .into());
                 }
 
                 let args = vec!["-S", "--noconfirm", app];
@@ -248,10 +533,10 @@ impl OmniBrain {
                     self.get_package_version(app, box_type).await?,
                 ))
             }
-            _ => Err(anyhow!(
-                "Box type '{}' not available or not supported",
-                box_type
-            )),
+            _ => Err(OmniError::UnsupportedBoxType {
+                box_type: box_type.to_string(),
+            }
+            .into()),
         }
     }
 
@@ -306,53 +591,50 @@ impl OmniBrain {
     async fn install_with_auto_detection(&self, app: &str) -> Result<(String, String)> {
         info!("🔥 Installing '{}'", app);
 
-        // Try boxes in order of preference
-        if distro::command_exists("apt") {
-            info!("Trying to install {} with apt", app);
-            if let Ok(apt_manager) = AptManager::new() {
-                apt_manager.install(app)?;
-                return Ok(("apt".to_string(), self.get_package_version(app, "apt").await?));
-            }
-        }
-
-        if distro::command_exists("dnf") {
-            info!("Trying to install {} with dnf", app);
-            if let Ok(dnf_manager) = DnfBox::new() {
-                dnf_manager.install(app)?;
-                return Ok(("dnf".to_string(), self.get_package_version(app, "dnf").await?));
-            }
-        }
+        // Try boxes in the configured order of preference
+        let config = OmniConfig::load().unwrap_or_default();
+        let mut last_error = None;
 
-        if distro::command_exists("pacman") {
-            info!("Trying to install {} with pacman", app);
-            if let Ok(pacman_manager) = PacmanBox::new() {
-                pacman_manager.install(app)?;
-                return Ok(("pacman".to_string(), self.get_package_version(app, "pacman").await?));
+        for box_type in priority::box_order(&config) {
+            if self.policy.as_ref().is_some_and(|p| !p.allows_box(&box_type)) {
+                continue;
             }
-        }
+            info!("Trying to install {} with {}", app, box_type);
+            let target = priority::install_target(&config, &box_type, app);
+            let result = match box_type.as_str() {
+                "apt" => AptManager::new().and_then(|m| m.install(&target)),
+                "dnf" => DnfBox::new().and_then(|m| m.install(&target)),
+                "pacman" => PacmanBox::new().and_then(|m| m.install(&target)),
+                "flatpak" => FlatpakBox::new().and_then(|m| m.install(&target)),
+                "snap" => SnapBox::new().and_then(|m| m.install(&target)),
+                _ => continue,
+            };
 
-        // Try snap
-        if distro::command_exists("snap") {
-            info!("Trying to install {} with snap", app);
-            if let Ok(snap_manager) = SnapBox::new() {
-                snap_manager.install(app)?;
-                return Ok(("snap".to_string(), self.get_package_version(app, "snap").await?));
+            match result {
+                Ok(()) => {
+                    let version = self.get_package_version(app, &box_type).await?;
+                    return Ok((box_type, version));
+                }
+                Err(e) if config.general.fallback_enabled => {
+                    warn!("Installing {} with {} failed: {}", app, box_type, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
             }
         }
 
-        // Try flatpak
-        if distro::command_exists("flatpak") {
-            info!("Trying to install {} with flatpak", app);
-            if let Ok(flatpak_manager) = FlatpakBox::new() {
-                flatpak_manager.install(app)?;
-                return Ok(("flatpak".to_string(), self.get_package_version(app, "flatpak").await?));
-            }
+        let source_allowed = self.policy.as_ref().map_or(true, |p| p.allows_box("source"));
+        if config.general.source_fallback && source_allowed && source::find_recipe(app)?.is_some() {
+            warn!("No binary package of {} could be installed", app);
+            return self.install_from_source(app);
         }
 
-        Err(anyhow::anyhow!("No supported package managers found"))
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No supported package managers found")))
     }
 
+    #[instrument(skip_all, fields(project = %manifest.project))]
     pub async fn install_from_manifest(&mut self, manifest: OmniManifest) -> Result<()> {
+        crate::config::ensure_writable("installing from a manifest")?;
         if self.mock_mode {
             println!("🎭 [MOCK] Installing from manifest: {}", manifest.project);
             if let Some(desc) = &manifest.description {
@@ -360,11 +642,7 @@ impl OmniBrain {
             }
 
             for app in &manifest.apps {
-                println!("🎭 [MOCK] Installing {} via {} box", app.name, app.box_type);
-                if let Some(source) = &app.source {
-                    println!("📦 [MOCK] Source: {}", source);
-                }
-                println!("✅ [MOCK] Successfully installed {} (simulated)", app.name);
+                mock_install(&app.name, None, None)?;
             }
             return Ok(());
         }
@@ -391,16 +669,41 @@ impl OmniBrain {
             .unwrap()
             .progress_chars("#>-"));
 
+        // Each package's history is written before the next install starts, so
+        // an interrupted run still records everything it installed
+        let mut batch = Vec::new();
         for (i, app) in manifest.apps.iter().enumerate() {
             pb.set_message(app.name.clone());
             pb.set_position(i as u64);
 
+            let operation = Operation::Install {
+                package: &app.name,
+                box_type: Some(&app.box_type),
+                source: app.source.as_deref(),
+            };
+            if let Err(e) = self.enforce(&operation).await {
+                eprintln!("❌ Skipping {}: {}", app.name, e);
+                continue;
+            }
+
+            if let Err(e) = self
+                .handle_license_requirements(&app.name, Some(app.box_type.as_str()))
+                .await
+            {
+                eprintln!("❌ Skipping {}: {}", app.name, e);
+                continue;
+            }
+
             let handled = match app.box_type.as_str() {
                 "apt" if distro::command_exists("apt") => {
                     if let Ok(apt_manager) = AptManager::new() {
                         if apt_manager.install(&app.name).is_ok() {
-                            self.record_manifest_install(&app.name, "apt", app.source.as_deref())
-                                .await;
+                            batch.extend(self.manifest_record(
+                                &manifest.project,
+                                &app.name,
+                                "apt",
+                                app.source.as_deref(),
+                            ));
                             true
                         } else {
                             false
@@ -412,8 +715,12 @@ impl OmniBrain {
                 "pacman" if distro::command_exists("pacman") => {
                     if let Ok(pacman_manager) = PacmanBox::new() {
                         if pacman_manager.install(&app.name).is_ok() {
-                            self.record_manifest_install(&app.name, "pacman", app.source.as_deref())
-                                .await;
+                            batch.extend(self.manifest_record(
+                                &manifest.project,
+                                &app.name,
+                                "pacman",
+                                app.source.as_deref(),
+                            ));
                             true
                         } else {
                             false
@@ -425,8 +732,12 @@ impl OmniBrain {
                 "dnf" if distro::command_exists("dnf") => {
                     if let Ok(dnf_manager) = DnfBox::new() {
                         if dnf_manager.install(&app.name).is_ok() {
-                            self.record_manifest_install(&app.name, "dnf", app.source.as_deref())
-                                .await;
+                            batch.extend(self.manifest_record(
+                                &manifest.project,
+                                &app.name,
+                                "dnf",
+                                app.source.as_deref(),
+                            ));
                             true
                         } else {
                             false
@@ -439,8 +750,12 @@ impl OmniBrain {
                     if let Ok(flatpak_manager) = FlatpakBox::new() {
                         let name = app.source.as_deref().unwrap_or(&app.name);
                         if flatpak_manager.install(name).is_ok() {
-                            self.record_manifest_install(&app.name, "flatpak", app.source.as_deref())
-                                .await;
+                            batch.extend(self.manifest_record(
+                                &manifest.project,
+                                &app.name,
+                                "flatpak",
+                                app.source.as_deref(),
+                            ));
                             true
                         } else {
                             false
@@ -452,8 +767,34 @@ impl OmniBrain {
                 "snap" if distro::command_exists("snap") => {
                     if let Ok(snap_manager) = SnapBox::new() {
                         if snap_manager.install(&app.name).is_ok() {
-                            self.record_manifest_install(&app.name, "snap", app.source.as_deref())
-                                .await;
+                            batch.extend(self.manifest_record(
+                                &manifest.project,
+                                &app.name,
+                                "snap",
+                                app.source.as_deref(),
+                            ));
+                            true
+                        } else {
+                            false
+                        }
+                    } else {
+                        false
+                    }
+                }
+                "brew" if distro::command_exists("brew") => {
+                    if let Ok(brew_manager) = BrewBox::new() {
+                        let installed = if app.source.as_deref() == Some(interop::CASK) {
+                            brew_manager.install_cask(&app.name)
+                        } else {
+                            brew_manager.install(&app.name)
+                        };
+                        if installed.is_ok() {
+                            batch.extend(self.manifest_record(
+                                &manifest.project,
+                                &app.name,
+                                "brew",
+                                app.source.as_deref(),
+                            ));
                             true
                         } else {
                             false
@@ -462,15 +803,79 @@ impl OmniBrain {
                         false
                     }
                 }
+                "mas" if distro::command_exists("mas") => {
+                    // `source` is the App Store id
+                    let id = app.source.as_deref().unwrap_or(&app.name);
+                    if MasBox::new().and_then(|mas| mas.install(id)).is_ok() {
+                        batch.extend(self.manifest_record(
+                            &manifest.project,
+                            &app.name,
+                            "mas",
+                            app.source.as_deref(),
+                        ));
+                        true
+                    } else {
+                        false
+                    }
+                }
+                "winget" if distro::command_exists("winget") => {
+                    if WingetBox::new()
+                        .and_then(|winget| winget.install(&app.name))
+                        .is_ok()
+                    {
+                        batch.extend(self.manifest_record(
+                            &manifest.project,
+                            &app.name,
+                            "winget",
+                            app.source.as_deref(),
+                        ));
+                        true
+                    } else {
+                        false
+                    }
+                }
+                "source" => {
+                    let recipe = app.recipe.clone().map(|mut recipe| {
+                        if recipe.repo.is_empty() {
+                            recipe.repo = app.source.clone().unwrap_or_default();
+                        }
+                        recipe
+                    });
+                    match recipe.map_or_else(|| source::find_recipe(&app.name), |r| Ok(Some(r))) {
+                        Ok(Some(recipe)) => match source::install(&app.name, &recipe) {
+                            Ok(_) => {
+                                batch.extend(self.manifest_record(
+                                    &manifest.project,
+                                    &app.name,
+                                    "source",
+                                    Some(recipe.repo.as_str()),
+                                ));
+                                true
+                            }
+                            Err(e) => {
+                                eprintln!("❌ Failed to build {}: {}", app.name, e);
+                                false
+                            }
+                        },
+                        Ok(None) => {
+                            warn!("No source recipe for {}", app.name);
+                            false
+                        }
+                        Err(e) => {
+                            warn!("Cannot read source recipes: {}", e);
+                            false
+                        }
+                    }
+                }
                 "appimage" => {
                     if let Some(url) = &app.source {
                         if appimage::install_appimage(url, &app.name).await.is_ok() {
-                            self.record_manifest_install(
+                            batch.extend(self.manifest_record(
+                                &manifest.project,
                                 &app.name,
                                 "appimage",
                                 app.source.as_deref(),
-                            )
-                            .await;
+                            ));
                             true
                         } else {
                             false
@@ -489,7 +894,12 @@ impl OmniBrain {
                         "apt" if distro::command_exists("apt") => {
                             if let Ok(apt_manager) = AptManager::new() {
                                 if apt_manager.install(&app.name).is_ok() {
-                                    self.record_manifest_install(&app.name, "apt", None).await;
+                                    batch.extend(self.manifest_record(
+                                        &manifest.project,
+                                        &app.name,
+                                        "apt",
+                                        None,
+                                    ));
                                 } else {
                                     eprintln!("❌ Failed to install {} with apt", app.name);
                                 }
@@ -500,8 +910,12 @@ impl OmniBrain {
                         "pacman" if distro::command_exists("pacman") => {
                             if let Ok(pacman_manager) = PacmanBox::new() {
                                 if pacman_manager.install(&app.name).is_ok() {
-                                    self.record_manifest_install(&app.name, "pacman", None)
-                                        .await;
+                                    batch.extend(self.manifest_record(
+                                        &manifest.project,
+                                        &app.name,
+                                        "pacman",
+                                        None,
+                                    ));
                                 } else {
                                     eprintln!("❌ Failed to install {} with pacman", app.name);
                                 }
@@ -512,7 +926,12 @@ impl OmniBrain {
                         "dnf" if distro::command_exists("dnf") => {
                             if let Ok(dnf_manager) = DnfBox::new() {
                                 if dnf_manager.install(&app.name).is_ok() {
-                                    self.record_manifest_install(&app.name, "dnf", None).await;
+                                    batch.extend(self.manifest_record(
+                                        &manifest.project,
+                                        &app.name,
+                                        "dnf",
+                                        None,
+                                    ));
                                 } else {
                                     eprintln!("❌ Failed to install {} with dnf", app.name);
                                 }
@@ -529,6 +948,8 @@ impl OmniBrain {
                     );
                 }
             }
+
+            self.record_manifest_installs(std::mem::take(&mut batch)).await;
         }
 
         pb.finish_with_message("Complete");
@@ -537,43 +958,138 @@ impl OmniBrain {
         Ok(())
     }
 
-    async fn record_manifest_install(
+    /// Refuse `operation` when the organisation policy forbids it; keeps the
+    /// policy for the rest of the operation
+    async fn enforce(&mut self, operation: &Operation<'_>) -> Result<()> {
+        let config = OmniConfig::load().unwrap_or_default();
+        self.policy = policy::enforce(&config, self.db.as_ref(), operation).await?;
+        Ok(())
+    }
+
+    /// Make sure any EULA gating `app` is accepted (and preseeded) before installing
+    async fn handle_license_requirements(&self, app: &str, box_type: Option<&str>) -> Result<()> {
+        let Some(requirement) = licenses::detect(app, box_type) else {
+            return Ok(());
+        };
+
+        let accepted = if self.accept_licenses {
+            println!(
+                "📜 Accepting {} for {} (--accept-licenses)",
+                requirement.license_name, app
+            );
+            true
+        } else if std::io::stdin().is_terminal() {
+            let license_text = licenses::fetch_license_text(&requirement);
+            InteractivePrompts::new().confirm_license(&requirement, license_text.as_deref())?
+        } else {
+            return Err(anyhow!(
+                "{} requires accepting the {}; re-run with --accept-licenses to accept it non-interactively",
+                app,
+                requirement.license_name
+            ));
+        };
+
+        if let Some(db) = &self.db {
+            let (event_type, severity) = if accepted {
+                (AuditEventType::LicenseAccepted, AuditSeverity::Info)
+            } else {
+                (AuditEventType::LicenseDeclined, AuditSeverity::Warning)
+            };
+            let details = format!(
+                "{} ({}) {}",
+                requirement.license_name,
+                requirement.question,
+                if self.accept_licenses {
+                    "accepted via --accept-licenses"
+                } else if accepted {
+                    "accepted interactively"
+                } else {
+                    "declined interactively"
+                }
+            );
+            let entry = AuditEntry::new(event_type, severity, &details)
+                .with_package(app, Some("apt"))
+                .with_success(accepted);
+            let config = OmniConfig::load().unwrap_or_default();
+            let audit = AuditManager::with_database(db.clone())
+                .with_alerts(config.audit.clone())
+                .with_notifier(Dispatcher::from_config(&config));
+            if let Err(e) = audit.log_event(&entry).await {
+                warn!("Failed to record license decision in audit log: {}", e);
+            }
+        }
+
+        if !accepted {
+            return Err(anyhow!(
+                "License for {} was declined; installation aborted",
+                app
+            ));
+        }
+
+        licenses::preseed(&requirement, &self.privilege_manager)
+    }
+
+    /// History and artifact records for a package installed from a manifest
+    fn manifest_record(
         &self,
+        project: &str,
         package_name: &str,
         box_type: &str,
         source_url: Option<&str>,
-    ) {
-        if let Some(db) = &self.db {
-            let install_record = InstallRecord {
-                id: Uuid::new_v4().to_string(),
-                package_name: package_name.to_string(),
-                box_type: box_type.to_string(),
-                version: None,
-                source_url: source_url.map(|s| s.to_string()),
-                install_path: None,
-                installed_at: Utc::now(),
-                status: InstallStatus::Success,
-                metadata: Some("Installed via manifest".to_string()),
-            };
+    ) -> Option<(InstallRecord, ArtifactRecord)> {
+        self.db.as_ref()?;
+        let provenance = provenance::detect(package_name, box_type);
+        let install_record = InstallRecord {
+            id: Uuid::new_v4().to_string(),
+            package_name: package_name.to_string(),
+            box_type: box_type.to_string(),
+            version: None,
+            source_url: source_url.map(|s| s.to_string()),
+            install_path: None,
+            installed_at: Utc::now(),
+            status: InstallStatus::Success,
+            repository: provenance.repository,
+            mirror_url: provenance.mirror_url,
+            channel: None,
+            metadata: Some(format!("Installed via manifest '{}'", project)),
+        };
+        let artifact = artifacts::capture(package_name, box_type, None, source_url);
+        Some((install_record, artifact))
+    }
 
-            let _ = db.record_install(&install_record).await;
+    async fn record_manifest_installs(&self, batch: Vec<(InstallRecord, ArtifactRecord)>) {
+        let Some(db) = &self.db else {
+            return;
+        };
+        if batch.is_empty() {
+            return;
+        }
+        let (records, artifacts): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        if let Err(e) = db.record_installs(&records).await {
+            warn!("Failed to record manifest installs in history: {}", e);
         }
+        let _ = db.record_artifacts(&artifacts).await;
     }
 
+    #[instrument(skip(self))]
     pub async fn remove(&mut self, app: &str, box_type: Option<&str>) -> Result<()> {
+        crate::config::ensure_writable("removing packages")?;
         if self.mock_mode {
             println!("🎭 [MOCK] Removing '{}'", app);
-            println!("✅ [MOCK] Successfully removed {} (simulated)", app);
+            mock::backend().remove(app, box_type)?;
+            println!("✅ [MOCK] Successfully removed {}", app);
             return Ok(());
         }
 
         self.ensure_initialized().await?;
+        self.enforce(&Operation::Remove { package: app }).await?;
 
         // Create automatic snapshot before removal
         if let Some(snapshot_manager) = &self.snapshot_manager {
             let _ = snapshot_manager.auto_snapshot("remove", app).await;
         }
 
+        let started = std::time::Instant::now();
         let pb = ProgressBar::new_spinner();
         pb.set_style(
             ProgressStyle::default_spinner()
@@ -606,22 +1122,139 @@ impl OmniBrain {
                         install_path: None,
                         installed_at: Utc::now(),
                         status: InstallStatus::Removed,
+                        repository: None,
+                        mirror_url: None,
+                        channel: None,
                         metadata: None,
                     };
 
                     let _ = db.record_install(&removal_record).await;
+                    let _ = db.forget_artifact(app, &box_type).await;
                 }
 
+                notifications::notify(
+                    PackageEvent::new(NotifyEvent::Remove, app)
+                        .with_box_type(Some(&box_type))
+                        .with_duration(started.elapsed()),
+                )
+                .await;
                 println!("✅ Successfully removed {}", app);
                 Ok(())
             }
             Err(e) => {
                 error!("❌ Failed to remove {}: {}", app, e);
+                notifications::notify(
+                    PackageEvent::failed(NotifyEvent::Remove, app, &e)
+                        .with_box_type(box_type)
+                        .with_duration(started.elapsed()),
+                )
+                .await;
                 Err(e)
             }
         }
     }
 
+    /// Remove orphaned dependencies from every backend after taking a snapshot
+    pub async fn autoremove(&mut self, dry_run: bool, assume_yes: bool) -> Result<()> {
+        if !dry_run {
+            crate::config::ensure_writable("removing orphaned packages")?;
+        }
+        if self.mock_mode {
+            let backend = mock::backend();
+            let orphans = backend.orphans();
+            if orphans.is_empty() {
+                println!("✅ [MOCK] No orphaned packages found");
+                return Ok(());
+            }
+            println!("🧹 [MOCK] Orphaned packages: {}", orphans.join(" "));
+            if !dry_run {
+                backend.autoremove()?;
+                println!("✅ [MOCK] Autoremove complete");
+            }
+            return Ok(());
+        }
+
+        let orphans = autoremove::find_orphans();
+        if orphans.is_empty() {
+            println!("✅ No orphaned packages found");
+            return Ok(());
+        }
+
+        println!("🧹 Orphaned packages:");
+        for set in &orphans {
+            println!("  [{}] {}", set.box_type, set.packages.join(" "));
+        }
+
+        if dry_run {
+            return Ok(());
+        }
+
+        if !assume_yes {
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "Refusing to remove packages without confirmation; pass --yes"
+                ));
+            }
+            let total: usize = orphans.iter().map(|s| s.packages.len()).sum();
+            let prompt = format!("Remove {} orphaned packages?", total);
+            if !InteractivePrompts::new().get_confirmation(&prompt, false)? {
+                println!("❌ Autoremove cancelled");
+                return Ok(());
+            }
+        }
+
+        self.ensure_initialized().await?;
+
+        if let Some(snapshot_manager) = &self.snapshot_manager {
+            let snapshot_name = format!("auto-autoremove-{}", Utc::now().format("%Y%m%d-%H%M%S"));
+            let snapshot_id = snapshot_manager
+                .create_snapshot(&snapshot_name, Some("Automatic snapshot before autoremove"))
+                .await
+                .map_err(|e| anyhow!("Not removing anything, snapshot failed: {}", e))?;
+            println!("📸 Created snapshot {} ({})", snapshot_name, snapshot_id);
+        }
+
+        for set in &orphans {
+            let (command, args) = autoremove::remove_command(set)?;
+            let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+            if let Err(e) = self.privilege_manager.execute_with_sudo(command, &args) {
+                error!("❌ Autoremove via {} failed: {}", set.box_type, e);
+                return Err(e);
+            }
+
+            if let Some(db) = &self.db {
+                for package in &set.packages {
+                    let removal_record = InstallRecord {
+                        id: Uuid::new_v4().to_string(),
+                        package_name: package.clone(),
+                        box_type: set.box_type.clone(),
+                        version: None,
+                        source_url: None,
+                        install_path: None,
+                        installed_at: Utc::now(),
+                        status: InstallStatus::Removed,
+                        repository: None,
+                        mirror_url: None,
+                        channel: None,
+                        metadata: Some("Removed by autoremove".to_string()),
+                    };
+
+                    let _ = db.record_install(&removal_record).await;
+                    let _ = db.forget_artifact(package, &set.box_type).await;
+                }
+            }
+
+            println!(
+                "✅ Removed {} orphaned packages via {}",
+                set.packages.len(),
+                set.box_type
+            );
+        }
+
+        Ok(())
+    }
+
     async fn remove_with_specific_box(&self, app: &str, box_type: &str) -> Result<String> {
         match box_type {
             "apt" if distro::command_exists("apt") => {
@@ -688,6 +1321,10 @@ impl OmniBrain {
                 appimage::remove_appimage(app)?;
                 Ok(box_type.to_string())
             }
+            "source" => {
+                source::remove(app)?;
+                Ok(box_type.to_string())
+            }
             _ => Err(anyhow::anyhow!(
                 "Box type '{}' not available or not supported",
                 box_type
@@ -705,23 +1342,29 @@ impl OmniBrain {
         }
 
         // Fallback: try all available package managers
-        let boxes = ["apt", "dnf", "pacman", "snap", "flatpak", "appimage"];
+        let boxes = [
+            "apt", "dnf", "pacman", "snap", "flatpak", "appimage", "source",
+        ];
 
         for box_name in &boxes {
-            if distro::command_exists(box_name) || *box_name == "appimage" {
+            if distro::command_exists(box_name) || ["appimage", "source"].contains(box_name) {
                 if let Ok(result) = self.remove_with_specific_box(app, box_name).await {
                     return Ok(result);
                 }
             }
         }
 
-        Err(anyhow::anyhow!("Package not found in any package manager"))
+        Err(OmniError::PackageNotFound {
+            package: app.to_string(),
+        }
+        .into())
     }
 
     pub async fn undo_last(&mut self) -> Result<()> {
+        crate::config::ensure_writable("undoing an install")?;
         if self.mock_mode {
-            println!("🎭 [MOCK] Undoing last installation (simulated)");
-            println!("✅ [MOCK] Successfully undid last installation");
+            let done = mock::backend().undo_last()?;
+            println!("✅ [MOCK] {}", done);
             return Ok(());
         }
 
@@ -741,6 +1384,27 @@ impl OmniBrain {
                         self.install(&last_record.package_name, Some(&last_record.box_type))
                             .await?;
                     }
+                    InstallStatus::Downgraded => {
+                        let version = last_record
+                            .metadata
+                            .as_deref()
+                            .and_then(downgrade::downgraded_from)
+                            .ok_or_else(|| {
+                                anyhow!(
+                                    "No previous version recorded for {}",
+                                    last_record.package_name
+                                )
+                            })?;
+                        info!("Re-upgrading {} to {}", last_record.package_name, version);
+                        UpdateManager::new(OmniConfig::load()?)
+                            .await?
+                            .restore_version(
+                                &last_record.package_name,
+                                &last_record.box_type,
+                                version,
+                            )
+                            .await?;
+                    }
                     _ => {
                         return Err(anyhow::anyhow!(
                             "Cannot undo operation with status: {:?}",
@@ -756,10 +1420,115 @@ impl OmniBrain {
         Ok(())
     }
 
+    /// Reverse everything in the install history after `point` (a time or a
+    /// history id) as one transaction
+    pub async fn rollback_to(
+        &mut self,
+        point: &str,
+        dry_run: bool,
+        assume_yes: bool,
+    ) -> Result<()> {
+        crate::config::ensure_writable("rolling back the install history")?;
+        let records = if self.mock_mode {
+            let mut records = mock::backend().history(usize::MAX);
+            records.reverse();
+            records
+        } else {
+            self.ensure_initialized().await?;
+            match &self.db {
+                Some(db) => db.get_history_until(Utc::now()).await?,
+                None => return Err(anyhow!("The install history is not available")),
+            }
+        };
+
+        let at = history::resolve_point(point, &records)?;
+        let plan = history::rollback_plan(&records, at);
+        if plan.is_empty() {
+            println!(
+                "✅ Nothing changed since {}",
+                at.format("%Y-%m-%d %H:%M:%S")
+            );
+            return Ok(());
+        }
+
+        println!("⏪ Rolling back to {}:", at.format("%Y-%m-%d %H:%M:%S"));
+        for step in &plan {
+            let action = match step.operation {
+                TransactionOperation::RemovePackage => "remove",
+                TransactionOperation::InstallPackage => "install",
+                _ => "restore",
+            };
+            match &step.version {
+                Some(version) => println!(
+                    "  {} {} {} [{}]",
+                    action, step.package, version, step.box_type
+                ),
+                None => println!("  {} {} [{}]", action, step.package, step.box_type),
+            }
+        }
+
+        if dry_run {
+            return Ok(());
+        }
+
+        if !assume_yes {
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "Refusing to roll back without confirmation; pass --yes"
+                ));
+            }
+            let prompt = format!("Apply {} changes?", plan.len());
+            if !InteractivePrompts::new().get_confirmation(&prompt, false)? {
+                println!("❌ Rollback cancelled");
+                return Ok(());
+            }
+        }
+
+        if self.mock_mode {
+            let backend = mock::backend();
+            for step in &plan {
+                match step.operation {
+                    TransactionOperation::RemovePackage => {
+                        backend.remove(&step.package, Some(&step.box_type))?
+                    }
+                    _ => {
+                        backend.install(&step.package, Some(&step.box_type))?;
+                    }
+                }
+            }
+            println!("✅ [MOCK] Rolled back {} changes", plan.len());
+            return Ok(());
+        }
+
+        if let Some(snapshot_manager) = &self.snapshot_manager {
+            let _ = snapshot_manager.auto_snapshot("rollback", point).await;
+        }
+
+        let mut transactions = TransactionManager::new().await?;
+        let transaction = transactions
+            .begin_transaction(TransactionType::Batch)
+            .await?;
+        for step in plan {
+            transactions
+                .add_box_operation(
+                    transaction,
+                    step.operation,
+                    step.package,
+                    Some(step.box_type),
+                    step.version,
+                )
+                .await?;
+        }
+        transactions.execute_transaction(transaction).await?;
+        transactions.commit_transaction(transaction).await?;
+
+        println!("✅ Rolled back to {}", at.format("%Y-%m-%d %H:%M:%S"));
+        Ok(())
+    }
+
     pub async fn snapshot(&mut self) -> Result<()> {
         if self.mock_mode {
-            println!("🎭 [MOCK] Creating system snapshot (simulated)");
-            println!("✅ [MOCK] Snapshot created successfully");
+            self.create_snapshot();
             return Ok(());
         }
 
@@ -780,9 +1549,16 @@ impl OmniBrain {
     }
 
     pub async fn revert(&mut self) -> Result<()> {
+        crate::config::ensure_writable("reverting a snapshot")?;
         if self.mock_mode {
-            println!("🎭 [MOCK] Reverting to last snapshot (simulated)");
-            println!("✅ [MOCK] System reverted successfully");
+            let backend = mock::backend();
+            let latest = backend
+                .snapshots()
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("No snapshots found"))?;
+            backend.revert(&latest.id)?;
+            println!("✅ [MOCK] Reverted to snapshot '{}'", latest.name);
             return Ok(());
         }
 
@@ -803,11 +1579,47 @@ impl OmniBrain {
         Ok(())
     }
 
+    /// Snapshot ahead of a batch of operations on `subject`; `None` when no
+    /// snapshot could be taken
+    pub async fn auto_snapshot(&mut self, operation: &str, subject: &str) -> Option<String> {
+        if self.mock_mode {
+            let name = format!("auto-{}-{}", operation, Utc::now().format("%Y%m%d-%H%M%S"));
+            let description = format!("Automatic snapshot before {} {}", operation, subject);
+            return Some(mock::backend().create_snapshot(&name, Some(&description)));
+        }
+
+        self.ensure_initialized().await.ok()?;
+        let snapshot_manager = self.snapshot_manager.as_ref()?;
+        snapshot_manager
+            .auto_snapshot(operation, subject)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Revert to the snapshot with this ID
+    pub async fn revert_to(&mut self, snapshot_id: &str) -> Result<()> {
+        crate::config::ensure_writable("reverting a snapshot")?;
+        if self.mock_mode {
+            mock::backend().revert(snapshot_id)?;
+            return Ok(());
+        }
+
+        self.ensure_initialized().await?;
+        match &self.snapshot_manager {
+            Some(snapshot_manager) => snapshot_manager.revert_to_snapshot(snapshot_id).await,
+            None => Err(anyhow!("Snapshots are not available")),
+        }
+    }
+
     /// Search for packages across all available package managers
     pub async fn search(&mut self, query: &str) -> Result<Vec<crate::search::SearchResult>> {
         if query.trim().is_empty() {
             return Ok(Vec::new());
         }
+        if self.mock_mode {
+            return Ok(mock::backend().search(query));
+        }
 
         // Ensure search engine is initialized
         self.ensure_initialized().await?;
@@ -832,6 +1644,14 @@ impl OmniBrain {
 
     /// List all installed packages
     pub fn list_installed(&self) -> Vec<String> {
+        if self.mock_mode {
+            return mock::backend()
+                .installed()
+                .into_iter()
+                .map(|r| r.package_name)
+                .collect();
+        }
+
         // Mock installed packages for now
         vec![
             "firefox".to_string(),
@@ -842,11 +1662,32 @@ impl OmniBrain {
         ]
     }
 
+    /// Names of the packages recorded as installed in the database
+    pub async fn installed_packages(&mut self) -> Result<Vec<String>> {
+        if self.mock_mode {
+            return Ok(self.list_installed());
+        }
+        self.ensure_initialized().await?;
+        if let Some(db) = &self.db {
+            Ok(db
+                .get_installed_packages()
+                .await?
+                .into_iter()
+                .map(|r| r.package_name)
+                .collect())
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
     /// Retrieve installation history records
     pub async fn get_install_history(
         &mut self,
         limit: usize,
     ) -> Result<Vec<crate::database::InstallRecord>> {
+        if self.mock_mode {
+            return Ok(mock::backend().history(limit));
+        }
         self.ensure_initialized().await?;
         if let Some(db) = &self.db {
             db.get_install_history(Some(limit as i64)).await
@@ -858,8 +1699,13 @@ impl OmniBrain {
     /// Update all packages
     pub fn update_all(&mut self) {
         if self.mock_mode {
-            println!("🎭 [MOCK] Updating all packages");
-            println!("✅ [MOCK] All packages updated (simulated)");
+            let backend = mock::backend();
+            let updates = backend.updates();
+            println!("🎭 [MOCK] Updating {} packages", updates.len());
+            match backend.update(&updates) {
+                Ok(()) => println!("✅ [MOCK] All packages updated"),
+                Err(e) => println!("❌ [MOCK] {}", e),
+            }
             return;
         }
 
@@ -871,8 +1717,9 @@ impl OmniBrain {
     /// Create a snapshot of the current system state
     pub fn create_snapshot(&self) {
         if self.mock_mode {
-            println!("🎭 [MOCK] Creating system snapshot");
-            println!("✅ [MOCK] Snapshot created (simulated)");
+            let name = format!("manual-{}", Utc::now().format("%Y%m%d-%H%M%S"));
+            let id = mock::backend().create_snapshot(&name, Some("Manual snapshot"));
+            println!("✅ [MOCK] Created snapshot '{}' with ID: {}", name, id);
             return;
         }
 
@@ -883,6 +1730,7 @@ impl OmniBrain {
 
     /// Detect hardware and suggest appropriate drivers for mixed server scenarios
     pub async fn detect_and_install_drivers(&mut self) -> Result<()> {
+        crate::config::ensure_writable("installing drivers")?;
         if self.mock_mode {
             println!("🎭 [MOCK] Detecting hardware and drivers");
             println!("✅ [MOCK] Driver detection completed (simulated)");
@@ -1016,6 +1864,7 @@ impl OmniBrain {
 
     /// Install drivers for specific server hardware vendor (Dell, HP, Supermicro, etc.)
     pub async fn install_vendor_drivers(&mut self, vendor: &str) -> Result<()> {
+        crate::config::ensure_writable("installing drivers")?;
         if self.mock_mode {
             println!("🎭 [MOCK] Installing {} vendor drivers", vendor);
             return Ok(());
@@ -1059,11 +1908,14 @@ impl OmniBrain {
         repo_type: Option<&str>,
         key_url: Option<&str>,
     ) -> Result<()> {
+        crate::config::ensure_writable("adding a repository")?;
         if self.mock_mode {
             println!("🎭 [MOCK] Would add repository: {}", repository);
             return Ok(());
         }
 
+        self.ensure_initialized().await?;
+        self.enforce(&Operation::AddRepository { repository }).await?;
         info!("Adding repository: {}", repository);
 
         // Detect the appropriate package manager and repository type
@@ -1084,6 +1936,7 @@ impl OmniBrain {
 
     /// Remove a repository from the system
     pub async fn remove_repository(&mut self, repository: &str) -> Result<()> {
+        crate::config::ensure_writable("removing a repository")?;
         if self.mock_mode {
             println!("🎭 [MOCK] Would remove repository: {}", repository);
             return Ok(());
@@ -1152,16 +2005,26 @@ impl OmniBrain {
     async fn add_apt_repository(&mut self, repository: &str, key_url: Option<&str>) -> Result<()> {
         info!("Adding APT repository: {}", repository);
         
-        // Add GPG key if provided
+        // Trust the key in omni's keyring, and export it to a keyring of its
+        // own that only this repository trusts; apt-key is gone from current
+        // releases
+        let mut entry = repository.to_string();
         if let Some(key) = key_url {
             info!("Adding repository key: {}", key);
-            let key_args = vec!["wget", "-qO", "-", key, "|", "apt-key", "add", "-"];
-            self.privilege_manager.execute_with_sudo("bash", &["-c", &key_args.join(" ")])?;
+            let config = OmniConfig::load().unwrap_or_default();
+            let db = Database::new().await?;
+            let store = KeyStore::open()?;
+            let added = keys::add(&db, &store, &config.security.signature_servers, key).await?;
+            let fingerprints: Vec<String> = added.into_iter().map(|k| k.fingerprint).collect();
+            let keyring =
+                std::path::Path::new(ownership::APT_KEYRINGS_DIR).join("omni-added-repo.gpg");
+            ownership::write(&keyring, &store.export(&fingerprints)?, FileKind::Shared)?;
+            entry = with_signed_by(repository, &keyring.display().to_string());
         }
         
         // Add repository to sources.list.d
-        let sources_file = format!("/etc/apt/sources.list.d/omni-added-repo.list");
-        std::fs::write(&sources_file, format!("{}\n", repository))?;
+        let sources_file = std::path::Path::new(ownership::APT_SOURCES_DIR).join("omni-added-repo.list");
+        ownership::write(&sources_file, format!("{}\n", entry).as_bytes(), FileKind::Shared)?;
         
         // Update package lists
         let update_args = vec!["update"];
@@ -1300,3 +2163,106 @@ impl OmniBrain {
         Ok(repos)
     }
 }
+
+/// The package to install for `name` when `box_type` only knows it as a
+/// virtual package, asked for in a terminal
+fn choose_provider(name: &str, box_type: &str) -> Result<Option<String>> {
+    let Some(providers) = advanced_resolver::virtual_providers(box_type, name) else {
+        return Ok(None);
+    };
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "{} is a virtual package provided by {}; install one of them by name",
+            name,
+            providers.join(", ")
+        ));
+    }
+    match InteractivePrompts::new().select_provider(name, &providers)? {
+        Some(provider) => {
+            info!("Installing {} to provide {}", provider, name);
+            Ok(Some(provider))
+        }
+        None => Err(anyhow!("Installation of {} cancelled", name)),
+    }
+}
+
+/// The name `app` built for `arch` goes by in the requested or native box,
+/// with that box; apt only installs architectures dpkg was told to accept
+fn qualify_for_architecture(
+    app: &str,
+    arch: &str,
+    box_type: Option<&str>,
+) -> Result<(String, String)> {
+    let box_type = box_type.map_or_else(distro::detect_distro, str::to_string);
+    let name = distro::qualify_package(&box_type, app, arch)?;
+    if box_type == "apt" {
+        let arch = distro::architecture_for("apt", arch).unwrap_or(arch);
+        let accepted = distro::native_architecture("apt").as_deref() == Some(arch)
+            || distro::foreign_architectures().iter().any(|a| a == arch);
+        if !accepted {
+            return Err(anyhow!(
+                "dpkg does not accept {} packages yet; run 'sudo dpkg --add-architecture {} && sudo apt update' first",
+                arch,
+                arch
+            ));
+        }
+    }
+    Ok((name, box_type))
+}
+
+/// Install through the mock backend, reporting every package the transaction added
+fn mock_install(
+    app: &str,
+    box_type: Option<&str>,
+    constraint: Option<&VersionConstraint>,
+) -> Result<()> {
+    println!("🎭 [MOCK] Installing '{}'", app);
+    if let (Some(constraint), Some(package)) = (constraint, mock::backend().package(app)) {
+        if !constraint.allows(&package.version) {
+            return Err(anyhow!(
+                "No version of {} satisfies {} (available: {})",
+                app,
+                constraint,
+                package.version
+            ));
+        }
+    }
+    let added = mock::backend().install(app, box_type)?;
+    if added.is_empty() {
+        println!("✅ [MOCK] {} is already installed", app);
+    } else {
+        println!(
+            "✅ [MOCK] Successfully installed {} ({})",
+            app,
+            added.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// A one-line `deb` entry that trusts only `keyring`
+fn with_signed_by(entry: &str, keyring: &str) -> String {
+    let Some(rest) = entry.strip_prefix("deb ") else {
+        return entry.to_string();
+    };
+    let rest = rest.trim_start();
+    if rest.contains("signed-by=") {
+        entry.to_string()
+    } else if let Some(options) = rest.strip_prefix('[') {
+        format!("deb [signed-by={} {}", keyring, options.trim_start())
+    } else {
+        format!("deb [signed-by={}] {}", keyring, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_provider_only_for_boxes_with_virtual_packages() {
+        // Neither box has virtual packages, so no lookup or prompt runs
+        assert_eq!(choose_provider("mta", "snap").unwrap(), None);
+        assert_eq!(choose_provider("java-runtime", "flatpak").unwrap(), None);
+    }
+}
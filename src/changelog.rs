@@ -0,0 +1,209 @@
+use anyhow::{anyhow, Result};
+use std::process::Command;
+use tracing::debug;
+
+/// Entries shown by `omni info --changelog` and per package in update reviews
+pub const DEFAULT_MAX_ENTRIES: usize = 5;
+
+/// Fetch the changelog for `package` from its backend.
+///
+/// With `installed_version`, only entries newer than that version are kept, so an
+/// update review shows what the update brings. At most `max_entries` entries are
+/// returned, newest first.
+pub fn fetch(
+    box_type: &str,
+    package: &str,
+    installed_version: Option<&str>,
+    max_entries: usize,
+) -> Result<String> {
+    let (command, args) = changelog_command(box_type, package, installed_version.is_some())?;
+    debug!("Fetching changelog: {} {}", command, args.join(" "));
+
+    let output = Command::new(command)
+        .args(&args)
+        .output()
+        .map_err(|e| anyhow!("Failed to run {}: {}", command, e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "No changelog available for {} via {}: {}",
+            package,
+            box_type,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    Ok(select_entries(
+        box_type,
+        &text,
+        installed_version,
+        max_entries,
+    ))
+}
+
+fn changelog_command(
+    box_type: &str,
+    package: &str,
+    upgrades_only: bool,
+) -> Result<(&'static str, Vec<String>)> {
+    let package = package.to_string();
+    let command = match box_type {
+        // Downloads the changelog of the candidate version
+        "apt" => ("apt-get", vec!["changelog".to_string(), package]),
+        "dnf" if upgrades_only => (
+            "dnf",
+            vec!["changelog".to_string(), "--upgrades".to_string(), package],
+        ),
+        "dnf" => ("dnf", vec!["changelog".to_string(), package]),
+        // Only packages that ship a changelog file have one
+        "pacman" => ("pacman", vec!["-Qc".to_string(), package]),
+        "flatpak" => {
+            let remote = flatpak_origin(&package).unwrap_or_else(|| "flathub".to_string());
+            (
+                "flatpak",
+                vec![
+                    "remote-info".to_string(),
+                    "--log".to_string(),
+                    remote,
+                    package,
+                ],
+            )
+        }
+        other => {
+            return Err(anyhow!(
+                "Changelogs are not available for box type '{}'",
+                other
+            ))
+        }
+    };
+    Ok(command)
+}
+
+/// Remote an installed flatpak came from
+pub(crate) fn flatpak_origin(app: &str) -> Option<String> {
+    let output = Command::new("flatpak")
+        .args(["info", "--show-origin", app])
+        .output()
+        .ok()?;
+    let origin = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !origin.is_empty()).then_some(origin)
+}
+
+/// Cut raw changelog output down to its newest entries, stopping at the entry for
+/// `installed_version` when given
+pub fn select_entries(
+    box_type: &str,
+    text: &str,
+    installed_version: Option<&str>,
+    max_entries: usize,
+) -> String {
+    let mut selected = Vec::new();
+    let mut entries = 0;
+
+    for line in text.lines() {
+        if let Some(version) = entry_header(box_type, line) {
+            if installed_version.is_some_and(|installed| version == installed) {
+                break;
+            }
+            entries += 1;
+            if entries > max_entries {
+                break;
+            }
+        } else if entries == 0 {
+            // Preamble before the first entry (flatpak's ref summary, dnf's
+            // "Listing changelogs..." banner)
+            continue;
+        }
+        selected.push(line);
+    }
+
+    selected.join("\n").trim_end().to_string()
+}
+
+/// Version named by a line that starts a changelog entry, or `Some("")` for
+/// formats whose headers carry no version
+fn entry_header<'a>(box_type: &str, line: &'a str) -> Option<&'a str> {
+    match box_type {
+        // Debian: `curl (8.5.0-2ubuntu10.1) noble-security; urgency=medium`
+        "apt" => {
+            if line.starts_with(char::is_whitespace) {
+                return None;
+            }
+            let (_, rest) = line.split_once(" (")?;
+            let (version, rest) = rest.split_once(')')?;
+            rest.contains("urgency=").then_some(version)
+        }
+        // RPM: `* Tue Jan 02 2024 Jane Doe <jane@example.com> - 8.2.1-4.fc39`
+        "dnf" | "pacman" if line.starts_with("* ") => {
+            Some(line.rsplit_once(" - ").map_or("", |(_, v)| v.trim()))
+        }
+        // flatpak remote-info --log: `Commit: 3f2a...`
+        "flatpak" if line.trim_start().starts_with("Commit:") => Some(""),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBIAN: &str = "curl (8.5.0-2ubuntu10.2) noble-security; urgency=medium
+
+  * SECURITY UPDATE: fix CVE-2024-0001
+
+ -- Security Team <security@ubuntu.com>  Mon, 01 Jul 2024 10:00:00 +0000
+
+curl (8.5.0-2ubuntu10.1) noble-updates; urgency=medium
+
+  * Fix HTTP/2 regression
+
+ -- Someone <someone@ubuntu.com>  Mon, 01 Apr 2024 10:00:00 +0000
+
+curl (8.5.0-2ubuntu10) noble; urgency=medium
+
+  * Initial noble release
+";
+
+    #[test]
+    fn test_select_debian_entries_since_installed() {
+        let since = select_entries("apt", DEBIAN, Some("8.5.0-2ubuntu10.1"), 10);
+        assert!(since.starts_with("curl (8.5.0-2ubuntu10.2)"));
+        assert!(since.contains("CVE-2024-0001"));
+        assert!(!since.contains("HTTP/2 regression"));
+
+        let newest_two = select_entries("apt", DEBIAN, None, 2);
+        assert!(newest_two.contains("HTTP/2 regression"));
+        assert!(!newest_two.contains("Initial noble release"));
+    }
+
+    #[test]
+    fn test_select_rpm_and_flatpak_entries() {
+        let rpm = "Listing changelogs for: curl
+* Tue Jan 02 2024 Jane Doe <jane@example.com> - 8.2.1-4.fc39
+- Fix a crash
+
+* Mon Dec 04 2023 Jane Doe <jane@example.com> - 8.2.1-3.fc39
+- Rebuild
+";
+        assert_eq!(
+            select_entries("dnf", rpm, Some("8.2.1-3.fc39"), 5),
+            "* Tue Jan 02 2024 Jane Doe <jane@example.com> - 8.2.1-4.fc39\n- Fix a crash"
+        );
+
+        let flatpak = "        ID: org.gimp.GIMP
+       Ref: app/org.gimp.GIMP/x86_64/stable
+
+    Commit: 3f2a
+   Subject: Update to 2.10.38
+      Date: 2024-05-03
+
+    Commit: 1b7c
+   Subject: Update to 2.10.36
+      Date: 2023-11-08
+";
+        let log = select_entries("flatpak", flatpak, None, 1);
+        assert!(log.contains("2.10.38"));
+        assert!(!log.contains("2.10.36"));
+        assert!(!log.contains("org.gimp.GIMP"));
+    }
+}
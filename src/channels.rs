@@ -0,0 +1,384 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+
+/// Release channel to install a package from, mapped onto each backend's own
+/// mechanism: snap channels, flatpak remotes, apt target releases, dnf testing
+/// repositories and brew `--HEAD` builds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Stable,
+    Beta,
+    Nightly,
+    /// Backend-specific channel, remote or repository, passed through as given
+    Named(String),
+}
+
+impl Channel {
+    pub fn parse(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "stable" | "release" => Channel::Stable,
+            "beta" | "candidate" | "testing" | "preview" => Channel::Beta,
+            "nightly" | "edge" | "unstable" | "head" | "dev" => Channel::Nightly,
+            _ => Channel::Named(name.trim().to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+            Channel::Nightly => "nightly",
+            Channel::Named(name) => name,
+        }
+    }
+
+    pub fn is_stable(&self) -> bool {
+        *self == Channel::Stable
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether the backend's channel install has to run as root
+pub fn needs_privilege(box_type: &str) -> bool {
+    box_type != "brew"
+}
+
+/// Command line that installs `package` from `channel` via `box_type`
+pub fn install_command(
+    box_type: &str,
+    package: &str,
+    channel: &Channel,
+) -> Result<(String, Vec<String>)> {
+    let (id, codename) = os_release();
+    install_command_for(box_type, package, channel, &id, &codename)
+}
+
+/// [`install_command`] for a given distribution `ID` and `VERSION_CODENAME`
+pub fn install_command_for(
+    box_type: &str,
+    package: &str,
+    channel: &Channel,
+    distro_id: &str,
+    codename: &str,
+) -> Result<(String, Vec<String>)> {
+    let package = package.to_string();
+    let unsupported = || {
+        anyhow!(
+            "The {} channel is not available via {}",
+            channel.as_str(),
+            box_type
+        )
+    };
+
+    let command = match box_type {
+        "snap" => {
+            let snap_channel = match channel {
+                Channel::Nightly => "edge",
+                other => other.as_str(),
+            };
+            (
+                "snap",
+                vec![
+                    "install".to_string(),
+                    package,
+                    format!("--channel={}", snap_channel),
+                ],
+            )
+        }
+        "flatpak" => {
+            let remote = match channel {
+                Channel::Stable => "flathub".to_string(),
+                Channel::Beta => "flathub-beta".to_string(),
+                Channel::Nightly => return Err(unsupported()),
+                Channel::Named(remote) => remote.clone(),
+            };
+            (
+                "flatpak",
+                vec!["install".to_string(), "-y".to_string(), remote, package],
+            )
+        }
+        "apt" => {
+            let release = apt_release(channel, distro_id, codename).ok_or_else(unsupported)?;
+            let mut args = vec!["install".to_string(), "-y".to_string()];
+            if let Some(release) = release {
+                args.extend(["-t".to_string(), release]);
+            }
+            args.push(package);
+            ("apt-get", args)
+        }
+        "dnf" => {
+            let repo = dnf_repository(channel).ok_or_else(unsupported)?;
+            let mut args = vec!["install".to_string(), "-y".to_string()];
+            if let Some(repo) = repo {
+                args.push(format!("--enablerepo={}", repo));
+            }
+            args.push(package);
+            ("dnf", args)
+        }
+        "pacman" => {
+            // Testing repositories must be enabled in pacman.conf; name one explicitly
+            let target = match channel {
+                Channel::Stable => package,
+                Channel::Named(repo) => format!("{}/{}", repo, package),
+                _ => {
+                    return Err(anyhow!(
+                        "pacman has no {} channel; enable a testing repository and pass \
+                         its name, e.g. --channel extra-testing",
+                        channel.as_str()
+                    ))
+                }
+            };
+            (
+                "pacman",
+                vec!["-S".to_string(), "--noconfirm".to_string(), target],
+            )
+        }
+        "brew" => {
+            let mut args = vec!["install".to_string()];
+            match channel {
+                Channel::Stable => {}
+                Channel::Nightly => args.push("--HEAD".to_string()),
+                _ => return Err(unsupported()),
+            }
+            args.push(package);
+            ("brew", args)
+        }
+        other => {
+            return Err(anyhow!(
+                "Release channels are not supported for box type '{}'",
+                other
+            ))
+        }
+    };
+
+    Ok((command.0.to_string(), command.1))
+}
+
+/// apt target release for `channel`; `Some(None)` is the default release
+fn apt_release(channel: &Channel, distro_id: &str, codename: &str) -> Option<Option<String>> {
+    match (channel, distro_id) {
+        (Channel::Stable, _) => Some(None),
+        (Channel::Beta, "ubuntu") if !codename.is_empty() => {
+            Some(Some(format!("{}-proposed", codename)))
+        }
+        (Channel::Beta, "debian") => Some(Some("testing".to_string())),
+        (Channel::Nightly, "debian") => Some(Some("unstable".to_string())),
+        (Channel::Named(release), _) => Some(Some(release.clone())),
+        _ => None,
+    }
+}
+
+/// dnf repository to enable for `channel`; `Some(None)` needs none
+fn dnf_repository(channel: &Channel) -> Option<Option<String>> {
+    match channel {
+        Channel::Stable => Some(None),
+        Channel::Beta => Some(Some("updates-testing".to_string())),
+        Channel::Nightly => None,
+        Channel::Named(repo) => Some(Some(repo.clone())),
+    }
+}
+
+/// Command line that upgrades `package`, installed from `channel`, within that channel
+pub fn update_command(
+    box_type: &str,
+    package: &str,
+    channel: &Channel,
+) -> Result<(String, Vec<String>)> {
+    let (id, codename) = os_release();
+    update_command_for(box_type, package, channel, &id, &codename)
+}
+
+/// [`update_command`] for a given distribution `ID` and `VERSION_CODENAME`
+pub fn update_command_for(
+    box_type: &str,
+    package: &str,
+    channel: &Channel,
+    distro_id: &str,
+    codename: &str,
+) -> Result<(String, Vec<String>)> {
+    let (command, mut args) = install_command_for(box_type, package, channel, distro_id, codename)?;
+    match box_type {
+        // snap keeps tracking the channel, but naming it again catches a manual switch
+        "snap" => args[0] = "refresh".to_string(),
+        "apt" => args.insert(1, "--only-upgrade".to_string()),
+        "dnf" => args[0] = "upgrade".to_string(),
+        // The installed ref remembers its remote
+        "flatpak" => args = vec!["update".to_string(), "-y".to_string(), package.to_string()],
+        "brew" => {
+            args[0] = "upgrade".to_string();
+            for arg in &mut args {
+                if arg == "--HEAD" {
+                    *arg = "--fetch-HEAD".to_string();
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok((command, args))
+}
+
+/// Command that reports whether `package` has an update in `channel`, for
+/// backends whose regular update check only looks at the default channel.
+/// apt is asked for a simulated upgrade, dnf checks with the repository enabled.
+pub fn check_command(
+    box_type: &str,
+    package: &str,
+    channel: &Channel,
+) -> Result<Option<(String, Vec<String>)>> {
+    let (id, codename) = os_release();
+    check_command_for(box_type, package, channel, &id, &codename)
+}
+
+/// [`check_command`] for a given distribution `ID` and `VERSION_CODENAME`
+pub fn check_command_for(
+    box_type: &str,
+    package: &str,
+    channel: &Channel,
+    distro_id: &str,
+    codename: &str,
+) -> Result<Option<(String, Vec<String>)>> {
+    if channel.is_stable() {
+        return Ok(None);
+    }
+    match box_type {
+        "apt" => {
+            let (command, mut args) =
+                update_command_for(box_type, package, channel, distro_id, codename)?;
+            args.insert(1, "--simulate".to_string());
+            Ok(Some((command, args)))
+        }
+        "dnf" => {
+            let mut args = vec!["check-update".to_string()];
+            if let Some(Some(repo)) = dnf_repository(channel) {
+                args.push(format!("--enablerepo={}", repo));
+            }
+            args.push(package.to_string());
+            Ok(Some(("dnf".to_string(), args)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn os_release() -> (String, String) {
+    let contents = fs::read_to_string("/etc/os-release").unwrap_or_default();
+    let field = |name: &str| {
+        contents
+            .lines()
+            .find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key == name).then(|| value.trim_matches('"').to_string())
+            })
+            .unwrap_or_default()
+    };
+    (field("ID"), field("VERSION_CODENAME"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_aliases() {
+        assert_eq!(Channel::parse("Edge"), Channel::Nightly);
+        assert_eq!(Channel::parse("candidate"), Channel::Beta);
+        assert_eq!(
+            Channel::parse("insiders"),
+            Channel::Named("insiders".to_string())
+        );
+        assert!(Channel::parse("stable").is_stable());
+    }
+
+    #[test]
+    fn test_channel_install_commands() {
+        let command = |box_type: &str, channel: &str, distro: &str| {
+            install_command_for(box_type, "pkg", &Channel::parse(channel), distro, "noble")
+                .map(|(cmd, args)| format!("{} {}", cmd, args.join(" ")))
+        };
+
+        assert_eq!(
+            command("snap", "nightly", "ubuntu").unwrap(),
+            "snap install pkg --channel=edge"
+        );
+        assert_eq!(
+            command("snap", "insiders", "ubuntu").unwrap(),
+            "snap install pkg --channel=insiders"
+        );
+        assert_eq!(
+            command("flatpak", "beta", "fedora").unwrap(),
+            "flatpak install -y flathub-beta pkg"
+        );
+        assert_eq!(
+            command("apt", "beta", "ubuntu").unwrap(),
+            "apt-get install -y -t noble-proposed pkg"
+        );
+        assert_eq!(
+            command("apt", "nightly", "debian").unwrap(),
+            "apt-get install -y -t unstable pkg"
+        );
+        assert!(command("apt", "nightly", "ubuntu").is_err());
+        assert_eq!(
+            command("dnf", "beta", "fedora").unwrap(),
+            "dnf install -y --enablerepo=updates-testing pkg"
+        );
+        assert_eq!(
+            command("pacman", "extra-testing", "arch").unwrap(),
+            "pacman -S --noconfirm extra-testing/pkg"
+        );
+        assert_eq!(
+            command("brew", "nightly", "").unwrap(),
+            "brew install --HEAD pkg"
+        );
+        assert!(command("brew", "beta", "").is_err());
+    }
+
+    #[test]
+    fn test_channel_update_commands() {
+        let update = |box_type: &str, channel: &str, distro: &str| {
+            update_command_for(box_type, "pkg", &Channel::parse(channel), distro, "noble")
+                .map(|(cmd, args)| format!("{} {}", cmd, args.join(" ")))
+                .unwrap()
+        };
+        let check = |box_type: &str, channel: &str, distro: &str| {
+            check_command_for(box_type, "pkg", &Channel::parse(channel), distro, "noble")
+                .unwrap()
+                .map(|(cmd, args)| format!("{} {}", cmd, args.join(" ")))
+        };
+
+        assert_eq!(
+            update("snap", "beta", "ubuntu"),
+            "snap refresh pkg --channel=beta"
+        );
+        assert_eq!(
+            update("apt", "beta", "ubuntu"),
+            "apt-get install --only-upgrade -y -t noble-proposed pkg"
+        );
+        assert_eq!(
+            update("dnf", "beta", "fedora"),
+            "dnf upgrade -y --enablerepo=updates-testing pkg"
+        );
+        assert_eq!(update("flatpak", "beta", "fedora"), "flatpak update -y pkg");
+        assert_eq!(
+            update("brew", "nightly", ""),
+            "brew upgrade --fetch-HEAD pkg"
+        );
+
+        assert_eq!(
+            check("apt", "nightly", "debian").as_deref(),
+            Some("apt-get install --simulate --only-upgrade -y -t unstable pkg")
+        );
+        assert_eq!(
+            check("dnf", "beta", "fedora").as_deref(),
+            Some("dnf check-update --enablerepo=updates-testing pkg")
+        );
+        assert_eq!(check("snap", "beta", "ubuntu"), None);
+        assert_eq!(check("apt", "stable", "debian"), None);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::config::OmniConfig;
+use crate::database::Database;
+use crate::distro;
+use crate::privilege_manager::PrivilegeManager;
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Runtime temp files younger than this may belong to a running omni and are kept
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What one cleanup step freed
+#[derive(Debug, Clone)]
+pub struct CleanEntry {
+    pub name: String,
+    pub reclaimed_bytes: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CleanReport {
+    pub entries: Vec<CleanEntry>,
+    /// Expired (or, with --all, every) cached package metadata rows removed from the database
+    pub cache_rows_removed: usize,
+}
+
+impl CleanReport {
+    pub fn total_reclaimed(&self) -> u64 {
+        self.entries.iter().map(|e| e.reclaimed_bytes).sum()
+    }
+}
+
+/// Clears backend package caches and omni's own caches
+pub struct CacheCleaner {
+    all: bool,
+    privilege_manager: PrivilegeManager,
+}
+
+impl CacheCleaner {
+    pub fn new() -> Self {
+        Self {
+            all: false,
+            privilege_manager: PrivilegeManager::new(),
+        }
+    }
+
+    /// Also drop caches that are still useful: every cached pacman package (`-Scc`),
+    /// all cached package metadata, and runtime temp data regardless of age
+    pub fn with_all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    pub async fn run(&self, db: Option<&Database>) -> CleanReport {
+        let mut report = CleanReport::default();
+
+        if distro::command_exists("apt-get") {
+            report.entries.push(self.clean_backend(
+                "apt",
+                Path::new("/var/cache/apt/archives"),
+                "apt-get",
+                &["clean"],
+            ));
+        }
+        if distro::command_exists("dnf") {
+            report.entries.push(self.clean_backend(
+                "dnf",
+                Path::new("/var/cache/dnf"),
+                "dnf",
+                &["clean", "all"],
+            ));
+        }
+        if distro::command_exists("pacman") {
+            let flag = if self.all { "-Scc" } else { "-Sc" };
+            report.entries.push(self.clean_backend(
+                "pacman",
+                Path::new("/var/cache/pacman/pkg"),
+                "pacman",
+                &[flag, "--noconfirm"],
+            ));
+        }
+
+        match OmniConfig::cache_dir() {
+            Ok(dir) => report
+                .entries
+                .push(measure("omni download cache", &dir, || {
+                    clear_dir(&dir, None)
+                })),
+            Err(e) => warn!("Could not locate omni cache directory: {}", e),
+        }
+
+        match OmniConfig::runtime_dir() {
+            Ok(dir) => {
+                let max_age = if self.all { None } else { Some(STALE_AFTER) };
+                report
+                    .entries
+                    .push(measure("stale snapshot temp data", &dir, || {
+                        clear_dir(&dir, max_age)
+                    }));
+            }
+            Err(e) => warn!("Could not locate omni runtime directory: {}", e),
+        }
+
+        if let Some(db) = db {
+            let removed = if self.all {
+                db.clear_package_cache().await
+            } else {
+                db.clean_expired_cache().await
+            };
+            match removed {
+                Ok(rows) => report.cache_rows_removed = rows,
+                Err(e) => warn!("Could not clean package metadata cache: {}", e),
+            }
+        }
+
+        info!("Cleanup reclaimed {} bytes", report.total_reclaimed());
+        report
+    }
+
+    fn clean_backend(&self, name: &str, cache: &Path, command: &str, args: &[&str]) -> CleanEntry {
+        measure(name, cache, || {
+            self.privilege_manager.execute_with_sudo(command, args)
+        })
+    }
+}
+
+impl Default for CacheCleaner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn measure(name: &str, path: &Path, clean: impl FnOnce() -> Result<()>) -> CleanEntry {
+    let before = dir_size(path);
+    let error = clean().err().map(|e| e.to_string());
+    let after = dir_size(path);
+
+    CleanEntry {
+        name: name.to_string(),
+        reclaimed_bytes: before.saturating_sub(after),
+        error,
+    }
+}
+
+/// Total size of regular files under `path`; unreadable entries count as empty
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if metadata.is_file() {
+        return metadata.len();
+    }
+    if !metadata.is_dir() {
+        return 0;
+    }
+
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| dir_size(&e.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Remove the contents of `dir` (not the directory itself), optionally only
+/// entries last modified more than `max_age` ago
+fn clear_dir(dir: &Path, max_age: Option<Duration>) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    let now = SystemTime::now();
+    let entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| match max_age {
+            Some(max_age) => e
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > max_age),
+            None => true,
+        })
+        .map(|e| e.path())
+        .collect();
+
+    for path in entries {
+        let result = if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        if let Err(e) = result {
+            warn!("Could not remove {}: {}", path.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clear_dir_reports_reclaimed_space() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.AppImage"), vec![0u8; 1000]).unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("nested/b"), vec![0u8; 24]).unwrap();
+
+        assert_eq!(dir_size(dir.path()), 1024);
+
+        // Nothing is older than a day yet
+        let entry = measure("test", dir.path(), || {
+            clear_dir(dir.path(), Some(STALE_AFTER))
+        });
+        assert_eq!(entry.reclaimed_bytes, 0);
+
+        let entry = measure("test", dir.path(), || clear_dir(dir.path(), None));
+        assert_eq!(entry.reclaimed_bytes, 1024);
+        assert!(entry.error.is_none());
+        assert!(dir.path().exists());
+    }
+}
@@ -1,15 +1,69 @@
+use crate::audit::AuditSeverity;
+use crate::config_migration;
+use crate::config_secrets;
+use crate::config_validation;
+use crate::error_handling::OmniError;
+use crate::privilege_manager::PrivilegeManager;
+use crate::project;
 use anyhow::Result;
 use dirs;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OmniConfig {
+    /// Layout version of the file; see `config_migration`
+    #[serde(default)]
+    pub version: u32,
     pub general: GeneralConfig,
     pub boxes: BoxConfig,
     pub security: SecurityConfig,
     pub ui: UiConfig,
+    #[serde(default)]
+    pub paths: PathsConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub policies: PoliciesConfig,
+    #[serde(default)]
+    pub self_update: SelfUpdateConfig,
+    #[serde(default)]
+    pub dotfiles: DotfilesConfig,
+    #[serde(default)]
+    pub release_support: ReleaseSupportConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub health_checks: HealthChecksConfig,
+    #[serde(default)]
+    pub files: FilesConfig,
+    #[serde(default)]
+    pub vulnerabilities: VulnerabilityConfig,
+    #[serde(default)]
+    pub enforcement: EnforcementConfig,
+    #[serde(default)]
+    pub snapshots: SnapshotsConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
+    /// Project config laid over this one, if any; see `project`
+    #[serde(skip)]
+    pub project_config: Option<PathBuf>,
+    /// `OMNI_*` variables and `--set` settings laid over the files
+    #[serde(skip)]
+    pub overridden_by: Vec<String>,
+    /// Values that were encrypted in the files; see `config_secrets`
+    #[serde(skip)]
+    pub decrypted: config_secrets::Decrypted,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -20,6 +74,12 @@ pub struct GeneralConfig {
     pub confirm_installs: bool,
     pub log_level: String,
     pub fallback_enabled: bool,
+    /// Refuse every operation that changes the system or omni's own state
+    #[serde(default)]
+    pub read_only: bool,
+    /// Build from a source recipe when no binary package can be installed
+    #[serde(default)]
+    pub source_fallback: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -31,6 +91,11 @@ pub struct BoxConfig {
     pub pacman_options: Vec<String>,
     pub snap_options: Vec<String>,
     pub flatpak_options: Vec<String>,
+    /// Repositories to prefer when a package is offered by several, best first.
+    /// Entries match any repository name or URL containing them, e.g.
+    /// `archive.ubuntu.com` ahead of `ppa.launchpadcontent.net`.
+    #[serde(default)]
+    pub repository_priority: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -50,11 +115,1055 @@ pub struct UiConfig {
     pub use_colors: bool,
     pub compact_output: bool,
     pub gui_theme: String,
+    /// Scales the whole GUI for readability
+    #[serde(default)]
+    pub gui_text_size: TextSize,
+}
+
+/// Text sizes offered in the GUI settings
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TextSize {
+    #[default]
+    Normal,
+    Large,
+    Larger,
+}
+
+impl TextSize {
+    pub const ALL: [TextSize; 3] = [TextSize::Normal, TextSize::Large, TextSize::Larger];
+
+    /// Zoom applied to the GUI
+    pub fn factor(self) -> f32 {
+        match self {
+            TextSize::Normal => 1.0,
+            TextSize::Large => 1.25,
+            TextSize::Larger => 1.5,
+        }
+    }
+}
+
+impl std::fmt::Display for TextSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TextSize::Normal => "normal",
+            TextSize::Large => "large",
+            TextSize::Larger => "larger",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Overrides for where omni keeps its per-user working files. Unset entries
+/// follow the XDG base directories.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct PathsConfig {
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub runtime_dir: Option<PathBuf>,
+}
+
+/// Schedule and policy for `omni daemon`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Minutes between update checks
+    pub check_interval_minutes: u64,
+    /// Download available updates ahead of time without installing them
+    pub auto_download: bool,
+    pub auto_install: AutoInstallPolicy,
+    pub maintenance: MaintenanceConfig,
+    pub digest: DigestConfig,
+}
+
+/// Digest reports the daemon mails on its own
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DigestConfig {
+    /// How often a digest is sent; none sends no digests
+    pub period: Option<DigestPeriod>,
+    /// Recipients; empty sends to `audit.email.to`
+    pub to: Vec<String>,
+}
+
+/// Span of time a digest report covers
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestPeriod {
+    #[default]
+    Week,
+    Month,
+}
+
+impl std::fmt::Display for DigestPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DigestPeriod::Week => "week",
+            DigestPeriod::Month => "month",
+        };
+        f.write_str(name)
+    }
+}
+
+/// When updates may be applied; see `maintenance::CronSchedule`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// Cron-like expressions of the minutes updates may run in; empty means any time
+    pub windows: Vec<String>,
+    /// `omni update --all` with at least this many updates waits for a window;
+    /// 0 never queues interactive updates
+    pub large_transaction: usize,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            windows: Vec::new(),
+            large_transaction: 20,
+        }
+    }
+}
+
+/// Which updates the daemon installs on its own
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoInstallPolicy {
+    Never,
+    Security,
+    All,
+}
+
+/// Rules for updates omni applies without a user at the keyboard
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct PoliciesConfig {
+    #[serde(default)]
+    pub unattended: UnattendedPolicy,
+    /// Whether pacman may update single packages while others are pending
+    #[serde(default)]
+    pub partial_upgrades: PartialUpgradePolicy,
+    /// Packages whose major upgrades show their upstream changes and wait for
+    /// a yes, even with `omni update --yes`; patterns may use `*`
+    #[serde(default)]
+    pub critical: Vec<String>,
+    /// Whether the resolver may take dependencies from other boxes
+    #[serde(default)]
+    pub cross_backend: CrossBackendPolicy,
+}
+
+/// Dependencies the package's own box does not have, taken from another box
+/// instead (a library as a flatpak runtime or a nix package). Off by default,
+/// since the native package manager cannot see what other boxes installed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct CrossBackendPolicy {
+    pub enabled: bool,
+    /// Boxes to look in, in order
+    pub boxes: Vec<String>,
+}
+
+impl Default for CrossBackendPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            boxes: vec!["flatpak".to_string(), "nix".to_string()],
+        }
+    }
+}
+
+/// What `omni update` does when updating some pacman packages would leave
+/// other pending upgrades behind, which Arch does not support
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PartialUpgradePolicy {
+    /// Warn and offer a full upgrade, letting the user go ahead anyway
+    #[default]
+    Warn,
+    /// Only offer a full upgrade
+    Forbid,
+}
+
+/// Which updates `omni daemon` may install on its own, on top of
+/// `daemon.auto_install`. Patterns match package names and may use `*`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct UnattendedPolicy {
+    /// Only packages matching one of these; empty allows every package
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Leave kernel packages for a reboot the user plans
+    pub exclude_kernels: bool,
+    /// Lowest advisory severity a security update needs to be installed
+    pub min_severity: Option<Severity>,
+    /// Highest impact class installed without a review; see `impact`
+    pub max_impact: Option<Impact>,
+}
+
+impl Default for UnattendedPolicy {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            exclude_kernels: true,
+            min_severity: None,
+            max_impact: None,
+        }
+    }
+}
+
+/// Severity of a security advisory, lowest first
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Low,
+    Moderate,
+    Important,
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Severity::Low => "low",
+            Severity::Moderate => "moderate",
+            Severity::Important => "important",
+            Severity::Critical => "critical",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How much an update is likely to change, least first
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Impact {
+    /// Packaging fixes and patch releases
+    Safe,
+    /// Minor releases, and updates the distribution is still phasing in
+    Minor,
+    /// Major releases, and versions omni cannot compare
+    Major,
+    /// Version scheme resets (epoch bumps) and updates testers reported broken
+    Breaking,
+}
+
+impl std::fmt::Display for Impact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Impact::Safe => "safe",
+            Impact::Minor => "minor",
+            Impact::Major => "major",
+            Impact::Breaking => "breaking",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Dotfiles manager to run after a manifest is applied; see `dotfiles`
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DotfilesConfig {
+    /// No manager means no hook
+    pub manager: Option<DotfilesManager>,
+    /// chezmoi source repository, or the stow directory
+    pub repo: Option<String>,
+    /// stow packages to link
+    pub packages: Vec<String>,
+    /// Command template for `manager: command`
+    pub command: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DotfilesManager {
+    Chezmoi,
+    Stow,
+    Command,
+}
+
+/// Where `omni self-update` looks for releases
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct SelfUpdateConfig {
+    /// URL returning the latest release in the GitHub releases API format
+    pub endpoint: String,
+    /// Fingerprint of the key releases must be signed with; without one any
+    /// good signature from the local keyring is accepted
+    pub signing_key: Option<String>,
+}
+
+impl Default for SelfUpdateConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "https://api.github.com/repos/therealcoolnerd/omni/releases/latest"
+                .to_string(),
+            signing_key: None,
+        }
+    }
+}
+
+/// How omni treats distribution releases nearing or past their end of life;
+/// see `lifecycle`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ReleaseSupportConfig {
+    /// Warn this many days before the release reaches end of life
+    pub warn_days: u32,
+    /// Ask endoflife.date for current dates instead of only the bundled ones
+    pub use_api: bool,
+    /// Refuse `omni update` and unattended updates once the release has ended
+    pub require_supported: bool,
+}
+
+impl Default for ReleaseSupportConfig {
+    fn default() -> Self {
+        Self {
+            warn_days: 90,
+            use_api: true,
+            require_supported: false,
+        }
+    }
+}
+
+/// Matching installed packages against OSV; see `vulnerabilities`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct VulnerabilityConfig {
+    /// Warn about vulnerable packages when `omni update` lists updates
+    pub check_on_update: bool,
+    /// Reuse OSV answers younger than this many hours
+    pub cache_hours: u64,
+    /// OSV API, or a mirror of it
+    pub osv_url: String,
+}
+
+impl Default for VulnerabilityConfig {
+    fn default() -> Self {
+        Self {
+            check_on_update: true,
+            cache_hours: 24,
+            osv_url: "https://api.osv.dev".to_string(),
+        }
+    }
+}
+
+/// The organisation policy consulted before installs, removals and repository
+/// additions; see `policy`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct EnforcementConfig {
+    /// YAML policy file; nothing is enforced while it does not exist
+    pub policy_file: PathBuf,
+    /// Fingerprints that may sign the policy; any key in `omni keys` when empty
+    pub signers: Vec<String>,
+    /// Refuse to act on a policy without a valid detached signature
+    /// (`<policy_file>.asc` or `.sig`)
+    pub require_signature: bool,
+}
+
+impl Default for EnforcementConfig {
+    fn default() -> Self {
+        Self {
+            policy_file: PathBuf::from("/etc/omni/policy.yaml"),
+            signers: Vec::new(),
+            require_signature: true,
+        }
+    }
+}
+
+/// How `omni search` orders results from every backend: by how well the name
+/// matches the query, then boosts for installed packages and popular backends
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Score of a name equal to the query
+    pub exact_weight: u32,
+    /// Score of a name starting with the query
+    pub prefix_weight: u32,
+    /// Score of a name containing the query
+    pub substring_weight: u32,
+    /// Score of a name one typo away from the query, less for each further typo
+    pub fuzzy_weight: u32,
+    /// Typos a name may be away from the query and still count as a fuzzy match
+    pub fuzzy_max_distance: u32,
+    /// Score of a result matched by its description only
+    pub description_weight: u32,
+    /// Added for packages already installed
+    pub installed_boost: u32,
+    /// Added for results from `popular_boxes`, the full boost for the first one
+    /// and less down the list
+    pub popular_boost: u32,
+    pub popular_boxes: Vec<String>,
+    /// Answer searches from the local index while each backend's catalog in
+    /// it is younger than this, refreshing older ones in the background; 0
+    /// asks the backends on every search
+    pub index_max_age_hours: u32,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            exact_weight: 100,
+            prefix_weight: 60,
+            substring_weight: 40,
+            fuzzy_weight: 25,
+            fuzzy_max_distance: 2,
+            description_weight: 10,
+            installed_boost: 15,
+            popular_boost: 10,
+            popular_boxes: vec![
+                "apt".to_string(),
+                "dnf".to_string(),
+                "pacman".to_string(),
+                "flatpak".to_string(),
+                "snap".to_string(),
+            ],
+            index_max_age_hours: 24,
+        }
+    }
+}
+
+/// Where package events are sent; see `notifications`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    pub webhooks: Vec<WebhookConfig>,
+    /// Pop desktop notifications for finished operations and available updates
+    pub desktop: bool,
+    /// Only operations that took at least this long pop a notification
+    pub desktop_min_seconds: u64,
+    /// Named webhooks and mail recipients that routes can send to, besides
+    /// the built-in `stdout`, `desktop` and `email` sinks
+    pub sinks: Vec<NotificationSinkConfig>,
+    /// Which notifications go to which sinks; see `notifications::Dispatcher`
+    pub routes: Vec<NotificationRoute>,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            desktop: true,
+            desktop_min_seconds: 10,
+            sinks: Vec::new(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+/// A sink routes refer to by `name`; exactly one of `webhook` and `email` is set
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct NotificationSinkConfig {
+    pub name: String,
+    /// URL that gets each notification POSTed as JSON
+    pub webhook: Option<String>,
+    /// Extra request headers for `webhook`, such as an `Authorization` token
+    pub headers: std::collections::BTreeMap<String, String>,
+    /// Signs each `webhook` body with HMAC-SHA256 in the `X-Omni-Signature` header
+    pub secret: Option<String>,
+    /// Addresses mailed through the SMTP server in `audit.email`
+    pub email: Vec<String>,
+}
+
+/// Sends the notifications of some categories, at or above a severity, to sinks
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct NotificationRoute {
+    /// Categories to send; none means all of them
+    pub categories: Vec<NotifyCategory>,
+    pub min_severity: AuditSeverity,
+    /// Names of the sinks that get matching notifications
+    pub sinks: Vec<String>,
+}
+
+impl Default for NotificationRoute {
+    fn default() -> Self {
+        Self {
+            categories: Vec::new(),
+            min_severity: AuditSeverity::Info,
+            sinks: Vec::new(),
+        }
+    }
+}
+
+impl NotificationRoute {
+    pub fn wants(&self, category: NotifyCategory, severity: AuditSeverity) -> bool {
+        (self.categories.is_empty() || self.categories.contains(&category))
+            && severity >= self.min_severity
+    }
+}
+
+/// Subsystems that send notifications through the routes
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyCategory {
+    /// Installs, removals and updates of single packages, and their failures
+    Package,
+    /// Updates found or installed by the daemon
+    Update,
+    /// Entries recorded in the audit log
+    Audit,
+    /// Results of fleet operations
+    Fleet,
+}
+
+impl std::fmt::Display for NotifyCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            NotifyCategory::Package => "package",
+            NotifyCategory::Update => "update",
+            NotifyCategory::Audit => "audit",
+            NotifyCategory::Fleet => "fleet",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Events to send; none means all of them
+    pub events: Vec<NotifyEvent>,
+    /// Extra request headers, such as an `Authorization` token
+    pub headers: std::collections::BTreeMap<String, String>,
+    /// Signs each body with HMAC-SHA256 in the `X-Omni-Signature` header
+    pub secret: Option<String>,
+}
+
+impl WebhookConfig {
+    pub fn wants(&self, event: NotifyEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Package events a webhook can subscribe to
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    Install,
+    Remove,
+    Update,
+    /// Any install, removal or update that failed
+    Failure,
+}
+
+impl std::fmt::Display for NotifyEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            NotifyEvent::Install => "install",
+            NotifyEvent::Remove => "remove",
+            NotifyEvent::Update => "update",
+            NotifyEvent::Failure => "failure",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Checks run after updates on this machine and in fleet rollouts; see
+/// `health_checks`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct HealthChecksConfig {
+    /// Undo the update when a check fails
+    pub rollback: bool,
+    pub checks: Vec<HealthCheckConfig>,
+}
+
+impl Default for HealthChecksConfig {
+    fn default() -> Self {
+        Self {
+            rollback: true,
+            checks: Vec::new(),
+        }
+    }
+}
+
+/// One check; exactly one of `http`, `systemd_unit` and `command` is set
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// Shown in results; defaults to a description of the probe
+    pub name: Option<String>,
+    /// Packages whose updates run the check; patterns may use `*`, empty means all
+    pub packages: Vec<String>,
+    /// Fleet hosts the check runs on, by name and with `*`, and `local` for
+    /// this machine; empty means everywhere
+    pub hosts: Vec<String>,
+    /// URL that has to answer with a 2xx status; `{host}` stands for the
+    /// address of the fleet host, or localhost
+    pub http: Option<String>,
+    /// systemd unit that has to be active
+    pub systemd_unit: Option<String>,
+    /// Shell command that has to exit with 0, run on the updated host
+    pub command: Option<String>,
+    /// Tries before the check counts as failed, for services still restarting
+    pub attempts: u32,
+    /// Seconds between tries
+    pub interval_seconds: u64,
+    /// Seconds one try may take
+    pub timeout_seconds: u64,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            packages: Vec::new(),
+            hosts: Vec::new(),
+            http: None,
+            systemd_unit: None,
+            command: None,
+            attempts: 3,
+            interval_seconds: 5,
+            timeout_seconds: 30,
+        }
+    }
+}
+
+/// Permissions and ownership of the files omni writes outside its own state:
+/// repository entries, keyrings, AppImages and desktop entries
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct FilesConfig {
+    /// Octal umask for files and directories omni creates, e.g. "022" or "077".
+    /// Repository entries and keyrings stay world-readable, apt needs that.
+    pub umask: String,
+    /// Under sudo, give files written into the invoking user's home back to
+    /// that user instead of leaving them owned by root
+    pub chown_to_invoking_user: bool,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self {
+            umask: "022".to_string(),
+            chown_to_invoking_user: true,
+        }
+    }
+}
+
+impl FilesConfig {
+    /// The umask as permission bits
+    pub fn umask_bits(&self) -> Result<u32> {
+        u32::from_str_radix(self.umask.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|bits| *bits <= 0o777)
+            .ok_or_else(|| anyhow::anyhow!("Invalid umask '{}' (files.umask)", self.umask))
+    }
+}
+
+/// Filesystem snapshots taken along with omni's package-list snapshots, so a
+/// revert restores the system and not only its packages
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct SnapshotsConfig {
+    pub backend: SnapshotBackendKind,
+    pub btrfs: BtrfsSnapshotConfig,
+    pub zfs: ZfsSnapshotConfig,
+    pub snapper: SnapperSnapshotConfig,
+    pub retention: SnapshotRetention,
+}
+
+/// Which filesystem snapshots omni takes; see `snapshot_backends`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotBackendKind {
+    /// snapper or Timeshift when set up, otherwise the one the root
+    /// filesystem supports, if any
+    #[default]
+    Auto,
+    /// Package lists only
+    None,
+    Btrfs,
+    Zfs,
+    Snapper,
+    Timeshift,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct BtrfsSnapshotConfig {
+    /// Subvolumes to snapshot, by mount point; ones that are not subvolumes
+    /// are skipped
+    pub subvolumes: Vec<PathBuf>,
+    /// Where the read-only snapshots go, on the same filesystem
+    pub snapshot_dir: PathBuf,
+}
+
+impl Default for BtrfsSnapshotConfig {
+    fn default() -> Self {
+        Self {
+            subvolumes: vec![PathBuf::from("/"), PathBuf::from("/home")],
+            snapshot_dir: PathBuf::from("/.snapshots/omni"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ZfsSnapshotConfig {
+    /// Datasets to snapshot, e.g. `rpool/ROOT/ubuntu`; empty means the one
+    /// mounted at `/`
+    pub datasets: Vec<String>,
+}
+
+/// Which automatic snapshots are kept, checked after each one is taken and
+/// by `omni snapshot prune`. Snapshots taken by hand are never pruned.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct SnapshotRetention {
+    /// Newest automatic snapshots to keep
+    pub keep: Option<usize>,
+    /// Drop automatic snapshots older than this
+    pub max_age_days: Option<u32>,
+    /// Drop the oldest automatic snapshots once their filesystem snapshots
+    /// take more than this; only Btrfs and ZFS report their size
+    pub max_disk_usage_mb: Option<u64>,
+}
+
+impl Default for SnapshotRetention {
+    fn default() -> Self {
+        Self {
+            keep: Some(20),
+            max_age_days: None,
+            max_disk_usage_mb: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct SnapperSnapshotConfig {
+    /// snapper config omni's snapshots go in, as in `snapper -c root`
+    pub config: String,
+}
+
+impl Default for SnapperSnapshotConfig {
+    fn default() -> Self {
+        Self {
+            config: "root".to_string(),
+        }
+    }
+}
+
+/// How log lines are written, on stdout and in the log files, and how long
+/// the files are kept
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// When a new log file is started
+    pub rotation: LogRotation,
+    /// Start a new file once the current one reaches this size
+    pub max_file_size_mb: Option<u64>,
+    /// Delete log files last written more than this many days ago
+    pub max_age_days: Option<u32>,
+    /// Keep at most this many log files, the newest ones
+    pub max_files: Option<usize>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            rotation: LogRotation::default(),
+            max_file_size_mb: Some(50),
+            max_age_days: Some(30),
+            max_files: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    /// One file, only rotated by size
+    Never,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line with the fields at the top level, for Loki,
+    /// Elasticsearch and other log collectors
+    Json,
+}
+
+/// Export of tracing spans to an OpenTelemetry collector over OTLP/HTTP;
+/// needs a build with the `otel` feature
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    /// Collector base URL such as http://tempo:4318; spans go to its
+    /// /v1/traces. OTEL_EXPORTER_OTLP_ENDPOINT or http://localhost:4318 when unset
+    pub endpoint: Option<String>,
+    /// Extra request headers, such as the API key of a hosted backend
+    pub headers: std::collections::BTreeMap<String, String>,
+    /// `service.name` of the exported spans
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: None,
+            headers: std::collections::BTreeMap::new(),
+            service_name: "omni".to_string(),
+        }
+    }
+}
+
+/// What happens to audit events beyond the `audit_log` table; see `audit`
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub email: EmailAlertConfig,
+    /// Chat channels alerted through incoming webhooks
+    pub chat: Vec<ChatAlertConfig>,
+    /// Remote syslog collectors that receive a copy of the audit log
+    pub syslog: Vec<SyslogConfig>,
+    /// Delete audit entries older than this many days with `omni logs prune`;
+    /// kept forever when unset
+    pub retention_days: Option<u32>,
+}
+
+/// An incoming webhook of a chat service
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ChatAlertConfig {
+    pub url: String,
+    pub format: ChatFormat,
+    pub min_severity: AuditSeverity,
+}
+
+impl Default for ChatAlertConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            format: ChatFormat::default(),
+            min_severity: AuditSeverity::Critical,
+        }
+    }
+}
+
+/// Message layout a chat webhook expects
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatFormat {
+    /// Block Kit message
+    #[default]
+    Slack,
+    /// Message with an embed
+    Discord,
+    /// Adaptive Card, for Workflows and Office 365 connector webhooks
+    Teams,
+}
+
+impl std::fmt::Display for ChatFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ChatFormat::Slack => "slack",
+            ChatFormat::Discord => "discord",
+            ChatFormat::Teams => "teams",
+        };
+        f.write_str(name)
+    }
+}
+
+/// SMTP delivery of audit events at or above a severity
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct EmailAlertConfig {
+    pub enabled: bool,
+    pub smtp_host: String,
+    /// Defaults to 587 for STARTTLS, 465 for implicit TLS and 25 without TLS
+    pub smtp_port: Option<u16>,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub min_severity: AuditSeverity,
+}
+
+impl Default for EmailAlertConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            smtp_host: String::new(),
+            smtp_port: None,
+            tls: SmtpTls::StartTls,
+            username: None,
+            password: None,
+            from: String::new(),
+            to: Vec::new(),
+            min_severity: AuditSeverity::Critical,
+        }
+    }
+}
+
+impl EmailAlertConfig {
+    pub fn port(&self) -> u16 {
+        self.smtp_port.unwrap_or(match self.tls {
+            SmtpTls::None => 25,
+            SmtpTls::StartTls => 587,
+            SmtpTls::Tls => 465,
+        })
+    }
+}
+
+/// How the SMTP connection is secured
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Plain text; only for a relay on localhost or a trusted network
+    None,
+    /// Upgrade a plain connection, refusing servers that cannot
+    StartTls,
+    /// TLS from the first byte (SMTPS)
+    Tls,
+}
+
+impl std::fmt::Display for SmtpTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SmtpTls::None => "none",
+            SmtpTls::StartTls => "start_tls",
+            SmtpTls::Tls => "tls",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A remote syslog collector; events go out as RFC 5424 messages
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct SyslogConfig {
+    pub host: String,
+    /// Defaults to 514, or 6514 over TLS
+    pub port: Option<u16>,
+    pub transport: SyslogTransport,
+    /// PEM file with the CA that signed the collector's certificate, for
+    /// collectors behind a private CA; the public web roots otherwise
+    pub ca_file: Option<PathBuf>,
+    pub facility: SyslogFacility,
+    pub min_severity: AuditSeverity,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: None,
+            transport: SyslogTransport::default(),
+            ca_file: None,
+            facility: SyslogFacility::default(),
+            min_severity: AuditSeverity::Info,
+        }
+    }
+}
+
+impl SyslogConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.transport {
+            SyslogTransport::Udp | SyslogTransport::Tcp => 514,
+            SyslogTransport::Tls => 6514,
+        })
+    }
+}
+
+/// How messages reach a syslog collector
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogTransport {
+    /// One datagram per message (RFC 5426); messages can be lost
+    #[default]
+    Udp,
+    /// Octet-counted frames over TCP (RFC 6587)
+    Tcp,
+    /// Octet-counted frames over TLS (RFC 5425)
+    Tls,
+}
+
+impl std::fmt::Display for SyslogTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SyslogTransport::Udp => "udp",
+            SyslogTransport::Tcp => "tcp",
+            SyslogTransport::Tls => "tls",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Syslog facility the messages are filed under
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogFacility {
+    User,
+    Daemon,
+    #[default]
+    Auth,
+    Authpriv,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Numeric code from RFC 5424, section 6.2.1
+    pub fn code(self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Auth => 4,
+            SyslogFacility::Authpriv => 10,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_minutes: 360,
+            auto_download: false,
+            auto_install: AutoInstallPolicy::Never,
+            maintenance: MaintenanceConfig::default(),
+            digest: DigestConfig::default(),
+        }
+    }
 }
 
 impl Default for OmniConfig {
     fn default() -> Self {
         Self {
+            version: config_migration::CURRENT_VERSION,
             general: GeneralConfig {
                 auto_update: false,
                 parallel_installs: true,
@@ -62,6 +1171,8 @@ impl Default for OmniConfig {
                 confirm_installs: true,
                 log_level: "info".to_string(),
                 fallback_enabled: true,
+                read_only: false,
+                source_fallback: false,
             },
             boxes: BoxConfig {
                 preferred_order: vec![
@@ -78,6 +1189,7 @@ impl Default for OmniConfig {
                 pacman_options: vec!["--noconfirm".to_string()],
                 snap_options: vec![],
                 flatpak_options: vec!["-y".to_string()],
+                repository_priority: vec![],
             },
             security: SecurityConfig {
                 verify_signatures: true,
@@ -97,60 +1209,208 @@ impl Default for OmniConfig {
                 use_colors: true,
                 compact_output: false,
                 gui_theme: "dark".to_string(),
+                gui_text_size: TextSize::Normal,
             },
+            paths: PathsConfig::default(),
+            daemon: DaemonConfig::default(),
+            policies: PoliciesConfig::default(),
+            self_update: SelfUpdateConfig::default(),
+            dotfiles: DotfilesConfig::default(),
+            release_support: ReleaseSupportConfig::default(),
+            notifications: NotificationsConfig::default(),
+            audit: AuditConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            logging: LoggingConfig::default(),
+            health_checks: HealthChecksConfig::default(),
+            files: FilesConfig::default(),
+            vulnerabilities: VulnerabilityConfig::default(),
+            enforcement: EnforcementConfig::default(),
+            snapshots: SnapshotsConfig::default(),
+            search: SearchConfig::default(),
+            project_config: None,
+            overridden_by: Vec::new(),
+            decrypted: config_secrets::Decrypted::default(),
         }
     }
 }
 
 impl OmniConfig {
+    /// The configuration in layers: the config file, the allowed config of the
+    /// project around the working directory (not in the system scope), `OMNI_*`
+    /// environment variables and finally `--set` on the command line
     pub fn load() -> Result<Self> {
+        let mut config = Self::load_user()?;
+        let mut decrypted = std::mem::take(&mut config.decrypted);
+
+        let overlay = match Self::scope() {
+            StateScope::System => None,
+            StateScope::User => project::overlay()?,
+        };
+        if let Some((path, mut overlay)) = overlay {
+            decrypted.extend(config_secrets::decrypt_values(&mut overlay)?);
+            let mut value = serde_yaml::to_value(&config)?;
+            project::merge(&mut value, overlay);
+            config_validation::check(&value, &path.display().to_string())?;
+            config = serde_yaml::from_value(value)?;
+            config.validate_and_fix();
+            config.project_config = Some(path);
+        }
+
+        let overrides = overrides()?;
+        if !overrides.is_empty() {
+            let project_config = config.project_config.take();
+            let mut value = serde_yaml::to_value(&config)?;
+            apply_overrides(&mut value, &overrides)?;
+            decrypted.extend(config_secrets::decrypt_values(&mut value)?);
+            let sources: Vec<String> = overrides.into_iter().map(|o| o.source).collect();
+            config_validation::check(&value, &format!("The config with {}", sources.join(", ")))?;
+            config = serde_yaml::from_value(value)?;
+            config.validate_and_fix();
+            config.project_config = project_config;
+            config.overridden_by = sources;
+        }
+        config.decrypted = decrypted;
+        Ok(config)
+    }
+
+    /// The user's (or with `--system` the machine's) configuration alone
+    pub fn load_user() -> Result<Self> {
         let config_path = Self::config_path()?;
 
         if config_path.exists() {
             let content = fs::read_to_string(&config_path)?;
-            let mut config: OmniConfig = serde_yaml::from_str(&content)?;
+            let mut value: serde_yaml::Value = serde_yaml::from_str(&content)?;
+            let migration = config_migration::migrate(&mut value)?;
+            let decrypted = config_secrets::decrypt_values(&mut value)?;
+            config_validation::check(&value, &config_path.display().to_string())?;
+            let mut config: OmniConfig = serde_yaml::from_value(value)?;
+            config.decrypted = decrypted;
+
+            if let Some(report) = migration {
+                if !Self::is_read_only() {
+                    let backup =
+                        config_path.with_extension(format!("yaml.v{}.backup", report.from));
+                    fs::copy(&config_path, &backup)?;
+                    config.save()?;
+                    eprintln!(
+                        "🔄 Migrated {} from version {} to {} ({}); the previous file is {}",
+                        config_path.display(),
+                        report.from,
+                        report.to,
+                        report.steps.join("; "),
+                        backup.display()
+                    );
+                }
+            }
 
             // Validate and update config if needed
             config.validate_and_fix();
             Ok(config)
         } else {
             let config = Self::default();
-            config.save()?;
+            if !Self::is_read_only() {
+                config.save()?;
+            }
             Ok(config)
         }
     }
 
     pub fn save(&self) -> Result<()> {
+        ensure_writable("saving the configuration")?;
+        if let Some(project_config) = &self.project_config {
+            return Err(anyhow::anyhow!(
+                "Not saving settings merged with {}; load the configuration without it",
+                project_config.display()
+            ));
+        }
+        if !self.overridden_by.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Not saving settings overridden by {}; load the configuration without them",
+                self.overridden_by.join(", ")
+            ));
+        }
         let config_path = Self::config_path()?;
 
         if let Some(parent) = config_path.parent() {
-            fs::create_dir_all(parent)?;
+            ensure_private_dir(parent)?;
         }
 
-        let content = serde_yaml::to_string(self)?;
-        fs::write(&config_path, content)?;
+        write_private_file(&config_path, self.to_yaml()?.as_bytes())?;
 
         Ok(())
     }
 
+    /// The configuration as YAML, with the values that were encrypted in the
+    /// files encrypted again
+    pub fn to_yaml(&self) -> Result<String> {
+        let mut value = serde_yaml::to_value(self)?;
+        self.decrypted.seal(&mut value);
+        Ok(serde_yaml::to_string(&value)?)
+    }
+
     pub fn config_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("config.yaml"))
+    }
+
+    /// Directory of the config file and the other files users edit
+    pub fn config_dir() -> Result<PathBuf> {
+        if Self::scope() == StateScope::System {
+            return Ok(system_dir("/etc/omni", "config"));
+        }
         let config_dir =
             dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?;
-        Ok(config_dir.join("omni").join("config.yaml"))
+        Ok(config_dir.join("omni"))
     }
 
     pub fn data_dir() -> Result<PathBuf> {
+        if Self::scope() == StateScope::System {
+            return Ok(system_dir("/var/lib/omni", "data"));
+        }
         let data_dir =
             dirs::data_dir().ok_or_else(|| anyhow::anyhow!("Could not find data directory"))?;
         Ok(data_dir.join("omni"))
     }
 
     pub fn cache_dir() -> Result<PathBuf> {
+        if let Some(dir) = &path_overrides().cache_dir {
+            return Ok(dir.clone());
+        }
+        if Self::scope() == StateScope::System {
+            return Ok(system_dir("/var/cache/omni", "cache"));
+        }
         let cache_dir =
             dirs::cache_dir().ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?;
         Ok(cache_dir.join("omni"))
     }
 
+    /// Get state directory for history and other data that should survive
+    /// a cache wipe (`$XDG_STATE_HOME/omni`, or the data directory where
+    /// the platform has no state directory)
+    pub fn state_dir() -> Result<PathBuf> {
+        if let Some(dir) = &path_overrides().state_dir {
+            return Ok(dir.clone());
+        }
+        if Self::scope() == StateScope::System {
+            return Self::data_dir();
+        }
+        match dirs::state_dir() {
+            Some(state_dir) => Ok(state_dir.join("omni")),
+            None => Self::data_dir(),
+        }
+    }
+
+    /// Private directory for downloads and scratch files, created with 0700
+    /// permissions. Use this instead of the shared system temp directory.
+    pub fn temp_dir() -> Result<PathBuf> {
+        let temp_dir = Self::runtime_dir()?.join("tmp");
+        let created = !temp_dir.exists();
+        ensure_private_dir(&temp_dir)?;
+        if created {
+            crate::access_control::relabel(&temp_dir);
+        }
+        Ok(temp_dir)
+    }
+
     pub fn is_box_enabled(&self, box_name: &str) -> bool {
         !self.boxes.disabled_boxes.contains(&box_name.to_string())
     }
@@ -162,6 +1422,15 @@ impl OmniConfig {
             .position(|name| name == box_name)
     }
 
+    /// Position of the first `repository_priority` entry matching `repository`
+    pub fn get_repository_priority(&self, repository: &str) -> Option<usize> {
+        let repository = repository.to_lowercase();
+        self.boxes
+            .repository_priority
+            .iter()
+            .position(|entry| !entry.is_empty() && repository.contains(&entry.to_lowercase()))
+    }
+
     /// Validate configuration and fix any issues
     pub fn validate_and_fix(&mut self) {
         // Ensure max_parallel_jobs is reasonable
@@ -208,6 +1477,8 @@ impl OmniConfig {
                 }
             }
             "fallback_enabled" => self.general.fallback_enabled = value.parse()?,
+            "read_only" => self.general.read_only = value.parse()?,
+            "source_fallback" => self.general.source_fallback = value.parse()?,
             _ => return Err(anyhow::anyhow!("Unknown general option: {}", key)),
         }
         Ok(())
@@ -237,8 +1508,79 @@ impl OmniConfig {
         }
     }
 
+    /// Put the whole process in read-only mode. There is deliberately no way
+    /// to switch it off again.
+    pub fn enable_read_only() {
+        READ_ONLY.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_read_only() -> bool {
+        READ_ONLY.load(Ordering::SeqCst)
+    }
+
+    /// Work on the machine-wide state instead of the current user's. Only root
+    /// may, so a user cannot leave files in directories only root can clean up.
+    pub fn enable_system_scope() -> Result<()> {
+        if !PrivilegeManager::is_root() {
+            return Err(OmniError::PermissionDenied {
+                operation: "using the system scope (run omni --system with sudo)".to_string(),
+            }
+            .into());
+        }
+        SYSTEM_SCOPE.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn scope() -> StateScope {
+        if SYSTEM_SCOPE.load(Ordering::SeqCst) {
+            StateScope::System
+        } else {
+            StateScope::User
+        }
+    }
+
+    /// One line naming the active scope and where its state lives
+    pub fn scope_summary() -> String {
+        let data_dir = Self::data_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        match Self::scope() {
+            StateScope::System => format!("System scope: state in {}", data_dir),
+            StateScope::User => format!(
+                "User scope ({}): state in {}",
+                current_user_name(),
+                data_dir
+            ),
+        }
+    }
+
+    /// Refuse to touch omni directories that belong to another user, e.g.
+    /// a user's own state reached through `sudo` keeping `$HOME`. Writing
+    /// there as root would leave files the user can no longer change.
+    pub fn check_state_ownership() -> Result<()> {
+        #[cfg(unix)]
+        {
+            let uid = unsafe { libc::geteuid() };
+            for dir in [
+                Self::config_dir()?,
+                Self::data_dir()?,
+                Self::state_dir()?,
+                Self::cache_dir()?,
+            ] {
+                ensure_owned_by(&dir, uid)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Get runtime directory for temporary files
     pub fn runtime_dir() -> Result<PathBuf> {
+        if let Some(dir) = &path_overrides().runtime_dir {
+            return Ok(dir.clone());
+        }
+        if Self::scope() == StateScope::System {
+            return Ok(system_dir("/run/omni", "run"));
+        }
         let runtime_dir = dirs::runtime_dir()
             .or_else(|| dirs::cache_dir())
             .ok_or_else(|| anyhow::anyhow!("Could not find runtime directory"))?;
@@ -247,8 +1589,11 @@ impl OmniConfig {
 
     /// Get log directory
     pub fn log_dir() -> Result<PathBuf> {
-        let log_dir = Self::data_dir()?.join("logs");
-        fs::create_dir_all(&log_dir)?;
+        let log_dir = match Self::scope() {
+            StateScope::System => system_dir("/var/log/omni", "logs"),
+            StateScope::User => Self::data_dir()?.join("logs"),
+        };
+        ensure_private_dir(&log_dir)?;
         Ok(log_dir)
     }
 
@@ -275,6 +1620,12 @@ impl OmniConfig {
         if other.general.fallback_enabled != default_config.general.fallback_enabled {
             self.general.fallback_enabled = other.general.fallback_enabled;
         }
+        if other.general.read_only != default_config.general.read_only {
+            self.general.read_only = other.general.read_only;
+        }
+        if other.general.source_fallback != default_config.general.source_fallback {
+            self.general.source_fallback = other.general.source_fallback;
+        }
     }
 
     /// Create backup of current config
@@ -302,3 +1653,483 @@ impl OmniConfig {
         }
     }
 }
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+static SYSTEM_SCOPE: AtomicBool = AtomicBool::new(false);
+static CLI_SETTINGS: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Environment variables starting with this override config keys, with `__`
+/// between the parts of the key: `OMNI_GENERAL__LOG_LEVEL=debug` sets
+/// `general.log_level`
+pub const ENV_PREFIX: &str = "OMNI_";
+
+/// A setting from outside the config files
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    /// The variable or `--set` argument it came from
+    pub source: String,
+    /// Dotted key, e.g. `general.log_level`
+    pub key: String,
+    pub value: serde_yaml::Value,
+}
+
+/// Settings given with `--set key=value`. Call once, before anything reads
+/// the configuration; they apply after the environment.
+pub fn set_cli_settings(settings: &[String]) -> Result<()> {
+    let settings = settings
+        .iter()
+        .map(|setting| {
+            setting
+                .split_once('=')
+                .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+                .ok_or_else(|| {
+                    OmniError::ConfigurationError {
+                        message: format!("--set {} is not key=value", setting),
+                    }
+                    .into()
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let _ = CLI_SETTINGS.set(settings);
+    Ok(())
+}
+
+/// The `OMNI_*` variables and `--set` settings of this process, in the
+/// order they apply
+pub fn overrides() -> Result<Vec<Override>> {
+    let mut vars: Vec<(String, String)> = std::env::vars().collect();
+    vars.sort();
+    overrides_from(
+        &vars,
+        CLI_SETTINGS.get().map_or(&[][..], Vec::as_slice),
+    )
+}
+
+fn overrides_from(vars: &[(String, String)], cli: &[(String, String)]) -> Result<Vec<Override>> {
+    let from_env = vars.iter().filter_map(|(name, value)| {
+        let key = name.strip_prefix(ENV_PREFIX)?;
+        // Other OMNI_ variables, such as OMNI_MOCK_FIXTURE, are not settings
+        key.contains("__").then(|| Override {
+            source: name.clone(),
+            key: key
+                .split("__")
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>()
+                .join("."),
+            value: parse_setting(value),
+        })
+    });
+    let from_cli = cli.iter().map(|(key, value)| Override {
+        source: format!("--set {}", key),
+        key: key.clone(),
+        value: parse_setting(value),
+    });
+
+    from_env
+        .chain(from_cli)
+        .map(|o| match config_migration::check_key(&o.key) {
+            Some(problem) => Err(OmniError::ConfigurationError {
+                message: format!("{}: {}", o.source, problem),
+            }
+            .into()),
+            None => Ok(o),
+        })
+        .collect()
+}
+
+/// A value as YAML, so `true`, `30` and `[apt, dnf]` get their types; text
+/// that is not valid YAML stays text
+fn parse_setting(value: &str) -> serde_yaml::Value {
+    if value.trim().is_empty() {
+        return serde_yaml::Value::String(value.to_string());
+    }
+    serde_yaml::from_str(value).unwrap_or_else(|_| serde_yaml::Value::String(value.to_string()))
+}
+
+/// Set every override's key in a config value
+pub fn apply_overrides(value: &mut serde_yaml::Value, overrides: &[Override]) -> Result<()> {
+    let serde_yaml::Value::Mapping(mapping) = value else {
+        return Err(anyhow::anyhow!("the configuration is not a YAML mapping"));
+    };
+    for o in overrides {
+        config_migration::set_path(mapping, &o.key, o.value.clone());
+    }
+    Ok(())
+}
+
+/// Whose state a process reads and writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateScope {
+    /// The current user's XDG directories
+    User,
+    /// Machine-wide directories managed by root (`--system`)
+    System,
+}
+
+impl std::fmt::Display for StateScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StateScope::User => "user",
+            StateScope::System => "system",
+        })
+    }
+}
+
+/// A machine-wide directory: the FHS path on Unix, a folder under
+/// `%ProgramData%\omni` on Windows
+fn system_dir(unix: &str, windows: &str) -> PathBuf {
+    if cfg!(windows) {
+        std::env::var_os("ProgramData")
+            .map_or_else(|| PathBuf::from(r"C:\ProgramData"), PathBuf::from)
+            .join("omni")
+            .join(windows)
+    } else {
+        PathBuf::from(unix)
+    }
+}
+
+fn current_user_name() -> String {
+    if let Ok(name) = std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+        return name;
+    }
+    #[cfg(unix)]
+    return format!("uid {}", unsafe { libc::geteuid() });
+    #[cfg(not(unix))]
+    "unknown".to_string()
+}
+
+/// `path`, or the closest parent that exists, has to belong to `uid`. Parents
+/// owned by root are fine too: that is `/tmp`, `/srv` and the like.
+#[cfg(unix)]
+pub fn ensure_owned_by(path: &Path, uid: u32) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let Some((existing, owner)) = path
+        .ancestors()
+        .find_map(|p| fs::metadata(p).ok().map(|m| (p, m.uid())))
+    else {
+        return Ok(());
+    };
+    if is_foreign(owner, uid, existing == path) {
+        return Err(OmniError::PermissionDenied {
+            operation: format!(
+                "using {}, which belongs to uid {} rather than uid {}. Run omni as that user, use 'sudo -H' so root keeps its own state, or pass --system for the machine-wide state. 'sudo omni permissions repair' gives files left by root back to their user",
+                path.display(),
+                owner,
+                uid
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Fail before `operation` changes anything when omni runs with `--read-only`
+pub fn ensure_writable(operation: &str) -> Result<()> {
+    if OmniConfig::is_read_only() {
+        return Err(OmniError::ReadOnly {
+            operation: operation.to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The `files` section of the config file, read once, for code that writes
+/// files without a loaded config at hand
+pub fn file_settings() -> &'static FilesConfig {
+    static SETTINGS: OnceLock<FilesConfig> = OnceLock::new();
+    SETTINGS.get_or_init(|| file_section("files"))
+}
+
+/// Path overrides from the config file, read once. The directory helpers are
+/// associated functions without a loaded config, so they consult this instead.
+fn path_overrides() -> &'static PathsConfig {
+    static OVERRIDES: OnceLock<PathsConfig> = OnceLock::new();
+    OVERRIDES.get_or_init(|| file_section("paths"))
+}
+
+/// One section of the config file with the environment and `--set` laid over
+/// it, or its defaults when it cannot be read
+fn file_section<T: serde::de::DeserializeOwned + Default>(section: &str) -> T {
+    let mut value = OmniConfig::config_path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_yaml::from_str(&content).ok())
+        .filter(serde_yaml::Value::is_mapping)
+        .unwrap_or_else(|| serde_yaml::Value::Mapping(serde_yaml::Mapping::new()));
+    if let Ok(overrides) = overrides() {
+        let _ = apply_overrides(&mut value, &overrides);
+    }
+    value
+        .get(section)
+        .cloned()
+        .and_then(|section| serde_yaml::from_value(section).ok())
+        .unwrap_or_default()
+}
+
+/// Create `path` (and any missing parents) and restrict it to the current user
+pub fn ensure_private_dir(path: &Path) -> Result<()> {
+    fs::create_dir_all(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Write `contents` to `path`, readable and writable by the current user only
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files; tighten ones written by older versions
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+/// Whether a directory owned by `owner` is off limits to `uid`. Root may own
+/// the parents of anyone's directories.
+fn is_foreign(owner: u32, uid: u32, is_target: bool) -> bool {
+    owner != uid && (is_target || owner != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_config_defaults_when_missing() {
+        let mut value = serde_yaml::to_value(OmniConfig::default()).unwrap();
+        value.as_mapping_mut().unwrap().remove("paths");
+        let config: OmniConfig = serde_yaml::from_value(value).unwrap();
+        assert_eq!(config.paths, PathsConfig::default());
+
+        let paths: PathsConfig = serde_yaml::from_str("cache_dir: /srv/omni-cache").unwrap();
+        assert_eq!(paths.cache_dir, Some(PathBuf::from("/srv/omni-cache")));
+        assert_eq!(paths.state_dir, None);
+    }
+
+    #[test]
+    fn test_daemon_config_partial_section() {
+        let daemon: DaemonConfig = serde_yaml::from_str("auto_install: security").unwrap();
+        assert_eq!(daemon.auto_install, AutoInstallPolicy::Security);
+        assert_eq!(daemon.check_interval_minutes, 360);
+        assert!(!daemon.auto_download);
+    }
+
+    #[test]
+    fn test_unattended_policy_defaults() {
+        let policies: PoliciesConfig =
+            serde_yaml::from_str("unattended:\n  exclude: [\"postgresql*\"]").unwrap();
+        let policy = policies.unattended;
+        assert_eq!(policy.exclude, vec!["postgresql*".to_string()]);
+        assert!(policy.exclude_kernels);
+        assert!(policy.include.is_empty());
+        assert_eq!(policy.min_severity, None);
+        assert!(!policies.cross_backend.enabled);
+        assert_eq!(policies.cross_backend.boxes, vec!["flatpak", "nix"]);
+
+        let policy: UnattendedPolicy = serde_yaml::from_str("min_severity: important").unwrap();
+        assert_eq!(policy.min_severity, Some(Severity::Important));
+        assert!(Severity::Critical > Severity::Important);
+    }
+
+    #[test]
+    fn test_search_config_defaults() {
+        let search: SearchConfig = serde_yaml::from_str("installed_boost: 0").unwrap();
+        assert_eq!(search.installed_boost, 0);
+        assert_eq!(search.exact_weight, 100);
+        assert_eq!(search.popular_boxes[0], "apt");
+        assert_eq!(search.index_max_age_hours, 24);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let vars = vec![
+            ("OMNI_GENERAL__LOG_LEVEL".to_string(), "debug".to_string()),
+            ("OMNI_BOXES__PREFERRED_ORDER".to_string(), "[flatpak, apt]".to_string()),
+            ("OMNI_MOCK_FIXTURE".to_string(), "universe.yaml".to_string()),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+        ];
+        let cli = vec![
+            ("general.log_level".to_string(), "trace".to_string()),
+            ("daemon.check_interval_minutes".to_string(), "30".to_string()),
+        ];
+        let overrides = overrides_from(&vars, &cli).unwrap();
+        assert_eq!(overrides.len(), 4);
+        assert_eq!(overrides[0].key, "general.log_level");
+
+        let mut value = serde_yaml::to_value(OmniConfig::default()).unwrap();
+        apply_overrides(&mut value, &overrides).unwrap();
+        let config: OmniConfig = serde_yaml::from_value(value).unwrap();
+        // --set comes after the environment
+        assert_eq!(config.general.log_level, "trace");
+        assert_eq!(config.boxes.preferred_order, vec!["flatpak", "apt"]);
+        assert_eq!(config.daemon.check_interval_minutes, 30);
+
+        let typo = vec![("OMNI_GENERAL__LOG_LEVLE".to_string(), "debug".to_string())];
+        let message = overrides_from(&typo, &[]).unwrap_err().to_string();
+        assert!(message.contains("OMNI_GENERAL__LOG_LEVLE"));
+        assert!(message.contains("did you mean `general.log_level`?"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_state_ownership() {
+        let dir = tempfile::tempdir().unwrap();
+        let uid = unsafe { libc::geteuid() };
+        assert!(ensure_owned_by(dir.path(), uid).is_ok());
+        assert!(ensure_owned_by(&dir.path().join("share/omni"), uid).is_ok());
+
+        // Someone else's home, as root sees it through sudo
+        assert!(is_foreign(1000, 0, false));
+        assert!(is_foreign(1000, 0, true));
+        // Root-owned parents such as /tmp are shared, root-owned state is not
+        assert!(!is_foreign(0, 1000, false));
+        assert!(is_foreign(0, 1000, true));
+        assert!(!is_foreign(1000, 1000, true));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_dir_and_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("state/omni");
+        ensure_private_dir(&dir).unwrap();
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let file = dir.join("history.json");
+        fs::write(&file, "[]").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private_file(&file, b"[1]").unwrap();
+        assert_eq!(
+            fs::metadata(&file).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert_eq!(fs::read_to_string(&file).unwrap(), "[1]");
+    }
+
+    #[test]
+    fn test_gui_text_size() {
+        let ui: UiConfig = serde_yaml::from_str(
+            "show_progress: true\nuse_colors: true\ncompact_output: false\ngui_theme: dark\n",
+        )
+        .unwrap();
+        assert_eq!(ui.gui_text_size, TextSize::Normal);
+
+        let ui: UiConfig = serde_yaml::from_str(
+            "show_progress: true\nuse_colors: true\ncompact_output: false\ngui_theme: dark\ngui_text_size: larger\n",
+        )
+        .unwrap();
+        assert_eq!(ui.gui_text_size.factor(), 1.5);
+        assert_eq!(TextSize::Large.to_string(), "large");
+    }
+
+    #[test]
+    fn test_telemetry_config() {
+        let config = OmniConfig::default();
+        assert!(!config.telemetry.enabled);
+        assert_eq!(config.telemetry.service_name, "omni");
+
+        let telemetry: TelemetryConfig =
+            serde_yaml::from_str("enabled: true\nendpoint: http://tempo:4318/\n").unwrap();
+        assert_eq!(telemetry.service_name, "omni");
+        assert_eq!(
+            crate::logging::traces_url(telemetry.endpoint.as_deref().unwrap()),
+            "http://tempo:4318/v1/traces"
+        );
+    }
+
+    #[test]
+    fn test_digest_config() {
+        let config = OmniConfig::default();
+        assert!(config.daemon.digest.period.is_none());
+
+        let daemon: DaemonConfig =
+            serde_yaml::from_str("digest:\n  period: month\n  to: [ops@example.com]\n").unwrap();
+        assert_eq!(daemon.digest.period, Some(DigestPeriod::Month));
+        assert_eq!(daemon.digest.to, ["ops@example.com"]);
+        assert_eq!(daemon.check_interval_minutes, 360);
+    }
+
+    #[test]
+    fn test_logging_config() {
+        assert_eq!(OmniConfig::default().logging.format, LogFormat::Text);
+
+        let logging: LoggingConfig = serde_yaml::from_str("format: json\n").unwrap();
+        assert_eq!(logging.format, LogFormat::Json);
+        assert!(serde_yaml::from_str::<LoggingConfig>("format: xml\n").is_err());
+    }
+
+    #[test]
+    fn test_critical_packages_config() {
+        assert!(OmniConfig::default().policies.critical.is_empty());
+
+        let policies: PoliciesConfig =
+            serde_yaml::from_str("critical: [\"postgresql*\", openssl]\n").unwrap();
+        assert_eq!(policies.critical, ["postgresql*", "openssl"]);
+        assert_eq!(policies.partial_upgrades, PartialUpgradePolicy::Warn);
+    }
+
+    #[test]
+    fn test_health_checks_config() {
+        let config = OmniConfig::default();
+        assert!(config.health_checks.rollback);
+        assert!(config.health_checks.checks.is_empty());
+
+        let health_checks: HealthChecksConfig = serde_yaml::from_str(
+            "checks:\n  - packages: [\"nginx*\"]\n    http: http://{host}/healthz\n",
+        )
+        .unwrap();
+        assert!(health_checks.rollback);
+        let check = &health_checks.checks[0];
+        assert_eq!(check.http.as_deref(), Some("http://{host}/healthz"));
+        assert_eq!(check.attempts, 3);
+        assert!(check.hosts.is_empty());
+    }
+
+    #[test]
+    fn test_log_retention_config() {
+        let config = OmniConfig::default();
+        assert_eq!(config.logging.rotation, LogRotation::Daily);
+        assert_eq!(config.logging.max_age_days, Some(30));
+        assert!(config.audit.retention_days.is_none());
+
+        let logging: LoggingConfig =
+            serde_yaml::from_str("rotation: hourly\nmax_files: 5\n").unwrap();
+        assert_eq!(logging.rotation, LogRotation::Hourly);
+        assert_eq!(logging.max_files, Some(5));
+        assert_eq!(logging.max_file_size_mb, Some(50));
+
+        let audit: AuditConfig = serde_yaml::from_str("retention_days: 365\n").unwrap();
+        assert_eq!(audit.retention_days, Some(365));
+    }
+
+    #[test]
+    fn test_files_config() {
+        let config = OmniConfig::default();
+        assert_eq!(config.files.umask_bits().unwrap(), 0o022);
+        assert!(config.files.chown_to_invoking_user);
+
+        let files: FilesConfig = serde_yaml::from_str("umask: \"077\"\n").unwrap();
+        assert_eq!(files.umask_bits().unwrap(), 0o077);
+        assert!(files.chown_to_invoking_user);
+
+        let files: FilesConfig = serde_yaml::from_str("umask: \"u=rwx\"\n").unwrap();
+        assert!(files.umask_bits().is_err());
+    }
+}
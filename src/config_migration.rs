@@ -0,0 +1,325 @@
+//! Versioned config files: migrates older `config.yaml` layouts to the current
+//! one and reports keys omni does not know.
+
+use crate::config::OmniConfig;
+use crate::error_handling::OmniError;
+use anyhow::Result;
+use serde_yaml::{Mapping, Value};
+
+/// Layout version written by this build. Files without a `version` are 0.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Keys that moved, as dotted paths: old name, new name
+pub const RENAMED_KEYS: &[(&str, &str)] = &[
+    ("logging.level", "general.log_level"),
+    ("ui.theme", "ui.gui_theme"),
+];
+
+struct Migration {
+    /// Version the step upgrades from
+    from: u32,
+    description: &'static str,
+    apply: fn(&mut Mapping),
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    description: "moved renamed keys and filled in settings added since the file was written",
+    apply: migrate_from_unversioned,
+}];
+
+/// What [`migrate`] changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from: u32,
+    pub to: u32,
+    pub steps: Vec<&'static str>,
+}
+
+/// Bring a parsed config file up to [`CURRENT_VERSION`]. Returns `None` when
+/// it already is current.
+pub fn migrate(value: &mut Value) -> Result<Option<MigrationReport>> {
+    let Value::Mapping(mapping) = value else {
+        return Err(config_error("the config file is not a YAML mapping"));
+    };
+
+    let version = match mapping.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| config_error("`version` must be a whole number"))?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(config_error(&format!(
+            "the config file has version {}, but this omni only understands up to {}; \
+             upgrade omni or restore an older config",
+            version, CURRENT_VERSION
+        )));
+    }
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    let mut steps = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        (migration.apply)(mapping);
+        mapping.insert("version".into(), (migration.from + 1).into());
+        steps.push(migration.description);
+    }
+
+    Ok(Some(MigrationReport {
+        from: version,
+        to: CURRENT_VERSION,
+        steps,
+    }))
+}
+
+fn migrate_from_unversioned(mapping: &mut Mapping) {
+    for (old, new) in RENAMED_KEYS {
+        if let Some(value) = take_path(mapping, old) {
+            if get_path(mapping, new).is_none() {
+                set_path(mapping, new, value);
+            }
+        }
+    }
+    // Files from before versioning lack settings that later became required
+    if let Ok(Value::Mapping(defaults)) = serde_yaml::to_value(OmniConfig::default()) {
+        fill_missing(mapping, &defaults);
+    }
+}
+
+/// What is wrong with a dotted key such as `general.log_level`, if anything
+pub fn check_key(path: &str) -> Option<String> {
+    let mut value = Mapping::new();
+    set_path(&mut value, path, Value::Null);
+    unknown_key_problems(&Value::Mapping(value))
+        .into_iter()
+        .next()
+        .map(|(path, problem)| format!("`{}` {}", path, problem))
+}
+
+/// Every key the current layout does not have, as a dotted path with what to
+/// use instead
+pub fn unknown_key_problems(value: &Value) -> Vec<(String, String)> {
+    let Ok(Value::Mapping(known)) = serde_yaml::to_value(OmniConfig::default()) else {
+        return Vec::new();
+    };
+    let Value::Mapping(mapping) = value else {
+        return Vec::new();
+    };
+
+    let mut unknown = Vec::new();
+    unknown_keys(mapping, &known, "", &mut unknown);
+    unknown
+        .into_iter()
+        .map(|(path, siblings)| {
+            let problem = if let Some((_, new)) = RENAMED_KEYS.iter().find(|(old, _)| *old == path) {
+                format!("was renamed to `{}`", new)
+            } else if let Some(suggestion) = closest(&path, &siblings) {
+                format!("is not a setting; did you mean `{}`?", suggestion)
+            } else {
+                "is not a setting".to_string()
+            };
+            (path, problem)
+        })
+        .collect()
+}
+
+/// Collect unknown keys as dotted paths with the known keys next to them
+fn unknown_keys(
+    mapping: &Mapping,
+    known: &Mapping,
+    prefix: &str,
+    unknown: &mut Vec<(String, Vec<String>)>,
+) {
+    for (key, value) in mapping {
+        let Some(name) = key.as_str() else {
+            continue;
+        };
+        let path = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+
+        match known.get(name) {
+            // Maps that are empty by default, such as request headers, take any key
+            Some(Value::Mapping(known_child)) if !known_child.is_empty() => {
+                if let Value::Mapping(child) = value {
+                    unknown_keys(child, known_child, &path, unknown);
+                }
+            }
+            Some(_) => {}
+            None => {
+                let siblings = known
+                    .keys()
+                    .filter_map(Value::as_str)
+                    .map(|k| {
+                        if prefix.is_empty() {
+                            k.to_string()
+                        } else {
+                            format!("{}.{}", prefix, k)
+                        }
+                    })
+                    .collect();
+                unknown.push((path, siblings));
+            }
+        }
+    }
+}
+
+/// The candidate a typo most likely meant
+fn closest<'a>(path: &str, candidates: &'a [String]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|c| (edit_distance(path, c), c))
+        .filter(|(distance, _)| *distance <= 3)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c.as_str())
+}
+
+/// Levenshtein distance between `a` and `b`, in characters
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn fill_missing(mapping: &mut Mapping, defaults: &Mapping) {
+    for (key, default) in defaults {
+        match (mapping.get_mut(key), default) {
+            (None, _) => {
+                mapping.insert(key.clone(), default.clone());
+            }
+            (Some(Value::Mapping(child)), Value::Mapping(default_child)) => {
+                fill_missing(child, default_child);
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn get_path<'a>(mapping: &'a Mapping, path: &str) -> Option<&'a Value> {
+    let (head, rest) = path.split_once('.').unwrap_or((path, ""));
+    let value = mapping.get(head)?;
+    if rest.is_empty() {
+        return Some(value);
+    }
+    get_path(value.as_mapping()?, rest)
+}
+
+/// Remove the value at `path`, dropping sections it leaves empty
+fn take_path(mapping: &mut Mapping, path: &str) -> Option<Value> {
+    let Some((head, rest)) = path.split_once('.') else {
+        return mapping.remove(path);
+    };
+    let child = mapping.get_mut(head)?.as_mapping_mut()?;
+    let value = take_path(child, rest);
+    if child.is_empty() {
+        mapping.remove(head);
+    }
+    value
+}
+
+pub(crate) fn set_path(mapping: &mut Mapping, path: &str, value: Value) {
+    let Some((head, rest)) = path.split_once('.') else {
+        mapping.insert(path.into(), value);
+        return;
+    };
+    let child = mapping
+        .entry(head.into())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if !child.is_mapping() {
+        *child = Value::Mapping(Mapping::new());
+    }
+    if let Value::Mapping(child) = child {
+        set_path(child, rest, value);
+    }
+}
+
+fn config_error(message: &str) -> anyhow::Error {
+    OmniError::ConfigurationError {
+        message: message.to_string(),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unversioned_file_migrates() {
+        let mut value: Value = serde_yaml::from_str(
+            r#"
+general:
+  confirm_installs: false
+logging:
+  level: debug
+ui:
+  theme: light
+"#,
+        )
+        .unwrap();
+
+        let report = migrate(&mut value).unwrap().unwrap();
+        assert_eq!((report.from, report.to), (0, CURRENT_VERSION));
+        assert!(unknown_key_problems(&value).is_empty());
+
+        let config: OmniConfig = serde_yaml::from_value(value.clone()).unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert!(!config.general.confirm_installs);
+        assert_eq!(config.general.log_level, "debug");
+        assert_eq!(config.ui.gui_theme, "light");
+        assert!(config.boxes.preferred_order.contains(&"apt".to_string()));
+
+        assert_eq!(migrate(&mut value).unwrap(), None);
+    }
+
+    #[test]
+    fn test_unknown_and_renamed_keys_are_reported() {
+        let mut value = serde_yaml::to_value(OmniConfig::default()).unwrap();
+        let general = value["general"].as_mapping_mut().unwrap();
+        general.insert("log_levle".into(), "debug".into());
+        let ui = value["ui"].as_mapping_mut().unwrap();
+        ui.insert("theme".into(), "light".into());
+        value
+            .as_mapping_mut()
+            .unwrap()
+            .insert("repositories".into(), Value::Sequence(Vec::new()));
+
+        let problems = unknown_key_problems(&value);
+        assert!(problems.contains(&(
+            "general.log_levle".to_string(),
+            "is not a setting; did you mean `general.log_level`?".to_string()
+        )));
+        assert!(problems.contains(&(
+            "ui.theme".to_string(),
+            "was renamed to `ui.gui_theme`".to_string()
+        )));
+        assert!(problems.contains(&(
+            "repositories".to_string(),
+            "is not a setting".to_string()
+        )));
+
+        let headers: Value =
+            serde_yaml::from_str("telemetry:\n  headers:\n    x-api-key: secret\n").unwrap();
+        assert!(unknown_key_problems(&headers).is_empty());
+    }
+
+    #[test]
+    fn test_newer_version_is_refused() {
+        let mut value: Value = serde_yaml::from_str("version: 99").unwrap();
+        let message = migrate(&mut value).unwrap_err().to_string();
+        assert!(message.contains("version 99"));
+    }
+}
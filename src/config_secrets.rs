@@ -0,0 +1,257 @@
+//! Encrypted values in config files, so a config with tokens and passwords can
+//! be committed to a dotfiles repository. `omni config encrypt` turns a secret
+//! into `enc:v1:<base64>`: AES-256-GCM under a key kept in the system keyring.
+//! omni decrypts these values when it loads the config and writes them back
+//! encrypted.
+
+use crate::credentials;
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_yaml::Value;
+
+/// Start of an encrypted config value
+pub const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+/// Keyring service holding the config key
+pub const KEY_SERVICE: &str = "omni-config-key";
+const KEY_ACCOUNT: &str = "default";
+
+/// The key in base64, for machines without a keyring such as CI runners
+pub const KEY_ENV: &str = "OMNI_CONFIG_KEY";
+
+const KEY_LEN: usize = 32;
+
+/// Key that encrypts and decrypts config values
+pub struct ConfigKey([u8; KEY_LEN]);
+
+impl ConfigKey {
+    pub fn generate() -> Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| anyhow!("No randomness available for a config key"))?;
+        Ok(Self(key))
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        BASE64_STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .and_then(|bytes| <[u8; KEY_LEN]>::try_from(bytes).ok())
+            .map(Self)
+            .ok_or_else(|| anyhow!("A config key is {} bytes in base64", KEY_LEN))
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64_STANDARD.encode(self.0)
+    }
+
+    /// The key from `OMNI_CONFIG_KEY` or the keyring, if there is one
+    pub fn load() -> Result<Option<Self>> {
+        if let Ok(encoded) = std::env::var(KEY_ENV) {
+            return Self::from_base64(&encoded)
+                .map(Some)
+                .map_err(|e| anyhow!("{}: {}", KEY_ENV, e));
+        }
+        credentials::keyring_get(KEY_SERVICE, KEY_ACCOUNT)
+            .map(|encoded| Self::from_base64(&encoded))
+            .transpose()
+    }
+
+    /// The stored key, created and put in the keyring on first use
+    pub fn load_or_create() -> Result<Self> {
+        if let Some(key) = Self::load()? {
+            return Ok(key);
+        }
+        let key = Self::generate()?;
+        key.store()?;
+        Ok(key)
+    }
+
+    /// Keep the key in the keyring, replacing the one there
+    pub fn store(&self) -> Result<()> {
+        credentials::keyring_set(KEY_SERVICE, KEY_ACCOUNT, &self.to_base64())
+    }
+
+    /// `plaintext` as an `enc:v1:` value, with a fresh nonce each time
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("No randomness available for encrypting"))?;
+
+        let mut sealed = plaintext.as_bytes().to_vec();
+        self.aead()
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("Encrypting failed"))?;
+
+        let mut encoded = nonce.to_vec();
+        encoded.extend(sealed);
+        Ok(format!(
+            "{}{}",
+            ENCRYPTED_PREFIX,
+            BASE64_STANDARD.encode(encoded)
+        ))
+    }
+
+    /// The plaintext of an `enc:v1:` value
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        let encoded = value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .ok_or_else(|| anyhow!("Not an encrypted value"))?;
+        let bytes = BASE64_STANDARD
+            .decode(encoded)
+            .map_err(|_| anyhow!("An encrypted value is not valid base64"))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(anyhow!("An encrypted value is cut short"));
+        }
+
+        let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+        let nonce =
+            Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("Invalid nonce"))?;
+        let mut sealed = sealed.to_vec();
+        let plaintext = self
+            .aead()
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .map_err(|_| {
+                anyhow!(
+                    "Cannot decrypt a config value: it was encrypted with another key. \
+                     Import that key with 'omni config key --import' or set {}",
+                    KEY_ENV
+                )
+            })?;
+        String::from_utf8(plaintext.to_vec())
+            .map_err(|_| anyhow!("A decrypted config value is not text"))
+    }
+
+    fn aead(&self) -> LessSafeKey {
+        LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM, &self.0).expect("AES-256 takes a 32-byte key"),
+        )
+    }
+}
+
+/// Values of a loaded config that were encrypted, with their encrypted form,
+/// so writing the config encrypts them again
+#[derive(Clone, Default)]
+pub struct Decrypted(Vec<(String, String)>);
+
+impl std::fmt::Debug for Decrypted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Decrypted({} values)", self.0.len())
+    }
+}
+
+impl Decrypted {
+    pub fn extend(&mut self, other: Decrypted) {
+        self.0.extend(other.0);
+    }
+
+    /// Put the encrypted form back wherever a decrypted value is in `value`
+    pub fn seal(&self, value: &mut Value) {
+        if self.0.is_empty() {
+            return;
+        }
+        let _ = visit_strings(value, &mut |text| {
+            if let Some((_, encrypted)) = self.0.iter().find(|(plain, _)| plain == text) {
+                *text = encrypted.clone();
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Replace every `enc:v1:` string in `value` with its plaintext. The key is
+/// only looked up when there is something to decrypt.
+pub fn decrypt_values(value: &mut Value) -> Result<Decrypted> {
+    let mut encrypted = false;
+    visit_strings(value, &mut |text| {
+        encrypted |= text.starts_with(ENCRYPTED_PREFIX);
+        Ok(())
+    })?;
+    if !encrypted {
+        return Ok(Decrypted::default());
+    }
+
+    let key = ConfigKey::load()?.ok_or_else(|| {
+        anyhow!(
+            "The config has encrypted values but there is no config key in the keyring; \
+             import it with 'omni config key --import' or set {}",
+            KEY_ENV
+        )
+    })?;
+    decrypt_with(value, &key)
+}
+
+fn decrypt_with(value: &mut Value, key: &ConfigKey) -> Result<Decrypted> {
+    let mut decrypted = Vec::new();
+    visit_strings(value, &mut |text| {
+        if text.starts_with(ENCRYPTED_PREFIX) {
+            let plain = key.decrypt(text)?;
+            decrypted.push((plain.clone(), std::mem::replace(text, plain)));
+        }
+        Ok(())
+    })?;
+    Ok(Decrypted(decrypted))
+}
+
+fn visit_strings(value: &mut Value, f: &mut dyn FnMut(&mut String) -> Result<()>) -> Result<()> {
+    match value {
+        Value::String(text) => f(text),
+        Value::Sequence(items) => items.iter_mut().try_for_each(|item| visit_strings(item, f)),
+        Value::Mapping(mapping) => mapping
+            .iter_mut()
+            .try_for_each(|(_, item)| visit_strings(item, f)),
+        Value::Tagged(tagged) => visit_strings(&mut tagged.value, f),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let key = ConfigKey::generate().unwrap();
+        let encrypted = key.encrypt("hunter2").unwrap();
+        assert!(encrypted.starts_with(ENCRYPTED_PREFIX));
+        assert_ne!(encrypted, key.encrypt("hunter2").unwrap());
+        assert_eq!(key.decrypt(&encrypted).unwrap(), "hunter2");
+
+        let other = ConfigKey::generate().unwrap();
+        assert!(other.decrypt(&encrypted).is_err());
+        let copy = ConfigKey::from_base64(&key.to_base64()).unwrap();
+        assert_eq!(copy.decrypt(&encrypted).unwrap(), "hunter2");
+    }
+
+    #[test]
+    fn test_decrypt_and_seal() {
+        let key = ConfigKey::generate().unwrap();
+        let token = key.encrypt("s3cret").unwrap();
+        let mut value: Value = serde_yaml::from_str(&format!(
+            "notifications:\n  webhooks:\n    - url: https://hooks.example.com\n      token: \"{}\"\n",
+            token
+        ))
+        .unwrap();
+
+        let decrypted = decrypt_with(&mut value, &key).unwrap();
+        assert_eq!(
+            value["notifications"]["webhooks"][0]["token"],
+            Value::from("s3cret")
+        );
+
+        decrypted.seal(&mut value);
+        assert_eq!(
+            value["notifications"]["webhooks"][0]["token"],
+            Value::from(token)
+        );
+        assert_eq!(format!("{:?}", decrypted), "Decrypted(1 values)");
+    }
+}
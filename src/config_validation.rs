@@ -0,0 +1,247 @@
+//! Strict checks of config files on load and for `omni config validate`:
+//! unknown keys, values of the wrong type and values out of range, each
+//! reported with the YAML path it is at.
+
+use crate::config::OmniConfig;
+use crate::config_migration;
+use crate::error_handling::OmniError;
+use anyhow::Result;
+use serde_yaml::{Mapping, Value};
+
+const LOG_LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+const GUI_THEMES: &[&str] = &["dark", "light", "auto"];
+
+/// Every problem in a config value, as `ValidationError`s whose field is the
+/// dotted YAML path, e.g. `general.log_level`
+pub fn validate(value: &Value) -> Vec<OmniError> {
+    let Value::Mapping(mapping) = value else {
+        return vec![problem("", "the config file is not a YAML mapping")];
+    };
+    let Ok(Value::Mapping(defaults)) = serde_yaml::to_value(OmniConfig::default()) else {
+        return Vec::new();
+    };
+
+    let mut problems: Vec<OmniError> = config_migration::unknown_key_problems(value)
+        .into_iter()
+        .map(|(path, message)| problem(&path, &message))
+        .collect();
+    let before = problems.len();
+    type_problems(mapping, &defaults, "", &mut problems);
+    if problems.len() > before {
+        // Ranges need a config that parses
+        return problems;
+    }
+
+    match serde_yaml::from_value::<OmniConfig>(value.clone()) {
+        Ok(config) => problems.extend(range_problems(&config)),
+        Err(e) => problems.push(problem("", &e.to_string())),
+    }
+    problems
+}
+
+/// Fail with every problem in `value`, read from `source`
+pub fn check(value: &Value, source: &str) -> Result<()> {
+    let problems = validate(value);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(OmniError::ConfigurationError {
+        message: format!(
+            "{} is not valid:\n  {}",
+            source,
+            problems
+                .iter()
+                .map(describe)
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        ),
+    }
+    .into())
+}
+
+/// `path: message` for a problem from [`validate`]
+pub fn describe(problem: &OmniError) -> String {
+    match problem {
+        OmniError::ValidationError { field, message } if field.is_empty() => message.clone(),
+        OmniError::ValidationError { field, message } => format!("{}: {}", field, message),
+        other => other.to_string(),
+    }
+}
+
+/// Find the values serde rejects by putting each one into the defaults on its
+/// own, descending into sections so the path is as exact as it can be. A
+/// section that only fails as a whole is missing a required key.
+fn type_problems(
+    mapping: &Mapping,
+    defaults: &Mapping,
+    prefix: &str,
+    problems: &mut Vec<OmniError>,
+) {
+    for (key, value) in mapping {
+        let Some(name) = key.as_str() else {
+            continue;
+        };
+        let path = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        // Unknown keys are reported on their own
+        let Some(default) = config_migration::get_path(defaults, &path) else {
+            continue;
+        };
+
+        let mut candidate = defaults.clone();
+        config_migration::set_path(&mut candidate, &path, value.clone());
+        let Err(e) = serde_yaml::from_value::<OmniConfig>(Value::Mapping(candidate)) else {
+            continue;
+        };
+
+        let before = problems.len();
+        if let (Value::Mapping(child), Value::Mapping(default_child)) = (value, default) {
+            if !default_child.is_empty() {
+                type_problems(child, defaults, &path, problems);
+            }
+        }
+        if problems.len() == before {
+            problems.push(problem(&path, &e.to_string()));
+        }
+    }
+}
+
+/// Values that parse but make no sense
+fn range_problems(config: &OmniConfig) -> Vec<OmniError> {
+    let mut problems = Vec::new();
+
+    if !(1..=16).contains(&config.general.max_parallel_jobs) {
+        problems.push(problem(
+            "general.max_parallel_jobs",
+            &format!(
+                "{} is out of range; use 1 to 16",
+                config.general.max_parallel_jobs
+            ),
+        ));
+    }
+    if !LOG_LEVELS.contains(&config.general.log_level.as_str()) {
+        problems.push(problem(
+            "general.log_level",
+            &format!(
+                "`{}` is not a log level; use one of {}",
+                config.general.log_level,
+                LOG_LEVELS.join(", ")
+            ),
+        ));
+    }
+    if config.boxes.preferred_order.is_empty() {
+        problems.push(problem(
+            "boxes.preferred_order",
+            "is empty; list at least one box, e.g. [apt]",
+        ));
+    }
+    if !GUI_THEMES.contains(&config.ui.gui_theme.as_str()) {
+        problems.push(problem(
+            "ui.gui_theme",
+            &format!(
+                "`{}` is not a theme; use one of {}",
+                config.ui.gui_theme,
+                GUI_THEMES.join(", ")
+            ),
+        ));
+    }
+    if config.daemon.check_interval_minutes == 0 {
+        problems.push(problem(
+            "daemon.check_interval_minutes",
+            "must be at least 1",
+        ));
+    }
+    if let Err(e) = config.files.umask_bits() {
+        problems.push(problem("files.umask", &e.to_string()));
+    }
+    if config.snapshots.retention.keep == Some(0) {
+        problems.push(problem(
+            "snapshots.retention.keep",
+            "must be at least 1; set it to null to keep every automatic snapshot",
+        ));
+    }
+    if config.logging.max_file_size_mb == Some(0) {
+        problems.push(problem(
+            "logging.max_file_size_mb",
+            "must be at least 1; leave it out to rotate by time only",
+        ));
+    }
+
+    problems
+}
+
+fn problem(field: &str, message: &str) -> OmniError {
+    OmniError::ValidationError {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(yaml: &str) -> Vec<String> {
+        let mut value = serde_yaml::to_value(OmniConfig::default()).unwrap();
+        let overlay: Value = serde_yaml::from_str(yaml).unwrap();
+        crate::project::merge(&mut value, overlay);
+        validate(&value)
+            .iter()
+            .map(|p| match p {
+                OmniError::ValidationError { field, .. } => field.clone(),
+                other => other.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_defaults_are_valid() {
+        let value = serde_yaml::to_value(OmniConfig::default()).unwrap();
+        assert!(validate(&value).is_empty());
+        assert!(check(&value, "config.yaml").is_ok());
+    }
+
+    #[test]
+    fn test_wrong_types_name_the_path() {
+        assert_eq!(
+            fields("general:\n  auto_update: sometimes\ndaemon:\n  maintenance:\n    large_transaction: many\n"),
+            vec!["general.auto_update", "daemon.maintenance.large_transaction"]
+        );
+        assert_eq!(
+            fields("daemon:\n  auto_install: always\n"),
+            vec!["daemon.auto_install"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_and_ranges() {
+        assert_eq!(
+            fields("general:\n  log_levle: debug\n"),
+            vec!["general.log_levle"]
+        );
+        assert_eq!(
+            fields("general:\n  max_parallel_jobs: 64\n  log_level: verbose\nfiles:\n  umask: \"999\"\n"),
+            vec!["general.max_parallel_jobs", "general.log_level", "files.umask"]
+        );
+
+        let value: Value = serde_yaml::from_str("general:\n  log_level: loud\n").unwrap();
+        let message = check(&value, "config.yaml").unwrap_err().to_string();
+        assert!(message.contains("config.yaml is not valid"));
+    }
+
+    #[test]
+    fn test_missing_key_names_the_section() {
+        let mut value = serde_yaml::to_value(OmniConfig::default()).unwrap();
+        value["general"]
+            .as_mapping_mut()
+            .unwrap()
+            .remove("auto_update");
+        let problems = validate(&value);
+        assert_eq!(problems.len(), 1);
+        assert!(describe(&problems[0]).starts_with("general: "));
+        assert!(describe(&problems[0]).contains("auto_update"));
+    }
+}
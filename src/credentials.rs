@@ -0,0 +1,342 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info};
+
+/// Keyring service under which per-host sudo passwords are stored
+pub const SUDO_KEYRING_SERVICE: &str = "omni-sudo";
+
+/// Keyring service under which per-host WinRM login passwords are stored
+pub const WINRM_KEYRING_SERVICE: &str = "omni-winrm";
+
+/// Keyring service for secrets stored with `omni credentials set`
+pub const CREDENTIALS_KEYRING_SERVICE: &str = "omni";
+
+/// Config values written as `keyring:<name>` are read from the keyring
+pub const KEYRING_PREFIX: &str = "keyring:";
+
+/// Loads the Windows password vault (Credential Manager) into `$vault`
+const WINDOWS_VAULT: &str = "[void][Windows.Security.Credentials.PasswordVault,Windows.Security.Credentials,ContentType=WindowsRuntime]; \
+$vault = New-Object Windows.Security.Credentials.PasswordVault; ";
+
+const MASK: &str = "********";
+
+/// Look up a secret in the system keyring (Secret Service on Linux, Keychain on
+/// macOS, Credential Manager on Windows)
+pub fn keyring_get(service: &str, account: &str) -> Option<String> {
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["find-generic-password", "-s", service, "-a", account, "-w"])
+            .output()
+    } else if cfg!(windows) {
+        windows_vault(
+            "$c = $vault.Retrieve($env:OMNI_KEYRING_SERVICE, $env:OMNI_KEYRING_ACCOUNT); \
+             $c.RetrievePassword(); [Console]::Out.Write($c.Password)",
+            service,
+            account,
+        )
+        .output()
+    } else {
+        Command::new("secret-tool")
+            .args(["lookup", "service", service, "account", account])
+            .output()
+    }
+    .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let secret = String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches(['\r', '\n'])
+        .to_string();
+    (!secret.is_empty()).then_some(secret)
+}
+
+/// Store a secret in the system keyring. The secret is written to the tool's stdin,
+/// never passed on its command line.
+pub fn keyring_set(service: &str, account: &str, secret: &str) -> Result<()> {
+    crate::config::ensure_writable("storing a password in the keyring")?;
+    if cfg!(target_os = "macos") {
+        // `security -i` reads commands from stdin, which keeps -w off the command line
+        if secret.contains(['\r', '\n']) {
+            return Err(anyhow!("The Keychain cannot store secrets that span lines"));
+        }
+        let command = format!(
+            "add-generic-password -U -s {} -a {} -w {}\n",
+            security_quote(service),
+            security_quote(account),
+            security_quote(secret)
+        );
+        return run_with_input(Command::new("security").arg("-i"), &command, "security");
+    }
+    if cfg!(windows) {
+        let mut command = windows_vault(
+            "$secret = [Console]::In.ReadToEnd(); \
+             $vault.Add((New-Object Windows.Security.Credentials.PasswordCredential(\
+             $env:OMNI_KEYRING_SERVICE, $env:OMNI_KEYRING_ACCOUNT, $secret)))",
+            service,
+            account,
+        );
+        return run_with_input(&mut command, secret, "PowerShell");
+    }
+
+    let label = format!("{} password for {}", service, account);
+    run_with_input(
+        Command::new("secret-tool").args([
+            "store", "--label", &label, "service", service, "account", account,
+        ]),
+        secret,
+        "secret-tool",
+    )
+}
+
+/// Delete a secret from the system keyring; `false` when there was none
+pub fn keyring_delete(service: &str, account: &str) -> Result<bool> {
+    crate::config::ensure_writable("removing a password from the keyring")?;
+    if keyring_get(service, account).is_none() {
+        return Ok(false);
+    }
+
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["delete-generic-password", "-s", service, "-a", account])
+            .output()?
+    } else if cfg!(windows) {
+        windows_vault(
+            "$vault.Remove($vault.Retrieve($env:OMNI_KEYRING_SERVICE, $env:OMNI_KEYRING_ACCOUNT))",
+            service,
+            account,
+        )
+        .output()?
+    } else {
+        Command::new("secret-tool")
+            .args(["clear", "service", service, "account", account])
+            .output()?
+    };
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to remove secret: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(true)
+}
+
+/// `value`, or the secret stored with `omni credentials set` that it names when
+/// it is `keyring:<name>`
+pub fn resolve(value: &str) -> Result<String> {
+    match value.strip_prefix(KEYRING_PREFIX) {
+        None => Ok(value.to_string()),
+        Some(name) => keyring_get(CREDENTIALS_KEYRING_SERVICE, name).ok_or_else(|| {
+            anyhow!(
+                "No credential named {} in the keyring; store it with 'omni credentials set {}'",
+                name,
+                name
+            )
+        }),
+    }
+}
+
+/// Run `command` with `input` on its stdin
+fn run_with_input(command: &mut Command, input: &str, tool: &str) -> Result<()> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("{} is not available: {}", tool, e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Failed to store secret: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// PowerShell running `script` against the Windows password vault; the service
+/// and account go through the environment so they need no quoting
+fn windows_vault(script: &str, service: &str, account: &str) -> Command {
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command"])
+        .arg(format!("{}{}", WINDOWS_VAULT, script))
+        .env("OMNI_KEYRING_SERVICE", service)
+        .env("OMNI_KEYRING_ACCOUNT", account);
+    command
+}
+
+/// Double-quote a word for a `security -i` command line
+fn security_quote(word: &str) -> String {
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Per-run cache of remote host passwords for one keyring service, shared between clones.
+///
+/// Each host's password is taken from the keyring or asked for once; the lock is
+/// held while prompting so concurrent operations on the same host never prompt twice.
+#[derive(Clone)]
+pub struct HostPasswords {
+    cache: Arc<Mutex<HashMap<String, String>>>,
+    interactive: bool,
+    service: &'static str,
+    /// What the password is for, as shown in prompts ("sudo", "WinRM")
+    purpose: &'static str,
+    /// `omni fleet` subcommand that stores this kind of password
+    store_command: &'static str,
+}
+
+impl HostPasswords {
+    /// Passwords sudo asks for on Unix hosts
+    pub fn sudo() -> Self {
+        Self::for_service(SUDO_KEYRING_SERVICE, "sudo", "sudo-password")
+    }
+
+    /// Login passwords for WinRM hosts
+    pub fn winrm() -> Self {
+        Self::for_service(WINRM_KEYRING_SERVICE, "WinRM", "winrm-password")
+    }
+
+    fn for_service(
+        service: &'static str,
+        purpose: &'static str,
+        store_command: &'static str,
+    ) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            interactive: std::io::stdin().is_terminal(),
+            service,
+            purpose,
+            store_command,
+        }
+    }
+
+    pub fn with_interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Password for `host`, from this run's cache, the keyring or a prompt
+    pub fn for_host(&self, host: &str) -> Result<String> {
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|_| anyhow!("{} password cache is poisoned", self.purpose))?;
+
+        if let Some(password) = cache.get(host) {
+            return Ok(password.clone());
+        }
+
+        let password = match keyring_get(self.service, host) {
+            Some(password) => {
+                debug!("Using {} password for {} from the keyring", self.purpose, host);
+                password
+            }
+            None if self.interactive => dialoguer::Password::new()
+                .with_prompt(format!("{} password for {}", self.purpose, host))
+                .interact()
+                .map_err(|e| anyhow!("Password prompt failed: {}", e))?,
+            None => {
+                return Err(anyhow!(
+                    "No {} password for {} in the keyring and no terminal to ask; run 'omni fleet {} {}'",
+                    self.purpose,
+                    host,
+                    self.store_command,
+                    host
+                ))
+            }
+        };
+
+        cache.insert(host.to_string(), password.clone());
+        info!("{} password for {} cached for this run", self.purpose, host);
+        Ok(password)
+    }
+
+    pub fn insert(&self, host: &str, password: &str) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.insert(host.to_string(), password.to_string());
+        }
+    }
+
+    /// Replace every cached password in `text` with a mask
+    pub fn mask(&self, text: &str) -> String {
+        match self.cache.lock() {
+            Ok(cache) => {
+                let secrets: Vec<&str> = cache.values().map(|s| s.as_str()).collect();
+                mask_secrets(text, &secrets)
+            }
+            Err(_) => text.to_string(),
+        }
+    }
+}
+
+pub fn mask_secrets(text: &str, secrets: &[&str]) -> String {
+    secrets
+        .iter()
+        .filter(|s| !s.is_empty())
+        .fold(text.to_string(), |masked, secret| {
+            masked.replace(secret, MASK)
+        })
+}
+
+/// Wrap a shell command in sudo. With a password, sudo reads it from stdin (`-S`)
+/// without printing a prompt, so the password never appears in the command line.
+pub fn sudo_wrap(command: &str, with_password: bool) -> String {
+    let escaped = command.replace('\'', r"'\''");
+    if with_password {
+        format!("sudo -S -p '' sh -c '{}'", escaped)
+    } else {
+        format!("sudo sh -c '{}'", escaped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_secrets() {
+        assert_eq!(
+            mask_secrets("auth failed for hunter2", &["hunter2", ""]),
+            "auth failed for ********"
+        );
+
+        let passwords = HostPasswords::sudo().with_interactive(false);
+        passwords.insert("web1", "s3cret");
+        assert_eq!(passwords.for_host("web1").unwrap(), "s3cret");
+        assert_eq!(passwords.mask("echo s3cret"), "echo ********");
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("Bearer abc").unwrap(), "Bearer abc");
+        let missing = resolve("keyring:omni-test-no-such-credential").unwrap_err();
+        assert!(missing
+            .to_string()
+            .contains("omni credentials set omni-test-no-such-credential"));
+        assert_eq!(security_quote(r#"a"b\c"#), r#""a\"b\\c""#);
+    }
+
+    #[test]
+    fn test_sudo_wrap() {
+        assert_eq!(
+            sudo_wrap("apt update && apt install -y curl", true),
+            "sudo -S -p '' sh -c 'apt update && apt install -y curl'"
+        );
+        assert_eq!(
+            sudo_wrap("echo 'hi'", false),
+            r"sudo sh -c 'echo '\''hi'\'''"
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use crate::audit::AuditSeverity;
+use crate::config::{
+    self, DaemonConfig, DigestConfig, EmailAlertConfig, NotifyCategory, OmniConfig,
+    ReleaseSupportConfig, SearchConfig,
+};
+use crate::database::Database;
+use crate::digest::{self, Digest};
+use crate::lifecycle::{self, Support};
+use crate::lock::{self, OperationLock};
+use crate::maintenance::{self, MaintenanceWindows, QueuedTransaction};
+use crate::notifications::{Dispatcher, Notification};
+use crate::search::SearchEngine;
+use crate::updater::{DeferredUpdate, UpdateCandidate, UpdateManager};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// An update found by the daemon's last check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingUpdate {
+    pub package_name: String,
+    pub box_type: String,
+    pub current_version: Option<String>,
+    pub available_version: Option<String>,
+    pub security: bool,
+}
+
+impl PendingUpdate {
+    pub fn new(candidate: &UpdateCandidate, security: bool) -> Self {
+        Self {
+            package_name: candidate.package_name.clone(),
+            box_type: candidate.box_type.clone(),
+            current_version: candidate.current_version.clone(),
+            available_version: candidate.available_version.clone(),
+            security,
+        }
+    }
+}
+
+/// What the daemon did last, persisted for `omni status`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub started_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_check: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub next_check: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub available_updates: Vec<PendingUpdate>,
+    /// Available updates were downloaded into the package caches
+    #[serde(default)]
+    pub downloaded: bool,
+    /// Updates installed by the last check, following `auto_install`
+    #[serde(default)]
+    pub installed: Vec<PendingUpdate>,
+    /// Updates `policies.unattended` kept the daemon from installing
+    #[serde(default)]
+    pub deferred: Vec<DeferredUpdate>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// When the last digest was mailed, or the clock for the first one started
+    #[serde(default)]
+    pub last_digest: Option<DateTime<Utc>>,
+}
+
+impl DaemonStatus {
+    fn new() -> Self {
+        Self {
+            pid: std::process::id(),
+            started_at: Utc::now(),
+            last_check: None,
+            next_check: None,
+            available_updates: Vec::new(),
+            downloaded: false,
+            installed: Vec::new(),
+            deferred: Vec::new(),
+            last_error: None,
+            last_digest: None,
+        }
+    }
+}
+
+pub fn status_path() -> Result<PathBuf> {
+    Ok(OmniConfig::state_dir()?.join("daemon.json"))
+}
+
+/// Status written by the most recent daemon run, if any
+pub fn load_status() -> Result<Option<DaemonStatus>> {
+    let path = status_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(&path)?)?))
+}
+
+fn save_status(status: &DaemonStatus) -> Result<()> {
+    let path = status_path()?;
+    if let Some(parent) = path.parent() {
+        config::ensure_private_dir(parent)?;
+    }
+    config::write_private_file(&path, serde_json::to_string_pretty(status)?.as_bytes())
+}
+
+/// PID of the running daemon, if one is running for this user
+pub fn running_pid() -> Option<u32> {
+    lock::daemon_pid()
+}
+
+/// Check for updates every `check_interval_minutes` until stopped with Ctrl-C or
+/// SIGTERM, or once with `once`
+pub async fn run(config: OmniConfig, once: bool) -> Result<()> {
+    let _instance = OperationLock::try_acquire_daemon()?.ok_or_else(|| {
+        anyhow!(
+            "The omni daemon is already running (pid {})",
+            running_pid().map_or_else(|| "unknown".to_string(), |pid| pid.to_string())
+        )
+    })?;
+
+    let settings = config.daemon.clone();
+    let release_support = config.release_support.clone();
+    let search = config.search.clone();
+    let dispatcher = Dispatcher::from_config(&config);
+    let email = config.audit.email.clone();
+    let windows = MaintenanceWindows::from_config(&settings.maintenance)?;
+    let interval = Duration::from_secs(settings.check_interval_minutes.max(1) * 60);
+    let manager = UpdateManager::new(config).await?;
+    let mut status = DaemonStatus::new();
+    // Digests are due a period after the last one, across restarts
+    status.last_digest = load_status().ok().flatten().and_then(|s| s.last_digest);
+    info!(
+        "omni daemon started: checking every {} minutes, auto-install {:?}",
+        settings.check_interval_minutes, settings.auto_install
+    );
+
+    loop {
+        status.last_check = Some(Utc::now());
+        let previous = status.available_updates.clone();
+        match run_cycle(&manager, &settings, &release_support, &windows, &mut status).await {
+            Ok(()) => status.last_error = None,
+            Err(e) => {
+                error!("Update check failed: {}", e);
+                status.last_error = Some(e.to_string());
+            }
+        }
+        announce(&dispatcher, &previous, &status).await;
+        if let Ok(db) = Database::new().await {
+            digest::sample_disk_usage(&db).await;
+        }
+        send_digest(&settings.digest, &email, &mut status).await;
+        refresh_search_index(&search).await;
+
+        let wait = next_wait(interval, &windows);
+        status.next_check =
+            (!once).then(|| Utc::now() + ChronoDuration::from_std(wait).unwrap_or_default());
+        save_status(&status)?;
+
+        if once || wait_or_stop(wait).await {
+            break;
+        }
+    }
+
+    status.next_check = None;
+    save_status(&status)?;
+    info!("omni daemon stopped");
+    Ok(())
+}
+
+/// Read backends' catalogs that are missing from the search index or too old
+/// into it, unless another process is refreshing it already
+async fn refresh_search_index(settings: &SearchConfig) {
+    if settings.index_max_age_hours == 0 {
+        return;
+    }
+    let Ok(Some(_lock)) = OperationLock::try_acquire_index() else {
+        return;
+    };
+    let result = async {
+        let engine = SearchEngine::new().await?;
+        let stale: Vec<String> = engine
+            .stale_index_boxes(settings.index_max_age_hours)
+            .await?
+            .into_iter()
+            .map(|(box_type, _)| box_type)
+            .collect();
+        if !stale.is_empty() {
+            engine.refresh_index(&stale).await?;
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        warn!("Could not refresh the search index: {}", e);
+    }
+}
+
+/// Time until the next check: the interval, or sooner when queued updates
+/// wait for a maintenance window that opens before then
+fn next_wait(interval: Duration, windows: &MaintenanceWindows) -> Duration {
+    let queued = maintenance::load_queue().is_ok_and(|queue| !queue.is_empty());
+    let now = maintenance::local_now();
+    if !queued || windows.is_open(now) {
+        return interval;
+    }
+    windows
+        .next_open(now)
+        .and_then(|open| (open - now).to_std().ok())
+        .map_or(interval, |until_open| {
+            until_open.clamp(Duration::from_secs(60), interval)
+        })
+}
+
+/// Sleep for `interval`; true when a stop signal arrived first
+async fn wait_or_stop(interval: Duration) -> bool {
+    let mut terminate =
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(signal) => signal,
+            Err(e) => {
+                warn!("Cannot listen for SIGTERM: {}", e);
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => return false,
+                    _ = tokio::signal::ctrl_c() => return true,
+                }
+            }
+        };
+
+    tokio::select! {
+        _ = tokio::time::sleep(interval) => false,
+        _ = tokio::signal::ctrl_c() => true,
+        _ = terminate.recv() => true,
+    }
+}
+
+async fn run_cycle(
+    manager: &UpdateManager,
+    settings: &DaemonConfig,
+    release_support: &ReleaseSupportConfig,
+    windows: &MaintenanceWindows,
+    status: &mut DaemonStatus,
+) -> Result<()> {
+    let candidates = manager.check_updates().await?;
+    let plan = manager.plan_unattended(&candidates).await;
+    let security = |candidate: &UpdateCandidate| {
+        plan.updates
+            .iter()
+            .any(|(c, info)| same_update(c, candidate) && info.security)
+    };
+    let pending = |candidate: &UpdateCandidate| PendingUpdate::new(candidate, security(candidate));
+
+    status.available_updates = candidates.iter().map(pending).collect();
+    status.downloaded = false;
+    status.installed.clear();
+    status.deferred = plan.deferred.clone();
+    info!(
+        "{} updates available, {} deferred by policy",
+        candidates.len(),
+        status.deferred.len()
+    );
+
+    // Updates interactive commands queued for a maintenance window
+    let mut queue = maintenance::load_queue()?;
+    let mut install = plan.install.clone();
+    for candidate in &candidates {
+        let requested = queue
+            .iter()
+            .filter(|t| t.requested_by != maintenance::DAEMON)
+            .flat_map(|t| &t.updates)
+            .any(|u| u.package_name == candidate.package_name && u.box_type == candidate.box_type);
+        if !requested || install.iter().any(|c| same_update(c, candidate)) {
+            continue;
+        }
+        if manager.critical_major(candidate).await.is_some() {
+            info!(
+                "Not applying the queued update of critical package {}; it needs a confirmation",
+                candidate.package_name
+            );
+            continue;
+        }
+        install.push(candidate.clone());
+    }
+
+    let in_window = windows.is_open(maintenance::local_now());
+    if !in_window {
+        queue.retain(|t| t.requested_by != maintenance::DAEMON);
+        if !plan.install.is_empty() {
+            queue.push(QueuedTransaction {
+                queued_at: Utc::now(),
+                requested_by: maintenance::DAEMON.to_string(),
+                updates: plan.install.iter().map(pending).collect(),
+            });
+        }
+        maintenance::save_queue(&queue)?;
+        info!(
+            "Outside the maintenance window; {} updates wait for the next one",
+            install.len()
+        );
+        install.clear();
+    }
+
+    if !install.is_empty() && release_support.require_supported {
+        let ended = lifecycle::check(release_support)
+            .await
+            .filter(|status| matches!(status.support, Support::Ended { .. }));
+        if let Some(status) = ended {
+            warn!("{}; not installing updates", status.describe());
+            install.clear();
+        }
+    }
+
+    if candidates.is_empty() || (!settings.auto_download && install.is_empty()) {
+        if in_window {
+            // Whatever is still queued is no longer available
+            maintenance::save_queue(&[])?;
+        }
+        return Ok(());
+    }
+
+    // Leave the package managers to an interactive omni command that is running
+    let Some(_lock) = OperationLock::try_acquire()? else {
+        info!("Another omni process is busy; skipping downloads and installs this cycle");
+        return Ok(());
+    };
+
+    // Downloading ahead is fine outside a window; it changes nothing installed
+    if settings.auto_download {
+        manager.download_updates(&candidates)?;
+        status.downloaded = true;
+    }
+
+    if in_window {
+        // Nobody is there to choose a full upgrade, so pacman waits for one
+        if let Some(partial) = manager.partial_upgrade(&install)? {
+            info!(
+                "Not updating {} alone; that would be a partial upgrade",
+                partial.requested.join(", ")
+            );
+            install.retain(|c| c.box_type != "pacman");
+        }
+        if !install.is_empty() {
+            manager.update_candidates(&install).await?;
+            status.installed = install.iter().map(pending).collect();
+        }
+        maintenance::save_queue(&[])?;
+    }
+
+    Ok(())
+}
+
+/// Mail the digest once `daemon.digest.period` has passed since the last
+/// one; the first run only starts the clock, and failures retry next cycle
+async fn send_digest(settings: &DigestConfig, email: &EmailAlertConfig, status: &mut DaemonStatus) {
+    let Some(period) = settings.period else {
+        return;
+    };
+    let now = Utc::now();
+    let Some(last) = status.last_digest else {
+        status.last_digest = Some(now);
+        return;
+    };
+    if !digest::due(period, last, now) {
+        return;
+    }
+
+    let sent = async {
+        let db = Database::new().await?;
+        let digest = Digest::build(&db, period).await?;
+        digest.send_email(email, &settings.to).await
+    }
+    .await;
+    match sent {
+        Ok(()) => {
+            info!("Mailed the {} digest", period);
+            status.last_digest = Some(now);
+        }
+        Err(e) => warn!("Cannot send the {} digest: {}", period, e),
+    }
+}
+
+/// Tell the routed sinks, the desktop by default, about updates they have
+/// not heard of yet, and what the daemon installed
+async fn announce(dispatcher: &Dispatcher, previous: &[PendingUpdate], status: &DaemonStatus) {
+    let available = &status.available_updates;
+    if available.iter().any(|u| !previous.contains(u)) {
+        let security = available.iter().filter(|u| u.security).count();
+        let body = match security {
+            0 => "Run omni update to install them".to_string(),
+            n => format!(
+                "{} fix security issues; run omni update",
+                count(n, "update")
+            ),
+        };
+        let summary = format!("{} available", count(available.len(), "update"));
+        let severity = if security > 0 {
+            AuditSeverity::Warning
+        } else {
+            AuditSeverity::Info
+        };
+        dispatcher
+            .dispatch(&Notification::new(
+                NotifyCategory::Update,
+                severity,
+                summary,
+                body,
+            ))
+            .await;
+    }
+
+    if !status.installed.is_empty() {
+        let summary = format!("{} installed", count(status.installed.len(), "update"));
+        let names: Vec<&str> = status
+            .installed
+            .iter()
+            .map(|u| u.package_name.as_str())
+            .collect();
+        dispatcher
+            .dispatch(&Notification::new(
+                NotifyCategory::Update,
+                AuditSeverity::Info,
+                summary,
+                names.join(", "),
+            ))
+            .await;
+    }
+}
+
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
+
+fn same_update(a: &UpdateCandidate, b: &UpdateCandidate) -> bool {
+    a.package_name == b.package_name && a.box_type == b.box_type
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_tolerates_missing_fields() {
+        let status: DaemonStatus =
+            serde_json::from_str(r#"{"pid": 42, "started_at": "2024-05-01T10:00:00Z"}"#).unwrap();
+        assert_eq!(status.pid, 42);
+        assert!(status.available_updates.is_empty());
+        assert!(status.last_check.is_none());
+        assert!(status.last_digest.is_none());
+    }
+}
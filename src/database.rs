@@ -2,7 +2,12 @@ use crate::config::OmniConfig;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{Row, SqlitePool};
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow, SqliteSynchronous,
+};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+use std::path::Path;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -15,6 +20,15 @@ pub struct InstallRecord {
     pub install_path: Option<String>,
     pub installed_at: DateTime<Utc>,
     pub status: InstallStatus,
+    /// Repository the package was installed from (apt suite, dnf repo id, flatpak remote, ...)
+    #[serde(default)]
+    pub repository: Option<String>,
+    /// Mirror or remote URL the package was fetched from
+    #[serde(default)]
+    pub mirror_url: Option<String>,
+    /// Release channel requested at install time (`beta`, `nightly`, `insiders`, ...)
+    #[serde(default)]
+    pub channel: Option<String>,
     pub metadata: Option<String>,
 }
 
@@ -24,6 +38,37 @@ pub enum InstallStatus {
     Failed,
     Removed,
     Updated,
+    Downgraded,
+}
+
+impl InstallStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstallStatus::Success => "success",
+            InstallStatus::Failed => "failed",
+            InstallStatus::Removed => "removed",
+            InstallStatus::Updated => "updated",
+            InstallStatus::Downgraded => "downgraded",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "success" => Some(InstallStatus::Success),
+            "failed" => Some(InstallStatus::Failed),
+            "removed" => Some(InstallStatus::Removed),
+            "updated" => Some(InstallStatus::Updated),
+            "downgraded" => Some(InstallStatus::Downgraded),
+            _ => None,
+        }
+    }
+}
+
+/// Used space of a filesystem at one point in time
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DiskUsageSample {
+    pub recorded_at: DateTime<Utc>,
+    pub used_kib: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -33,6 +78,12 @@ pub struct Snapshot {
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
     pub packages: Vec<InstallRecord>,
+    /// Snapshot backend that took a filesystem snapshot with this one
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// How that backend finds its snapshot again
+    #[serde(default)]
+    pub backend_ref: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -45,6 +96,110 @@ pub struct PackageCache {
     pub cached_at: DateTime<Utc>,
 }
 
+/// A package a backend offers, as recorded in the local search index
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct IndexedPackage {
+    pub package_name: String,
+    pub box_type: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+/// When a backend's whole catalog was last read into the search index
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexSource {
+    pub box_type: String,
+    pub refreshed_at: DateTime<Utc>,
+    pub package_count: usize,
+}
+
+/// A package on the watchlist, with what was seen at the last check
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WatchedPackage {
+    pub package_name: String,
+    pub box_type: String,
+    pub last_version: Option<String>,
+    /// Advisory IDs (e.g. CVE numbers) already reported
+    pub known_advisories: Vec<String>,
+    pub added_at: DateTime<Utc>,
+    pub checked_at: Option<DateTime<Utc>>,
+}
+
+/// Whose signature vouched for an installed artifact
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    /// The backend checked its repository's signature
+    Repository,
+    /// omni checked the artifact's own signature
+    Verified,
+    /// Nothing checked a signature
+    Unverified,
+    /// A signature check failed and the artifact was installed anyway
+    Failed,
+}
+
+impl SignatureStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureStatus::Repository => "repository",
+            SignatureStatus::Verified => "verified",
+            SignatureStatus::Unverified => "unverified",
+            SignatureStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "repository" => SignatureStatus::Repository,
+            "verified" => SignatureStatus::Verified,
+            "failed" => SignatureStatus::Failed,
+            _ => SignatureStatus::Unverified,
+        }
+    }
+}
+
+/// What omni installed for a package: where from, its checksum and who signed it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ArtifactRecord {
+    pub package_name: String,
+    pub box_type: String,
+    /// Package version, or the git rev a source build was asked for
+    pub version: Option<String>,
+    /// Download URL, or the git repository of a source build
+    pub url: Option<String>,
+    /// Installed file, for artifacts omni writes itself (AppImages)
+    pub path: Option<String>,
+    /// `sha256:<hex>`, or `git:<commit>` for source builds
+    pub checksum: Option<String>,
+    pub signature: SignatureStatus,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// Where a key in omni's trusted keyring came from, for `omni keys refresh`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TrustedKey {
+    pub fingerprint: String,
+    /// URL or file the key was added from, or `keyserver`
+    pub source: String,
+    pub added_at: DateTime<Utc>,
+    pub refreshed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PinnedPackage {
+    pub package_name: String,
+    pub box_type: String,
+    /// Version that was installed when the pin was created
+    pub version: Option<String>,
+    pub reason: Option<String>,
+    pub pinned_at: DateTime<Utc>,
+    /// Versions updates may move to (`>=1.24,<2`); a pin without one holds
+    /// the package where it is
+    #[serde(default)]
+    pub constraint: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CacheStats {
     pub total_entries: usize,
@@ -68,21 +223,42 @@ pub struct Database {
 impl Database {
     pub async fn new() -> Result<Self> {
         let data_dir = OmniConfig::data_dir()?;
-        std::fs::create_dir_all(&data_dir)?;
-
-        let database_url = format!("sqlite:{}/omni.db", data_dir.display());
+        crate::config::ensure_private_dir(&data_dir)?;
+        Self::open(&data_dir.join("omni.db")).await
+    }
 
-        // Configure connection pool for optimal performance
-        let pool = sqlx::sqlite::SqlitePoolOptions::new()
-            .max_connections(10)
+    /// Open, or create, the database at `path` and bring its schema up to date
+    pub async fn open(path: &Path) -> Result<Self> {
+        let existed = path.exists();
+        // WAL lets readers such as the GUI and daemon run while a write is in
+        // progress; the pragmas are set on every connection the pool opens
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(Duration::from_secs(30))
+            .pragma("cache_size", "-10000") // 10MB
+            .pragma("mmap_size", "268435456") // 256MB
+            .pragma("temp_store", "MEMORY");
+
+        // SQLite has one writer at a time, so more connections only help readers
+        let max_connections = std::thread::available_parallelism()
+            .map_or(4, |n| n.get() as u32)
+            .clamp(2, 8);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
             .min_connections(1)
-            .max_lifetime(Some(std::time::Duration::from_secs(3600))) // 1 hour
-            .idle_timeout(Some(std::time::Duration::from_secs(600))) // 10 minutes
-            .test_before_acquire(true)
-            .connect(&database_url)
+            .acquire_timeout(Duration::from_secs(30))
+            .max_lifetime(Some(Duration::from_secs(3600))) // 1 hour
+            .idle_timeout(Some(Duration::from_secs(600))) // 10 minutes
+            .connect_with(options)
             .await?;
 
         let db = Database { pool };
+        if existed {
+            db.backup_before_migrating(path).await?;
+        }
         db.migrate().await?;
 
         Ok(db)
@@ -90,7 +266,7 @@ impl Database {
 
     /// Create an in-memory database for testing
     pub async fn new_in_memory() -> Result<Self> {
-        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        let pool = SqlitePoolOptions::new()
             .max_connections(1)
             .connect("sqlite::memory:")
             .await?;
@@ -101,197 +277,89 @@ impl Database {
         Ok(db)
     }
 
+    /// Bring the schema up to date with the numbered migrations in
+    /// `database_migration`
     async fn migrate(&self) -> Result<()> {
-        // Create tables with optimized schema
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS install_records (
-                id TEXT PRIMARY KEY,
-                package_name TEXT NOT NULL,
-                box_type TEXT NOT NULL,
-                version TEXT,
-                source_url TEXT,
-                install_path TEXT,
-                installed_at TEXT NOT NULL,
-                status TEXT NOT NULL,
-                metadata TEXT
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS snapshots (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                description TEXT,
-                created_at TEXT NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS snapshot_packages (
-                snapshot_id TEXT NOT NULL,
-                install_record_id TEXT NOT NULL,
-                FOREIGN KEY (snapshot_id) REFERENCES snapshots (id),
-                FOREIGN KEY (install_record_id) REFERENCES install_records (id)
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS package_cache (
-                package_name TEXT NOT NULL,
-                box_type TEXT NOT NULL,
-                version TEXT NOT NULL,
-                description TEXT,
-                dependencies TEXT,
-                cached_at TEXT NOT NULL,
-                expires_at TEXT,
-                hits INTEGER DEFAULT 0,
-                PRIMARY KEY (package_name, box_type)
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        // Create performance indexes
-        self.create_indexes().await?;
-
-        // Optimize database settings
-        self.optimize_database().await?;
-
+        crate::database_migration::migrate(&self.pool).await?;
         Ok(())
     }
 
-    async fn create_indexes(&self) -> Result<()> {
-        // Index for install_records queries
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_install_records_package_name ON install_records(package_name)")
-            .execute(&self.pool).await?;
-
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_install_records_box_type ON install_records(box_type)",
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_install_records_status ON install_records(status)",
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_install_records_installed_at ON install_records(installed_at)")
-            .execute(&self.pool).await?;
-
-        // Composite index for common queries
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_install_records_package_box ON install_records(package_name, box_type)")
-            .execute(&self.pool).await?;
-
-        // Index for snapshot queries
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_snapshots_created_at ON snapshots(created_at)")
-            .execute(&self.pool)
-            .await?;
-
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_snapshots_name ON snapshots(name)")
-            .execute(&self.pool)
-            .await?;
-
-        // Index for snapshot_packages
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_snapshot_packages_snapshot_id ON snapshot_packages(snapshot_id)")
-            .execute(&self.pool).await?;
-
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_snapshot_packages_record_id ON snapshot_packages(install_record_id)")
-            .execute(&self.pool).await?;
-
-        // Index for package_cache queries
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_package_cache_cached_at ON package_cache(cached_at)",
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_package_cache_expires_at ON package_cache(expires_at)",
-        )
+    /// Copy an existing database aside before migrations change it, so a
+    /// failed upgrade can be undone by hand
+    async fn backup_before_migrating(&self, path: &Path) -> Result<()> {
+        let pending = crate::database_migration::pending(&self.pool).await?;
+        let Some((version, _)) = pending.last() else {
+            return Ok(());
+        };
+        let backup = path.with_extension(format!("db.v{}.backup", version));
+        if backup.exists() {
+            return Ok(());
+        }
+        tracing::info!(
+            "Upgrading the database to schema version {}; backup at {}",
+            version,
+            backup.display()
+        );
+        sqlx::query(&format!(
+            "VACUUM INTO '{}'",
+            backup.display().to_string().replace('\'', "''")
+        ))
         .execute(&self.pool)
         .await?;
-
         Ok(())
     }
 
-    async fn optimize_database(&self) -> Result<()> {
-        // Enable WAL mode for better concurrency
-        sqlx::query("PRAGMA journal_mode = WAL")
-            .execute(&self.pool)
-            .await?;
-
-        // Set synchronous to NORMAL for better performance
-        sqlx::query("PRAGMA synchronous = NORMAL")
-            .execute(&self.pool)
-            .await?;
-
-        // Increase cache size (10MB)
-        sqlx::query("PRAGMA cache_size = -10000")
-            .execute(&self.pool)
-            .await?;
-
-        // Enable memory-mapped I/O (256MB)
-        sqlx::query("PRAGMA mmap_size = 268435456")
-            .execute(&self.pool)
-            .await?;
-
-        // Optimize temp store
-        sqlx::query("PRAGMA temp_store = MEMORY")
-            .execute(&self.pool)
-            .await?;
-
-        // Set busy timeout (30 seconds)
-        sqlx::query("PRAGMA busy_timeout = 30000")
-            .execute(&self.pool)
-            .await?;
+    pub async fn record_install(&self, record: &InstallRecord) -> Result<()> {
+        self.record_installs(std::slice::from_ref(record)).await
+    }
 
+    /// Record several installs in one transaction, for runs that touch many
+    /// packages
+    pub async fn record_installs(&self, records: &[InstallRecord]) -> Result<()> {
+        crate::config::ensure_writable("recording an install")?;
+        self.insert_install_records(records, "INSERT").await?;
         Ok(())
     }
 
-    pub async fn record_install(&self, record: &InstallRecord) -> Result<()> {
-        let status_str = match record.status {
-            InstallStatus::Success => "success",
-            InstallStatus::Failed => "failed",
-            InstallStatus::Removed => "removed",
-            InstallStatus::Updated => "updated",
-        };
+    /// Add records from another machine or an archive, skipping those already
+    /// present; returns how many were new
+    pub async fn import_install_records(&self, records: &[InstallRecord]) -> Result<u64> {
+        crate::config::ensure_writable("importing install history")?;
+        self.insert_install_records(records, "INSERT OR IGNORE")
+            .await
+    }
 
-        sqlx::query(
-            r#"
-            INSERT INTO install_records 
-            (id, package_name, box_type, version, source_url, install_path, installed_at, status, metadata)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-            "#,
-        )
-        .bind(&record.id)
-        .bind(&record.package_name)
-        .bind(&record.box_type)
-        .bind(&record.version)
-        .bind(&record.source_url)
-        .bind(&record.install_path)
-        .bind(record.installed_at.to_rfc3339())
-        .bind(status_str)
-        .bind(&record.metadata)
-        .execute(&self.pool)
-        .await?;
+    async fn insert_install_records(&self, records: &[InstallRecord], insert: &str) -> Result<u64> {
+        let mut inserted = 0;
+        let mut tx = self.pool.begin().await?;
+        for record in records {
+            inserted += sqlx::query(&format!(
+                r#"
+                {} INTO install_records
+                (id, package_name, box_type, version, source_url, install_path, installed_at, status, metadata, repository, mirror_url, channel)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                "#,
+                insert
+            ))
+            .bind(&record.id)
+            .bind(&record.package_name)
+            .bind(&record.box_type)
+            .bind(&record.version)
+            .bind(&record.source_url)
+            .bind(&record.install_path)
+            .bind(record.installed_at.to_rfc3339())
+            .bind(record.status.as_str())
+            .bind(&record.metadata)
+            .bind(&record.repository)
+            .bind(&record.mirror_url)
+            .bind(&record.channel)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+        tx.commit().await?;
 
-        Ok(())
+        Ok(inserted)
     }
 
     pub async fn get_install_history(&self, limit: Option<i64>) -> Result<Vec<InstallRecord>> {
@@ -302,33 +370,48 @@ impl Database {
             .fetch_all(&self.pool)
             .await?;
 
-        let mut records = Vec::new();
-        for row in rows {
-            let status = match row.get::<String, _>("status").as_str() {
-                "success" => InstallStatus::Success,
-                "failed" => InstallStatus::Failed,
-                "removed" => InstallStatus::Removed,
-                "updated" => InstallStatus::Updated,
-                _ => InstallStatus::Failed,
-            };
+        rows.iter().map(install_record).collect()
+    }
 
-            let installed_at: String = row.get("installed_at");
-            let installed_at = DateTime::parse_from_rfc3339(&installed_at)?.with_timezone(&Utc);
+    /// Records from `since` up to `until`, oldest first
+    pub async fn get_history_between(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Vec<InstallRecord>> {
+        let rows = sqlx::query(
+            "SELECT * FROM install_records WHERE installed_at >= ?1 AND installed_at <= ?2 ORDER BY installed_at",
+        )
+        .bind(since.to_rfc3339())
+        .bind(until.to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
 
-            records.push(InstallRecord {
-                id: row.get("id"),
-                package_name: row.get("package_name"),
-                box_type: row.get("box_type"),
-                version: row.get("version"),
-                source_url: row.get("source_url"),
-                install_path: row.get("install_path"),
-                installed_at,
-                status,
-                metadata: row.get("metadata"),
-            });
-        }
+        rows.iter().map(install_record).collect()
+    }
 
-        Ok(records)
+    /// Every record up to `until`, oldest first
+    pub async fn get_history_until(&self, until: DateTime<Utc>) -> Result<Vec<InstallRecord>> {
+        let rows = sqlx::query(
+            "SELECT * FROM install_records WHERE installed_at <= ?1 ORDER BY installed_at",
+        )
+        .bind(until.to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(install_record).collect()
+    }
+
+    /// All records for one package, newest first
+    pub async fn get_package_history(&self, package_name: &str) -> Result<Vec<InstallRecord>> {
+        let rows = sqlx::query(
+            "SELECT * FROM install_records WHERE package_name = ?1 ORDER BY installed_at DESC",
+        )
+        .bind(package_name)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(install_record).collect()
     }
 
     pub async fn get_installed_packages(&self) -> Result<Vec<InstallRecord>> {
@@ -352,6 +435,9 @@ impl Database {
                 install_path: row.get("install_path"),
                 installed_at,
                 status: InstallStatus::Success,
+                repository: row.get("repository"),
+                mirror_url: row.get("mirror_url"),
+                channel: row.get("channel"),
                 metadata: row.get("metadata"),
             });
         }
@@ -360,6 +446,7 @@ impl Database {
     }
 
     pub async fn create_snapshot(&self, name: &str, description: Option<&str>) -> Result<String> {
+        crate::config::ensure_writable("creating a snapshot")?;
         let snapshot_id = Uuid::new_v4().to_string();
         let created_at = Utc::now();
 
@@ -408,13 +495,32 @@ impl Database {
                 description: row.get("description"),
                 created_at,
                 packages,
+                backend: row.get("backend"),
+                backend_ref: row.get("backend_ref"),
             });
         }
 
         Ok(snapshots)
     }
 
+    /// Link a snapshot to the filesystem snapshot `backend` took with it
+    pub async fn set_snapshot_backend(
+        &self,
+        snapshot_id: &str,
+        backend: &str,
+        reference: &str,
+    ) -> Result<()> {
+        sqlx::query("UPDATE snapshots SET backend = ?1, backend_ref = ?2 WHERE id = ?3")
+            .bind(backend)
+            .bind(reference)
+            .bind(snapshot_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn delete_snapshot(&self, snapshot_id: &str) -> Result<()> {
+        crate::config::ensure_writable("deleting a snapshot")?;
         // Start a transaction to ensure atomicity
         let mut tx = self.pool.begin().await?;
 
@@ -467,6 +573,9 @@ impl Database {
                 install_path: row.get("install_path"),
                 installed_at,
                 status: InstallStatus::Success,
+                repository: row.get("repository"),
+                mirror_url: row.get("mirror_url"),
+                channel: row.get("channel"),
                 metadata: row.get("metadata"),
             });
         }
@@ -554,6 +663,15 @@ impl Database {
         Ok(result.rows_affected() as usize)
     }
 
+    /// Remove every cached package metadata entry
+    pub async fn clear_package_cache(&self) -> Result<usize> {
+        let result = sqlx::query("DELETE FROM package_cache")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() as usize)
+    }
+
     /// Get cache statistics
     pub async fn get_cache_stats(&self) -> Result<CacheStats> {
         let total_entries: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM package_cache")
@@ -579,8 +697,582 @@ impl Database {
         })
     }
 
+    pub async fn pin_package(&self, pin: &PinnedPackage) -> Result<()> {
+        crate::config::ensure_writable("pinning a package")?;
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO pinned_packages
+            (package_name, box_type, version, reason, pinned_at, version_constraint)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(&pin.package_name)
+        .bind(&pin.box_type)
+        .bind(&pin.version)
+        .bind(&pin.reason)
+        .bind(pin.pinned_at.to_rfc3339())
+        .bind(&pin.constraint)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove pins for a package (all boxes when `box_type` is None); returns the removed pins
+    pub async fn unpin_package(
+        &self,
+        package_name: &str,
+        box_type: Option<&str>,
+    ) -> Result<Vec<PinnedPackage>> {
+        crate::config::ensure_writable("unpinning a package")?;
+        let removed: Vec<PinnedPackage> = self
+            .get_pinned_packages()
+            .await?
+            .into_iter()
+            .filter(|p| p.package_name == package_name)
+            .filter(|p| box_type.map_or(true, |bt| p.box_type == bt))
+            .collect();
+
+        for pin in &removed {
+            sqlx::query("DELETE FROM pinned_packages WHERE package_name = ?1 AND box_type = ?2")
+                .bind(&pin.package_name)
+                .bind(&pin.box_type)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(removed)
+    }
+
+    pub async fn get_pinned_packages(&self) -> Result<Vec<PinnedPackage>> {
+        let rows = sqlx::query("SELECT * FROM pinned_packages ORDER BY package_name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut pins = Vec::new();
+        for row in rows {
+            let pinned_at: String = row.get("pinned_at");
+            pins.push(PinnedPackage {
+                package_name: row.get("package_name"),
+                box_type: row.get("box_type"),
+                version: row.get("version"),
+                reason: row.get("reason"),
+                pinned_at: DateTime::parse_from_rfc3339(&pinned_at)?.with_timezone(&Utc),
+                constraint: row.get("version_constraint"),
+            });
+        }
+
+        Ok(pins)
+    }
+
+    /// Add a package to the watchlist, or reset its state if it is already there
+    pub async fn watch_package(&self, watch: &WatchedPackage) -> Result<()> {
+        crate::config::ensure_writable("changing the watchlist")?;
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO watched_packages
+            (package_name, box_type, last_version, known_advisories, added_at, checked_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(&watch.package_name)
+        .bind(&watch.box_type)
+        .bind(&watch.last_version)
+        .bind(serde_json::to_string(&watch.known_advisories)?)
+        .bind(watch.added_at.to_rfc3339())
+        .bind(watch.checked_at.map(|t| t.to_rfc3339()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove a package from the watchlist (all boxes when `box_type` is None);
+    /// returns how many entries were removed
+    pub async fn unwatch_package(&self, package_name: &str, box_type: Option<&str>) -> Result<u64> {
+        crate::config::ensure_writable("changing the watchlist")?;
+        let result = match box_type {
+            Some(box_type) => {
+                sqlx::query(
+                    "DELETE FROM watched_packages WHERE package_name = ?1 AND box_type = ?2",
+                )
+                .bind(package_name)
+                .bind(box_type)
+                .execute(&self.pool)
+                .await?
+            }
+            None => {
+                sqlx::query("DELETE FROM watched_packages WHERE package_name = ?1")
+                    .bind(package_name)
+                    .execute(&self.pool)
+                    .await?
+            }
+        };
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_watched_packages(&self) -> Result<Vec<WatchedPackage>> {
+        let rows = sqlx::query("SELECT * FROM watched_packages ORDER BY package_name, box_type")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut watched = Vec::new();
+        for row in rows {
+            let added_at: String = row.get("added_at");
+            let checked_at: Option<String> = row.get("checked_at");
+            let known_advisories: String = row.get("known_advisories");
+            watched.push(WatchedPackage {
+                package_name: row.get("package_name"),
+                box_type: row.get("box_type"),
+                last_version: row.get("last_version"),
+                known_advisories: serde_json::from_str(&known_advisories).unwrap_or_default(),
+                added_at: DateTime::parse_from_rfc3339(&added_at)?.with_timezone(&Utc),
+                checked_at: checked_at
+                    .map(|t| DateTime::parse_from_rfc3339(&t).map(|t| t.with_timezone(&Utc)))
+                    .transpose()?,
+            });
+        }
+
+        Ok(watched)
+    }
+
+    /// Record what was installed for a package, replacing an earlier record
+    pub async fn record_artifact(&self, artifact: &ArtifactRecord) -> Result<()> {
+        self.record_artifacts(std::slice::from_ref(artifact)).await
+    }
+
+    /// Record several artifacts in one transaction
+    pub async fn record_artifacts(&self, artifacts: &[ArtifactRecord]) -> Result<()> {
+        crate::config::ensure_writable("recording installed artifacts")?;
+        let mut tx = self.pool.begin().await?;
+        for artifact in artifacts {
+            sqlx::query(
+                r#"
+                INSERT OR REPLACE INTO artifacts
+                (package_name, box_type, version, url, path, checksum, signature, recorded_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                "#,
+            )
+            .bind(&artifact.package_name)
+            .bind(&artifact.box_type)
+            .bind(&artifact.version)
+            .bind(&artifact.url)
+            .bind(&artifact.path)
+            .bind(&artifact.checksum)
+            .bind(artifact.signature.as_str())
+            .bind(artifact.recorded_at.to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn forget_artifact(&self, package_name: &str, box_type: &str) -> Result<()> {
+        crate::config::ensure_writable("recording installed artifacts")?;
+        sqlx::query("DELETE FROM artifacts WHERE package_name = ?1 AND box_type = ?2")
+            .bind(package_name)
+            .bind(box_type)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Recorded artifacts, of one package or of all
+    pub async fn get_artifacts(&self, package_name: Option<&str>) -> Result<Vec<ArtifactRecord>> {
+        let rows = match package_name {
+            Some(package_name) => {
+                sqlx::query("SELECT * FROM artifacts WHERE package_name = ?1 ORDER BY box_type")
+                    .bind(package_name)
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+            None => {
+                sqlx::query("SELECT * FROM artifacts ORDER BY package_name, box_type")
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+        };
+
+        let mut artifacts = Vec::new();
+        for row in rows {
+            let recorded_at: String = row.get("recorded_at");
+            let signature: String = row.get("signature");
+            artifacts.push(ArtifactRecord {
+                package_name: row.get("package_name"),
+                box_type: row.get("box_type"),
+                version: row.get("version"),
+                url: row.get("url"),
+                path: row.get("path"),
+                checksum: row.get("checksum"),
+                signature: SignatureStatus::parse(&signature),
+                recorded_at: DateTime::parse_from_rfc3339(&recorded_at)?.with_timezone(&Utc),
+            });
+        }
+
+        Ok(artifacts)
+    }
+
+    /// Record where a trusted key came from, replacing an earlier record
+    pub async fn trust_key(&self, key: &TrustedKey) -> Result<()> {
+        crate::config::ensure_writable("changing trusted keys")?;
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO trusted_keys (fingerprint, source, added_at, refreshed_at)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+        )
+        .bind(&key.fingerprint)
+        .bind(&key.source)
+        .bind(key.added_at.to_rfc3339())
+        .bind(key.refreshed_at.map(|t| t.to_rfc3339()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn untrust_key(&self, fingerprint: &str) -> Result<u64> {
+        crate::config::ensure_writable("changing trusted keys")?;
+        let result = sqlx::query("DELETE FROM trusted_keys WHERE fingerprint = ?1")
+            .bind(fingerprint)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_trusted_keys(&self) -> Result<Vec<TrustedKey>> {
+        let rows = sqlx::query("SELECT * FROM trusted_keys ORDER BY added_at")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut keys = Vec::new();
+        for row in rows {
+            let added_at: String = row.get("added_at");
+            let refreshed_at: Option<String> = row.get("refreshed_at");
+            keys.push(TrustedKey {
+                fingerprint: row.get("fingerprint"),
+                source: row.get("source"),
+                added_at: DateTime::parse_from_rfc3339(&added_at)?.with_timezone(&Utc),
+                refreshed_at: refreshed_at
+                    .map(|t| DateTime::parse_from_rfc3339(&t).map(|t| t.with_timezone(&Utc)))
+                    .transpose()?,
+            });
+        }
+
+        Ok(keys)
+    }
+
+    /// Add packages to the search index, or refresh the ones already in it
+    pub async fn index_packages(&self, packages: &[IndexedPackage]) -> Result<()> {
+        let indexed_at = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+        for package in packages {
+            upsert_indexed_package(&mut tx, package, &indexed_at).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Replace what the search index holds for `box_type` with its whole
+    /// catalog, and note when that was
+    pub async fn replace_package_index(
+        &self,
+        box_type: &str,
+        packages: &[IndexedPackage],
+    ) -> Result<()> {
+        let refreshed_at = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM package_index WHERE box_type = ?1")
+            .bind(box_type)
+            .execute(&mut *tx)
+            .await?;
+        for package in packages {
+            upsert_indexed_package(&mut tx, package, &refreshed_at).await?;
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO package_index_sources (box_type, refreshed_at, package_count)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT (box_type) DO UPDATE SET
+                refreshed_at = excluded.refreshed_at,
+                package_count = excluded.package_count
+            "#,
+        )
+        .bind(box_type)
+        .bind(&refreshed_at)
+        .bind(packages.len() as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Backends whose whole catalog is in the search index
+    pub async fn package_index_sources(&self) -> Result<Vec<IndexSource>> {
+        let rows = sqlx::query(
+            "SELECT box_type, refreshed_at, package_count FROM package_index_sources ORDER BY box_type",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut sources = Vec::new();
+        for row in rows {
+            let refreshed_at: String = row.get("refreshed_at");
+            let package_count: i64 = row.get("package_count");
+            sources.push(IndexSource {
+                box_type: row.get("box_type"),
+                refreshed_at: DateTime::parse_from_rfc3339(&refreshed_at)?.with_timezone(&Utc),
+                package_count: usize::try_from(package_count).unwrap_or(0),
+            });
+        }
+        Ok(sources)
+    }
+
+    /// Indexed packages whose name or description contains `query`, names
+    /// starting with it first
+    pub async fn search_package_index(
+        &self,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<IndexedPackage>> {
+        let escaped = query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        // The trigram index needs three characters; shorter queries scan the table
+        let sql = if query.chars().count() >= 3 {
+            r#"
+            SELECT p.package_name, p.box_type, p.version, p.description
+            FROM package_index_fts f JOIN package_index p ON p.rowid = f.rowid
+            WHERE package_index_fts MATCH ?4
+            ORDER BY p.package_name LIKE ?2 ESCAPE '\' DESC, length(p.package_name), p.package_name
+            LIMIT ?3
+            "#
+        } else {
+            r#"
+            SELECT package_name, box_type, version, description FROM package_index
+            WHERE package_name LIKE ?1 ESCAPE '\' OR description LIKE ?1 ESCAPE '\'
+            ORDER BY package_name LIKE ?2 ESCAPE '\' DESC, length(package_name), package_name
+            LIMIT ?3
+            "#
+        };
+        let rows = sqlx::query(sql)
+            .bind(format!("%{}%", escaped))
+            .bind(format!("{}%", escaped))
+            .bind(limit)
+            .bind(format!("\"{}\"", query.replace('"', "\"\"")))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| IndexedPackage {
+                package_name: row.get("package_name"),
+                box_type: row.get("box_type"),
+                version: row.get("version"),
+                description: row.get("description"),
+            })
+            .collect())
+    }
+
+    /// Vulnerability IDs OSV last reported for a package version, and when
+    pub async fn cached_vulnerability_ids(
+        &self,
+        ecosystem: &str,
+        package_name: &str,
+        version: &str,
+    ) -> Result<Option<(Vec<String>, DateTime<Utc>)>> {
+        let row: Option<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT vulnerability_ids, queried_at FROM vulnerability_queries
+            WHERE ecosystem = ?1 AND package_name = ?2 AND version = ?3
+            "#,
+        )
+        .bind(ecosystem)
+        .bind(package_name)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|(ids, queried_at)| {
+            Ok((
+                serde_json::from_str(&ids)?,
+                DateTime::parse_from_rfc3339(&queried_at)?.with_timezone(&Utc),
+            ))
+        })
+        .transpose()
+    }
+
+    pub async fn cache_vulnerability_ids(
+        &self,
+        ecosystem: &str,
+        package_name: &str,
+        version: &str,
+        ids: &[String],
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO vulnerability_queries
+            (ecosystem, package_name, version, vulnerability_ids, queried_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(ecosystem)
+        .bind(package_name)
+        .bind(version)
+        .bind(serde_json::to_string(ids)?)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The OSV record stored for `id`, and when it was fetched
+    pub async fn cached_vulnerability(&self, id: &str) -> Result<Option<(String, DateTime<Utc>)>> {
+        let row: Option<(String, String)> =
+            sqlx::query_as("SELECT record, fetched_at FROM vulnerabilities WHERE id = ?1")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        row.map(|(record, fetched_at)| {
+            Ok((
+                record,
+                DateTime::parse_from_rfc3339(&fetched_at)?.with_timezone(&Utc),
+            ))
+        })
+        .transpose()
+    }
+
+    pub async fn cache_vulnerability(&self, id: &str, record: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO vulnerabilities (id, record, fetched_at) VALUES (?1, ?2, ?3)",
+        )
+        .bind(id)
+        .bind(record)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Write the journal of a transaction, replacing the one stored for it
+    pub async fn save_transaction(
+        &self,
+        id: &str,
+        status: &str,
+        journal: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO transactions (id, status, journal, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(id) DO UPDATE SET
+                status = excluded.status,
+                journal = excluded.journal,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(status)
+        .bind(journal)
+        .bind(created_at.to_rfc3339())
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The journal stored for a transaction
+    pub async fn get_transaction(&self, id: &str) -> Result<Option<String>> {
+        Ok(
+            sqlx::query_scalar("SELECT journal FROM transactions WHERE id = ?1")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    /// Journals of the transactions in `status`, or of all of them, oldest first
+    pub async fn list_transactions(&self, status: Option<&str>) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar(
+            "SELECT journal FROM transactions WHERE ?1 IS NULL OR status = ?1 ORDER BY created_at",
+        )
+        .bind(status)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Note how much of the filesystem holding `path` is in use, for the
+    /// disk changes in digests; samples older than a year are dropped
+    pub async fn record_disk_usage(&self, path: &str, used_kib: u64) -> Result<()> {
+        crate::config::ensure_writable("recording disk usage")?;
+        let now = Utc::now();
+        sqlx::query("INSERT INTO disk_usage (recorded_at, path, used_kib) VALUES (?1, ?2, ?3)")
+            .bind(now.to_rfc3339())
+            .bind(path)
+            .bind(used_kib as i64)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM disk_usage WHERE recorded_at < ?1")
+            .bind((now - chrono::Duration::days(366)).to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Disk usage samples for `path` from `since` up to `until`, oldest first
+    pub async fn disk_usage_between(
+        &self,
+        path: &str,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Vec<DiskUsageSample>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT recorded_at, used_kib FROM disk_usage
+            WHERE path = ?1 AND recorded_at >= ?2 AND recorded_at <= ?3
+            ORDER BY recorded_at
+            "#,
+        )
+        .bind(path)
+        .bind(since.to_rfc3339())
+        .bind(until.to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let recorded_at: String = row.get("recorded_at");
+                Ok(DiskUsageSample {
+                    recorded_at: DateTime::parse_from_rfc3339(&recorded_at)?.with_timezone(&Utc),
+                    used_kib: row.get::<i64, _>("used_kib") as u64,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn is_pinned(&self, package_name: &str, box_type: &str) -> Result<bool> {
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pinned_packages WHERE package_name = ?1 AND box_type = ?2",
+        )
+        .bind(package_name)
+        .bind(box_type)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
     /// Optimize database by running maintenance tasks
     pub async fn maintenance(&self) -> Result<()> {
+        crate::config::ensure_writable("database maintenance")?;
         // Clean expired cache entries
         let cleaned = self.clean_expired_cache().await?;
         if cleaned > 0 {
@@ -590,6 +1282,12 @@ impl Database {
         // Analyze tables for query optimizer
         sqlx::query("ANALYZE").execute(&self.pool).await?;
 
+        // Fold the write-ahead log back into the database so it does not grow
+        // between runs
+        sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .execute(&self.pool)
+            .await?;
+
         // Vacuum if needed (only if significant deletions occurred)
         if cleaned > 100 {
             sqlx::query("VACUUM").execute(&self.pool).await?;
@@ -624,3 +1322,258 @@ impl Database {
         })
     }
 }
+
+fn install_record(row: &SqliteRow) -> Result<InstallRecord> {
+    let status =
+        InstallStatus::parse(&row.get::<String, _>("status")).unwrap_or(InstallStatus::Failed);
+
+    let installed_at: String = row.get("installed_at");
+    let installed_at = DateTime::parse_from_rfc3339(&installed_at)?.with_timezone(&Utc);
+
+    Ok(InstallRecord {
+        id: row.get("id"),
+        package_name: row.get("package_name"),
+        box_type: row.get("box_type"),
+        version: row.get("version"),
+        source_url: row.get("source_url"),
+        install_path: row.get("install_path"),
+        installed_at,
+        status,
+        repository: row.get("repository"),
+        mirror_url: row.get("mirror_url"),
+        channel: row.get("channel"),
+        metadata: row.get("metadata"),
+    })
+}
+
+/// Insert an indexed package, or update the row it already has; an update
+/// rather than `INSERT OR REPLACE` so the full-text index triggers see it
+async fn upsert_indexed_package(
+    conn: &mut SqliteConnection,
+    package: &IndexedPackage,
+    indexed_at: &str,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO package_index
+        (package_name, box_type, version, description, indexed_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT (package_name, box_type) DO UPDATE SET
+            version = excluded.version,
+            description = excluded.description,
+            indexed_at = excluded.indexed_at
+        "#,
+    )
+    .bind(&package.package_name)
+    .bind(&package.box_type)
+    .bind(&package.version)
+    .bind(&package.description)
+    .bind(indexed_at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pin_and_unpin_package() {
+        let db = Database::new_in_memory().await.unwrap();
+
+        let pin = PinnedPackage {
+            package_name: "firefox".to_string(),
+            box_type: "apt".to_string(),
+            version: Some("120.0".to_string()),
+            reason: Some("extension compatibility".to_string()),
+            pinned_at: Utc::now(),
+            constraint: None,
+        };
+        db.pin_package(&pin).await.unwrap();
+
+        assert!(db.is_pinned("firefox", "apt").await.unwrap());
+        assert!(!db.is_pinned("firefox", "snap").await.unwrap());
+        assert_eq!(db.get_pinned_packages().await.unwrap().len(), 1);
+
+        let removed = db.unpin_package("firefox", None).await.unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(!db.is_pinned("firefox", "apt").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_search_package_index() {
+        let db = Database::new_in_memory().await.unwrap();
+        let package = |name: &str, box_type: &str, description: &str| IndexedPackage {
+            package_name: name.to_string(),
+            box_type: box_type.to_string(),
+            version: Some("1.0".to_string()),
+            description: Some(description.to_string()),
+        };
+        db.index_packages(&[
+            package("thunderbird", "apt", "Email client from the firefox makers"),
+            package("firefox-esr", "apt", "Extended support release"),
+            package("firefox", "flatpak", "Web browser"),
+            package("fire_tool", "apt", "Underscores are not wildcards"),
+        ])
+        .await
+        .unwrap();
+
+        let names: Vec<String> = db
+            .search_package_index("FIREFOX", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.package_name)
+            .collect();
+        assert_eq!(names, ["firefox", "firefox-esr", "thunderbird"]);
+
+        let underscore = db.search_package_index("fire_", 10).await.unwrap();
+        assert_eq!(underscore.len(), 1);
+        assert_eq!(
+            db.search_package_index("firefox", 1).await.unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disk_usage_samples() {
+        let db = Database::new_in_memory().await.unwrap();
+        let since = Utc::now() - chrono::Duration::minutes(1);
+        db.record_disk_usage("/", 1000).await.unwrap();
+        db.record_disk_usage("/", 1500).await.unwrap();
+        db.record_disk_usage("/home", 10).await.unwrap();
+
+        let samples = db.disk_usage_between("/", since, Utc::now()).await.unwrap();
+        let used: Vec<u64> = samples.iter().map(|s| s.used_kib).collect();
+        assert_eq!(used, [1000, 1500]);
+        assert!(db
+            .disk_usage_between("/", since - chrono::Duration::days(1), since)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replace_package_index() {
+        let db = Database::new_in_memory().await.unwrap();
+        let package = |name: &str, box_type: &str| IndexedPackage {
+            package_name: name.to_string(),
+            box_type: box_type.to_string(),
+            version: None,
+            description: None,
+        };
+        db.index_packages(&[package("vim-old", "apt"), package("vim", "flatpak")])
+            .await
+            .unwrap();
+        db.replace_package_index("apt", &[package("vim", "apt"), package("vim-tiny", "apt")])
+            .await
+            .unwrap();
+
+        let mut found: Vec<(String, String)> = db
+            .search_package_index("vim", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.package_name, p.box_type))
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                ("vim".to_string(), "apt".to_string()),
+                ("vim".to_string(), "flatpak".to_string()),
+                ("vim-tiny".to_string(), "apt".to_string()),
+            ]
+        );
+
+        let sources = db.package_index_sources().await.unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].box_type, "apt");
+        assert_eq!(sources[0].package_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_open_sets_pragmas_on_every_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(&dir.path().join("omni.db")).await.unwrap();
+        let max_connections = db.pool.options().get_max_connections();
+        assert!((2..=8).contains(&max_connections));
+
+        // Hold both so the second comes from a fresh connection
+        let mut first = db.pool.acquire().await.unwrap();
+        let mut second = db.pool.acquire().await.unwrap();
+        for conn in [&mut first, &mut second] {
+            let journal: String = sqlx::query_scalar("PRAGMA journal_mode")
+                .fetch_one(&mut **conn)
+                .await
+                .unwrap();
+            assert_eq!(journal, "wal");
+            let busy_timeout: i64 = sqlx::query_scalar("PRAGMA busy_timeout")
+                .fetch_one(&mut **conn)
+                .await
+                .unwrap();
+            assert_eq!(busy_timeout, 30_000);
+            // NORMAL
+            let synchronous: i64 = sqlx::query_scalar("PRAGMA synchronous")
+                .fetch_one(&mut **conn)
+                .await
+                .unwrap();
+            assert_eq!(synchronous, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_installs_and_artifacts() {
+        let db = Database::new_in_memory().await.unwrap();
+        let record = |name: &str| InstallRecord {
+            id: Uuid::new_v4().to_string(),
+            package_name: name.to_string(),
+            box_type: "apt".to_string(),
+            version: None,
+            source_url: None,
+            install_path: None,
+            installed_at: Utc::now(),
+            status: InstallStatus::Success,
+            repository: None,
+            mirror_url: None,
+            channel: None,
+            metadata: Some("Installed via manifest".to_string()),
+        };
+        let artifact = |name: &str, version: &str| ArtifactRecord {
+            package_name: name.to_string(),
+            box_type: "apt".to_string(),
+            version: Some(version.to_string()),
+            url: None,
+            path: None,
+            checksum: None,
+            signature: SignatureStatus::Repository,
+            recorded_at: Utc::now(),
+        };
+
+        db.record_installs(&[record("curl"), record("git")])
+            .await
+            .unwrap();
+        db.record_installs(&[]).await.unwrap();
+        let mut names: Vec<String> = db
+            .get_install_history(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.package_name)
+            .collect();
+        names.sort();
+        assert_eq!(names, ["curl", "git"]);
+
+        db.record_artifacts(&[artifact("curl", "8.5.0"), artifact("git", "2.43.0")])
+            .await
+            .unwrap();
+        db.record_artifacts(&[artifact("curl", "8.6.0")])
+            .await
+            .unwrap();
+        let artifacts = db.get_artifacts(Some("curl")).await.unwrap();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].version.as_deref(), Some("8.6.0"));
+        assert_eq!(db.get_artifacts(None).await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,530 @@
+//! Numbered schema migrations for omni's SQLite database. `Database::new`
+//! applies the ones a database has not seen yet, each in a transaction, and
+//! records them in the `schema_version` table. Later schema changes append a
+//! migration here instead of editing an earlier one.
+
+use crate::error_handling::OmniError;
+use anyhow::Result;
+use chrono::Utc;
+use sqlx::{Row, SqliteConnection, SqlitePool};
+
+/// One change to the schema
+enum Step {
+    Sql(&'static str),
+    /// A column added to a table that databases from before versioning may
+    /// already have
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        decl: &'static str,
+    },
+}
+
+struct Migration {
+    /// Schema version the migration brings the database to
+    version: u32,
+    description: &'static str,
+    steps: &'static [Step],
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "initial schema",
+    steps: &[
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS install_records (
+                id TEXT PRIMARY KEY,
+                package_name TEXT NOT NULL,
+                box_type TEXT NOT NULL,
+                version TEXT,
+                source_url TEXT,
+                install_path TEXT,
+                installed_at TEXT NOT NULL,
+                status TEXT NOT NULL,
+                metadata TEXT,
+                repository TEXT,
+                mirror_url TEXT
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS snapshots (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                description TEXT,
+                created_at TEXT NOT NULL
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS snapshot_packages (
+                snapshot_id TEXT NOT NULL,
+                install_record_id TEXT NOT NULL,
+                FOREIGN KEY (snapshot_id) REFERENCES snapshots (id),
+                FOREIGN KEY (install_record_id) REFERENCES install_records (id)
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS package_cache (
+                package_name TEXT NOT NULL,
+                box_type TEXT NOT NULL,
+                version TEXT NOT NULL,
+                description TEXT,
+                dependencies TEXT,
+                cached_at TEXT NOT NULL,
+                expires_at TEXT,
+                hits INTEGER DEFAULT 0,
+                PRIMARY KEY (package_name, box_type)
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS pinned_packages (
+                package_name TEXT NOT NULL,
+                box_type TEXT NOT NULL,
+                version TEXT,
+                reason TEXT,
+                pinned_at TEXT NOT NULL,
+                PRIMARY KEY (package_name, box_type)
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS watched_packages (
+                package_name TEXT NOT NULL,
+                box_type TEXT NOT NULL,
+                last_version TEXT,
+                known_advisories TEXT NOT NULL DEFAULT '[]',
+                added_at TEXT NOT NULL,
+                checked_at TEXT,
+                PRIMARY KEY (package_name, box_type)
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id TEXT PRIMARY KEY,
+                timestamp TEXT NOT NULL,
+                event_type TEXT NOT NULL,
+                severity TEXT NOT NULL,
+                user TEXT NOT NULL,
+                package_name TEXT,
+                box_type TEXT,
+                details TEXT NOT NULL,
+                success INTEGER NOT NULL DEFAULT 1
+            )
+            "#,
+        ),
+        // Start and head of the audit log hash chain; see `audit::entry_hash`
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_chain (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                anchor_hash TEXT NOT NULL DEFAULT '',
+                last_hash TEXT NOT NULL DEFAULT ''
+            )
+            "#,
+        ),
+        Step::Sql("INSERT OR IGNORE INTO audit_chain (id) VALUES (1)"),
+        // Packages seen in search results, so later searches can be answered locally
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS package_index (
+                package_name TEXT NOT NULL,
+                box_type TEXT NOT NULL,
+                version TEXT,
+                description TEXT,
+                indexed_at TEXT NOT NULL,
+                PRIMARY KEY (package_name, box_type)
+            )
+            "#,
+        ),
+        // Used space over time, for the disk changes in digests
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS disk_usage (
+                recorded_at TEXT NOT NULL,
+                path TEXT NOT NULL,
+                used_kib INTEGER NOT NULL
+            )
+            "#,
+        ),
+        // OSV answers per installed package version, and the OSV records they
+        // name, for vulnerability scans
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS vulnerability_queries (
+                ecosystem TEXT NOT NULL,
+                package_name TEXT NOT NULL,
+                version TEXT NOT NULL,
+                vulnerability_ids TEXT NOT NULL,
+                queried_at TEXT NOT NULL,
+                PRIMARY KEY (ecosystem, package_name, version)
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS vulnerabilities (
+                id TEXT PRIMARY KEY,
+                record TEXT NOT NULL,
+                fetched_at TEXT NOT NULL
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS artifacts (
+                package_name TEXT NOT NULL,
+                box_type TEXT NOT NULL,
+                version TEXT,
+                url TEXT,
+                path TEXT,
+                checksum TEXT,
+                signature TEXT NOT NULL,
+                recorded_at TEXT NOT NULL,
+                PRIMARY KEY (package_name, box_type)
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS trusted_keys (
+                fingerprint TEXT PRIMARY KEY,
+                source TEXT NOT NULL,
+                added_at TEXT NOT NULL,
+                refreshed_at TEXT
+            )
+            "#,
+        ),
+        // Columns added before the schema was versioned
+        Step::AddColumn {
+            table: "install_records",
+            column: "repository",
+            decl: "TEXT",
+        },
+        Step::AddColumn {
+            table: "install_records",
+            column: "mirror_url",
+            decl: "TEXT",
+        },
+        Step::AddColumn {
+            table: "install_records",
+            column: "channel",
+            decl: "TEXT",
+        },
+        Step::AddColumn {
+            table: "audit_log",
+            column: "trace_id",
+            decl: "TEXT",
+        },
+        Step::AddColumn {
+            table: "audit_log",
+            column: "prev_hash",
+            decl: "TEXT",
+        },
+        Step::AddColumn {
+            table: "audit_log",
+            column: "hash",
+            decl: "TEXT",
+        },
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_install_records_package_name ON install_records(package_name)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_install_records_box_type ON install_records(box_type)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_install_records_status ON install_records(status)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_install_records_installed_at ON install_records(installed_at)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_install_records_package_box ON install_records(package_name, box_type)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_snapshots_created_at ON snapshots(created_at)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_snapshots_name ON snapshots(name)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_snapshot_packages_snapshot_id ON snapshot_packages(snapshot_id)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_snapshot_packages_record_id ON snapshot_packages(install_record_id)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_package_cache_cached_at ON package_cache(cached_at)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_package_cache_expires_at ON package_cache(expires_at)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_audit_log_timestamp ON audit_log(timestamp)"),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_audit_log_event_type ON audit_log(event_type)"),
+    ],
+},
+Migration {
+    version: 2,
+    description: "filesystem snapshot references",
+    steps: &[
+        Step::AddColumn {
+            table: "snapshots",
+            column: "backend",
+            decl: "TEXT",
+        },
+        Step::AddColumn {
+            table: "snapshots",
+            column: "backend_ref",
+            decl: "TEXT",
+        },
+    ],
+},
+Migration {
+    version: 3,
+    description: "transaction journal",
+    steps: &[
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS transactions (
+                id TEXT PRIMARY KEY,
+                status TEXT NOT NULL,
+                journal TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )
+            "#,
+        ),
+        Step::Sql("CREATE INDEX IF NOT EXISTS idx_transactions_status ON transactions(status)"),
+    ],
+},
+Migration {
+    version: 4,
+    description: "version constraints on pins",
+    steps: &[Step::AddColumn {
+        table: "pinned_packages",
+        column: "version_constraint",
+        decl: "TEXT",
+    }],
+},
+Migration {
+    version: 5,
+    description: "full-text package search index",
+    steps: &[
+        // Trigram tokens let any three characters of a name or description
+        // match, like the LIKE search the index answered with before
+        Step::Sql(
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS package_index_fts USING fts5(
+                package_name,
+                description,
+                content = 'package_index',
+                tokenize = 'trigram'
+            )
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS package_index_insert AFTER INSERT ON package_index BEGIN
+                INSERT INTO package_index_fts (rowid, package_name, description)
+                VALUES (new.rowid, new.package_name, new.description);
+            END
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS package_index_delete AFTER DELETE ON package_index BEGIN
+                INSERT INTO package_index_fts (package_index_fts, rowid, package_name, description)
+                VALUES ('delete', old.rowid, old.package_name, old.description);
+            END
+            "#,
+        ),
+        Step::Sql(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS package_index_update AFTER UPDATE ON package_index BEGIN
+                INSERT INTO package_index_fts (package_index_fts, rowid, package_name, description)
+                VALUES ('delete', old.rowid, old.package_name, old.description);
+                INSERT INTO package_index_fts (rowid, package_name, description)
+                VALUES (new.rowid, new.package_name, new.description);
+            END
+            "#,
+        ),
+        Step::Sql("INSERT INTO package_index_fts (package_index_fts) VALUES ('rebuild')"),
+        // When each backend's whole catalog was last read into the index
+        Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS package_index_sources (
+                box_type TEXT PRIMARY KEY,
+                refreshed_at TEXT NOT NULL,
+                package_count INTEGER NOT NULL
+            )
+            "#,
+        ),
+    ],
+}];
+
+/// Schema version written by this build
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Version of the schema in `pool`; 0 for a new database, or one from before
+/// versioning
+pub async fn current_version(pool: &SqlitePool) -> Result<u32> {
+    create_version_table(pool).await?;
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    Ok(version.map_or(0, |v| u32::try_from(v).unwrap_or(u32::MAX)))
+}
+
+/// Migrations the database still needs, as (version, description)
+pub async fn pending(pool: &SqlitePool) -> Result<Vec<(u32, &'static str)>> {
+    let current = check_version(pool).await?;
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|m| m.version > current)
+        .map(|m| (m.version, m.description))
+        .collect())
+}
+
+/// Apply every migration newer than the database, each in its own
+/// transaction. Returns the versions applied.
+pub async fn migrate(pool: &SqlitePool) -> Result<Vec<u32>> {
+    let current = check_version(pool).await?;
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let mut tx = pool.begin().await?;
+        for step in migration.steps {
+            apply(&mut tx, step).await.map_err(|e| {
+                anyhow::anyhow!(
+                    "Database migration {} ({}) failed: {}",
+                    migration.version,
+                    migration.description,
+                    e
+                )
+            })?;
+        }
+        sqlx::query(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)",
+        )
+        .bind(i64::from(migration.version))
+        .bind(migration.description)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+/// The database's version, refusing one written by a newer omni
+async fn check_version(pool: &SqlitePool) -> Result<u32> {
+    let current = current_version(pool).await?;
+    if current > latest_version() {
+        return Err(OmniError::DatabaseError {
+            message: format!(
+                "the database has schema version {}, but this omni only understands up to {}; \
+                 upgrade omni",
+                current,
+                latest_version()
+            ),
+        }
+        .into());
+    }
+    Ok(current)
+}
+
+async fn create_version_table(pool: &SqlitePool) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn apply(conn: &mut SqliteConnection, step: &Step) -> Result<()> {
+    match step {
+        Step::Sql(sql) => {
+            sqlx::query(sql).execute(&mut *conn).await?;
+        }
+        Step::AddColumn {
+            table,
+            column,
+            decl,
+        } => {
+            let columns = sqlx::query(&format!("PRAGMA table_info({})", table))
+                .fetch_all(&mut *conn)
+                .await?;
+            if !columns
+                .iter()
+                .any(|row| row.get::<String, _>("name") == *column)
+            {
+                sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn memory_pool() -> SqlitePool {
+        sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_versions_increase() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_once() {
+        let pool = memory_pool().await;
+        assert_eq!(current_version(&pool).await.unwrap(), 0);
+        assert_eq!(pending(&pool).await.unwrap().len(), MIGRATIONS.len());
+
+        let applied = migrate(&pool).await.unwrap();
+        assert_eq!(applied.last(), Some(&latest_version()));
+        assert_eq!(current_version(&pool).await.unwrap(), latest_version());
+        assert!(pending(&pool).await.unwrap().is_empty());
+        assert!(migrate(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unversioned_database_gains_columns() {
+        let pool = memory_pool().await;
+        sqlx::query(
+            "CREATE TABLE install_records (id TEXT PRIMARY KEY, package_name TEXT NOT NULL, \
+             box_type TEXT NOT NULL, version TEXT, source_url TEXT, install_path TEXT, \
+             installed_at TEXT NOT NULL, status TEXT NOT NULL, metadata TEXT)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        migrate(&pool).await.unwrap();
+        sqlx::query("SELECT channel, repository FROM install_records")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_newer_database_is_refused() {
+        let pool = memory_pool().await;
+        migrate(&pool).await.unwrap();
+        sqlx::query("INSERT INTO schema_version VALUES (999, 'future', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let message = migrate(&pool).await.unwrap_err().to_string();
+        assert!(message.contains("schema version 999"));
+    }
+}
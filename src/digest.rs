@@ -0,0 +1,448 @@
+//! Digest reports: what omni changed on this machine over the last week or
+//! month, from the install history, the audit log and disk usage samples,
+//! as Markdown for the terminal or HTML to mail to an ops team.
+
+use crate::audit::{self, AuditManager};
+use crate::config::{DigestPeriod, EmailAlertConfig};
+use crate::database::{Database, DiskUsageSample, InstallRecord, InstallStatus};
+use crate::health;
+use crate::resolver::DependencyResolver;
+use crate::watchlist::advisory_ids;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Months, Utc};
+use std::path::Path;
+use tracing::debug;
+
+/// Filesystem whose used space digests follow
+pub const DISK_PATH: &str = "/";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+/// A CVE an update fixed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedCve {
+    pub id: String,
+    pub package_name: String,
+    pub box_type: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Digest {
+    pub period: DigestPeriod,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub host: String,
+    pub installed: Vec<InstallRecord>,
+    pub updated: Vec<InstallRecord>,
+    pub removed: Vec<InstallRecord>,
+    pub failed: Vec<InstallRecord>,
+    pub cves_fixed: Vec<FixedCve>,
+    /// Used KiB on `DISK_PATH` at the first and the last sample of the period
+    pub disk_kib: Option<(u64, u64)>,
+    pub audit_events: usize,
+    pub failed_audit_events: usize,
+}
+
+/// Start of the `period` that ends at `until`
+pub fn period_start(period: DigestPeriod, until: DateTime<Utc>) -> DateTime<Utc> {
+    match period {
+        DigestPeriod::Week => until - Duration::days(7),
+        DigestPeriod::Month => until
+            .checked_sub_months(Months::new(1))
+            .unwrap_or(until - Duration::days(30)),
+    }
+}
+
+/// Note the used space of `DISK_PATH`, so the next digest can show how it changed
+pub async fn sample_disk_usage(db: &Database) {
+    let Some(used) = health::used_kib(Path::new(DISK_PATH)) else {
+        return;
+    };
+    if let Err(e) = db.record_disk_usage(DISK_PATH, used).await {
+        debug!("Not recording disk usage: {}", e);
+    }
+}
+
+impl Digest {
+    /// The digest of the `period` that ends now
+    pub async fn build(db: &Database, period: DigestPeriod) -> Result<Self> {
+        sample_disk_usage(db).await;
+        let until = Utc::now();
+        let since = period_start(period, until);
+
+        let records = db.get_history_between(since, until).await?;
+        let disk = db.disk_usage_between(DISK_PATH, since, until).await?;
+        let statistics = AuditManager::with_database(db.clone())
+            .get_statistics(Some(since))
+            .await?;
+
+        let mut digest = Self::from_records(period, since, until, records, &disk);
+        digest.audit_events = statistics.total_events;
+        digest.failed_audit_events = statistics.failed_events;
+        Ok(digest)
+    }
+
+    /// Sort `records` and disk samples of the period into a digest
+    pub fn from_records(
+        period: DigestPeriod,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        records: Vec<InstallRecord>,
+        disk: &[DiskUsageSample],
+    ) -> Self {
+        let mut digest = Self {
+            period,
+            since,
+            until,
+            host: hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            installed: Vec::new(),
+            updated: Vec::new(),
+            removed: Vec::new(),
+            failed: Vec::new(),
+            cves_fixed: Vec::new(),
+            disk_kib: disk
+                .first()
+                .zip(disk.last())
+                .map(|(first, last)| (first.used_kib, last.used_kib)),
+            audit_events: 0,
+            failed_audit_events: 0,
+        };
+
+        for record in records {
+            match record.status {
+                InstallStatus::Success => digest.installed.push(record),
+                InstallStatus::Updated | InstallStatus::Downgraded => {
+                    for id in advisory_ids(record.metadata.as_deref().unwrap_or_default()) {
+                        if !digest.cves_fixed.iter().any(|cve| cve.id == id) {
+                            digest.cves_fixed.push(FixedCve {
+                                id,
+                                package_name: record.package_name.clone(),
+                                box_type: record.box_type.clone(),
+                            });
+                        }
+                    }
+                    digest.updated.push(record);
+                }
+                InstallStatus::Removed => digest.removed.push(record),
+                InstallStatus::Failed => digest.failed.push(record),
+            }
+        }
+        digest
+    }
+
+    pub fn subject(&self) -> String {
+        format!(
+            "omni {} digest for {}: {} installed, {} updated, {} failed",
+            self.period,
+            self.host,
+            self.installed.len(),
+            self.updated.len(),
+            self.failed.len()
+        )
+    }
+
+    /// Mail the digest as HTML with the Markdown as plain-text alternative,
+    /// through the SMTP settings of audit alerts; `to` replaces their recipients
+    pub async fn send_email(&self, email: &EmailAlertConfig, to: &[String]) -> Result<()> {
+        let mut email = email.clone();
+        if !to.is_empty() {
+            email.to = to.to_vec();
+        }
+        audit::send_html_email(
+            &email,
+            &self.subject(),
+            &self.to_markdown(),
+            &self.to_html(),
+        )
+        .await
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Markdown => self.to_markdown(),
+            ReportFormat::Html => self.to_html(),
+        }
+    }
+
+    /// The summary lines, shared by both formats
+    fn summary(&self) -> Vec<(&'static str, String)> {
+        let mut summary = vec![
+            ("Installed", self.installed.len().to_string()),
+            ("Updated", self.updated.len().to_string()),
+            ("Removed", self.removed.len().to_string()),
+            ("Failed", self.failed.len().to_string()),
+            ("CVEs fixed", self.cves_fixed.len().to_string()),
+        ];
+        if let Some((first, last)) = self.disk_kib {
+            summary.push((
+                "Disk used",
+                format!(
+                    "{} on {} ({})",
+                    DependencyResolver::format_size(last * 1024),
+                    DISK_PATH,
+                    size_change(first, last)
+                ),
+            ));
+        }
+        summary.push((
+            "Audit log",
+            format!(
+                "{} events, {} failed",
+                self.audit_events, self.failed_audit_events
+            ),
+        ));
+        summary
+    }
+
+    /// Tables of the non-empty sections: title, column headers and rows
+    fn sections(&self) -> Vec<(&'static str, Vec<&'static str>, Vec<Vec<String>>)> {
+        let columns = || vec!["Package", "Source", "Version", "When"];
+        let mut sections = vec![
+            ("Installed", columns(), record_rows(&self.installed)),
+            ("Updated", columns(), record_rows(&self.updated)),
+            (
+                "CVEs fixed",
+                vec!["CVE", "Package", "Source"],
+                self.cves_fixed
+                    .iter()
+                    .map(|cve| {
+                        vec![
+                            cve.id.clone(),
+                            cve.package_name.clone(),
+                            cve.box_type.clone(),
+                        ]
+                    })
+                    .collect(),
+            ),
+            ("Removed", columns(), record_rows(&self.removed)),
+            (
+                "Failed",
+                vec!["Package", "Source", "Version", "When", "Details"],
+                self.failed
+                    .iter()
+                    .zip(record_rows(&self.failed))
+                    .map(|(record, mut row)| {
+                        row.push(record.metadata.clone().unwrap_or_default());
+                        row
+                    })
+                    .collect(),
+            ),
+        ];
+        sections.retain(|(_, _, rows)| !rows.is_empty());
+        sections
+    }
+
+    fn span(&self) -> String {
+        format!(
+            "{} to {}",
+            self.since.format("%Y-%m-%d %H:%M UTC"),
+            self.until.format("%Y-%m-%d %H:%M UTC")
+        )
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!(
+            "# omni {} digest for {}\n\n{}\n\n",
+            self.period,
+            self.host,
+            self.span()
+        );
+        for (label, value) in self.summary() {
+            out.push_str(&format!("- **{}:** {}\n", label, value));
+        }
+
+        for (title, columns, rows) in self.sections() {
+            out.push_str(&format!("\n## {}\n\n", title));
+            out.push_str(&format!("| {} |\n", columns.join(" | ")));
+            out.push_str(&format!("|{}\n", "---|".repeat(columns.len())));
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|cell| markdown_cell(cell)).collect();
+                out.push_str(&format!("| {} |\n", cells.join(" | ")));
+            }
+        }
+        out
+    }
+
+    pub fn to_html(&self) -> String {
+        let title = format!(
+            "omni {} digest for {}",
+            self.period,
+            html_escape(&self.host)
+        );
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n<p>{1}</p>\n<ul>\n",
+            title,
+            self.span()
+        );
+        for (label, value) in self.summary() {
+            out.push_str(&format!(
+                "<li><strong>{}:</strong> {}</li>\n",
+                label,
+                html_escape(&value)
+            ));
+        }
+        out.push_str("</ul>\n");
+
+        for (title, columns, rows) in self.sections() {
+            out.push_str(&format!(
+                "<h2>{}</h2>\n<table border=\"1\" cellpadding=\"4\">\n<tr>",
+                title
+            ));
+            for column in columns {
+                out.push_str(&format!("<th>{}</th>", column));
+            }
+            out.push_str("</tr>\n");
+            for row in rows {
+                out.push_str("<tr>");
+                for cell in row {
+                    out.push_str(&format!("<td>{}</td>", html_escape(&cell)));
+                }
+                out.push_str("</tr>\n");
+            }
+            out.push_str("</table>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+/// How used space went from `first` to `last` KiB
+fn size_change(first: u64, last: u64) -> String {
+    match last.cmp(&first) {
+        std::cmp::Ordering::Equal => "unchanged".to_string(),
+        std::cmp::Ordering::Greater => format!(
+            "+{}",
+            DependencyResolver::format_size((last - first) * 1024)
+        ),
+        std::cmp::Ordering::Less => format!(
+            "-{}",
+            DependencyResolver::format_size((first - last) * 1024)
+        ),
+    }
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Whether a digest for `period` is due when the last one went out at `last`
+pub fn due(period: DigestPeriod, last: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    period_start(period, now) >= last
+}
+
+fn record_rows(records: &[InstallRecord]) -> Vec<Vec<String>> {
+    records
+        .iter()
+        .map(|record| {
+            vec![
+                record.package_name.clone(),
+                record.box_type.clone(),
+                record.version.clone().unwrap_or_else(|| "-".to_string()),
+                record.installed_at.format("%Y-%m-%d %H:%M").to_string(),
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(package: &str, status: InstallStatus, metadata: Option<&str>) -> InstallRecord {
+        InstallRecord {
+            id: package.to_string(),
+            package_name: package.to_string(),
+            box_type: "apt".to_string(),
+            version: Some("1.0".to_string()),
+            source_url: None,
+            install_path: None,
+            installed_at: "2026-03-03T09:30:00Z".parse().unwrap(),
+            status,
+            repository: None,
+            mirror_url: None,
+            channel: None,
+            metadata: metadata.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_period_start() {
+        let until: DateTime<Utc> = "2026-03-31T12:00:00Z".parse().unwrap();
+        assert_eq!(
+            period_start(DigestPeriod::Week, until),
+            "2026-03-24T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            period_start(DigestPeriod::Month, until),
+            "2026-02-28T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        let last = period_start(DigestPeriod::Week, until);
+        assert!(due(DigestPeriod::Week, last, until));
+        assert!(!due(DigestPeriod::Week, last + Duration::hours(1), until));
+    }
+
+    #[test]
+    fn test_digest() {
+        let since = "2026-03-01T00:00:00Z".parse().unwrap();
+        let until = "2026-03-08T00:00:00Z".parse().unwrap();
+        let records = vec![
+            record("git", InstallStatus::Success, None),
+            record(
+                "openssl",
+                InstallStatus::Updated,
+                Some("Updated from version Some(\"3.0.1\"); fixes CVE-2024-5535, CVE-2024-4741"),
+            ),
+            record("curl", InstallStatus::Updated, Some("fixes CVE-2024-5535")),
+            record("bad<pkg>", InstallStatus::Failed, Some("exit | status 100")),
+        ];
+        let disk = [
+            DiskUsageSample {
+                recorded_at: since,
+                used_kib: 1024 * 1024,
+            },
+            DiskUsageSample {
+                recorded_at: until,
+                used_kib: 1536 * 1024,
+            },
+        ];
+
+        let digest = Digest::from_records(DigestPeriod::Week, since, until, records, &disk);
+        assert_eq!(digest.installed.len(), 1);
+        assert_eq!(digest.updated.len(), 2);
+        assert_eq!(digest.failed.len(), 1);
+        let cves: Vec<_> = digest
+            .cves_fixed
+            .iter()
+            .map(|cve| cve.id.as_str())
+            .collect();
+        assert_eq!(cves, ["CVE-2024-5535", "CVE-2024-4741"]);
+        assert_eq!(digest.cves_fixed[0].package_name, "openssl");
+
+        let markdown = digest.to_markdown();
+        assert!(markdown.contains("- **Updated:** 2\n"));
+        assert!(markdown.contains("- **Disk used:** 1.5 GB on / (+512.0 MB)\n"));
+        assert!(markdown.contains("\n## CVEs fixed\n\n| CVE | Package | Source |\n|---|---|---|\n"));
+        assert!(markdown.contains("| exit \\| status 100 |"));
+        assert!(!markdown.contains("## Removed"));
+
+        let html = digest.to_html();
+        assert!(html.contains("<td>bad&lt;pkg&gt;</td>"));
+        assert!(html.contains("<h2>Installed</h2>"));
+        assert_eq!(digest.render(ReportFormat::Html), html);
+    }
+}
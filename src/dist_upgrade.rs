@@ -0,0 +1,545 @@
+//! `omni dist-upgrade`: moves the system to the next major release with the
+//! distribution's own upgrade tool, after pre-flight checks and a snapshot,
+//! and verifies the result.
+
+use crate::config::{self, OmniConfig};
+use crate::doctor::{self, Check, Level};
+use crate::health;
+use crate::interactive::InteractivePrompts;
+use crate::lifecycle::os_release_field;
+use crate::privilege_manager::PrivilegeManager;
+use crate::snapshot::SnapshotManager;
+use crate::updater::UpdateManager;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Free space the upgrade tools need on `/` to download and unpack a release
+const MIN_FREE_KIB: u64 = 5 * 1024 * 1024;
+
+/// Distribution families with a supported major-release upgrade path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Family {
+    /// `do-release-upgrade`
+    Ubuntu,
+    /// `dnf system-upgrade`
+    Fedora,
+    /// `zypper dup` against the next Leap release
+    OpenSuseLeap,
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Family::Ubuntu => "Ubuntu",
+            Family::Fedora => "Fedora",
+            Family::OpenSuseLeap => "openSUSE Leap",
+        })
+    }
+}
+
+impl Family {
+    /// Family and `VERSION_ID` of a system from its `/etc/os-release`
+    pub fn detect(os_release: &str) -> Option<(Self, String)> {
+        let family = match os_release_field(os_release, "ID")?.as_str() {
+            "ubuntu" => Family::Ubuntu,
+            "fedora" => Family::Fedora,
+            "opensuse-leap" => Family::OpenSuseLeap,
+            _ => return None,
+        };
+        Some((family, os_release_field(os_release, "VERSION_ID")?))
+    }
+
+    /// The release after `current`. Ubuntu's tool picks it itself.
+    pub fn next_release(self, current: &str) -> Option<String> {
+        match self {
+            Family::Ubuntu => None,
+            Family::Fedora => current.parse::<u32>().ok().map(|v| (v + 1).to_string()),
+            Family::OpenSuseLeap => {
+                let (major, minor) = current.split_once('.')?;
+                Some(format!("{}.{}", major, minor.parse::<u32>().ok()? + 1))
+            }
+        }
+    }
+
+    /// Commands that download and apply the upgrade, run as root in order
+    pub fn steps(self, target: Option<&str>, yes: bool) -> Vec<Vec<String>> {
+        let step = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        match (self, target) {
+            (Family::Ubuntu, _) if yes => vec![step(&[
+                "do-release-upgrade",
+                "-f",
+                "DistUpgradeViewNonInteractive",
+            ])],
+            (Family::Ubuntu, _) => vec![step(&["do-release-upgrade"])],
+            (Family::Fedora, Some(target)) => {
+                let releasever = format!("--releasever={}", target);
+                let mut download = step(&["dnf", "system-upgrade", "download", &releasever]);
+                if yes {
+                    download.push("-y".to_string());
+                }
+                vec![step(&["dnf", "upgrade", "--refresh", "-y"]), download]
+            }
+            (Family::OpenSuseLeap, Some(target)) => {
+                let releasever = format!("--releasever={}", target);
+                let mut dup = step(&["zypper", &releasever, "dup", "--allow-vendor-change"]);
+                if yes {
+                    dup.insert(1, "--non-interactive".to_string());
+                }
+                vec![step(&["zypper", &releasever, "refresh"]), dup]
+            }
+            (Family::Fedora | Family::OpenSuseLeap, None) => Vec::new(),
+        }
+    }
+
+    /// Fedora installs the downloaded release on the next boot
+    fn installs_on_reboot(self) -> bool {
+        self == Family::Fedora
+    }
+
+    /// Repositories outside the distribution's own archive
+    fn third_party_repos(self) -> Vec<String> {
+        match self {
+            Family::Ubuntu => fs::read_dir("/etc/apt/sources.list.d")
+                .map(|entries| {
+                    entries
+                        .flatten()
+                        .filter(|entry| {
+                            fs::read_to_string(entry.path())
+                                .is_ok_and(|contents| !apt_third_party_uris(&contents).is_empty())
+                        })
+                        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            Family::Fedora => command_output("dnf", &["repolist", "--enabled"])
+                .map(|output| dnf_third_party_repos(&output))
+                .unwrap_or_default(),
+            Family::OpenSuseLeap => command_output("zypper", &["--non-interactive", "lr", "-u"])
+                .map(|output| zypper_third_party_repos(&output))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Package database consistency check after the upgrade
+    fn verify_command(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Family::Ubuntu => ("dpkg", &["--audit"]),
+            Family::Fedora => ("dnf", &["check"]),
+            Family::OpenSuseLeap => ("zypper", &["--non-interactive", "verify", "--dry-run"]),
+        }
+    }
+}
+
+/// An upgrade that was started and not yet verified
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpgradeState {
+    pub family: Family,
+    pub from: String,
+    /// Release asked for; `None` when the upgrade tool chooses
+    pub to: Option<String>,
+    pub snapshot_id: String,
+    pub started_at: DateTime<Utc>,
+}
+
+fn state_path() -> Result<PathBuf> {
+    Ok(OmniConfig::state_dir()?.join("dist-upgrade.json"))
+}
+
+fn load_state() -> Result<Option<UpgradeState>> {
+    let path = state_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(&path)?)?))
+}
+
+fn save_state(state: &UpgradeState) -> Result<()> {
+    let path = state_path()?;
+    if let Some(parent) = path.parent() {
+        config::ensure_private_dir(parent)?;
+    }
+    config::write_private_file(&path, serde_json::to_string_pretty(state)?.as_bytes())
+}
+
+fn detect() -> Result<(Family, String)> {
+    let os_release = fs::read_to_string("/etc/os-release").unwrap_or_default();
+    Family::detect(&os_release).ok_or_else(|| {
+        anyhow!("omni dist-upgrade supports Ubuntu, Fedora and openSUSE Leap; this system is none of them")
+    })
+}
+
+/// Checks that have to pass before the upgrade starts
+pub async fn preflight(config: &OmniConfig, family: Family) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    checks.push(
+        if PrivilegeManager::is_root() || PrivilegeManager::can_sudo() {
+            Check::new("Privileges", Level::Ok, "Upgrade commands can run as root")
+        } else {
+            Check::new(
+                "Privileges",
+                Level::Error,
+                "Run as root or as a user who can use sudo",
+            )
+        },
+    );
+
+    checks.push(match health::free_kib(Path::new("/")) {
+        Some(free) if free < MIN_FREE_KIB => Check::new(
+            "Disk space",
+            Level::Error,
+            format!(
+                "{} GiB free on /, the upgrade needs at least {} GiB",
+                free / 1024 / 1024,
+                MIN_FREE_KIB / 1024 / 1024
+            ),
+        ),
+        Some(free) => Check::new(
+            "Disk space",
+            Level::Ok,
+            format!("{} GiB free on /", free / 1024 / 1024),
+        ),
+        None => Check::new(
+            "Disk space",
+            Level::Warning,
+            "Could not read free space on /",
+        ),
+    });
+
+    let repos = family.third_party_repos();
+    checks.push(if repos.is_empty() {
+        Check::new("Third-party repositories", Level::Ok, "None enabled")
+    } else {
+        Check::new(
+            "Third-party repositories",
+            Level::Warning,
+            format!(
+                "{} may not have packages for the new release and can block the upgrade",
+                repos.join(", ")
+            ),
+        )
+    });
+
+    match UpdateManager::new(config.clone()).await {
+        Ok(manager) => {
+            let pinned: Vec<String> = manager
+                .list_pinned()
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .map(|pin| pin.package_name)
+                .collect();
+            checks.push(if pinned.is_empty() {
+                Check::new("Pinned packages", Level::Ok, "None")
+            } else {
+                Check::new(
+                    "Pinned packages",
+                    Level::Warning,
+                    format!(
+                        "{} will be upgraded anyway; pin them again afterwards if needed",
+                        pinned.join(", ")
+                    ),
+                )
+            });
+
+            checks.push(match manager.check_updates().await {
+                Ok(pending) if pending.is_empty() => Check::new(
+                    "Pending updates",
+                    Level::Ok,
+                    "The current release is up to date",
+                ),
+                Ok(pending) => Check::new(
+                    "Pending updates",
+                    Level::Error,
+                    format!(
+                        "{} updates are pending; run 'omni update --all' first",
+                        pending.len()
+                    ),
+                ),
+                Err(e) => Check::new("Pending updates", Level::Warning, e.to_string()),
+            });
+        }
+        Err(e) => checks.push(Check::new("Database", Level::Error, e.to_string())),
+    }
+
+    checks
+}
+
+/// Check, snapshot and upgrade to `target`, or to the next release
+pub async fn run(
+    config: &OmniConfig,
+    target: Option<String>,
+    check_only: bool,
+    yes: bool,
+) -> Result<()> {
+    let (family, current) = detect()?;
+    let target = match (family, target) {
+        (Family::Ubuntu, Some(_)) => {
+            return Err(anyhow!(
+                "do-release-upgrade always moves to the next supported release; drop --to"
+            ))
+        }
+        (_, Some(target)) => Some(target),
+        (_, None) => family.next_release(&current),
+    };
+    if family != Family::Ubuntu && target.is_none() {
+        return Err(anyhow!(
+            "Cannot tell the release after {} {}; pass it with --to",
+            family,
+            current
+        ));
+    }
+    let target_name = target.as_deref().unwrap_or("the next release");
+    println!("⬆️  {} {} -> {}\n", family, current, target_name);
+
+    let checks = preflight(config, family).await;
+    doctor::print(&checks);
+    println!();
+    let failed = checks.iter().filter(|c| c.level == Level::Error).count();
+    let warned = checks.iter().any(|c| c.level == Level::Warning);
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} pre-flight check(s) failed; fix them before upgrading",
+            failed
+        ));
+    }
+    if check_only {
+        println!("✅ Ready to upgrade");
+        return Ok(());
+    }
+
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow!("Pass --yes to upgrade without a terminal"));
+        }
+        let prompt = if warned {
+            format!("Upgrade to {} despite the warnings above?", target_name)
+        } else {
+            format!("Upgrade to {}?", target_name)
+        };
+        if !InteractivePrompts::new().get_confirmation(&prompt, !warned)? {
+            println!("❌ Upgrade cancelled");
+            return Ok(());
+        }
+    }
+
+    // No way back without one, so a failed snapshot stops the upgrade
+    let snapshot_id = SnapshotManager::new()
+        .await?
+        .create_snapshot(
+            &format!("dist-upgrade-{}", Utc::now().format("%Y%m%d-%H%M%S")),
+            Some(&format!(
+                "Before upgrading {} {} to {}",
+                family, current, target_name
+            )),
+        )
+        .await?;
+    println!("📸 Snapshot {} taken", snapshot_id);
+
+    let state = UpgradeState {
+        family,
+        from: current,
+        to: target.clone(),
+        snapshot_id,
+        started_at: Utc::now(),
+    };
+    save_state(&state)?;
+
+    for step in family.steps(target.as_deref(), yes) {
+        println!("🔄 {}", step.join(" "));
+        if let Err(e) = run_as_root(&step) {
+            println!(
+                "💡 Package state before the upgrade is in snapshot {}; 'omni snapshot revert {}' restores it",
+                state.snapshot_id, state.snapshot_id
+            );
+            return Err(e);
+        }
+    }
+
+    if family.installs_on_reboot() {
+        println!("✅ {} downloaded", target_name);
+        println!("   It is installed while the system reboots; run 'omni dist-upgrade --verify' afterwards");
+        let reboot = yes
+            || (std::io::stdin().is_terminal()
+                && InteractivePrompts::new().get_confirmation("Reboot now?", false)?);
+        if reboot {
+            run_as_root(&["dnf", "system-upgrade", "reboot"].map(String::from))?;
+        }
+        return Ok(());
+    }
+
+    verify()
+}
+
+/// Check that the upgrade started by `run` reached its release and left a
+/// consistent package database
+pub fn verify() -> Result<()> {
+    let state = load_state()?.ok_or_else(|| {
+        anyhow!("No distribution upgrade to verify; start one with 'omni dist-upgrade'")
+    })?;
+    let (family, current) = detect()?;
+
+    let reached = match &state.to {
+        Some(target) => &current == target,
+        None => current != state.from,
+    };
+    if family != state.family || !reached {
+        return Err(anyhow!(
+            "Still on {} {} after upgrading from {}; snapshot {} has the package state from before",
+            family,
+            current,
+            state.from,
+            state.snapshot_id
+        ));
+    }
+
+    let (command, args) = family.verify_command();
+    let output = Command::new(command).args(args).output()?;
+    let problems = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || (family == Family::Ubuntu && !problems.is_empty()) {
+        println!("{}", problems);
+        return Err(anyhow!(
+            "{} {} found package problems after the upgrade",
+            command,
+            args.join(" ")
+        ));
+    }
+
+    fs::remove_file(state_path()?)?;
+    println!("✅ Upgraded {} {} to {}", family, state.from, current);
+    if Path::new("/var/run/reboot-required").exists() {
+        println!("🔁 Reboot to finish the upgrade");
+    }
+    Ok(())
+}
+
+fn run_as_root(step: &[String]) -> Result<()> {
+    let mut command = if PrivilegeManager::is_root() {
+        Command::new(&step[0])
+    } else {
+        let mut sudo = Command::new("sudo");
+        sudo.arg(&step[0]);
+        sudo
+    };
+    let status = command.args(&step[1..]).status()?;
+    if !status.success() {
+        return Err(anyhow!("{} failed with {}", step.join(" "), status));
+    }
+    Ok(())
+}
+
+fn command_output(command: &str, args: &[&str]) -> Option<String> {
+    Command::new(command)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+}
+
+/// Repository URIs in an apt source file that are not Ubuntu's or Canonical's,
+/// in one-line or deb822 format
+pub fn apt_third_party_uris(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(|line| {
+            if let Some(uris) = line.strip_prefix("URIs:") {
+                uris.split_whitespace().collect::<Vec<_>>()
+            } else if line.starts_with("deb ") || line.starts_with("deb-src ") {
+                line.split_whitespace()
+                    .skip(1)
+                    .find(|word| word.contains("://"))
+                    .into_iter()
+                    .collect()
+            } else {
+                Vec::new()
+            }
+        })
+        .filter(|uri| !uri.contains("ubuntu.com") && !uri.contains("canonical.com"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Enabled repositories in `dnf repolist --enabled` output that Fedora does
+/// not ship
+pub fn dnf_third_party_repos(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("repo id"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|id| !id.starts_with("fedora") && !id.starts_with("updates"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Enabled repositories in `zypper lr -u` output served from outside
+/// opensuse.org
+pub fn zypper_third_party_repos(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split('|').map(str::trim).collect();
+            // # | Alias | Name | Enabled | GPG Check | Refresh | URI
+            let (alias, enabled, uri) = (columns.get(1)?, columns.get(3)?, columns.last()?);
+            (*enabled == "Yes" && uri.contains("://") && !uri.contains("opensuse.org"))
+                .then(|| alias.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_family_steps() {
+        let fedora = "NAME=\"Fedora Linux\"\nVERSION_ID=40\nID=fedora\n";
+        let (family, current) = Family::detect(fedora).unwrap();
+        assert_eq!((family, current.as_str()), (Family::Fedora, "40"));
+        assert_eq!(family.next_release(&current).as_deref(), Some("41"));
+        assert_eq!(
+            family.steps(Some("41"), true)[1].join(" "),
+            "dnf system-upgrade download --releasever=41 -y"
+        );
+
+        let leap = "NAME=\"openSUSE Leap\"\nVERSION_ID=\"15.5\"\nID=\"opensuse-leap\"\n";
+        let (family, current) = Family::detect(leap).unwrap();
+        assert_eq!(family.next_release(&current).as_deref(), Some("15.6"));
+        assert_eq!(
+            family.steps(Some("15.6"), true)[1].join(" "),
+            "zypper --non-interactive --releasever=15.6 dup --allow-vendor-change"
+        );
+
+        let ubuntu = "ID=ubuntu\nVERSION_ID=\"22.04\"\n";
+        let (family, _) = Family::detect(ubuntu).unwrap();
+        assert_eq!(family.steps(None, false), vec![vec!["do-release-upgrade"]]);
+        assert_eq!(Family::detect("ID=arch\n"), None);
+    }
+
+    #[test]
+    fn test_preflight_parsers() {
+        let list = "deb http://archive.ubuntu.com/ubuntu noble main\n# deb http://old.example.com stable main\ndeb [arch=amd64 signed-by=/etc/apt/keyrings/docker.gpg] https://download.docker.com/linux/ubuntu noble stable\n";
+        assert_eq!(
+            apt_third_party_uris(list),
+            vec!["https://download.docker.com/linux/ubuntu"]
+        );
+        let deb822 = "Types: deb\nURIs: http://archive.ubuntu.com/ubuntu/\nSuites: noble\n";
+        assert!(apt_third_party_uris(deb822).is_empty());
+
+        let repolist = "repo id                       repo name\nfedora                        Fedora 40 - x86_64\nupdates                       Fedora 40 - x86_64 - Updates\nrpmfusion-free                RPM Fusion for Fedora 40 - Free\n";
+        assert_eq!(dnf_third_party_repos(repolist), vec!["rpmfusion-free"]);
+
+        let zypper = "# | Alias      | Name       | Enabled | GPG Check | Refresh | URI\n--+------------+------------+---------+-----------+---------+----\n1 | repo-oss   | Main       | Yes     | (r ) Yes  | Yes     | http://download.opensuse.org/distribution/leap/15.5/repo/oss/\n2 | packman    | Packman    | Yes     | (r ) Yes  | Yes     | https://ftp.gwdg.de/pub/linux/misc/packman/suse/openSUSE_Leap_15.5/\n3 | vscode     | VS Code    | No      | ----      | ----    | https://packages.microsoft.com/yumrepos/vscode\n";
+        assert_eq!(zypper_third_party_repos(zypper), vec!["packman"]);
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use std::fs;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -154,6 +154,83 @@ pub fn command_exists(cmd: &str) -> bool {
     }
 }
 
+/// Machines packages can be built for, by their Debian and their rpm names
+const ARCHITECTURES: &[(&str, &str)] = &[
+    ("amd64", "x86_64"),
+    ("i386", "i686"),
+    ("arm64", "aarch64"),
+    ("armhf", "armv7hl"),
+    ("ppc64el", "ppc64le"),
+    ("s390x", "s390x"),
+    ("riscv64", "riscv64"),
+];
+
+/// Whether `arch` names a machine by its Debian or its rpm name
+pub fn is_architecture(arch: &str) -> bool {
+    ARCHITECTURES
+        .iter()
+        .any(|(deb, rpm)| *deb == arch || *rpm == arch)
+}
+
+/// `box_type`'s own name for the machine `arch` names, so `i386` and `i686`
+/// both become `i686` for dnf; None for boxes without architectures
+pub fn architecture_for(box_type: &str, arch: &str) -> Option<&'static str> {
+    let (deb, rpm) = ARCHITECTURES
+        .iter()
+        .find(|(deb, rpm)| *deb == arch || *rpm == arch)?;
+    match box_type {
+        "apt" => Some(deb),
+        "dnf" => Some(rpm),
+        _ => None,
+    }
+}
+
+/// Architecture `box_type` installs packages for by default
+pub fn native_architecture(box_type: &str) -> Option<String> {
+    let output = match box_type {
+        "apt" => std::process::Command::new("dpkg")
+            .arg("--print-architecture")
+            .output(),
+        "dnf" => std::process::Command::new("rpm")
+            .args(["--eval", "%{_arch}"])
+            .output(),
+        _ => return None,
+    }
+    .ok()?;
+    let arch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !arch.is_empty()).then_some(arch)
+}
+
+/// Extra architectures dpkg has been told to accept packages for
+/// (`dpkg --add-architecture`)
+pub fn foreign_architectures() -> Vec<String> {
+    std::process::Command::new("dpkg")
+        .arg("--print-foreign-architectures")
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The name `box_type` installs `package` built for `arch` under:
+/// `libc6:i386` for apt, `glibc.i686` for dnf
+pub fn qualify_package(box_type: &str, package: &str, arch: &str) -> Result<String> {
+    let arch = architecture_for(box_type, arch).ok_or_else(|| {
+        anyhow!(
+            "{} does not install packages for other architectures; use apt or dnf",
+            box_type
+        )
+    })?;
+    Ok(match box_type {
+        "apt" => format!("{}:{}", package, arch),
+        _ => format!("{}.{}", package, arch),
+    })
+}
+
 pub fn get_os_display_name() -> String {
     match detect_os() {
         OperatingSystem::Linux(distro) => {
@@ -219,6 +296,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_qualify_package() {
+        assert_eq!(
+            qualify_package("apt", "libc6", "i386").unwrap(),
+            "libc6:i386"
+        );
+        assert_eq!(
+            qualify_package("apt", "libc6", "i686").unwrap(),
+            "libc6:i386"
+        );
+        assert_eq!(
+            qualify_package("dnf", "glibc", "i386").unwrap(),
+            "glibc.i686"
+        );
+        assert_eq!(
+            qualify_package("dnf", "glibc", "arm64").unwrap(),
+            "glibc.aarch64"
+        );
+        assert!(qualify_package("pacman", "glibc", "i386").is_err());
+        assert!(is_architecture("armhf"));
+        assert!(!is_architecture("sparc"));
+    }
+
     #[test]
     fn test_package_manager_detection() {
         let managers = get_available_package_managers();
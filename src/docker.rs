@@ -22,6 +22,9 @@ pub struct DockerConfig {
     pub restart_policy: RestartPolicy,
     pub resources: ResourceLimits,
     pub security_options: SecurityOptions,
+    /// Command to run instead of the image's default
+    #[serde(default)]
+    pub command: Vec<String>,
 }
 
 impl Default for DockerConfig {
@@ -39,6 +42,7 @@ impl Default for DockerConfig {
             restart_policy: RestartPolicy::No,
             resources: ResourceLimits::default(),
             security_options: SecurityOptions::default(),
+            command: Vec::new(),
         }
     }
 }
@@ -188,6 +192,11 @@ impl DockerClient {
         Ok(Self { docker_command })
     }
 
+    /// The container runtime in use, `docker` or `podman`
+    pub fn runtime(&self) -> &str {
+        &self.docker_command
+    }
+
     /// Check if Docker daemon is running
     pub async fn check_daemon(&self) -> Result<bool> {
         let output = Command::new(&self.docker_command)
@@ -306,8 +315,9 @@ impl DockerClient {
         };
         args.extend(vec!["--restart".to_string(), restart_policy.to_string()]);
 
-        // Add image
+        // Add image and command
         args.push(format!("{}:{}", config.image, config.tag));
+        args.extend(config.command.iter().cloned());
 
         // Execute docker run command
         let output = Command::new(&self.docker_command)
@@ -0,0 +1,102 @@
+//! `omni doctor`: checks the system omni runs on and says what to fix.
+
+use crate::config::OmniConfig;
+use crate::health;
+use crate::lifecycle::{self, Status, Support};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub level: Level,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(name: &'static str, level: Level, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            level,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Release support followed by the `omni health` checks
+pub async fn run(config: &OmniConfig) -> Vec<Check> {
+    let mut checks = vec![release_check(
+        lifecycle::check(&config.release_support).await,
+    )];
+    checks.extend(health::run().await.checks);
+    checks
+}
+
+/// How the support status of the running release rates
+pub fn release_check(status: Option<Status>) -> Check {
+    let Some(status) = status else {
+        return Check::new(
+            "Release support",
+            Level::Ok,
+            "Rolling or unrecognised release; no end of life to track",
+        );
+    };
+    let level = match status.support {
+        Support::Supported { .. } | Support::Unknown => Level::Ok,
+        Support::EndingSoon { .. } => Level::Warning,
+        Support::Ended { .. } => Level::Error,
+    };
+    Check::new("Release support", level, status.describe())
+}
+
+pub fn print(checks: &[Check]) {
+    for check in checks {
+        let icon = match check.level {
+            Level::Ok => "✅",
+            Level::Warning => "⚠️ ",
+            Level::Error => "❌",
+        };
+        println!("{} {}: {}", icon, check.name, check.detail);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::{Release, Source};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_release_check_levels() {
+        let status = |support| Status {
+            release: Release {
+                product: "debian".to_string(),
+                cycle: "11".to_string(),
+                name: "Debian GNU/Linux 11 (bullseye)".to_string(),
+            },
+            support,
+            source: Some(Source::Bundled),
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 8, 14).unwrap();
+
+        let ended = release_check(Some(status(Support::Ended { since: Some(date) })));
+        assert_eq!(ended.level, Level::Error);
+        assert!(ended.detail.contains("2024-08-14"));
+        assert!(ended.detail.ends_with("(bundled data)"));
+
+        let ending = release_check(Some(status(Support::EndingSoon { until: date })));
+        assert_eq!(ending.level, Level::Warning);
+        assert_eq!(
+            release_check(Some(status(Support::Unknown))).level,
+            Level::Ok
+        );
+        assert_eq!(release_check(None).level, Level::Ok);
+    }
+}
@@ -0,0 +1,200 @@
+//! Hands configuration over to a dotfiles manager (chezmoi, stow or any
+//! command) once a manifest's packages are installed, so one run provisions
+//! both.
+
+use crate::config::{DotfilesConfig, DotfilesManager};
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The command the hook runs, or `None` when no manager is configured.
+/// `manifest` is the applied manifest's path, available as `{manifest}`.
+pub fn command_line(
+    config: &DotfilesConfig,
+    manifest: Option<&str>,
+) -> Result<Option<Vec<String>>> {
+    let Some(manager) = config.manager else {
+        return Ok(None);
+    };
+
+    let template = match manager {
+        DotfilesManager::Chezmoi if config.repo.is_some() => {
+            "chezmoi init --apply {repo}".to_string()
+        }
+        DotfilesManager::Chezmoi => "chezmoi apply".to_string(),
+        DotfilesManager::Stow => {
+            if config.repo.is_none() || config.packages.is_empty() {
+                return Err(anyhow!(
+                    "dotfiles.manager stow needs dotfiles.repo (the stow directory) and dotfiles.packages"
+                ));
+            }
+            "stow --dir {repo} --target {home} --restow {packages}".to_string()
+        }
+        DotfilesManager::Command => config
+            .command
+            .clone()
+            .ok_or_else(|| anyhow!("dotfiles.manager command needs dotfiles.command"))?,
+    };
+
+    let home = dirs::home_dir().map(|h| h.display().to_string());
+    let repo = config
+        .repo
+        .as_deref()
+        .map(|repo| expand_home(repo, home.as_deref()));
+    let values = [
+        ("{repo}", repo.as_deref()),
+        ("{home}", home.as_deref()),
+        ("{manifest}", manifest),
+    ];
+
+    let mut argv = Vec::new();
+    for word in split_words(&template)? {
+        if word == "{packages}" {
+            argv.extend(config.packages.iter().cloned());
+            continue;
+        }
+        let mut word = word;
+        for (placeholder, value) in values {
+            if word.contains(placeholder) {
+                let value = value.ok_or_else(|| {
+                    anyhow!("'{}' uses {} but it has no value", template, placeholder)
+                })?;
+                word = word.replace(placeholder, value);
+            }
+        }
+        argv.push(word);
+    }
+
+    if argv.is_empty() {
+        return Err(anyhow!("dotfiles.command is empty"));
+    }
+    Ok(Some(argv))
+}
+
+fn expand_home(path: &str, home: Option<&str>) -> String {
+    match (path.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest).display().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+/// Split a command template into arguments. Single and double quotes group
+/// words; there is no shell, so placeholders cannot inject commands.
+pub fn split_words(template: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+
+    for c in template.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow!("Unbalanced quote in '{}'", template));
+    }
+    if in_word {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+/// Run the dotfiles hook as the current user
+pub fn apply(argv: &[String], dry_run: bool) -> Result<()> {
+    let Some((program, args)) = argv.split_first() else {
+        return Ok(());
+    };
+    println!("🏠 Applying dotfiles: {}", argv.join(" "));
+    if dry_run {
+        return Ok(());
+    }
+
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| anyhow!("Cannot run {}: {}", program, e))?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", program, status));
+    }
+    println!("✅ Dotfiles applied");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(manager: DotfilesManager) -> DotfilesConfig {
+        DotfilesConfig {
+            manager: Some(manager),
+            ..DotfilesConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_manager_command_lines() {
+        assert_eq!(
+            command_line(&DotfilesConfig::default(), None).unwrap(),
+            None
+        );
+
+        let mut chezmoi = config(DotfilesManager::Chezmoi);
+        assert_eq!(
+            command_line(&chezmoi, None).unwrap().unwrap(),
+            vec!["chezmoi", "apply"]
+        );
+        chezmoi.repo = Some("github.com/me/dotfiles".to_string());
+        assert_eq!(
+            command_line(&chezmoi, None).unwrap().unwrap(),
+            vec!["chezmoi", "init", "--apply", "github.com/me/dotfiles"]
+        );
+
+        let mut stow = config(DotfilesManager::Stow);
+        assert!(command_line(&stow, None).is_err());
+        stow.repo = Some("/srv/dotfiles".to_string());
+        stow.packages = vec!["zsh".to_string(), "git".to_string()];
+        let argv = command_line(&stow, None).unwrap().unwrap();
+        assert_eq!(argv[..3], ["stow", "--dir", "/srv/dotfiles"]);
+        assert_eq!(argv[5..], ["--restow", "zsh", "git"]);
+    }
+
+    #[test]
+    fn test_command_template() {
+        let mut custom = config(DotfilesManager::Command);
+        assert!(command_line(&custom, None).is_err());
+
+        custom.command = Some("yadm bootstrap --label 'from {manifest}'".to_string());
+        assert_eq!(
+            command_line(&custom, Some("dev.yml")).unwrap().unwrap(),
+            vec!["yadm", "bootstrap", "--label", "from dev.yml"]
+        );
+        assert!(command_line(&custom, None).is_err());
+
+        // Values are substituted into single arguments, never re-split
+        custom.command = Some("echo {repo}".to_string());
+        custom.repo = Some("a; rm -rf b".to_string());
+        assert_eq!(
+            command_line(&custom, None).unwrap().unwrap(),
+            vec!["echo", "a; rm -rf b"]
+        );
+
+        assert!(split_words("echo 'unterminated").is_err());
+        assert_eq!(split_words(" a  \"\" b ").unwrap(), vec!["a", "", "b"]);
+    }
+}
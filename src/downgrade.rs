@@ -0,0 +1,584 @@
+use crate::distro;
+use crate::provenance;
+use anyhow::{anyhow, Result};
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use std::process::Command;
+use tracing::debug;
+
+const PACMAN_CACHE_DIR: &str = "/var/cache/pacman/pkg";
+const APT_ARCHIVES_DIR: &str = "/var/cache/apt/archives";
+/// Repository caches of dnf and dnf5, each holding a `packages` directory per repository
+const DNF_CACHE_DIRS: &[&str] = &["/var/cache/dnf", "/var/cache/libdnf5"];
+
+/// Installed and installable versions of a package, newest first.
+///
+/// For snap the entries are revisions and for flatpak they are commits,
+/// since those are what the backends can roll back to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionListing {
+    pub installed: Option<String>,
+    pub available: Vec<String>,
+}
+
+impl VersionListing {
+    /// Version to downgrade to: `requested` if it is older than the installed one,
+    /// otherwise the next version below the installed one
+    pub fn downgrade_target(&self, package: &str, requested: Option<&str>) -> Result<String> {
+        let installed = self
+            .installed
+            .as_deref()
+            .ok_or_else(|| anyhow!("{} is not installed", package))?;
+        let installed_pos = self.available.iter().position(|v| v == installed);
+
+        match requested {
+            Some(version) => {
+                if version == installed {
+                    return Err(anyhow!("{} {} is already installed", package, version));
+                }
+                let requested_pos = self
+                    .available
+                    .iter()
+                    .position(|v| v == version)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Version {} of {} is not available (available: {})",
+                            version,
+                            package,
+                            self.available.join(", ")
+                        )
+                    })?;
+                if installed_pos.is_some_and(|pos| requested_pos < pos) {
+                    return Err(anyhow!(
+                        "{} {} is newer than the installed {}; use 'omni update' instead",
+                        package,
+                        version,
+                        installed
+                    ));
+                }
+                Ok(version.to_string())
+            }
+            None => installed_pos
+                .and_then(|pos| self.available.get(pos + 1))
+                .cloned()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No older version of {} is available; pass --to <version>",
+                        package
+                    )
+                }),
+        }
+    }
+}
+
+const DOWNGRADE_NOTE_PREFIX: &str = "Downgraded from version ";
+
+/// Metadata stored on a downgrade record; `downgraded_from` reads it back for undo
+pub fn downgrade_note(previous_version: &str) -> String {
+    format!("{}{}", DOWNGRADE_NOTE_PREFIX, previous_version)
+}
+
+pub fn downgraded_from(metadata: &str) -> Option<&str> {
+    metadata
+        .strip_prefix(DOWNGRADE_NOTE_PREFIX)
+        .filter(|version| !version.is_empty())
+}
+
+/// Ask the backend which versions of `package` it can install
+pub fn query_versions(package: &str, box_type: &str) -> Result<VersionListing> {
+    let listing = match box_type {
+        "apt" if distro::command_exists("apt-cache") => VersionListing {
+            installed: run("dpkg-query", &["-W", "-f=${Version}", package])
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| v.trim().to_string()),
+            available: parse_apt_madison(&run_required("apt-cache", &["madison", package])?),
+        },
+        "dnf" if distro::command_exists("dnf") => parse_dnf_showduplicates(
+            &run_required("dnf", &["list", "--showduplicates", package])?,
+            package,
+        ),
+        "pacman" if distro::command_exists("pacman") => {
+            let installed = run("pacman", &["-Q", package])
+                .and_then(|out| out.split_whitespace().nth(1).map(|v| v.to_string()));
+            let mut available: Vec<String> = pacman_cached_packages(package)
+                .into_iter()
+                .map(|(version, _)| version)
+                .collect();
+            sort_newest_first(&mut available);
+            VersionListing {
+                installed,
+                available,
+            }
+        }
+        "snap" if distro::command_exists("snap") => {
+            parse_snap_list_all(&run_required("snap", &["list", "--all", package])?)
+        }
+        "flatpak" if distro::command_exists("flatpak") => {
+            let remote = provenance::detect(package, "flatpak")
+                .repository
+                .unwrap_or_else(|| "flathub".to_string());
+            VersionListing {
+                installed: run("flatpak", &["info", "--show-commit", package])
+                    .map(|c| c.trim().to_string())
+                    .filter(|c| !c.is_empty()),
+                available: parse_flatpak_log(&run_required(
+                    "flatpak",
+                    &["remote-info", "--log", &remote, package],
+                )?),
+            }
+        }
+        _ => {
+            return Err(anyhow!(
+                "Downgrading is not supported for box type '{}'",
+                box_type
+            ))
+        }
+    };
+
+    debug!("Versions of {} via {}: {:?}", package, box_type, listing);
+    Ok(listing)
+}
+
+/// Boxes that can install a version omni picks, which version constraints
+/// need; pacman only from its package cache
+pub const VERSIONED_BOXES: &[&str] = &["apt", "dnf", "pacman"];
+
+/// Command line that installs exactly `version` of `package`, in either direction.
+/// apt and dnf install the package from their download cache when it is
+/// still there, so a version gone from the repositories can come back.
+pub fn install_version_command(
+    package: &str,
+    box_type: &str,
+    version: &str,
+) -> Result<(String, Vec<String>)> {
+    let cached = cached_archive(package, box_type, version);
+    let (command, args) = match box_type {
+        "apt" => (
+            "apt-get",
+            vec![
+                "install".to_string(),
+                "-y".to_string(),
+                "--allow-downgrades".to_string(),
+                cached.unwrap_or_else(|| format!("{}={}", package, version)),
+            ],
+        ),
+        "dnf" => (
+            "dnf",
+            vec![
+                "install".to_string(),
+                "-y".to_string(),
+                cached.unwrap_or_else(|| format!("{}-{}", package, version)),
+            ],
+        ),
+        "pacman" => {
+            let path = cached.ok_or_else(|| {
+                anyhow!(
+                    "{} {} is not in the pacman cache ({})",
+                    package,
+                    version,
+                    PACMAN_CACHE_DIR
+                )
+            })?;
+            (
+                "pacman",
+                vec!["-U".to_string(), "--noconfirm".to_string(), path],
+            )
+        }
+        "snap" => (
+            "snap",
+            vec![
+                "revert".to_string(),
+                package.to_string(),
+                format!("--revision={}", version),
+            ],
+        ),
+        "flatpak" => (
+            "flatpak",
+            vec![
+                "update".to_string(),
+                "-y".to_string(),
+                format!("--commit={}", version),
+                package.to_string(),
+            ],
+        ),
+        _ => {
+            return Err(anyhow!(
+                "Downgrading is not supported for box type '{}'",
+                box_type
+            ))
+        }
+    };
+
+    Ok((command.to_string(), args))
+}
+
+fn run(command: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(command).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn run_required(command: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(command).args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(anyhow!(
+            "{} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// `apt-cache madison` lists `pkg | version | source` rows, newest first
+pub fn parse_apt_madison(output: &str) -> Vec<String> {
+    let mut versions: Vec<String> = Vec::new();
+    for line in output.lines() {
+        let mut columns = line.split('|').map(str::trim);
+        if let (Some(_), Some(version)) = (columns.next(), columns.next()) {
+            if !version.is_empty() && !versions.iter().any(|v| v == version) {
+                versions.push(version.to_string());
+            }
+        }
+    }
+    versions
+}
+
+/// `dnf list --showduplicates` prints `name.arch version repo` under
+/// "Installed Packages" and "Available Packages" headings
+pub fn parse_dnf_showduplicates(output: &str, package: &str) -> VersionListing {
+    let mut listing = VersionListing::default();
+    let mut in_installed = false;
+    let prefix = format!("{}.", package);
+
+    for line in output.lines() {
+        match line.trim() {
+            "Installed Packages" => {
+                in_installed = true;
+                continue;
+            }
+            "Available Packages" => {
+                in_installed = false;
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut parts = line.split_whitespace();
+        let (Some(name), Some(version)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if !name.starts_with(&prefix) {
+            continue;
+        }
+
+        if in_installed {
+            listing.installed = Some(version.to_string());
+        }
+        if !listing.available.iter().any(|v| v == version) {
+            listing.available.push(version.to_string());
+        }
+    }
+
+    sort_newest_first(&mut listing.available);
+    listing
+}
+
+/// `snap list --all` prints every revision still on disk; the active one is not marked disabled
+pub fn parse_snap_list_all(output: &str) -> VersionListing {
+    let mut listing = VersionListing::default();
+
+    for line in output.lines().skip(1) {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.len() < 3 {
+            continue;
+        }
+        let revision = columns[2].to_string();
+        let disabled = columns
+            .last()
+            .is_some_and(|notes| notes.contains("disabled"));
+        if !disabled {
+            listing.installed = Some(revision.clone());
+        }
+        listing.available.push(revision);
+    }
+
+    sort_newest_first(&mut listing.available);
+    listing
+}
+
+/// Commits from `flatpak remote-info --log`, newest first
+pub fn parse_flatpak_log(output: &str) -> Vec<String> {
+    let mut commits: Vec<String> = Vec::new();
+    for line in output.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            if key.trim() == "Commit" && !value.is_empty() && !commits.iter().any(|c| c == value) {
+                commits.push(value.to_string());
+            }
+        }
+    }
+    commits
+}
+
+/// Path of the downloaded archive of `version` of `package`, if the box still has it
+pub fn cached_archive(package: &str, box_type: &str, version: &str) -> Option<String> {
+    match box_type {
+        "apt" => find_archive(&[PathBuf::from(APT_ARCHIVES_DIR)], |file_name| {
+            parse_deb_archive_name(file_name, package).as_deref() == Some(version)
+        }),
+        "dnf" => {
+            // rpm file names leave out the epoch
+            let version = split_epoch(version).1;
+            let dirs: Vec<PathBuf> = DNF_CACHE_DIRS
+                .iter()
+                .filter_map(|dir| std::fs::read_dir(dir).ok())
+                .flatten()
+                .filter_map(|e| e.ok())
+                .map(|repo| repo.path().join("packages"))
+                .collect();
+            find_archive(&dirs, |file_name| {
+                parse_rpm_archive_name(file_name, package).as_deref() == Some(version)
+            })
+        }
+        "pacman" => pacman_cached_packages(package)
+            .into_iter()
+            .find(|(cached, _)| cached == version)
+            .map(|(_, path)| path),
+        _ => None,
+    }
+}
+
+fn find_archive(dirs: &[PathBuf], matches: impl Fn(&str) -> bool) -> Option<String> {
+    dirs.iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|e| e.ok())
+        .find(|entry| matches(&entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path().to_string_lossy().to_string())
+}
+
+/// Version of `name_version_arch.deb` if the archive belongs to `package`;
+/// apt writes the epoch's `:` as `%3a`
+pub fn parse_deb_archive_name(file_name: &str, package: &str) -> Option<String> {
+    let rest = file_name.strip_prefix(package)?.strip_prefix('_')?;
+    let (version, _arch) = rest.strip_suffix(".deb")?.rsplit_once('_')?;
+    // Package names never contain '_', so anything else is a different package
+    (!version.contains('_')).then(|| version.replace("%3a", ":"))
+}
+
+/// `version-release` of `name-version-release.arch.rpm` if the archive belongs to `package`
+pub fn parse_rpm_archive_name(file_name: &str, package: &str) -> Option<String> {
+    let rest = file_name.strip_prefix(package)?.strip_prefix('-')?;
+    let (version, _arch) = rest.strip_suffix(".rpm")?.rsplit_once('.')?;
+    // version and release never contain '-', so anything else is a different package
+    (version.matches('-').count() == 1).then(|| version.to_string())
+}
+
+/// `(pkgver-pkgrel, path)` for every cached pacman archive of `package`
+fn pacman_cached_packages(package: &str) -> Vec<(String, String)> {
+    let Ok(entries) = std::fs::read_dir(PACMAN_CACHE_DIR) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let version = parse_pacman_archive_name(&file_name, package)?;
+            Some((version, entry.path().to_string_lossy().to_string()))
+        })
+        .collect()
+}
+
+/// Version of `name-pkgver-pkgrel-arch.pkg.tar.*` if the archive belongs to `package`
+pub fn parse_pacman_archive_name(file_name: &str, package: &str) -> Option<String> {
+    if file_name.ends_with(".sig") {
+        return None;
+    }
+    let rest = file_name.strip_prefix(package)?.strip_prefix('-')?;
+    let stem = &rest[..rest.find(".pkg.tar")?];
+
+    // pkgver, pkgrel and arch never contain '-', so anything else is a different package
+    let parts: Vec<&str> = stem.split('-').collect();
+    match parts.as_slice() {
+        [version, release, _arch] => Some(format!("{}-{}", version, release)),
+        _ => None,
+    }
+}
+
+fn sort_newest_first(versions: &mut [String]) {
+    versions.sort_by(|a, b| compare_versions(b, a));
+}
+
+/// rpmvercmp-style comparison: optional `epoch:`, then alternating numeric and
+/// alphabetic segments, numeric segments compared as numbers
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (epoch_a, rest_a) = split_epoch(a);
+    let (epoch_b, rest_b) = split_epoch(b);
+    epoch_a
+        .cmp(&epoch_b)
+        .then_with(|| compare_segments(rest_a, rest_b))
+}
+
+fn split_epoch(version: &str) -> (u64, &str) {
+    match version.split_once(':') {
+        Some((epoch, rest)) => match epoch.parse() {
+            Ok(epoch) => (epoch, rest),
+            Err(_) => (0, version),
+        },
+        None => (0, version),
+    }
+}
+
+fn compare_segments(a: &str, b: &str) -> Ordering {
+    let segments_a = segments(a);
+    let segments_b = segments(b);
+
+    for (x, y) in segments_a.iter().zip(&segments_b) {
+        let x_numeric = x.chars().all(|c| c.is_ascii_digit());
+        let y_numeric = y.chars().all(|c| c.is_ascii_digit());
+
+        let ordering = match (x_numeric, y_numeric) {
+            (true, true) => {
+                let x = x.trim_start_matches('0');
+                let y = y.trim_start_matches('0');
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            // A numeric segment is newer than an alphabetic one
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    segments_a.len().cmp(&segments_b.len())
+}
+
+fn segments(version: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = None;
+    let mut numeric = false;
+
+    for (i, c) in version.char_indices() {
+        if !c.is_ascii_alphanumeric() {
+            if let Some(s) = start.take() {
+                segments.push(&version[s..i]);
+            }
+            continue;
+        }
+        match start {
+            Some(s) if c.is_ascii_digit() != numeric => {
+                segments.push(&version[s..i]);
+                start = Some(i);
+                numeric = c.is_ascii_digit();
+            }
+            Some(_) => {}
+            None => {
+                start = Some(i);
+                numeric = c.is_ascii_digit();
+            }
+        }
+    }
+    if let Some(s) = start {
+        segments.push(&version[s..]);
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(
+            compare_versions("8.2.1-3.fc39", "8.2.1-1.fc39"),
+            Ordering::Greater
+        );
+        assert_eq!(compare_versions("1:1.0", "2.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0", "1.0.1"), Ordering::Less);
+        assert_eq!(compare_versions("2.0-1", "2.0-1"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_parse_backend_listings() {
+        let madison = "      curl | 7.81.0-1ubuntu1.15 | http://archive.ubuntu.com/ubuntu jammy-updates/main amd64 Packages
+      curl | 7.81.0-1ubuntu1.15 | http://security.ubuntu.com/ubuntu jammy-security/main amd64 Packages
+      curl | 7.81.0-1 | http://archive.ubuntu.com/ubuntu jammy/main amd64 Packages
+";
+        assert_eq!(
+            parse_apt_madison(madison),
+            vec!["7.81.0-1ubuntu1.15", "7.81.0-1"]
+        );
+
+        let dnf = "Installed Packages
+curl.x86_64          8.2.1-3.fc39          @updates
+Available Packages
+curl.x86_64          8.2.1-1.fc39          fedora
+curl.x86_64          8.2.1-3.fc39          updates
+curl-minimal.x86_64  8.2.1-3.fc39          updates
+";
+        let listing = parse_dnf_showduplicates(dnf, "curl");
+        assert_eq!(listing.installed.as_deref(), Some("8.2.1-3.fc39"));
+        assert_eq!(listing.available, vec!["8.2.1-3.fc39", "8.2.1-1.fc39"]);
+
+        let snap = "Name     Version  Rev   Tracking       Publisher   Notes
+firefox  120.0    3358  latest/stable  mozilla✓    disabled
+firefox  121.0    3504  latest/stable  mozilla✓    -
+";
+        let listing = parse_snap_list_all(snap);
+        assert_eq!(listing.installed.as_deref(), Some("3504"));
+        assert_eq!(listing.available, vec!["3504", "3358"]);
+
+        assert_eq!(
+            parse_pacman_archive_name("curl-8.5.0-1-x86_64.pkg.tar.zst", "curl").as_deref(),
+            Some("8.5.0-1")
+        );
+        assert!(parse_pacman_archive_name("curl-8.5.0-1-x86_64.pkg.tar.zst.sig", "curl").is_none());
+        assert!(parse_pacman_archive_name("curl-rustls-1.0-1-any.pkg.tar.zst", "curl").is_none());
+
+        assert_eq!(
+            parse_deb_archive_name("vim_2%3a9.1.0016-1ubuntu7_amd64.deb", "vim").as_deref(),
+            Some("2:9.1.0016-1ubuntu7")
+        );
+        assert!(parse_deb_archive_name("vim_2%3a9.1.0016-1ubuntu7_amd64.deb", "vi").is_none());
+        assert!(parse_deb_archive_name("vim-tiny_2%3a9.1.0016-1_amd64.deb", "vim").is_none());
+
+        assert_eq!(
+            parse_rpm_archive_name("curl-8.2.1-1.fc39.x86_64.rpm", "curl").as_deref(),
+            Some("8.2.1-1.fc39")
+        );
+        assert!(parse_rpm_archive_name("curl-minimal-8.2.1-1.fc39.x86_64.rpm", "curl").is_none());
+    }
+
+    #[test]
+    fn test_downgrade_target() {
+        let listing = VersionListing {
+            installed: Some("2.0".to_string()),
+            available: vec!["3.0".to_string(), "2.0".to_string(), "1.0".to_string()],
+        };
+
+        assert_eq!(listing.downgrade_target("foo", None).unwrap(), "1.0");
+        assert_eq!(listing.downgrade_target("foo", Some("1.0")).unwrap(), "1.0");
+        assert!(listing.downgrade_target("foo", Some("3.0")).is_err());
+        assert!(listing.downgrade_target("foo", Some("2.0")).is_err());
+        assert!(listing.downgrade_target("foo", Some("0.5")).is_err());
+
+        assert_eq!(downgraded_from(&downgrade_note("2.0")), Some("2.0"));
+
+        let oldest = VersionListing {
+            installed: Some("1.0".to_string()),
+            available: vec!["2.0".to_string(), "1.0".to_string()],
+        };
+        assert!(oldest.downgrade_target("foo", None).is_err());
+    }
+}
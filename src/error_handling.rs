@@ -74,6 +74,16 @@ pub enum OmniError {
 
     #[error("Cache operation failed: {operation}: {reason}")]
     CacheFailed { operation: String, reason: String },
+
+    #[error("Read-only mode: refusing {operation}")]
+    ReadOnly { operation: String },
+
+    #[error("{system} denied {operation}")]
+    AccessControlDenied {
+        system: String,
+        operation: String,
+        suggestions: Vec<String>,
+    },
 }
 
 impl From<anyhow::Error> for OmniError {
@@ -105,6 +115,8 @@ impl OmniError {
             OmniError::TransactionFailed { .. } => true,
             OmniError::SnapshotFailed { .. } => true,
             OmniError::CacheFailed { .. } => true,
+            OmniError::ReadOnly { .. } => false,
+            OmniError::AccessControlDenied { .. } => false,
         }
     }
 
@@ -128,6 +140,8 @@ impl OmniError {
             OmniError::TransactionFailed { .. } => ErrorSeverity::High,
             OmniError::SnapshotFailed { .. } => ErrorSeverity::Medium,
             OmniError::CacheFailed { .. } => ErrorSeverity::Low,
+            OmniError::ReadOnly { .. } => ErrorSeverity::Low,
+            OmniError::AccessControlDenied { .. } => ErrorSeverity::High,
         }
     }
 
@@ -214,6 +228,11 @@ impl OmniError {
                 "Check network connectivity".to_string(),
                 "Retry with --no-cache flag".to_string(),
             ],
+            OmniError::ReadOnly { .. } => vec![
+                "Run without --read-only to make changes".to_string(),
+                "Set general.read_only to false in the configuration".to_string(),
+            ],
+            OmniError::AccessControlDenied { suggestions, .. } => suggestions.clone(),
             _ => vec!["Check logs for more details".to_string()],
         }
     }
@@ -237,6 +256,8 @@ impl OmniError {
             OmniError::TransactionFailed { .. } => ErrorCategory::Transaction,
             OmniError::SnapshotFailed { .. } => ErrorCategory::Storage,
             OmniError::CacheFailed { .. } => ErrorCategory::Storage,
+            OmniError::ReadOnly { .. } => ErrorCategory::Configuration,
+            OmniError::AccessControlDenied { .. } => ErrorCategory::Security,
             OmniError::Unknown { .. } => ErrorCategory::Unknown,
         }
     }
@@ -260,9 +281,94 @@ impl OmniError {
             OmniError::TransactionFailed { .. } => "OMNI_TXN_001",
             OmniError::SnapshotFailed { .. } => "OMNI_SNP_001",
             OmniError::CacheFailed { .. } => "OMNI_CHE_001",
+            OmniError::ReadOnly { .. } => "OMNI_CFG_002",
+            OmniError::AccessControlDenied { .. } => "OMNI_SEC_003",
             OmniError::Unknown { .. } => "OMNI_UNK_001",
         }
     }
+
+    /// Process exit code for this error; "package not found" gets its own code
+    /// so scripts can tell it apart from other package failures
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OmniError::PackageNotFound { .. } => 10,
+            _ => self.category().exit_code(),
+        }
+    }
+
+    /// Recover a typed error from an anyhow error chain.
+    ///
+    /// Errors that were raised as `OmniError` are returned as-is; well-known
+    /// library errors (I/O, SQLite, HTTP, YAML) are mapped to the matching
+    /// variant and anything else becomes `Unknown`.
+    pub fn classify(err: &anyhow::Error) -> OmniError {
+        for cause in err.chain() {
+            if let Some(omni_error) = cause.downcast_ref::<OmniError>() {
+                return omni_error.clone();
+            }
+            if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+                match io_error.kind() {
+                    std::io::ErrorKind::PermissionDenied => {
+                        return OmniError::PermissionDenied {
+                            operation: err.to_string(),
+                        }
+                    }
+                    std::io::ErrorKind::TimedOut => {
+                        return OmniError::NetworkError {
+                            message: err.to_string(),
+                            url: None,
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(http_error) = cause.downcast_ref::<reqwest::Error>() {
+                return OmniError::NetworkError {
+                    message: err.to_string(),
+                    url: http_error.url().map(|u| u.to_string()),
+                };
+            }
+            if cause.downcast_ref::<sqlx::Error>().is_some() {
+                return OmniError::DatabaseError {
+                    message: err.to_string(),
+                };
+            }
+            if cause.downcast_ref::<serde_yaml::Error>().is_some() {
+                return OmniError::ConfigurationError {
+                    message: err.to_string(),
+                };
+            }
+        }
+
+        OmniError::Unknown {
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Machine-readable error emitted on stderr with `--error-format json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorReport {
+    pub code: String,
+    pub category: String,
+    pub severity: String,
+    pub exit_code: i32,
+    pub message: String,
+    pub suggestions: Vec<String>,
+}
+
+impl ErrorReport {
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        let omni_error = OmniError::classify(err);
+        Self {
+            code: omni_error.error_code().to_string(),
+            category: omni_error.category().to_string().to_lowercase(),
+            severity: omni_error.severity().to_string().to_lowercase(),
+            exit_code: omni_error.exit_code(),
+            message: err.to_string(),
+            suggestions: omni_error.recovery_suggestions(),
+        }
+    }
 }
 
 /// Error categories for grouping and analytics
@@ -281,6 +387,25 @@ pub enum ErrorCategory {
     Unknown,
 }
 
+impl ErrorCategory {
+    /// Process exit code used for errors of this category
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorCategory::Unknown => 1,
+            ErrorCategory::Validation => 3,
+            ErrorCategory::Configuration => 4,
+            ErrorCategory::Package => 11,
+            ErrorCategory::Dependencies => 12,
+            ErrorCategory::Transaction => 13,
+            ErrorCategory::Security => 20,
+            ErrorCategory::Network => 30,
+            ErrorCategory::Storage => 40,
+            ErrorCategory::System => 50,
+            ErrorCategory::Recovery => 51,
+        }
+    }
+}
+
 /// Error context for tracking error details and recovery attempts
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorContext {
@@ -1308,6 +1433,36 @@ mod tests {
         assert_eq!(network_error.severity(), ErrorSeverity::Medium);
     }
 
+    #[test]
+    fn test_error_classification_and_exit_codes() {
+        let not_found: anyhow::Error = OmniError::PackageNotFound {
+            package: "missing".to_string(),
+        }
+        .into();
+        let report = ErrorReport::from_error(&not_found.context("install failed"));
+        assert_eq!(report.code, "OMNI_PKG_001");
+        assert_eq!(report.exit_code, 10);
+
+        let io_error: anyhow::Error =
+            std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied").into();
+        assert!(matches!(
+            OmniError::classify(&io_error),
+            OmniError::PermissionDenied { .. }
+        ));
+        assert_eq!(OmniError::classify(&io_error).exit_code(), 20);
+
+        let read_only: anyhow::Error = OmniError::ReadOnly {
+            operation: "installing packages".to_string(),
+        }
+        .into();
+        let report = ErrorReport::from_error(&read_only);
+        assert_eq!(report.code, "OMNI_CFG_002");
+        assert_eq!(report.exit_code, 4);
+
+        let unknown = anyhow!("something odd");
+        assert_eq!(OmniError::classify(&unknown).exit_code(), 1);
+    }
+
     #[test]
     fn test_retry_config() {
         let config = RetryConfig::new_network();
@@ -1328,7 +1483,9 @@ mod tests {
                 attempt_count += 1;
                 async move {
                     if attempt_count < 2 {
-                        Err("temporary failure".to_string())
+                        Err(OmniError::Unknown {
+                            message: "temporary failure".to_string(),
+                        })
                     } else {
                         Ok("success")
                     }
@@ -1357,7 +1514,11 @@ mod tests {
         let result = handler
             .execute(|| {
                 attempt_count += 1;
-                async move { Err("persistent failure".to_string()) }
+                async move {
+                    Err::<(), _>(OmniError::Unknown {
+                        message: "persistent failure".to_string(),
+                    })
+                }
             })
             .await;
 
@@ -0,0 +1,360 @@
+use crate::credentials;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::Arc;
+
+/// Which remote output stream a line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Something that happened during a remote operation, attributed to a host
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RemoteEvent {
+    Started {
+        host: String,
+        operation: String,
+        target: String,
+    },
+    Output {
+        host: String,
+        stream: OutputStream,
+        line: String,
+    },
+    Progress {
+        host: String,
+        percent: u8,
+        message: String,
+    },
+    Finished {
+        host: String,
+        success: bool,
+        message: String,
+    },
+    /// Emitted once after every host has finished
+    Summary {
+        total: usize,
+        succeeded: usize,
+        failed: Vec<String>,
+    },
+}
+
+impl RemoteEvent {
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            RemoteEvent::Started { host, .. }
+            | RemoteEvent::Output { host, .. }
+            | RemoteEvent::Progress { host, .. }
+            | RemoteEvent::Finished { host, .. } => Some(host),
+            RemoteEvent::Summary { .. } => None,
+        }
+    }
+
+    fn set_host(&mut self, new_host: &str) {
+        match self {
+            RemoteEvent::Started { host, .. }
+            | RemoteEvent::Output { host, .. }
+            | RemoteEvent::Progress { host, .. }
+            | RemoteEvent::Finished { host, .. } => *host = new_host.to_string(),
+            RemoteEvent::Summary { .. } => {}
+        }
+    }
+}
+
+/// One line of the JSON-lines event stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: RemoteEvent,
+}
+
+impl EventRecord {
+    pub fn to_json_line(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+type Handler = Arc<dyn Fn(&EventRecord) + Send + Sync>;
+
+/// Where remote events go. The default sink drops everything, so callers that do
+/// not care about live progress pay nothing.
+#[derive(Clone, Default)]
+pub struct EventSink {
+    handler: Option<Handler>,
+}
+
+impl EventSink {
+    pub fn new(handler: impl Fn(&EventRecord) + Send + Sync + 'static) -> Self {
+        Self {
+            handler: Some(Arc::new(handler)),
+        }
+    }
+
+    /// Write every event to stdout as one JSON object per line
+    pub fn json_lines() -> Self {
+        Self::new(|record| {
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{}", record.to_json_line());
+            let _ = stdout.flush();
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.handler.is_some()
+    }
+
+    pub fn emit(&self, event: RemoteEvent) {
+        if let Some(handler) = &self.handler {
+            handler(&EventRecord {
+                timestamp: Utc::now(),
+                event,
+            });
+        }
+    }
+}
+
+/// Splits a byte stream into lines as chunks arrive. Carriage returns end a line
+/// too, so progress bars that redraw in place show up as separate updates.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            if byte == b'\n' || byte == b'\r' {
+                if !self.pending.is_empty() {
+                    lines.push(String::from_utf8_lossy(&self.pending).into_owned());
+                    self.pending.clear();
+                }
+            } else {
+                self.pending.push(byte);
+            }
+        }
+        lines
+    }
+
+    /// Whatever is left after the stream ends without a final newline
+    pub fn finish(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let line = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        Some(line)
+    }
+}
+
+/// Turns one host's raw command output into events as it arrives
+pub struct OutputForwarder {
+    host: String,
+    sink: EventSink,
+    secrets: Vec<String>,
+    stdout: LineBuffer,
+    stderr: LineBuffer,
+    last_percent: Option<u8>,
+}
+
+impl OutputForwarder {
+    pub fn new(host: &str, sink: EventSink) -> Self {
+        Self {
+            host: host.to_string(),
+            sink,
+            secrets: Vec::new(),
+            stdout: LineBuffer::default(),
+            stderr: LineBuffer::default(),
+            last_percent: None,
+        }
+    }
+
+    /// Mask `secret` (e.g. a sudo password echoed back) in forwarded lines
+    pub fn with_secret(mut self, secret: &str) -> Self {
+        self.add_secret(secret);
+        self
+    }
+
+    pub fn add_secret(&mut self, secret: &str) {
+        self.secrets.push(secret.to_string());
+    }
+
+    pub fn on_data(&mut self, stream: OutputStream, data: &[u8]) {
+        if !self.sink.is_enabled() {
+            return;
+        }
+        let lines = match stream {
+            OutputStream::Stdout => self.stdout.push(data),
+            OutputStream::Stderr => self.stderr.push(data),
+        };
+        for line in lines {
+            self.forward_line(stream, &line);
+        }
+    }
+
+    /// Flush partial lines once the command has exited
+    pub fn finish(&mut self) {
+        if let Some(line) = self.stdout.finish() {
+            self.forward_line(OutputStream::Stdout, &line);
+        }
+        if let Some(line) = self.stderr.finish() {
+            self.forward_line(OutputStream::Stderr, &line);
+        }
+    }
+
+    fn forward_line(&mut self, stream: OutputStream, line: &str) {
+        let secrets: Vec<&str> = self.secrets.iter().map(String::as_str).collect();
+        let line = credentials::mask_secrets(line.trim_end(), &secrets);
+
+        // A remote omni already speaks this protocol; re-attribute its events to
+        // the inventory name instead of wrapping them as output
+        if let Ok(mut record) = serde_json::from_str::<EventRecord>(&line) {
+            if record.event.host().is_some() {
+                record.event.set_host(&self.host);
+                self.sink.emit(record.event);
+                return;
+            }
+        }
+
+        if let Some(percent) = parse_progress(&line) {
+            if self.last_percent != Some(percent) {
+                self.last_percent = Some(percent);
+                self.sink.emit(RemoteEvent::Progress {
+                    host: self.host.clone(),
+                    percent,
+                    message: line.trim().to_string(),
+                });
+            }
+            return;
+        }
+
+        self.sink.emit(RemoteEvent::Output {
+            host: self.host.clone(),
+            stream,
+            line,
+        });
+    }
+}
+
+/// Percentage from a line ending in one, such as apt's `Progress: [ 45%]` or
+/// winget's `████▒▒▒  45%`
+pub fn parse_progress(line: &str) -> Option<u8> {
+    let line = line.trim();
+    let before = line.strip_suffix("%]").or_else(|| line.strip_suffix('%'))?;
+    let digits: String = before
+        .chars()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+
+    if digits.is_empty() || digits.len() > 3 {
+        return None;
+    }
+    digits.parse::<u8>().ok().filter(|p| *p <= 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn collecting_sink() -> (EventSink, Arc<Mutex<Vec<RemoteEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let collected = events.clone();
+        let sink = EventSink::new(move |record| {
+            collected.lock().unwrap().push(record.event.clone());
+        });
+        (sink, events)
+    }
+
+    #[test]
+    fn test_forwarder_splits_chunks_into_lines() {
+        let (sink, events) = collecting_sink();
+        let mut forwarder = OutputForwarder::new("web1", sink).with_secret("hunter2");
+
+        forwarder.on_data(OutputStream::Stdout, b"Reading package li");
+        forwarder.on_data(
+            OutputStream::Stdout,
+            b"sts...\nProgress: [ 40%]\rProgress: [ 40%]\r",
+        );
+        forwarder.on_data(OutputStream::Stderr, b"sudo: hunter2 rejected");
+        forwarder.finish();
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                RemoteEvent::Output {
+                    host: "web1".to_string(),
+                    stream: OutputStream::Stdout,
+                    line: "Reading package lists...".to_string(),
+                },
+                RemoteEvent::Progress {
+                    host: "web1".to_string(),
+                    percent: 40,
+                    message: "Progress: [ 40%]".to_string(),
+                },
+                RemoteEvent::Output {
+                    host: "web1".to_string(),
+                    stream: OutputStream::Stderr,
+                    line: "sudo: ******** rejected".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remote_events_are_reattributed() {
+        let (sink, events) = collecting_sink();
+        let mut forwarder = OutputForwarder::new("web1", sink);
+
+        let remote = EventRecord {
+            timestamp: Utc::now(),
+            event: RemoteEvent::Finished {
+                host: "localhost".to_string(),
+                success: true,
+                message: "installed".to_string(),
+            },
+        };
+        forwarder.on_data(
+            OutputStream::Stdout,
+            format!("{}\n", remote.to_json_line()).as_bytes(),
+        );
+
+        assert_eq!(events.lock().unwrap()[0].host(), Some("web1"));
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        assert_eq!(parse_progress("Progress: [ 45%]"), Some(45));
+        assert_eq!(parse_progress("  ██████████▒▒▒▒▒  67%"), Some(67));
+        assert_eq!(parse_progress("Downloading packages 100%"), Some(100));
+        assert_eq!(parse_progress("Setting up curl (7.81.0-1) ..."), None);
+        assert_eq!(parse_progress("disk usage 250%"), None);
+    }
+
+    #[test]
+    fn test_event_json_line() {
+        let record = EventRecord {
+            timestamp: Utc::now(),
+            event: RemoteEvent::Summary {
+                total: 2,
+                succeeded: 1,
+                failed: vec!["db1".to_string()],
+            },
+        };
+        let json: serde_json::Value = serde_json::from_str(&record.to_json_line()).unwrap();
+        assert_eq!(json["event"], "summary");
+        assert_eq!(json["failed"][0], "db1");
+        assert!(json["timestamp"].is_string());
+    }
+}
@@ -0,0 +1,1255 @@
+use crate::config::{self, HealthCheckConfig, HealthChecksConfig};
+use crate::credentials::HostPasswords;
+use crate::events::{EventSink, OutputForwarder, RemoteEvent};
+use crate::health_checks::{self, Shell};
+use crate::input_validation::InputValidator;
+use crate::manifest::{OmniApp, OmniManifest};
+use crate::windows_remote;
+use crate::winrm::WinRmClient;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+#[cfg(feature = "ssh")]
+use crate::ssh::{RealAuthMethod, RealSshClient, RealSshConfig};
+
+/// A remote host managed through `omni fleet`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FleetHost {
+    /// Inventory name used on the command line
+    pub name: String,
+    pub address: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_user")]
+    pub user: String,
+    #[serde(default)]
+    pub identity_file: Option<PathBuf>,
+    /// Package manager on the host; detected remotely when unset
+    #[serde(default)]
+    pub box_type: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// sudo on the host asks for a password (taken from the keyring or prompted once per run)
+    #[serde(default)]
+    pub sudo_password: bool,
+    #[serde(default)]
+    pub transport: Transport,
+}
+
+/// How omni reaches a host
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// SSH, including the OpenSSH server shipped with Windows
+    #[default]
+    Ssh,
+    /// WS-Management (Windows Remote Management) with Basic auth
+    Winrm,
+}
+
+impl Transport {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Transport::Ssh => "ssh",
+            Transport::Winrm => "winrm",
+        }
+    }
+
+    pub fn default_port(self) -> u16 {
+        match self {
+            Transport::Ssh => 22,
+            Transport::Winrm => crate::winrm::WINRM_HTTPS_PORT,
+        }
+    }
+}
+
+impl FleetHost {
+    pub fn has_tag(&self, key: &str, value: &str) -> bool {
+        self.tags.get(key).is_some_and(|v| v == value)
+    }
+
+    /// Tags rendered as `key=value`, sorted by key
+    pub fn tag_labels(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect()
+    }
+}
+
+fn default_port() -> u16 {
+    22
+}
+
+fn default_user() -> String {
+    "root".to_string()
+}
+
+/// Extra safety rules applied to every host carrying `tag`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagPolicy {
+    /// `key=value` tag the policy applies to
+    pub tag: String,
+    /// Operations on matching hosts need `--confirm`
+    #[serde(default)]
+    pub require_confirm: bool,
+}
+
+impl TagPolicy {
+    pub fn matches(&self, host: &FleetHost) -> bool {
+        parse_tag(&self.tag).is_ok_and(|(k, v)| host.has_tag(&k, &v))
+    }
+}
+
+/// Which inventory hosts an operation targets. An empty selector targets every host.
+#[derive(Debug, Clone, Default)]
+pub struct HostSelector {
+    pub tags: Vec<(String, String)>,
+    pub groups: Vec<String>,
+    pub names: Vec<String>,
+}
+
+impl HostSelector {
+    /// Build a selector from `--tags role=web,env=prod`, `--group` and `--host` arguments
+    pub fn from_args(tags: Option<&str>, groups: &[String], names: &[String]) -> Result<Self> {
+        let tags = match tags {
+            Some(spec) => spec
+                .split(',')
+                .filter(|t| !t.trim().is_empty())
+                .map(parse_tag)
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            tags,
+            groups: groups.to_vec(),
+            names: names.to_vec(),
+        })
+    }
+
+    pub fn matches(&self, host: &FleetHost) -> bool {
+        self.tags.iter().all(|(k, v)| host.has_tag(k, v))
+            && self.groups.iter().all(|g| host.groups.contains(g))
+            && (self.names.is_empty() || self.names.contains(&host.name))
+    }
+}
+
+/// Parse a single `key=value` tag
+pub fn parse_tag(spec: &str) -> Result<(String, String)> {
+    let (key, value) = spec
+        .trim()
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid tag '{}': expected key=value", spec))?;
+    let (key, value) = (key.trim(), value.trim());
+
+    if key.is_empty() || value.is_empty() {
+        return Err(anyhow!("Invalid tag '{}': expected key=value", spec));
+    }
+    if !key
+        .chars()
+        .chain(value.chars())
+        .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    {
+        return Err(anyhow!("Tag '{}' contains invalid characters", spec));
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Hosts, groups and tag policies stored in `~/.config/omni/inventory.yaml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FleetInventory {
+    #[serde(default)]
+    pub hosts: Vec<FleetHost>,
+    #[serde(default)]
+    pub policies: Vec<TagPolicy>,
+}
+
+impl FleetInventory {
+    pub fn inventory_path() -> Result<PathBuf> {
+        Ok(config::OmniConfig::config_dir()?.join("inventory.yaml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::inventory_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let inventory: FleetInventory = serde_yaml::from_str(&content)?;
+        Ok(inventory)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        config::ensure_writable("saving the fleet inventory")?;
+        let path = Self::inventory_path()?;
+        if let Some(parent) = path.parent() {
+            config::ensure_private_dir(parent)?;
+        }
+
+        config::write_private_file(&path, serde_yaml::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn add_host(&mut self, host: FleetHost) -> Result<()> {
+        InputValidator::validate_hostname(&host.address)?;
+        if let Some(box_type) = &host.box_type {
+            InputValidator::validate_box_type(box_type)?;
+        }
+
+        if let Some(existing) = self.hosts.iter_mut().find(|h| h.name == host.name) {
+            *existing = host;
+        } else {
+            self.hosts.push(host);
+        }
+        Ok(())
+    }
+
+    pub fn remove_host(&mut self, name: &str) -> bool {
+        let before = self.hosts.len();
+        self.hosts.retain(|h| h.name != name);
+        self.hosts.len() != before
+    }
+
+    pub fn select(&self, selector: &HostSelector) -> Vec<&FleetHost> {
+        self.hosts.iter().filter(|h| selector.matches(h)).collect()
+    }
+
+    pub fn set_policy(&mut self, tag: &str, require_confirm: bool) -> Result<()> {
+        parse_tag(tag)?;
+        self.policies.retain(|p| p.tag != tag);
+        if require_confirm {
+            self.policies.push(TagPolicy {
+                tag: tag.to_string(),
+                require_confirm,
+            });
+        }
+        Ok(())
+    }
+
+    /// Refuse to run against hosts whose tag policy requires `--confirm` unless it was given
+    pub fn check_policies(&self, hosts: &[&FleetHost], confirmed: bool) -> Result<()> {
+        if confirmed {
+            return Ok(());
+        }
+
+        let blocked: Vec<String> = hosts
+            .iter()
+            .filter_map(|host| {
+                self.policies
+                    .iter()
+                    .find(|p| p.require_confirm && p.matches(host))
+                    .map(|p| format!("{} ({})", host.name, p.tag))
+            })
+            .collect();
+
+        if blocked.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Policy requires --confirm for: {}",
+                blocked.join(", ")
+            ))
+        }
+    }
+}
+
+/// Outcome of one operation on one host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostResult {
+    pub host: String,
+    pub success: bool,
+    pub message: String,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Success/failure counts per `key=value` tag
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TagReport {
+    pub hosts: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+pub fn summarize_by_tag(results: &[HostResult]) -> BTreeMap<String, TagReport> {
+    let mut report: BTreeMap<String, TagReport> = BTreeMap::new();
+
+    for result in results {
+        for (key, value) in &result.tags {
+            let entry = report.entry(format!("{}={}", key, value)).or_default();
+            entry.hosts += 1;
+            if result.success {
+                entry.succeeded += 1;
+            } else {
+                entry.failed += 1;
+            }
+        }
+    }
+
+    report
+}
+
+/// Host counts per `key=value` tag for `omni fleet list`
+pub fn count_by_tag(hosts: &[&FleetHost]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for host in hosts {
+        for label in host.tag_labels() {
+            *counts.entry(label).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// How a host's package differs from the desired manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    /// In the manifest but not installed
+    Missing,
+    /// Explicitly installed on the host but not in the manifest
+    Extra,
+    /// Installed at a different version than the manifest pins
+    VersionMismatch,
+}
+
+impl DriftKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DriftKind::Missing => "missing",
+            DriftKind::Extra => "extra",
+            DriftKind::VersionMismatch => "version_mismatch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DriftEntry {
+    pub package: String,
+    pub kind: DriftKind,
+    pub expected: Option<String>,
+    pub installed: Option<String>,
+}
+
+/// Drift of one host against the manifest; `error` is set when the host could not be inspected
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostDrift {
+    pub host: String,
+    pub box_type: Option<String>,
+    pub entries: Vec<DriftEntry>,
+    pub error: Option<String>,
+}
+
+impl HostDrift {
+    pub fn is_clean(&self) -> bool {
+        self.error.is_none() && self.entries.is_empty()
+    }
+
+    pub fn count(&self, kind: DriftKind) -> usize {
+        self.entries.iter().filter(|e| e.kind == kind).count()
+    }
+}
+
+/// Installed packages on a host, plus the ones the admin asked for explicitly
+#[derive(Debug, Clone, Default)]
+pub struct HostPackageState {
+    pub installed: BTreeMap<String, String>,
+    pub explicit: BTreeSet<String>,
+}
+
+/// Commands that list `name version` pairs and explicitly installed names for a package manager
+fn package_list_commands(box_type: &str) -> Option<(&'static str, &'static str)> {
+    match box_type {
+        "apt" => Some((
+            "dpkg-query -W -f='${Package} ${Version}\\n'",
+            "apt-mark showmanual",
+        )),
+        "dnf" => Some((
+            "rpm -qa --qf '%{NAME} %{EPOCHNUM}:%{VERSION}-%{RELEASE}\\n'",
+            "dnf repoquery --userinstalled --qf '%{name}'",
+        )),
+        "pacman" => Some(("pacman -Q", "pacman -Qqe")),
+        _ => None,
+    }
+}
+
+/// Parse `name version` lines as printed by `pacman -Q`, `dpkg-query` and `rpm --qf`
+pub fn parse_package_list(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?.to_string(), parts.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// Drop a leading `epoch:` so `0:1.2-3` and `1.2-3` compare equal
+fn strip_epoch(version: &str) -> &str {
+    match version.split_once(':') {
+        Some((epoch, rest)) if epoch.chars().all(|c| c.is_ascii_digit()) => rest,
+        _ => version,
+    }
+}
+
+/// A manifest version matches when it equals the installed version or is a prefix of it
+/// ending on a component boundary (`7.81` matches `7.81.0-1ubuntu1`, not `7.810`)
+pub fn version_matches(expected: &str, installed: &str) -> bool {
+    let expected = strip_epoch(expected);
+    let installed = strip_epoch(installed);
+
+    match installed.strip_prefix(expected) {
+        Some("") => true,
+        Some(rest) => rest.starts_with(['.', '-', '+', '~']),
+        None => false,
+    }
+}
+
+/// Compare the manifest apps for `box_type` against a host's package state.
+/// Apps for other boxes (flatpak, snap, ...) are not checked.
+pub fn compute_drift(
+    apps: &[OmniApp],
+    box_type: &str,
+    state: &HostPackageState,
+) -> Vec<DriftEntry> {
+    let wanted: Vec<&OmniApp> = apps.iter().filter(|app| app.box_type == box_type).collect();
+    let mut entries = Vec::new();
+
+    for app in &wanted {
+        match state.installed.get(&app.name) {
+            None => entries.push(DriftEntry {
+                package: app.name.clone(),
+                kind: DriftKind::Missing,
+                expected: app.version.clone(),
+                installed: None,
+            }),
+            Some(installed) => {
+                if let Some(expected) = &app.version {
+                    if !version_matches(expected, installed) {
+                        entries.push(DriftEntry {
+                            package: app.name.clone(),
+                            kind: DriftKind::VersionMismatch,
+                            expected: Some(expected.clone()),
+                            installed: Some(installed.clone()),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    for name in &state.explicit {
+        if !wanted.iter().any(|app| &app.name == name) {
+            entries.push(DriftEntry {
+                package: name.clone(),
+                kind: DriftKind::Extra,
+                expected: None,
+                installed: state.installed.get(name).cloned(),
+            });
+        }
+    }
+
+    entries
+}
+
+/// Package × host matrix for the drift table: one row per drifting package, one cell per host
+pub fn drift_matrix(report: &[HostDrift]) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut matrix: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+
+    for host in report {
+        for entry in &host.entries {
+            let cell = match entry.kind {
+                DriftKind::VersionMismatch => format!(
+                    "{} != {}",
+                    entry.installed.as_deref().unwrap_or("?"),
+                    entry.expected.as_deref().unwrap_or("?")
+                ),
+                kind => kind.as_str().to_string(),
+            };
+            matrix
+                .entry(entry.package.clone())
+                .or_default()
+                .insert(host.host.clone(), cell);
+        }
+    }
+
+    matrix
+}
+
+/// Command that puts `version` of `package` back on a host, for rolling back
+/// an update that failed its health checks
+pub fn rollback_command(box_type: &str, package: &str, version: &str) -> Result<String> {
+    InputValidator::validate_package_name(package)?;
+    // Installed versions as the package list commands print them, epochs included
+    let valid = Regex::new(r"^[A-Za-z0-9._+~:-]{1,64}$").expect("valid version pattern");
+    if !valid.is_match(version) {
+        return Err(anyhow!("Invalid version for rollback: {}", version));
+    }
+
+    match box_type {
+        "apt" => Ok(format!(
+            "apt-get install -y --allow-downgrades {}={}",
+            package, version
+        )),
+        "dnf" => Ok(format!("dnf downgrade -y {}-{}", package, version)),
+        "pacman" => Ok(format!(
+            "pacman -U --noconfirm /var/cache/pacman/pkg/{}-{}-*.pkg.tar.*",
+            package, version
+        )),
+        _ => Err(anyhow!(
+            "Rolling back updates is not supported for '{}'",
+            box_type
+        )),
+    }
+}
+
+/// A rollout step whose health checks failed; later hosts are skipped
+#[derive(Debug)]
+struct HealthCheckFailure(String);
+
+impl std::fmt::Display for HealthCheckFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for HealthCheckFailure {}
+
+/// Runs health check commands on the host that was just changed
+struct HostShell<'a> {
+    runner: &'a mut FleetRunner,
+    host: &'a FleetHost,
+}
+
+#[async_trait(?Send)]
+impl Shell for HostShell<'_> {
+    async fn run(&mut self, command: &str) -> Result<RemoteOutput> {
+        self.runner.execute(self.host, command).await
+    }
+}
+
+/// Output of a command run on a fleet host, whatever the transport
+#[derive(Debug, Clone)]
+pub struct RemoteOutput {
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs commands on fleet hosts over SSH or WinRM
+pub struct FleetRunner {
+    #[cfg(feature = "ssh")]
+    client: RealSshClient,
+    #[cfg_attr(not(feature = "ssh"), allow(dead_code))]
+    sudo: HostPasswords,
+    winrm: HostPasswords,
+    events: EventSink,
+    health_checks: HealthChecksConfig,
+}
+
+impl FleetRunner {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "ssh")]
+            client: RealSshClient::new(),
+            sudo: HostPasswords::sudo(),
+            winrm: HostPasswords::winrm(),
+            events: EventSink::default(),
+            health_checks: HealthChecksConfig::default(),
+        }
+    }
+
+    /// Stream per-host start, output, progress and result events while operations run
+    pub fn with_events(mut self, events: EventSink) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Share a sudo password cache with other runners in the same run
+    pub fn with_sudo_passwords(mut self, sudo: HostPasswords) -> Self {
+        self.sudo = sudo;
+        self
+    }
+
+    /// Share a WinRM login password cache with other runners in the same run
+    pub fn with_winrm_passwords(mut self, winrm: HostPasswords) -> Self {
+        self.winrm = winrm;
+        self
+    }
+
+    /// Check each host after installs and updates, rolling back and halting
+    /// the rollout when a check fails
+    pub fn with_health_checks(mut self, health_checks: HealthChecksConfig) -> Self {
+        self.health_checks = health_checks;
+        self
+    }
+
+    /// Run `operation` (install/remove/update) for `package` on every host, one host at a time.
+    /// Once a host fails its health checks the remaining hosts are skipped.
+    pub async fn run_package_operation(
+        &mut self,
+        hosts: &[&FleetHost],
+        operation: &str,
+        package: &str,
+    ) -> Vec<HostResult> {
+        let mut results = Vec::new();
+        let mut halted_by: Option<String> = None;
+
+        for host in hosts {
+            if let Some(failed_host) = &halted_by {
+                let message = format!("skipped: health checks failed on {}", failed_host);
+                self.events.emit(RemoteEvent::Finished {
+                    host: host.name.clone(),
+                    success: false,
+                    message: message.clone(),
+                });
+                results.push(HostResult {
+                    host: host.name.clone(),
+                    success: false,
+                    message,
+                    tags: host.tags.clone(),
+                });
+                continue;
+            }
+
+            info!("Fleet {} {} on {}", operation, package, host.name);
+            self.events.emit(RemoteEvent::Started {
+                host: host.name.clone(),
+                operation: operation.to_string(),
+                target: package.to_string(),
+            });
+            let outcome = self.run_checked(host, operation, package).await;
+
+            let (success, message) = match outcome {
+                Ok(message) => (true, message),
+                Err(e) => {
+                    warn!("Fleet {} on {} failed: {}", operation, host.name, e);
+                    if e.downcast_ref::<HealthCheckFailure>().is_some() {
+                        halted_by = Some(host.name.clone());
+                    }
+                    (false, e.to_string())
+                }
+            };
+
+            self.events.emit(RemoteEvent::Finished {
+                host: host.name.clone(),
+                success,
+                message: message.clone(),
+            });
+            results.push(HostResult {
+                host: host.name.clone(),
+                success,
+                message,
+                tags: host.tags.clone(),
+            });
+        }
+
+        self.events.emit(RemoteEvent::Summary {
+            total: results.len(),
+            succeeded: results.iter().filter(|r| r.success).count(),
+            failed: results
+                .iter()
+                .filter(|r| !r.success)
+                .map(|r| r.host.clone())
+                .collect(),
+        });
+
+        results
+    }
+
+    /// Run the operation, then the health checks that apply to it on this host
+    async fn run_checked(
+        &mut self,
+        host: &FleetHost,
+        operation: &str,
+        package: &str,
+    ) -> Result<String> {
+        let checks: Vec<HealthCheckConfig> = match operation {
+            "install" | "update" => {
+                health_checks::applicable(&self.health_checks.checks, package, &host.name)
+                    .into_iter()
+                    .cloned()
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        if checks.is_empty() {
+            return self.run_on_host(host, operation, package).await;
+        }
+
+        let box_type = self.box_type(host).await?;
+        let previous = if operation == "update" {
+            self.collect_package_state(host, &box_type)
+                .await?
+                .installed
+                .remove(package)
+        } else {
+            None
+        };
+
+        let message = self.run_on_host(host, operation, package).await?;
+        let checks: Vec<&HealthCheckConfig> = checks.iter().collect();
+        let mut shell = HostShell { runner: self, host };
+        let outcomes = health_checks::run(&checks, &mut shell, &host.address).await;
+        let Some(failure) = health_checks::failures(&outcomes) else {
+            return Ok(format!("{}; health checks passed", message));
+        };
+
+        let rollback = if !self.health_checks.rollback {
+            "left in place (health_checks.rollback is off)".to_string()
+        } else {
+            self.roll_back(host, &box_type, operation, package, previous.as_deref())
+                .await
+                .unwrap_or_else(|e| format!("rollback failed: {}", e))
+        };
+        Err(HealthCheckFailure(format!(
+            "health checks failed after {} {} ({}); {}",
+            operation, package, failure, rollback
+        ))
+        .into())
+    }
+
+    /// Undo an install or update whose health checks failed
+    async fn roll_back(
+        &mut self,
+        host: &FleetHost,
+        box_type: &str,
+        operation: &str,
+        package: &str,
+        previous: Option<&str>,
+    ) -> Result<String> {
+        if operation == "install" {
+            self.run_on_host(host, "remove", package).await?;
+            return Ok(format!("removed {} again", package));
+        }
+
+        let version =
+            previous.ok_or_else(|| anyhow!("the version before the update is unknown"))?;
+        let command = rollback_command(box_type, package, version)?;
+        self.run_privileged_command(host, &command).await?;
+        Ok(format!("rolled back to {}", version))
+    }
+
+    async fn box_type(&mut self, host: &FleetHost) -> Result<String> {
+        match &host.box_type {
+            Some(box_type) => Ok(box_type.clone()),
+            None => self.detect_box_type(host).await,
+        }
+    }
+
+    async fn run_on_host(
+        &mut self,
+        host: &FleetHost,
+        operation: &str,
+        package: &str,
+    ) -> Result<String> {
+        InputValidator::validate_package_name(package)?;
+
+        let box_type = self.box_type(host).await?;
+
+        if windows_remote::is_windows_box(&box_type) {
+            let command = windows_remote::package_command(&box_type, operation, package)?;
+            let mut forwarder = OutputForwarder::new(&host.name, self.events.clone());
+            let output = self
+                .execute_forwarded(host, &command, Some(&mut forwarder))
+                .await?;
+            return windows_remote::interpret_result(
+                &box_type,
+                operation,
+                package,
+                output.exit_code,
+                &output.stdout,
+                &output.stderr,
+            );
+        }
+        if host.transport == Transport::Winrm {
+            return Err(anyhow!(
+                "WinRM hosts only support winget, choco and scoop, not '{}'",
+                box_type
+            ));
+        }
+
+        self.run_unix_package_command(host, &box_type, operation, package)
+            .await
+    }
+
+    /// Run a package operation through the SSH client's sudo-aware package helper
+    #[cfg(feature = "ssh")]
+    async fn run_unix_package_command(
+        &mut self,
+        host: &FleetHost,
+        box_type: &str,
+        operation: &str,
+        package: &str,
+    ) -> Result<String> {
+        let sudo_password = if host.sudo_password {
+            Some(self.sudo.for_host(&host.name)?)
+        } else {
+            None
+        };
+        let mut forwarder = OutputForwarder::new(&host.name, self.events.clone());
+        if let Some(password) = &sudo_password {
+            forwarder.add_secret(password);
+        }
+
+        let result = self
+            .client
+            .execute_remote_package_command(
+                &host.address,
+                ssh_config(host),
+                box_type,
+                package,
+                operation,
+                sudo_password.as_deref(),
+                Some(&mut forwarder),
+            )
+            .await?;
+
+        if result.success() {
+            Ok(format!("{} {} via {}", operation, package, box_type))
+        } else {
+            Err(anyhow!(
+                "exit code {}: {}",
+                result.exit_code,
+                result.stderr.trim()
+            ))
+        }
+    }
+
+    #[cfg(not(feature = "ssh"))]
+    async fn run_unix_package_command(
+        &mut self,
+        _host: &FleetHost,
+        _box_type: &str,
+        _operation: &str,
+        _package: &str,
+    ) -> Result<String> {
+        Err(anyhow!(
+            "omni was built without SSH support (enable the 'ssh' feature)"
+        ))
+    }
+
+    /// Run a command under sudo on an SSH host, with its sudo password when it needs one
+    #[cfg(feature = "ssh")]
+    async fn run_privileged_command(&mut self, host: &FleetHost, command: &str) -> Result<()> {
+        if host.transport == Transport::Winrm {
+            return Err(anyhow!("WinRM hosts do not run privileged shell commands"));
+        }
+        let sudo_password = if host.sudo_password {
+            Some(self.sudo.for_host(&host.name)?)
+        } else {
+            None
+        };
+        let mut forwarder = OutputForwarder::new(&host.name, self.events.clone());
+        if let Some(password) = &sudo_password {
+            forwarder.add_secret(password);
+        }
+
+        let result = self
+            .client
+            .execute_remote_privileged_command(
+                &host.address,
+                ssh_config(host),
+                command,
+                sudo_password.as_deref(),
+                Some(&mut forwarder),
+            )
+            .await?;
+
+        if result.success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "exit code {}: {}",
+                result.exit_code,
+                result.stderr.trim()
+            ))
+        }
+    }
+
+    #[cfg(not(feature = "ssh"))]
+    async fn run_privileged_command(&mut self, _host: &FleetHost, _command: &str) -> Result<()> {
+        Err(anyhow!(
+            "omni was built without SSH support (enable the 'ssh' feature)"
+        ))
+    }
+
+    /// Run an arbitrary read-only command on a host and return its stdout
+    pub async fn run_command(&mut self, host: &FleetHost, command: &str) -> Result<String> {
+        let output = self.execute(host, command).await?;
+
+        if output.exit_code == 0 {
+            Ok(output.stdout)
+        } else {
+            let reason = if output.stderr.trim().is_empty() {
+                output.stdout.trim()
+            } else {
+                output.stderr.trim()
+            };
+            Err(anyhow!("'{}' failed on {}: {}", command, host.name, reason))
+        }
+    }
+
+    /// Run a command over the host's transport without interpreting its exit code
+    pub async fn execute(&mut self, host: &FleetHost, command: &str) -> Result<RemoteOutput> {
+        self.execute_forwarded(host, command, None).await
+    }
+
+    /// Like [`execute`](Self::execute), streaming output to `forwarder` as it arrives
+    pub async fn execute_forwarded(
+        &mut self,
+        host: &FleetHost,
+        command: &str,
+        forwarder: Option<&mut OutputForwarder>,
+    ) -> Result<RemoteOutput> {
+        match host.transport {
+            Transport::Ssh => self.execute_ssh(host, command, forwarder).await,
+            Transport::Winrm => {
+                let password = self.winrm.for_host(&host.name)?;
+                let client = WinRmClient::new(&host.address, host.port, &host.user, &password)?;
+                let forwarder = forwarder.map(|f| {
+                    f.add_secret(&password);
+                    f
+                });
+                let output = client.run_command_forwarded(command, forwarder).await?;
+                Ok(RemoteOutput {
+                    exit_code: output.exit_code,
+                    stdout: self.winrm.mask(&output.stdout),
+                    stderr: self.winrm.mask(&output.stderr),
+                })
+            }
+        }
+    }
+
+    #[cfg(feature = "ssh")]
+    async fn execute_ssh(
+        &mut self,
+        host: &FleetHost,
+        command: &str,
+        forwarder: Option<&mut OutputForwarder>,
+    ) -> Result<RemoteOutput> {
+        let result = self
+            .client
+            .execute_remote_command_forwarded(&host.address, ssh_config(host), command, forwarder)
+            .await?;
+
+        Ok(RemoteOutput {
+            exit_code: result.exit_code,
+            stdout: result.stdout,
+            stderr: result.stderr,
+        })
+    }
+
+    #[cfg(not(feature = "ssh"))]
+    async fn execute_ssh(
+        &mut self,
+        _host: &FleetHost,
+        _command: &str,
+        _forwarder: Option<&mut OutputForwarder>,
+    ) -> Result<RemoteOutput> {
+        Err(anyhow!(
+            "omni was built without SSH support (enable the 'ssh' feature)"
+        ))
+    }
+
+    /// List installed and explicitly installed packages on a host
+    pub async fn collect_package_state(
+        &mut self,
+        host: &FleetHost,
+        box_type: &str,
+    ) -> Result<HostPackageState> {
+        if let Some(list) = windows_remote::list_command(box_type) {
+            // Windows managers do not track dependencies apart, so every package counts as explicit
+            let installed =
+                windows_remote::parse_package_list(box_type, &self.run_command(host, list).await?);
+            let explicit = installed.keys().cloned().collect();
+            return Ok(HostPackageState {
+                installed,
+                explicit,
+            });
+        }
+
+        let (list, explicit) = package_list_commands(box_type)
+            .ok_or_else(|| anyhow!("Drift detection does not support '{}'", box_type))?;
+
+        let installed = parse_package_list(&self.run_command(host, list).await?);
+        let explicit = self
+            .run_command(host, explicit)
+            .await?
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect();
+
+        Ok(HostPackageState {
+            installed,
+            explicit,
+        })
+    }
+
+    /// Compare every host against the manifest without changing anything
+    pub async fn drift_report(
+        &mut self,
+        hosts: &[&FleetHost],
+        manifest: &OmniManifest,
+    ) -> Vec<HostDrift> {
+        let mut report = Vec::new();
+
+        for host in hosts {
+            info!("Checking drift on {}", host.name);
+
+            let box_type = match &host.box_type {
+                Some(box_type) => Ok(box_type.clone()),
+                None => self.detect_box_type(host).await,
+            };
+            let outcome = match &box_type {
+                Ok(box_type) => self
+                    .collect_package_state(host, box_type)
+                    .await
+                    .map(|state| compute_drift(&manifest.apps, box_type, &state)),
+                Err(e) => Err(anyhow!("{}", e)),
+            };
+
+            let (entries, error) = match outcome {
+                Ok(entries) => (entries, None),
+                Err(e) => {
+                    warn!("Drift check on {} failed: {}", host.name, e);
+                    (Vec::new(), Some(e.to_string()))
+                }
+            };
+
+            report.push(HostDrift {
+                host: host.name.clone(),
+                box_type: box_type.ok(),
+                entries,
+                error,
+            });
+        }
+
+        report
+    }
+
+    /// Work out which package manager a host uses
+    pub async fn detect_box_type(&mut self, host: &FleetHost) -> Result<String> {
+        if host.transport == Transport::Ssh {
+            for box_type in ["apt", "dnf", "pacman"] {
+                let probe = format!("command -v {}", box_type);
+                if self.run_command(host, &probe).await.is_ok() {
+                    return Ok(box_type.to_string());
+                }
+            }
+        }
+
+        for box_type in windows_remote::WINDOWS_BOX_TYPES {
+            let probe = windows_remote::probe_command(box_type);
+            if self.run_command(host, &probe).await.is_ok() {
+                return Ok(box_type.to_string());
+            }
+        }
+
+        Err(anyhow!(
+            "No supported package manager found on {}",
+            host.name
+        ))
+    }
+}
+
+impl Default for FleetRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "ssh")]
+fn ssh_config(host: &FleetHost) -> RealSshConfig {
+    let defaults = RealSshConfig::default();
+    let auth_method = match &host.identity_file {
+        Some(path) => RealAuthMethod::PublicKey {
+            private_key_path: path.clone(),
+            passphrase: None,
+        },
+        None => defaults.auth_method.clone(),
+    };
+
+    RealSshConfig {
+        host: host.address.clone(),
+        port: host.port,
+        username: host.user.clone(),
+        auth_method,
+        ..defaults
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, tags: &[(&str, &str)], groups: &[&str]) -> FleetHost {
+        FleetHost {
+            name: name.to_string(),
+            address: format!("{}.example.com", name),
+            port: 22,
+            user: "root".to_string(),
+            identity_file: None,
+            box_type: Some("apt".to_string()),
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            sudo_password: false,
+            transport: Transport::Ssh,
+        }
+    }
+
+    #[test]
+    fn test_selector_matches_tags_and_groups() {
+        let inventory = FleetInventory {
+            hosts: vec![
+                host("web1", &[("role", "web"), ("env", "prod")], &["frontend"]),
+                host(
+                    "web2",
+                    &[("role", "web"), ("env", "staging")],
+                    &["frontend"],
+                ),
+                host("db1", &[("role", "db"), ("env", "prod")], &[]),
+            ],
+            policies: vec![],
+        };
+
+        let selector = HostSelector::from_args(Some("role=web,env=prod"), &[], &[]).unwrap();
+        let selected: Vec<_> = inventory
+            .select(&selector)
+            .iter()
+            .map(|h| h.name.clone())
+            .collect();
+        assert_eq!(selected, vec!["web1"]);
+
+        let selector = HostSelector::from_args(None, &["frontend".to_string()], &[]).unwrap();
+        assert_eq!(inventory.select(&selector).len(), 2);
+
+        assert_eq!(inventory.select(&HostSelector::default()).len(), 3);
+        assert!(HostSelector::from_args(Some("role"), &[], &[]).is_err());
+    }
+
+    #[test]
+    fn test_policy_requires_confirm() {
+        let mut inventory = FleetInventory {
+            hosts: vec![
+                host("web1", &[("env", "prod")], &[]),
+                host("web2", &[("env", "staging")], &[]),
+            ],
+            policies: vec![],
+        };
+        inventory.set_policy("env=prod", true).unwrap();
+
+        let all: Vec<&FleetHost> = inventory.hosts.iter().collect();
+        assert!(inventory.check_policies(&all, false).is_err());
+        assert!(inventory.check_policies(&all, true).is_ok());
+        assert!(inventory.check_policies(&all[1..], false).is_ok());
+    }
+
+    #[test]
+    fn test_summarize_by_tag() {
+        let results = vec![
+            HostResult {
+                host: "web1".to_string(),
+                success: true,
+                message: String::new(),
+                tags: host("web1", &[("role", "web")], &[]).tags,
+            },
+            HostResult {
+                host: "web2".to_string(),
+                success: false,
+                message: String::new(),
+                tags: host("web2", &[("role", "web")], &[]).tags,
+            },
+        ];
+
+        let report = summarize_by_tag(&results);
+        let web = &report["role=web"];
+        assert_eq!((web.hosts, web.succeeded, web.failed), (2, 1, 1));
+    }
+
+    fn app(name: &str, box_type: &str, version: Option<&str>) -> OmniApp {
+        OmniApp {
+            name: name.to_string(),
+            box_type: box_type.to_string(),
+            version: version.map(|v| v.to_string()),
+            source: None,
+            recipe: None,
+        }
+    }
+
+    #[test]
+    fn test_compute_drift() {
+        let state = HostPackageState {
+            installed: parse_package_list(
+                "curl 7.81.0-1ubuntu1.15\nnginx 1.18.0-6ubuntu14\nhtop 3.0.5-7build2\nlibc6 2.35-0ubuntu3\n",
+            ),
+            explicit: ["curl", "nginx", "htop"]
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+        };
+        let apps = vec![
+            app("curl", "apt", Some("7.81")),
+            app("nginx", "apt", Some("1.24.0")),
+            app("git", "apt", None),
+            app("org.mozilla.firefox", "flatpak", None),
+        ];
+
+        let drift = compute_drift(&apps, "apt", &state);
+        let kinds: Vec<_> = drift.iter().map(|e| (e.package.as_str(), e.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("nginx", DriftKind::VersionMismatch),
+                ("git", DriftKind::Missing),
+                ("htop", DriftKind::Extra),
+            ]
+        );
+
+        let report = vec![HostDrift {
+            host: "web1".to_string(),
+            box_type: Some("apt".to_string()),
+            entries: drift,
+            error: None,
+        }];
+        let matrix = drift_matrix(&report);
+        assert_eq!(matrix["nginx"]["web1"], "1.18.0-6ubuntu14 != 1.24.0");
+        assert_eq!(matrix["git"]["web1"], "missing");
+    }
+
+    #[test]
+    fn test_version_matches() {
+        assert!(version_matches("7.81", "7.81.0-1ubuntu1"));
+        assert!(version_matches("1:2.3-1", "2.3-1"));
+        assert!(!version_matches("7.8", "7.81.0"));
+        assert!(!version_matches("1.24.0", "1.18.0-6"));
+    }
+
+    #[test]
+    fn test_rollback_command() {
+        assert_eq!(
+            rollback_command("apt", "nginx", "1:1.24.0-2ubuntu7").unwrap(),
+            "apt-get install -y --allow-downgrades nginx=1:1.24.0-2ubuntu7"
+        );
+        assert_eq!(
+            rollback_command("dnf", "nginx", "1:1.24.0-1.fc40").unwrap(),
+            "dnf downgrade -y nginx-1:1.24.0-1.fc40"
+        );
+        assert!(rollback_command("apt", "nginx", "1.0; reboot").is_err());
+        assert!(rollback_command("winget", "Git.Git", "2.45.0").is_err());
+    }
+}
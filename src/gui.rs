@@ -1,23 +1,74 @@
 use crate::brain::OmniBrain;
 use crate::branding::OmniBranding;
+use crate::config::{OmniConfig, TextSize};
 use crate::distro::{get_available_package_managers, get_os_display_name};
+use crate::incremental_search::IncrementalSearch;
+use crate::lifecycle::{self, Support};
 use crate::manifest::OmniManifest;
+use crate::package_queue::{self, PackageQueue, QueueProgress, QueuedOperation};
 use crate::search::SearchResult;
 use crate::database::InstallRecord;
 use eframe::{egui, App};
 use rfd::FileDialog;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum Tab {
     Dashboard,
     Installed,
+    Queue,
     History,
     Systems,
     Settings,
 }
 
+impl Tab {
+    /// In sidebar order; Ctrl+1 to Ctrl+6 select them
+    const ALL: [Tab; 6] = [
+        Tab::Dashboard,
+        Tab::Installed,
+        Tab::Queue,
+        Tab::History,
+        Tab::Systems,
+        Tab::Settings,
+    ];
+    const KEYS: [egui::Key; 6] = [
+        egui::Key::Num1,
+        egui::Key::Num2,
+        egui::Key::Num3,
+        egui::Key::Num4,
+        egui::Key::Num5,
+        egui::Key::Num6,
+    ];
+
+    fn icon(self) -> &'static str {
+        match self {
+            Tab::Dashboard => "📊",
+            Tab::Installed => "📦",
+            Tab::Queue => "🧾",
+            Tab::History => "📜",
+            Tab::Systems => "🖥️",
+            Tab::Settings => "⚙️",
+        }
+    }
+}
+
+/// Name `response` for screen readers, and in a tooltip, where its text is
+/// only an icon or starts with one
+fn accessible(response: egui::Response, typ: egui::WidgetType, name: &str) -> egui::Response {
+    response.widget_info(|| egui::WidgetInfo::labeled(typ, response.enabled(), name));
+    response.on_hover_text(name)
+}
+
+/// A change to the queue picked in the queue tab, applied after drawing it
+enum QueueEdit {
+    Up(usize),
+    Down(usize),
+    Cancel(usize),
+}
+
 #[derive(PartialEq)]
 enum SearchFilter {
     All,
@@ -37,6 +88,7 @@ enum SortMode {
 
 pub struct OmniGui {
     brain: OmniBrain,
+    mock: bool,
     active_tab: Tab,
     package_input: String,
     manifest_path: String,
@@ -56,12 +108,31 @@ pub struct OmniGui {
     history: Vec<InstallRecord>,
     ssh_host: String,
     container_name: String,
+    /// End-of-life status of the running release, from the bundled dates
+    release_status: Option<lifecycle::Status>,
+    queue: PackageQueue,
+    /// Progress of the queue while it runs
+    queue_progress: Option<Arc<Mutex<QueueProgress>>>,
+    /// Searches the local index as the package name is typed
+    live_search: IncrementalSearch,
+    text_size: TextSize,
+    /// Move keyboard focus to the search box on the next frame
+    focus_search: bool,
 }
 
 impl Default for OmniGui {
     fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl OmniGui {
+    /// A GUI working on the system, or on the in-memory backend with `mock`
+    pub fn new(mock: bool) -> Self {
+        let config = OmniConfig::load().unwrap_or_default();
         let mut gui = Self {
-            brain: OmniBrain::new(),
+            brain: OmniBrain::new_with_mock(mock),
+            mock,
             active_tab: Tab::Dashboard,
             package_input: String::new(),
             manifest_path: String::new(),
@@ -81,6 +152,12 @@ impl Default for OmniGui {
             history: Vec::new(),
             ssh_host: String::new(),
             container_name: String::new(),
+            release_status: lifecycle::check_offline(&config.release_support),
+            queue: PackageQueue::default(),
+            queue_progress: None,
+            live_search: IncrementalSearch::spawn(mock),
+            text_size: config.ui.gui_text_size,
+            focus_search: false,
         };
 
         // Initialize with real data
@@ -92,6 +169,11 @@ impl Default for OmniGui {
 impl App for OmniGui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         ctx.request_repaint();
+        self.handle_shortcuts(ctx);
+        self.poll_queue();
+        if let Some(results) = self.live_search.poll() {
+            self.search_results = results;
+        }
 
         // Enhanced theme with custom styling
         let mut visuals = if self.dark_mode {
@@ -180,10 +262,18 @@ impl App for OmniGui {
                         }
 
                         // Enhanced theme toggle
-                        let theme_btn = ui.add_sized(
-                            [40.0, 32.0],
-                            egui::Button::new(if self.dark_mode { "☀" } else { "🌙" })
-                                .corner_radius(16.0),
+                        let theme_btn = accessible(
+                            ui.add_sized(
+                                [40.0, 32.0],
+                                egui::Button::new(if self.dark_mode { "☀" } else { "🌙" })
+                                    .corner_radius(16.0),
+                            ),
+                            egui::WidgetType::Button,
+                            if self.dark_mode {
+                                "Switch to light mode"
+                            } else {
+                                "Switch to dark mode"
+                            },
                         );
 
                         if theme_btn.clicked() {
@@ -205,73 +295,43 @@ impl App for OmniGui {
                     let tab_height = 48.0;
                     let tab_spacing = 8.0;
 
-                    // Dashboard tab
-                    ui.add_space(tab_spacing);
-                    let dashboard_response = ui.add_sized(
-                        [180.0, tab_height],
-                        egui::SelectableLabel::new(
-                            self.active_tab == Tab::Dashboard,
-                            egui::RichText::new("📊  Dashboard").size(16.0).strong(),
-                        ),
-                    );
-                    if dashboard_response.clicked() {
-                        self.active_tab = Tab::Dashboard;
-                    }
-
-                    // Installed tab with badge
-                    ui.add_space(tab_spacing);
-                    let installed_text = format!(
-                        "📦  Installed ({})",
-                        self.package_stats.get("Installed").unwrap_or(&0)
-                    );
-                    let installed_response = ui.add_sized(
-                        [180.0, tab_height],
-                        egui::SelectableLabel::new(
-                            self.active_tab == Tab::Installed,
-                            egui::RichText::new(installed_text).size(16.0),
-                        ),
-                    );
-                    if installed_response.clicked() {
-                        self.active_tab = Tab::Installed;
-                    }
-
-                    // History tab
-                    ui.add_space(tab_spacing);
-                    let history_response = ui.add_sized(
-                        [180.0, tab_height],
-                        egui::SelectableLabel::new(
-                            self.active_tab == Tab::History,
-                            egui::RichText::new("📜  History").size(16.0),
-                        ),
-                    );
-                    if history_response.clicked() {
-                        self.active_tab = Tab::History;
-                    }
-
-                    // Systems tab
-                    ui.add_space(tab_spacing);
-                    let systems_response = ui.add_sized(
-                        [180.0, tab_height],
-                        egui::SelectableLabel::new(
-                            self.active_tab == Tab::Systems,
-                            egui::RichText::new("🖥️  Systems").size(16.0),
-                        ),
-                    );
-                    if systems_response.clicked() {
-                        self.active_tab = Tab::Systems;
-                    }
-
-                    // Settings tab
-                    ui.add_space(tab_spacing);
-                    let settings_response = ui.add_sized(
-                        [180.0, tab_height],
-                        egui::SelectableLabel::new(
-                            self.active_tab == Tab::Settings,
-                            egui::RichText::new("⚙️  Settings").size(16.0),
-                        ),
-                    );
-                    if settings_response.clicked() {
-                        self.active_tab = Tab::Settings;
+                    for (tab, key) in Tab::ALL.into_iter().zip(Tab::KEYS) {
+                        // Counts ride along as badges
+                        let name = match tab {
+                            Tab::Dashboard => "Dashboard".to_string(),
+                            Tab::Installed => format!(
+                                "Installed ({})",
+                                self.package_stats.get("Installed").unwrap_or(&0)
+                            ),
+                            Tab::Queue => format!("Queue ({})", self.queue.len()),
+                            Tab::History => "History".to_string(),
+                            Tab::Systems => "Systems".to_string(),
+                            Tab::Settings => "Settings".to_string(),
+                        };
+                        let selected = self.active_tab == tab;
+
+                        ui.add_space(tab_spacing);
+                        let response = ui
+                            .add_sized(
+                                [180.0, tab_height],
+                                egui::SelectableLabel::new(
+                                    selected,
+                                    egui::RichText::new(format!("{}  {}", tab.icon(), name))
+                                        .size(16.0),
+                                ),
+                            )
+                            .on_hover_text(format!("Ctrl+{}", key.name()));
+                        response.widget_info(|| {
+                            egui::WidgetInfo::selected(
+                                egui::WidgetType::SelectableLabel,
+                                true,
+                                selected,
+                                &name,
+                            )
+                        });
+                        if response.clicked() {
+                            self.active_tab = tab;
+                        }
                     }
 
                     ui.add_space(32.0);
@@ -328,6 +388,7 @@ impl App for OmniGui {
         egui::CentralPanel::default().show(ctx, |ui| match self.active_tab {
             Tab::Dashboard => self.show_dashboard(ui),
             Tab::Installed => self.show_installed(ui),
+            Tab::Queue => self.show_queue(ui),
             Tab::History => self.show_history(ui),
             Tab::Systems => self.show_systems(ui),
             Tab::Settings => self.show_settings(ui),
@@ -436,6 +497,20 @@ impl OmniGui {
                     egui::RichText::new(format!("📦 {} package managers", managers.len()))
                         .color(egui::Color32::from_rgb(100, 150, 255)),
                 );
+                if let Some(status) = &self.release_status {
+                    let color = match status.support {
+                        Support::EndingSoon { .. } => Some(egui::Color32::from_rgb(255, 165, 0)),
+                        Support::Ended { .. } => Some(egui::Color32::from_rgb(244, 67, 54)),
+                        Support::Supported { .. } | Support::Unknown => None,
+                    };
+                    if let Some(color) = color {
+                        ui.separator();
+                        ui.label(
+                            egui::RichText::new(format!("⚠️ {}", status.describe()))
+                                .color(color),
+                        );
+                    }
+                }
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                     if ui
                         .button(
@@ -456,8 +531,8 @@ impl OmniGui {
         ui.group(|ui| {
             ui.vertical(|ui| {
                 // Main search bar
-                ui.horizontal(|ui| {
-                    ui.label(
+                let search_label = ui.horizontal(|ui| {
+                    let label = ui.label(
                         egui::RichText::new("🔍 Search packages:")
                             .size(16.0)
                             .strong(),
@@ -476,23 +551,36 @@ impl OmniGui {
                             self.show_advanced_search = !self.show_advanced_search;
                         }
                     });
+                    label.id
                 });
 
                 ui.add_space(8.0);
 
                 // Search input with enhanced styling
                 ui.horizontal(|ui| {
-                    let search_response = ui.add_sized(
-                        [300.0, 32.0],
-                        egui::TextEdit::singleline(&mut self.package_input)
-                            .hint_text("Enter package name...")
-                            .font(egui::TextStyle::Body),
-                    );
+                    let search_response = ui
+                        .add_sized(
+                            [300.0, 32.0],
+                            egui::TextEdit::singleline(&mut self.package_input)
+                                .hint_text(
+                                    "Type a package name; Enter searches every package manager",
+                                )
+                                .font(egui::TextStyle::Body),
+                        )
+                        .labelled_by(search_label.inner);
+                    if self.focus_search {
+                        search_response.request_focus();
+                        self.focus_search = false;
+                    }
+                    if search_response.changed() {
+                        self.live_search.edited(&self.package_input);
+                    }
 
                     // Keyboard shortcut handling
                     if search_response.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))
                     {
                         if !self.package_input.is_empty() {
+                            self.live_search.cancel();
                             // Use async search - for GUI we'll use block_on for now
                             if let Ok(results) =
                                 futures::executor::block_on(self.brain.search(&self.package_input))
@@ -509,6 +597,7 @@ impl OmniGui {
                         egui::Button::new("🔍 Search").corner_radius(6.0),
                     );
                     if search_btn.clicked() && !self.package_input.is_empty() {
+                        self.live_search.cancel();
                         // Use async search - for GUI we'll use block_on for now
                         if let Ok(results) =
                             futures::executor::block_on(self.brain.search(&self.package_input))
@@ -580,8 +669,9 @@ impl OmniGui {
         ui.add_space(16.0);
 
         ui.horizontal(|ui| {
-            ui.label("Manifest file:");
-            ui.text_edit_singleline(&mut self.manifest_path);
+            let label = ui.label("Manifest file:");
+            ui.text_edit_singleline(&mut self.manifest_path)
+                .labelled_by(label.id);
             if ui.button("📁 Browse").clicked() {
                 if let Some(path) = FileDialog::new()
                     .add_filter("YAML", &["yml", "yaml"])
@@ -686,6 +776,23 @@ impl OmniGui {
                                                             .insert(result.name.clone(), 0.0);
                                                     }
 
+                                                    let queue_btn = ui.add_sized(
+                                                        [80.0, 28.0],
+                                                        egui::Button::new("➕ Queue")
+                                                            .corner_radius(4.0),
+                                                    );
+                                                    if queue_btn.clicked() {
+                                                        self.queue.push(QueuedOperation::install(
+                                                            &result.name,
+                                                            Some(result.box_type.as_str()),
+                                                        ));
+                                                        self.status = format!(
+                                                            "Queued {} ({} in queue)",
+                                                            result.name,
+                                                            self.queue.len()
+                                                        );
+                                                    }
+
                                                     // Popularity indicator
                                                     let popularity = ((i + 1) as f32
                                                         / self.search_results.len() as f32)
@@ -782,11 +889,158 @@ impl OmniGui {
                         let _ = futures::executor::block_on(self.brain.remove(package, None));
                         self.status = format!("Removing {}", package);
                     }
+                    if ui.button("➕ Queue Removal").clicked() {
+                        self.queue.push(QueuedOperation::remove(package));
+                        self.status = format!(
+                            "Queued removal of {} ({} in queue)",
+                            package,
+                            self.queue.len()
+                        );
+                    }
                 });
             }
         });
     }
 
+    fn show_queue(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Package Queue");
+        let running = self.queue_progress.is_some();
+
+        ui.horizontal(|ui| {
+            let run_btn = ui.add_enabled(
+                !running && !self.queue.is_empty(),
+                egui::Button::new("▶ Run Queue").fill(egui::Color32::from_rgb(50, 150, 50)),
+            );
+            if run_btn.clicked() {
+                self.queue_progress = Some(package_queue::spawn(
+                    self.queue.operations().to_vec(),
+                    self.mock,
+                ));
+                self.status = format!("Running {} queued operations", self.queue.len());
+            }
+            if ui
+                .add_enabled(
+                    !running && !self.queue.is_empty(),
+                    egui::Button::new("🗑️ Clear"),
+                )
+                .clicked()
+            {
+                self.queue.clear();
+            }
+        });
+
+        // Combined progress over every queued operation
+        if let Some(progress) = &self.queue_progress {
+            let progress = progress.lock().map(|p| p.clone()).unwrap_or_default();
+            ui.add_space(8.0);
+            ui.label(format!(
+                "{} ({} of {} done)",
+                progress.current.as_deref().unwrap_or("Starting"),
+                progress.done,
+                progress.total
+            ));
+            ui.add(
+                egui::ProgressBar::new(progress.fraction())
+                    .show_percentage()
+                    .desired_width(400.0)
+                    .fill(egui::Color32::from_rgb(50, 150, 50)),
+            );
+        }
+
+        ui.separator();
+
+        if self.queue.is_empty() {
+            ui.label("Nothing queued. Add packages with ➕ Queue in search results or the installed list.");
+            return;
+        }
+
+        let mut edit = None;
+        let last = self.queue.len() - 1;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (index, operation) in self.queue.operations().iter().enumerate() {
+                // Laid out left to right, so Tab visits the buttons in the
+                // order they are drawn
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}.", index + 1));
+                    ui.label(operation.describe());
+                    let button = |ui: &mut egui::Ui, enabled: bool, icon: &str, name: String| {
+                        let response = ui.add_enabled(enabled, egui::Button::new(icon));
+                        accessible(response, egui::WidgetType::Button, &name).clicked()
+                    };
+                    let package = &operation.package;
+                    let up = format!("Move {} up", package);
+                    if button(ui, !running && index > 0, "⬆", up) {
+                        edit = Some(QueueEdit::Up(index));
+                    }
+                    let down = format!("Move {} down", package);
+                    if button(ui, !running && index < last, "⬇", down) {
+                        edit = Some(QueueEdit::Down(index));
+                    }
+                    let cancel = format!("Cancel {}", operation.describe());
+                    if button(ui, !running, "✖", cancel) {
+                        edit = Some(QueueEdit::Cancel(index));
+                    }
+                });
+            }
+        });
+
+        match edit {
+            Some(QueueEdit::Up(index)) => self.queue.move_up(index),
+            Some(QueueEdit::Down(index)) => self.queue.move_down(index),
+            Some(QueueEdit::Cancel(index)) => {
+                self.queue.cancel(index);
+            }
+            None => {}
+        }
+    }
+
+    /// Ctrl+1 to Ctrl+6 switch tabs; Ctrl+F jumps to the search box
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        ctx.input_mut(|input| {
+            for (tab, key) in Tab::ALL.into_iter().zip(Tab::KEYS) {
+                if input.consume_key(egui::Modifiers::COMMAND, key) {
+                    self.active_tab = tab;
+                }
+            }
+            if input.consume_key(egui::Modifiers::COMMAND, egui::Key::F) {
+                self.active_tab = Tab::Dashboard;
+                self.focus_search = true;
+            }
+        });
+    }
+
+    /// Keep the text size for the next start
+    fn save_text_size(&mut self) {
+        let saved = OmniConfig::load_user().and_then(|mut config| {
+            config.ui.gui_text_size = self.text_size;
+            config.save()
+        });
+        if let Err(e) = saved {
+            self.status = format!("Text size not saved: {}", e);
+        }
+    }
+
+    /// Pick up the outcome of a queue run once it has finished
+    fn poll_queue(&mut self) {
+        let Some(progress) = &self.queue_progress else {
+            return;
+        };
+        let progress = progress.lock().map(|p| p.clone()).unwrap_or_default();
+        if !progress.finished {
+            return;
+        }
+
+        self.queue_progress = None;
+        match progress.error {
+            None => {
+                self.status = format!("Queue finished: {} operations applied", progress.total);
+                self.queue.clear();
+                self.refresh_stats();
+            }
+            Some(e) => self.status = format!("Queue failed: {}", e),
+        }
+    }
+
     fn show_history(&mut self, ui: &mut egui::Ui) {
         ui.heading("Installation History & Snapshots");
 
@@ -871,16 +1125,18 @@ impl OmniGui {
             ui.label("Manage packages on remote Linux servers from Windows/macOS");
 
             ui.horizontal(|ui| {
-                ui.label("SSH Host:");
-                ui.text_edit_singleline(&mut self.ssh_host);
+                let label = ui.label("SSH Host:");
+                ui.text_edit_singleline(&mut self.ssh_host)
+                    .labelled_by(label.id);
                 if ui.button("Connect").clicked() {
                     self.status = format!("Connecting to {}...", self.ssh_host);
                 }
             });
 
             ui.horizontal(|ui| {
-                ui.label("Docker Container:");
-                ui.text_edit_singleline(&mut self.container_name);
+                let label = ui.label("Docker Container:");
+                ui.text_edit_singleline(&mut self.container_name)
+                    .labelled_by(label.id);
                 if ui.button("Attach").clicked() {
                     self.status = format!("Attaching to container {}...", self.container_name);
                 }
@@ -915,6 +1171,27 @@ impl OmniGui {
 
         ui.checkbox(&mut self.dark_mode, "Dark Mode");
 
+        ui.horizontal(|ui| {
+            ui.label("Text size:");
+            for size in TextSize::ALL {
+                let name = match size {
+                    TextSize::Normal => "Normal",
+                    TextSize::Large => "Large",
+                    TextSize::Larger => "Larger",
+                };
+                if ui.radio_value(&mut self.text_size, size, name).changed() {
+                    ui.ctx().set_zoom_factor(size.factor());
+                    self.save_text_size();
+                }
+            }
+        });
+
+        ui.separator();
+        ui.label("Keyboard:");
+        ui.label("• Tab and Shift+Tab move between controls; Space or Enter activates one");
+        ui.label("• Ctrl+1 to Ctrl+6 switch tabs, Ctrl+F jumps to the search box");
+        ui.label("• Ctrl+Plus and Ctrl+Minus zoom, Ctrl+0 resets the zoom");
+
         ui.separator();
         ui.label("Package Manager Preferences:");
         // TODO: Add package manager priority settings
@@ -926,7 +1203,7 @@ impl OmniGui {
     }
 }
 
-pub fn launch_gui() {
+pub fn launch_gui(mock: bool) {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1200.0, 800.0])
@@ -937,25 +1214,25 @@ pub fn launch_gui() {
     let _ = eframe::run_native(
         "Omni - Universal Cross-Platform Package Manager",
         options,
-        Box::new(|_| Ok(Box::new(OmniGui::default()))),
+        Box::new(move |cc| {
+            let gui = OmniGui::new(mock);
+            cc.egui_ctx.set_zoom_factor(gui.text_size.factor());
+            Ok(Box::new(gui))
+        }),
     );
 }
 
 impl OmniGui {
     /// Refresh package statistics with real data from the system
     fn refresh_stats(&mut self) {
-        // Get real data from the brain's list_installed functionality
-        match self.brain.list_installed() {
+        // Get real data from the installed-package records
+        match futures::executor::block_on(self.brain.installed_packages()) {
             Ok(installed_packages) => {
-                let total_installed: usize = installed_packages.values().map(|v| v.len()).sum();
                 self.package_stats
-                    .insert("Installed".to_string(), total_installed as u32);
+                    .insert("Installed".to_string(), installed_packages.len() as u32);
 
                 // Store installed packages for display
-                self.installed_packages = installed_packages
-                    .into_iter()
-                    .flat_map(|(_, packages)| packages)
-                    .collect();
+                self.installed_packages = installed_packages;
             }
             Err(e) => {
                 self.status = format!("Failed to get installed packages: {}", e);
@@ -0,0 +1,228 @@
+//! `omni health` and `GET /health`: whether omni can work right now, for
+//! container readiness probes and monitoring. Only errors make it unhealthy.
+
+use crate::config::OmniConfig;
+use crate::database::Database;
+use crate::distro;
+use crate::doctor::{self, Check, Level};
+use crate::lock;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Below this much free space omni cannot be relied on to write its database
+const MIN_FREE_KIB: u64 = 512 * 1024;
+/// Below this much free space downloads may start failing
+const LOW_FREE_KIB: u64 = 2 * 1024 * 1024;
+/// Lock pacman leaves behind when it is killed mid-transaction
+const PACMAN_LOCK: &str = "/var/lib/pacman/db.lck";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub healthy: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn new(checks: Vec<Check>) -> Self {
+        Self {
+            healthy: checks.iter().all(|c| c.level != Level::Error),
+            checks,
+        }
+    }
+}
+
+pub async fn run() -> Report {
+    let mut checks = vec![scope(), database().await, backends()];
+    checks.extend(disk_space());
+    checks.extend(locks());
+    Report::new(checks)
+}
+
+fn scope() -> Check {
+    match OmniConfig::check_state_ownership() {
+        Ok(()) => Check::new("State scope", Level::Ok, OmniConfig::scope_summary()),
+        Err(e) => Check::new("State scope", Level::Error, e.to_string()),
+    }
+}
+
+async fn database() -> Check {
+    let result = match Database::new().await {
+        Ok(db) => db.get_install_history(Some(1)).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => Check::new("Database", Level::Ok, "Install history is readable"),
+        Err(e) => Check::new("Database", Level::Error, e.to_string()),
+    }
+}
+
+fn backends() -> Check {
+    let managers = distro::get_available_package_managers();
+    if managers.is_empty() {
+        Check::new(
+            "Package managers",
+            Level::Error,
+            "None found; omni needs at least one supported package manager",
+        )
+    } else {
+        Check::new("Package managers", Level::Ok, managers.join(", "))
+    }
+}
+
+/// Free space where omni keeps its database and its caches
+fn disk_space() -> Vec<Check> {
+    let mut dirs: Vec<PathBuf> = [OmniConfig::data_dir(), OmniConfig::cache_dir()]
+        .into_iter()
+        .flatten()
+        .collect();
+    dirs.dedup();
+
+    dirs.iter()
+        .map(|dir| match free_kib(dir) {
+            Some(free) => Check::new(
+                "Disk space",
+                disk_level(free),
+                format!("{} MiB free for {}", free / 1024, dir.display()),
+            ),
+            None => Check::new(
+                "Disk space",
+                Level::Warning,
+                format!("Could not read free space for {}", dir.display()),
+            ),
+        })
+        .collect()
+}
+
+pub fn disk_level(free_kib: u64) -> Level {
+    if free_kib < MIN_FREE_KIB {
+        Level::Error
+    } else if free_kib < LOW_FREE_KIB {
+        Level::Warning
+    } else {
+        Level::Ok
+    }
+}
+
+/// KiB available on the filesystem holding `path`, or its closest existing parent
+pub fn free_kib(path: &Path) -> Option<u64> {
+    parse_df_available(&df(path)?)
+}
+
+/// KiB in use on the filesystem holding `path`
+pub fn used_kib(path: &Path) -> Option<u64> {
+    parse_df_used(&df(path)?)
+}
+
+fn df(path: &Path) -> Option<String> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let output = Command::new("df").arg("-Pk").arg(existing).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Available KiB in `df -Pk` output
+pub fn parse_df_available(output: &str) -> Option<u64> {
+    df_column(output, 3)
+}
+
+/// Used KiB in `df -Pk` output
+pub fn parse_df_used(output: &str) -> Option<u64> {
+    df_column(output, 2)
+}
+
+fn df_column(output: &str, column: usize) -> Option<u64> {
+    output
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(column)?
+        .parse()
+        .ok()
+}
+
+/// Locks left by processes that are gone, which block every later operation
+fn locks() -> Vec<Check> {
+    let mut checks = vec![match lock::operations_holder() {
+        Some(pid) if process_exists(pid) => Check::new(
+            "Operations lock",
+            Level::Ok,
+            format!("Held by omni process {}", pid),
+        ),
+        Some(pid) => Check::new(
+            "Operations lock",
+            Level::Warning,
+            format!(
+                "Held by pid {}, which is not visible here; another container may share the runtime directory",
+                pid
+            ),
+        ),
+        None => Check::new("Operations lock", Level::Ok, "Free"),
+    }];
+
+    if Path::new(PACMAN_LOCK).exists() && !process_running("pacman") {
+        checks.push(Check::new(
+            "Pacman lock",
+            Level::Error,
+            format!(
+                "{} is left over from an interrupted pacman; remove it once no pacman is running",
+                PACMAN_LOCK
+            ),
+        ));
+    }
+    checks
+}
+
+fn process_exists(pid: u32) -> bool {
+    Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+/// Whether a process with this command name is running
+fn process_running(name: &str) -> bool {
+    fs::read_dir("/proc")
+        .map(|entries| {
+            entries.flatten().any(|entry| {
+                fs::read_to_string(entry.path().join("comm")).is_ok_and(|comm| comm.trim() == name)
+            })
+        })
+        .unwrap_or(false)
+}
+
+pub fn print(report: &Report) {
+    doctor::print(&report.checks);
+    if report.healthy {
+        println!("\n💚 Healthy");
+    } else {
+        println!("\n💔 Unhealthy");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_report() {
+        let warning = Check::new("Disk space", Level::Warning, "1500 MiB free");
+        assert!(Report::new(vec![warning.clone()]).healthy);
+
+        let error = Check::new("Database", Level::Error, "unable to open database file");
+        let report = Report::new(vec![warning, error]);
+        assert!(!report.healthy);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["healthy"], false);
+        assert_eq!(json["checks"][1]["level"], "error");
+
+        assert_eq!(disk_level(100 * 1024), Level::Error);
+        assert_eq!(disk_level(1024 * 1024), Level::Warning);
+        assert_eq!(disk_level(20 * 1024 * 1024), Level::Ok);
+
+        let df = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n/dev/nvme0n1p2   490617784 201948372 263669636      44% /\n";
+        assert_eq!(parse_df_available(df), Some(263_669_636));
+        assert_eq!(parse_df_used(df), Some(201_948_372));
+        assert!(free_kib(Path::new("/definitely/not/here")).is_some());
+    }
+}
@@ -0,0 +1,354 @@
+//! Health checks run after an update (`health_checks` in the config): an HTTP
+//! probe, the state of a systemd unit or a custom command. Local updates and
+//! fleet rollouts undo the update when one of them fails.
+
+use crate::config::HealthCheckConfig;
+use crate::fleet::RemoteOutput;
+use crate::updater::matches_pattern;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Name of this machine in a check's `hosts`
+pub const LOCAL_HOST: &str = "local";
+
+/// What a check looks at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    Http(String),
+    SystemdUnit(String),
+    Command(String),
+}
+
+impl Probe {
+    /// The probe of a check; exactly one of its probe fields has to be set
+    pub fn of(check: &HealthCheckConfig) -> Result<Self> {
+        match (&check.http, &check.systemd_unit, &check.command) {
+            (Some(url), None, None) => {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(anyhow!(
+                        "Health check '{}': '{}' is not an http:// or https:// URL",
+                        label(check),
+                        url
+                    ));
+                }
+                Ok(Self::Http(url.clone()))
+            }
+            (None, Some(unit), None) => {
+                let valid = Regex::new(r"^[A-Za-z0-9@._:-]+$").expect("valid unit pattern");
+                if !valid.is_match(unit) {
+                    return Err(anyhow!("Invalid systemd unit name: {}", unit));
+                }
+                Ok(Self::SystemdUnit(unit.clone()))
+            }
+            (None, None, Some(command)) => Ok(Self::Command(command.clone())),
+            _ => Err(anyhow!(
+                "Health check '{}' needs exactly one of http, systemd_unit and command",
+                label(check)
+            )),
+        }
+    }
+}
+
+/// Result of one check after all its attempts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckOutcome {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Runs the commands of systemd and command probes on the updated machine
+#[async_trait(?Send)]
+pub trait Shell {
+    async fn run(&mut self, command: &str) -> Result<RemoteOutput>;
+}
+
+/// Runs probe commands on this machine
+pub struct LocalShell;
+
+#[async_trait(?Send)]
+impl Shell for LocalShell {
+    async fn run(&mut self, command: &str) -> Result<RemoteOutput> {
+        let output = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .await?;
+        Ok(RemoteOutput {
+            exit_code: output.status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+/// The name of a check, or what it probes when it has none
+pub fn label(check: &HealthCheckConfig) -> String {
+    if let Some(name) = &check.name {
+        return name.clone();
+    }
+    match (&check.http, &check.systemd_unit, &check.command) {
+        (Some(url), _, _) => url.clone(),
+        (_, Some(unit), _) => format!("systemd unit {}", unit),
+        (_, _, Some(command)) => command.clone(),
+        _ => "unnamed".to_string(),
+    }
+}
+
+/// Reject malformed checks before anything is updated, so a typo in the
+/// config never ends in a rollback
+pub fn validate(checks: &[HealthCheckConfig]) -> Result<()> {
+    for check in checks {
+        Probe::of(check)?;
+        if check.attempts == 0 {
+            return Err(anyhow!(
+                "Health check '{}' needs at least one attempt",
+                label(check)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The checks that apply to an update of `package` on `host`
+pub fn applicable<'a>(
+    checks: &'a [HealthCheckConfig],
+    package: &str,
+    host: &str,
+) -> Vec<&'a HealthCheckConfig> {
+    checks
+        .iter()
+        .filter(|check| {
+            check.packages.is_empty()
+                || check
+                    .packages
+                    .iter()
+                    .any(|pattern| matches_pattern(pattern, package))
+        })
+        .filter(|check| {
+            check.hosts.is_empty()
+                || check
+                    .hosts
+                    .iter()
+                    .any(|pattern| matches_pattern(pattern, host))
+        })
+        .collect()
+}
+
+/// Run `checks` one after another; `address` replaces `{host}` in HTTP probes
+pub async fn run(
+    checks: &[&HealthCheckConfig],
+    shell: &mut impl Shell,
+    address: &str,
+) -> Vec<CheckOutcome> {
+    let mut outcomes = Vec::new();
+
+    for check in checks {
+        let name = label(check);
+        let mut result = Err(anyhow!("not run"));
+        for attempt in 1..=check.attempts.max(1) {
+            if attempt > 1 {
+                tokio::time::sleep(Duration::from_secs(check.interval_seconds)).await;
+            }
+            result = probe(check, shell, address).await;
+            match &result {
+                Ok(_) => break,
+                Err(e) => warn!(
+                    "Health check '{}' failed (attempt {}/{}): {}",
+                    name, attempt, check.attempts, e
+                ),
+            }
+        }
+
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+        info!("Health check '{}': {}", name, detail);
+        outcomes.push(CheckOutcome {
+            name,
+            passed,
+            detail,
+        });
+    }
+
+    outcomes
+}
+
+async fn probe(check: &HealthCheckConfig, shell: &mut impl Shell, address: &str) -> Result<String> {
+    let timeout = Duration::from_secs(check.timeout_seconds);
+    match Probe::of(check)? {
+        Probe::Http(url) => {
+            let url = url.replace("{host}", address);
+            let client = reqwest::Client::builder().timeout(timeout).build()?;
+            let status = client.get(&url).send().await?.status();
+            if status.is_success() {
+                Ok(format!("HTTP {}", status))
+            } else {
+                Err(anyhow!("{} answered HTTP {}", url, status))
+            }
+        }
+        Probe::SystemdUnit(unit) => {
+            let command = format!("systemctl is-active {}", unit);
+            let output = run_with_timeout(shell, &command, timeout).await?;
+            let state = output.stdout.trim();
+            if output.exit_code == 0 {
+                Ok(format!("{} is active", unit))
+            } else {
+                Err(anyhow!(
+                    "{} is {}",
+                    unit,
+                    if state.is_empty() {
+                        "not active"
+                    } else {
+                        state
+                    }
+                ))
+            }
+        }
+        Probe::Command(command) => {
+            let output = run_with_timeout(shell, &command, timeout).await?;
+            if output.exit_code == 0 {
+                Ok("exited with 0".to_string())
+            } else {
+                let reason = if output.stderr.trim().is_empty() {
+                    output.stdout.trim()
+                } else {
+                    output.stderr.trim()
+                };
+                Err(anyhow!("exit code {}: {}", output.exit_code, reason))
+            }
+        }
+    }
+}
+
+async fn run_with_timeout(
+    shell: &mut impl Shell,
+    command: &str,
+    timeout: Duration,
+) -> Result<RemoteOutput> {
+    tokio::time::timeout(timeout, shell.run(command))
+        .await
+        .map_err(|_| anyhow!("timed out after {}s", timeout.as_secs()))?
+}
+
+/// The failed checks as one line, `None` when all passed
+pub fn failures(outcomes: &[CheckOutcome]) -> Option<String> {
+    let failed: Vec<String> = outcomes
+        .iter()
+        .filter(|outcome| !outcome.passed)
+        .map(|outcome| format!("{}: {}", outcome.name, outcome.detail))
+        .collect();
+    (!failed.is_empty()).then(|| failed.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers commands with the queued exit codes, 0 once they run out
+    struct ScriptedShell {
+        exit_codes: Vec<i32>,
+        commands: Vec<String>,
+    }
+
+    #[async_trait(?Send)]
+    impl Shell for ScriptedShell {
+        async fn run(&mut self, command: &str) -> Result<RemoteOutput> {
+            self.commands.push(command.to_string());
+            let exit_code = if self.exit_codes.is_empty() {
+                0
+            } else {
+                self.exit_codes.remove(0)
+            };
+            Ok(RemoteOutput {
+                exit_code,
+                stdout: if exit_code == 0 { "active" } else { "failed" }.to_string(),
+                stderr: String::new(),
+            })
+        }
+    }
+
+    fn check(packages: &[&str], hosts: &[&str]) -> HealthCheckConfig {
+        HealthCheckConfig {
+            packages: packages.iter().map(|p| p.to_string()).collect(),
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            systemd_unit: Some("nginx.service".to_string()),
+            interval_seconds: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[check(&[], &[])]).is_ok());
+
+        let both = HealthCheckConfig {
+            command: Some("true".to_string()),
+            ..check(&[], &[])
+        };
+        assert!(validate(&[both]).is_err());
+        assert!(validate(&[HealthCheckConfig::default()]).is_err());
+
+        let bad_url = HealthCheckConfig {
+            http: Some("localhost/health".to_string()),
+            ..Default::default()
+        };
+        assert!(validate(&[bad_url]).is_err());
+
+        let bad_unit = HealthCheckConfig {
+            systemd_unit: Some("nginx; reboot".to_string()),
+            ..Default::default()
+        };
+        assert!(validate(&[bad_unit]).is_err());
+    }
+
+    #[test]
+    fn test_applicable() {
+        let checks = vec![
+            check(&[], &[]),
+            check(&["nginx*"], &[]),
+            check(&[], &["web-*"]),
+            check(&["postgresql"], &[LOCAL_HOST]),
+        ];
+
+        assert_eq!(applicable(&checks, "nginx-core", "web-1").len(), 3);
+        assert_eq!(applicable(&checks, "curl", "db-1").len(), 1);
+        assert_eq!(applicable(&checks, "postgresql", LOCAL_HOST).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_run_retries_until_attempts_run_out() {
+        let unit = check(&[], &[]);
+        let mut shell = ScriptedShell {
+            exit_codes: vec![3, 0],
+            commands: Vec::new(),
+        };
+        let outcomes = run(&[&unit], &mut shell, "localhost").await;
+        assert!(outcomes[0].passed);
+        assert_eq!(shell.commands, ["systemctl is-active nginx.service"; 2]);
+        assert!(failures(&outcomes).is_none());
+
+        let command = HealthCheckConfig {
+            name: Some("smoke test".to_string()),
+            command: Some("/usr/local/bin/smoke".to_string()),
+            systemd_unit: None,
+            attempts: 2,
+            ..unit
+        };
+        let mut shell = ScriptedShell {
+            exit_codes: vec![1, 1, 1],
+            commands: Vec::new(),
+        };
+        let outcomes = run(&[&command], &mut shell, "localhost").await;
+        assert!(!outcomes[0].passed);
+        assert_eq!(shell.commands.len(), 2);
+        assert_eq!(
+            failures(&outcomes).as_deref(),
+            Some("smoke test: exit code 1: failed")
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Extended help: `omni help <topic>` prints guides compiled into the binary,
+//! so they always describe the omni that is installed, and `omni man` writes
+//! man pages generated from the clap definitions. The pages are written by
+//! the built binary, not by build.rs: the definitions live in main.rs and use
+//! value enums from across the crate, which a build script cannot include.
+
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Topic {
+    pub name: &'static str,
+    pub summary: &'static str,
+    /// Markdown; see `render`
+    pub body: &'static str,
+}
+
+pub const TOPICS: &[Topic] = &[
+    Topic {
+        name: "config",
+        summary: "Where the config file lives and what its sections do",
+        body: include_str!("help/config.md"),
+    },
+    Topic {
+        name: "manifests",
+        summary: "Installing a set of packages from a YAML manifest",
+        body: include_str!("help/manifests.md"),
+    },
+    Topic {
+        name: "notifications",
+        summary: "Webhooks, desktop notifications and audit alerts",
+        body: include_str!("help/notifications.md"),
+    },
+    Topic {
+        name: "security",
+        summary: "Verifying packages, read-only mode and the audit log",
+        body: include_str!("help/security.md"),
+    },
+    Topic {
+        name: "snapshots",
+        summary: "Snapshots, history and undoing changes",
+        body: include_str!("help/snapshots.md"),
+    },
+];
+
+/// The topic called `name`, also in the singular ("manifest")
+pub fn find(name: &str) -> Option<&'static Topic> {
+    let name = name.to_lowercase();
+    TOPICS
+        .iter()
+        .find(|topic| topic.name == name || topic.name.strip_suffix('s') == Some(&name))
+}
+
+/// The list printed under `omni help`
+pub fn topic_list() -> String {
+    let mut list = String::from("Help topics (omni help <topic>):\n");
+    for topic in TOPICS {
+        list.push_str(&format!("  {:<15}{}\n", topic.name, topic.summary));
+    }
+    list
+}
+
+/// Markdown as plain terminal text: headings in bold when `bold`, code
+/// blocks indented, fences dropped
+pub fn render(body: &str, bold: bool) -> String {
+    let mut out = String::new();
+    let mut in_code = false;
+    for line in body.lines() {
+        if line.starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            out.push_str(&format!("    {}\n", line));
+            continue;
+        }
+
+        let heading = line
+            .strip_prefix("# ")
+            .map(str::to_uppercase)
+            .or_else(|| line.strip_prefix("## ").map(str::to_string));
+        match heading {
+            Some(heading) if bold => out.push_str(&format!("\x1b[1m{}\x1b[0m\n", heading)),
+            Some(heading) => out.push_str(&format!("{}\n", heading)),
+            None => out.push_str(&format!("{}\n", line)),
+        }
+    }
+    out
+}
+
+/// Write `<name>.1` for `command` and `<name>-<subcommand>.1` for each of
+/// its subcommands, nested ones included, into `dir`
+pub fn write_man_pages(command: clap::Command, dir: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut command = command;
+    command.build();
+    let source = format!(
+        "{} {}",
+        command.get_name(),
+        command.get_version().unwrap_or("")
+    );
+    let mut written = Vec::new();
+    write_man_page(&command, command.get_name(), &source, dir, &mut written)?;
+    Ok(written)
+}
+
+fn write_man_page(
+    command: &clap::Command,
+    name: &str,
+    source: &str,
+    dir: &Path,
+    written: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let page = command.clone().display_name(name.to_string());
+    let mut roff = Vec::new();
+    clap_mangen::Man::new(page)
+        .source(source.trim())
+        .render(&mut roff)?;
+    let path = dir.join(format!("{}.1", name));
+    fs::write(&path, roff)?;
+    written.push(path);
+
+    for sub in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        if sub.get_name() == "help" {
+            continue;
+        }
+        let sub_name = format!("{}-{}", name, sub.get_name());
+        write_man_page(sub, &sub_name, source, dir, written)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topics() {
+        for topic in TOPICS {
+            assert!(topic.body.starts_with("# "), "{} has no title", topic.name);
+        }
+        assert_eq!(find("Manifest").map(|t| t.name), Some("manifests"));
+        assert_eq!(find("config").map(|t| t.name), Some("config"));
+        assert!(find("nonsense").is_none());
+        assert!(topic_list().contains("  security       Verifying packages"));
+
+        let text = render("# Title\n\n```\nomni install\n```\n## Part\n", false);
+        assert_eq!(text, "TITLE\n\n    omni install\nPart\n");
+    }
+
+    #[test]
+    fn test_write_man_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = clap::Command::new("omni")
+            .about("Universal package manager")
+            .subcommand(
+                clap::Command::new("fleet")
+                    .about("Manage hosts")
+                    .subcommand(clap::Command::new("add-host").about("Add a host")),
+            )
+            .subcommand(clap::Command::new("man").hide(true));
+
+        let written = write_man_pages(command, dir.path()).unwrap();
+        let names: Vec<_> = written
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["omni.1", "omni-fleet.1", "omni-fleet-add-host.1"]);
+        let page = fs::read_to_string(dir.path().join("omni-fleet-add-host.1")).unwrap();
+        assert!(page.contains("Add a host"));
+    }
+}
@@ -1,13 +1,49 @@
+use crate::audit;
 use crate::boxes::apt::AptManager;
 use crate::boxes::dnf::DnfBox;
 use crate::boxes::flatpak::FlatpakBox;
 use crate::boxes::pacman::PacmanBox;
+use crate::config::{self, OmniConfig};
+use crate::database::{self, InstallStatus};
 use crate::distro::PackageManager;
+use crate::downgrade;
+use crate::transaction::OperationType;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::OpenOptions;
 use std::io::BufReader;
+use std::path::{Path, PathBuf};
 
-const HISTORY_FILE: &str = ".omni_history.json";
+const HISTORY_FILE: &str = "history.json";
+
+/// Install history lives in the per-user state directory rather than the
+/// current working directory
+fn history_path() -> PathBuf {
+    OmniConfig::state_dir()
+        .unwrap_or_else(|_| PathBuf::from(".omni"))
+        .join(HISTORY_FILE)
+}
+
+fn write_history(history: &[InstallRecord]) {
+    let path = history_path();
+    let result = (|| -> anyhow::Result<()> {
+        config::ensure_writable("recording install history")?;
+        if let Some(parent) = path.parent() {
+            config::ensure_private_dir(parent)?;
+        }
+        config::write_private_file(&path, &serde_json::to_vec_pretty(history)?)
+    })();
+    if let Err(e) = result {
+        eprintln!(
+            "❌ Failed to write install history to {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct InstallRecord {
@@ -26,19 +62,11 @@ pub fn save_install(package: &str, box_type: &str) {
 
     let mut history = load_history();
     history.push(record);
-
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(HISTORY_FILE)
-        .unwrap();
-
-    serde_json::to_writer_pretty(file, &history).unwrap();
+    write_history(&history);
 }
 
 pub fn load_history() -> Vec<InstallRecord> {
-    let file = OpenOptions::new().read(true).open(HISTORY_FILE);
+    let file = OpenOptions::new().read(true).open(history_path());
     match file {
         Ok(f) => {
             let reader = BufReader::new(f);
@@ -91,13 +119,525 @@ pub fn undo_last_install() {
             }
             other => eprintln!("❌ Unknown box type: {}", other),
         }
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(HISTORY_FILE)
-            .unwrap();
-        serde_json::to_writer_pretty(file, &history).unwrap();
+        write_history(&history);
     } else {
         println!("📭 No install history found.");
     }
 }
+
+/// Installed packages keyed by (box type, name), with the version recorded
+pub type PackageSet = BTreeMap<(String, String), Option<String>>;
+
+/// How a package differs between two points in the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Upgraded,
+    Downgraded,
+    /// Reinstalled with a version that cannot be compared
+    Changed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackageChange {
+    pub box_type: String,
+    pub package: String,
+    pub kind: ChangeKind,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// Replay `records`, oldest first, up to and including `at`
+pub fn package_set_at(records: &[database::InstallRecord], at: DateTime<Utc>) -> PackageSet {
+    let mut packages = PackageSet::new();
+    for record in records.iter().filter(|r| r.installed_at <= at) {
+        let key = (record.box_type.clone(), record.package_name.clone());
+        match record.status {
+            InstallStatus::Success | InstallStatus::Updated | InstallStatus::Downgraded => {
+                packages.insert(key, record.version.clone());
+            }
+            InstallStatus::Removed => {
+                packages.remove(&key);
+            }
+            InstallStatus::Failed => {}
+        }
+    }
+    packages
+}
+
+/// What changed going from `from` to `to`, by box type then package
+pub fn diff(from: &PackageSet, to: &PackageSet) -> Vec<PackageChange> {
+    let keys: BTreeSet<&(String, String)> = from.keys().chain(to.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let before = from.get(key);
+            let after = to.get(key);
+            let kind = match (before, after) {
+                (None, Some(_)) => ChangeKind::Added,
+                (Some(_), None) => ChangeKind::Removed,
+                (Some(a), Some(b)) if a == b => return None,
+                (Some(Some(a)), Some(Some(b))) => match downgrade::compare_versions(a, b) {
+                    Ordering::Less => ChangeKind::Upgraded,
+                    Ordering::Greater => ChangeKind::Downgraded,
+                    Ordering::Equal => ChangeKind::Changed,
+                },
+                (Some(_), Some(_)) => ChangeKind::Changed,
+                (None, None) => return None,
+            };
+            Some(PackageChange {
+                box_type: key.0.clone(),
+                package: key.1.clone(),
+                kind,
+                from: before.cloned().flatten(),
+                to: after.cloned().flatten(),
+            })
+        })
+        .collect()
+}
+
+/// The time a history point refers to: `now`, a date, an RFC 3339 timestamp,
+/// or the id (or a unique start of the id) of one of `records`
+pub fn resolve_point(point: &str, records: &[database::InstallRecord]) -> Result<DateTime<Utc>> {
+    if point == "now" {
+        return Ok(Utc::now());
+    }
+    if let Ok(time) = audit::parse_time(point) {
+        return Ok(time);
+    }
+    let matches: Vec<_> = records.iter().filter(|r| r.id.starts_with(point)).collect();
+    match matches.as_slice() {
+        [record] => Ok(record.installed_at),
+        [] => Err(anyhow!(
+            "'{}' is neither a time (YYYY-MM-DD or RFC 3339) nor a history id",
+            point
+        )),
+        _ => Err(anyhow!(
+            "'{}' matches {} history ids; give more of the id",
+            point,
+            matches.len()
+        )),
+    }
+}
+
+/// Changes grouped by box type, for `omni history diff`
+pub fn print_diff(changes: &[PackageChange]) {
+    if changes.is_empty() {
+        println!("No package changes");
+        return;
+    }
+
+    let mut current_box = None;
+    for change in changes {
+        if current_box != Some(&change.box_type) {
+            if current_box.is_some() {
+                println!();
+            }
+            println!("[{}]", change.box_type);
+            current_box = Some(&change.box_type);
+        }
+        let version = |v: &Option<String>| v.clone().unwrap_or_else(|| "?".to_string());
+        match change.kind {
+            ChangeKind::Added => println!("  + {} {}", change.package, version(&change.to)),
+            ChangeKind::Removed => println!("  - {} {}", change.package, version(&change.from)),
+            ChangeKind::Upgraded | ChangeKind::Downgraded | ChangeKind::Changed => println!(
+                "  ~ {} {} -> {} ({:?})",
+                change.package,
+                version(&change.from),
+                version(&change.to),
+                change.kind
+            ),
+        }
+    }
+
+    let count = |kind| changes.iter().filter(|c| c.kind == kind).count();
+    println!(
+        "\n{} added, {} removed, {} upgraded, {} downgraded, {} changed",
+        count(ChangeKind::Added),
+        count(ChangeKind::Removed),
+        count(ChangeKind::Upgraded),
+        count(ChangeKind::Downgraded),
+        count(ChangeKind::Changed)
+    );
+}
+
+/// One operation that takes a package back to how it was at a history point
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollbackStep {
+    pub operation: OperationType,
+    pub box_type: String,
+    pub package: String,
+    /// Version to go back to, for version changes
+    pub version: Option<String>,
+}
+
+/// Operations that reverse everything `records` did after `at`: removals
+/// first, newest install first so dependents go before their dependencies,
+/// then version changes, then reinstalls in their original install order
+pub fn rollback_plan(records: &[database::InstallRecord], at: DateTime<Utc>) -> Vec<RollbackStep> {
+    let current = package_set_at(records, Utc::now().max(at));
+    let target = package_set_at(records, at);
+
+    // When each package last changed, to order the steps
+    let mut changed_at = HashMap::new();
+    for record in records {
+        changed_at.insert(
+            (record.box_type.clone(), record.package_name.clone()),
+            record.installed_at,
+        );
+    }
+    let mut target_changed_at = HashMap::new();
+    for record in records.iter().filter(|r| r.installed_at <= at) {
+        target_changed_at.insert(
+            (record.box_type.clone(), record.package_name.clone()),
+            record.installed_at,
+        );
+    }
+
+    let mut removals = Vec::new();
+    let mut restores = Vec::new();
+    let mut installs = Vec::new();
+    for change in diff(&current, &target) {
+        let key = (change.box_type.clone(), change.package.clone());
+        let step = |operation| RollbackStep {
+            operation,
+            box_type: change.box_type.clone(),
+            package: change.package.clone(),
+            version: change.to.clone(),
+        };
+        match change.kind {
+            ChangeKind::Removed => removals.push((
+                changed_at.get(&key).copied(),
+                step(OperationType::RemovePackage),
+            )),
+            // Reinstalled at the current version; the old one may be gone
+            // from the repositories
+            ChangeKind::Added => installs.push((
+                target_changed_at.get(&key).copied(),
+                RollbackStep {
+                    version: None,
+                    ..step(OperationType::InstallPackage)
+                },
+            )),
+            // Without a recorded version there is nothing to go back to
+            _ if change.to.is_none() => {}
+            _ => restores.push((
+                changed_at.get(&key).copied(),
+                step(OperationType::UpdatePackage),
+            )),
+        }
+    }
+    removals.sort_by(|a, b| b.0.cmp(&a.0));
+    restores.sort_by(|a, b| b.0.cmp(&a.0));
+    installs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    removals
+        .into_iter()
+        .chain(restores)
+        .chain(installs)
+        .map(|(_, step)| step)
+        .collect()
+}
+
+/// Formats of `omni history export` and `omni history import`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// An array of history records
+    Json,
+    /// One record per row, with a header row
+    Csv,
+}
+
+impl ExportFormat {
+    /// The format a file name's extension implies
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(ExportFormat::Json),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+const CSV_COLUMNS: &[&str] = &[
+    "id",
+    "installed_at",
+    "package_name",
+    "box_type",
+    "version",
+    "status",
+    "repository",
+    "mirror_url",
+    "channel",
+    "source_url",
+    "install_path",
+    "metadata",
+];
+
+/// `records` written as `format`
+pub fn export(records: &[database::InstallRecord], format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(records)? + "\n"),
+        ExportFormat::Csv => {
+            let mut out = CSV_COLUMNS.join(",") + "\n";
+            for record in records {
+                let fields = [
+                    record.id.as_str(),
+                    &record.installed_at.to_rfc3339(),
+                    &record.package_name,
+                    &record.box_type,
+                    record.version.as_deref().unwrap_or(""),
+                    record.status.as_str(),
+                    record.repository.as_deref().unwrap_or(""),
+                    record.mirror_url.as_deref().unwrap_or(""),
+                    record.channel.as_deref().unwrap_or(""),
+                    record.source_url.as_deref().unwrap_or(""),
+                    record.install_path.as_deref().unwrap_or(""),
+                    record.metadata.as_deref().unwrap_or(""),
+                ];
+                let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+                out.push_str(&row.join(","));
+                out.push('\n');
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Records read back from [`export`]'s output. CSV columns are matched by
+/// their header, so reordered or extra columns are fine.
+pub fn import(content: &str, format: ExportFormat) -> Result<Vec<database::InstallRecord>> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::from_str(content)?),
+        ExportFormat::Csv => {
+            let mut rows = csv_rows(content)?.into_iter();
+            let header = rows.next().unwrap_or_default();
+            let column = |name: &str| header.iter().position(|h| h.trim() == name);
+            let columns: HashMap<&str, usize> = CSV_COLUMNS
+                .iter()
+                .filter_map(|&name| column(name).map(|i| (name, i)))
+                .collect();
+            for required in ["id", "installed_at", "package_name", "box_type", "status"] {
+                if !columns.contains_key(required) {
+                    return Err(anyhow!("The CSV has no `{}` column", required));
+                }
+            }
+
+            rows.enumerate()
+                .filter(|(_, row)| row.iter().any(|f| !f.is_empty()))
+                .map(|(i, row)| {
+                    let field = |name: &str| {
+                        columns
+                            .get(name)
+                            .and_then(|&c| row.get(c))
+                            .filter(|f| !f.is_empty())
+                            .cloned()
+                    };
+                    let required = |name: &str| {
+                        field(name).ok_or_else(|| anyhow!("Row {}: `{}` is empty", i + 2, name))
+                    };
+                    let installed_at = DateTime::parse_from_rfc3339(&required("installed_at")?)
+                        .map_err(|e| anyhow!("Row {}: installed_at: {}", i + 2, e))?
+                        .with_timezone(&Utc);
+                    let status = required("status")?;
+                    Ok(database::InstallRecord {
+                        id: required("id")?,
+                        package_name: required("package_name")?,
+                        box_type: required("box_type")?,
+                        version: field("version"),
+                        source_url: field("source_url"),
+                        install_path: field("install_path"),
+                        installed_at,
+                        status: InstallStatus::parse(&status).ok_or_else(|| {
+                            anyhow!("Row {}: `{}` is not a status", i + 2, status)
+                        })?,
+                        repository: field("repository"),
+                        mirror_url: field("mirror_url"),
+                        channel: field("channel"),
+                        metadata: field("metadata"),
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+/// A CSV field, quoted when it holds a comma, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Rows of RFC 4180 CSV; quoted fields may span lines
+fn csv_rows(content: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("The CSV ends inside a quoted field"));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn record(
+        package: &str,
+        version: Option<&str>,
+        status: InstallStatus,
+        day: u32,
+    ) -> database::InstallRecord {
+        database::InstallRecord {
+            id: format!("{}-{}", package, day),
+            package_name: package.to_string(),
+            box_type: "apt".to_string(),
+            version: version.map(str::to_string),
+            source_url: None,
+            install_path: None,
+            installed_at: Utc.with_ymd_and_hms(2026, 1, day, 12, 0, 0).unwrap(),
+            status,
+            repository: None,
+            mirror_url: None,
+            channel: None,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_between_points() {
+        let records = vec![
+            record("curl", Some("7.0"), InstallStatus::Success, 1),
+            record("vim", Some("9.0"), InstallStatus::Success, 1),
+            record("git", Some("2.40"), InstallStatus::Success, 2),
+            record("curl", Some("8.0"), InstallStatus::Updated, 3),
+            record("vim", None, InstallStatus::Removed, 3),
+            record("htop", None, InstallStatus::Failed, 3),
+        ];
+        let day = |d| Utc.with_ymd_and_hms(2026, 1, d, 23, 0, 0).unwrap();
+
+        let from = package_set_at(&records, day(1));
+        let to = package_set_at(&records, day(3));
+        assert_eq!(from.len(), 2);
+
+        let changes: Vec<_> = diff(&from, &to)
+            .into_iter()
+            .map(|c| (c.package, c.kind))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("curl".to_string(), ChangeKind::Upgraded),
+                ("git".to_string(), ChangeKind::Added),
+                ("vim".to_string(), ChangeKind::Removed),
+            ]
+        );
+
+        let back: Vec<_> = diff(&to, &from).into_iter().map(|c| c.kind).collect();
+        assert_eq!(
+            back,
+            vec![
+                ChangeKind::Downgraded,
+                ChangeKind::Removed,
+                ChangeKind::Added
+            ]
+        );
+        assert!(diff(&to, &to).is_empty());
+
+        assert_eq!(
+            resolve_point("git-2", &records).unwrap(),
+            records[2].installed_at
+        );
+        assert!(resolve_point("curl", &records).is_err());
+        assert!(resolve_point("2026-01-02", &records).is_ok());
+    }
+
+    #[test]
+    fn test_rollback_plan() {
+        let records = vec![
+            record("curl", Some("7.0"), InstallStatus::Success, 1),
+            record("vim", Some("9.0"), InstallStatus::Success, 1),
+            record("libgit", Some("1.0"), InstallStatus::Success, 2),
+            record("git", Some("2.40"), InstallStatus::Success, 3),
+            record("curl", Some("8.0"), InstallStatus::Updated, 3),
+            record("vim", None, InstallStatus::Removed, 4),
+        ];
+        let at = Utc.with_ymd_and_hms(2026, 1, 1, 23, 0, 0).unwrap();
+
+        let plan: Vec<_> = rollback_plan(&records, at)
+            .into_iter()
+            .map(|s| (s.operation, s.package, s.version))
+            .collect();
+        assert_eq!(
+            plan,
+            vec![
+                (OperationType::RemovePackage, "git".to_string(), None),
+                (OperationType::RemovePackage, "libgit".to_string(), None),
+                (
+                    OperationType::UpdatePackage,
+                    "curl".to_string(),
+                    Some("7.0".to_string())
+                ),
+                (OperationType::InstallPackage, "vim".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let mut records = vec![
+            record("curl", Some("8.0"), InstallStatus::Success, 1),
+            record("vim", None, InstallStatus::Removed, 2),
+        ];
+        records[0].metadata = Some("Installed via manifest, \"dev\" profile\nline two".to_string());
+        records[1].repository = Some("bookworm".to_string());
+
+        for format in [ExportFormat::Json, ExportFormat::Csv] {
+            let exported = export(&records, format).unwrap();
+            let imported = import(&exported, format).unwrap();
+            assert_eq!(imported.len(), 2);
+            assert_eq!(imported[0].metadata, records[0].metadata);
+            assert_eq!(imported[0].installed_at, records[0].installed_at);
+            assert_eq!(imported[1].repository.as_deref(), Some("bookworm"));
+            assert_eq!(imported[1].version, None);
+            assert!(matches!(imported[1].status, InstallStatus::Removed));
+        }
+
+        assert!(import("id,package_name\n1,curl\n", ExportFormat::Csv).is_err());
+        assert_eq!(
+            ExportFormat::from_path(Path::new("history.CSV")),
+            Some(ExportFormat::Csv)
+        );
+    }
+}
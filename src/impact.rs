@@ -0,0 +1,235 @@
+//! Classifies an update as safe, minor, major or breaking from the version
+//! delta and what the distribution says about it: apt phased updates and
+//! Fedora bodhi karma.
+
+use crate::config::Impact;
+use anyhow::Result;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::process::Command;
+use std::time::Duration;
+
+const BODHI_UPDATES: &str = "https://bodhi.fedoraproject.org/updates/";
+const BODHI_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Distribution metadata about an update
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Hints {
+    /// Share of machines apt offers the update to while it is phased in
+    pub phased_percentage: Option<u8>,
+    /// Sum of the karma testers gave the update in bodhi
+    pub karma: Option<i32>,
+}
+
+/// Classify the update from `current` to `available`
+pub fn classify(current: Option<&str>, available: Option<&str>, hints: Hints) -> Impact {
+    let mut impact = match (current, available) {
+        (Some(current), Some(available)) => version_delta(current, available),
+        _ => None,
+    }
+    .unwrap_or(Impact::Major);
+
+    if matches!(hints.phased_percentage, Some(p) if p < 100) {
+        impact = impact.max(Impact::Minor);
+    }
+    if matches!(hints.karma, Some(k) if k < 0) {
+        impact = Impact::Breaking;
+    }
+    impact
+}
+
+/// A distribution version split into epoch and numeric upstream release
+#[derive(Debug, PartialEq, Eq)]
+struct Release {
+    epoch: u64,
+    numbers: Vec<u64>,
+}
+
+/// Parse `[epoch:]upstream[-revision]`, the shape deb, rpm and pacman
+/// versions share. The packaging revision is dropped and the upstream
+/// version is read up to its first component without a leading number.
+fn parse_release(version: &str) -> Option<Release> {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) if !epoch.is_empty() && epoch.chars().all(|c| c.is_ascii_digit()) => {
+            (epoch.parse().ok()?, rest)
+        }
+        _ => (0, version),
+    };
+    let upstream = rest.rsplit_once('-').map_or(rest, |(upstream, _)| upstream);
+
+    let numbers: Vec<u64> = upstream
+        .split('.')
+        .map_while(|part| {
+            let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse().ok()
+        })
+        .collect();
+    (!numbers.is_empty()).then_some(Release { epoch, numbers })
+}
+
+/// Impact judged from the versions alone; `None` when they cannot be compared
+pub fn version_delta(current: &str, available: &str) -> Option<Impact> {
+    let current = parse_release(current)?;
+    let available = parse_release(available)?;
+    if current.epoch != available.epoch {
+        return Some(Impact::Breaking);
+    }
+
+    let changed = (0..current.numbers.len().max(available.numbers.len()))
+        .find(|&i| current.numbers.get(i).unwrap_or(&0) != available.numbers.get(i).unwrap_or(&0));
+    let Some(index) = changed else {
+        // Only the packaging revision moved
+        return Some(Impact::Safe);
+    };
+    if available.numbers.get(index).unwrap_or(&0) < current.numbers.get(index).unwrap_or(&0) {
+        // Going backwards is no routine update
+        return Some(Impact::Major);
+    }
+
+    // Before 1.0, semver treats minor releases as major ones
+    let major_index = usize::from(current.numbers.first() == Some(&0));
+    Some(match index.cmp(&major_index) {
+        Ordering::Less | Ordering::Equal => Impact::Major,
+        Ordering::Greater if index == major_index + 1 => Impact::Minor,
+        Ordering::Greater => Impact::Safe,
+    })
+}
+
+/// Metadata apt and bodhi publish about the update of `package` to `version`
+pub async fn hints(box_type: &str, package: &str, version: Option<&str>) -> Hints {
+    let Some(version) = version else {
+        return Hints::default();
+    };
+    match box_type {
+        "apt" => Hints {
+            phased_percentage: Command::new("apt-cache")
+                .arg("show")
+                .arg(format!("{}={}", package, version))
+                .output()
+                .ok()
+                .and_then(|o| parse_phased_percentage(&String::from_utf8_lossy(&o.stdout))),
+            karma: None,
+        },
+        "dnf" => Hints {
+            phased_percentage: None,
+            karma: bodhi_karma(package, version).await.unwrap_or(None),
+        },
+        _ => Hints::default(),
+    }
+}
+
+/// `Phased-Update-Percentage` in `apt-cache show` output
+pub fn parse_phased_percentage(output: &str) -> Option<u8> {
+    output.lines().find_map(|line| {
+        line.strip_prefix("Phased-Update-Percentage:")
+            .and_then(|value| value.trim().parse().ok())
+    })
+}
+
+#[derive(Deserialize)]
+struct BodhiUpdates {
+    updates: Vec<BodhiUpdate>,
+}
+
+#[derive(Deserialize)]
+struct BodhiUpdate {
+    karma: i32,
+}
+
+/// Karma of the bodhi update shipping `package` at `version`. Only Fedora
+/// builds (`.fcNN` releases) are in bodhi.
+async fn bodhi_karma(package: &str, version: &str) -> Result<Option<i32>> {
+    let version = version.split_once(':').map_or(version, |(_, v)| v);
+    if !version.contains(".fc") {
+        return Ok(None);
+    }
+    let build = format!("{}-{}", package, version);
+
+    let client = reqwest::Client::builder()
+        .user_agent("omni-package-manager/0.2.0")
+        .timeout(BODHI_TIMEOUT)
+        .build()?;
+    let body = client
+        .get(BODHI_UPDATES)
+        .query(&[("builds", build.as_str())])
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(parse_bodhi_karma(&body))
+}
+
+/// Karma of the first update in a bodhi `/updates/` response
+pub fn parse_bodhi_karma(body: &str) -> Option<i32> {
+    serde_json::from_str::<BodhiUpdates>(body)
+        .ok()?
+        .updates
+        .first()
+        .map(|update| update.karma)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_delta() {
+        let delta = version_delta;
+        assert_eq!(
+            delta("3.0.13-0ubuntu3.1", "3.0.13-0ubuntu3.4"),
+            Some(Impact::Safe)
+        );
+        assert_eq!(delta("2.43.0-1", "2.43.2-1"), Some(Impact::Safe));
+        assert_eq!(delta("128.0-1", "128.0.3-1"), Some(Impact::Safe));
+        assert_eq!(delta("2.43.0", "2.44.0"), Some(Impact::Minor));
+        assert_eq!(
+            delta("1:3.2.2-3.fc40", "1:4.0.0-1.fc40"),
+            Some(Impact::Major)
+        );
+        assert_eq!(
+            delta("3.2.2-3.fc40", "1:3.2.3-1.fc40"),
+            Some(Impact::Breaking)
+        );
+        assert_eq!(delta("2.44.0", "2.43.0"), Some(Impact::Major));
+        // 0.x releases
+        assert_eq!(delta("0.9.1", "0.10.0"), Some(Impact::Major));
+        assert_eq!(delta("0.9.1", "0.9.2"), Some(Impact::Minor));
+        assert_eq!(delta("latest", "1.0"), None);
+    }
+
+    #[test]
+    fn test_classify_with_hints() {
+        let phased = Hints {
+            phased_percentage: Some(30),
+            karma: None,
+        };
+        assert_eq!(
+            classify(Some("1.2.3"), Some("1.2.4"), Hints::default()),
+            Impact::Safe
+        );
+        assert_eq!(
+            classify(Some("1.2.3"), Some("1.2.4"), phased),
+            Impact::Minor
+        );
+        assert_eq!(classify(None, Some("1.2.4"), phased), Impact::Major);
+
+        let rejected = Hints {
+            phased_percentage: None,
+            karma: Some(-2),
+        };
+        assert_eq!(
+            classify(Some("1.2.3"), Some("1.2.4"), rejected),
+            Impact::Breaking
+        );
+
+        let show = "Package: firefox\nVersion: 129.0\nPhased-Update-Percentage: 40\n";
+        assert_eq!(parse_phased_percentage(show), Some(40));
+        assert_eq!(parse_phased_percentage("Package: firefox\n"), None);
+        assert_eq!(
+            parse_bodhi_karma(r#"{"updates": [{"karma": 3, "status": "stable"}], "total": 1}"#),
+            Some(3)
+        );
+        assert_eq!(parse_bodhi_karma(r#"{"updates": []}"#), None);
+    }
+}
@@ -0,0 +1,200 @@
+//! Search-as-you-type for the GUI. Keystrokes are debounced and only the
+//! newest query runs, against the local search index on a worker thread;
+//! queries a newer one supersedes are dropped, and so are their late results.
+
+use crate::mock;
+use crate::search::{SearchEngine, SearchResult};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Quiet time after the last keystroke before searching
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+/// Results shown while typing
+const LIMIT: usize = 50;
+
+/// Text waiting for the typing to pause
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    pending: Option<(String, Instant)>,
+}
+
+impl Debouncer {
+    pub fn edit(&mut self, text: &str, now: Instant) {
+        self.pending = Some((text.to_string(), now));
+    }
+
+    /// The text, once `DEBOUNCE` has passed without another edit
+    pub fn take_due(&mut self, now: Instant) -> Option<String> {
+        match &self.pending {
+            Some((_, edited)) if now.duration_since(*edited) >= DEBOUNCE => {
+                self.pending.take().map(|(text, _)| text)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pending = None;
+    }
+}
+
+pub struct IncrementalSearch {
+    debouncer: Debouncer,
+    /// Bumped for every query sent; results for older ones are stale
+    generation: Arc<AtomicU64>,
+    queries: Sender<(u64, String)>,
+    results: Receiver<(u64, Vec<SearchResult>)>,
+}
+
+impl IncrementalSearch {
+    /// Start the worker; with `mock` it searches the in-memory backend
+    pub fn spawn(mock: bool) -> Self {
+        let (queries, query_rx) = mpsc::channel();
+        let (result_tx, results) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let current = Arc::clone(&generation);
+        std::thread::spawn(move || worker(query_rx, result_tx, current, mock));
+
+        Self {
+            debouncer: Debouncer::default(),
+            generation,
+            queries,
+            results,
+        }
+    }
+
+    /// Note a change to the search text
+    pub fn edited(&mut self, text: &str) {
+        self.debouncer.edit(text, Instant::now());
+    }
+
+    /// Drop the pending and running queries, e.g. before a full search
+    pub fn cancel(&mut self) {
+        self.debouncer.clear();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Send the query once typing pauses and hand back the newest results;
+    /// call once per frame
+    pub fn poll(&mut self) -> Option<Vec<SearchResult>> {
+        if let Some(text) = self.debouncer.take_due(Instant::now()) {
+            let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+            if text.trim().is_empty() {
+                return Some(Vec::new());
+            }
+            let _ = self.queries.send((generation, text));
+        }
+
+        let current = self.generation.load(Ordering::SeqCst);
+        let mut latest = None;
+        while let Ok((generation, results)) = self.results.try_recv() {
+            if generation == current {
+                latest = Some(results);
+            }
+        }
+        latest
+    }
+}
+
+fn worker(
+    queries: Receiver<(u64, String)>,
+    results: Sender<(u64, Vec<SearchResult>)>,
+    current: Arc<AtomicU64>,
+    mock: bool,
+) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            warn!("Search-as-you-type is unavailable: {}", e);
+            return;
+        }
+    };
+    // No engine means the mock backend
+    let engine = if mock {
+        None
+    } else {
+        match runtime.block_on(SearchEngine::new()) {
+            Ok(engine) => Some(engine),
+            Err(e) => {
+                warn!("Search-as-you-type is unavailable: {}", e);
+                return;
+            }
+        }
+    };
+
+    // Ends once the GUI drops its side of the channel
+    while let Ok(mut query) = queries.recv() {
+        // Skip straight to the newest query waiting
+        while let Ok(newer) = queries.try_recv() {
+            query = newer;
+        }
+        let (generation, text) = query;
+        if generation != current.load(Ordering::SeqCst) {
+            continue;
+        }
+
+        let found = match &engine {
+            Some(engine) => runtime
+                .block_on(engine.search_local(&text, LIMIT))
+                .unwrap_or_else(|e| {
+                    warn!("Local search for '{}' failed: {}", text, e);
+                    Vec::new()
+                }),
+            None => mock::backend()
+                .search(&text)
+                .into_iter()
+                .take(LIMIT)
+                .collect(),
+        };
+        if results.send((generation, found)).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::default();
+        debouncer.edit("fi", start);
+        debouncer.edit("fire", start + Duration::from_millis(100));
+        assert_eq!(debouncer.take_due(start + Duration::from_millis(300)), None);
+        assert_eq!(
+            debouncer.take_due(start + Duration::from_millis(350)),
+            Some("fire".to_string())
+        );
+        assert_eq!(debouncer.take_due(start + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_only_newest_query_answers() {
+        let mut search = IncrementalSearch::spawn(true);
+        search.edited("git");
+        search.edited("vim");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let results = loop {
+            if let Some(results) = search.poll() {
+                break results;
+            }
+            assert!(Instant::now() < deadline, "no results from the worker");
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        assert!(results.iter().any(|r| r.name == "vim"));
+        assert!(!results.iter().any(|r| r.name == "git"));
+
+        search.edited("");
+        std::thread::sleep(DEBOUNCE);
+        assert_eq!(search.poll().map(|r| r.len()), Some(0));
+    }
+}
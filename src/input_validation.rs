@@ -1,5 +1,9 @@
+use crate::advanced_resolver::Comparison;
+use crate::distro;
+use crate::downgrade::compare_versions;
 use anyhow::{anyhow, Result};
 use regex::Regex;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use url::Url;
 
@@ -37,6 +41,36 @@ impl InputValidator {
         Ok(())
     }
 
+    /// Split `nginx>=1.24,<2` into the package name and the versions it may be
+    /// installed at, and `libc6:i386` into the name and the architecture to
+    /// install it for; a plain name has neither
+    pub fn parse_package_spec(spec: &str) -> Result<PackageSpec> {
+        let spec = spec.trim();
+        let (name, constraint) = match spec.find(['<', '>', '=', '!']) {
+            Some(at) => (
+                spec[..at].trim(),
+                Some(VersionConstraint::parse(&spec[at..])?),
+            ),
+            None => (spec, None),
+        };
+        let (name, arch) = match name.split_once(':') {
+            Some((name, arch)) => {
+                if !distro::is_architecture(arch) {
+                    return Err(anyhow!("Unknown architecture '{}' in '{}'", arch, spec));
+                }
+                (name, Some(arch.to_string()))
+            }
+            None => (name, None),
+        };
+        Self::validate_package_name(name)?;
+
+        Ok(PackageSpec {
+            name: name.to_string(),
+            arch,
+            constraint,
+        })
+    }
+
     /// Validate URLs to prevent SSRF and other attacks
     pub fn validate_url(url_str: &str) -> Result<Url> {
         if url_str.is_empty() {
@@ -235,6 +269,7 @@ impl InputValidator {
             "snap",
             "flatpak",
             "appimage",
+            "source",
             "winget",
             "chocolatey",
             "scoop",
@@ -249,6 +284,30 @@ impl InputValidator {
         Ok(())
     }
 
+    /// Validate hostnames and IP addresses of remote targets
+    pub fn validate_hostname(host: &str) -> Result<()> {
+        if host.is_empty() {
+            return Err(anyhow!("Hostname cannot be empty"));
+        }
+
+        if host.len() > 253 {
+            return Err(anyhow!("Hostname too long (max 253 characters)"));
+        }
+
+        // Hostnames, IPv4 and IPv6 addresses only
+        let valid_chars = Regex::new(r"^[a-zA-Z0-9.:-]+$").unwrap();
+        if !valid_chars.is_match(host) {
+            return Err(anyhow!("Hostname contains invalid characters"));
+        }
+
+        // Prevent option injection into ssh
+        if host.starts_with('-') {
+            return Err(anyhow!("Hostname cannot start with '-'"));
+        }
+
+        Ok(())
+    }
+
     /// Validate input to prevent shell injection attacks
     pub fn validate_shell_safe(input: &str) -> Result<()> {
         if input.is_empty() {
@@ -367,6 +426,84 @@ impl InputValidator {
     }
 }
 
+/// A package to install, optionally limited to some of its versions or built
+/// for another architecture than the system's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    pub name: String,
+    pub arch: Option<String>,
+    pub constraint: Option<VersionConstraint>,
+}
+
+/// Comparisons a version has to pass all of, written `>=1.24,<2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionConstraint {
+    pub bounds: Vec<(Comparison, String)>,
+}
+
+impl VersionConstraint {
+    pub fn parse(text: &str) -> Result<Self> {
+        let operators = [
+            (">=", Comparison::GreaterOrEqual),
+            ("<=", Comparison::LessOrEqual),
+            ("==", Comparison::Equal),
+            ("=", Comparison::Equal),
+            (">", Comparison::Greater),
+            ("<", Comparison::Less),
+        ];
+        // Debian epochs and tildes are allowed on top of the usual characters
+        let valid_version = Regex::new(r"^[a-zA-Z0-9._+:~-]{1,64}$").unwrap();
+
+        let mut bounds = Vec::new();
+        for bound in text.split(',').map(str::trim) {
+            let (comparison, version) = operators
+                .iter()
+                .find_map(|(symbol, comparison)| {
+                    bound
+                        .strip_prefix(symbol)
+                        .map(|version| (*comparison, version.trim()))
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Version bound '{}' must start with >=, <=, =, > or <",
+                        bound
+                    )
+                })?;
+            if !valid_version.is_match(version) {
+                return Err(anyhow!("Invalid version '{}' in '{}'", version, text));
+            }
+            bounds.push((comparison, version.to_string()));
+        }
+
+        Ok(Self { bounds })
+    }
+
+    pub fn allows(&self, version: &str) -> bool {
+        self.bounds
+            .iter()
+            .all(|(comparison, bound)| comparison.holds(compare_versions(version, bound)))
+    }
+
+    /// The newest of `versions` the constraint allows
+    pub fn newest<'a>(&self, versions: &'a [String]) -> Option<&'a String> {
+        versions
+            .iter()
+            .filter(|version| self.allows(version))
+            .max_by(|a, b| compare_versions(a, b))
+    }
+}
+
+impl fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bounds: Vec<String> = self
+            .bounds
+            .iter()
+            .map(|(comparison, version)| format!("{}{}", comparison.symbol(), version))
+            .collect();
+        write!(f, "{}", bounds.join(","))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -404,20 +541,49 @@ mod tests {
     #[test]
     fn test_command_args_validation() {
         // Valid args
-        assert!(InputValidator::validate_command_args(&[
-            "install".to_string(),
-            "firefox".to_string()
-        ])
-        .is_ok());
-        assert!(
-            InputValidator::validate_command_args(&["-y".to_string(), "--force".to_string()])
-                .is_ok()
-        );
+        assert!(InputValidator::validate_command_args(&["install", "firefox"]).is_ok());
+        assert!(InputValidator::validate_command_args(&["-y", "--force"]).is_ok());
 
         // Invalid args
-        assert!(InputValidator::validate_command_args(&["test; rm -rf /".to_string()]).is_err());
-        assert!(InputValidator::validate_command_args(&["test && malicious".to_string()]).is_err());
-        assert!(InputValidator::validate_command_args(&["test`whoami`".to_string()]).is_err());
+        assert!(InputValidator::validate_command_args(&["test; rm -rf /"]).is_err());
+        assert!(InputValidator::validate_command_args(&["test && malicious"]).is_err());
+        assert!(InputValidator::validate_command_args(&["test`whoami`"]).is_err());
+    }
+
+    #[test]
+    fn test_package_spec_parsing() {
+        let spec = InputValidator::parse_package_spec("nginx>=1.24,<2").unwrap();
+        assert_eq!(spec.name, "nginx");
+        let constraint = spec.constraint.unwrap();
+        assert_eq!(constraint.to_string(), ">=1.24,<2");
+        assert!(constraint.allows("1.24.0-2ubuntu7"));
+        assert!(constraint.allows("1.26.1"));
+        assert!(!constraint.allows("1.22.1"));
+        assert!(!constraint.allows("2.0"));
+
+        let available = ["2.1".to_string(), "1.25.3".to_string(), "1.24.0".to_string()];
+        assert_eq!(constraint.newest(&available).map(String::as_str), Some("1.25.3"));
+
+        let spec = InputValidator::parse_package_spec("vim = 2:9.1.0016-1").unwrap();
+        assert_eq!(spec.name, "vim");
+        assert!(spec.constraint.unwrap().allows("2:9.1.0016-1"));
+        assert_eq!(InputValidator::parse_package_spec("git").unwrap().constraint, None);
+
+        assert!(InputValidator::parse_package_spec(">=1.0").is_err());
+        assert!(InputValidator::parse_package_spec("nginx>=1.24,").is_err());
+        assert!(InputValidator::parse_package_spec("nginx!=1.24").is_err());
+        assert!(InputValidator::parse_package_spec("nginx>=1;id").is_err());
+
+        let spec = InputValidator::parse_package_spec("libc6:i386").unwrap();
+        assert_eq!(spec.name, "libc6");
+        assert_eq!(spec.arch.as_deref(), Some("i386"));
+        let spec = InputValidator::parse_package_spec("zlib:arm64>=1.3").unwrap();
+        assert_eq!(spec.name, "zlib");
+        assert_eq!(spec.arch.as_deref(), Some("arm64"));
+        assert_eq!(spec.constraint.unwrap().to_string(), ">=1.3");
+        assert_eq!(InputValidator::parse_package_spec("git").unwrap().arch, None);
+        assert!(InputValidator::parse_package_spec("libc6:sparc").is_err());
+        assert!(InputValidator::parse_package_spec(":i386").is_err());
     }
 
     #[test]
@@ -426,6 +592,7 @@ mod tests {
         assert!(InputValidator::validate_box_type("apt").is_ok());
         assert!(InputValidator::validate_box_type("dnf").is_ok());
         assert!(InputValidator::validate_box_type("snap").is_ok());
+        assert!(InputValidator::validate_box_type("source").is_ok());
 
         // Invalid box types
         assert!(InputValidator::validate_box_type("").is_err());
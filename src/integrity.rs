@@ -0,0 +1,333 @@
+use crate::distro;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use tracing::{debug, info};
+
+/// How an installed file differs from what its package shipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileIssueKind {
+    Modified,
+    Missing,
+    Permissions,
+    Ownership,
+}
+
+impl FileIssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileIssueKind::Modified => "modified",
+            FileIssueKind::Missing => "missing",
+            FileIssueKind::Permissions => "permissions changed",
+            FileIssueKind::Ownership => "ownership changed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileIssue {
+    pub path: String,
+    pub kind: FileIssueKind,
+    /// Owning package, when the backend reports it
+    pub package: Option<String>,
+    /// Configuration files are expected to be edited locally
+    pub config: bool,
+}
+
+/// Result of one backend's integrity check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    pub box_type: String,
+    /// Package checked, or `None` when every installed package was checked
+    pub package: Option<String>,
+    /// Native command that produced the report
+    pub tool: String,
+    pub issues: Vec<FileIssue>,
+}
+
+impl IntegrityReport {
+    /// Issues outside configuration files
+    pub fn problems(&self) -> impl Iterator<Item = &FileIssue> {
+        self.issues.iter().filter(|issue| !issue.config)
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.problems().next().is_none()
+    }
+}
+
+/// Verify installed files for `package`, or for every package of every available backend
+pub fn check_installed(package: Option<&str>) -> Result<Vec<IntegrityReport>> {
+    let mut reports = Vec::new();
+
+    for (box_type, command) in [("apt", "dpkg"), ("dnf", "rpm"), ("pacman", "pacman")] {
+        if !distro::command_exists(command) {
+            continue;
+        }
+        if let Some(package) = package {
+            if !is_installed(box_type, package) {
+                debug!("{} is not installed via {}", package, box_type);
+                continue;
+            }
+        }
+        reports.push(check_backend(box_type, package)?);
+    }
+
+    if reports.is_empty() {
+        return Err(match package {
+            Some(package) => anyhow!("Package {} is not installed", package),
+            None => anyhow!("No supported package manager found for integrity checks"),
+        });
+    }
+
+    Ok(reports)
+}
+
+fn is_installed(box_type: &str, package: &str) -> bool {
+    let (command, args): (&str, Vec<&str>) = match box_type {
+        "apt" => ("dpkg", vec!["-s", package]),
+        "dnf" => ("rpm", vec!["-q", package]),
+        "pacman" => ("pacman", vec!["-Q", package]),
+        _ => return false,
+    };
+
+    Command::new(command)
+        .args(&args)
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+/// Run the backend's own verifier. These tools exit non-zero when they find
+/// differences, so the exit code is not treated as an error.
+pub fn check_backend(box_type: &str, package: Option<&str>) -> Result<IntegrityReport> {
+    let (tool, args, parse): (&str, Vec<&str>, fn(&str) -> Vec<FileIssue>) = match box_type {
+        // debsums checks every shipped md5sum; dpkg --verify is the fallback
+        "apt" if distro::command_exists("debsums") => ("debsums", vec!["-s"], parse_debsums),
+        "apt" => ("dpkg", vec!["--verify"], parse_verify_flags),
+        "dnf" => (
+            "rpm",
+            if package.is_some() {
+                vec!["-V"]
+            } else {
+                vec!["-Va"]
+            },
+            parse_verify_flags,
+        ),
+        "pacman" => ("pacman", vec!["-Qkk"], parse_pacman_check),
+        other => {
+            return Err(anyhow!(
+                "Integrity checks are not supported for box type '{}'",
+                other
+            ))
+        }
+    };
+
+    let mut command = Command::new(tool);
+    command.args(&args);
+    if let Some(package) = package {
+        command.arg(package);
+    }
+
+    info!("Verifying installed files with {} {}", tool, args.join(" "));
+    let output = command
+        .output()
+        .map_err(|e| anyhow!("Failed to run {}: {}", tool, e))?;
+
+    // debsums and pacman report problems on stderr
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut issues = parse(&text);
+    for issue in issues.iter_mut().filter(|i| i.package.is_none()) {
+        issue.package = package.map(|p| p.to_string());
+    }
+
+    Ok(IntegrityReport {
+        box_type: box_type.to_string(),
+        package: package.map(|p| p.to_string()),
+        tool: format!("{} {}", tool, args.join(" ")),
+        issues,
+    })
+}
+
+/// `debsums -s`: `debsums: changed file /usr/bin/x (from pkg package)`
+pub fn parse_debsums(output: &str) -> Vec<FileIssue> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let rest = line.strip_prefix("debsums: ")?;
+            let (kind, rest) = if let Some(rest) = rest.strip_prefix("changed file ") {
+                (FileIssueKind::Modified, rest)
+            } else if let Some(rest) = rest.strip_prefix("missing file ") {
+                (FileIssueKind::Missing, rest)
+            } else {
+                return None;
+            };
+
+            let (path, package) = match rest.rsplit_once(" (from ") {
+                Some((path, owner)) => {
+                    (path, owner.strip_suffix(" package)").map(|p| p.to_string()))
+                }
+                None => (rest, None),
+            };
+
+            Some(FileIssue {
+                path: path.to_string(),
+                kind,
+                package,
+                config: false,
+            })
+        })
+        .collect()
+}
+
+/// `rpm -V` / `dpkg --verify`: an attribute string such as `S.5....T.` (or
+/// `missing`), an optional file-type marker (`c` for config) and the path.
+/// Files whose only difference is their modification time are ignored.
+pub fn parse_verify_flags(output: &str) -> Vec<FileIssue> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let flags = parts.next()?;
+            let mut path = parts.next()?;
+            let mut config = false;
+            if path.len() == 1 && !path.starts_with('/') {
+                config = path == "c";
+                path = parts.next()?;
+            }
+            if !path.starts_with('/') {
+                return None;
+            }
+
+            let kind = if flags == "missing" {
+                FileIssueKind::Missing
+            } else if flags.len() < 8 {
+                return None;
+            } else if flags.contains(['S', '5', 'L']) {
+                FileIssueKind::Modified
+            } else if flags.contains('M') {
+                FileIssueKind::Permissions
+            } else if flags.contains(['U', 'G']) {
+                FileIssueKind::Ownership
+            } else {
+                return None;
+            };
+
+            Some(FileIssue {
+                path: path.to_string(),
+                kind,
+                package: None,
+                config,
+            })
+        })
+        .collect()
+}
+
+/// `pacman -Qkk`: `warning: pkg: /path (Size mismatch)`, with `backup file:`
+/// instead of `warning:` for files listed in the package's backup array
+pub fn parse_pacman_check(output: &str) -> Vec<FileIssue> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (config, rest) = if let Some(rest) = line.strip_prefix("warning: ") {
+                (false, rest)
+            } else if let Some(rest) = line.strip_prefix("backup file: ") {
+                (true, rest)
+            } else {
+                return None;
+            };
+
+            let (package, rest) = rest.split_once(": ")?;
+            let (path, reason) = rest.rsplit_once(" (")?;
+            let reason = reason.trim_end_matches(')');
+
+            let kind = if reason.contains("No such file") {
+                FileIssueKind::Missing
+            } else if reason.contains("checksum mismatch")
+                || reason.contains("Size mismatch")
+                || reason.contains("Symlink path mismatch")
+            {
+                FileIssueKind::Modified
+            } else if reason.contains("Permissions mismatch") {
+                FileIssueKind::Permissions
+            } else if reason.contains("UID mismatch") || reason.contains("GID mismatch") {
+                FileIssueKind::Ownership
+            } else {
+                // Modification time changes and unreadable files are not content changes
+                return None;
+            };
+
+            Some(FileIssue {
+                path: path.to_string(),
+                kind,
+                package: Some(package.to_string()),
+                config,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_debsums() {
+        let output = "debsums: changed file /usr/bin/curl (from curl package)
+debsums: missing file /usr/share/doc/curl/README (from curl package)
+";
+        let issues = parse_debsums(output);
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].path, "/usr/bin/curl");
+        assert_eq!(issues[0].kind, FileIssueKind::Modified);
+        assert_eq!(issues[1].kind, FileIssueKind::Missing);
+        assert_eq!(issues[1].package.as_deref(), Some("curl"));
+    }
+
+    #[test]
+    fn test_parse_verify_flags() {
+        let output = "S.5....T.  c /etc/ssh/sshd_config
+missing     /usr/lib/libfoo.so.1
+.M.......    /usr/bin/sudo
+.......T.    /usr/share/man/man1/ls.1.gz
+??5??????   /usr/bin/dpkg-thing
+";
+        let issues = parse_verify_flags(output);
+        assert_eq!(issues.len(), 4);
+        assert!(issues[0].config);
+        assert_eq!(issues[0].kind, FileIssueKind::Modified);
+        assert_eq!(issues[1].kind, FileIssueKind::Missing);
+        assert_eq!(issues[2].kind, FileIssueKind::Permissions);
+        assert_eq!(issues[3].path, "/usr/bin/dpkg-thing");
+    }
+
+    #[test]
+    fn test_parse_pacman_check() {
+        let output = "backup file: pacman: /etc/pacman.conf (Modification time mismatch)
+backup file: pacman: /etc/pacman.conf (Size mismatch)
+warning: bash: /usr/bin/bash (SHA256 checksum mismatch)
+warning: sudo: /etc/sudoers.d (Permission denied)
+warning: curl: /usr/share/man/man1/curl.1.gz (No such file or directory)
+bash: 150 total files, 1 altered file
+";
+        let issues = parse_pacman_check(output);
+        assert_eq!(issues.len(), 3);
+        assert!(issues[0].config);
+        assert_eq!(issues[1].package.as_deref(), Some("bash"));
+        assert_eq!(issues[2].kind, FileIssueKind::Missing);
+
+        let report = IntegrityReport {
+            box_type: "pacman".to_string(),
+            package: None,
+            tool: "pacman -Qkk".to_string(),
+            issues,
+        };
+        assert_eq!(report.problems().count(), 2);
+        assert!(!report.is_clean());
+    }
+}
@@ -1,6 +1,8 @@
-use crate::resolver::{ResolutionPlan, ResolvedPackage};
+use crate::licenses::LicenseRequirement;
+use crate::resolver::{PackageConflict, ResolutionPlan, ResolvedPackage};
 use crate::search::SearchResult;
 use crate::security::{TrustLevel, VerificationResult};
+use crate::updater::PartialUpgradeChoice;
 use anyhow::Result;
 use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect, Input, MultiSelect, Select};
 use std::fmt::Display;
@@ -28,6 +30,7 @@ pub enum ConflictAction {
     Abort,
     Force,
     SelectAlternative,
+    RemoveInstalled,
     Skip,
 }
 
@@ -227,6 +230,28 @@ impl InteractivePrompts {
         }
     }
 
+    pub fn confirm_license(
+        &self,
+        requirement: &LicenseRequirement,
+        license_text: Option<&str>,
+    ) -> Result<bool> {
+        println!("\n📜 License Agreement: {}", requirement.license_name);
+        println!("{}", "─".repeat(50));
+        println!("{} requires you to accept a license before it can be installed.", requirement.package);
+
+        match (license_text, &requirement.license_url) {
+            (Some(text), _) => println!("\n{}\n", text),
+            (None, Some(url)) => println!("Read the full terms at: {}", url),
+            (None, None) => println!("The license text could not be retrieved."),
+        }
+
+        Confirm::with_theme(&self.theme)
+            .with_prompt(format!("Do you accept the {}?", requirement.license_name))
+            .default(false)
+            .interact()
+            .map_err(|e| anyhow::anyhow!("Failed to get user confirmation: {}", e))
+    }
+
     pub fn select_from_search_results(
         &self,
         results: &[SearchResult],
@@ -303,73 +328,98 @@ impl InteractivePrompts {
         }
     }
 
-    pub fn resolve_conflict(
-        &self,
-        conflict: &str,
-        alternatives: &[String],
-    ) -> Result<ConflictResolution> {
+    /// Offer the alternative providers of the conflicting package, removing
+    /// the installed package it conflicts with, skipping it, forcing it or
+    /// aborting
+    pub fn resolve_conflict(&self, conflict: &PackageConflict) -> Result<ConflictResolution> {
         println!("\n⚠️  Conflict Detected:");
         println!("{}", "─".repeat(50));
         println!("{}", conflict);
 
-        if alternatives.is_empty() {
-            let options = vec![
-                "Abort installation",
-                "Force installation (ignore conflict)",
-                "Skip this package",
-            ];
-
-            let selection = Select::with_theme(&self.theme)
-                .with_prompt("How would you like to resolve this conflict?")
-                .items(&options)
-                .default(0)
-                .interact()?;
-
-            let action = match selection {
-                0 => ConflictAction::Abort,
-                1 => ConflictAction::Force,
-                2 => ConflictAction::Skip,
-                _ => ConflictAction::Abort,
-            };
-
-            Ok(ConflictResolution {
-                action,
-                selected_option: None,
+        let mut options: Vec<(String, ConflictAction, Option<String>)> = conflict
+            .alternatives
+            .iter()
+            .map(|alternative| {
+                (
+                    format!("Install {} instead of {}", alternative, conflict.package),
+                    ConflictAction::SelectAlternative,
+                    Some(alternative.clone()),
+                )
             })
-        } else {
-            println!("\nAvailable alternatives:");
-            let mut options = alternatives.to_vec();
-            options.extend(vec![
-                "Abort installation".to_string(),
-                "Force original (ignore conflict)".to_string(),
-                "Skip this package".to_string(),
-            ]);
-
-            let selection = Select::with_theme(&self.theme)
-                .with_prompt("Select an alternative or action")
-                .items(&options)
-                .default(0)
-                .interact()?;
+            .collect();
+        if let Some((installed, box_type)) = &conflict.installed {
+            options.push((
+                format!("Remove installed {} [{}] first", installed, box_type),
+                ConflictAction::RemoveInstalled,
+                None,
+            ));
+        }
+        options.push((format!("Skip {}", conflict.package), ConflictAction::Skip, None));
+        options.push((
+            "Force installation (ignore conflict)".to_string(),
+            ConflictAction::Force,
+            None,
+        ));
+        options.push(("Abort installation".to_string(), ConflictAction::Abort, None));
+
+        let labels: Vec<&str> = options.iter().map(|(label, _, _)| label.as_str()).collect();
+        let selection = Select::with_theme(&self.theme)
+            .with_prompt("How would you like to resolve this conflict?")
+            .items(&labels)
+            .default(0)
+            .interact_opt()?;
 
-            if selection < alternatives.len() {
-                Ok(ConflictResolution {
-                    action: ConflictAction::SelectAlternative,
-                    selected_option: Some(alternatives[selection].clone()),
-                })
-            } else {
-                let action = match selection - alternatives.len() {
-                    0 => ConflictAction::Abort,
-                    1 => ConflictAction::Force,
-                    2 => ConflictAction::Skip,
-                    _ => ConflictAction::Abort,
-                };
-
-                Ok(ConflictResolution {
+        Ok(match selection {
+            Some(index) => {
+                let (_, action, selected_option) = options.swap_remove(index);
+                ConflictResolution {
                     action,
-                    selected_option: None,
-                })
+                    selected_option,
+                }
             }
+            None => ConflictResolution {
+                action: ConflictAction::Abort,
+                selected_option: None,
+            },
+        })
+    }
+
+    /// Pick the package to install for the virtual package `name`; None when
+    /// the user cancels
+    pub fn select_provider(&self, name: &str, providers: &[String]) -> Result<Option<String>> {
+        println!("\n📦 {} is a virtual package provided by:", name);
+        let selection = Select::with_theme(&self.theme)
+            .with_prompt("Which package should provide it?")
+            .items(providers)
+            .default(0)
+            .interact_opt()?;
+
+        Ok(selection.map(|index| providers[index].clone()))
+    }
+
+    /// Offer a full upgrade in place of a partial one; going ahead with the
+    /// partial upgrade is only offered when `allow_partial`
+    pub fn resolve_partial_upgrade(&self, allow_partial: bool) -> Result<PartialUpgradeChoice> {
+        let mut options = vec![(
+            "Upgrade the whole system with pacman -Syu (recommended)",
+            PartialUpgradeChoice::FullSync,
+        )];
+        if allow_partial {
+            options.push((
+                "Update only the requested packages (partial upgrade)",
+                PartialUpgradeChoice::Partial,
+            ));
         }
+        options.push(("Cancel", PartialUpgradeChoice::Cancel));
+
+        let labels: Vec<&str> = options.iter().map(|(label, _)| *label).collect();
+        let selection = Select::with_theme(&self.theme)
+            .with_prompt("How would you like to proceed?")
+            .items(&labels)
+            .default(0)
+            .interact_opt()?;
+
+        Ok(selection.map_or(PartialUpgradeChoice::Cancel, |index| options[index].1))
     }
 
     pub fn get_input<T>(&self, prompt: &str, default: Option<T>) -> Result<T>
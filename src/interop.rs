@@ -0,0 +1,432 @@
+//! Converts between omni manifests and other tools' declarative package lists:
+//! Homebrew `Brewfile`s and `winget export` JSON.
+
+use crate::manifest::{MetaInfo, OmniApp, OmniManifest};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// `source` of a manifest entry that is a Homebrew cask rather than a formula
+pub const CASK: &str = "cask";
+
+const WINGET_SCHEMA: &str = "https://aka.ms/winget-packages.schema.2.0.json";
+/// winget's default source, the one entries without a `source` come from
+const WINGET_SOURCE: &str = "winget";
+
+/// Package list format omni can import and export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Homebrew Bundle `Brewfile`
+    Brewfile,
+    /// `winget export` / `winget import` JSON
+    Winget,
+}
+
+impl Format {
+    /// Guess the format from the file name
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.starts_with("brewfile") {
+            Some(Format::Brewfile)
+        } else if name.ends_with(".json") {
+            Some(Format::Winget)
+        } else {
+            None
+        }
+    }
+}
+
+/// Result of a conversion, with the entries the target format cannot express
+#[derive(Debug)]
+pub struct Converted<T> {
+    pub output: T,
+    pub skipped: Vec<String>,
+}
+
+/// Read a Brewfile or winget export into a manifest named after the file
+pub fn import_file(path: &Path, format: Option<Format>) -> Result<Converted<OmniManifest>> {
+    let format = format.or_else(|| Format::detect(path)).ok_or_else(|| {
+        anyhow!(
+            "Cannot tell the format of {}; pass --format brewfile or --format winget",
+            path.display()
+        )
+    })?;
+    let content = std::fs::read_to_string(path)?;
+    let project = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("imported")
+        .to_string();
+
+    match format {
+        Format::Brewfile => import_brewfile(&content, &project),
+        Format::Winget => import_winget(&content, &project),
+    }
+}
+
+/// Write the manifest entries `format` can hold
+pub fn export(manifest: &OmniManifest, format: Format) -> Result<Converted<String>> {
+    match format {
+        Format::Brewfile => Ok(export_brewfile(manifest)),
+        Format::Winget => export_winget(manifest),
+    }
+}
+
+fn new_manifest(project: &str, description: String, apps: Vec<OmniApp>) -> OmniManifest {
+    OmniManifest {
+        project: project.to_string(),
+        description: Some(description),
+        apps,
+        meta: Some(MetaInfo {
+            created_by: Some("omni manifest import".to_string()),
+            created_on: Some(Utc::now().format("%Y-%m-%d").to_string()),
+            distro_fallback: None,
+        }),
+        dotfiles: None,
+    }
+}
+
+fn app(name: &str, box_type: &str, version: Option<String>, source: Option<String>) -> OmniApp {
+    OmniApp {
+        name: name.to_string(),
+        box_type: box_type.to_string(),
+        version,
+        source,
+        recipe: None,
+    }
+}
+
+/// `brew`, `cask` and `mas` entries become brew and mas apps. Taps are added
+/// by brew when a formula names them (`user/repo/formula`), so `tap` lines and
+/// entries for other tools (`vscode`, `whalebrew`, ...) are reported as skipped.
+pub fn import_brewfile(content: &str, project: &str) -> Result<Converted<OmniManifest>> {
+    let mut apps = Vec::new();
+    let mut skipped = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (kind, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let Some((name, options)) = quoted(rest.trim_start()) else {
+            skipped.push(format!("line {}: {}", number + 1, line));
+            continue;
+        };
+        let options = options.trim_start_matches(',').trim();
+
+        match kind {
+            "brew" | "cask" => {
+                let source = (kind == "cask").then(|| CASK.to_string());
+                apps.push(app(name, "brew", None, source));
+                if !options.is_empty() {
+                    skipped.push(format!(
+                        "line {}: options for {} ({})",
+                        number + 1,
+                        name,
+                        options
+                    ));
+                }
+            }
+            "mas" => {
+                let id = options
+                    .strip_prefix("id:")
+                    .map(|id| id.trim().to_string())
+                    .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+                    .ok_or_else(|| {
+                        anyhow!("line {}: mas \"{}\" needs an `id:`", number + 1, name)
+                    })?;
+                apps.push(app(name, "mas", None, Some(id)));
+            }
+            _ => skipped.push(format!("line {}: {}", number + 1, line)),
+        }
+    }
+
+    Ok(Converted {
+        output: new_manifest(project, "Imported from a Brewfile".to_string(), apps),
+        skipped,
+    })
+}
+
+/// The first quoted string in `text`, and what follows it
+fn quoted(text: &str) -> Option<(&str, &str)> {
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let rest = &text[1..];
+    let end = rest.find(quote)?;
+    Some((&rest[..end], &rest[end + 1..]))
+}
+
+/// brew and mas apps as a Brewfile; other boxes are skipped
+pub fn export_brewfile(manifest: &OmniManifest) -> Converted<String> {
+    let mut formulae = Vec::new();
+    let mut casks = Vec::new();
+    let mut mas = Vec::new();
+    let mut skipped = Vec::new();
+
+    for app in &manifest.apps {
+        match (app.box_type.as_str(), app.source.as_deref()) {
+            ("brew", Some(CASK)) => casks.push(format!("cask \"{}\"", app.name)),
+            ("brew", _) => formulae.push(format!("brew \"{}\"", app.name)),
+            ("mas", Some(id)) if id.chars().all(|c| c.is_ascii_digit()) => {
+                mas.push(format!("mas \"{}\", id: {}", app.name, id))
+            }
+            ("mas", _) => skipped.push(format!("{} (mas entries need an App Store id)", app.name)),
+            (other, _) => skipped.push(format!("{} ({})", app.name, other)),
+        }
+    }
+
+    let mut output = format!("# Exported by omni from {}\n", manifest.project);
+    for section in [formulae, casks, mas] {
+        if !section.is_empty() {
+            output.push('\n');
+            output.push_str(&section.join("\n"));
+            output.push('\n');
+        }
+    }
+
+    Converted { output, skipped }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WingetExport {
+    #[serde(rename = "$schema", default)]
+    schema: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    creation_date: Option<String>,
+    sources: Vec<WingetSource>,
+    #[serde(
+        rename = "WinGetVersion",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    winget_version: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WingetSource {
+    packages: Vec<WingetPackage>,
+    source_details: WingetSourceDetails,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WingetPackage {
+    package_identifier: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WingetSourceDetails {
+    argument: String,
+    identifier: String,
+    name: String,
+    #[serde(rename = "Type")]
+    kind: String,
+}
+
+impl WingetSourceDetails {
+    /// Details of the sources winget ships with
+    fn builtin(name: &str) -> Option<Self> {
+        let (argument, identifier, kind) = match name {
+            WINGET_SOURCE => (
+                "https://cdn.winget.microsoft.com/cache",
+                "Microsoft.Winget.Source_8wekyb3d8bbwe",
+                "Microsoft.PreIndexed.Package",
+            ),
+            "msstore" => (
+                "https://storeedgefd.dsx.mp.microsoft.com/v9.0",
+                "StoreEdgeFD",
+                "Microsoft.Rest",
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            argument: argument.to_string(),
+            identifier: identifier.to_string(),
+            name: name.to_string(),
+            kind: kind.to_string(),
+        })
+    }
+}
+
+/// Every package becomes a winget app; packages from sources other than
+/// `winget` keep the source name as their `source`
+pub fn import_winget(content: &str, project: &str) -> Result<Converted<OmniManifest>> {
+    let export: WingetExport =
+        serde_json::from_str(content).map_err(|e| anyhow!("Not a winget export file: {}", e))?;
+
+    let apps = export
+        .sources
+        .into_iter()
+        .flat_map(|source| {
+            let name = source.source_details.name;
+            source.packages.into_iter().map(move |package| {
+                app(
+                    &package.package_identifier,
+                    "winget",
+                    package.version,
+                    (name != WINGET_SOURCE).then(|| name.clone()),
+                )
+            })
+        })
+        .collect();
+
+    Ok(Converted {
+        output: new_manifest(project, "Imported from winget export".to_string(), apps),
+        skipped: Vec::new(),
+    })
+}
+
+/// winget apps as a file `winget import` accepts; other boxes, and sources
+/// winget does not ship with, are skipped
+pub fn export_winget(manifest: &OmniManifest) -> Result<Converted<String>> {
+    let mut sources: BTreeMap<&str, Vec<WingetPackage>> = BTreeMap::new();
+    let mut skipped = Vec::new();
+
+    for app in &manifest.apps {
+        if app.box_type != "winget" {
+            skipped.push(format!("{} ({})", app.name, app.box_type));
+            continue;
+        }
+        sources
+            .entry(app.source.as_deref().unwrap_or(WINGET_SOURCE))
+            .or_default()
+            .push(WingetPackage {
+                package_identifier: app.name.clone(),
+                version: app.version.clone(),
+            });
+    }
+
+    let mut export = WingetExport {
+        schema: WINGET_SCHEMA.to_string(),
+        creation_date: Some(Utc::now().to_rfc3339()),
+        sources: Vec::new(),
+        winget_version: None,
+    };
+    for (name, packages) in sources {
+        match WingetSourceDetails::builtin(name) {
+            Some(source_details) => export.sources.push(WingetSource {
+                packages,
+                source_details,
+            }),
+            None => skipped.extend(
+                packages
+                    .iter()
+                    .map(|p| format!("{} (unknown winget source {})", p.package_identifier, name)),
+            ),
+        }
+    }
+
+    Ok(Converted {
+        output: serde_json::to_string_pretty(&export)? + "\n",
+        skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brewfile_round_trip() {
+        let brewfile = r#"
+# Development
+tap "homebrew/bundle"
+brew "git"
+brew "mysql@8.0", restart_service: true
+brew "hashicorp/tap/terraform"
+cask 'firefox'
+mas "Xcode", id: 497799835
+vscode "rust-lang.rust-analyzer"
+"#;
+        let imported = import_brewfile(brewfile, "laptop").unwrap();
+        let apps = &imported.output.apps;
+        assert_eq!(apps.len(), 5);
+        assert_eq!(apps[2].name, "hashicorp/tap/terraform");
+        assert_eq!(
+            (apps[3].box_type.as_str(), apps[3].source.as_deref()),
+            ("brew", Some(CASK))
+        );
+        assert_eq!(
+            (apps[4].box_type.as_str(), apps[4].source.as_deref()),
+            ("mas", Some("497799835"))
+        );
+        assert_eq!(imported.skipped.len(), 3);
+        assert!(imported.skipped[1].contains("restart_service"));
+
+        let exported = export_brewfile(&imported.output);
+        assert!(exported.output.contains("brew \"mysql@8.0\"\n"));
+        assert!(exported.output.contains("cask \"firefox\"\n"));
+        assert!(exported.output.contains("mas \"Xcode\", id: 497799835\n"));
+        assert!(exported.skipped.is_empty());
+
+        assert!(import_brewfile("mas \"Xcode\"", "laptop").is_err());
+    }
+
+    #[test]
+    fn test_winget_round_trip() {
+        let export = r#"{
+  "$schema" : "https://aka.ms/winget-packages.schema.2.0.json",
+  "CreationDate" : "2024-06-01T10:00:00.000-00:00",
+  "Sources" : [
+    {
+      "Packages" : [
+        { "PackageIdentifier" : "Git.Git", "Version" : "2.45.1" },
+        { "PackageIdentifier" : "Mozilla.Firefox" }
+      ],
+      "SourceDetails" : {
+        "Argument" : "https://cdn.winget.microsoft.com/cache",
+        "Identifier" : "Microsoft.Winget.Source_8wekyb3d8bbwe",
+        "Name" : "winget",
+        "Type" : "Microsoft.PreIndexed.Package"
+      }
+    },
+    {
+      "Packages" : [ { "PackageIdentifier" : "9NBLGGH4NNS1" } ],
+      "SourceDetails" : {
+        "Argument" : "https://storeedgefd.dsx.mp.microsoft.com/v9.0",
+        "Identifier" : "StoreEdgeFD",
+        "Name" : "msstore",
+        "Type" : "Microsoft.Rest"
+      }
+    }
+  ],
+  "WinGetVersion" : "1.8.1911"
+}"#;
+        let mut imported = import_winget(export, "desktop").unwrap().output;
+        assert_eq!(imported.apps.len(), 3);
+        assert_eq!(imported.apps[0].version.as_deref(), Some("2.45.1"));
+        assert_eq!(imported.apps[1].source, None);
+        assert_eq!(imported.apps[2].source.as_deref(), Some("msstore"));
+
+        imported.apps.push(app("git", "apt", None, None));
+        let exported = export_winget(&imported).unwrap();
+        assert_eq!(exported.skipped, vec!["git (apt)".to_string()]);
+        let reimported = import_winget(&exported.output, "desktop").unwrap().output;
+        assert_eq!(reimported.apps.len(), 3);
+        // Sources are written in name order
+        assert_eq!(reimported.apps[0].name, "9NBLGGH4NNS1");
+        assert_eq!(reimported.apps[0].source.as_deref(), Some("msstore"));
+
+        assert!(import_winget("{}", "desktop").is_err());
+    }
+
+    #[test]
+    fn test_format_detection() {
+        assert_eq!(
+            Format::detect(Path::new("Brewfile")),
+            Some(Format::Brewfile)
+        );
+        assert_eq!(
+            Format::detect(Path::new("/tmp/packages.json")),
+            Some(Format::Winget)
+        );
+        assert_eq!(Format::detect(Path::new("omni.yaml")), None);
+    }
+}
@@ -0,0 +1,449 @@
+//! Signing keys omni trusts: a GnuPG home of omni's own, used for signature
+//! checks and for the keyrings of apt repositories added with a key
+
+use crate::config::OmniConfig;
+use crate::database::{Database, TrustedKey};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tracing::{info, warn};
+
+/// Source recorded for keys received from a key server
+const KEYSERVER_SOURCE: &str = "keyserver";
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A public key in the keyring
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyInfo {
+    pub fingerprint: String,
+    pub user_ids: Vec<String>,
+    pub created: Option<DateTime<Utc>>,
+    pub expires: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+impl KeyInfo {
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= Utc::now())
+    }
+
+    /// The key as one line for listings
+    pub fn describe(&self) -> String {
+        let state = if self.revoked {
+            " [revoked]".to_string()
+        } else if self.is_expired() {
+            " [expired]".to_string()
+        } else {
+            self.expires
+                .map(|e| format!(" (expires {})", e.format("%Y-%m-%d")))
+                .unwrap_or_default()
+        };
+        format!(
+            "{} {}{}",
+            self.fingerprint,
+            self.user_ids
+                .first()
+                .map(String::as_str)
+                .unwrap_or("(no user ID)"),
+            state
+        )
+    }
+}
+
+/// omni's GnuPG home; `gpg --homedir` keeps it apart from the user's keyring
+#[derive(Debug, Clone)]
+pub struct KeyStore {
+    home: PathBuf,
+}
+
+impl KeyStore {
+    pub fn open() -> Result<Self> {
+        Self::at(OmniConfig::data_dir()?.join("keys"))
+    }
+
+    pub fn at(home: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&home)?;
+        // gpg refuses homes others can read
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&home, fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(Self { home })
+    }
+
+    /// A `gpg` command working on this keyring
+    pub fn gpg(&self) -> Command {
+        let mut command = Command::new("gpg");
+        command.arg("--homedir").arg(&self.home).arg("--batch");
+        command
+    }
+
+    pub fn list(&self) -> Result<Vec<KeyInfo>> {
+        let output = self
+            .gpg()
+            .args(["--with-colons", "--fixed-list-mode", "--list-keys"])
+            .output()?;
+        Ok(parse_key_listing(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    pub fn has_keys(&self) -> bool {
+        self.list().is_ok_and(|keys| !keys.is_empty())
+    }
+
+    /// Import armored or binary key data; returns the fingerprints gpg took
+    pub fn import(&self, data: &[u8]) -> Result<Vec<String>> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(data)?;
+        let output = self
+            .gpg()
+            .args(["--status-fd", "1", "--import"])
+            .arg(file.path())
+            .output()?;
+        imported_or_error(&output)
+    }
+
+    /// Fetch a key by fingerprint from the first of `servers` that has it
+    pub fn receive(&self, servers: &[String], key_id: &str) -> Result<Vec<String>> {
+        let mut last_error = anyhow!("No key servers configured (security.signature_servers)");
+        for server in servers {
+            let output = self
+                .gpg()
+                .args([
+                    "--status-fd",
+                    "1",
+                    "--keyserver",
+                    server,
+                    "--recv-keys",
+                    key_id,
+                ])
+                .output()?;
+            match imported_or_error(&output) {
+                Ok(fingerprints) => return Ok(fingerprints),
+                Err(e) => {
+                    warn!("Could not fetch key {} from {}: {}", key_id, server, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn delete(&self, fingerprint: &str) -> Result<()> {
+        let output = self
+            .gpg()
+            .args(["--yes", "--delete-keys", fingerprint])
+            .output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "gpg could not delete {}: {}",
+                fingerprint,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Binary keyring with just these keys, e.g. for an apt `signed-by` file
+    pub fn export(&self, fingerprints: &[String]) -> Result<Vec<u8>> {
+        let output = self.gpg().arg("--export").args(fingerprints).output()?;
+        if !output.status.success() || output.stdout.is_empty() {
+            return Err(anyhow!(
+                "gpg exported nothing for {}",
+                fingerprints.join(", ")
+            ));
+        }
+        Ok(output.stdout)
+    }
+
+    /// The key whose fingerprint is or ends with `key` (a long key ID)
+    pub fn find(&self, key: &str) -> Result<KeyInfo> {
+        let wanted = normalize_key_id(key);
+        let mut matches: Vec<KeyInfo> = self
+            .list()?
+            .into_iter()
+            .filter(|k| k.fingerprint.ends_with(&wanted))
+            .collect();
+        match matches.len() {
+            0 => Err(anyhow!("No trusted key matches {}", key)),
+            1 => Ok(matches.remove(0)),
+            _ => Err(anyhow!(
+                "{} matches several keys; give the full fingerprint",
+                key
+            )),
+        }
+    }
+}
+
+/// Trust the keys at `source`: an https URL, a file, or a fingerprint to look up
+/// on `servers`
+pub async fn add(
+    db: &Database,
+    store: &KeyStore,
+    servers: &[String],
+    source: &str,
+) -> Result<Vec<KeyInfo>> {
+    crate::config::ensure_writable("changing trusted keys")?;
+    let (fingerprints, recorded_source) = fetch(store, servers, source).await?;
+    let now = Utc::now();
+    for fingerprint in &fingerprints {
+        db.trust_key(&TrustedKey {
+            fingerprint: fingerprint.clone(),
+            source: recorded_source.clone(),
+            added_at: now,
+            refreshed_at: None,
+        })
+        .await?;
+    }
+
+    let keys = store.list()?;
+    Ok(keys
+        .into_iter()
+        .filter(|k| fingerprints.contains(&k.fingerprint))
+        .collect())
+}
+
+pub async fn remove(db: &Database, store: &KeyStore, key: &str) -> Result<KeyInfo> {
+    crate::config::ensure_writable("changing trusted keys")?;
+    let info = store.find(key)?;
+    store.delete(&info.fingerprint)?;
+    db.untrust_key(&info.fingerprint).await?;
+    Ok(info)
+}
+
+/// Fetch every key again from where it came from, which picks up new expiry
+/// dates and revocations. Returns the keys that could not be refreshed.
+pub async fn refresh(
+    db: &Database,
+    store: &KeyStore,
+    servers: &[String],
+) -> Result<Vec<(String, String)>> {
+    crate::config::ensure_writable("changing trusted keys")?;
+    let records = db.get_trusted_keys().await?;
+    let mut failed = Vec::new();
+    for key in store.list()? {
+        let record = records.iter().find(|r| r.fingerprint == key.fingerprint);
+        let source = match record {
+            Some(record) if record.source != KEYSERVER_SOURCE => record.source.clone(),
+            _ => key.fingerprint.clone(),
+        };
+        info!("Refreshing key {} from {}", key.fingerprint, source);
+        match fetch(store, servers, &source).await {
+            Ok((_, recorded_source)) => {
+                db.trust_key(&TrustedKey {
+                    fingerprint: key.fingerprint.clone(),
+                    source: recorded_source,
+                    added_at: record.map_or_else(Utc::now, |r| r.added_at),
+                    refreshed_at: Some(Utc::now()),
+                })
+                .await?;
+            }
+            Err(e) => failed.push((key.fingerprint, e.to_string())),
+        }
+    }
+    Ok(failed)
+}
+
+/// Import the keys at `source`; returns their fingerprints and the source to
+/// record for refreshes
+async fn fetch(
+    store: &KeyStore,
+    servers: &[String],
+    source: &str,
+) -> Result<(Vec<String>, String)> {
+    if source.starts_with("http://") {
+        return Err(anyhow!(
+            "Refusing to fetch a signing key over plain http: {}",
+            source
+        ));
+    }
+    if source.starts_with("https://") {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("omni/", env!("CARGO_PKG_VERSION")))
+            .timeout(FETCH_TIMEOUT)
+            .build()?;
+        let data = client
+            .get(source)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        return Ok((store.import(&data)?, source.to_string()));
+    }
+
+    let path = Path::new(source);
+    if path.is_file() {
+        let absolute = fs::canonicalize(path)?;
+        let data = fs::read(&absolute)?;
+        return Ok((store.import(&data)?, absolute.display().to_string()));
+    }
+
+    let key_id = normalize_key_id(source);
+    if is_key_id(&key_id) {
+        return Ok((
+            store.receive(servers, &key_id)?,
+            KEYSERVER_SOURCE.to_string(),
+        ));
+    }
+    Err(anyhow!(
+        "{} is not an https URL, a key file or a key fingerprint",
+        source
+    ))
+}
+
+fn imported_or_error(output: &std::process::Output) -> Result<Vec<String>> {
+    let fingerprints = parse_imported(&String::from_utf8_lossy(&output.stdout));
+    if fingerprints.is_empty() {
+        return Err(anyhow!(
+            "gpg imported no keys: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(fingerprints)
+}
+
+/// Fingerprints from the `IMPORT_OK` lines of `gpg --status-fd` output
+pub fn parse_imported(status: &str) -> Vec<String> {
+    let mut fingerprints: Vec<String> = Vec::new();
+    for line in status.lines() {
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("[GNUPG:]") || fields.next() != Some("IMPORT_OK") {
+            continue;
+        }
+        if let Some(fingerprint) = fields.nth(1) {
+            if !fingerprints.iter().any(|f| f == fingerprint) {
+                fingerprints.push(fingerprint.to_string());
+            }
+        }
+    }
+    fingerprints
+}
+
+/// Keys from `gpg --with-colons --fixed-list-mode --list-keys`
+pub fn parse_key_listing(listing: &str) -> Vec<KeyInfo> {
+    let timestamp = |field: Option<&&str>| {
+        field
+            .and_then(|f| f.parse::<i64>().ok())
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+    };
+
+    let mut keys: Vec<KeyInfo> = Vec::new();
+    // The first `fpr` after `pub` is the primary key's; later ones are subkeys'
+    let mut want_fingerprint = false;
+    for line in listing.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.first().copied() {
+            Some("pub") => {
+                keys.push(KeyInfo {
+                    fingerprint: String::new(),
+                    user_ids: Vec::new(),
+                    created: timestamp(fields.get(5)),
+                    expires: timestamp(fields.get(6)),
+                    revoked: fields.get(1) == Some(&"r"),
+                });
+                want_fingerprint = true;
+            }
+            Some("fpr") if want_fingerprint => {
+                if let (Some(key), Some(fingerprint)) = (keys.last_mut(), fields.get(9)) {
+                    key.fingerprint = fingerprint.to_string();
+                }
+                want_fingerprint = false;
+            }
+            Some("uid") => {
+                if let (Some(key), Some(user_id)) = (keys.last_mut(), fields.get(9)) {
+                    key.user_ids.push(unescape_colons(user_id));
+                }
+            }
+            Some("sub") => want_fingerprint = false,
+            _ => {}
+        }
+    }
+    keys
+}
+
+/// `--with-colons` escapes `:` in user IDs as `\x3a`
+fn unescape_colons(field: &str) -> String {
+    field.replace("\\x3a", ":")
+}
+
+fn normalize_key_id(key: &str) -> String {
+    key.trim_start_matches("0x")
+        .replace(' ', "")
+        .to_ascii_uppercase()
+}
+
+/// Long key IDs (16 hex digits) and fingerprints (40); short IDs collide too
+/// easily to trust
+fn is_key_id(key: &str) -> bool {
+    matches!(key.len(), 16 | 40) && key.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_listing() {
+        let listing = "tru::1:1718000000:0:3:1:5
+pub:-:4096:1:9DC858229FC7DD38:1515601920:1831134720::-:::scESC::::::23::0:
+fpr:::::::::A8A2C5D3E3F1C6AE31D9E0F49DC858229FC7DD38:
+uid:-::::1515601920::2CB1A7A0E24F38F1C9B1A8E8C1F2BB1E4D6C3A55::Example Repo <repo@example.org>::::::::::0:
+uid:-::::1515601920::73F1A4B0E24F38F1C9B1A8E8C1F2BB1E4D6C3A55::Example Repo \\x3a signing::::::::::0:
+sub:-:4096:1:D1A8F40C6F4BBE92:1515601920:1831134720:::::e::::::23:
+fpr:::::::::B1C6AE31D9E0F49C8E7E2C9E0D1A8F40C6F4BBE92:
+pub:r:2048:1:0123456789ABCDEF:1300000000:::-:::sc::::::23::0:
+fpr:::::::::FEDCBA98765432100123456789ABCDEF01234567:
+uid:r::::1300000000::AA::Old Key <old@example.org>::::::::::0:
+";
+        let keys = parse_key_listing(listing);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(
+            keys[0].fingerprint,
+            "A8A2C5D3E3F1C6AE31D9E0F49DC858229FC7DD38"
+        );
+        assert_eq!(
+            keys[0].user_ids,
+            vec!["Example Repo <repo@example.org>", "Example Repo : signing"]
+        );
+        assert_eq!(keys[0].created.unwrap().timestamp(), 1515601920);
+        assert!(keys[0].expires.is_some());
+        assert!(!keys[0].revoked);
+        assert!(keys[1].revoked);
+        assert_eq!(keys[1].expires, None);
+        assert!(keys[1].describe().ends_with("[revoked]"));
+    }
+
+    #[test]
+    fn test_parse_imported() {
+        let status = "[GNUPG:] KEY_CONSIDERED A8A2C5D3E3F1C6AE31D9E0F49DC858229FC7DD38 0
+[GNUPG:] IMPORTED 9DC858229FC7DD38 Example Repo <repo@example.org>
+[GNUPG:] IMPORT_OK 1 A8A2C5D3E3F1C6AE31D9E0F49DC858229FC7DD38
+[GNUPG:] IMPORT_OK 0 A8A2C5D3E3F1C6AE31D9E0F49DC858229FC7DD38
+[GNUPG:] IMPORT_RES 1 0 1 0 0 0 0 0 0 0 0 0 0 0 0
+";
+        assert_eq!(
+            parse_imported(status),
+            vec!["A8A2C5D3E3F1C6AE31D9E0F49DC858229FC7DD38"]
+        );
+        assert!(parse_imported("[GNUPG:] IMPORT_RES 0 0 0").is_empty());
+    }
+
+    #[test]
+    fn test_key_ids() {
+        assert_eq!(normalize_key_id("0x9dc858229fc7dd38"), "9DC858229FC7DD38");
+        assert!(is_key_id("9DC858229FC7DD38"));
+        assert!(is_key_id(&normalize_key_id(
+            "A8A2 C5D3 E3F1 C6AE 31D9 E0F4 9DC8 5822 9FC7 DD38"
+        )));
+        assert!(!is_key_id("9FC7DD38"));
+        assert!(!is_key_id("nginx-signing.key"));
+    }
+}
@@ -1,37 +1,94 @@
 // Omni Universal Linux Package Manager Library
 // This file exposes the public API for testing and benchmarking
 
+pub mod access_control;
+#[cfg(feature = "grpc")]
+pub mod api;
+pub mod appstream;
+pub mod artifacts;
 pub mod boxes;
 pub mod brain;
 pub mod branding;
+pub mod changelog;
+pub mod channels;
+pub mod clean;
 pub mod config;
+pub mod config_migration;
+pub mod config_secrets;
+pub mod config_validation;
+pub mod credentials;
+pub mod daemon;
 pub mod database;
+pub mod database_migration;
+pub mod digest;
+pub mod dist_upgrade;
 pub mod distro;
+pub mod doctor;
+pub mod dotfiles;
+pub mod downgrade;
+pub mod events;
+pub mod fleet;
 #[cfg(feature = "gui")]
 pub mod gui;
 pub mod hardware;
+pub mod incremental_search;
+pub mod health;
+pub mod health_checks;
+pub mod help;
 pub mod history;
+pub mod impact;
+pub mod integrity;
 pub mod interactive;
+pub mod interop;
+pub mod keys;
+pub mod licenses;
+pub mod lifecycle;
+pub mod lock;
 pub mod logging;
+pub mod maintenance;
 pub mod manifest;
+pub mod mirrors;
+pub mod mock;
+pub mod notifications;
+pub mod ownership;
 pub mod package_discovery;
+pub mod package_queue;
+pub mod policy;
+pub mod priority;
+pub mod provenance;
 pub mod resolver;
 pub mod search;
 pub mod security;
+pub mod self_update;
+pub mod server;
+pub mod siem;
 pub mod snapshot;
+pub mod snapshot_backends;
 pub mod types;
 pub mod updater;
+pub mod vendored;
+pub mod vulnerabilities;
+pub mod watchlist;
+pub mod why;
+pub mod windows_remote;
+pub mod winrm;
 
 // Essential modules
 pub mod advanced_resolver;
 pub mod audit;
+pub mod authorization;
+pub mod autoremove;
 pub mod error_handling;
 pub mod input_validation;
 pub mod privilege_manager;
+pub mod project;
 pub mod runtime;
 pub mod sandboxing;
+pub mod sat;
+pub mod schedule;
 pub mod secure_brain;
 pub mod secure_executor;
+pub mod syslog;
 pub mod transaction;
 pub mod unified_manager;
 
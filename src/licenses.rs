@@ -0,0 +1,318 @@
+use crate::config::OmniConfig;
+use crate::distro;
+use crate::privilege_manager::PrivilegeManager;
+use anyhow::{anyhow, Result};
+use std::process::Command;
+use tracing::{info, warn};
+
+/// A debconf question that must be answered before a package will install
+/// non-interactively
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LicenseRequirement {
+    pub package: String,
+    /// debconf owner (usually the package that ships the template)
+    pub owner: String,
+    pub question: String,
+    pub question_type: String,
+    pub accept_value: String,
+    pub license_name: String,
+    pub license_url: Option<String>,
+    /// Template whose description carries the license text, if any
+    pub notice_template: Option<String>,
+}
+
+impl LicenseRequirement {
+    /// Line suitable for `debconf-set-selections`
+    pub fn selection_line(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.owner, self.question, self.question_type, self.accept_value
+        )
+    }
+}
+
+struct KnownLicense {
+    packages: &'static [&'static str],
+    owner: &'static str,
+    question: &'static str,
+    question_type: &'static str,
+    accept_value: &'static str,
+    license_name: &'static str,
+    license_url: Option<&'static str>,
+    notice_template: Option<&'static str>,
+}
+
+const KNOWN_LICENSES: &[KnownLicense] = &[
+    KnownLicense {
+        packages: &[
+            "ttf-mscorefonts-installer",
+            "ubuntu-restricted-extras",
+            "kubuntu-restricted-extras",
+            "xubuntu-restricted-extras",
+        ],
+        owner: "ttf-mscorefonts-installer",
+        question: "msttcorefonts/accepted-mscorefonts-eula",
+        question_type: "boolean",
+        accept_value: "true",
+        license_name: "Microsoft TrueType core fonts EULA",
+        license_url: Some("http://corefonts.sourceforge.net/eula.htm"),
+        notice_template: Some("msttcorefonts/present-mscorefonts-eula"),
+    },
+    KnownLicense {
+        packages: &["steam", "steam-installer"],
+        owner: "steam",
+        question: "steam/question",
+        question_type: "select",
+        accept_value: "I AGREE",
+        license_name: "Steam Install Agreement",
+        license_url: Some("https://store.steampowered.com/subscriber_agreement/"),
+        notice_template: Some("steam/license"),
+    },
+    KnownLicense {
+        packages: &["virtualbox-ext-pack"],
+        owner: "virtualbox-ext-pack",
+        question: "virtualbox-ext-pack/license",
+        question_type: "boolean",
+        accept_value: "true",
+        license_name: "VirtualBox Extension Pack PUEL",
+        license_url: Some("https://www.virtualbox.org/wiki/VirtualBox_PUEL"),
+        notice_template: Some("virtualbox-ext-pack/license"),
+    },
+    KnownLicense {
+        packages: &["firmware-ipw2x00"],
+        owner: "firmware-ipw2x00",
+        question: "firmware-ipw2x00/license/accepted",
+        question_type: "boolean",
+        accept_value: "true",
+        license_name: "Intel PRO/Wireless firmware license",
+        license_url: None,
+        notice_template: Some("firmware-ipw2x00/license/accepted"),
+    },
+    KnownLicense {
+        packages: &["firmware-ivtv"],
+        owner: "firmware-ivtv",
+        question: "firmware-ivtv/license/accepted",
+        question_type: "boolean",
+        accept_value: "true",
+        license_name: "Hauppauge ivtv firmware license",
+        license_url: None,
+        notice_template: Some("firmware-ivtv/license/accepted"),
+    },
+    KnownLicense {
+        packages: &["oracle-java8-installer"],
+        owner: "oracle-java8-installer",
+        question: "shared/accepted-oracle-license-v1-1",
+        question_type: "select",
+        accept_value: "true",
+        license_name: "Oracle Binary Code License Agreement",
+        license_url: Some("https://www.oracle.com/technetwork/java/javase/terms/license/"),
+        notice_template: Some("shared/present-oracle-license-v1-1"),
+    },
+];
+
+/// Returns the EULA that must be accepted before `package` can be installed,
+/// if it is one of the packages known to gate installation on debconf.
+pub fn detect(package: &str, box_type: Option<&str>) -> Option<LicenseRequirement> {
+    match box_type {
+        Some("apt") => {}
+        None if distro::command_exists("apt") => {}
+        _ => return None,
+    }
+
+    KNOWN_LICENSES
+        .iter()
+        .find(|known| known.packages.contains(&package))
+        .map(|known| LicenseRequirement {
+            package: package.to_string(),
+            owner: known.owner.to_string(),
+            question: known.question.to_string(),
+            question_type: known.question_type.to_string(),
+            accept_value: known.accept_value.to_string(),
+            license_name: known.license_name.to_string(),
+            license_url: known.license_url.map(|u| u.to_string()),
+            notice_template: known.notice_template.map(|t| t.to_string()),
+        })
+}
+
+/// Fetch the license text shipped in the package's debconf templates.
+///
+/// Downloads the owning .deb without installing it and extracts its control
+/// archive. Returns `None` when the text cannot be obtained; callers should
+/// fall back to `license_url`.
+pub fn fetch_license_text(requirement: &LicenseRequirement) -> Option<String> {
+    let template_name = requirement.notice_template.as_deref()?;
+    let workdir = tempfile::tempdir_in(OmniConfig::temp_dir().ok()?).ok()?;
+
+    let download = Command::new("apt-get")
+        .args(["download", &requirement.owner])
+        .current_dir(workdir.path())
+        .output()
+        .ok()?;
+    if !download.status.success() {
+        warn!(
+            "Could not download {} to read its license: {}",
+            requirement.owner,
+            String::from_utf8_lossy(&download.stderr)
+        );
+        return None;
+    }
+
+    let deb = std::fs::read_dir(workdir.path())
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| p.extension().is_some_and(|ext| ext == "deb"))?;
+
+    let control_dir = workdir.path().join("control");
+    let extract = Command::new("dpkg-deb")
+        .arg("-e")
+        .arg(&deb)
+        .arg(&control_dir)
+        .output()
+        .ok()?;
+    if !extract.status.success() {
+        return None;
+    }
+
+    let content = std::fs::read_to_string(control_dir.join("templates")).ok()?;
+    parse_templates(&content)
+        .into_iter()
+        .find(|t| t.name == template_name)
+        .map(|t| t.extended_description)
+        .filter(|text| !text.trim().is_empty())
+}
+
+/// Preseed acceptance so the package's maintainer scripts do not prompt
+pub fn preseed(
+    requirement: &LicenseRequirement,
+    privilege_manager: &PrivilegeManager,
+) -> Result<()> {
+    let selection = format!("{}\n", requirement.selection_line());
+    privilege_manager
+        .execute_with_sudo_stdin("debconf-set-selections", &[], &selection)
+        .map_err(|e| anyhow!("Failed to preseed license acceptance: {}", e))?;
+
+    info!(
+        "Preseeded {} acceptance for {}",
+        requirement.license_name, requirement.package
+    );
+    Ok(())
+}
+
+/// One stanza of a debconf `templates` file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebconfTemplate {
+    pub name: String,
+    pub template_type: String,
+    pub short_description: String,
+    pub extended_description: String,
+}
+
+/// Parse the untranslated fields of a debconf `templates` file
+pub fn parse_templates(content: &str) -> Vec<DebconfTemplate> {
+    let mut templates = Vec::new();
+    let mut current = DebconfTemplate::default();
+    let mut in_description = false;
+
+    for line in content.lines() {
+        if line.trim().is_empty() {
+            if !current.name.is_empty() {
+                templates.push(std::mem::take(&mut current));
+            }
+            in_description = false;
+            continue;
+        }
+
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if in_description {
+                let text = line.trim();
+                if text == "." {
+                    current.extended_description.push('\n');
+                } else {
+                    if !current.extended_description.is_empty()
+                        && !current.extended_description.ends_with('\n')
+                    {
+                        current.extended_description.push(' ');
+                    }
+                    current.extended_description.push_str(text);
+                }
+            }
+            continue;
+        }
+
+        in_description = false;
+        if let Some((field, value)) = line.split_once(':') {
+            let value = value.trim();
+            match field {
+                "Template" => current.name = value.to_string(),
+                "Type" => current.template_type = value.to_string(),
+                "Description" => {
+                    current.short_description = value.to_string();
+                    in_description = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if !current.name.is_empty() {
+        templates.push(current);
+    }
+
+    templates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATES: &str = "Template: msttcorefonts/present-mscorefonts-eula
+Type: note
+Description: TrueType core fonts for the Web EULA
+ END-USER LICENSE AGREEMENT FOR MICROSOFT SOFTWARE
+ .
+ IMPORTANT-READ CAREFULLY: This Microsoft End-User
+ License Agreement is a legal agreement.
+Description-de.UTF-8: EULA
+ Übersetzt
+
+Template: msttcorefonts/accepted-mscorefonts-eula
+Type: boolean
+Default: false
+Description: Do you accept the EULA license terms?
+";
+
+    #[test]
+    fn test_parse_templates() {
+        let templates = parse_templates(TEMPLATES);
+        assert_eq!(templates.len(), 2);
+
+        assert_eq!(templates[0].name, "msttcorefonts/present-mscorefonts-eula");
+        assert_eq!(templates[0].template_type, "note");
+        assert_eq!(
+            templates[0].extended_description,
+            "END-USER LICENSE AGREEMENT FOR MICROSOFT SOFTWARE\nIMPORTANT-READ CAREFULLY: This Microsoft End-User License Agreement is a legal agreement."
+        );
+
+        assert_eq!(templates[1].template_type, "boolean");
+        assert!(templates[1].extended_description.is_empty());
+    }
+
+    #[test]
+    fn test_detect_known_license() {
+        let requirement = detect("ttf-mscorefonts-installer", Some("apt")).unwrap();
+        assert_eq!(
+            requirement.selection_line(),
+            "ttf-mscorefonts-installer msttcorefonts/accepted-mscorefonts-eula boolean true"
+        );
+
+        let steam = detect("steam", Some("apt")).unwrap();
+        assert_eq!(
+            steam.selection_line(),
+            "steam steam/question select I AGREE"
+        );
+
+        assert!(detect("ttf-mscorefonts-installer", Some("flatpak")).is_none());
+        assert!(detect("firefox", Some("apt")).is_none());
+    }
+}
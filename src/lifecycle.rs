@@ -0,0 +1,375 @@
+//! Knows when the running distribution release stops getting updates, from
+//! the endoflife.date API with bundled dates as the fallback.
+
+use crate::config::ReleaseSupportConfig;
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+const API: &str = "https://endoflife.date/api";
+const API_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// End of regular support per release, as endoflife.date listed it when this
+/// version of omni was built. Used when the API cannot be reached.
+const BUNDLED: &[(&str, &str, &str)] = &[
+    ("ubuntu", "18.04", "2023-05-31"),
+    ("ubuntu", "20.04", "2025-05-29"),
+    ("ubuntu", "22.04", "2027-06-01"),
+    ("ubuntu", "24.04", "2029-05-31"),
+    ("ubuntu", "24.10", "2025-07-10"),
+    ("ubuntu", "25.04", "2026-01-15"),
+    ("debian", "10", "2022-09-10"),
+    ("debian", "11", "2024-08-14"),
+    ("debian", "12", "2026-06-10"),
+    ("fedora", "39", "2024-11-26"),
+    ("fedora", "40", "2025-05-13"),
+    ("fedora", "41", "2025-12-15"),
+    ("fedora", "42", "2026-05-13"),
+    ("rhel", "7", "2024-06-30"),
+    ("rhel", "8", "2029-05-31"),
+    ("rhel", "9", "2032-05-31"),
+    ("centos", "7", "2024-06-30"),
+    ("centos-stream", "9", "2027-05-31"),
+    ("rocky-linux", "8", "2029-05-31"),
+    ("rocky-linux", "9", "2032-05-31"),
+    ("almalinux", "8", "2029-03-01"),
+    ("almalinux", "9", "2032-05-31"),
+    ("opensuse", "15.5", "2024-12-31"),
+    ("opensuse", "15.6", "2025-12-31"),
+    ("alpine", "3.19", "2025-11-01"),
+    ("alpine", "3.20", "2026-04-01"),
+];
+
+/// A distribution release, named the way endoflife.date names it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    /// endoflife.date product, e.g. `ubuntu` or `rocky-linux`
+    pub product: String,
+    /// Release cycle of the product, e.g. `24.04` or `9`
+    pub cycle: String,
+    /// `PRETTY_NAME` for messages
+    pub name: String,
+}
+
+impl Release {
+    /// The running release, or `None` for rolling and unknown distributions
+    pub fn detect() -> Option<Self> {
+        Self::from_os_release(&std::fs::read_to_string("/etc/os-release").ok()?)
+    }
+
+    pub fn from_os_release(contents: &str) -> Option<Self> {
+        let field = |name: &str| os_release_field(contents, name);
+        let id = field("ID")?;
+        let version = field("VERSION_ID")?;
+        let major = version.split('.').next().unwrap_or(&version).to_string();
+        let stream = field("NAME").is_some_and(|name| name.contains("Stream"));
+
+        let (product, cycle) = match id.as_str() {
+            "ubuntu" | "debian" | "fedora" | "linuxmint" => (id.clone(), version.clone()),
+            "centos" if stream => ("centos-stream".to_string(), major),
+            "rhel" | "centos" | "almalinux" => (id.clone(), major),
+            "rocky" => ("rocky-linux".to_string(), major),
+            "opensuse-leap" => ("opensuse".to_string(), version.clone()),
+            "alpine" => {
+                let cycle = version.split('.').take(2).collect::<Vec<_>>().join(".");
+                (id.clone(), cycle)
+            }
+            // Rolling releases (Arch, Tumbleweed, ...) have no end of life
+            _ => return None,
+        };
+
+        Some(Self {
+            product,
+            cycle,
+            name: field("PRETTY_NAME").unwrap_or_else(|| format!("{} {}", id, version)),
+        })
+    }
+}
+
+/// A `KEY=value` field of `/etc/os-release` contents, unquoted
+pub fn os_release_field(contents: &str, name: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let value = line.strip_prefix(name)?.strip_prefix('=')?;
+        Some(value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// End of life of a release cycle, as endoflife.date reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eol {
+    On(NaiveDate),
+    /// No date announced yet
+    NotAnnounced,
+    /// Ended on a date endoflife.date does not list
+    Ended,
+}
+
+/// Where an end-of-life date came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Api,
+    Bundled,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::Api => "endoflife.date",
+            Source::Bundled => "bundled data",
+        })
+    }
+}
+
+/// Whether a release still gets updates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    Supported { until: Option<NaiveDate> },
+    EndingSoon { until: NaiveDate },
+    Ended { since: Option<NaiveDate> },
+    Unknown,
+}
+
+impl Support {
+    pub fn from_eol(eol: Eol, today: NaiveDate, warn_days: u32) -> Self {
+        match eol {
+            Eol::On(date) if date <= today => Support::Ended { since: Some(date) },
+            Eol::On(date) if (date - today).num_days() <= i64::from(warn_days) => {
+                Support::EndingSoon { until: date }
+            }
+            Eol::On(date) => Support::Supported { until: Some(date) },
+            Eol::NotAnnounced => Support::Supported { until: None },
+            Eol::Ended => Support::Ended { since: None },
+        }
+    }
+}
+
+/// A release and how long it is supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub release: Release,
+    pub support: Support,
+    pub source: Option<Source>,
+}
+
+impl Status {
+    /// One line for warnings and `omni doctor`
+    pub fn describe(&self) -> String {
+        let name = &self.release.name;
+        let text = match self.support {
+            Support::Supported { until: Some(date) } => {
+                format!("{} is supported until {}", name, date)
+            }
+            Support::Supported { until: None } => {
+                format!("{} is supported; no end of life is announced", name)
+            }
+            Support::EndingSoon { until } => format!(
+                "{} reaches end of life on {} ({} days left)",
+                name,
+                until,
+                (until - Utc::now().date_naive()).num_days()
+            ),
+            Support::Ended { since: Some(date) } => {
+                format!(
+                    "{} reached end of life on {} and gets no more updates",
+                    name, date
+                )
+            }
+            Support::Ended { since: None } => {
+                format!("{} reached end of life and gets no more updates", name)
+            }
+            Support::Unknown => format!("No end-of-life data for {}", name),
+        };
+        match self.source {
+            Some(source) => format!("{} ({})", text, source),
+            None => text,
+        }
+    }
+}
+
+/// Support status of the running release; `None` on rolling releases and
+/// systems without `/etc/os-release`
+pub async fn check(config: &ReleaseSupportConfig) -> Option<Status> {
+    let release = Release::detect()?;
+    let api = if config.use_api {
+        fetch(&release)
+            .await
+            .ok()
+            .flatten()
+            .map(|eol| (eol, Source::Api))
+    } else {
+        None
+    };
+    let found = api.or_else(|| bundled(&release).map(|eol| (eol, Source::Bundled)));
+
+    let today = Utc::now().date_naive();
+    Some(Status {
+        support: found.map_or(Support::Unknown, |(eol, _)| {
+            Support::from_eol(eol, today, config.warn_days)
+        }),
+        source: found.map(|(_, source)| source),
+        release,
+    })
+}
+
+/// Support status from the bundled data only, for callers that cannot wait
+/// on the network
+pub fn check_offline(config: &ReleaseSupportConfig) -> Option<Status> {
+    let release = Release::detect()?;
+    let eol = bundled(&release);
+    Some(Status {
+        support: eol.map_or(Support::Unknown, |eol| {
+            Support::from_eol(eol, Utc::now().date_naive(), config.warn_days)
+        }),
+        source: eol.map(|_| Source::Bundled),
+        release,
+    })
+}
+
+/// Warn when the release is ending or has ended, and refuse to go on past
+/// its end when `release_support.require_supported` is set
+pub async fn warn_or_refuse(config: &ReleaseSupportConfig, operation: &str) -> Result<()> {
+    let Some(status) = check(config).await else {
+        return Ok(());
+    };
+    match status.support {
+        Support::EndingSoon { .. } => {
+            println!("⚠️  {}", status.describe());
+            println!("   Plan the upgrade to a supported release");
+        }
+        Support::Ended { .. } if config.require_supported => {
+            return Err(anyhow!(
+                "Refusing {}: {}. Upgrade to a supported release, or set release_support.require_supported to false",
+                operation,
+                status.describe()
+            ));
+        }
+        Support::Ended { .. } => {
+            println!("⚠️  {}", status.describe());
+            println!("   Repositories may be archived and security fixes have stopped; upgrade the release");
+        }
+        Support::Supported { .. } | Support::Unknown => {}
+    }
+    Ok(())
+}
+
+fn bundled(release: &Release) -> Option<Eol> {
+    BUNDLED
+        .iter()
+        .find(|(product, cycle, _)| *product == release.product && *cycle == release.cycle)
+        .and_then(|(_, _, date)| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        .map(Eol::On)
+}
+
+#[derive(Deserialize)]
+struct Cycle {
+    eol: serde_json::Value,
+}
+
+async fn fetch(release: &Release) -> Result<Option<Eol>> {
+    let client = reqwest::Client::builder()
+        .user_agent("omni-package-manager/0.2.0")
+        .timeout(API_TIMEOUT)
+        .build()?;
+    let body = client
+        .get(format!(
+            "{}/{}/{}.json",
+            API, release.product, release.cycle
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(parse_cycle(&body))
+}
+
+/// The `eol` of an endoflife.date cycle: a date, or a boolean saying whether
+/// it has ended
+pub fn parse_cycle(body: &str) -> Option<Eol> {
+    let cycle: Cycle = serde_json::from_str(body).ok()?;
+    match cycle.eol {
+        serde_json::Value::String(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+            .ok()
+            .map(Eol::On),
+        serde_json::Value::Bool(true) => Some(Eol::Ended),
+        serde_json::Value::Bool(false) => Some(Eol::NotAnnounced),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_release_from_os_release() {
+        let ubuntu = "PRETTY_NAME=\"Ubuntu 24.04.1 LTS\"\nNAME=\"Ubuntu\"\nVERSION_ID=\"24.04\"\nID=ubuntu\nID_LIKE=debian\n";
+        let release = Release::from_os_release(ubuntu).unwrap();
+        assert_eq!(
+            (release.product.as_str(), release.cycle.as_str()),
+            ("ubuntu", "24.04")
+        );
+        assert_eq!(release.name, "Ubuntu 24.04.1 LTS");
+        assert_eq!(bundled(&release), Some(Eol::On(date("2029-05-31"))));
+
+        let rocky = "NAME=\"Rocky Linux\"\nVERSION_ID=\"9.4\"\nID=\"rocky\"\n";
+        let release = Release::from_os_release(rocky).unwrap();
+        assert_eq!(
+            (release.product.as_str(), release.cycle.as_str()),
+            ("rocky-linux", "9")
+        );
+
+        let stream = "NAME=\"CentOS Stream\"\nVERSION_ID=\"9\"\nID=\"centos\"\n";
+        assert_eq!(
+            Release::from_os_release(stream).unwrap().product,
+            "centos-stream"
+        );
+
+        let alpine = "NAME=\"Alpine Linux\"\nID=alpine\nVERSION_ID=3.20.3\n";
+        assert_eq!(Release::from_os_release(alpine).unwrap().cycle, "3.20");
+
+        let arch = "NAME=\"Arch Linux\"\nID=arch\nBUILD_ID=rolling\n";
+        assert_eq!(Release::from_os_release(arch), None);
+    }
+
+    #[test]
+    fn test_support_windows() {
+        let today = date("2026-10-16");
+        assert_eq!(
+            Support::from_eol(Eol::On(date("2029-05-31")), today, 90),
+            Support::Supported {
+                until: Some(date("2029-05-31"))
+            }
+        );
+        assert_eq!(
+            Support::from_eol(Eol::On(date("2026-12-01")), today, 90),
+            Support::EndingSoon {
+                until: date("2026-12-01")
+            }
+        );
+        assert_eq!(
+            Support::from_eol(Eol::On(date("2026-06-10")), today, 90),
+            Support::Ended {
+                since: Some(date("2026-06-10"))
+            }
+        );
+        assert_eq!(
+            Support::from_eol(Eol::Ended, today, 90),
+            Support::Ended { since: None }
+        );
+
+        assert_eq!(
+            parse_cycle(r#"{"cycle": "24.04", "lts": true, "eol": "2029-05-31"}"#),
+            Some(Eol::On(date("2029-05-31")))
+        );
+        assert_eq!(parse_cycle(r#"{"eol": false}"#), Some(Eol::NotAnnounced));
+        assert_eq!(parse_cycle(r#"{"eol": true}"#), Some(Eol::Ended));
+        assert_eq!(parse_cycle("not json"), None);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::config::{self, OmniConfig};
+use anyhow::{anyhow, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Lock serializing operations that change packages, shared by the CLI and the daemon
+const OPERATIONS_LOCK: &str = "operations.lock";
+/// Lock ensuring a single `omni daemon` per user
+const DAEMON_LOCK: &str = "daemon.lock";
+/// Lock held while a process rebuilds the search index
+const INDEX_LOCK: &str = "index.lock";
+
+/// An exclusive `flock` on a file in the runtime directory, released on drop.
+/// The holder's PID is written into the file so waiters can say who they wait for.
+#[derive(Debug)]
+pub struct OperationLock {
+    file: File,
+}
+
+impl OperationLock {
+    /// Take the operations lock, waiting for another omni process to finish
+    pub fn acquire() -> Result<Self> {
+        let path = lock_path(OPERATIONS_LOCK)?;
+        if let Some(lock) = Self::try_acquire_at(&path)? {
+            return Ok(lock);
+        }
+
+        match holder(&path) {
+            Some(pid) => println!(
+                "⏳ Waiting for another omni process (pid {}) to finish...",
+                pid
+            ),
+            None => println!("⏳ Waiting for another omni process to finish..."),
+        }
+        Self::acquire_at(&path)
+    }
+
+    /// Take the operations lock if it is free
+    pub fn try_acquire() -> Result<Option<Self>> {
+        Self::try_acquire_at(&lock_path(OPERATIONS_LOCK)?)
+    }
+
+    /// Take the single-instance lock of the daemon
+    pub fn try_acquire_daemon() -> Result<Option<Self>> {
+        Self::try_acquire_at(&lock_path(DAEMON_LOCK)?)
+    }
+
+    /// Take the lock of search index refreshes if no other process is refreshing
+    pub fn try_acquire_index() -> Result<Option<Self>> {
+        Self::try_acquire_at(&lock_path(INDEX_LOCK)?)
+    }
+
+    pub fn try_acquire_at(path: &Path) -> Result<Option<Self>> {
+        let file = open(path)?;
+        if !flock(&file, libc::LOCK_EX | libc::LOCK_NB)? {
+            debug!("{} is held by another process", path.display());
+            return Ok(None);
+        }
+        Ok(Some(Self::claim(file)?))
+    }
+
+    fn acquire_at(path: &Path) -> Result<Self> {
+        let file = open(path)?;
+        flock(&file, libc::LOCK_EX)?;
+        Self::claim(file)
+    }
+
+    fn claim(mut file: File) -> Result<Self> {
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for OperationLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = flock(&self.file, libc::LOCK_UN);
+    }
+}
+
+/// PID of the process holding the lock at `path`, if it recorded one
+pub fn holder(path: &Path) -> Option<u32> {
+    let mut contents = String::new();
+    File::open(path).ok()?.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+/// PID of the running daemon, if any
+pub fn daemon_pid() -> Option<u32> {
+    let path = lock_path(DAEMON_LOCK).ok()?;
+    // A free lock means the recorded PID is stale
+    let probe = OperationLock::try_acquire_at(&path).ok()?;
+    match probe {
+        Some(_) => None,
+        None => holder(&path),
+    }
+}
+
+/// PID of the omni process holding the operations lock, if it is held
+pub fn operations_holder() -> Option<u32> {
+    let path = lock_path(OPERATIONS_LOCK).ok()?;
+    match OperationLock::try_acquire_at(&path).ok()? {
+        Some(_) => None,
+        None => holder(&path),
+    }
+}
+
+fn lock_path(name: &str) -> Result<PathBuf> {
+    let dir = OmniConfig::runtime_dir()?;
+    config::ensure_private_dir(&dir)?;
+    Ok(dir.join(name))
+}
+
+fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open lock file {}: {}", path.display(), e))
+}
+
+/// `flock` the file; `Ok(false)` when a non-blocking request finds it taken
+fn flock(file: &File, operation: libc::c_int) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(anyhow!("Failed to lock: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive_and_released_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("operations.lock");
+
+        let lock = OperationLock::try_acquire_at(&path).unwrap().unwrap();
+        assert_eq!(holder(&path), Some(std::process::id()));
+        assert!(OperationLock::try_acquire_at(&path).unwrap().is_none());
+
+        drop(lock);
+        assert_eq!(holder(&path), None);
+        assert!(OperationLock::try_acquire_at(&path).unwrap().is_some());
+    }
+}
@@ -1,7 +1,12 @@
-use crate::config::OmniConfig;
+#[cfg(feature = "otel")]
+use crate::config::TelemetryConfig;
+use crate::config::{LogFormat, LogRotation, LoggingConfig, OmniConfig};
 use anyhow::Result;
-use std::fs;
-use std::path::PathBuf;
+use chrono::{DateTime, Duration, Utc};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use tracing::{Level, Subscriber};
 use tracing_subscriber::{
     fmt::{self, format::FmtSpan},
@@ -12,39 +17,324 @@ use tracing_subscriber::{
 
 pub fn init_logging(config: &OmniConfig) -> Result<()> {
     let log_level = parse_log_level(&config.general.log_level);
-    let log_dir = OmniConfig::data_dir()?.join("logs");
-    fs::create_dir_all(&log_dir)?;
+    let log_dir = OmniConfig::log_dir()?;
 
     let log_file = log_dir.join("omni.log");
-    let file_appender = tracing_appender::rolling::daily(&log_dir, "omni.log");
+    let file_appender = Mutex::new(RotatingFile::new(log_dir, config.logging.clone()));
 
     let env_filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new(format!("omni={}", log_level)));
 
-    let file_layer = fmt::layer()
+    // Only one of each pair is set; JSON lines carry the event fields at the
+    // top level (timestamp, level, message, target, ...) for log collectors
+    let (text_file, json_file) = match config.logging.format {
+        LogFormat::Text => (Some(file_layer().with_writer(file_appender)), None),
+        LogFormat::Json => (
+            None,
+            Some(
+                file_layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_writer(file_appender),
+            ),
+        ),
+    };
+    let (text_stdout, json_stdout) = match config.logging.format {
+        LogFormat::Text => (
+            Some(
+                fmt::layer()
+                    .with_target(false)
+                    .with_thread_ids(false)
+                    .with_file(false)
+                    .with_line_number(false)
+                    .with_ansi(config.ui.use_colors)
+                    .compact(),
+            ),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(fmt::layer().json().flatten_event(true).with_ansi(false)),
+        ),
+    };
+
+    let registry = Registry::default()
+        .with(env_filter)
+        .with(text_file)
+        .with(json_file)
+        .with(text_stdout)
+        .with(json_stdout);
+    #[cfg(feature = "otel")]
+    registry.with(otel_layer(&config.telemetry)?).init();
+    #[cfg(not(feature = "otel"))]
+    {
+        registry.init();
+        if config.telemetry.enabled {
+            tracing::warn!(
+                "telemetry.enabled needs omni built with the otel feature; no spans are exported"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The log file layer, before its format and writer are set
+fn file_layer<S>() -> fmt::Layer<S> {
+    fmt::layer()
         .with_file(true)
         .with_line_number(true)
         .with_thread_ids(true)
         .with_target(true)
         .with_span_events(FmtSpan::CLOSE)
-        .with_writer(file_appender)
-        .with_ansi(false);
-
-    let stdout_layer = fmt::layer()
-        .with_target(false)
-        .with_thread_ids(false)
-        .with_file(false)
-        .with_line_number(false)
-        .with_ansi(config.ui.use_colors)
-        .compact();
-
-    Registry::default()
-        .with(env_filter)
-        .with(file_layer)
-        .with(stdout_layer)
-        .init();
+        .with_ansi(false)
+}
 
-    Ok(())
+/// Start of every log file name: `omni.log`, `omni.log.2026-10-16`, and
+/// `omni.log.2026-10-16.1` for a file rotated by size
+const LOG_FILE_PREFIX: &str = "omni.log";
+
+/// Log file writer starting a new file every hour or day, as configured, and
+/// whenever the current one outgrows `max_file_size_mb`. Old files are pruned
+/// each time a new one is started.
+pub struct RotatingFile {
+    dir: PathBuf,
+    config: LoggingConfig,
+    current: Option<OpenLog>,
+}
+
+struct OpenLog {
+    file: File,
+    path: PathBuf,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn new(dir: PathBuf, config: LoggingConfig) -> Self {
+        Self {
+            dir,
+            config,
+            current: None,
+        }
+    }
+
+    /// Move the full current file out of the way, to the first free
+    /// `<name>.<n>`
+    fn roll_over(&mut self) -> io::Result<()> {
+        let Some(log) = self.current.take() else {
+            return Ok(());
+        };
+        drop(log.file);
+        let mut n = 1;
+        let target = loop {
+            let candidate = PathBuf::from(format!("{}.{}", log.path.display(), n));
+            if !candidate.exists() {
+                break candidate;
+            }
+            n += 1;
+        };
+        fs::rename(&log.path, target)
+    }
+
+    fn open(&mut self, path: PathBuf) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            // Private like the log directory
+            options.mode(0o600);
+        }
+        let file = options.open(&path)?;
+        let size = file.metadata()?.len();
+        // Nowhere to report a failure from inside the log writer; the next
+        // rotation or `omni logs prune` tries again
+        let _ = crate::ownership::hand_over(&path);
+        self.current = Some(OpenLog { file, path, size });
+        let _ = prune_logs(&self.dir, &self.config, Utc::now());
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let path = self
+            .dir
+            .join(log_file_name(self.config.rotation, Utc::now()));
+        let max_bytes = self
+            .config
+            .max_file_size_mb
+            .map(|mb| mb.saturating_mul(1024 * 1024));
+        let full = match (&self.current, max_bytes) {
+            (Some(log), Some(max)) => log.size > 0 && log.size + buf.len() as u64 > max,
+            _ => false,
+        };
+
+        if full {
+            self.roll_over()?;
+        }
+        if self.current.as_ref().is_none_or(|log| log.path != path) {
+            self.open(path)?;
+        }
+
+        let log = self.current.as_mut().expect("log file was just opened");
+        log.file.write_all(buf)?;
+        log.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(log) => log.file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The file lines written at `now` go to, named like tracing-appender names them
+fn log_file_name(rotation: LogRotation, now: DateTime<Utc>) -> String {
+    match rotation {
+        LogRotation::Hourly => format!("{}.{}", LOG_FILE_PREFIX, now.format("%Y-%m-%d-%H")),
+        LogRotation::Daily => format!("{}.{}", LOG_FILE_PREFIX, now.format("%Y-%m-%d")),
+        LogRotation::Never => LOG_FILE_PREFIX.to_string(),
+    }
+}
+
+/// One of omni's log files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub modified: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// omni's log files in `dir`, last written first
+pub fn log_files(dir: &Path) -> Result<Vec<LogFile>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let is_log = entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(LOG_FILE_PREFIX);
+        if metadata.is_file() && is_log {
+            files.push(LogFile {
+                path: entry.path(),
+                modified: metadata.modified()?.into(),
+                size: metadata.len(),
+            });
+        }
+    }
+    files.sort_by(|a, b| b.modified.cmp(&a.modified));
+    Ok(files)
+}
+
+/// The files past `max_age_days` or beyond the newest `max_files`; the file
+/// written last is always kept
+pub fn expired<'a>(
+    files: &'a [LogFile],
+    config: &LoggingConfig,
+    now: DateTime<Utc>,
+) -> Vec<&'a LogFile> {
+    files
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(index, file)| {
+            config.max_files.is_some_and(|max| *index >= max)
+                || config
+                    .max_age_days
+                    .is_some_and(|days| now - file.modified > Duration::days(i64::from(days)))
+        })
+        .map(|(_, file)| file)
+        .collect()
+}
+
+/// Delete the expired log files in `dir`, returning them
+pub fn prune_logs(dir: &Path, config: &LoggingConfig, now: DateTime<Utc>) -> Result<Vec<LogFile>> {
+    let files = log_files(dir)?;
+    let mut removed = Vec::new();
+    for file in expired(&files, config, now) {
+        fs::remove_file(&file.path)?;
+        removed.push(file.clone());
+    }
+    Ok(removed)
+}
+
+/// Layer shipping spans to the OTLP collector in `config`, when enabled
+#[cfg(feature = "otel")]
+fn otel_layer<S>(
+    config: &TelemetryConfig,
+) -> Result<Option<tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::SdkTracer>>>
+where
+    S: Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{WithExportConfig, WithHttpConfig};
+
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let mut exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_headers(config.headers.clone().into_iter().collect());
+    if let Some(endpoint) = &config.endpoint {
+        exporter = exporter.with_endpoint(traces_url(endpoint));
+    }
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .with_attribute(opentelemetry::KeyValue::new(
+            "service.version",
+            env!("CARGO_PKG_VERSION"),
+        ))
+        .build();
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter.build()?)
+        .with_resource(resource)
+        .build();
+
+    let tracer = provider.tracer("omni");
+    let _ = TRACER_PROVIDER.set(provider);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+#[cfg(feature = "otel")]
+static TRACER_PROVIDER: std::sync::OnceLock<opentelemetry_sdk::trace::SdkTracerProvider> =
+    std::sync::OnceLock::new();
+
+/// Where the traces of an OTLP/HTTP collector at `endpoint` are posted
+pub fn traces_url(endpoint: &str) -> String {
+    format!("{}/v1/traces", endpoint.trim_end_matches('/'))
+}
+
+/// Export the spans still buffered; call before the process exits
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            tracing::warn!("Failed to export the last spans: {}", e);
+        }
+    }
+}
+
+/// Trace ID of the current span while spans are exported, to correlate
+/// audit entries with traces
+pub fn current_trace_id() -> Option<String> {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TraceContextExt;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let context = tracing::Span::current().context();
+        let span = context.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            return Some(span_context.trace_id().to_string());
+        }
+    }
+    None
 }
 
 fn parse_log_level(level_str: &str) -> &'static str {
@@ -92,3 +382,82 @@ macro_rules! log_error {
         );
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn log_file(name: &str, age_days: i64, now: DateTime<Utc>) -> LogFile {
+        LogFile {
+            path: PathBuf::from(name),
+            modified: now - Duration::days(age_days),
+            size: 10,
+        }
+    }
+
+    #[test]
+    fn test_expired() {
+        let now = Utc::now();
+        let files = vec![
+            log_file("omni.log.2026-10-16", 0, now),
+            log_file("omni.log.2026-10-15", 1, now),
+            log_file("omni.log.2026-09-01", 45, now),
+        ];
+        let names = |config: &LoggingConfig| -> Vec<String> {
+            expired(&files, config, now)
+                .iter()
+                .map(|file| file.path.display().to_string())
+                .collect()
+        };
+
+        assert_eq!(names(&LoggingConfig::default()), ["omni.log.2026-09-01"]);
+        let config = LoggingConfig {
+            max_age_days: None,
+            max_files: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&config),
+            ["omni.log.2026-10-15", "omni.log.2026-09-01"]
+        );
+
+        // The current file stays, however old
+        let stale = [log_file("omni.log", 400, now)];
+        assert!(expired(&stale, &LoggingConfig::default(), now).is_empty());
+    }
+
+    #[test]
+    fn test_rotating_file_rolls_over_by_size() {
+        let dir = TempDir::new().unwrap();
+        let config = LoggingConfig {
+            rotation: LogRotation::Never,
+            max_file_size_mb: Some(0),
+            ..Default::default()
+        };
+        let mut writer = RotatingFile::new(dir.path().to_path_buf(), config.clone());
+        writer.write_all(b"first\n").unwrap();
+        writer.write_all(b"second\n").unwrap();
+        writer.write_all(b"third\n").unwrap();
+        writer.flush().unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("omni.log"), "third\n");
+        assert_eq!(read("omni.log.1"), "first\n");
+        assert_eq!(read("omni.log.2"), "second\n");
+
+        let files = log_files(dir.path()).unwrap();
+        assert_eq!(files.len(), 3);
+        let removed = prune_logs(
+            dir.path(),
+            &LoggingConfig {
+                max_files: Some(1),
+                ..config
+            },
+            Utc::now(),
+        )
+        .unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(log_files(dir.path()).unwrap().len(), 1);
+    }
+}
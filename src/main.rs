@@ -1,46 +1,105 @@
+mod access_control;
 mod advanced_resolver;
+#[cfg(feature = "grpc")]
+mod api;
+mod appstream;
+mod artifacts;
 mod audit;
+mod authorization;
+mod autoremove;
 mod boxes;
 mod brain;
 mod branding;
+mod changelog;
+mod channels;
+mod clean;
 mod config;
+mod config_migration;
+mod config_secrets;
+mod config_validation;
+mod credentials;
+mod daemon;
 mod database;
+mod database_migration;
+mod digest;
+mod dist_upgrade;
 mod distro;
+mod doctor;
 mod docker;
+mod dotfiles;
+mod downgrade;
 mod error_handling;
+mod events;
+mod fleet;
 #[cfg(feature = "gui")]
 mod gui;
 mod hardware;
+mod incremental_search;
+mod health;
+mod health_checks;
+mod help;
 mod history;
+mod impact;
+mod integrity;
 mod input_validation;
 mod interactive;
+mod interop;
+mod keys;
+mod licenses;
+mod lifecycle;
+mod lock;
 mod logging;
+mod maintenance;
 mod manifest;
+mod mirrors;
+mod mock;
+mod notifications;
+mod ownership;
 mod package_discovery;
+mod package_queue;
+mod policy;
+mod priority;
 mod privilege_manager;
+mod project;
+mod provenance;
 mod resolver;
 mod runtime;
 mod sandboxing;
+mod sat;
+mod schedule;
 mod search;
 mod secure_brain;
 mod secure_executor;
 mod security;
+mod self_update;
+mod siem;
 mod snapshot;
+mod snapshot_backends;
 #[cfg(feature = "ssh")]
 mod ssh;
+mod syslog;
 #[cfg(test)]
 mod testing;
 mod transaction;
 mod types;
 mod unified_manager;
 mod updater;
+mod vendored;
+mod vulnerabilities;
+mod watchlist;
+mod why;
+mod windows_remote;
+mod winrm;
 mod server;
 
 use anyhow::Result;
 use brain::OmniBrain;
 use branding::OmniBranding;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use config::OmniConfig;
+use error_handling::ErrorReport;
+use fleet::{FleetInventory, FleetRunner, HostSelector};
+use input_validation::InputValidator;
 use manifest::OmniManifest;
 use search::SearchEngine;
 use snapshot::SnapshotManager;
@@ -50,16 +109,47 @@ use updater::UpdateManager;
 #[derive(Parser)]
 #[command(name = "omni")]
 #[command(about = "Universal Cross-Platform Package Manager - Linux, Windows, macOS")]
-#[command(version = "1.0.0")]
+#[command(version)]
+#[command(disable_help_subcommand = true)]
+#[command(after_help = "Run 'omni help' for guides on manifests, security and more.")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
 
+    /// Work against an in-memory package universe instead of the system
     #[arg(long, global = true)]
     mock: bool,
 
+    /// Package universe for --mock (implies --mock)
+    #[arg(long, global = true, value_name = "FILE")]
+    mock_fixture: Option<std::path::PathBuf>,
+
     #[arg(long, global = true)]
     verbose: bool,
+
+    /// How to report a failing command on stderr
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+
+    /// Refuse anything that would change the system or omni's state
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Use the machine-wide config and state (/etc/omni, /var/lib/omni) instead
+    /// of the current user's; root only
+    #[arg(long, global = true)]
+    system: bool,
+
+    /// Override a config key for this run, e.g. --set general.log_level=debug;
+    /// applies after the config files and OMNI_* variables
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    settings: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ErrorFormat {
+    Human,
+    Json,
 }
 
 #[derive(Subcommand)]
@@ -81,6 +171,40 @@ enum Commands {
         /// AppImage source URL
         #[arg(long)]
         url: Option<String>,
+
+        /// Accept package EULAs (e.g. msttcorefonts) without prompting
+        #[arg(long)]
+        accept_licenses: bool,
+
+        /// Release channel: stable, beta, nightly, or a backend-specific name
+        #[arg(long)]
+        channel: Option<String>,
+
+        /// Do not run the dotfiles manager after installing a manifest
+        #[arg(long)]
+        skip_dotfiles: bool,
+
+        /// Plan the package and its dependencies with omni's resolver, settle
+        /// conflicts, and install the plan as one transaction
+        #[arg(long)]
+        resolve_deps: bool,
+    },
+
+    /// Install what the project's .omni/manifest.yaml lists and is not installed yet
+    Sync {
+        /// Show what would be installed
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Do not run the dotfiles manager afterwards
+        #[arg(long)]
+        skip_dotfiles: bool,
+    },
+
+    /// Show, allow or deny the .omni/config.yaml of the project around the current directory
+    Project {
+        #[command(subcommand)]
+        action: ProjectCommands,
     },
 
     /// Remove/uninstall packages
@@ -103,6 +227,12 @@ enum Commands {
         limit: usize,
     },
 
+    /// Local search index of every package the backends offer
+    Index {
+        #[command(subcommand)]
+        action: IndexCommands,
+    },
+
     /// Show package information
     Info {
         /// Package name
@@ -111,6 +241,58 @@ enum Commands {
         /// Specify package box type
         #[arg(long)]
         box_type: Option<String>,
+
+        /// Show which repository/mirror recorded installs came from
+        #[arg(long)]
+        provenance: bool,
+
+        /// Show the package's recent changelog entries
+        #[arg(long)]
+        changelog: bool,
+    },
+
+    /// List the files an installed package owns
+    Files {
+        /// Package name
+        package: String,
+
+        /// Specify package box type
+        #[arg(long)]
+        box_type: Option<String>,
+
+        /// Render the list as a directory tree
+        #[arg(long)]
+        tree: bool,
+    },
+
+    /// Explain why a package is installed
+    Why {
+        /// Package name
+        package: String,
+
+        /// Specify package box type
+        #[arg(long)]
+        box_type: Option<String>,
+    },
+
+    /// Find which installed package owns a file
+    Owns {
+        /// File path, absolute or relative to the current directory
+        path: String,
+    },
+
+    /// List the packages that depend on a package
+    Rdepends {
+        /// Package name
+        package: String,
+
+        /// Ask only this box
+        #[arg(long)]
+        box_type: Option<String>,
+
+        /// Only list installed packages
+        #[arg(long)]
+        installed: bool,
     },
 
     /// Update packages
@@ -125,6 +307,94 @@ enum Commands {
         /// Refresh repositories first
         #[arg(long)]
         refresh: bool,
+
+        /// Update without reviewing changelogs and confirming first
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Apply a large update now instead of queueing it for a maintenance window
+        #[arg(long)]
+        now: bool,
+
+        /// On pacman systems, upgrade everything (pacman -Syu) rather than
+        /// leave other packages behind
+        #[arg(long)]
+        full_sync: bool,
+
+        /// Go through pending updates by impact, confirming each one above
+        /// policies.unattended.max_impact (default: minor)
+        #[arg(long, conflicts_with_all = ["package", "all"])]
+        review: bool,
+
+        /// Apply major updates of critical packages (policies.critical) without
+        /// showing their upstream changes and asking first
+        #[arg(long)]
+        force_critical: bool,
+
+        /// Update only packages with a security advisory (apt's security
+        /// archive, dnf updateinfo)
+        #[arg(long, conflicts_with = "package")]
+        security_only: bool,
+    },
+
+    /// Pin a package so updates skip it (lists pins when no package is given)
+    Pin {
+        /// Package name
+        package: Option<String>,
+
+        /// Specify package box type (defaults to the box it was installed with)
+        #[arg(long)]
+        box_type: Option<String>,
+
+        /// Why the package is pinned
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// Remove a package pin
+    Unpin {
+        /// Package name
+        package: String,
+
+        /// Only unpin from this box type
+        #[arg(long)]
+        box_type: Option<String>,
+    },
+
+    /// Clear package manager caches and omni's own caches
+    Clean {
+        /// Also remove caches that are still useful (pacman -Scc, all cached metadata)
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Remove automatically installed packages that are no longer needed
+    Autoremove {
+        /// Only list the packages that would be removed
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Install an older version of a package (undo with 'omni history undo')
+    Downgrade {
+        /// Package name
+        package: String,
+
+        /// Version to install (defaults to the one before the installed version)
+        #[arg(long)]
+        to: Option<String>,
+
+        /// Specify package box type (defaults to the box it was installed with)
+        #[arg(long)]
+        box_type: Option<String>,
+
+        /// Only list the versions available to downgrade to
+        #[arg(long)]
+        list: bool,
     },
 
     /// List installed packages
@@ -150,6 +420,12 @@ enum Commands {
         action: SnapshotCommands,
     },
 
+    /// Stage package operations and apply them as one transaction
+    Transaction {
+        #[command(subcommand)]
+        action: TransactionCommands,
+    },
+
     /// Launch GUI
     Gui,
 
@@ -159,6 +435,12 @@ enum Commands {
         action: ConfigCommands,
     },
 
+    /// Convert between omni manifests and Brewfiles or winget exports
+    Manifest {
+        #[command(subcommand)]
+        action: ManifestCommands,
+    },
+
     /// Dependency resolution
     Resolve {
         /// Package name to resolve
@@ -173,10 +455,20 @@ enum Commands {
         detailed: bool,
     },
 
-    /// Security verification
+    /// Security verification of a package file, or of installed packages with --installed
     Verify {
-        /// File path to verify
-        file_path: String,
+        /// File path to verify (package name with --installed)
+        #[arg(value_name = "FILE|PACKAGE")]
+        file_path: Option<String>,
+
+        /// Check installed files against their packages (all packages when none is given)
+        #[arg(long)]
+        installed: bool,
+
+        /// Check the checksums, signature status and sources omni recorded when it
+        /// installed the package (all packages when none is given)
+        #[arg(long, conflicts_with_all = ["installed", "checksum", "signature"])]
+        provenance: bool,
 
         /// Expected checksum
         #[arg(long)]
@@ -191,6 +483,18 @@ enum Commands {
         box_type: Option<String>,
     },
 
+    /// Manage the signing keys omni trusts for signature checks and repositories
+    Keys {
+        #[command(subcommand)]
+        action: KeyCommands,
+    },
+
+    /// Keep passwords and tokens in the system keyring instead of the config file
+    Credentials {
+        #[command(subcommand)]
+        action: CredentialCommands,
+    },
+
     /// Hardware detection and driver management
     Hardware {
         #[command(subcommand)]
@@ -198,11 +502,165 @@ enum Commands {
     },
 
     /// Repository management
+    #[command(alias = "repo")]
     Repository {
         #[command(subcommand)]
         action: RepositoryCommands,
     },
 
+    /// Manage packages across remote hosts from the fleet inventory
+    Fleet {
+        #[command(subcommand)]
+        action: FleetCommands,
+    },
+
+    /// Watch packages for new versions and security advisories
+    Watch {
+        #[command(subcommand)]
+        action: WatchCommands,
+    },
+
+    /// Check for updates in the background on the configured schedule
+    Daemon {
+        /// Run a single check and exit
+        #[arg(long)]
+        once: bool,
+    },
+
+    /// Show what the update daemon last found and did
+    Status {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Run update checks and snapshots from systemd, launchd or Task Scheduler
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleCommands,
+    },
+
+    /// Run the configured dotfiles manager (chezmoi, stow or a command)
+    Dotfiles {
+        /// Use the dotfiles section of this manifest instead of the config
+        #[arg(long)]
+        from: Option<String>,
+
+        /// Print the command without running it
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Check the system for problems, such as a release past its end of life
+    Doctor,
+
+    /// Match installed packages against known vulnerabilities (OSV, with CVE IDs)
+    Scan {
+        /// Only these packages (source package names on Debian and Ubuntu)
+        packages: Vec<String>,
+
+        /// Ask OSV again instead of using cached answers
+        #[arg(long)]
+        refresh: bool,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check the database, backends, disk space and locks; fails when unhealthy
+    Health {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Query, export and verify the audit log, and test its alerts
+    Audit {
+        #[command(subcommand)]
+        action: AuditCommands,
+    },
+
+    /// omni's own log files and audit entries
+    Logs {
+        #[command(subcommand)]
+        action: LogsCommands,
+    },
+
+    /// Permissions and ownership of files omni wrote (files in the config)
+    Permissions {
+        #[command(subcommand)]
+        action: PermissionsCommands,
+    },
+
+    /// Reports on package activity for ops teams
+    Report {
+        #[command(subcommand)]
+        action: ReportCommands,
+    },
+
+    /// Show a help topic, or the help of a command ('omni help fleet add-host')
+    Help {
+        /// Topic or command; lists the topics when left out
+        #[arg(value_name = "TOPIC|COMMAND")]
+        topic: Vec<String>,
+    },
+
+    /// Write man pages for omni and its commands
+    #[command(hide = true)]
+    Man {
+        /// Directory for the pages
+        #[arg(long, default_value = "man")]
+        out_dir: std::path::PathBuf,
+    },
+
+    /// Upgrade the distribution to its next major release
+    DistUpgrade {
+        /// Release to upgrade to (Fedora and openSUSE Leap); defaults to the next one
+        #[arg(long)]
+        to: Option<String>,
+
+        /// Only run the pre-flight checks
+        #[arg(long)]
+        check: bool,
+
+        /// Check that a finished upgrade reached its release
+        #[arg(long, conflicts_with_all = ["to", "check"])]
+        verify: bool,
+
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Update omni itself to the latest release
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+
+        /// Reinstall the latest release even if it is not newer
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Serve the REST API for dashboards and other tools
+    Serve {
+        /// Address for the REST API (default 127.0.0.1:3000 when --grpc is not given)
+        #[arg(long, value_name = "ADDR")]
+        http: Option<std::net::SocketAddr>,
+
+        /// Address for the gRPC interface (needs the grpc feature)
+        #[arg(long, value_name = "ADDR")]
+        grpc: Option<std::net::SocketAddr>,
+    },
+
+    /// Manage who may call omni serve and what their role lets them do
+    Users {
+        #[command(subcommand)]
+        action: UserCommands,
+    },
+
     /// Start the web interface server
     Web {
         /// Port to listen on
@@ -211,6 +669,56 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum ScheduleCommands {
+    /// Write service and timer units (launchd jobs, scheduled tasks) for omni's periodic tasks
+    InstallUnits {
+        /// Install for the current user or the whole machine
+        #[arg(long, value_enum, default_value_t = schedule::Scope::User)]
+        scope: schedule::Scope,
+
+        /// Task to schedule; repeat for several (default: all)
+        #[arg(long = "task", value_enum)]
+        tasks: Vec<schedule::Task>,
+
+        /// Hours between automatic snapshots
+        #[arg(long, default_value = "24")]
+        snapshot_interval_hours: u64,
+
+        /// Enable and start the timers once written
+        #[arg(long)]
+        enable: bool,
+
+        /// Print the units and commands without installing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Stop and delete units written by install-units
+    RemoveUnits {
+        /// Remove from the current user or the whole machine
+        #[arg(long, value_enum, default_value_t = schedule::Scope::User)]
+        scope: schedule::Scope,
+
+        /// Task to unschedule; repeat for several (default: all)
+        #[arg(long = "task", value_enum)]
+        tasks: Vec<schedule::Task>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexCommands {
+    /// Read the backends' whole catalogs into the search index
+    Refresh {
+        /// Only refresh this backend (apt, dnf, pacman or flatpak)
+        #[arg(long)]
+        box_type: Option<String>,
+    },
+
+    /// Show which catalogs are indexed and how old they are
+    Status,
+}
+
 #[derive(Subcommand)]
 enum HistoryCommands {
     /// Show installation history
@@ -222,14 +730,67 @@ enum HistoryCommands {
 
     /// Undo last installation
     Undo,
+
+    /// Reverse everything done after a point in the history
+    Rollback {
+        /// A history id, a date or an RFC 3339 timestamp
+        point: String,
+
+        /// Only show what would be done
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Write the install history as JSON or CSV, to archive or move to another machine
+    Export {
+        /// Output format (default: from the --output extension, else JSON)
+        #[arg(long, value_enum)]
+        format: Option<history::ExportFormat>,
+
+        /// Only records from this date on (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+
+    /// Add the records of an exported history; records already present are skipped
+    Import {
+        /// File written by 'omni history export'
+        file: std::path::PathBuf,
+
+        /// Input format (default: from the file extension)
+        #[arg(long, value_enum)]
+        format: Option<history::ExportFormat>,
+    },
+
+    /// Show packages added, removed and upgraded between two points in the history
+    Diff {
+        /// Start: a date, an RFC 3339 timestamp or a history id
+        from: String,
+
+        /// End: a date, an RFC 3339 timestamp, a history id or `now`
+        #[arg(default_value = "now")]
+        to: String,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 #[derive(Subcommand)]
 enum SnapshotCommands {
     /// Create a snapshot
     Create {
-        /// Snapshot name
-        name: String,
+        /// Snapshot name (default: snapshot-<date>-<time>)
+        name: Option<String>,
 
         /// Snapshot description
         #[arg(short, long)]
@@ -244,612 +805,4878 @@ enum SnapshotCommands {
         /// Snapshot ID or name
         snapshot: String,
     },
-}
 
-#[derive(Subcommand)]
-enum ConfigCommands {
-    /// Show current configuration
-    Show,
+    /// Delete the automatic snapshots the retention settings do not keep
+    Prune {
+        /// Keep only this many automatic snapshots
+        #[arg(long)]
+        keep: Option<usize>,
 
-    /// Edit configuration
-    Edit,
+        /// Delete automatic snapshots older than this many days
+        #[arg(long)]
+        max_age_days: Option<u32>,
 
-    /// Reset to defaults
-    Reset,
+        /// Delete the oldest automatic snapshots past this many MiB of disk
+        #[arg(long)]
+        max_disk_usage_mb: Option<u64>,
+
+        /// List what would be deleted without deleting it
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 #[derive(Subcommand)]
-enum HardwareCommands {
-    /// Detect server hardware and show information
-    Detect,
+enum TransactionCommands {
+    /// Start staging a transaction and print its ID
+    Begin,
+    /// Add operations to a staged transaction
+    Add {
+        /// Transaction ID
+        id: uuid::Uuid,
 
-    /// Auto-detect and install recommended drivers
-    Install,
+        /// Package to install, as [box:]package[=version] (repeatable)
+        #[arg(long = "install", value_name = "PACKAGE")]
+        install: Vec<String>,
 
-    /// Install drivers for specific vendor (Dell, HP, Supermicro, etc.)
-    Vendor {
-        /// Hardware vendor name
-        vendor: String,
-    },
-}
+        /// Package to remove, as [box:]package (repeatable)
+        #[arg(long = "remove", value_name = "PACKAGE")]
+        remove: Vec<String>,
 
-#[derive(Subcommand)]
-enum RepositoryCommands {
-    /// Add a new repository
-    Add {
-        /// Repository URL or identifier
-        repository: String,
-
-        /// Repository type (ppa, rpm, deb, etc.)
+        /// Package to update, as [box:]package[=version] (repeatable)
+        #[arg(long = "update", value_name = "PACKAGE")]
+        update: Vec<String>,
+    },
+    /// Run a staged transaction, rolling it back if an operation fails
+    Commit {
+        /// Transaction ID
+        id: uuid::Uuid,
+    },
+    /// List transactions
+    List {
+        /// Only those in this status: pending, in-progress, completed, failed or rolled-back
         #[arg(long)]
-        repo_type: Option<String>,
+        status: Option<String>,
 
-        /// Repository key URL for verification
+        /// Print the transactions as JSON
         #[arg(long)]
-        key_url: Option<String>,
+        json: bool,
     },
+    /// Show the operations of a transaction and how far they got
+    Show {
+        /// Transaction ID
+        id: uuid::Uuid,
 
-    /// Remove a repository
-    Remove {
-        /// Repository identifier
-        repository: String,
+        /// Print the transaction as JSON
+        #[arg(long)]
+        json: bool,
     },
+    /// Show what a transaction would do without running it
+    Plan {
+        /// Package to install, as [box:]package[=version] (repeatable)
+        #[arg(long = "install", value_name = "PACKAGE")]
+        install: Vec<String>,
 
-    /// List configured repositories
-    List,
+        /// Package to remove, as [box:]package (repeatable)
+        #[arg(long = "remove", value_name = "PACKAGE")]
+        remove: Vec<String>,
 
-    /// Refresh repository metadata
-    Refresh,
+        /// Package to update, as [box:]package[=version] (repeatable)
+        #[arg(long = "update", value_name = "PACKAGE")]
+        update: Vec<String>,
+
+        /// Print the plan as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Finish a transaction that was interrupted
+    Resume {
+        /// Transaction ID
+        id: uuid::Uuid,
+    },
+    /// Drop a staged transaction, or roll back one that ran or was interrupted
+    Rollback {
+        /// Transaction ID
+        id: uuid::Uuid,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Show current configuration
+    Show,
 
-    // Show welcome banner for interactive commands
-    if !cli.verbose && matches!(cli.command, Commands::Search { .. } | Commands::Install { .. } | Commands::Gui) {
-        println!("{}", OmniBranding::welcome_banner());
-    }
+    /// Edit configuration
+    Edit,
 
-    // Load configuration
-    let config = OmniConfig::load()?;
+    /// Reset to defaults
+    Reset,
 
-    // Initialize logging
-    logging::init_logging(&config)?;
+    /// Check the config file for unknown keys, wrong types and values out of range
+    Validate {
+        /// File to check instead of the config file in use
+        file: Option<std::path::PathBuf>,
+    },
 
-    // Handle the command
-    match handle_command(cli, config).await {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Application error: {}", e);
-            std::process::exit(1);
-        }
-    }
+    /// Encrypt a secret for the config file, asked for on the terminal or read from stdin
+    Encrypt,
 
-    Ok(())
+    /// Print the key of encrypted config values, to set up another machine
+    Key {
+        /// Store a key asked for on the terminal or read from stdin instead
+        #[arg(long)]
+        import: bool,
+    },
 }
 
-async fn handle_command(cli: Cli, config: OmniConfig) -> Result<()> {
-    match cli.command {
-        Commands::Install {
-            package,
-            from,
-            box_type,
-            url,
-        } => {
-            let mut brain = OmniBrain::new_with_mock(cli.mock);
+#[derive(Subcommand)]
+enum ManifestCommands {
+    /// Turn a Brewfile or `winget export` JSON into an omni manifest
+    Import {
+        /// Brewfile or winget export file
+        file: String,
 
-            if let Some(manifest_path) = from {
-                let manifest = OmniManifest::from_file(&manifest_path)?;
-                brain.install_from_manifest(manifest).await?;
-            } else if let Some(package_name) = package {
-                if let Some(url) = url {
-                    // AppImage installation
-                    if cli.mock {
-                        println!(
-                            "🎭 [MOCK] Would install AppImage {} from {}",
-                            package_name, url
-                        );
-                    } else {
-                        boxes::appimage::install_appimage(&url, &package_name).await?;
-                        println!("✅ Successfully installed AppImage {}", package_name);
-                    }
-                } else {
-                    brain.install(&package_name, box_type.as_deref()).await?;
-                }
-            } else {
-                return Err(anyhow::anyhow!(
-                    "Please specify a package name or manifest file"
-                ));
-            }
-        }
+        /// Input format (guessed from the file name when omitted)
+        #[arg(long, value_enum)]
+        format: Option<interop::Format>,
 
-        Commands::Remove { package, box_type } => {
-            let mut brain = OmniBrain::new_with_mock(cli.mock);
-            brain.remove(&package, box_type.as_deref()).await?;
-        }
+        /// Write the manifest here instead of printing it
+        #[arg(short, long)]
+        output: Option<String>,
+    },
 
-        Commands::Search { query, limit } => {
-            let search_engine = SearchEngine::new().await?;
-            let results = search_engine.search_all(&query).await?;
+    /// Write a manifest's brew/mas or winget packages in that tool's format
+    Export {
+        /// omni manifest to convert
+        manifest: String,
 
-            println!("🔍 Search results for '{}':\n", query);
+        /// Output format
+        #[arg(long, value_enum)]
+        format: interop::Format,
 
-            for (i, result) in results.iter().take(limit).enumerate() {
-                let status = if result.installed {
-                    "✅ Installed"
-                } else {
-                    "  Available"
-                };
-                println!(
-                    "{}. {} [{}] {}",
-                    i + 1,
-                    result.name,
-                    result.box_type,
-                    status
-                );
+        /// Write the file here instead of printing it
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
 
-                if let Some(desc) = &result.description {
-                    println!("   {}", desc);
-                }
-                println!();
-            }
+#[derive(Subcommand)]
+enum HardwareCommands {
+    /// Detect server hardware and show information
+    Detect,
 
-            if results.len() > limit {
-                println!("... and {} more results", results.len() - limit);
-            }
-        }
+    /// Auto-detect and install recommended drivers
+    Install,
 
-        Commands::Info { package, box_type } => {
-            let search_engine = SearchEngine::new().await?;
+    /// Install drivers for specific vendor (Dell, HP, Supermicro, etc.)
+    Vendor {
+        /// Hardware vendor name
+        vendor: String,
+    },
+}
 
-            if let Some(box_type) = box_type {
-                if let Some(info) = search_engine.get_package_info(&package, &box_type).await? {
-                    println!("{}", info);
-                } else {
-                    println!("❌ Package information not found");
-                }
-            } else {
-                // Try all available box types
-                let box_types = ["apt", "dnf", "pacman", "snap", "flatpak"];
-                let mut found = false;
+#[derive(Subcommand)]
+enum FleetCommands {
+    /// Add or replace a host in the inventory
+    AddHost {
+        /// Inventory name for the host
+        name: String,
 
-                for bt in &box_types {
-                    if distro::command_exists(bt) {
-                        if let Some(info) = search_engine.get_package_info(&package, bt).await? {
-                            println!("📦 Information from {} box:\n{}\n", bt, info);
-                            found = true;
-                        }
-                    }
-                }
+        /// Hostname or IP address
+        address: String,
 
-                if !found {
-                    println!("❌ Package information not found in any available box");
-                }
-            }
-        }
+        /// SSH or WinRM port (22, or 5986 for WinRM over HTTPS)
+        #[arg(long)]
+        port: Option<u16>,
 
-        Commands::Update {
-            package,
-            all,
-            refresh,
-        } => {
-            let update_manager = UpdateManager::new(config).await?;
+        /// Login user
+        #[arg(long, default_value = "root")]
+        user: String,
 
-            if refresh {
-                update_manager.refresh_repositories().await?;
-            }
+        /// How to reach the host
+        #[arg(long, value_enum, default_value_t = fleet::Transport::Ssh)]
+        transport: fleet::Transport,
 
-            if all {
-                update_manager.update_all().await?;
-            } else if let Some(package_name) = package {
-                let candidates = update_manager.check_updates().await?;
-                if let Some(candidate) = candidates.iter().find(|c| c.package_name == package_name)
-                {
-                    update_manager.update_package(candidate).await?;
-                } else {
-                    println!("✅ Package {} is already up to date", package_name);
-                }
-            } else {
-                let candidates = update_manager.check_updates().await?;
+        /// SSH private key
+        #[arg(long)]
+        identity_file: Option<std::path::PathBuf>,
 
-                if candidates.is_empty() {
-                    println!("✅ All packages are up to date");
-                } else {
-                    println!("📦 Available updates:");
-                    for candidate in &candidates {
-                        println!(
-                            "  {} [{}]: {} -> {}",
-                            candidate.package_name,
-                            candidate.box_type,
-                            candidate.current_version.as_deref().unwrap_or("unknown"),
-                            candidate.available_version.as_deref().unwrap_or("latest")
-                        );
-                    }
-                    println!("\nRun 'omni update --all' to update all packages");
-                }
-            }
-        }
+        /// Package manager on the host (detected when omitted)
+        #[arg(long)]
+        box_type: Option<String>,
 
-        Commands::List { box_type, detailed } => {
-            let update_manager = UpdateManager::new(config).await?;
-            let installed = update_manager.list_installed().await?;
+        /// Tag as key=value (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
 
-            let filtered: Vec<_> = if let Some(bt) = box_type {
-                installed.into_iter().filter(|p| p.box_type == bt).collect()
-            } else {
-                installed
-            };
+        /// Group membership (repeatable)
+        #[arg(long = "group")]
+        groups: Vec<String>,
 
-            if filtered.is_empty() {
-                println!("No installed packages found");
-                return Ok(());
-            }
+        /// sudo on the host needs a password (keyring or prompt)
+        #[arg(long)]
+        sudo_password: bool,
+    },
 
-            println!("📦 Installed packages ({}):\n", filtered.len());
+    /// Store a host's sudo password in the system keyring
+    SudoPassword {
+        /// Inventory name for the host
+        name: String,
+    },
 
-            for package in filtered {
-                if detailed {
-                    println!("Name: {}", package.package_name);
-                    println!("Box: {}", package.box_type);
-                    println!(
-                        "Version: {}",
-                        package.version.as_deref().unwrap_or("unknown")
-                    );
-                    println!(
-                        "Installed: {}",
-                        package.installed_at.format("%Y-%m-%d %H:%M:%S")
-                    );
-                    if let Some(source) = &package.source_url {
-                        println!("Source: {}", source);
-                    }
-                    println!();
-                } else {
-                    println!(
-                        "{} [{}] ({})",
-                        package.package_name,
-                        package.box_type,
-                        package.version.as_deref().unwrap_or("unknown")
-                    );
-                }
-            }
-        }
+    /// Store a WinRM host's login password in the system keyring
+    WinrmPassword {
+        /// Inventory name for the host
+        name: String,
+    },
 
-        Commands::History { action } => match action {
-            HistoryCommands::Show { limit } => {
-                let db = database::Database::new().await?;
-                let history = db.get_install_history(Some(limit)).await?;
+    /// Remove a host from the inventory
+    RemoveHost {
+        /// Inventory name for the host
+        name: String,
+    },
 
-                if history.is_empty() {
-                    println!("No installation history found");
-                    return Ok(());
-                }
+    /// List hosts with a per-tag summary
+    List {
+        /// Only hosts with all of these tags (role=web,env=prod)
+        #[arg(long)]
+        tags: Option<String>,
 
-                println!("📜 Installation history:\n");
+        /// Only hosts in this group (repeatable)
+        #[arg(long = "group")]
+        groups: Vec<String>,
+    },
 
-                for record in history {
-                    let status = match record.status {
-                        database::InstallStatus::Success => "✅ Installed",
-                        database::InstallStatus::Updated => "🔄 Updated",
-                        database::InstallStatus::Removed => "❌ Removed",
-                        database::InstallStatus::Failed => "💥 Failed",
+    /// Install a package on the selected hosts
+    Install {
+        /// Package name
+        package: String,
+
+        /// Only hosts with all of these tags (role=web,env=prod)
+        #[arg(long)]
+        tags: Option<String>,
+
+        /// Only hosts in this group (repeatable)
+        #[arg(long = "group")]
+        groups: Vec<String>,
+
+        /// Only these hosts (repeatable)
+        #[arg(long = "host")]
+        hosts: Vec<String>,
+
+        /// Confirm operations on hosts whose tag policy requires it
+        #[arg(long)]
+        confirm: bool,
+
+        /// Stream progress as JSON lines instead of the summary table
+        #[arg(long)]
+        events: bool,
+    },
+
+    /// Update a package on the selected hosts, one at a time, running health checks after each
+    Update {
+        /// Package name
+        package: String,
+
+        /// Only hosts with all of these tags (role=web,env=prod)
+        #[arg(long)]
+        tags: Option<String>,
+
+        /// Only hosts in this group (repeatable)
+        #[arg(long = "group")]
+        groups: Vec<String>,
+
+        /// Only these hosts (repeatable)
+        #[arg(long = "host")]
+        hosts: Vec<String>,
+
+        /// Confirm operations on hosts whose tag policy requires it
+        #[arg(long)]
+        confirm: bool,
+
+        /// Stream progress as JSON lines instead of the summary table
+        #[arg(long)]
+        events: bool,
+    },
+
+    /// Remove a package from the selected hosts
+    Remove {
+        /// Package name
+        package: String,
+
+        /// Only hosts with all of these tags (role=web,env=prod)
+        #[arg(long)]
+        tags: Option<String>,
+
+        /// Only hosts in this group (repeatable)
+        #[arg(long = "group")]
+        groups: Vec<String>,
+
+        /// Only these hosts (repeatable)
+        #[arg(long = "host")]
+        hosts: Vec<String>,
+
+        /// Confirm operations on hosts whose tag policy requires it
+        #[arg(long)]
+        confirm: bool,
+
+        /// Stream progress as JSON lines instead of the summary table
+        #[arg(long)]
+        events: bool,
+    },
+
+    /// Compare each host's packages to a manifest without changing anything
+    Drift {
+        /// Desired state manifest
+        #[arg(long)]
+        from: String,
+
+        /// Only hosts with all of these tags (role=web,env=prod)
+        #[arg(long)]
+        tags: Option<String>,
+
+        /// Only hosts in this group (repeatable)
+        #[arg(long = "group")]
+        groups: Vec<String>,
+
+        /// Only these hosts (repeatable)
+        #[arg(long = "host")]
+        hosts: Vec<String>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Require --confirm for hosts carrying a tag (or lift the requirement)
+    Policy {
+        /// Tag as key=value
+        tag: String,
+
+        /// Require --confirm for matching hosts
+        #[arg(long)]
+        require_confirm: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeyCommands {
+    /// List trusted keys
+    List {
+        /// Print keys as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Trust the keys from an https URL, a key file, or a key server (by fingerprint)
+    Add {
+        /// URL, file path or fingerprint
+        source: String,
+    },
+
+    /// Stop trusting a key
+    Remove {
+        /// Fingerprint or long key ID
+        key: String,
+    },
+
+    /// Fetch keys again from where they came from, for new expiry dates and revocations
+    Refresh,
+}
+
+#[derive(Subcommand)]
+enum ProjectCommands {
+    /// Show the project omni found and whether its config applies
+    Status,
+
+    /// Apply the project's config from now on (until it changes)
+    Allow,
+
+    /// Stop applying the project's config
+    Deny,
+}
+
+#[derive(Subcommand)]
+enum CredentialCommands {
+    /// Store a secret, asked for on the terminal or read from stdin
+    Set {
+        /// Name that config values refer to as keyring:<name>
+        name: String,
+    },
+
+    /// Print a stored secret
+    Get {
+        /// Credential name
+        name: String,
+    },
+
+    /// Delete a stored secret
+    Remove {
+        /// Credential name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum UserCommands {
+    /// List API users and their roles
+    List {
+        /// Print users as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Add an API user and print their token
+    Add {
+        /// User name
+        name: String,
+
+        /// What the user may do
+        #[arg(long, value_enum, default_value = "viewer")]
+        role: authorization::Role,
+    },
+
+    /// Remove an API user; their token stops working at once
+    Remove {
+        /// User name
+        name: String,
+    },
+
+    /// Change an API user's role
+    SetRole {
+        /// User name
+        name: String,
+
+        #[arg(value_enum)]
+        role: authorization::Role,
+    },
+
+    /// Show what each role may do
+    Roles,
+}
+
+#[derive(Subcommand)]
+enum WatchCommands {
+    /// Add a package to the watchlist (it does not need to be installed)
+    Add {
+        /// Package name
+        package: String,
+
+        /// Package box type (first backend that knows the package by default)
+        #[arg(long)]
+        box_type: Option<String>,
+    },
+
+    /// Remove a package from the watchlist
+    Remove {
+        /// Package name
+        package: String,
+
+        /// Only remove the entry for this box type
+        #[arg(long)]
+        box_type: Option<String>,
+    },
+
+    /// List watched packages
+    List,
+
+    /// Check watched packages for new versions and advisories
+    Check {
+        /// Print changes as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommands {
+    /// Show recorded audit entries, newest first
+    Show {
+        /// Only entries recorded for this user
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Only entries of this severity
+        #[arg(long, value_enum)]
+        severity: Option<audit::AuditSeverity>,
+
+        /// Only entries of this kind
+        #[arg(long, value_enum)]
+        event_type: Option<audit::AuditEventType>,
+
+        /// Only entries for this package
+        #[arg(long)]
+        package: Option<String>,
+
+        /// Only entries recorded on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+
+        /// Only entries recorded up to this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_name = "DATE")]
+        until: Option<String>,
+
+        /// Number of entries to show
+        #[arg(short, long, default_value = "50")]
+        limit: i64,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Count audit entries by event type and severity
+    Stats {
+        /// Only entries recorded on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Send a test mail with the configured SMTP settings (audit.email)
+    TestEmail {
+        /// Send to this address instead of the configured recipients (repeatable)
+        #[arg(long = "to", value_name = "ADDRESS")]
+        to: Vec<String>,
+    },
+
+    /// Post a test alert to the configured chat channels (audit.chat)
+    TestChat {
+        /// Post to this webhook instead of the configured channels
+        #[arg(long, value_name = "URL")]
+        url: Option<String>,
+
+        /// Message format for --url
+        #[arg(long, value_enum, default_value_t = config::ChatFormat::Slack)]
+        format: config::ChatFormat,
+    },
+
+    /// Forward a test entry to the configured syslog collectors (audit.syslog)
+    TestSyslog {
+        /// Send to this collector instead of the configured ones
+        #[arg(long, value_name = "HOST")]
+        host: Option<String>,
+
+        /// Port for --host; defaults to 514, or 6514 over TLS
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// Transport for --host
+        #[arg(long, value_enum, default_value_t = config::SyslogTransport::Udp)]
+        transport: config::SyslogTransport,
+    },
+
+    /// Write audit entries for a SIEM (CEF, LEEF or JSON lines)
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = siem::ExportFormat::Jsonl)]
+        format: siem::ExportFormat,
+
+        /// Only entries recorded on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+
+    /// Check the audit log's hash chain for changed, deleted or inserted entries
+    Verify {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum LogsCommands {
+    /// Delete log files and audit entries past their retention (logging, audit.retention_days)
+    Prune {
+        /// Only list what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum PermissionsCommands {
+    /// Give files back to their user and narrow modes wider than the umask
+    Repair {
+        /// Only list what would be fixed
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ReportCommands {
+    /// Summarize installs, updates, fixed CVEs, failures and disk usage
+    Digest {
+        /// Span of time up to now to cover
+        #[arg(long, value_enum, default_value_t = config::DigestPeriod::Week)]
+        period: config::DigestPeriod,
+
+        #[arg(long, value_enum, default_value_t = digest::ReportFormat::Markdown)]
+        format: digest::ReportFormat,
+
+        /// Write the report to this file instead of printing it
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+
+        /// Also mail it as HTML via audit.email, to daemon.digest.to if set
+        #[arg(long)]
+        email: bool,
+    },
+
+    /// Libraries bundled inside snaps, flatpaks and AppImages, with their versions
+    Vendored {
+        /// Only this snap, flatpak or AppImage
+        package: Option<String>,
+
+        #[arg(long, value_enum, default_value_t = vendored::VendoredFormat::Text)]
+        format: vendored::VendoredFormat,
+
+        /// Write the report to this file instead of printing it
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RepositoryCommands {
+    /// Add a new repository
+    Add {
+        /// Repository URL or identifier
+        repository: String,
+
+        /// Repository type (ppa, rpm, deb, etc.)
+        #[arg(long)]
+        repo_type: Option<String>,
+
+        /// Signing key: an https URL, a key file or a fingerprint (added to omni keys)
+        #[arg(long)]
+        key_url: Option<String>,
+    },
+
+    /// Remove a repository
+    Remove {
+        /// Repository identifier
+        repository: String,
+    },
+
+    /// List configured repositories
+    List,
+
+    /// Refresh repository metadata
+    Refresh,
+
+    /// Measure configured mirrors and optionally switch to the fastest
+    Benchmark {
+        /// Only benchmark this backend (apt, dnf or pacman)
+        #[arg(long)]
+        box_type: Option<String>,
+
+        /// Additional mirror to compare against the configured ones (repeatable)
+        #[arg(long = "mirror", value_name = "URL")]
+        mirrors: Vec<String>,
+
+        /// Rewrite the mirror configuration to use the fastest mirrors
+        #[arg(long)]
+        apply: bool,
+
+        /// Number of mirrors to keep when applying (pacman)
+        #[arg(long, default_value = "5")]
+        top: usize,
+
+        /// Print results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl Commands {
+    /// What the command would change, or `None` if it only reads. Used to
+    /// reject the command up front in read-only mode.
+    fn mutation(&self) -> Option<&'static str> {
+        match self {
+            Commands::Install { .. } => Some("installing packages"),
+            Commands::Sync { dry_run: false, .. } => Some("installing packages"),
+            Commands::Project {
+                action: ProjectCommands::Allow | ProjectCommands::Deny,
+            } => Some("changing allowed project configs"),
+            Commands::Remove { .. } => Some("removing packages"),
+            Commands::Update {
+                package,
+                all,
+                security_only,
+                refresh,
+                review,
+                ..
+            } if package.is_some() || *all || *security_only || *refresh || *review => {
+                Some("updating packages")
+            }
+            Commands::Pin {
+                package: Some(_), ..
+            } => Some("pinning a package"),
+            Commands::Unpin { .. } => Some("unpinning a package"),
+            Commands::Clean { .. } => Some("cleaning caches"),
+            Commands::Autoremove { dry_run: false, .. } => Some("removing orphaned packages"),
+            Commands::Downgrade { list: false, .. } => Some("downgrading a package"),
+            Commands::History {
+                action: HistoryCommands::Undo,
+            } => Some("undoing an install"),
+            Commands::History {
+                action: HistoryCommands::Rollback { dry_run: false, .. },
+            } => Some("rolling back the install history"),
+            Commands::History {
+                action: HistoryCommands::Import { .. },
+            } => Some("importing install history"),
+            Commands::Snapshot { action } => match action {
+                SnapshotCommands::Create { .. } => Some("creating a snapshot"),
+                SnapshotCommands::Revert { .. } => Some("reverting a snapshot"),
+                SnapshotCommands::Prune { dry_run: false, .. } => {
+                    Some("pruning snapshots")
+                }
+                SnapshotCommands::List | SnapshotCommands::Prune { .. } => None,
+            },
+            Commands::Transaction { action } => match action {
+                TransactionCommands::Plan { .. }
+                | TransactionCommands::List { .. }
+                | TransactionCommands::Show { .. } => None,
+                TransactionCommands::Begin | TransactionCommands::Add { .. } => {
+                    Some("staging a transaction")
+                }
+                TransactionCommands::Commit { .. } => Some("running a transaction"),
+                TransactionCommands::Resume { .. } => Some("resuming a transaction"),
+                TransactionCommands::Rollback { .. } => Some("rolling back a transaction"),
+            },
+            Commands::Gui => Some("starting the GUI"),
+            Commands::Config { action } => match action {
+                ConfigCommands::Show
+                | ConfigCommands::Validate { .. }
+                | ConfigCommands::Encrypt
+                | ConfigCommands::Key { import: false } => None,
+                ConfigCommands::Key { import: true } => Some("changing the config key"),
+                ConfigCommands::Edit | ConfigCommands::Reset => Some("changing the configuration"),
+            },
+            Commands::Hardware { action } => match action {
+                HardwareCommands::Detect => None,
+                HardwareCommands::Install | HardwareCommands::Vendor { .. } => {
+                    Some("installing drivers")
+                }
+            },
+            Commands::Repository { action } => match action {
+                RepositoryCommands::List | RepositoryCommands::Benchmark { apply: false, .. } => {
+                    None
+                }
+                RepositoryCommands::Benchmark { apply: true, .. } => Some("rewriting mirror lists"),
+                RepositoryCommands::Add { .. }
+                | RepositoryCommands::Remove { .. }
+                | RepositoryCommands::Refresh => Some("changing repositories"),
+            },
+            Commands::Fleet { action } => match action {
+                FleetCommands::List { .. } | FleetCommands::Drift { .. } => None,
+                FleetCommands::Install { .. }
+                | FleetCommands::Update { .. }
+                | FleetCommands::Remove { .. } => Some("changing packages on fleet hosts"),
+                _ => Some("changing the fleet inventory"),
+            },
+            Commands::Keys { action } => match action {
+                KeyCommands::List { .. } => None,
+                KeyCommands::Add { .. } | KeyCommands::Remove { .. } | KeyCommands::Refresh => {
+                    Some("changing trusted keys")
+                }
+            },
+            Commands::Credentials { action } => match action {
+                CredentialCommands::Get { .. } => None,
+                CredentialCommands::Set { .. } | CredentialCommands::Remove { .. } => {
+                    Some("changing stored credentials")
+                }
+            },
+            Commands::Users { action } => match action {
+                UserCommands::List { .. } | UserCommands::Roles => None,
+                UserCommands::Add { .. }
+                | UserCommands::Remove { .. }
+                | UserCommands::SetRole { .. } => Some("changing API users"),
+            },
+            Commands::Watch { action } => match action {
+                WatchCommands::Add { .. } | WatchCommands::Remove { .. } => {
+                    Some("changing the watchlist")
+                }
+                WatchCommands::List | WatchCommands::Check { .. } => None,
+            },
+            Commands::Logs {
+                action: LogsCommands::Prune { dry_run: false },
+            } => Some("pruning logs"),
+            Commands::Permissions {
+                action: PermissionsCommands::Repair { dry_run: false },
+            } => Some("repairing file permissions"),
+            Commands::Daemon { .. } => Some("running the update daemon"),
+            Commands::Schedule { action } => match action {
+                ScheduleCommands::InstallUnits { dry_run: true, .. } => None,
+                ScheduleCommands::InstallUnits { .. } => Some("installing scheduled tasks"),
+                ScheduleCommands::RemoveUnits { .. } => Some("removing scheduled tasks"),
+            },
+            Commands::Dotfiles { dry_run: true, .. } => None,
+            Commands::Dotfiles { .. } => Some("applying dotfiles"),
+            Commands::DistUpgrade { check: true, .. }
+            | Commands::DistUpgrade { verify: true, .. } => None,
+            Commands::DistUpgrade { .. } => Some("upgrading the distribution"),
+            Commands::SelfUpdate { check: true, .. } => None,
+            Commands::SelfUpdate { .. } => Some("replacing the omni binary"),
+            _ => None,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Show welcome banner for interactive commands
+    if !cli.verbose && matches!(cli.command, Commands::Search { .. } | Commands::Install { .. } | Commands::Gui) {
+        println!("{}", OmniBranding::welcome_banner());
+    }
+
+    let error_format = cli.error_format;
+
+    let result = run(cli).await;
+    // Spans are exported in batches; send the rest before exit() skips destructors
+    logging::shutdown_tracing();
+
+    if let Err(e) = result {
+        let e = access_control::explain(e);
+        let report = ErrorReport::from_error(&e);
+        match error_format {
+            ErrorFormat::Json => eprintln!("{}", serde_json::to_string(&report)?),
+            ErrorFormat::Human => {
+                error!("Application error: {}", e);
+                if report.code == "OMNI_SEC_003" {
+                    for suggestion in &report.suggestions {
+                        eprintln!("💡 {}", suggestion);
+                    }
+                }
+            }
+        }
+        std::process::exit(report.exit_code);
+    }
+
+    Ok(())
+}
+
+/// `omni help`: a topic, the help of a (nested) command, or the overview
+/// with the list of topics
+fn show_help(words: &[String]) -> Result<()> {
+    use std::io::IsTerminal;
+
+    let mut command = Cli::command();
+    command.build();
+    if let [word] = words {
+        if let Some(topic) = help::find(word) {
+            print!(
+                "{}",
+                help::render(topic.body, std::io::stdout().is_terminal())
+            );
+            return Ok(());
+        }
+    }
+
+    // Logging is not set up yet, so report unknown words the way clap does
+    let mut target = command.clone();
+    for word in words {
+        target = match target.find_subcommand(word) {
+            Some(sub) => sub.clone(),
+            None => command
+                .error(
+                    clap::error::ErrorKind::InvalidSubcommand,
+                    format!(
+                        "no help topic or command '{}'; 'omni help' lists them",
+                        words.join(" ")
+                    ),
+                )
+                .exit(),
+        };
+    }
+    target.print_help()?;
+    if words.is_empty() {
+        println!("\n{}", help::topic_list());
+    }
+    Ok(())
+}
+
+async fn run(mut cli: Cli) -> Result<()> {
+    cli.mock |= cli.mock_fixture.is_some();
+    if cli.mock {
+        mock::init(cli.mock_fixture.as_deref())?;
+    }
+
+    // Help needs neither the config nor omni's state
+    match &cli.command {
+        Commands::Help { topic } => return show_help(topic),
+        Commands::Man { out_dir } => {
+            let pages = help::write_man_pages(Cli::command(), out_dir)?;
+            println!(
+                "📖 Wrote {} man pages to {}",
+                pages.len(),
+                out_dir.display()
+            );
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Read-only mode and the scope have to be set before loading, which may
+    // write a default config
+    if cli.read_only {
+        OmniConfig::enable_read_only();
+    }
+    if cli.system {
+        OmniConfig::enable_system_scope()?;
+    }
+    config::set_cli_settings(&cli.settings)?;
+    // Repairing is the way out when the ownership check below fails, so it
+    // runs before it, without logging
+    if let Commands::Permissions {
+        action: PermissionsCommands::Repair { dry_run },
+    } = cli.command
+    {
+        let result = match cli.command.mutation() {
+            Some(mutation) => config::ensure_writable(mutation),
+            None => Ok(()),
+        }
+        .and_then(|_| repair_permissions(dry_run));
+        if let Err(e) = &result {
+            eprintln!("❌ {}", e);
+        }
+        return result;
+    }
+    // Has to work on a config that does not load
+    if let Commands::Config {
+        action: ConfigCommands::Validate { file },
+    } = &cli.command
+    {
+        return validate_config(file.clone());
+    }
+
+    // Logging is not up yet, and must not be: it writes to the directories checked
+    if let Err(e) = OmniConfig::check_state_ownership() {
+        eprintln!("❌ {}", e);
+        return Err(e);
+    }
+
+    // Load configuration
+    let config = OmniConfig::load()?;
+    if config.general.read_only {
+        OmniConfig::enable_read_only();
+    }
+
+    // Initialize logging
+    logging::init_logging(&config)?;
+
+    if let Some(mutation) = cli.command.mutation() {
+        config::ensure_writable(mutation)?;
+        // Root can work on its own state or the machine's; say which one
+        if OmniConfig::scope() == config::StateScope::System
+            || privilege_manager::PrivilegeManager::is_root()
+        {
+            eprintln!("📂 {}", OmniConfig::scope_summary());
+        }
+    }
+
+    // Serialize package changes with other omni processes, including the daemon.
+    // Long-running servers take the lock per operation instead.
+    let operation_lock = match cli.command {
+        Commands::Gui
+        | Commands::Serve { .. }
+        | Commands::Web { .. }
+        | Commands::Daemon { .. } => None,
+        _ if cli.mock || cli.command.mutation().is_none() => None,
+        _ => Some(lock::OperationLock::acquire()?),
+    };
+    if operation_lock.is_some() && !matches!(cli.command, Commands::Transaction { .. }) {
+        transaction::warn_interrupted().await;
+    }
+
+    // Handle the command
+    let result = handle_command(cli, config).await;
+    if result.is_ok() && operation_lock.is_some() {
+        // Used space after package changes, for the disk changes in digests
+        if let Ok(db) = database::Database::new().await {
+            digest::sample_disk_usage(&db).await;
+        }
+    }
+    result
+}
+
+async fn handle_command(cli: Cli, config: OmniConfig) -> Result<()> {
+    match cli.command {
+        Commands::Install {
+            package,
+            from,
+            box_type,
+            url,
+            accept_licenses,
+            channel,
+            skip_dotfiles,
+            resolve_deps,
+        } => {
+            let mut brain = OmniBrain::new_with_mock(cli.mock)
+                .with_license_acceptance(accept_licenses)
+                .with_channel(channel.as_deref().map(channels::Channel::parse));
+
+            if let Some(manifest_path) = from {
+                let manifest = OmniManifest::from_file(&manifest_path)?;
+                let dotfiles_config = manifest
+                    .dotfiles
+                    .clone()
+                    .unwrap_or_else(|| config.dotfiles.clone());
+                brain.install_from_manifest(manifest).await?;
+
+                if !skip_dotfiles {
+                    if let Some(argv) =
+                        dotfiles::command_line(&dotfiles_config, Some(&manifest_path))?
+                    {
+                        dotfiles::apply(&argv, cli.mock)?;
+                    }
+                }
+            } else if let Some(package_name) = package {
+                if let Some(url) = url {
+                    // AppImage installation
+                    if cli.mock {
+                        println!(
+                            "🎭 [MOCK] Would install AppImage {} from {}",
+                            package_name, url
+                        );
+                    } else {
+                        access_control::preflight("appimage")?;
+                        let db = database::Database::new().await.ok();
+                        let operation = policy::Operation::Install {
+                            package: &package_name,
+                            box_type: Some("appimage"),
+                            source: Some(&url),
+                        };
+                        policy::enforce(&config, db.as_ref(), &operation).await?;
+                        boxes::appimage::install_appimage(&url, &package_name).await?;
+                        if let Some(db) = &db {
+                            let artifact =
+                                artifacts::capture(&package_name, "appimage", None, Some(&url));
+                            let _ = db.record_artifact(&artifact).await;
+                        }
+                        println!("✅ Successfully installed AppImage {}", package_name);
+                    }
+                } else if resolve_deps && !cli.mock {
+                    install_resolved(&package_name, box_type.as_deref()).await?;
+                } else {
+                    brain.install(&package_name, box_type.as_deref()).await?;
+                }
+            } else {
+                return Err(anyhow::anyhow!(
+                    "Please specify a package name or manifest file"
+                ));
+            }
+        }
+
+        Commands::Sync {
+            dry_run,
+            skip_dotfiles,
+        } => {
+            let project = project::Project::discover()
+                .filter(|project| project.manifest_path().is_file())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "No {}/manifest.yaml in this directory or its parents",
+                        project::PROJECT_DIR
+                    )
+                })?;
+            let manifest_path = project.manifest_path().display().to_string();
+            let mut manifest = OmniManifest::from_file(&manifest_path)?;
+            println!("📁 {}", manifest_path);
+
+            let installed = if cli.mock {
+                mock::backend().installed()
+            } else {
+                database::Database::new()
+                    .await?
+                    .get_installed_packages()
+                    .await?
+            };
+            manifest.apps.retain(|app| {
+                !installed
+                    .iter()
+                    .any(|r| r.package_name == app.name && r.box_type == app.box_type)
+            });
+
+            let dotfiles_config = manifest
+                .dotfiles
+                .clone()
+                .unwrap_or_else(|| config.dotfiles.clone());
+            if manifest.apps.is_empty() {
+                println!("✅ Everything {} lists is installed", manifest.project);
+            } else {
+                println!("📦 To install:");
+                for app in &manifest.apps {
+                    println!("  {} ({})", app.name, app.box_type);
+                }
+                if dry_run {
+                    return Ok(());
+                }
+
+                let mut brain = OmniBrain::new_with_mock(cli.mock);
+                brain.install_from_manifest(manifest).await?;
+            }
+
+            if !dry_run && !skip_dotfiles {
+                if let Some(argv) =
+                    dotfiles::command_line(&dotfiles_config, Some(&manifest_path))?
+                {
+                    dotfiles::apply(&argv, cli.mock)?;
+                }
+            }
+        }
+
+        Commands::Project { action } => {
+            let project = project::Project::discover().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No {} directory with config.yaml or manifest.yaml here or in the parents",
+                    project::PROJECT_DIR
+                )
+            })?;
+            let store = project::TrustStore::open()?;
+            let config_path = project.config_path();
+
+            match action {
+                ProjectCommands::Status => {
+                    println!("📁 Project {}", project.root().display());
+                    if project.manifest_path().is_file() {
+                        println!("📦 Manifest {} (omni sync)", project.manifest_path().display());
+                    }
+                    if !config_path.is_file() {
+                        println!("⚙️  No project config");
+                    } else if store.is_allowed(&project)? {
+                        println!("⚙️  {} applies", config_path.display());
+                    } else {
+                        println!(
+                            "⚙️  {} is ignored; review it and run 'omni project allow'",
+                            config_path.display()
+                        );
+                    }
+                }
+
+                ProjectCommands::Allow => {
+                    if !config_path.is_file() {
+                        return Err(anyhow::anyhow!("{} does not exist", config_path.display()));
+                    }
+                    store.allow(&project)?;
+                    println!("✅ {} applies from now on", config_path.display());
+                }
+
+                ProjectCommands::Deny => {
+                    if store.deny(&project)? {
+                        println!("✅ {} no longer applies", config_path.display());
+                    } else {
+                        println!("{} was not allowed", config_path.display());
+                    }
+                }
+            }
+        }
+
+        Commands::Remove { package, box_type } => {
+            let reports =
+                reverse_dependencies(&package, box_type.as_deref(), true, cli.mock).await?;
+            if !reports.is_empty() {
+                println!("⚠️  Installed packages depend on {}:", package);
+                print_reverse_dependencies(&reports);
+                if std::io::IsTerminal::is_terminal(&std::io::stdin())
+                    && !interactive::InteractivePrompts::new()
+                        .get_confirmation(&format!("Remove {} anyway?", package), false)?
+                {
+                    println!("❌ Removal cancelled");
+                    return Ok(());
+                }
+            }
+
+            let mut brain = OmniBrain::new_with_mock(cli.mock);
+            brain.remove(&package, box_type.as_deref()).await?;
+        }
+
+        Commands::Search { query, limit } => {
+            let results = if cli.mock {
+                mock::backend().search(&query)
+            } else {
+                let engine = SearchEngine::new().await?;
+                let results = engine.search_all(&query).await?;
+                refresh_stale_index(&engine).await;
+                results
+            };
+
+            println!("🔍 Search results for '{}':\n", query);
+
+            for (i, result) in results.iter().take(limit).enumerate() {
+                let status = if result.installed {
+                    "✅ Installed"
+                } else {
+                    "  Available"
+                };
+                let origin = match &result.architecture {
+                    Some(arch) => format!("{}, {}", result.box_type, arch),
+                    None => result.box_type.clone(),
+                };
+                println!("{}. {} [{}] {}", i + 1, result.name, origin, status);
+
+                if let Some(desc) = &result.description {
+                    println!("   {}", desc);
+                }
+                println!();
+            }
+
+            if results.len() > limit {
+                println!("... and {} more results", results.len() - limit);
+            }
+        }
+
+        Commands::Index { action } => {
+            if cli.mock {
+                println!("🎭 [MOCK] Mock searches read the mock universe; there is no index");
+                return Ok(());
+            }
+            let engine = SearchEngine::new().await?;
+            match action {
+                IndexCommands::Refresh { box_type } => {
+                    let Some(_lock) = lock::OperationLock::try_acquire_index()? else {
+                        println!("⏳ Another omni process is already refreshing the search index");
+                        return Ok(());
+                    };
+                    let box_types: Vec<String> = match box_type {
+                        Some(box_type) => {
+                            InputValidator::validate_box_type(&box_type)?;
+                            vec![box_type]
+                        }
+                        None => search::indexed_boxes()
+                            .into_iter()
+                            .map(str::to_string)
+                            .collect(),
+                    };
+                    if box_types.is_empty() {
+                        println!("ℹ️  No backend here has a catalog to index");
+                        return Ok(());
+                    }
+                    let refreshed = engine.refresh_index(&box_types).await?;
+                    for (box_type, count) in &refreshed {
+                        println!("✅ Indexed {} {} packages", count, box_type);
+                    }
+                    if refreshed.len() < box_types.len() {
+                        return Err(anyhow::anyhow!(
+                            "Some catalogs could not be read; run with --verbose for details"
+                        ));
+                    }
+                }
+
+                IndexCommands::Status => {
+                    let max_age = OmniConfig::load()
+                        .unwrap_or_default()
+                        .search
+                        .index_max_age_hours;
+                    let sources = engine.index_sources().await?;
+                    let stale = engine.stale_index_boxes(max_age).await?;
+                    if sources.is_empty() {
+                        println!("📭 No catalogs indexed yet; run 'omni index refresh'");
+                    }
+                    for source in &sources {
+                        let is_stale = stale
+                            .iter()
+                            .any(|(box_type, _)| *box_type == source.box_type);
+                        let state = if is_stale { "stale" } else { "fresh" };
+                        println!(
+                            "📚 {}: {} packages, read {} ({})",
+                            source.box_type,
+                            source.package_count,
+                            source.refreshed_at.format("%Y-%m-%d %H:%M UTC"),
+                            state
+                        );
+                    }
+                    for (box_type, _) in stale.iter().filter(|(_, at)| at.is_none()) {
+                        println!("❔ {}: not indexed", box_type);
+                    }
+                    if max_age == 0 {
+                        println!("ℹ️  search.index_max_age_hours is 0; searches ask the backends");
+                    }
+                }
+            }
+        }
+
+        Commands::Info {
+            package,
+            box_type,
+            provenance: true,
+            ..
+        } => {
+            let db = database::Database::new().await?;
+            let records: Vec<_> = db
+                .get_package_history(&package)
+                .await?
+                .into_iter()
+                .filter(|r| box_type.as_deref().map_or(true, |bt| r.box_type == bt))
+                .collect();
+
+            if records.is_empty() {
+                println!("❌ No install records found for {}", package);
+                return Ok(());
+            }
+
+            println!("🔎 Provenance for {}:\n", package);
+
+            for record in records {
+                let status = match record.status {
+                    database::InstallStatus::Success => "✅ Installed",
+                    database::InstallStatus::Updated => "🔄 Updated",
+                    database::InstallStatus::Downgraded => "⏪ Downgraded",
+                    database::InstallStatus::Removed => "❌ Removed",
+                    database::InstallStatus::Failed => "💥 Failed",
+                };
+
+                println!(
+                    "{} {} [{}] {} - {}",
+                    record.installed_at.format("%Y-%m-%d %H:%M:%S"),
+                    record.package_name,
+                    record.box_type,
+                    record.version.as_deref().unwrap_or("unknown version"),
+                    status
+                );
+                println!(
+                    "   Repository: {}",
+                    record.repository.as_deref().unwrap_or("unknown")
+                );
+                println!(
+                    "   Mirror:     {}",
+                    record
+                        .mirror_url
+                        .as_deref()
+                        .or(record.source_url.as_deref())
+                        .unwrap_or("unknown")
+                );
+                if let Some(channel) = &record.channel {
+                    println!("   Channel:    {}", channel);
+                }
+            }
+        }
+
+        Commands::Info {
+            package,
+            box_type,
+            changelog,
+            ..
+        } => {
+            let search_engine = SearchEngine::new().await?;
+
+            if let Some(box_type) = box_type {
+                if let Some(info) = search_engine.get_package_info(&package, &box_type).await? {
+                    println!("{}", info);
+                    if changelog {
+                        print_changelog(&box_type, &package);
+                    }
+                } else {
+                    println!("❌ Package information not found");
+                }
+            } else {
+                // Try all available box types
+                let box_types = ["apt", "dnf", "pacman", "snap", "flatpak"];
+                let mut found = false;
+
+                for bt in &box_types {
+                    if distro::command_exists(bt) {
+                        if let Some(info) = search_engine.get_package_info(&package, bt).await? {
+                            println!("📦 Information from {} box:\n{}\n", bt, info);
+                            if changelog {
+                                print_changelog(bt, &package);
+                            }
+                            found = true;
+                        }
+                    }
+                }
+
+                if !found {
+                    println!("❌ Package information not found in any available box");
+                }
+            }
+        }
+
+        Commands::Files {
+            package,
+            box_type,
+            tree,
+        } => {
+            InputValidator::validate_package_name(&package)?;
+            let search_engine = SearchEngine::new().await?;
+            let owned = search_engine
+                .list_package_files(&package, box_type.as_deref())
+                .await?;
+
+            if tree {
+                print!("{}", search::render_file_tree(&owned.files));
+            } else {
+                for file in &owned.files {
+                    println!("{}", file);
+                }
+            }
+            eprintln!(
+                "📦 {} files from {} ({})",
+                owned.files.len(),
+                owned.package,
+                owned.box_type
+            );
+        }
+
+        Commands::Why { package, box_type } => {
+            InputValidator::validate_package_name(&package)?;
+            if let Some(bt) = &box_type {
+                InputValidator::validate_box_type(bt)?;
+            }
+            let explanation = if cli.mock {
+                why::explain_mock(&package)?
+            } else {
+                let db = database::Database::new().await?;
+                why::explain(&db, &package, box_type.as_deref()).await?
+            };
+
+            println!(
+                "📦 {} [{}] was {}",
+                explanation.package, explanation.box_type, explanation.reason
+            );
+            if let Some(record) = &explanation.record {
+                println!(
+                    "   Recorded by omni on {}{}",
+                    record.installed_at.format("%Y-%m-%d %H:%M"),
+                    record
+                        .version
+                        .as_deref()
+                        .map(|v| format!(" (version {})", v))
+                        .unwrap_or_default()
+                );
+            }
+            if explanation.reason == why::InstallReason::Dependency {
+                if explanation.chain.is_empty() {
+                    println!(
+                        "   No explicitly installed package needs it; 'omni autoremove' may remove it"
+                    );
+                } else {
+                    println!("   Needed by: {}", explanation.chain.join(" ← "));
+                }
+            }
+        }
+
+        Commands::Rdepends {
+            package,
+            box_type,
+            installed,
+        } => {
+            InputValidator::validate_package_name(&package)?;
+            if let Some(bt) = &box_type {
+                InputValidator::validate_box_type(bt)?;
+            }
+            let reports =
+                reverse_dependencies(&package, box_type.as_deref(), installed, cli.mock).await?;
+
+            if reports.is_empty() {
+                println!(
+                    "No {}packages depend on {}",
+                    if installed { "installed " } else { "" },
+                    package
+                );
+            } else {
+                println!("🔗 Packages depending on {}:", package);
+                print_reverse_dependencies(&reports);
+            }
+        }
+
+        Commands::Owns { path } => {
+            let search_engine = SearchEngine::new().await?;
+            let owners = search_engine.find_file_owners(&path).await?;
+
+            if owners.is_empty() {
+                return Err(anyhow::anyhow!("No installed package owns {}", path));
+            }
+            for owner in &owners {
+                println!(
+                    "📦 {} is owned by {} ({})",
+                    owner.path, owner.package, owner.box_type
+                );
+            }
+        }
+
+        Commands::Update {
+            package,
+            all,
+            refresh,
+            yes,
+            now,
+            full_sync,
+            review,
+            force_critical,
+            security_only,
+        } => {
+            if cli.mock {
+                return mock_update(package.as_deref(), all, security_only);
+            }
+            if package.is_some() || all || review || security_only {
+                lifecycle::warn_or_refuse(&config.release_support, "updating packages").await?;
+            }
+
+            let confirm = config.general.confirm_installs && !yes;
+            let window_settings = config.daemon.maintenance.clone();
+            let partial_policy = config.policies.partial_upgrades;
+            let auto_apply = config
+                .policies
+                .unattended
+                .max_impact
+                .unwrap_or(config::Impact::Minor);
+            let vulnerability_settings = config.vulnerabilities.clone();
+            let update_manager = UpdateManager::new(config).await?;
+
+            if refresh {
+                update_manager.refresh_repositories().await?;
+            }
+
+            let check_updates = || async {
+                let mut candidates = update_manager.check_updates().await?;
+                if security_only {
+                    candidates.retain(|c| c.advisory.is_some());
+                }
+                Ok::<_, anyhow::Error>(candidates)
+            };
+
+            if review {
+                let candidates = check_updates().await?;
+                if candidates.is_empty() {
+                    println!("{}", up_to_date(security_only));
+                    return Ok(());
+                }
+                let selected = review_by_impact(&update_manager, &candidates, auto_apply).await?;
+                let selected =
+                    confirm_critical_updates(&update_manager, selected, force_critical).await?;
+                if selected.is_empty() {
+                    println!("❌ No updates selected");
+                } else if let Some(rest) =
+                    settle_partial_upgrade(&update_manager, &selected, partial_policy, full_sync)
+                        .await?
+                {
+                    update_manager.update_candidates(&rest).await?;
+                }
+            } else if all || security_only {
+                let candidates = check_updates().await?;
+                if candidates.is_empty() {
+                    println!("{}", up_to_date(security_only));
+                } else if !now && queue_for_window(&window_settings, &candidates)? {
+                    let flag = if security_only { "--security-only" } else { "--all" };
+                    println!("   Run 'omni update {} --now' to apply them immediately", flag);
+                } else if !confirm || review_updates(&update_manager, &candidates)? {
+                    let candidates =
+                        confirm_critical_updates(&update_manager, candidates, force_critical)
+                            .await?;
+                    if let Some(rest) = settle_partial_upgrade(
+                        &update_manager,
+                        &candidates,
+                        partial_policy,
+                        full_sync,
+                    )
+                    .await?
+                    {
+                        update_manager.update_candidates(&rest).await?;
+                    }
+                } else {
+                    println!("❌ Update cancelled");
+                }
+            } else if let Some(package_name) = package {
+                let pinned = update_manager.list_pinned().await?;
+                if let Some(pin) = pinned
+                    .iter()
+                    .find(|p| p.package_name == package_name && p.constraint.is_none())
+                {
+                    println!(
+                        "📌 {} is pinned [{}]; run 'omni unpin {}' to allow updates",
+                        package_name, pin.box_type, package_name
+                    );
+                    return Ok(());
+                }
+
+                let candidates = update_manager.check_updates().await?;
+                if let Some(candidate) = candidates.iter().find(|c| c.package_name == package_name)
+                {
+                    let candidates = confirm_critical_updates(
+                        &update_manager,
+                        vec![candidate.clone()],
+                        force_critical,
+                    )
+                    .await?;
+                    if let Some(rest) = settle_partial_upgrade(
+                        &update_manager,
+                        &candidates,
+                        partial_policy,
+                        full_sync,
+                    )
+                    .await?
+                    {
+                        for candidate in &rest {
+                            update_manager.update_package(candidate).await?;
+                        }
+                    }
+                } else {
+                    println!("✅ Package {} is already up to date", package_name);
+                }
+            } else {
+                let candidates = update_manager.check_updates().await?;
+
+                if candidates.is_empty() {
+                    println!("✅ All packages are up to date");
+                } else {
+                    println!("📦 Available updates:");
+                    for candidate in &candidates {
+                        println!(
+                            "  {}{} [{}]: {} -> {}{}",
+                            if candidate.advisory.is_some() {
+                                "🛡️  "
+                            } else {
+                                ""
+                            },
+                            candidate.package_name,
+                            candidate.box_type,
+                            candidate.current_version.as_deref().unwrap_or("unknown"),
+                            candidate.available_version.as_deref().unwrap_or("latest"),
+                            candidate
+                                .advisory
+                                .as_ref()
+                                .map(|a| format!(" ({})", a))
+                                .unwrap_or_default()
+                        );
+                    }
+                    println!("\nRun 'omni update --all' to update all packages, or 'omni update --review'");
+                    println!("to go through them by impact");
+                    if candidates.iter().any(|c| c.advisory.is_some()) {
+                        println!("Run 'omni update --security-only' to apply only the 🛡️  security updates");
+                    }
+                }
+                warn_vulnerable(&vulnerability_settings).await;
+            }
+        }
+
+        Commands::Pin {
+            package,
+            box_type,
+            reason,
+        } => {
+            let update_manager = UpdateManager::new(config).await?;
+
+            if let Some(package_name) = package {
+                InputValidator::validate_package_name(&package_name)?;
+
+                let box_type = match box_type {
+                    Some(bt) => bt,
+                    None => update_manager
+                        .list_installed()
+                        .await?
+                        .into_iter()
+                        .find(|r| r.package_name == package_name)
+                        .map(|r| r.box_type)
+                        .unwrap_or_else(distro::detect_distro),
+                };
+                InputValidator::validate_box_type(&box_type)?;
+
+                if cli.mock {
+                    println!("🎭 [MOCK] Would pin {} [{}]", package_name, box_type);
+                    return Ok(());
+                }
+
+                let pin = update_manager
+                    .pin_package(&package_name, &box_type, reason.as_deref())
+                    .await?;
+                println!(
+                    "📌 Pinned {} [{}] at {}",
+                    pin.package_name,
+                    pin.box_type,
+                    pin.version.as_deref().unwrap_or("its current version")
+                );
+            } else {
+                let pins = update_manager.list_pinned().await?;
+
+                if pins.is_empty() {
+                    println!("No pinned packages");
+                } else {
+                    println!("📌 Pinned packages:");
+                    for pin in pins {
+                        println!(
+                            "  {} [{}] {}{} - pinned {}{}",
+                            pin.package_name,
+                            pin.box_type,
+                            pin.version.as_deref().unwrap_or("unknown"),
+                            pin.constraint
+                                .as_deref()
+                                .map(|c| format!(", updates within {}", c))
+                                .unwrap_or_default(),
+                            pin.pinned_at.format("%Y-%m-%d"),
+                            pin.reason
+                                .as_deref()
+                                .map(|r| format!(" ({})", r))
+                                .unwrap_or_default()
+                        );
+                    }
+                }
+            }
+        }
+
+        Commands::Unpin { package, box_type } => {
+            if cli.mock {
+                println!("🎭 [MOCK] Would unpin {}", package);
+                return Ok(());
+            }
+
+            let update_manager = UpdateManager::new(config).await?;
+            let removed = update_manager
+                .unpin_package(&package, box_type.as_deref())
+                .await?;
+
+            if removed.is_empty() {
+                println!("❌ {} is not pinned", package);
+            } else {
+                for pin in removed {
+                    println!("✅ Unpinned {} [{}]", pin.package_name, pin.box_type);
+                }
+            }
+        }
+
+        Commands::Clean { all } => {
+            if cli.mock {
+                println!(
+                    "🎭 [MOCK] Would clean package caches{}",
+                    if all { " (all)" } else { "" }
+                );
+                return Ok(());
+            }
+
+            let db = database::Database::new().await.ok();
+            let report = clean::CacheCleaner::new()
+                .with_all(all)
+                .run(db.as_ref())
+                .await;
+
+            println!("🧹 Cache cleanup:");
+            for entry in &report.entries {
+                match &entry.error {
+                    Some(error) => println!("  ❌ {}: {}", entry.name, error),
+                    None => println!(
+                        "  ✅ {}: {} reclaimed",
+                        entry.name,
+                        resolver::DependencyResolver::format_size(entry.reclaimed_bytes)
+                    ),
+                }
+            }
+            if report.cache_rows_removed > 0 {
+                println!(
+                    "  ✅ package metadata cache: {} entries removed",
+                    report.cache_rows_removed
+                );
+            }
+            println!(
+                "💾 Reclaimed {} in total",
+                resolver::DependencyResolver::format_size(report.total_reclaimed())
+            );
+        }
+
+        Commands::Autoremove { dry_run, yes } => {
+            let mut brain = OmniBrain::new_with_mock(cli.mock);
+            brain.autoremove(dry_run, yes).await?;
+        }
+
+        Commands::Downgrade {
+            package,
+            to,
+            box_type,
+            list,
+        } => {
+            InputValidator::validate_package_name(&package)?;
+            let update_manager = UpdateManager::new(config).await?;
+
+            let box_type = match box_type {
+                Some(bt) => bt,
+                None => update_manager
+                    .list_installed()
+                    .await?
+                    .into_iter()
+                    .find(|r| r.package_name == package)
+                    .map(|r| r.box_type)
+                    .unwrap_or_else(distro::detect_distro),
+            };
+            InputValidator::validate_box_type(&box_type)?;
+
+            if list {
+                let listing = downgrade::query_versions(&package, &box_type)?;
+                println!("📦 Versions of {} [{}]:", package, box_type);
+                for version in &listing.available {
+                    let marker = if listing.installed.as_deref() == Some(version.as_str()) {
+                        " (installed)"
+                    } else {
+                        ""
+                    };
+                    println!("  {}{}", version, marker);
+                }
+                return Ok(());
+            }
+
+            if cli.mock {
+                println!(
+                    "🎭 [MOCK] Would downgrade {} [{}] to {}",
+                    package,
+                    box_type,
+                    to.as_deref().unwrap_or("the previous version")
+                );
+                return Ok(());
+            }
+
+            let record = update_manager
+                .downgrade_package(&package, &box_type, to.as_deref())
+                .await?;
+            println!(
+                "⏪ Downgraded {} to {} [{}]",
+                package,
+                record.version.as_deref().unwrap_or("unknown"),
+                box_type
+            );
+            println!("💡 Run 'omni history undo' to upgrade it again");
+        }
+
+        Commands::List { box_type, detailed } => {
+            let installed = if cli.mock {
+                mock::backend().installed()
+            } else {
+                UpdateManager::new(config).await?.list_installed().await?
+            };
+
+            let filtered: Vec<_> = if let Some(bt) = box_type {
+                installed.into_iter().filter(|p| p.box_type == bt).collect()
+            } else {
+                installed
+            };
+
+            if filtered.is_empty() {
+                println!("No installed packages found");
+                return Ok(());
+            }
+
+            println!("📦 Installed packages ({}):\n", filtered.len());
+
+            for package in filtered {
+                if detailed {
+                    println!("Name: {}", package.package_name);
+                    println!("Box: {}", package.box_type);
+                    println!(
+                        "Version: {}",
+                        package.version.as_deref().unwrap_or("unknown")
+                    );
+                    println!(
+                        "Installed: {}",
+                        package.installed_at.format("%Y-%m-%d %H:%M:%S")
+                    );
+                    if let Some(source) = &package.source_url {
+                        println!("Source: {}", source);
+                    }
+                    println!();
+                } else {
+                    println!(
+                        "{} [{}] ({})",
+                        package.package_name,
+                        package.box_type,
+                        package.version.as_deref().unwrap_or("unknown")
+                    );
+                }
+            }
+        }
+
+        Commands::History { action } => match action {
+            HistoryCommands::Show { limit } => {
+                let history = if cli.mock {
+                    mock::backend().history(usize::try_from(limit).unwrap_or(0))
+                } else {
+                    let db = database::Database::new().await?;
+                    db.get_install_history(Some(limit)).await?
+                };
+
+                if history.is_empty() {
+                    println!("No installation history found");
+                    return Ok(());
+                }
+
+                println!("📜 Installation history:\n");
+
+                for record in history {
+                    let status = match record.status {
+                        database::InstallStatus::Success => "✅ Installed",
+                        database::InstallStatus::Updated => "🔄 Updated",
+                        database::InstallStatus::Downgraded => "⏪ Downgraded",
+                        database::InstallStatus::Removed => "❌ Removed",
+                        database::InstallStatus::Failed => "💥 Failed",
+                    };
+
+                    println!(
+                        "{} {} {} [{}] - {}",
+                        record.id.get(..8).unwrap_or(&record.id),
+                        record.installed_at.format("%Y-%m-%d %H:%M:%S"),
+                        record.package_name,
+                        record.box_type,
+                        status
+                    );
+                }
+            }
+
+            HistoryCommands::Undo => {
+                let mut brain = OmniBrain::new_with_mock(cli.mock);
+                brain.undo_last().await?;
+            }
+
+            HistoryCommands::Rollback {
+                point,
+                dry_run,
+                yes,
+            } => {
+                let mut brain = OmniBrain::new_with_mock(cli.mock);
+                brain.rollback_to(&point, dry_run, yes).await?;
+            }
+
+            HistoryCommands::Export {
+                format,
+                since,
+                output,
+            } => {
+                let format = format
+                    .or_else(|| output.as_deref().and_then(history::ExportFormat::from_path))
+                    .unwrap_or(history::ExportFormat::Json);
+                let mut records = if cli.mock {
+                    let mut records = mock::backend().history(usize::MAX);
+                    records.reverse();
+                    records
+                } else {
+                    let db = database::Database::new().await?;
+                    db.get_history_until(chrono::Utc::now()).await?
+                };
+                if let Some(since) = since.as_deref().map(audit::parse_time).transpose()? {
+                    records.retain(|r| r.installed_at >= since);
+                }
+
+                let exported = history::export(&records, format)?;
+                match &output {
+                    Some(path) => {
+                        std::fs::write(path, &exported)?;
+                        println!(
+                            "📤 Exported {} history records to {}",
+                            records.len(),
+                            path.display()
+                        );
+                    }
+                    None => print!("{}", exported),
+                }
+            }
+
+            HistoryCommands::Import { file, format } => {
+                let format = format
+                    .or_else(|| history::ExportFormat::from_path(&file))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Cannot tell the format of {}; pass --format json or --format csv",
+                            file.display()
+                        )
+                    })?;
+                let records = history::import(&std::fs::read_to_string(&file)?, format)?;
+                if cli.mock {
+                    println!("✅ [MOCK] Would import {} history records", records.len());
+                    return Ok(());
+                }
+
+                let db = database::Database::new().await?;
+                let imported = db.import_install_records(&records).await?;
+                println!(
+                    "📥 Imported {} history records from {} ({} already present)",
+                    imported,
+                    file.display(),
+                    records.len() as u64 - imported
+                );
+            }
+
+            HistoryCommands::Diff { from, to, json } => {
+                let records = if cli.mock {
+                    let mut records = mock::backend().history(usize::MAX);
+                    records.reverse();
+                    records
+                } else {
+                    let db = database::Database::new().await?;
+                    db.get_history_until(chrono::Utc::now()).await?
+                };
+                let from_time = history::resolve_point(&from, &records)?;
+                let to_time = history::resolve_point(&to, &records)?;
+                let changes = history::diff(
+                    &history::package_set_at(&records, from_time),
+                    &history::package_set_at(&records, to_time),
+                );
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&changes)?);
+                } else {
+                    println!(
+                        "📜 Changes from {} to {}:\n",
+                        from_time.format("%Y-%m-%d %H:%M:%S"),
+                        to_time.format("%Y-%m-%d %H:%M:%S")
+                    );
+                    history::print_diff(&changes);
+                }
+            }
+        },
+
+        Commands::Snapshot { action } if cli.mock => {
+            let backend = mock::backend();
+            match action {
+                SnapshotCommands::Create { name, description } => {
+                    let name = name.unwrap_or_else(|| {
+                        format!("snapshot-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S"))
+                    });
+                    let id = backend.create_snapshot(&name, description.as_deref());
+                    println!("✅ [MOCK] Created snapshot '{}' with ID: {}", name, id);
+                }
+                SnapshotCommands::List => {
+                    for snapshot in backend.snapshots() {
+                        println!(
+                            "{} {} ({} packages)",
+                            snapshot.id,
+                            snapshot.name,
+                            snapshot.packages.len()
+                        );
+                    }
+                }
+                SnapshotCommands::Revert { snapshot } => {
+                    let reverted = backend.revert(&snapshot)?;
+                    println!("✅ [MOCK] Reverted to snapshot '{}'", reverted.name);
+                }
+                SnapshotCommands::Prune {
+                    keep,
+                    max_age_days,
+                    ..
+                } => {
+                    let retention = config::SnapshotRetention {
+                        keep: keep.or(config.snapshots.retention.keep),
+                        max_age_days: max_age_days.or(config.snapshots.retention.max_age_days),
+                        max_disk_usage_mb: None,
+                    };
+                    let snapshots = backend.snapshots();
+                    let pruned = snapshot::prune_plan(
+                        &snapshots,
+                        &Default::default(),
+                        &retention,
+                        chrono::Utc::now(),
+                    );
+                    println!("✅ [MOCK] Would prune {} snapshots", pruned.len());
+                }
+            }
+        }
+
+        Commands::Snapshot { action } => {
+            let snapshot_manager = SnapshotManager::new().await?;
+
+            match action {
+                SnapshotCommands::Create { name, description } => {
+                    let name = name.unwrap_or_else(|| {
+                        format!("snapshot-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S"))
+                    });
+                    let snapshot_id = snapshot_manager
+                        .create_snapshot(&name, description.as_deref())
+                        .await?;
+                    println!("✅ Created snapshot '{}' with ID: {}", name, snapshot_id);
+                }
+
+                SnapshotCommands::List => {
+                    let snapshots = snapshot_manager.list_snapshots().await?;
+
+                    if snapshots.is_empty() {
+                        println!("No snapshots found");
+                        return Ok(());
+                    }
+
+                    println!("📸 Available snapshots:\n");
+
+                    for snapshot in snapshots {
+                        println!("Name: {}", snapshot.name);
+                        println!("ID: {}", snapshot.id);
+                        println!(
+                            "Created: {}",
+                            snapshot.created_at.format("%Y-%m-%d %H:%M:%S")
+                        );
+                        println!("Packages: {}", snapshot.packages.len());
+                        if let (Some(backend), Some(reference)) =
+                            (&snapshot.backend, &snapshot.backend_ref)
+                        {
+                            println!("Filesystem: {} {}", backend, reference);
+                        }
+                        if let Some(desc) = &snapshot.description {
+                            println!("Description: {}", desc);
+                        }
+                        println!();
+                    }
+                }
+
+                SnapshotCommands::Revert { snapshot } => {
+                    snapshot_manager.revert_to_snapshot(&snapshot).await?;
+                }
+
+                SnapshotCommands::Prune {
+                    keep,
+                    max_age_days,
+                    max_disk_usage_mb,
+                    dry_run,
+                } => {
+                    let configured = snapshot_manager.retention();
+                    let retention = config::SnapshotRetention {
+                        keep: keep.or(configured.keep),
+                        max_age_days: max_age_days.or(configured.max_age_days),
+                        max_disk_usage_mb: max_disk_usage_mb.or(configured.max_disk_usage_mb),
+                    };
+                    let pruned = snapshot_manager.prune(&retention, dry_run).await?;
+
+                    if pruned.is_empty() {
+                        println!("No snapshots to prune");
+                        return Ok(());
+                    }
+                    let verb = if dry_run { "Would prune" } else { "Pruned" };
+                    println!("🧹 {} {} snapshots:", verb, pruned.len());
+                    for snapshot in &pruned {
+                        println!(
+                            "  {} {} ({})",
+                            &snapshot.id[..8.min(snapshot.id.len())],
+                            snapshot.name,
+                            snapshot.created_at.format("%Y-%m-%d %H:%M")
+                        );
+                    }
+                }
+            }
+        }
+
+        Commands::Transaction { action } => match action {
+            TransactionCommands::Plan {
+                install,
+                remove,
+                update,
+                json,
+            } => {
+                let operations = operation_specs(&install, &remove, &update);
+                if operations.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "Nothing to plan; pass --install, --remove or --update"
+                    ));
+                }
+
+                let plan = if cli.mock {
+                    let backend = mock::backend();
+                    let steps = operations
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, (operation_type, box_type, package, version))| {
+                            backend.plan_step(
+                                index + 1,
+                                operation_type,
+                                &package,
+                                box_type.as_deref(),
+                                version.as_deref(),
+                            )
+                        })
+                        .collect();
+                    transaction::TransactionPlan::new(uuid::Uuid::new_v4(), steps)
+                } else {
+                    let mut manager = transaction::TransactionManager::new().await?;
+                    let id = manager
+                        .begin_transaction(transaction::TransactionType::Batch)
+                        .await?;
+                    for (operation_type, box_type, package, version) in operations {
+                        manager
+                            .add_box_operation(id, operation_type, package, box_type, version)
+                            .await?;
+                    }
+                    manager.plan(id).await?
+                };
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&plan)?);
+                } else {
+                    transaction::print_plan(&plan);
+                }
+            }
+            TransactionCommands::Begin if cli.mock => {
+                println!("✅ [MOCK] Started transaction {}", uuid::Uuid::new_v4());
+            }
+            TransactionCommands::Begin => {
+                let mut manager = transaction::TransactionManager::new().await?;
+                let id = manager
+                    .begin_transaction(transaction::TransactionType::Batch)
+                    .await?;
+                manager.journal(id).await?;
+                println!("✅ Started transaction {}", id);
+                println!("   Add operations with 'omni transaction add {} --install <package>'", id);
+            }
+            TransactionCommands::Add {
+                id,
+                install,
+                remove,
+                update,
+            } => {
+                let operations = operation_specs(&install, &remove, &update);
+                if operations.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "Nothing to add; pass --install, --remove or --update"
+                    ));
+                }
+                let count = operations.len();
+                if !cli.mock {
+                    let mut manager = transaction::TransactionManager::new().await?;
+                    manager
+                        .load_with_status(id, transaction::TransactionStatus::Pending)
+                        .await?;
+                    for (operation_type, box_type, package, version) in operations {
+                        manager
+                            .add_box_operation(id, operation_type, package, box_type, version)
+                            .await?;
+                    }
+                    manager.journal(id).await?;
+                }
+                println!(
+                    "✅ {}Added {} operations to transaction {}",
+                    if cli.mock { "[MOCK] " } else { "" },
+                    count,
+                    id
+                );
+            }
+            TransactionCommands::Commit { id } if cli.mock => {
+                println!("✅ [MOCK] Committed transaction {}", id);
+            }
+            TransactionCommands::Commit { id } => {
+                let mut manager = transaction::TransactionManager::new().await?;
+                let staged = manager
+                    .load_with_status(id, transaction::TransactionStatus::Pending)
+                    .await?;
+                if staged.operations.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "Transaction {} has no operations; add some with 'omni transaction add {}'",
+                        id,
+                        id
+                    ));
+                }
+                transaction::print_plan(&manager.plan(id).await?);
+                manager.execute_transaction(id).await?;
+                manager.commit_transaction(id).await?;
+                println!("✅ Transaction {} completed", id);
+            }
+            TransactionCommands::List { status, json } => {
+                let status = status
+                    .map(|name| {
+                        transaction::TransactionStatus::from_name(&name).ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "Unknown status '{}'; use pending, in-progress, completed, failed or rolled-back",
+                                name
+                            )
+                        })
+                    })
+                    .transpose()?;
+                let transactions = if cli.mock {
+                    Vec::new()
+                } else {
+                    transaction::TransactionManager::new()
+                        .await?
+                        .list(status)
+                        .await?
+                };
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&transactions)?);
+                } else if transactions.is_empty() {
+                    println!("No transactions");
+                } else {
+                    println!("📋 Transactions:");
+                    for transaction in &transactions {
+                        println!(
+                            "  {} {:<10} {} operations, {}",
+                            transaction.id,
+                            transaction.status.as_str(),
+                            transaction.operations.len(),
+                            transaction.created_at.format("%Y-%m-%d %H:%M")
+                        );
+                    }
+                }
+            }
+            TransactionCommands::Show { id, json } => {
+                if cli.mock {
+                    return Err(anyhow::anyhow!("Transaction not found: {}", id));
+                }
+                let mut manager = transaction::TransactionManager::new().await?;
+                let transaction = manager.load(id).await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(transaction)?);
+                } else {
+                    transaction::print_transaction(transaction);
+                }
+            }
+            TransactionCommands::Resume { id } if cli.mock => {
+                println!("✅ [MOCK] Resumed transaction {}", id);
+            }
+            TransactionCommands::Resume { id } => {
+                let mut manager = transaction::TransactionManager::new().await?;
+                manager.resume(id).await?;
+                println!("✅ Transaction {} completed", id);
+            }
+            TransactionCommands::Rollback { id } if cli.mock => {
+                println!("✅ [MOCK] Rolled back transaction {}", id);
+            }
+            TransactionCommands::Rollback { id } => {
+                let mut manager = transaction::TransactionManager::new().await?;
+                manager.rollback(id).await?;
+                println!("✅ Transaction {} rolled back", id);
+            }
+        },
+
+        Commands::Gui => {
+            #[cfg(feature = "gui")]
+            {
+                gui::launch_gui(cli.mock);
+            }
+            #[cfg(not(feature = "gui"))]
+            {
+                println!("❌ GUI feature not compiled. Please rebuild with --features gui");
+            }
+        }
+
+        Commands::Config { action } => {
+            match action {
+                ConfigCommands::Show => {
+                    println!("📂 {}", OmniConfig::scope_summary());
+                    if let Some(project_config) = &config.project_config {
+                        println!("📁 Including project config {}", project_config.display());
+                    }
+                    if !config.overridden_by.is_empty() {
+                        println!("🔧 Overridden by {}", config.overridden_by.join(", "));
+                    }
+                    println!("📋 Current configuration:\n");
+                    println!("{}", config.to_yaml()?);
+                }
+
+                ConfigCommands::Edit => {
+                    let config_path = OmniConfig::config_path()?;
+                    println!("📝 Edit configuration file: {}", config_path.display());
+
+                    // Try to open with default editor
+                    if let Ok(editor) = std::env::var("EDITOR") {
+                        std::process::Command::new(editor)
+                            .arg(&config_path)
+                            .status()?;
+                    } else {
+                        println!("Set EDITOR environment variable or edit manually");
+                    }
+                }
+
+                ConfigCommands::Reset => {
+                    let default_config = OmniConfig::default();
+                    default_config.save()?;
+                    println!("✅ Configuration reset to defaults");
+                }
+
+                ConfigCommands::Validate { file } => validate_config(file)?,
+
+                ConfigCommands::Encrypt => {
+                    let secret = read_secret("Secret to encrypt")?;
+                    let key = config_secrets::ConfigKey::load_or_create()?;
+                    println!("{}", key.encrypt(&secret)?);
+                    eprintln!("💡 Paste the value into the config in place of the secret");
+                }
+
+                ConfigCommands::Key { import: false } => {
+                    let key = config_secrets::ConfigKey::load()?.ok_or_else(|| {
+                        anyhow::anyhow!("No config key yet; 'omni config encrypt' creates one")
+                    })?;
+                    println!("{}", key.to_base64());
+                }
+
+                ConfigCommands::Key { import: true } => {
+                    let key = config_secrets::ConfigKey::from_base64(&read_secret("Config key")?)?;
+                    key.store()?;
+                    println!("🔑 Stored the config key in the keyring");
+                }
+            }
+        }
+
+        Commands::Manifest { action } => {
+            let (converted, output) = match action {
+                ManifestCommands::Import {
+                    file,
+                    format,
+                    output,
+                } => {
+                    let imported = interop::import_file(std::path::Path::new(&file), format)?;
+                    let manifest = imported.output.to_yaml()?;
+                    (
+                        interop::Converted {
+                            output: manifest,
+                            skipped: imported.skipped,
+                        },
+                        output,
+                    )
+                }
+                ManifestCommands::Export {
+                    manifest,
+                    format,
+                    output,
+                } => (
+                    interop::export(&OmniManifest::from_file(&manifest)?, format)?,
+                    output,
+                ),
+            };
+
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, &converted.output)?;
+                    println!("✅ Wrote {}", path);
+                }
+                None => print!("{}", converted.output),
+            }
+            if !converted.skipped.is_empty() {
+                eprintln!("⚠️  Not carried over:");
+                for entry in &converted.skipped {
+                    eprintln!("   • {}", entry);
+                }
+            }
+        }
+
+        Commands::Resolve {
+            package,
+            box_type,
+            detailed,
+        } => {
+            let resolver = resolver::DependencyResolver::new().await?;
+            let mut plan = resolver
+                .resolve_dependencies(&package, box_type.as_deref())
+                .await?;
+
+            let prompts = interactive::InteractivePrompts::new();
+            if !plan.conflicts.is_empty()
+                && std::io::IsTerminal::is_terminal(&std::io::stdin())
+                && !resolver
+                    .resolve_conflicts(&mut plan, |conflict| prompts.resolve_conflict(conflict))
+                    .await?
+            {
+                println!("❌ Resolution aborted");
+                return Ok(());
+            }
+
+            println!("🔍 Dependency resolution for '{}':\n", package);
+
+            if plan.packages.is_empty() {
+                println!("No dependencies found or package not available.");
+                return Ok(());
+            }
+
+            println!("📦 Packages to install ({}):", plan.packages.len());
+            for (i, pkg) in plan.packages.iter().enumerate() {
+                let marker = if i == 0 { "🎯" } else { "📎" };
+                println!(
+                    "{} {} [{}] v{}",
+                    marker, pkg.name, pkg.box_type, pkg.version
+                );
+
+                if detailed && !pkg.dependencies.is_empty() {
+                    for dep in &pkg.dependencies {
+                        let opt = if dep.optional { " (optional)" } else { "" };
+                        println!("   └─ {}{}", dep.name, opt);
+                    }
+                }
+            }
+
+            if !plan.removals.is_empty() {
+                println!("\n🗑️  Packages to remove first:");
+                for (name, box_type) in &plan.removals {
+                    println!("   • {} [{}]", name, box_type);
+                }
+            }
+
+            if let Some(size) = plan.total_size {
+                println!(
+                    "\n💾 Total size: {}",
+                    resolver::DependencyResolver::format_size(size)
+                );
+            }
+
+            if !plan.conflicts.is_empty() {
+                println!("\n⚠️  Conflicts:");
+                for conflict in &plan.conflicts {
+                    println!("   • {}", conflict);
+                }
+            }
+
+            if !plan.warnings.is_empty() {
+                println!("\n⚠️  Warnings:");
+                for warning in &plan.warnings {
+                    println!("   • {}", warning);
+                }
+            }
+        }
+
+        Commands::Verify {
+            file_path,
+            installed,
+            provenance,
+            checksum,
+            signature,
+            box_type,
+        } => {
+            use security::{SecurityPolicy, SecurityVerifier};
+            use std::path::Path;
+
+            let policy = SecurityPolicy::default();
+            let mut verifier = SecurityVerifier::new(policy);
+            let key_store = keys::KeyStore::open()?;
+            if key_store.has_keys() {
+                verifier = verifier.with_key_store(key_store);
+            }
+
+            if provenance {
+                if let Some(package) = &file_path {
+                    InputValidator::validate_package_name(package)?;
+                }
+                let db = database::Database::new().await?;
+                let recorded = db.get_artifacts(file_path.as_deref()).await?;
+                if recorded.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "omni has no install records for {}",
+                        file_path.as_deref().unwrap_or("any package")
+                    ));
+                }
+
+                let mut failed = 0;
+                for artifact in &recorded {
+                    println!(
+                        "\n📋 {} [{}] {}",
+                        artifact.package_name,
+                        artifact.box_type,
+                        artifact.version.as_deref().unwrap_or("")
+                    );
+                    println!(
+                        "   from {}",
+                        artifact.url.as_deref().unwrap_or("an unknown source")
+                    );
+                    println!(
+                        "   checksum {}",
+                        artifact.checksum.as_deref().unwrap_or("not recorded")
+                    );
+                    println!(
+                        "   signature {}, recorded {}",
+                        artifact.signature.as_str(),
+                        artifact.recorded_at.format("%Y-%m-%d %H:%M")
+                    );
+                    match artifacts::recheck(artifact) {
+                        artifacts::Recheck::Matches => println!("✅ Matches the record"),
+                        artifacts::Recheck::Unchecked(reason) => {
+                            println!("➖ Not checked: {}", reason)
+                        }
+                        artifacts::Recheck::Problems(problems) => {
+                            failed += 1;
+                            for problem in problems {
+                                println!("❌ {}", problem);
+                            }
+                        }
+                    }
+                }
+
+                if failed > 0 {
+                    return Err(anyhow::anyhow!(
+                        "{} of {} packages no longer match their install records",
+                        failed,
+                        recorded.len()
+                    ));
+                }
+                return Ok(());
+            }
+
+            if installed {
+                if let Some(package) = &file_path {
+                    InputValidator::validate_package_name(package)?;
+                }
+                println!(
+                    "🔒 Verifying installed files for: {}",
+                    file_path.as_deref().unwrap_or("all packages")
+                );
+
+                let results = verifier.verify_installed(file_path.as_deref()).await?;
+                let mut problems = 0;
+                for (report, result) in &results {
+                    println!("\n📋 {} ({}):", report.box_type, report.tool);
+                    println!("{}", "─".repeat(50));
+                    println!("{}", result.details);
+
+                    problems += report.problems().count();
+                    if report.is_clean() {
+                        println!("✅ No modified or missing files");
+                    } else {
+                        println!("❌ {} modified or missing files", report.problems().count());
+                    }
+                }
+
+                if problems > 0 {
+                    return Err(anyhow::anyhow!(
+                        "Integrity check found {} modified or missing files",
+                        problems
+                    ));
+                }
+                return Ok(());
+            }
+
+            let file_path = file_path.ok_or_else(|| {
+                anyhow::anyhow!("Give a file to verify, or use --installed [PACKAGE]")
+            })?;
+            let path = Path::new(&file_path);
+            if !path.exists() {
+                return Err(anyhow::anyhow!("File not found: {}", file_path));
+            }
+
+            println!("🔒 Verifying security for: {}", file_path);
+
+            let result = verifier
+                .verify_package(
+                    path,
+                    checksum.as_deref(),
+                    signature.as_deref(),
+                    box_type.as_deref().unwrap_or("unknown"),
+                )
+                .await?;
+
+            println!("\n📋 Verification Results:");
+            println!("{}", "─".repeat(50));
+            println!("{}", result.details);
+
+            match result.trust_level {
+                security::TrustLevel::Trusted => println!("✅ Package is trusted and verified"),
+                security::TrustLevel::Valid => println!("✅ Package signature is valid"),
+                security::TrustLevel::Unsigned => {
+                    println!("⚠️  Package is unsigned but checksum verified")
+                }
+                security::TrustLevel::Untrusted => println!("❌ Package failed verification"),
+            }
+        }
+
+        Commands::Hardware { action } => {
+            let mut brain = OmniBrain::new_with_mock(cli.mock);
+
+            match action {
+                HardwareCommands::Detect => {
+                    println!("🔍 Detecting server hardware configuration...");
+                    match brain.get_hardware_info() {
+                        Ok(info) => {
+                            println!("\n📋 Hardware Information:");
+                            println!("{}", "─".repeat(50));
+                            println!("{}", info);
+                        }
+                        Err(e) => {
+                            error!("❌ Failed to detect hardware: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+
+                HardwareCommands::Install => {
+                    println!("🤖 Auto-detecting hardware and installing optimal drivers...");
+                    match brain.detect_and_install_drivers().await {
+                        Ok(()) => {
+                            println!("✅ Driver installation process completed");
+                        }
+                        Err(e) => {
+                            error!("❌ Driver installation failed: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+
+                HardwareCommands::Vendor { vendor } => {
+                    println!("🏢 Installing {} vendor-specific drivers...", vendor);
+                    match brain.install_vendor_drivers(&vendor).await {
+                        Ok(()) => {
+                            println!("✅ {} vendor drivers installation completed", vendor);
+                        }
+                        Err(e) => {
+                            error!("❌ Vendor driver installation failed: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Keys { action } => {
+            let db = database::Database::new().await?;
+            let store = keys::KeyStore::open()?;
+            let servers = &config.security.signature_servers;
+
+            match action {
+                KeyCommands::List { json } => {
+                    let trusted = store.list()?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&trusted)?);
+                    } else if trusted.is_empty() {
+                        println!("No trusted keys; signatures are checked against your own GnuPG keyring");
+                    } else {
+                        println!("🔑 Trusted keys:");
+                        for key in &trusted {
+                            println!("  {}", key.describe());
+                        }
+                    }
+                }
+
+                KeyCommands::Add { source } => {
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would trust the keys from {}", source);
+                        return Ok(());
+                    }
+
+                    for key in keys::add(&db, &store, servers, &source).await? {
+                        println!("✅ Trusted {}", key.describe());
+                    }
+                }
+
+                KeyCommands::Remove { key } => {
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would stop trusting {}", key);
+                        return Ok(());
+                    }
+
+                    let removed = keys::remove(&db, &store, &key).await?;
+                    println!("✅ No longer trusting {}", removed.describe());
+                }
+
+                KeyCommands::Refresh => {
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would refresh trusted keys");
+                        return Ok(());
+                    }
+
+                    let failed = keys::refresh(&db, &store, servers).await?;
+                    for (fingerprint, error) in &failed {
+                        println!("⚠️  Could not refresh {}: {}", fingerprint, error);
+                    }
+                    for key in store.list()? {
+                        println!("  {}", key.describe());
+                    }
+                    if !failed.is_empty() {
+                        return Err(anyhow::anyhow!("{} keys could not be refreshed", failed.len()));
+                    }
+                }
+            }
+        }
+
+        Commands::Credentials { action } => {
+            let service = credentials::CREDENTIALS_KEYRING_SERVICE;
+
+            match action {
+                CredentialCommands::Set { name } => {
+                    if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                        return Err(anyhow::anyhow!(
+                            "Credential names cannot be empty or contain spaces"
+                        ));
+                    }
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would store {} in the keyring", name);
+                        return Ok(());
+                    }
+
+                    let secret = read_secret(&format!("Secret for {}", name))?;
+                    credentials::keyring_set(service, &name, &secret)?;
+                    println!("🔑 Stored {} in the keyring", name);
+                    println!(
+                        "💡 Use it in the config as \"{}{}\"",
+                        credentials::KEYRING_PREFIX,
+                        name
+                    );
+                }
+
+                CredentialCommands::Get { name } => {
+                    match credentials::keyring_get(service, &name) {
+                        Some(secret) => println!("{}", secret),
+                        None => {
+                            return Err(anyhow::anyhow!(
+                                "No credential named {} in the keyring",
+                                name
+                            ))
+                        }
+                    }
+                }
+
+                CredentialCommands::Remove { name } => {
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would remove {} from the keyring", name);
+                        return Ok(());
+                    }
+
+                    if !credentials::keyring_delete(service, &name)? {
+                        return Err(anyhow::anyhow!(
+                            "No credential named {} in the keyring",
+                            name
+                        ));
+                    }
+                    println!("✅ Removed {} from the keyring", name);
+                }
+            }
+        }
+
+        Commands::Watch { action } => {
+            let db = database::Database::new().await?;
+
+            match action {
+                WatchCommands::Add { package, box_type } => {
+                    InputValidator::validate_package_name(&package)?;
+                    if let Some(box_type) = &box_type {
+                        InputValidator::validate_box_type(box_type)?;
+                    }
+
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would watch {}", package);
+                        return Ok(());
+                    }
+
+                    let watch = watchlist::add(&db, &package, box_type.as_deref()).await?;
+                    println!(
+                        "👀 Watching {} [{}] (currently {})",
+                        watch.package_name,
+                        watch.box_type,
+                        watch.last_version.as_deref().unwrap_or("unavailable")
+                    );
+                }
+
+                WatchCommands::Remove { package, box_type } => {
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would stop watching {}", package);
+                        return Ok(());
+                    }
+
+                    if db.unwatch_package(&package, box_type.as_deref()).await? == 0 {
+                        println!("❌ {} is not on the watchlist", package);
+                    } else {
+                        println!("✅ Stopped watching {}", package);
+                    }
+                }
+
+                WatchCommands::List => {
+                    let watched = db.get_watched_packages().await?;
+
+                    if watched.is_empty() {
+                        println!("No watched packages");
+                    } else {
+                        println!("👀 Watched packages:");
+                        for watch in watched {
+                            println!(
+                                "  {} [{}] {} - checked {}{}",
+                                watch.package_name,
+                                watch.box_type,
+                                watch.last_version.as_deref().unwrap_or("unknown"),
+                                watch
+                                    .checked_at
+                                    .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                                    .unwrap_or_else(|| "never".to_string()),
+                                if watch.known_advisories.is_empty() {
+                                    String::new()
+                                } else {
+                                    format!(" ({} advisories seen)", watch.known_advisories.len())
+                                }
+                            );
+                        }
+                    }
+                }
+
+                WatchCommands::Check { json } => {
+                    let changes = watchlist::check(&db).await?;
+
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&changes)?);
+                    } else if changes.is_empty() {
+                        println!("✅ No changes to watched packages");
+                    } else {
+                        println!("🔔 Watched packages changed:");
+                        for change in &changes {
+                            if change.version_changed() {
+                                println!(
+                                    "  {} [{}]: {} -> {}",
+                                    change.package,
+                                    change.box_type,
+                                    change.previous_version.as_deref().unwrap_or("unknown"),
+                                    change.current_version.as_deref().unwrap_or("unknown")
+                                );
+                            } else {
+                                println!("  {} [{}]", change.package, change.box_type);
+                            }
+                            for advisory in &change.new_advisories {
+                                println!("    🛡️  {}", advisory);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Repository { action } => {
+            let mut brain = OmniBrain::new_with_mock(cli.mock);
+
+            match action {
+                RepositoryCommands::Add {
+                    repository,
+                    repo_type,
+                    key_url,
+                } => {
+                    println!("➕ Adding repository: {}", repository);
+                    match brain
+                        .add_repository(&repository, repo_type.as_deref(), key_url.as_deref())
+                        .await
+                    {
+                        Ok(()) => {
+                            println!("✅ Repository added successfully");
+                        }
+                        Err(e) => {
+                            error!("❌ Failed to add repository: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+
+                RepositoryCommands::Remove { repository } => {
+                    println!("➖ Removing repository: {}", repository);
+                    match brain.remove_repository(&repository).await {
+                        Ok(()) => {
+                            println!("✅ Repository removed successfully");
+                        }
+                        Err(e) => {
+                            error!("❌ Failed to remove repository: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+
+                RepositoryCommands::List => {
+                    println!("📦 Configured repositories:");
+                    match brain.list_repositories().await {
+                        Ok(repositories) => {
+                            if repositories.is_empty() {
+                                println!("No repositories configured");
+                            } else {
+                                for repo in repositories {
+                                    println!("- {}", repo);
+                                }
+                            }
+                        }
+                        Err(e) => error!("❌ Failed to list repositories: {}", e),
+                    }
+                }
+
+                RepositoryCommands::Benchmark {
+                    box_type,
+                    mirrors: extra_mirrors,
+                    apply,
+                    top,
+                    json,
+                } => {
+                    let box_types: Vec<String> = match box_type {
+                        Some(box_type) => vec![box_type],
+                        None => mirrors::available_box_types()
+                            .into_iter()
+                            .map(|bt| bt.to_string())
+                            .collect(),
+                    };
+                    if box_types.is_empty() {
+                        println!(
+                            "❌ No backend with a mirror configuration (apt, dnf, pacman) found"
+                        );
+                        return Ok(());
+                    }
+
+                    let mut all_results = Vec::new();
+                    for box_type in box_types {
+                        let configured = mirrors::configured_mirrors(&box_type)?;
+                        let mut candidates = configured.clone();
+                        for url in &extra_mirrors {
+                            candidates.push(mirrors::candidate_mirror(&box_type, url, &configured));
+                        }
+
+                        if !json {
+                            println!(
+                                "⏱️  Benchmarking {} {} mirror(s)...",
+                                candidates.len(),
+                                box_type
+                            );
+                        }
+                        let results = mirrors::benchmark(candidates).await?;
+
+                        if !json {
+                            if results.is_empty() {
+                                println!("  No mirrors configured");
+                            }
+                            for result in &results {
+                                match (result.latency_ms, result.throughput_kbps) {
+                                    (Some(latency), Some(throughput)) => println!(
+                                        "  {:>6} ms {:>9.0} KiB/s  {}{}",
+                                        latency,
+                                        throughput,
+                                        result.mirror.url,
+                                        if result.mirror.enabled {
+                                            ""
+                                        } else {
+                                            " (disabled)"
+                                        }
+                                    ),
+                                    _ => println!(
+                                        "  ❌ {} - {}",
+                                        result.mirror.url,
+                                        result.error.as_deref().unwrap_or("failed")
+                                    ),
+                                }
+                            }
+                        }
+
+                        if apply {
+                            if cli.mock {
+                                println!("🎭 [MOCK] Would apply the {} mirror ranking", box_type);
+                            } else {
+                                for path in mirrors::apply(&box_type, &results, top)? {
+                                    println!(
+                                        "✅ Updated {} (backup: {}.omni-bak)",
+                                        path.display(),
+                                        path.display()
+                                    );
+                                }
+                            }
+                        }
+                        all_results.extend(results);
+                    }
+
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&all_results)?);
+                    } else if !apply {
+                        println!("\nRun with --apply to switch to the fastest mirrors");
+                    }
+                }
+
+                RepositoryCommands::Refresh => {
+                    println!("🔄 Refreshing repository metadata...");
+                    let update_manager = UpdateManager::new(config).await?;
+                    match update_manager.refresh_repositories().await {
+                        Ok(()) => {
+                            println!("✅ Repository refresh completed");
+                        }
+                        Err(e) => {
+                            error!("❌ Failed to refresh repositories: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Fleet { action } => {
+            let mut inventory = FleetInventory::load()?;
+
+            match action {
+                FleetCommands::AddHost {
+                    name,
+                    address,
+                    port,
+                    user,
+                    transport,
+                    identity_file,
+                    box_type,
+                    tags,
+                    groups,
+                    sudo_password,
+                } => {
+                    let tags = tags
+                        .iter()
+                        .map(|t| fleet::parse_tag(t))
+                        .collect::<Result<_>>()?;
+
+                    inventory.add_host(fleet::FleetHost {
+                        name: name.clone(),
+                        address,
+                        port: port.unwrap_or(transport.default_port()),
+                        user,
+                        identity_file,
+                        box_type,
+                        tags,
+                        groups,
+                        sudo_password,
+                        transport,
+                    })?;
+                    inventory.save()?;
+                    println!("✅ Added {} to the fleet inventory", name);
+                }
+
+                FleetCommands::SudoPassword { name } => {
+                    store_host_password(
+                        &inventory,
+                        &name,
+                        credentials::SUDO_KEYRING_SERVICE,
+                        "sudo",
+                    )?;
+                }
+
+                FleetCommands::WinrmPassword { name } => {
+                    store_host_password(
+                        &inventory,
+                        &name,
+                        credentials::WINRM_KEYRING_SERVICE,
+                        "WinRM",
+                    )?;
+                }
+
+                FleetCommands::RemoveHost { name } => {
+                    if inventory.remove_host(&name) {
+                        inventory.save()?;
+                        println!("✅ Removed {} from the fleet inventory", name);
+                    } else {
+                        println!("❌ Host {} is not in the inventory", name);
+                    }
+                }
+
+                FleetCommands::List { tags, groups } => {
+                    let selector = HostSelector::from_args(tags.as_deref(), &groups, &[])?;
+                    let hosts = inventory.select(&selector);
+
+                    if hosts.is_empty() {
+                        println!("No hosts match");
+                        return Ok(());
+                    }
+
+                    println!("🖥️  Fleet hosts:");
+                    for host in &hosts {
+                        println!(
+                            "  {} ({}@{}:{} via {}) [{}] {}{}",
+                            host.name,
+                            host.user,
+                            host.address,
+                            host.port,
+                            host.transport.as_str(),
+                            host.box_type.as_deref().unwrap_or("auto"),
+                            host.tag_labels().join(" "),
+                            if host.groups.is_empty() {
+                                String::new()
+                            } else {
+                                format!(" groups: {}", host.groups.join(","))
+                            }
+                        );
+                    }
+
+                    println!("\n🏷️  Hosts per tag:");
+                    for (tag, count) in fleet::count_by_tag(&hosts) {
+                        println!("  {}: {}", tag, count);
+                    }
+                }
+
+                FleetCommands::Install {
+                    package,
+                    tags,
+                    groups,
+                    hosts,
+                    confirm,
+                    events,
+                } => {
+                    let options = FleetRunOptions {
+                        confirm,
+                        mock: cli.mock,
+                        events,
+                        verbose: cli.verbose,
+                        health_checks: config.health_checks.clone(),
+                        notifier: notifications::Dispatcher::from_config(&config),
+                    };
+                    run_fleet_operation(&inventory, "install", &package, tags, groups, hosts, options)
+                        .await?;
+                }
+
+                FleetCommands::Update {
+                    package,
+                    tags,
+                    groups,
+                    hosts,
+                    confirm,
+                    events,
+                } => {
+                    let options = FleetRunOptions {
+                        confirm,
+                        mock: cli.mock,
+                        events,
+                        verbose: cli.verbose,
+                        health_checks: config.health_checks.clone(),
+                        notifier: notifications::Dispatcher::from_config(&config),
+                    };
+                    run_fleet_operation(&inventory, "update", &package, tags, groups, hosts, options)
+                        .await?;
+                }
+
+                FleetCommands::Remove {
+                    package,
+                    tags,
+                    groups,
+                    hosts,
+                    confirm,
+                    events,
+                } => {
+                    let options = FleetRunOptions {
+                        confirm,
+                        mock: cli.mock,
+                        events,
+                        verbose: cli.verbose,
+                        health_checks: config.health_checks.clone(),
+                        notifier: notifications::Dispatcher::from_config(&config),
+                    };
+                    run_fleet_operation(&inventory, "remove", &package, tags, groups, hosts, options)
+                        .await?;
+                }
+
+                FleetCommands::Drift {
+                    from,
+                    tags,
+                    groups,
+                    hosts,
+                    json,
+                } => {
+                    let manifest = OmniManifest::from_file(&from)?;
+                    let selector = HostSelector::from_args(tags.as_deref(), &groups, &hosts)?;
+                    let targets = inventory.select(&selector);
+                    if targets.is_empty() {
+                        return Err(anyhow::anyhow!("No fleet hosts match the given selection"));
+                    }
+
+                    if cli.mock {
+                        for host in &targets {
+                            println!("🎭 [MOCK] Would compare {} against {}", host.name, from);
+                        }
+                        return Ok(());
+                    }
+
+                    let report = FleetRunner::new().drift_report(&targets, &manifest).await;
+
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&report)?);
+                    } else {
+                        print_drift_report(&report);
+                    }
+                }
+
+                FleetCommands::Policy {
+                    tag,
+                    require_confirm,
+                } => {
+                    inventory.set_policy(&tag, require_confirm)?;
+                    inventory.save()?;
+                    if require_confirm {
+                        println!("✅ Hosts tagged {} now require --confirm", tag);
+                    } else {
+                        println!("✅ Removed policy for {}", tag);
+                    }
+                }
+            }
+        }
+
+        Commands::Daemon { once } => {
+            if cli.mock {
+                println!("🎭 [MOCK] Would start the update daemon");
+                return Ok(());
+            }
+
+            println!(
+                "🕒 Checking for updates every {} minutes (auto-install: {:?})",
+                config.daemon.check_interval_minutes, config.daemon.auto_install
+            );
+            daemon::run(config, once).await?;
+        }
+
+        Commands::Status { json } => {
+            let status = daemon::load_status()?;
+            let running = daemon::running_pid();
+            let queue = maintenance::load_queue()?;
+            let windows = maintenance::MaintenanceWindows::from_config(&config.daemon.maintenance)?;
+            let next_window = windows
+                .is_configured()
+                .then(|| windows.next_open(maintenance::local_now()))
+                .flatten();
+
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&serde_json::json!({
+                        "running": running.is_some(),
+                        "pid": running,
+                        "status": status,
+                        "queue": queue,
+                        "next_window": next_window,
+                    }))?
+                );
+                return Ok(());
+            }
+
+            match running {
+                Some(pid) => println!("🟢 Daemon running (pid {})", pid),
+                None => println!("⚪ Daemon not running"),
+            }
+
+            if windows.is_open(maintenance::local_now()) && windows.is_configured() {
+                println!("  Maintenance window: open now");
+            } else if let Some(open) = next_window {
+                println!(
+                    "  Next maintenance window: {}",
+                    open.format("%Y-%m-%d %H:%M")
+                );
+            }
+            for transaction in &queue {
+                println!(
+                    "⏳ Queued by {} at {} for the next window:",
+                    transaction.requested_by,
+                    transaction.queued_at.format("%Y-%m-%d %H:%M")
+                );
+                for update in &transaction.updates {
+                    println!(
+                        "  {} [{}] -> {}",
+                        update.package_name,
+                        update.box_type,
+                        update.available_version.as_deref().unwrap_or("latest")
+                    );
+                }
+            }
+
+            let Some(status) = status else {
+                println!("No update checks recorded yet; run 'omni daemon'");
+                return Ok(());
+            };
+
+            let format_time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+                time.map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|| "never".to_string())
+            };
+            println!("  Last check: {}", format_time(status.last_check));
+            if running.is_some() {
+                println!("  Next check: {}", format_time(status.next_check));
+            }
+            if let Some(period) = config.daemon.digest.period {
+                println!(
+                    "  Last {} digest: {}",
+                    period,
+                    format_time(status.last_digest)
+                );
+            }
+            if let Some(error) = &status.last_error {
+                println!("  ❌ Last check failed: {}", error);
+            }
+
+            if status.available_updates.is_empty() {
+                println!("✅ No updates available");
+            } else {
+                println!(
+                    "📦 {} updates available{}:",
+                    status.available_updates.len(),
+                    if status.downloaded {
+                        " (downloaded)"
+                    } else {
+                        ""
+                    }
+                );
+                for update in &status.available_updates {
+                    println!(
+                        "  {}{} [{}]: {} -> {}",
+                        if update.security { "🛡️  " } else { "" },
+                        update.package_name,
+                        update.box_type,
+                        update.current_version.as_deref().unwrap_or("unknown"),
+                        update.available_version.as_deref().unwrap_or("latest")
+                    );
+                }
+            }
+
+            if !status.installed.is_empty() {
+                println!("🔄 Installed automatically:");
+                for update in &status.installed {
+                    println!("  {} [{}]", update.package_name, update.box_type);
+                }
+            }
+
+            if !status.deferred.is_empty() {
+                println!("⏸️  Deferred by policy:");
+                for update in &status.deferred {
+                    println!(
+                        "  {} [{}] -> {}: {}",
+                        update.package_name,
+                        update.box_type,
+                        update.available_version.as_deref().unwrap_or("latest"),
+                        update.reason
+                    );
+                }
+            }
+        }
+
+        Commands::Schedule { action } => {
+            let platform = schedule::Platform::current();
+
+            match action {
+                ScheduleCommands::InstallUnits {
+                    scope,
+                    tasks,
+                    snapshot_interval_hours,
+                    enable,
+                    dry_run,
+                } => {
+                    let tasks = if tasks.is_empty() {
+                        schedule::Task::ALL.to_vec()
+                    } else {
+                        tasks
+                    };
+                    let scheduled: Vec<schedule::ScheduledTask> = tasks
+                        .into_iter()
+                        .map(|task| schedule::ScheduledTask {
+                            task,
+                            interval_minutes: match task {
+                                schedule::Task::Update => config.daemon.check_interval_minutes,
+                                schedule::Task::Snapshot => snapshot_interval_hours.max(1) * 60,
+                            },
+                        })
+                        .collect();
+                    let exe = std::env::current_exe()?;
+                    let plan = schedule::install_plan(platform, scope, &exe, &scheduled, enable)?;
+
+                    if dry_run || cli.mock {
+                        for file in &plan.files {
+                            println!("📄 {}\n{}", file.path.display(), file.contents);
+                        }
+                        for command in plan.reload.iter().chain(&plan.commands) {
+                            println!("▶️  {}", command.join(" "));
+                        }
+                        return Ok(());
+                    }
+
+                    schedule::install(&plan)?;
+                    for scheduled in &scheduled {
+                        println!(
+                            "✅ Scheduled {} every {} minutes",
+                            scheduled.task.name(),
+                            scheduled.interval_minutes
+                        );
+                    }
+                    if !enable && platform != schedule::Platform::TaskScheduler {
+                        println!("💡 Pass --enable to start the timers, or enable them yourself");
+                    }
+                }
+
+                ScheduleCommands::RemoveUnits { scope, tasks } => {
+                    let tasks = if tasks.is_empty() {
+                        schedule::Task::ALL.to_vec()
+                    } else {
+                        tasks
                     };
+                    let plan = schedule::remove_plan(platform, scope, &tasks)?;
+
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would remove {} scheduled tasks", tasks.len());
+                        return Ok(());
+                    }
+
+                    schedule::remove(&plan)?;
+                    println!("✅ Removed {} scheduled tasks", tasks.len());
+                }
+            }
+        }
+
+        Commands::Dotfiles { from, dry_run } => {
+            let dotfiles_config = match &from {
+                Some(path) => OmniManifest::from_file(path)?
+                    .dotfiles
+                    .ok_or_else(|| anyhow::anyhow!("{} has no dotfiles section", path))?,
+                None => config.dotfiles.clone(),
+            };
+            match dotfiles::command_line(&dotfiles_config, from.as_deref())? {
+                Some(argv) => dotfiles::apply(&argv, dry_run || cli.mock)?,
+                None => println!("ℹ️  No dotfiles manager is configured (dotfiles.manager)"),
+            }
+        }
 
+        Commands::Doctor => {
+            let checks = doctor::run(&config).await;
+            println!("🩺 Omni doctor\n");
+            doctor::print(&checks);
+            let failed = checks
+                .iter()
+                .filter(|c| c.level == doctor::Level::Error)
+                .count();
+            if failed > 0 {
+                return Err(anyhow::anyhow!("{} doctor check(s) failed", failed));
+            }
+        }
+        Commands::Scan {
+            packages,
+            refresh,
+            json,
+        } => {
+            let only = (!packages.is_empty()).then_some(packages.as_slice());
+            let report =
+                vulnerabilities::scan_installed(&config.vulnerabilities, only, refresh).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report.to_text());
+            }
+        }
+        Commands::Health { json } => {
+            let report = health::run().await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                health::print(&report);
+            }
+            if !report.healthy {
+                return Err(anyhow::anyhow!("omni is unhealthy"));
+            }
+        }
+        Commands::Audit { action } => match action {
+            AuditCommands::Show {
+                user,
+                severity,
+                event_type,
+                package,
+                since,
+                until,
+                limit,
+                json,
+            } => {
+                let filter = audit::AuditFilter {
+                    user,
+                    event_type,
+                    severity,
+                    package_name: package,
+                    since: since.as_deref().map(audit::parse_time).transpose()?,
+                    until: until.as_deref().map(audit::parse_time).transpose()?,
+                    limit: Some(limit),
+                };
+                let entries = audit::AuditManager::new()
+                    .await?
+                    .query_logs(&filter)
+                    .await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&entries)?);
+                } else if entries.is_empty() {
+                    println!("No audit entries match");
+                } else {
+                    println!("🧾 Audit log:\n");
+                    for entry in &entries {
+                        println!("{}", audit::format_entry(entry));
+                    }
+                }
+            }
+            AuditCommands::Stats { since, json } => {
+                let since = since.as_deref().map(audit::parse_time).transpose()?;
+                let stats = audit::AuditManager::new()
+                    .await?
+                    .get_statistics(since)
+                    .await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&stats)?);
+                } else {
+                    audit::print_statistics(&stats);
+                }
+            }
+            AuditCommands::Export {
+                format,
+                since,
+                output,
+            } => {
+                let since = since.as_deref().map(audit::parse_time).transpose()?;
+                let entries = audit::AuditManager::new()
+                    .await?
+                    .entries_since(since)
+                    .await?;
+                let device = siem::Device::local();
+                let mut lines = String::new();
+                for entry in &entries {
+                    lines.push_str(&siem::format_entry(format, entry, &device));
+                    lines.push('\n');
+                }
+                match &output {
+                    Some(path) => {
+                        std::fs::write(path, &lines)?;
+                        println!(
+                            "📤 Exported {} audit entries to {}",
+                            entries.len(),
+                            path.display()
+                        );
+                    }
+                    None => print!("{}", lines),
+                }
+            }
+            AuditCommands::Verify { json } => {
+                let report = audit::AuditManager::new().await?.verify().await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    for chain_break in &report.breaks {
+                        println!("❌ {}", chain_break);
+                    }
+                    if report.is_intact() {
+                        println!("✅ Audit log intact: {} entries chained", report.chained);
+                    }
+                    if report.legacy > 0 {
+                        println!(
+                            "ℹ️  {} older entries predate hash chaining and are not covered",
+                            report.legacy
+                        );
+                    }
+                    if !report.head.is_empty() {
+                        println!("🔗 Chain head: {}", report.head);
+                    }
+                }
+                if !report.is_intact() {
+                    return Err(anyhow::anyhow!(
+                        "The audit log was tampered with: {} break(s) in the hash chain",
+                        report.breaks.len()
+                    ));
+                }
+            }
+            AuditCommands::TestEmail { to } => {
+                let mut email = config.audit.email.clone();
+                if !to.is_empty() {
+                    email.to = to;
+                }
+                if cli.mock {
                     println!(
-                        "{} {} [{}] - {}",
-                        record.installed_at.format("%Y-%m-%d %H:%M:%S"),
-                        record.package_name,
-                        record.box_type,
-                        status
+                        "🎭 [MOCK] Would send a test mail via {}:{} to {}",
+                        email.smtp_host,
+                        email.port(),
+                        email.to.join(", ")
                     );
+                    return Ok(());
                 }
-            }
 
-            HistoryCommands::Undo => {
-                let mut brain = OmniBrain::new_with_mock(cli.mock);
-                brain.undo_last().await?;
+                let subject = format!("[omni] Test alert from {}", audit::current_user());
+                let body = "omni can deliver audit alerts to this address.\n";
+                audit::send_email(&email, &subject, body).await?;
+                println!(
+                    "📧 Sent a test mail via {}:{} ({}) to {}",
+                    email.smtp_host,
+                    email.port(),
+                    email.tls,
+                    email.to.join(", ")
+                );
+                if !email.enabled {
+                    println!("ℹ️  Alerts stay off until audit.email.enabled is true");
+                }
             }
-        },
+            AuditCommands::TestChat { url, format } => {
+                let channels = match url {
+                    Some(url) => vec![config::ChatAlertConfig {
+                        url,
+                        format,
+                        ..Default::default()
+                    }],
+                    None => config.audit.chat.clone(),
+                };
+                if channels.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "No chat channels configured (audit.chat); pass --url to try one"
+                    ));
+                }
 
-        Commands::Snapshot { action } => {
-            let snapshot_manager = SnapshotManager::new().await?;
+                let entry = audit::AuditEntry::new(
+                    audit::AuditEventType::ConfigurationChange,
+                    audit::AuditSeverity::Info,
+                    "Test alert: omni can post audit alerts to this channel",
+                );
+                let mut failed = 0;
+                for chat in &channels {
+                    if cli.mock {
+                        println!(
+                            "🎭 [MOCK] Would post a {} test alert to {}",
+                            chat.format, chat.url
+                        );
+                        continue;
+                    }
+                    match audit::send_chat_alert(chat, &entry).await {
+                        Ok(()) => {
+                            println!("💬 Posted a {} test alert to {}", chat.format, chat.url)
+                        }
+                        Err(e) => {
+                            println!("❌ {}: {:#}", chat.url, e);
+                            failed += 1;
+                        }
+                    }
+                }
+                if failed > 0 {
+                    return Err(anyhow::anyhow!("{} chat channel(s) failed", failed));
+                }
+            }
+            AuditCommands::TestSyslog {
+                host,
+                port,
+                transport,
+            } => {
+                let targets = match host {
+                    Some(host) => vec![config::SyslogConfig {
+                        host,
+                        port,
+                        transport,
+                        ..Default::default()
+                    }],
+                    None => config.audit.syslog.clone(),
+                };
+                if targets.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "No syslog collectors configured (audit.syslog); pass --host to try one"
+                    ));
+                }
 
-            match action {
-                SnapshotCommands::Create { name, description } => {
-                    let snapshot_id = snapshot_manager
-                        .create_snapshot(&name, description.as_deref())
-                        .await?;
-                    println!("✅ Created snapshot '{}' with ID: {}", name, snapshot_id);
+                let entry = audit::AuditEntry::new(
+                    audit::AuditEventType::ConfigurationChange,
+                    audit::AuditSeverity::Info,
+                    "Test entry: omni can forward its audit log to this collector",
+                );
+                let mut failed = 0;
+                for target in &targets {
+                    let address = format!("{}:{}", target.host, target.port());
+                    if cli.mock {
+                        println!(
+                            "🎭 [MOCK] Would send a test entry to {} over {}",
+                            address, target.transport
+                        );
+                        continue;
+                    }
+                    match audit::send_syslog(target, &entry).await {
+                        Ok(()) => {
+                            println!(
+                                "📜 Sent a test entry to {} over {}",
+                                address, target.transport
+                            );
+                            if target.transport == config::SyslogTransport::Udp {
+                                println!("ℹ️  UDP is not acknowledged; check the collector");
+                            }
+                        }
+                        Err(e) => {
+                            println!("❌ {:#}", e);
+                            failed += 1;
+                        }
+                    }
+                }
+                if failed > 0 {
+                    return Err(anyhow::anyhow!("{} syslog collector(s) failed", failed));
                 }
+            }
+        },
+        Commands::Permissions {
+            action: PermissionsCommands::Repair { dry_run },
+        } => repair_permissions(dry_run)?,
+        Commands::Logs { action } => match action {
+            LogsCommands::Prune { dry_run } => {
+                let log_dir = OmniConfig::log_dir()?;
+                let now = chrono::Utc::now();
+                let files = logging::log_files(&log_dir)?;
+                let expired: Vec<logging::LogFile> = if dry_run || cli.mock {
+                    logging::expired(&files, &config.logging, now)
+                        .into_iter()
+                        .cloned()
+                        .collect()
+                } else {
+                    logging::prune_logs(&log_dir, &config.logging, now)?
+                };
+                let verb = if dry_run || cli.mock {
+                    "Would delete"
+                } else {
+                    "Deleted"
+                };
+                for file in &expired {
+                    println!("🗑️  {} {}", verb, file.path.display());
+                }
+                let bytes: u64 = expired.iter().map(|file| file.size).sum();
+                println!(
+                    "📜 {} {} log file(s), {} KiB; {} kept",
+                    verb,
+                    expired.len(),
+                    bytes / 1024,
+                    files.len() - expired.len()
+                );
 
-                SnapshotCommands::List => {
-                    let snapshots = snapshot_manager.list_snapshots().await?;
+                match config.audit.retention_days {
+                    Some(days) => {
+                        let cutoff = now - chrono::Duration::days(i64::from(days));
+                        let audit = audit::AuditManager::new().await?;
+                        let entries = if dry_run || cli.mock {
+                            audit.count_before(cutoff).await?
+                        } else {
+                            audit.prune(cutoff).await?
+                        };
+                        println!(
+                            "🔍 {} {} audit entries older than {} days",
+                            verb, entries, days
+                        );
+                    }
+                    None => println!("ℹ️  Audit entries are kept (audit.retention_days is unset)"),
+                }
+            }
+        },
+        Commands::Report { action } => match action {
+            ReportCommands::Digest {
+                period,
+                format,
+                output,
+                email,
+            } => {
+                let db = database::Database::new().await?;
+                let digest = digest::Digest::build(&db, period).await?;
+                let report = digest.render(format);
+                match &output {
+                    Some(path) => {
+                        std::fs::write(path, &report)?;
+                        println!("📊 Wrote the {} digest to {}", period, path.display());
+                    }
+                    None => print!("{}", report),
+                }
 
-                    if snapshots.is_empty() {
-                        println!("No snapshots found");
+                if email {
+                    let to = &config.daemon.digest.to;
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would mail the {} digest", period);
                         return Ok(());
                     }
+                    digest.send_email(&config.audit.email, to).await?;
+                    let recipients = if to.is_empty() {
+                        &config.audit.email.to
+                    } else {
+                        to
+                    };
+                    println!("📧 Mailed the digest to {}", recipients.join(", "));
+                }
+            }
+            ReportCommands::Vendored {
+                package,
+                format,
+                output,
+            } => {
+                let reports = vendored::scan(package.as_deref());
+                if reports.is_empty() {
+                    return match package {
+                        Some(package) => Err(anyhow::anyhow!(
+                            "No snap, flatpak or AppImage named {} is installed",
+                            package
+                        )),
+                        None => {
+                            println!("📦 No snaps, flatpaks or AppImages installed");
+                            Ok(())
+                        }
+                    };
+                }
 
-                    println!("📸 Available snapshots:\n");
-
-                    for snapshot in snapshots {
-                        println!("Name: {}", snapshot.name);
-                        println!("ID: {}", snapshot.id);
+                let report = match format {
+                    vendored::VendoredFormat::Text => vendored::BundleReport::to_text(&reports),
+                    vendored::VendoredFormat::Json => {
+                        format!("{}\n", serde_json::to_string_pretty(&reports)?)
+                    }
+                    vendored::VendoredFormat::Cyclonedx => format!(
+                        "{}\n",
+                        serde_json::to_string_pretty(&vendored::BundleReport::to_cyclonedx(
+                            &reports
+                        ))?
+                    ),
+                };
+                match &output {
+                    Some(path) => {
+                        std::fs::write(path, &report)?;
                         println!(
-                            "Created: {}",
-                            snapshot.created_at.format("%Y-%m-%d %H:%M:%S")
+                            "📊 Wrote the report on {} bundles to {}",
+                            reports.len(),
+                            path.display()
                         );
-                        println!("Packages: {}", snapshot.packages.len());
-                        if let Some(desc) = &snapshot.description {
-                            println!("Description: {}", desc);
-                        }
-                        println!();
                     }
-                }
-
-                SnapshotCommands::Revert { snapshot } => {
-                    snapshot_manager.revert_to_snapshot(&snapshot).await?;
+                    None => print!("{}", report),
                 }
             }
+        },
+        Commands::DistUpgrade {
+            to,
+            check,
+            verify,
+            yes,
+        } => {
+            if cli.mock {
+                println!("🎭 [MOCK] Would upgrade the distribution to its next release");
+                return Ok(());
+            }
+            if verify {
+                dist_upgrade::verify()?;
+            } else {
+                dist_upgrade::run(&config, to, check, yes).await?;
+            }
         }
-
-        Commands::Gui => {
-            #[cfg(feature = "gui")]
-            {
-                gui::launch_gui();
+        Commands::SelfUpdate { check, force } => {
+            if cli.mock {
+                println!(
+                    "🎭 [MOCK] Would check {} for a newer omni",
+                    config.self_update.endpoint
+                );
+                return Ok(());
             }
-            #[cfg(not(feature = "gui"))]
+
+            match self_update::SelfUpdater::new(&config)?
+                .run(check, force)
+                .await?
             {
-                println!("❌ GUI feature not compiled. Please rebuild with --features gui");
+                self_update::Outcome::UpToDate(version) => {
+                    println!("✅ omni {} is the latest release", version);
+                }
+                self_update::Outcome::Available(version) => {
+                    println!(
+                        "🆕 omni {} is available (running {}); run `omni self-update` to install it",
+                        version,
+                        self_update::current_version()
+                    );
+                }
+                self_update::Outcome::Updated { from, to } => {
+                    println!("✅ Updated omni from {} to {}", from, to);
+                }
             }
         }
 
-        Commands::Config { action } => {
-            match action {
-                ConfigCommands::Show => {
-                    println!("📋 Current configuration:\n");
-                    println!("{}", serde_yaml::to_string(&config)?);
+        Commands::Serve { http, grpc } => {
+            let http = match (http, grpc) {
+                (None, None) => Some(server::DEFAULT_ADDR.parse()?),
+                (http, _) => http,
+            };
+            let rest = async {
+                match http {
+                    Some(addr) => server::serve(addr, cli.mock).await,
+                    None => Ok(()),
+                }
+            };
+            let rpc = async {
+                match grpc {
+                    #[cfg(feature = "grpc")]
+                    Some(addr) => api::serve(addr, config.clone(), cli.mock).await,
+                    #[cfg(not(feature = "grpc"))]
+                    Some(_) => Err(anyhow::anyhow!(
+                        "gRPC support is not compiled in; rebuild with --features grpc"
+                    )),
+                    None => Ok(()),
                 }
+            };
+            tokio::try_join!(rest, rpc)?;
+        }
 
-                ConfigCommands::Edit => {
-                    let config_path = OmniConfig::config_path()?;
-                    println!("📝 Edit configuration file: {}", config_path.display());
+        Commands::Users { action } => {
+            let store = authorization::UserStore::open()?;
 
-                    // Try to open with default editor
-                    if let Ok(editor) = std::env::var("EDITOR") {
-                        std::process::Command::new(editor)
-                            .arg(&config_path)
-                            .status()?;
+            match action {
+                UserCommands::List { json } => {
+                    let users = store.list()?;
+                    if json {
+                        let users: Vec<_> = users
+                            .iter()
+                            .map(|u| {
+                                serde_json::json!({
+                                    "name": u.name,
+                                    "role": u.role,
+                                    "created_at": u.created_at,
+                                })
+                            })
+                            .collect();
+                        println!("{}", serde_json::to_string_pretty(&users)?);
+                    } else if users.is_empty() {
+                        println!("No API users; only the owner's token can call omni serve");
                     } else {
-                        println!("Set EDITOR environment variable or edit manually");
+                        println!("👥 API users:");
+                        for user in &users {
+                            println!(
+                                "  {:<20} {:<9} added {}",
+                                user.name,
+                                user.role,
+                                user.created_at.format("%Y-%m-%d")
+                            );
+                        }
+                    }
+                }
+
+                UserCommands::Add { name, role } => {
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would add API user {} as {}", name, role);
+                        return Ok(());
+                    }
+
+                    let token = store.add(&name, role)?;
+                    println!("✅ Added {} as {}", name, role);
+                    println!("🔑 Token (shown only once): {}", token);
+                }
+
+                UserCommands::Remove { name } => {
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would remove API user {}", name);
+                        return Ok(());
+                    }
+
+                    if !store.remove(&name)? {
+                        return Err(anyhow::anyhow!("No API user named {}", name));
+                    }
+                    println!("✅ Removed {}", name);
+                }
+
+                UserCommands::SetRole { name, role } => {
+                    if cli.mock {
+                        println!("🎭 [MOCK] Would make {} a {}", name, role);
+                        return Ok(());
+                    }
+
+                    if !store.set_role(&name, role)? {
+                        return Err(anyhow::anyhow!("No API user named {}", name));
+                    }
+                    println!("✅ {} is now a {}", name, role);
+                }
+
+                UserCommands::Roles => {
+                    for role in authorization::Role::ALL {
+                        let allowed: Vec<_> = authorization::Permission::ALL
+                            .iter()
+                            .filter(|p| role.allows(**p))
+                            .map(|p| p.describe())
+                            .collect();
+                        println!("{:<9} {}", role, allowed.join(", "));
                     }
                 }
+            }
+        }
+
+        Commands::Web { port } => {
+            server::serve(([127, 0, 0, 1], port).into(), cli.mock).await?;
+        }
+
+        // Answered in run() before the config loads
+        Commands::Help { .. } | Commands::Man { .. } => {}
+    }
+
+    Ok(())
+}
+
+/// Report, and unless `dry_run` fix, files omni wrote with the wrong owner or mode
+fn repair_permissions(dry_run: bool) -> Result<()> {
+    let findings = ownership::scan()?;
+    if findings.is_empty() {
+        println!("✅ Files omni wrote have the right owner and mode");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for finding in &findings {
+        if dry_run {
+            println!("🔎 {}", finding);
+            continue;
+        }
+        match ownership::repair(finding) {
+            Ok(()) => println!("🔧 Fixed {}", finding),
+            Err(e) => {
+                failed += 1;
+                println!("❌ {}: {}", finding, e);
+            }
+        }
+    }
+
+    if dry_run {
+        println!(
+            "📋 {} problem(s); run without --dry-run to fix them",
+            findings.len()
+        );
+    } else if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} problem(s) could not be fixed; changing owners needs sudo",
+            failed,
+            findings.len()
+        ));
+    }
+    Ok(())
+}
+
+/// `omni config validate`: every problem in a config file, with its YAML path.
+/// Older layouts are checked as they will be after migrating.
+fn validate_config(file: Option<std::path::PathBuf>) -> Result<()> {
+    let path = match file {
+        Some(path) => path,
+        None => OmniConfig::config_path()?,
+    };
+    if !path.exists() {
+        println!("✅ {} does not exist; omni uses the defaults", path.display());
+        return Ok(());
+    }
+
+    let mut value: serde_yaml::Value = serde_yaml::from_str(&std::fs::read_to_string(&path)?)
+        .map_err(|e| anyhow::anyhow!("{} is not valid YAML: {}", path.display(), e))?;
+    if let Some(report) = config_migration::migrate(&mut value)? {
+        println!(
+            "🔄 {} is version {}; omni migrates it to {} on the next load",
+            path.display(),
+            report.from,
+            report.to
+        );
+    }
+
+    let problems = config_validation::validate(&value);
+    if problems.is_empty() {
+        println!("✅ {} is valid", path.display());
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("❌ {}", config_validation::describe(problem));
+    }
+    Err(anyhow::anyhow!(
+        "{} problem(s) in {}",
+        problems.len(),
+        path.display()
+    ))
+}
+
+/// A secret typed twice on the terminal, or stdin up to the end when it is not one
+fn read_secret(prompt: &str) -> Result<String> {
+    if std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        return Ok(dialoguer::Password::new()
+            .with_prompt(prompt)
+            .with_confirmation("Confirm secret", "Secrets do not match")
+            .interact()?);
+    }
+    let mut secret = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut secret)?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Prompt for a host password and store it in the keyring under `service`
+fn store_host_password(
+    inventory: &FleetInventory,
+    name: &str,
+    service: &str,
+    purpose: &str,
+) -> Result<()> {
+    if !inventory.hosts.iter().any(|h| h.name == name) {
+        return Err(anyhow::anyhow!("Host {} is not in the inventory", name));
+    }
+
+    let password = dialoguer::Password::new()
+        .with_prompt(format!("{} password for {}", purpose, name))
+        .with_confirmation("Confirm password", "Passwords do not match")
+        .interact()?;
+    credentials::keyring_set(service, name, &password)?;
+    println!("🔑 Stored {} password for {} in the keyring", purpose, name);
+    Ok(())
+}
+
+fn print_drift_report(report: &[fleet::HostDrift]) {
+    println!("📊 Drift per host:");
+    for host in report {
+        match &host.error {
+            Some(error) => println!("  ❌ {}: {}", host.host, error),
+            None if host.is_clean() => println!("  ✅ {}: in sync", host.host),
+            None => println!(
+                "  ⚠️  {}: {} missing, {} extra, {} version mismatch",
+                host.host,
+                host.count(fleet::DriftKind::Missing),
+                host.count(fleet::DriftKind::Extra),
+                host.count(fleet::DriftKind::VersionMismatch)
+            ),
+        }
+    }
+
+    let matrix = fleet::drift_matrix(report);
+    if matrix.is_empty() {
+        return;
+    }
+
+    let hosts: Vec<&str> = report.iter().map(|h| h.host.as_str()).collect();
+    let package_width = matrix.keys().map(|p| p.len()).max().unwrap_or(0).max(7);
+    let column_widths: Vec<usize> = hosts
+        .iter()
+        .map(|host| {
+            matrix
+                .values()
+                .filter_map(|row| row.get(*host))
+                .map(|cell| cell.len())
+                .max()
+                .unwrap_or(0)
+                .max(host.len())
+        })
+        .collect();
+
+    println!();
+    print!("{:<width$}", "PACKAGE", width = package_width);
+    for (host, width) in hosts.iter().zip(&column_widths) {
+        print!("  {:<width$}", host, width = width);
+    }
+    println!();
+
+    for (package, row) in &matrix {
+        print!("{:<width$}", package, width = package_width);
+        for (host, width) in hosts.iter().zip(&column_widths) {
+            let cell = row.get(*host).map(|c| c.as_str()).unwrap_or("-");
+            print!("  {:<width$}", cell, width = width);
+        }
+        println!();
+    }
+}
+
+/// Flags shared by fleet package operations
+struct FleetRunOptions {
+    confirm: bool,
+    mock: bool,
+    /// Print JSON-lines events instead of the human summary
+    events: bool,
+    verbose: bool,
+    health_checks: config::HealthChecksConfig,
+    /// Gets the outcome for the `fleet` notification routes
+    notifier: notifications::Dispatcher,
+}
+
+/// Live progress for a terminal: which host is being worked on, plus its output with --verbose
+/// Operations given as `--install`, `--remove` and `--update` specs, as
+/// (operation, box, package, version)
+fn operation_specs(
+    install: &[String],
+    remove: &[String],
+    update: &[String],
+) -> Vec<(
+    transaction::OperationType,
+    Option<String>,
+    String,
+    Option<String>,
+)> {
+    install
+        .iter()
+        .map(|spec| (transaction::OperationType::InstallPackage, spec))
+        .chain(
+            remove
+                .iter()
+                .map(|spec| (transaction::OperationType::RemovePackage, spec)),
+        )
+        .chain(
+            update
+                .iter()
+                .map(|spec| (transaction::OperationType::UpdatePackage, spec)),
+        )
+        .map(|(operation_type, spec)| {
+            let (box_type, package, version) = transaction::parse_operation_spec(spec);
+            (operation_type, box_type, package, version)
+        })
+        .collect()
+}
+
+fn human_event_sink(verbose: bool) -> events::EventSink {
+    events::EventSink::new(move |record| match &record.event {
+        events::RemoteEvent::Started {
+            host,
+            operation,
+            target,
+        } => println!("⏳ {}: {} {}...", host, operation, target),
+        events::RemoteEvent::Output { host, line, .. } if verbose => {
+            println!("   [{}] {}", host, line)
+        }
+        events::RemoteEvent::Progress { host, percent, .. } if verbose => {
+            println!("   [{}] {}%", host, percent)
+        }
+        _ => {}
+    })
+}
+
+/// What depends on `package`, from the mock universe or every backend
+async fn reverse_dependencies(
+    package: &str,
+    box_type: Option<&str>,
+    installed_only: bool,
+    mock: bool,
+) -> Result<Vec<search::ReverseDependencies>> {
+    if mock {
+        let packages = mock::backend().reverse_dependencies(package, installed_only);
+        return Ok(if packages.is_empty() {
+            Vec::new()
+        } else {
+            vec![search::ReverseDependencies {
+                box_type: "mock".to_string(),
+                packages,
+            }]
+        });
+    }
+    SearchEngine::new()
+        .await?
+        .find_reverse_dependencies(package, box_type, installed_only)
+        .await
+}
+
+fn print_reverse_dependencies(reports: &[search::ReverseDependencies]) {
+    for report in reports {
+        println!("  [{}] {}", report.box_type, report.packages.join(", "));
+    }
+}
+
+fn print_changelog(box_type: &str, package: &str) {
+    match changelog::fetch(box_type, package, None, changelog::DEFAULT_MAX_ENTRIES) {
+        Ok(text) if !text.is_empty() => println!("📝 Changelog ({}):\n{}\n", box_type, text),
+        Ok(_) => println!("📝 No changelog entries for {} ({})\n", package, box_type),
+        Err(e) => println!("⚠️  {}\n", e),
+    }
+}
+
+/// Point out installed packages with known vulnerabilities after the list of
+/// updates. Scans are cached, and a distribution OSV does not cover or a
+/// failed scan only leaves the warning out.
+async fn warn_vulnerable(settings: &config::VulnerabilityConfig) {
+    if !settings.check_on_update || vulnerabilities::Ecosystem::detect().is_none() {
+        return;
+    }
+    let report = match vulnerabilities::scan_installed(settings, None, false).await {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::debug!("No vulnerability scan: {}", e);
+            return;
+        }
+    };
+    if report.findings.is_empty() {
+        return;
+    }
+    let packages: Vec<String> = report
+        .findings
+        .iter()
+        .map(|f| format!("{} ({})", f.package.name, f.vulnerabilities.len()))
+        .collect();
+    println!(
+        "\n⚠️  {} known vulnerabilities in installed packages: {}",
+        report.vulnerability_count(),
+        packages.join(", ")
+    );
+    println!("   Run 'omni scan' for details and the versions that fix them");
+}
 
-                ConfigCommands::Reset => {
-                    let default_config = OmniConfig::default();
-                    default_config.save()?;
-                    println!("✅ Configuration reset to defaults");
+/// Show what each pending update changes and ask before applying them. Without a
+/// terminal to ask on, the review is printed and the update goes ahead.
+fn review_updates(
+    update_manager: &UpdateManager,
+    candidates: &[updater::UpdateCandidate],
+) -> Result<bool> {
+    println!("📦 {} updates available:\n", candidates.len());
+    for candidate in candidates {
+        println!(
+            "🔄 {} [{}]: {} -> {}",
+            candidate.package_name,
+            candidate.box_type,
+            candidate.current_version.as_deref().unwrap_or("unknown"),
+            candidate.available_version.as_deref().unwrap_or("latest")
+        );
+        match update_manager.changelog(candidate) {
+            Ok(text) if !text.is_empty() => {
+                for line in text.lines() {
+                    println!("    {}", line);
                 }
             }
+            Ok(_) => println!("    (no changelog entries)"),
+            Err(e) => println!("    (changelog unavailable: {})", e),
         }
+        println!();
+    }
 
-        Commands::Resolve {
-            package,
-            box_type,
-            detailed,
-        } => {
-            let resolver = resolver::DependencyResolver::new().await?;
-            let plan = resolver
-                .resolve_dependencies(&package, box_type.as_deref())
-                .await?;
+    if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        return Ok(true);
+    }
+    interactive::InteractivePrompts::new()
+        .get_confirmation(&format!("Apply {} updates?", candidates.len()), true)
+}
 
-            println!("🔍 Dependency resolution for '{}':\n", package);
+/// Start `omni index refresh` in the background when a catalog in the search
+/// index is old or missing, so the search does not wait for it. The refresh
+/// holds the index lock until it is done; one started while another runs
+/// exits at once
+async fn refresh_stale_index(engine: &SearchEngine) {
+    let max_age = OmniConfig::load()
+        .unwrap_or_default()
+        .search
+        .index_max_age_hours;
+    if max_age == 0
+        || !engine
+            .stale_index_boxes(max_age)
+            .await
+            .is_ok_and(|stale| !stale.is_empty())
+    {
+        return;
+    }
+    let spawned = std::env::current_exe().and_then(|exe| {
+        std::process::Command::new(exe)
+            .args(["index", "refresh"])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+    });
+    if let Err(e) = spawned {
+        tracing::warn!("Could not start refreshing the search index: {}", e);
+    }
+}
 
-            if plan.packages.is_empty() {
-                println!("No dependencies found or package not available.");
-                return Ok(());
-            }
+/// Install `package` and the dependencies omni's resolver plans for it as one
+/// transaction, removing the installed packages conflicts were settled by
+/// removing first
+async fn install_resolved(package: &str, box_type: Option<&str>) -> Result<()> {
+    let resolver = resolver::DependencyResolver::new().await?;
+    let mut plan = resolver.resolve_dependencies(package, box_type).await?;
 
-            println!("📦 Packages to install ({}):", plan.packages.len());
-            for (i, pkg) in plan.packages.iter().enumerate() {
-                let marker = if i == 0 { "🎯" } else { "📎" };
-                println!(
-                    "{} {} [{}] v{}",
-                    marker, pkg.name, pkg.box_type, pkg.version
-                );
+    if !plan.conflicts.is_empty() && std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        let prompts = interactive::InteractivePrompts::new();
+        if !resolver
+            .resolve_conflicts(&mut plan, |conflict| prompts.resolve_conflict(conflict))
+            .await?
+        {
+            println!("❌ Installation aborted");
+            return Ok(());
+        }
+    }
+    if !plan.conflicts.is_empty() {
+        let conflicts: Vec<String> = plan.conflicts.iter().map(|c| c.to_string()).collect();
+        return Err(anyhow::anyhow!(
+            "Unresolved conflicts; run 'omni install {} --resolve-deps' in a terminal to settle them:\n  {}",
+            package,
+            conflicts.join("\n  ")
+        ));
+    }
+    if plan.packages.is_empty() {
+        return Err(anyhow::anyhow!("Nothing to install for {}", package));
+    }
+    for warning in &plan.warnings {
+        println!("⚠️  {}", warning);
+    }
 
-                if detailed && !pkg.dependencies.is_empty() {
-                    for dep in &pkg.dependencies {
-                        let opt = if dep.optional { " (optional)" } else { "" };
-                        println!("   └─ {}{}", dep.name, opt);
-                    }
-                }
-            }
+    let mut manager = transaction::TransactionManager::new().await?;
+    let id = manager
+        .begin_transaction(transaction::TransactionType::Install)
+        .await?;
+    manager.add_resolution_plan(id, &plan).await?;
+    transaction::print_plan(&manager.plan(id).await?);
+    manager.execute_transaction(id).await?;
+    manager.commit_transaction(id).await?;
+    println!("✅ Installed {} and its dependencies", package);
+    Ok(())
+}
 
-            if let Some(size) = plan.total_size {
-                println!(
-                    "\n💾 Total size: {}",
-                    resolver::DependencyResolver::format_size(size)
-                );
-            }
+/// List updates from least to most impact. Those up to `auto_apply` are
+/// selected; each one above needs a yes, and is skipped without a terminal.
+async fn review_by_impact(
+    update_manager: &UpdateManager,
+    candidates: &[updater::UpdateCandidate],
+    auto_apply: config::Impact,
+) -> Result<Vec<updater::UpdateCandidate>> {
+    let mut classified = Vec::new();
+    for candidate in candidates {
+        classified.push((update_manager.impact(candidate).await, candidate));
+    }
+    classified.sort_by_key(|(impact, _)| *impact);
 
-            if !plan.conflicts.is_empty() {
-                println!("\n⚠️  Conflicts:");
-                for conflict in &plan.conflicts {
-                    println!("   • {}", conflict);
-                }
-            }
+    println!("📦 {} updates available:\n", classified.len());
+    for (impact, candidate) in &classified {
+        let icon = match impact {
+            config::Impact::Safe => "🟢",
+            config::Impact::Minor => "🟡",
+            config::Impact::Major => "🟠",
+            config::Impact::Breaking => "🔴",
+        };
+        println!(
+            "{} {:<8} {} [{}]: {} -> {}",
+            icon,
+            impact,
+            candidate.package_name,
+            candidate.box_type,
+            candidate.current_version.as_deref().unwrap_or("unknown"),
+            candidate.available_version.as_deref().unwrap_or("latest")
+        );
+    }
+    println!();
 
-            if !plan.warnings.is_empty() {
-                println!("\n⚠️  Warnings:");
-                for warning in &plan.warnings {
-                    println!("   • {}", warning);
+    let interactive = std::io::IsTerminal::is_terminal(&std::io::stdin());
+    let prompts = interactive::InteractivePrompts::new();
+    let mut selected = Vec::new();
+    for (impact, candidate) in classified {
+        if impact <= auto_apply {
+            selected.push(candidate.clone());
+            continue;
+        }
+        if !interactive {
+            println!(
+                "⏭️  Skipping {} ({} update); run 'omni update --review' in a terminal to confirm it",
+                candidate.package_name, impact
+            );
+            continue;
+        }
+
+        println!(
+            "🔍 {} [{}] is a {} update:",
+            candidate.package_name, candidate.box_type, impact
+        );
+        match update_manager.changelog(candidate) {
+            Ok(text) if !text.is_empty() => {
+                for line in text.lines() {
+                    println!("    {}", line);
                 }
             }
+            Ok(_) => println!("    (no changelog entries)"),
+            Err(e) => println!("    (changelog unavailable: {})", e),
+        }
+        let question = format!("Apply the update of {}?", candidate.package_name);
+        if prompts.get_confirmation(&question, false)? {
+            selected.push(candidate.clone());
         }
+    }
+    Ok(selected)
+}
 
-        Commands::Verify {
-            file_path,
-            checksum,
-            signature,
-            box_type,
-        } => {
-            use security::{SecurityPolicy, SecurityVerifier};
-            use std::path::Path;
+/// Major updates of packages in `policies.critical` show what changed
+/// upstream and need a yes, `--yes` or not; without a terminal they are
+/// skipped. `force` lets them through without asking.
+async fn confirm_critical_updates(
+    update_manager: &UpdateManager,
+    candidates: Vec<updater::UpdateCandidate>,
+    force: bool,
+) -> Result<Vec<updater::UpdateCandidate>> {
+    let interactive = std::io::IsTerminal::is_terminal(&std::io::stdin());
+    let prompts = interactive::InteractivePrompts::new();
+    let mut confirmed = Vec::new();
+    for candidate in candidates {
+        let Some(impact) = update_manager.critical_major(&candidate).await else {
+            confirmed.push(candidate);
+            continue;
+        };
+        if force {
+            println!(
+                "⚠️  Applying the {} update of critical package {} (--force-critical)",
+                impact, candidate.package_name
+            );
+            confirmed.push(candidate);
+            continue;
+        }
 
-            let policy = SecurityPolicy::default();
-            let verifier = SecurityVerifier::new(policy);
+        println!(
+            "\n🛡️  {} [{}] is critical; this is a {} update: {} -> {}",
+            candidate.package_name,
+            candidate.box_type,
+            impact,
+            candidate.current_version.as_deref().unwrap_or("unknown"),
+            candidate.available_version.as_deref().unwrap_or("latest")
+        );
+        show_upstream_changes(update_manager, &candidate);
+        if !interactive {
+            println!(
+                "⏭️  Skipping {}; confirm it in a terminal or pass --force-critical",
+                candidate.package_name
+            );
+            continue;
+        }
+        let question = format!(
+            "Apply the {} update of critical package {}?",
+            impact, candidate.package_name
+        );
+        if prompts.get_confirmation(&question, false)? {
+            confirmed.push(candidate);
+        }
+    }
+    Ok(confirmed)
+}
 
-            let path = Path::new(&file_path);
-            if !path.exists() {
-                return Err(anyhow::anyhow!("File not found: {}", file_path));
+/// The changelog entries of an update, with the release notes and a GitHub
+/// compare link from the package's AppStream metadata when it has some
+fn show_upstream_changes(update_manager: &UpdateManager, candidate: &updater::UpdateCandidate) {
+    match update_manager.changelog(candidate) {
+        Ok(text) if !text.is_empty() => {
+            for line in text.lines() {
+                println!("    {}", line);
             }
+        }
+        Ok(_) => println!("    (no changelog entries)"),
+        Err(e) => println!("    (changelog unavailable: {})", e),
+    }
 
-            println!("🔒 Verifying security for: {}", file_path);
+    let (Some(from), Some(to)) = (
+        candidate.current_version.as_deref(),
+        candidate.available_version.as_deref(),
+    ) else {
+        return;
+    };
+    let Some(component) = appstream::find(&candidate.package_name) else {
+        return;
+    };
+    for release in component.releases_between(from, to) {
+        println!(
+            "  📰 {} {}",
+            release.version,
+            release.date.as_deref().unwrap_or_default()
+        );
+        for line in release.notes.lines() {
+            println!("    {}", line);
+        }
+    }
+    if let Some(url) = component.compare_url(from, to) {
+        println!("  🔗 {}", url);
+    }
+}
 
-            let result = verifier
-                .verify_package(
-                    path,
-                    checksum.as_deref(),
-                    signature.as_deref(),
-                    box_type.as_deref().unwrap_or("unknown"),
-                )
-                .await?;
+/// Updating single pacman packages while others have upgrades pending is a
+/// partial upgrade, which Arch does not support. Warn and offer a full
+/// upgrade; returns the candidates still to update, or `None` when cancelled.
+async fn settle_partial_upgrade(
+    update_manager: &UpdateManager,
+    candidates: &[updater::UpdateCandidate],
+    policy: config::PartialUpgradePolicy,
+    full_sync: bool,
+) -> Result<Option<Vec<updater::UpdateCandidate>>> {
+    let Some(partial) = update_manager.partial_upgrade(candidates)? else {
+        return Ok(Some(candidates.to_vec()));
+    };
+    let allow_partial = policy == config::PartialUpgradePolicy::Warn;
 
-            println!("\n📋 Verification Results:");
-            println!("{}", "─".repeat(50));
-            println!("{}", result.details);
+    println!(
+        "\n⚠️  PARTIAL UPGRADE: updating {} alone leaves {} other packages at their old versions:",
+        partial.requested.join(", "),
+        partial.left_behind.len()
+    );
+    println!("   {}", partial.left_behind.join(" "));
+    println!("   Arch does not support partial upgrades; they can break shared libraries");
+    println!("   and leave pacman itself unusable. Upgrade the whole system instead.\n");
 
-            match result.trust_level {
-                security::TrustLevel::Trusted => println!("✅ Package is trusted and verified"),
-                security::TrustLevel::Valid => println!("✅ Package signature is valid"),
-                security::TrustLevel::Unsigned => {
-                    println!("⚠️  Package is unsigned but checksum verified")
-                }
-                security::TrustLevel::Untrusted => println!("❌ Package failed verification"),
+    let choice = if full_sync {
+        updater::PartialUpgradeChoice::FullSync
+    } else if std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        interactive::InteractivePrompts::new().resolve_partial_upgrade(allow_partial)?
+    } else {
+        println!("💡 Run again with --full-sync to upgrade the whole system");
+        updater::PartialUpgradeChoice::Cancel
+    };
+
+    match choice {
+        updater::PartialUpgradeChoice::FullSync => {
+            update_manager.full_sync(candidates).await?;
+            Ok(Some(
+                candidates
+                    .iter()
+                    .filter(|c| c.box_type != "pacman")
+                    .cloned()
+                    .collect(),
+            ))
+        }
+        updater::PartialUpgradeChoice::Partial => Ok(Some(candidates.to_vec())),
+        updater::PartialUpgradeChoice::Cancel => {
+            if !allow_partial {
+                println!("🚫 policies.partial_upgrades is 'forbid'");
             }
+            println!("❌ Update cancelled");
+            Ok(None)
         }
+    }
+}
 
-        Commands::Hardware { action } => {
-            let mut brain = OmniBrain::new_with_mock(cli.mock);
+/// Queue a large update for the next maintenance window when none is open;
+/// true when it was queued
+fn queue_for_window(
+    settings: &config::MaintenanceConfig,
+    candidates: &[updater::UpdateCandidate],
+) -> Result<bool> {
+    let windows = maintenance::MaintenanceWindows::from_config(settings)?;
+    let now = maintenance::local_now();
+    let large = settings.large_transaction > 0 && candidates.len() >= settings.large_transaction;
+    if !large || windows.is_open(now) {
+        return Ok(false);
+    }
 
-            match action {
-                HardwareCommands::Detect => {
-                    println!("🔍 Detecting server hardware configuration...");
-                    match brain.get_hardware_info() {
-                        Ok(info) => {
-                            println!("\n📋 Hardware Information:");
-                            println!("{}", "─".repeat(50));
-                            println!("{}", info);
-                        }
-                        Err(e) => {
-                            error!("❌ Failed to detect hardware: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
+    maintenance::enqueue(maintenance::QueuedTransaction {
+        queued_at: chrono::Utc::now(),
+        requested_by: "omni update --all".to_string(),
+        updates: candidates
+            .iter()
+            .map(|c| daemon::PendingUpdate::new(c, false))
+            .collect(),
+    })?;
+    println!(
+        "⏳ {} updates queued for the next maintenance window ({}); the daemon applies them then",
+        candidates.len(),
+        windows.next_open(now).map_or_else(
+            || "none within a year".to_string(),
+            |t| t.format("%Y-%m-%d %H:%M").to_string()
+        )
+    );
+    Ok(true)
+}
 
-                HardwareCommands::Install => {
-                    println!("🤖 Auto-detecting hardware and installing optimal drivers...");
-                    match brain.detect_and_install_drivers().await {
-                        Ok(()) => {
-                            println!("✅ Driver installation process completed");
-                        }
-                        Err(e) => {
-                            error!("❌ Driver installation failed: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
+/// What `omni update` says when nothing is left to update
+fn up_to_date(security_only: bool) -> &'static str {
+    if security_only {
+        "✅ No security updates available"
+    } else {
+        "✅ All packages are up to date"
+    }
+}
 
-                HardwareCommands::Vendor { vendor } => {
-                    println!("🏢 Installing {} vendor-specific drivers...", vendor);
-                    match brain.install_vendor_drivers(&vendor).await {
-                        Ok(()) => {
-                            println!("✅ {} vendor drivers installation completed", vendor);
-                        }
-                        Err(e) => {
-                            error!("❌ Vendor driver installation failed: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
-            }
-        }
+/// `omni update` against the mock backend
+fn mock_update(package: Option<&str>, all: bool, security_only: bool) -> Result<()> {
+    let backend = mock::backend();
+    let candidates: Vec<_> = backend
+        .updates()
+        .into_iter()
+        .filter(|c| package.map_or(true, |p| c.package_name == p))
+        .filter(|c| !security_only || c.advisory.is_some())
+        .collect();
 
-        Commands::Repository { action } => {
-            let mut brain = OmniBrain::new_with_mock(cli.mock);
+    if candidates.is_empty() {
+        println!("{}", up_to_date(security_only));
+        return Ok(());
+    }
 
-            match action {
-                RepositoryCommands::Add {
-                    repository,
-                    repo_type,
-                    key_url,
-                } => {
-                    println!("➕ Adding repository: {}", repository);
-                    match brain
-                        .add_repository(&repository, repo_type.as_deref(), key_url.as_deref())
-                        .await
-                    {
-                        Ok(()) => {
-                            println!("✅ Repository added successfully");
-                        }
-                        Err(e) => {
-                            error!("❌ Failed to add repository: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
+    for candidate in &candidates {
+        println!(
+            "  {}{} [{}]: {} -> {}",
+            if candidate.advisory.is_some() {
+                "🛡️  "
+            } else {
+                ""
+            },
+            candidate.package_name,
+            candidate.box_type,
+            candidate.current_version.as_deref().unwrap_or("unknown"),
+            candidate.available_version.as_deref().unwrap_or("latest")
+        );
+    }
+    if !all && !security_only && package.is_none() {
+        println!("\nRun 'omni update --all' to update all packages");
+        return Ok(());
+    }
 
-                RepositoryCommands::Remove { repository } => {
-                    println!("➖ Removing repository: {}", repository);
-                    match brain.remove_repository(&repository).await {
-                        Ok(()) => {
-                            println!("✅ Repository removed successfully");
-                        }
-                        Err(e) => {
-                            error!("❌ Failed to remove repository: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
+    backend.update(&candidates)?;
+    println!("✅ [MOCK] Updated {} packages", candidates.len());
+    Ok(())
+}
 
-                RepositoryCommands::List => {
-                    println!("📦 Configured repositories:");
-                    match brain.list_repositories().await {
-                        Ok(repositories) => {
-                            if repositories.is_empty() {
-                                println!("No repositories configured");
-                            } else {
-                                for repo in repositories {
-                                    println!("- {}", repo);
-                                }
-                            }
-                        }
-                        Err(e) => error!("❌ Failed to list repositories: {}", e),
-                    }
-                }
+async fn run_fleet_operation(
+    inventory: &FleetInventory,
+    operation: &str,
+    package: &str,
+    tags: Option<String>,
+    groups: Vec<String>,
+    hosts: Vec<String>,
+    options: FleetRunOptions,
+) -> Result<()> {
+    InputValidator::validate_package_name(package)?;
 
-                RepositoryCommands::Refresh => {
-                    println!("🔄 Refreshing repository metadata...");
-                    let update_manager = UpdateManager::new(config).await?;
-                    match update_manager.refresh_repositories().await {
-                        Ok(()) => {
-                            println!("✅ Repository refresh completed");
-                        }
-                        Err(e) => {
-                            error!("❌ Failed to refresh repositories: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
-            }
+    let selector = HostSelector::from_args(tags.as_deref(), &groups, &hosts)?;
+    let targets = inventory.select(&selector);
+    if targets.is_empty() {
+        return Err(anyhow::anyhow!("No fleet hosts match the given selection"));
+    }
+
+    inventory.check_policies(&targets, options.confirm)?;
+    health_checks::validate(&options.health_checks.checks)?;
+
+    if options.mock {
+        for host in &targets {
+            println!("🎭 [MOCK] Would {} {} on {}", operation, package, host.name);
         }
+        return Ok(());
+    }
 
-        Commands::Web { port } => {
-            server::start_server(port).await?;
+    let sink = if options.events {
+        events::EventSink::json_lines()
+    } else {
+        human_event_sink(options.verbose)
+    };
+    let results = FleetRunner::new()
+        .with_events(sink)
+        .with_health_checks(options.health_checks)
+        .run_package_operation(&targets, operation, package)
+        .await;
+
+    // With --events the summary event already attributes every failure to its host
+    if !options.events {
+        for result in &results {
+            let icon = if result.success { "✅" } else { "❌" };
+            println!("{} {}: {}", icon, result.host, result.message);
+        }
+
+        println!("\n🏷️  Results per tag:");
+        for (tag, report) in fleet::summarize_by_tag(&results) {
+            println!(
+                "  {}: {}/{} succeeded{}",
+                tag,
+                report.succeeded,
+                report.hosts,
+                if report.failed > 0 {
+                    format!(", {} failed", report.failed)
+                } else {
+                    String::new()
+                }
+            );
         }
     }
 
+    let failed = results.iter().filter(|r| !r.success).count();
+    let failed_hosts: Vec<&str> = results
+        .iter()
+        .filter(|r| !r.success)
+        .map(|r| r.host.as_str())
+        .collect();
+    let (severity, body) = if failed > 0 {
+        (
+            audit::AuditSeverity::Warning,
+            format!("Failed on {}", failed_hosts.join(", ")),
+        )
+    } else {
+        (audit::AuditSeverity::Info, String::new())
+    };
+    let title = format!(
+        "fleet {} {}: {} of {} hosts succeeded",
+        operation,
+        package,
+        results.len() - failed,
+        results.len()
+    );
+    options
+        .notifier
+        .dispatch(&notifications::Notification::new(
+            config::NotifyCategory::Fleet,
+            severity,
+            title,
+            body,
+        ))
+        .await;
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} hosts failed to {} {}",
+            failed,
+            results.len(),
+            operation,
+            package
+        ));
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_security_only_update_is_a_mutation() {
+        let cli = Cli::try_parse_from(["omni", "update", "--security-only"]).unwrap();
+        assert_eq!(cli.command.mutation(), Some("updating packages"));
+
+        let cli = Cli::try_parse_from(["omni", "update"]).unwrap();
+        assert_eq!(cli.command.mutation(), None);
+    }
+
+    #[test]
+    fn test_man_pages_cover_every_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = help::write_man_pages(Cli::command(), dir.path()).unwrap();
+        for page in ["omni.1", "omni-install.1", "omni-fleet-add-host.1"] {
+            assert!(dir.path().join(page).exists(), "{} is missing", page);
+        }
+        let visible = Cli::command()
+            .get_subcommands()
+            .filter(|c| !c.is_hide_set())
+            .count();
+        assert!(pages.len() > visible);
+    }
+}
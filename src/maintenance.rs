@@ -0,0 +1,302 @@
+//! Maintenance windows: cron-like times when updates may be applied, and the
+//! queue of updates waiting for the next window.
+
+use crate::config::{self, MaintenanceConfig, OmniConfig};
+use crate::daemon::PendingUpdate;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// Who queued the daemon's own unattended updates
+pub const DAEMON: &str = "daemon";
+
+/// How far ahead to look for the next window
+const SEARCH_DAYS: i64 = 366;
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A five-field cron expression (`minute hour day-of-month month day-of-week`)
+/// matching every minute that belongs to the window. `* 2-4 * * sat,sun` is
+/// open from 02:00 to 04:59 on weekends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Cron matches either day field when both are restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(anyhow!(
+                "'{}' needs five fields: minute hour day-of-month month day-of-week",
+                expression
+            ));
+        };
+
+        let field_error = |e: anyhow::Error| anyhow!("Invalid schedule '{}': {}", expression, e);
+        let mut weekdays = parse_field(weekday, 0, 7, WEEKDAYS, 0).map_err(field_error)?;
+        // 7 is Sunday as well
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[], 0).map_err(field_error)?,
+            hours: parse_field(hour, 0, 23, &[], 0).map_err(field_error)?,
+            days: parse_field(day, 1, 31, &[], 0).map_err(field_error)?,
+            months: parse_field(month, 1, 12, MONTHS, 1).map_err(field_error)?,
+            weekdays,
+            days_restricted: day != "*",
+            weekdays_restricted: weekday != "*",
+        })
+    }
+
+    pub fn matches(&self, time: NaiveDateTime) -> bool {
+        self.matches_day(time) && bit(self.hours, time.hour()) && bit(self.minutes, time.minute())
+    }
+
+    fn matches_day(&self, time: NaiveDateTime) -> bool {
+        let day = bit(self.days, time.day());
+        let weekday = bit(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        day_matches && bit(self.months, time.month())
+    }
+
+    /// First minute at or after `from` inside the window
+    pub fn next_match(&self, from: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time = from.with_second(0)?.with_nanosecond(0)?;
+        let end = from + Duration::days(SEARCH_DAYS);
+        while time < end {
+            if !self.matches_day(time) {
+                time = (time.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if !bit(self.hours, time.hour()) {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if !bit(self.minutes, time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+fn bit(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+/// Parse one field into a bitmask of the values it allows. `names` spell out
+/// values starting at `first_name`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], first_name: u32) -> Result<u64> {
+    let value = |text: &str| -> Result<u32> {
+        let lower = text.to_ascii_lowercase();
+        let value = match names.iter().position(|name| *name == lower) {
+            Some(index) => u32::try_from(index)? + first_name,
+            None => text
+                .parse()
+                .map_err(|_| anyhow!("'{}' is not a number", text))?,
+        };
+        if !(min..=max).contains(&value) {
+            return Err(anyhow!("{} is outside {}-{}", value, min, max));
+        }
+        Ok(value)
+    };
+
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| anyhow!("'{}' is not a step", step))?;
+                if step == 0 {
+                    return Err(anyhow!("a step must be at least 1"));
+                }
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // `5/15` runs from 5 to the end of the range
+            None if step.is_some() => (value(range)?, max),
+            None => {
+                let single = value(range)?;
+                (single, single)
+            }
+        };
+        if start > end {
+            return Err(anyhow!("range {} runs backwards", range));
+        }
+        for v in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+/// The configured windows; without any, updates may run at any time
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceWindows {
+    schedules: Vec<CronSchedule>,
+}
+
+impl MaintenanceWindows {
+    pub fn parse(expressions: &[String]) -> Result<Self> {
+        Ok(Self {
+            schedules: expressions
+                .iter()
+                .map(|e| CronSchedule::parse(e))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn from_config(config: &MaintenanceConfig) -> Result<Self> {
+        Self::parse(&config.windows)
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.schedules.is_empty()
+    }
+
+    /// Whether updates may be applied at `time` (local time)
+    pub fn is_open(&self, time: NaiveDateTime) -> bool {
+        !self.is_configured() || self.schedules.iter().any(|s| s.matches(time))
+    }
+
+    /// Start of the next window at or after `from` (local time)
+    pub fn next_open(&self, from: NaiveDateTime) -> Option<NaiveDateTime> {
+        if !self.is_configured() {
+            return Some(from);
+        }
+        self.schedules
+            .iter()
+            .filter_map(|s| s.next_match(from))
+            .min()
+    }
+}
+
+/// Local time now, as the windows are written in
+pub fn local_now() -> NaiveDateTime {
+    chrono::Local::now().naive_local()
+}
+
+/// Updates held back until a maintenance window opens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedTransaction {
+    pub queued_at: DateTime<Utc>,
+    /// `daemon` or the command that queued the updates
+    pub requested_by: String,
+    pub updates: Vec<PendingUpdate>,
+}
+
+pub fn queue_path() -> Result<PathBuf> {
+    Ok(OmniConfig::state_dir()?.join("queue.json"))
+}
+
+pub fn load_queue() -> Result<Vec<QueuedTransaction>> {
+    let path = queue_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(&path)?)?)
+}
+
+pub fn save_queue(queue: &[QueuedTransaction]) -> Result<()> {
+    let path = queue_path()?;
+    if queue.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        config::ensure_private_dir(parent)?;
+    }
+    config::write_private_file(&path, serde_json::to_string_pretty(queue)?.as_bytes())
+}
+
+/// Add `transaction` to the queue, replacing earlier ones from the same requester
+pub fn enqueue(transaction: QueuedTransaction) -> Result<()> {
+    let mut queue = load_queue()?;
+    queue.retain(|t| t.requested_by != transaction.requested_by);
+    queue.push(transaction);
+    save_queue(&queue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // June 2024 starts on a Saturday
+        NaiveDate::from_ymd_opt(2024, 6, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_weekend_night_window() {
+        let schedule = CronSchedule::parse("* 2-4 * * sat,sun").unwrap();
+        assert!(schedule.matches(at(1, 2, 0)));
+        assert!(schedule.matches(at(2, 4, 59)));
+        assert!(!schedule.matches(at(1, 5, 0)));
+        assert!(!schedule.matches(at(3, 3, 0)));
+
+        assert_eq!(schedule.next_match(at(3, 12, 30)), Some(at(8, 2, 0)));
+        assert_eq!(schedule.next_match(at(1, 3, 15)), Some(at(1, 3, 15)));
+    }
+
+    #[test]
+    fn test_cron_fields() {
+        let schedule = CronSchedule::parse("*/15 22 1,15 * *").unwrap();
+        assert!(schedule.matches(at(15, 22, 45)));
+        assert!(!schedule.matches(at(15, 22, 50)));
+        assert!(!schedule.matches(at(14, 22, 0)));
+
+        // Both day fields restricted: either may match
+        let schedule = CronSchedule::parse("0 0 13 * fri").unwrap();
+        assert!(schedule.matches(at(13, 0, 0)));
+        assert!(schedule.matches(at(7, 0, 0)));
+        assert!(!schedule.matches(at(8, 0, 0)));
+
+        assert!(CronSchedule::parse("0 0 * * 7")
+            .unwrap()
+            .matches(at(2, 0, 0)));
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("* 5-2 * * *").is_err());
+        assert!(CronSchedule::parse("* * * smarch *").is_err());
+    }
+
+    #[test]
+    fn test_windows() {
+        let windows = MaintenanceWindows::default();
+        assert!(windows.is_open(at(3, 12, 0)));
+
+        let windows =
+            MaintenanceWindows::parse(&["* 1 * * *".to_string(), "30-59 23 * * *".to_string()])
+                .unwrap();
+        assert!(!windows.is_open(at(3, 12, 0)));
+        assert_eq!(windows.next_open(at(3, 12, 0)), Some(at(3, 23, 30)));
+    }
+}
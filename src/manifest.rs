@@ -1,32 +1,45 @@
-use serde::Deserialize;
+use crate::boxes::source::SourceRecipe;
+use crate::config::DotfilesConfig;
+use serde::{Deserialize, Serialize};
 use std::fs;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct OmniManifest {
     #[allow(dead_code)]
     pub project: String,
     #[allow(dead_code)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     pub apps: Vec<OmniApp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub meta: Option<MetaInfo>,
+    /// Dotfiles to apply after the packages, replacing the configured ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dotfiles: Option<DotfilesConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct OmniApp {
     pub name: String,
     #[serde(rename = "box")]
     pub box_type: String,
     #[allow(dead_code)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub source: Option<String>,
+    /// Build steps for `box: source`; the repository defaults to `source`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipe: Option<SourceRecipe>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MetaInfo {
     #[allow(dead_code)]
     pub created_by: Option<String>,
     #[allow(dead_code)]
     pub created_on: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub distro_fallback: Option<bool>,
 }
 
@@ -36,4 +49,8 @@ impl OmniManifest {
         let manifest: OmniManifest = serde_yaml::from_str(&content)?;
         Ok(manifest)
     }
+
+    pub fn to_yaml(&self) -> anyhow::Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
 }
@@ -0,0 +1,693 @@
+use crate::config::ensure_writable;
+use crate::distro;
+use crate::error_handling::OmniError;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+const APT_SOURCES: &str = "/etc/apt/sources.list";
+const APT_SOURCES_DIR: &str = "/etc/apt/sources.list.d";
+const PACMAN_MIRRORLIST: &str = "/etc/pacman.d/mirrorlist";
+const DNF_CONF: &str = "/etc/dnf/dnf.conf";
+const DNF_REPOS_DIR: &str = "/etc/yum.repos.d";
+
+/// Per-mirror limit for the whole probe download
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Backends whose mirror configuration omni can benchmark
+pub const BENCHMARK_BOX_TYPES: [&str; 3] = ["apt", "dnf", "pacman"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mirror {
+    pub box_type: String,
+    /// Base URL as written in the backend's configuration
+    pub url: String,
+    /// Small metadata file downloaded to measure the mirror
+    pub probe_url: String,
+    /// False for mirrors listed but commented out, as pacman mirrorlists ship them
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorBenchmark {
+    pub mirror: Mirror,
+    /// Time until response headers arrived
+    pub latency_ms: Option<u64>,
+    pub throughput_kbps: Option<f64>,
+    pub error: Option<String>,
+}
+
+impl MirrorBenchmark {
+    pub fn is_reachable(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Mirrors configured for `box_type`. For apt only mirrors of the distribution
+/// archive count, since third-party repositories are not interchangeable with it,
+/// and security archives are left out: security updates should keep coming from
+/// the distribution itself.
+pub fn configured_mirrors(box_type: &str) -> Result<Vec<Mirror>> {
+    let mirrors = match box_type {
+        "apt" => {
+            let codename = os_release_field("VERSION_CODENAME");
+            let sources = apt_source_files()
+                .iter()
+                .filter_map(|path| fs::read_to_string(path).ok())
+                .flat_map(|text| parse_apt_sources(&text))
+                .collect::<Vec<_>>();
+            let mut mirrors: Vec<Mirror> = Vec::new();
+            for (uri, suite) in sources {
+                let distro_suite = codename
+                    .as_deref()
+                    .map_or(true, |codename| is_distro_suite(&suite, codename));
+                if !distro_suite || uri.contains("security") || mirrors.iter().any(|m| m.url == uri)
+                {
+                    continue;
+                }
+                mirrors.push(apt_mirror(&uri, &suite));
+            }
+            mirrors
+        }
+        "pacman" => parse_pacman_mirrorlist(&read(PACMAN_MIRRORLIST)?)
+            .into_iter()
+            .map(|(url, enabled)| pacman_mirror(&url, enabled))
+            .collect(),
+        "dnf" => {
+            let release = os_release_field("VERSION_ID");
+            let mut mirrors: Vec<Mirror> = Vec::new();
+            for path in files_with_extension(DNF_REPOS_DIR, "repo") {
+                let Ok(text) = fs::read_to_string(&path) else {
+                    continue;
+                };
+                for url in parse_dnf_baseurls(&text) {
+                    if mirrors.iter().all(|m| m.url != url) {
+                        mirrors.push(dnf_mirror(&url, release.as_deref()));
+                    }
+                }
+            }
+            mirrors
+        }
+        other => {
+            return Err(anyhow!(
+                "Mirror benchmarks are not supported for box type '{}'",
+                other
+            ))
+        }
+    };
+    Ok(mirrors)
+}
+
+/// An extra mirror to benchmark next to the configured ones, probed the same way
+pub fn candidate_mirror(box_type: &str, url: &str, configured: &[Mirror]) -> Mirror {
+    let url = url.trim_end_matches('/');
+    match box_type {
+        "apt" => {
+            // Probe the suite the primary configured mirror serves
+            let suite = configured
+                .first()
+                .and_then(|m| m.probe_url.rsplit_once("/dists/"))
+                .and_then(|(_, rest)| rest.strip_suffix("/Release"))
+                .unwrap_or("stable")
+                .to_string();
+            apt_mirror(url, &suite)
+        }
+        "pacman" => pacman_mirror(url, true),
+        _ => dnf_mirror(url, os_release_field("VERSION_ID").as_deref()),
+    }
+}
+
+fn apt_mirror(uri: &str, suite: &str) -> Mirror {
+    Mirror {
+        box_type: "apt".to_string(),
+        url: uri.to_string(),
+        probe_url: format!("{}/dists/{}/Release", uri.trim_end_matches('/'), suite),
+        enabled: true,
+    }
+}
+
+fn pacman_mirror(url: &str, enabled: bool) -> Mirror {
+    let base = url
+        .replace("$repo", "core")
+        .replace("$arch", std::env::consts::ARCH);
+    Mirror {
+        box_type: "pacman".to_string(),
+        url: url.to_string(),
+        probe_url: format!("{}/core.db", base.trim_end_matches('/')),
+        enabled,
+    }
+}
+
+fn dnf_mirror(url: &str, release: Option<&str>) -> Mirror {
+    let mut base = url.replace("$basearch", std::env::consts::ARCH);
+    if let Some(release) = release {
+        base = base.replace("$releasever", release);
+    }
+    Mirror {
+        box_type: "dnf".to_string(),
+        url: url.to_string(),
+        probe_url: format!("{}/repodata/repomd.xml", base.trim_end_matches('/')),
+        enabled: true,
+    }
+}
+
+/// Download each mirror's probe file concurrently and rank the results
+pub async fn benchmark(mirrors: Vec<Mirror>) -> Result<Vec<MirrorBenchmark>> {
+    let client = reqwest::Client::builder()
+        .user_agent("omni-package-manager/0.2.0")
+        .timeout(PROBE_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::limited(3))
+        .build()?;
+
+    let probes = mirrors.into_iter().map(|mirror| {
+        let client = client.clone();
+        async move {
+            let started = Instant::now();
+            let result = async {
+                let response = client.get(&mirror.probe_url).send().await?;
+                let latency = started.elapsed();
+                let response = response.error_for_status()?;
+                let body = response.bytes().await?;
+                Ok::<_, reqwest::Error>((latency, body.len(), started.elapsed()))
+            }
+            .await;
+
+            match result {
+                Ok((latency, bytes, total)) => {
+                    let transfer = total.saturating_sub(latency).as_secs_f64().max(0.001);
+                    MirrorBenchmark {
+                        mirror,
+                        latency_ms: Some(latency.as_millis() as u64),
+                        throughput_kbps: Some(bytes as f64 / 1024.0 / transfer),
+                        error: None,
+                    }
+                }
+                Err(e) => {
+                    debug!("Mirror {} failed: {}", mirror.probe_url, e);
+                    MirrorBenchmark {
+                        mirror,
+                        latency_ms: None,
+                        throughput_kbps: None,
+                        error: Some(e.to_string()),
+                    }
+                }
+            }
+        }
+    });
+
+    let mut results = futures::future::join_all(probes).await;
+    rank(&mut results);
+    Ok(results)
+}
+
+/// Reachable mirrors first, fastest transfer first, latency breaking ties
+pub fn rank(results: &mut [MirrorBenchmark]) {
+    results.sort_by(|a, b| {
+        b.is_reachable()
+            .cmp(&a.is_reachable())
+            .then_with(|| {
+                let a = a.throughput_kbps.unwrap_or(0.0);
+                let b = b.throughput_kbps.unwrap_or(0.0);
+                b.total_cmp(&a)
+            })
+            .then_with(|| a.latency_ms.cmp(&b.latency_ms))
+    });
+}
+
+/// Point the backend at the fastest mirrors. Each rewritten file is copied to
+/// `<file>.omni-bak` first. Returns the files that changed.
+pub fn apply(box_type: &str, ranked: &[MirrorBenchmark], top: usize) -> Result<Vec<PathBuf>> {
+    ensure_writable("rewriting mirror lists")?;
+
+    let fastest: Vec<&str> = ranked
+        .iter()
+        .filter(|r| r.is_reachable())
+        .map(|r| r.mirror.url.as_str())
+        .take(top.max(1))
+        .collect();
+    let best = fastest.first().copied();
+    if best.is_none() && box_type != "dnf" {
+        return Err(anyhow!(
+            "No {} mirror responded; nothing to apply",
+            box_type
+        ));
+    }
+
+    let mut changed = Vec::new();
+    match (box_type, best) {
+        ("apt", Some(best)) => {
+            let replaced: Vec<&str> = ranked
+                .iter()
+                .map(|r| r.mirror.url.as_str())
+                .filter(|url| *url != best)
+                .collect();
+            for path in apt_source_files() {
+                let Ok(text) = fs::read_to_string(&path) else {
+                    continue;
+                };
+                let rewritten = rewrite_apt_sources(&text, &replaced, best);
+                if rewritten != text {
+                    write_with_backup(&path, &rewritten)?;
+                    changed.push(path);
+                }
+            }
+        }
+        ("pacman", _) => {
+            let path = PathBuf::from(PACMAN_MIRRORLIST);
+            let text = read(PACMAN_MIRRORLIST)?;
+            write_with_backup(&path, &render_pacman_mirrorlist(&text, &fastest))?;
+            changed.push(path);
+        }
+        ("dnf", _) => {
+            // dnf picks among metalink mirrors itself; ask it to prefer the fastest
+            let path = PathBuf::from(DNF_CONF);
+            let text = fs::read_to_string(&path).unwrap_or_default();
+            let rewritten = enable_dnf_fastestmirror(&text);
+            if rewritten != text {
+                write_with_backup(&path, &rewritten)?;
+                changed.push(path);
+            }
+        }
+        (other, _) => {
+            return Err(anyhow!(
+                "Mirror lists cannot be rewritten for box type '{}'",
+                other
+            ))
+        }
+    }
+
+    info!("Applied {} mirror ranking to {:?}", box_type, changed);
+    Ok(changed)
+}
+
+fn write_with_backup(path: &Path, contents: &str) -> Result<()> {
+    let backup = PathBuf::from(format!("{}.omni-bak", path.display()));
+    let result = fs::copy(path, &backup)
+        .or_else(|e| {
+            // A missing dnf.conf has nothing to back up
+            if e.kind() == std::io::ErrorKind::NotFound {
+                Ok(0)
+            } else {
+                Err(e)
+            }
+        })
+        .and_then(|_| fs::write(path, contents));
+
+    result.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            OmniError::PermissionDenied {
+                operation: format!("rewriting {} (run with sudo)", path.display()),
+            }
+            .into()
+        } else {
+            anyhow!("Failed to rewrite {}: {}", path.display(), e)
+        }
+    })
+}
+
+fn read(path: &str) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path, e))
+}
+
+fn apt_source_files() -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(APT_SOURCES)];
+    files.extend(files_with_extension(APT_SOURCES_DIR, "list"));
+    files.extend(files_with_extension(APT_SOURCES_DIR, "sources"));
+    files.retain(|path| path.exists());
+    files
+}
+
+fn files_with_extension(dir: &str, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+fn os_release_field(name: &str) -> Option<String> {
+    let contents = fs::read_to_string("/etc/os-release").ok()?;
+    contents.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key == name).then(|| value.trim_matches('"').to_string())
+    })
+}
+
+/// `noble`, `noble-updates` and `noble-backports` are pockets of the `noble` archive
+fn is_distro_suite(suite: &str, codename: &str) -> bool {
+    suite.split('-').next() == Some(codename)
+}
+
+/// Backends on this machine that have a mirror configuration to benchmark
+pub fn available_box_types() -> Vec<&'static str> {
+    BENCHMARK_BOX_TYPES
+        .into_iter()
+        .filter(|bt| distro::command_exists(bt))
+        .collect()
+}
+
+/// `(uri, suite)` of every enabled binary source, from one-line `.list` files
+/// and deb822 `.sources` files
+pub fn parse_apt_sources(text: &str) -> Vec<(String, String)> {
+    let mut sources = Vec::new();
+
+    if text.lines().any(|l| l.trim_start().starts_with("URIs:")) {
+        for stanza in text.split("\n\n") {
+            let field = |name: &str| {
+                stanza.lines().find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    (key.trim() == name).then(|| value.trim().to_string())
+                })
+            };
+            let is_binary =
+                field("Types").is_some_and(|t| t.split_whitespace().any(|t| t == "deb"));
+            let enabled = field("Enabled").map_or(true, |e| e != "no");
+            let (Some(uris), Some(suites)) = (field("URIs"), field("Suites")) else {
+                continue;
+            };
+            let Some(suite) = suites.split_whitespace().next() else {
+                continue;
+            };
+            if is_binary && enabled {
+                for uri in uris.split_whitespace() {
+                    sources.push((uri.trim_end_matches('/').to_string(), suite.to_string()));
+                }
+            }
+        }
+        return sources;
+    }
+
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        if parts.next() != Some("deb") {
+            continue;
+        }
+        let mut uri = parts.next();
+        if let Some(options) = uri.filter(|u| u.starts_with('[')) {
+            // Skip `[arch=amd64 signed-by=...]` options
+            if !options.ends_with(']') {
+                parts.find(|p| p.ends_with(']'));
+            }
+            uri = parts.next();
+        }
+        if let (Some(uri), Some(suite)) = (uri, parts.next()) {
+            sources.push((uri.trim_end_matches('/').to_string(), suite.to_string()));
+        }
+    }
+    sources
+}
+
+/// Replace every URI in `replaced` with `fastest`, leaving the rest of each line intact
+pub fn rewrite_apt_sources(text: &str, replaced: &[&str], fastest: &str) -> String {
+    let mut rewritten: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if !(trimmed.starts_with("deb ") || trimmed.starts_with("URIs:")) {
+            rewritten.push(line.to_string());
+            continue;
+        }
+        let tokens: Vec<&str> = line
+            .split(' ')
+            .map(|token| {
+                if replaced.contains(&token.trim_end_matches('/')) {
+                    fastest
+                } else {
+                    token
+                }
+            })
+            .collect();
+        rewritten.push(tokens.join(" "));
+    }
+
+    let mut output = rewritten.join("\n");
+    if text.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+/// `(server, enabled)` for each `Server =` line, commented ones included
+pub fn parse_pacman_mirrorlist(text: &str) -> Vec<(String, bool)> {
+    text.lines()
+        .filter_map(|line| {
+            let trimmed = line.trim();
+            let (enabled, entry) = match trimmed.strip_prefix('#') {
+                Some(rest) => (false, rest.trim_start()),
+                None => (true, trimmed),
+            };
+            let (key, value) = entry.split_once('=')?;
+            (key.trim() == "Server").then(|| (value.trim().to_string(), enabled))
+        })
+        .collect()
+}
+
+/// Put the ranked servers at the top and comment out every other active server
+pub fn render_pacman_mirrorlist(text: &str, ranked: &[&str]) -> String {
+    let mut output = format!(
+        "## Ranked by `omni repository benchmark` on {}\n",
+        Utc::now().format("%Y-%m-%d")
+    );
+    for server in ranked {
+        output.push_str(&format!("Server = {}\n", server));
+    }
+    output.push('\n');
+
+    for line in text.lines() {
+        if line.starts_with("## Ranked by `omni repository benchmark`") {
+            continue;
+        }
+        let is_active_server = line
+            .split_once('=')
+            .is_some_and(|(key, _)| key.trim() == "Server");
+        if is_active_server {
+            output.push('#');
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+/// `baseurl=` entries of enabled `.repo` sections
+pub fn parse_dnf_baseurls(text: &str) -> Vec<String> {
+    let mut urls = Vec::new();
+    for section in text.split("\n[") {
+        let enabled = !section.lines().any(|l| l.replace(' ', "") == "enabled=0");
+        if !enabled {
+            continue;
+        }
+        for line in section.lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == "baseurl" {
+                    urls.extend(
+                        value
+                            .split_whitespace()
+                            .map(|u| u.trim_end_matches('/').to_string()),
+                    );
+                }
+            }
+        }
+    }
+    urls
+}
+
+/// Set `fastestmirror=True` in the `[main]` section of dnf.conf
+pub fn enable_dnf_fastestmirror(text: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_main = false;
+    let mut done = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if in_main && !done {
+                lines.push("fastestmirror=True".to_string());
+                done = true;
+            }
+            in_main = trimmed == "[main]";
+        } else if in_main && trimmed.split('=').next().map(str::trim) == Some("fastestmirror") {
+            lines.push("fastestmirror=True".to_string());
+            done = true;
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+
+    if !done {
+        if in_main {
+            lines.push("fastestmirror=True".to_string());
+        } else {
+            if !lines.is_empty() {
+                warn!("{} has no [main] section; adding one", DNF_CONF);
+            }
+            lines.push("[main]".to_string());
+            lines.push("fastestmirror=True".to_string());
+        }
+    }
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_rewrite_apt_sources() {
+        let list = "# main archive
+deb http://archive.ubuntu.com/ubuntu/ noble main restricted
+deb [arch=amd64 signed-by=/usr/share/keyrings/x.gpg] http://archive.ubuntu.com/ubuntu noble-updates main
+deb-src http://archive.ubuntu.com/ubuntu noble main
+deb http://security.ubuntu.com/ubuntu noble-security main
+";
+        let sources = parse_apt_sources(list);
+        assert!(is_distro_suite("noble-updates", "noble"));
+        assert!(!is_distro_suite("cloud-sdk-noble", "noble"));
+        assert_eq!(sources.len(), 3);
+        assert_eq!(
+            sources[0],
+            (
+                "http://archive.ubuntu.com/ubuntu".to_string(),
+                "noble".to_string()
+            )
+        );
+        assert_eq!(sources[1].1, "noble-updates");
+
+        let rewritten = rewrite_apt_sources(
+            list,
+            &["http://archive.ubuntu.com/ubuntu"],
+            "http://mirror.example.com/ubuntu",
+        );
+        assert!(rewritten.contains("deb http://mirror.example.com/ubuntu noble main restricted"));
+        assert!(rewritten.contains("x.gpg] http://mirror.example.com/ubuntu noble-updates"));
+        // Source packages and the security archive are left alone
+        assert!(rewritten.contains("deb-src http://archive.ubuntu.com/ubuntu noble main"));
+        assert!(rewritten.contains("http://security.ubuntu.com/ubuntu noble-security"));
+
+        let deb822 = "Types: deb
+URIs: http://archive.ubuntu.com/ubuntu/
+Suites: noble noble-updates
+Components: main
+
+Types: deb
+URIs: http://ppa.example.com/ubuntu
+Suites: noble
+Enabled: no
+";
+        assert_eq!(
+            parse_apt_sources(deb822),
+            vec![(
+                "http://archive.ubuntu.com/ubuntu".to_string(),
+                "noble".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_pacman_mirrorlist_ranking() {
+        let mirrorlist = "## Worldwide
+#Server = https://geo.mirror.pkgbuild.com/$repo/os/$arch
+Server = https://mirror.rackspace.com/archlinux/$repo/os/$arch
+";
+        assert_eq!(
+            parse_pacman_mirrorlist(mirrorlist),
+            vec![
+                (
+                    "https://geo.mirror.pkgbuild.com/$repo/os/$arch".to_string(),
+                    false
+                ),
+                (
+                    "https://mirror.rackspace.com/archlinux/$repo/os/$arch".to_string(),
+                    true
+                ),
+            ]
+        );
+
+        let rendered = render_pacman_mirrorlist(
+            mirrorlist,
+            &["https://geo.mirror.pkgbuild.com/$repo/os/$arch"],
+        );
+        let active: Vec<_> = parse_pacman_mirrorlist(&rendered)
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .collect();
+        assert_eq!(active.len(), 1);
+        assert!(active[0].0.starts_with("https://geo.mirror.pkgbuild.com"));
+        assert!(rendered.contains("#Server = https://mirror.rackspace.com"));
+
+        let probe = pacman_mirror(
+            "https://mirror.rackspace.com/archlinux/$repo/os/$arch",
+            true,
+        );
+        assert!(probe.probe_url.contains("/archlinux/core/os/"));
+        assert!(probe.probe_url.ends_with("/core.db"));
+    }
+
+    #[test]
+    fn test_dnf_baseurls_and_fastestmirror() {
+        let repo = "[fedora]
+name=Fedora $releasever
+metalink=https://mirrors.fedoraproject.org/metalink?repo=fedora-$releasever
+enabled=1
+
+[local]
+baseurl=http://repo.example.com/fedora/$releasever/$basearch/
+enabled=1
+
+[disabled]
+baseurl=http://old.example.com/fedora
+enabled=0
+";
+        assert_eq!(
+            parse_dnf_baseurls(repo),
+            vec!["http://repo.example.com/fedora/$releasever/$basearch".to_string()]
+        );
+
+        let conf = "[main]\ngpgcheck=True\nfastestmirror=False\n";
+        assert_eq!(
+            enable_dnf_fastestmirror(conf),
+            "[main]\ngpgcheck=True\nfastestmirror=True\n"
+        );
+        assert_eq!(
+            enable_dnf_fastestmirror("[main]\ngpgcheck=True\n"),
+            "[main]\ngpgcheck=True\nfastestmirror=True\n"
+        );
+        assert_eq!(enable_dnf_fastestmirror(""), "[main]\nfastestmirror=True\n");
+    }
+
+    #[test]
+    fn test_rank_prefers_reachable_and_fast() {
+        let result = |url: &str, throughput: Option<f64>, latency: Option<u64>| MirrorBenchmark {
+            mirror: apt_mirror(url, "noble"),
+            latency_ms: latency,
+            throughput_kbps: throughput,
+            error: throughput.is_none().then(|| "timed out".to_string()),
+        };
+        let mut results = vec![
+            result("http://down.example.com", None, None),
+            result("http://slow.example.com", Some(200.0), Some(40)),
+            result("http://fast.example.com", Some(900.0), Some(90)),
+        ];
+        rank(&mut results);
+        let order: Vec<_> = results.iter().map(|r| r.mirror.url.as_str()).collect();
+        assert_eq!(
+            order,
+            vec![
+                "http://fast.example.com",
+                "http://slow.example.com",
+                "http://down.example.com"
+            ]
+        );
+    }
+}
@@ -0,0 +1,654 @@
+//! In-memory package backend behind `--mock`.
+//!
+//! Mock mode works against a fake package universe loaded from a fixture file
+//! instead of the system's package managers. Installs resolve dependencies and
+//! run as transactions that roll back when a package is marked to fail, and
+//! snapshots capture and restore the in-memory state, so demos and tests see
+//! the same flows as a real system without touching it.
+
+use crate::config::SearchConfig;
+use crate::database::{InstallRecord, InstallStatus, Snapshot};
+use crate::error_handling::OmniError;
+use crate::search::{self, SearchResult};
+use crate::transaction::{OperationType, PlannedStep};
+use crate::updater::{Advisory, UpdateCandidate};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Universe used when no fixture is given
+const BUILTIN_UNIVERSE: &str = include_str!("mock_universe.yaml");
+
+static BACKEND: OnceLock<MockBackend> = OnceLock::new();
+
+/// A package in the mock universe
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockPackage {
+    pub name: String,
+    pub version: String,
+    #[serde(default = "default_box_type")]
+    pub box_type: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// Installed when mock mode starts
+    #[serde(default)]
+    pub installed: bool,
+    /// Newer version offered as an update
+    #[serde(default)]
+    pub update: Option<String>,
+    #[serde(default)]
+    pub security: bool,
+    /// Installing or updating the package fails
+    #[serde(default)]
+    pub fail: bool,
+}
+
+fn default_box_type() -> String {
+    "apt".to_string()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MockSettings {
+    /// Simulated time one package takes to install, update or remove
+    pub delay_ms: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct MockUniverse {
+    #[serde(default)]
+    settings: MockSettings,
+    packages: Vec<MockPackage>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    installed: BTreeMap<String, InstallRecord>,
+    /// Installed on request rather than as a dependency
+    explicit: BTreeSet<String>,
+    /// Newest last
+    history: Vec<InstallRecord>,
+    snapshots: Vec<(Snapshot, BTreeSet<String>)>,
+}
+
+pub struct MockBackend {
+    packages: BTreeMap<String, MockPackage>,
+    delay: Duration,
+    state: Mutex<MockState>,
+}
+
+/// Set up the backend from `fixture`, `OMNI_MOCK_FIXTURE` or the built-in
+/// universe. Call before the first [`backend`] use for the fixture to apply.
+pub fn init(fixture: Option<&Path>) -> Result<&'static MockBackend> {
+    let fixture = fixture
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::var_os("OMNI_MOCK_FIXTURE").map(PathBuf::from));
+    if let Some(path) = fixture {
+        let backend = MockBackend::load(&path)?;
+        if BACKEND.set(backend).is_err() {
+            return Err(anyhow!("The mock backend is already set up"));
+        }
+    }
+    Ok(backend())
+}
+
+/// The process-wide mock backend
+pub fn backend() -> &'static MockBackend {
+    BACKEND.get_or_init(MockBackend::builtin)
+}
+
+impl MockBackend {
+    pub fn builtin() -> Self {
+        Self::from_yaml(BUILTIN_UNIVERSE).expect("the built-in mock universe is valid")
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_yaml(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow!("Invalid mock fixture {}: {}", path.display(), e))
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let universe: MockUniverse = serde_yaml::from_str(yaml)?;
+
+        let mut packages = BTreeMap::new();
+        for package in universe.packages {
+            if packages.contains_key(&package.name) {
+                return Err(anyhow!("{} is listed twice", package.name));
+            }
+            packages.insert(package.name.clone(), package);
+        }
+        for package in packages.values() {
+            if let Some(missing) = package.depends.iter().find(|d| !packages.contains_key(*d)) {
+                return Err(anyhow!(
+                    "{} depends on {}, which is not in the universe",
+                    package.name,
+                    missing
+                ));
+            }
+        }
+
+        let mut state = MockState::default();
+        for package in packages.values().filter(|p| p.installed) {
+            let record = new_record(package, &package.version, InstallStatus::Success);
+            state.installed.insert(package.name.clone(), record);
+        }
+        // Preinstalled packages nothing else needs count as requested
+        state.explicit = state
+            .installed
+            .keys()
+            .filter(|name| required_by(&packages, &state.installed, name).is_none())
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            packages,
+            delay: Duration::from_millis(universe.settings.delay_ms),
+            state: Mutex::new(state),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn simulate_work(&self) {
+        if !self.delay.is_zero() {
+            std::thread::sleep(self.delay);
+        }
+    }
+
+    pub fn package(&self, name: &str) -> Option<&MockPackage> {
+        self.packages.get(name)
+    }
+
+    /// Packages whose name or description contains `query`, ranked the way
+    /// `SearchEngine::search_all` ranks real results
+    pub fn search(&self, query: &str) -> Vec<SearchResult> {
+        let lowered = query.to_lowercase();
+        let state = self.state();
+        let results: Vec<SearchResult> = self
+            .packages
+            .values()
+            .filter(|p| {
+                p.name.to_lowercase().contains(&lowered)
+                    || p.description
+                        .as_deref()
+                        .is_some_and(|d| d.to_lowercase().contains(&lowered))
+            })
+            .map(|p| SearchResult {
+                name: p.name.clone(),
+                description: p.description.clone(),
+                version: Some(p.version.clone()),
+                architecture: None,
+                box_type: p.box_type.clone(),
+                source: Some("mock".to_string()),
+                installed: state.installed.contains_key(&p.name),
+                popularity_rank: None,
+                security_score: None,
+                similar_packages: Vec::new(),
+                cross_platform_available: false,
+                category: None,
+                homepage: None,
+            })
+            .collect();
+
+        search::rank_results(results, query, &SearchConfig::default())
+    }
+
+    /// Packages that installing `name` would add, dependencies first
+    pub fn plan_install(&self, name: &str, box_type: Option<&str>) -> Result<Vec<String>> {
+        let package = self
+            .packages
+            .get(name)
+            .ok_or_else(|| OmniError::PackageNotFound {
+                package: name.to_string(),
+            })?;
+        if let Some(box_type) = box_type.filter(|b| *b != package.box_type) {
+            return Err(anyhow!(
+                "{} is not available from the {} box (it is in {})",
+                name,
+                box_type,
+                package.box_type
+            ));
+        }
+
+        let mut order = Vec::new();
+        self.resolve(&self.state().installed, name, &mut order, &mut Vec::new())?;
+        Ok(order)
+    }
+
+    fn resolve(
+        &self,
+        installed: &BTreeMap<String, InstallRecord>,
+        name: &str,
+        order: &mut Vec<String>,
+        visiting: &mut Vec<String>,
+    ) -> Result<()> {
+        if installed.contains_key(name) || order.iter().any(|n| n == name) {
+            return Ok(());
+        }
+        if visiting.iter().any(|n| n == name) {
+            return Err(anyhow!(
+                "Dependency cycle: {} -> {}",
+                visiting.join(" -> "),
+                name
+            ));
+        }
+
+        visiting.push(name.to_string());
+        for dependency in &self.packages[name].depends {
+            self.resolve(installed, dependency, order, visiting)?;
+        }
+        visiting.pop();
+        order.push(name.to_string());
+        Ok(())
+    }
+
+    /// Install `name` and its missing dependencies as one transaction. Returns
+    /// the packages added; a failing package rolls back the whole transaction.
+    pub fn install(&self, name: &str, box_type: Option<&str>) -> Result<Vec<String>> {
+        let plan = self.plan_install(name, box_type)?;
+        let mut state = self.state();
+
+        for (done, package_name) in plan.iter().enumerate() {
+            let package = &self.packages[package_name];
+            self.simulate_work();
+
+            if package.fail {
+                for added in &plan[..done] {
+                    state.installed.remove(added);
+                }
+                let failed = new_record(package, &package.version, InstallStatus::Failed);
+                state.history.push(failed);
+                let rolled_back = &plan[..done];
+                return Err(if rolled_back.is_empty() {
+                    anyhow!("Simulated failure installing {}", package_name)
+                } else {
+                    anyhow!(
+                        "Simulated failure installing {}; rolled back {}",
+                        package_name,
+                        rolled_back.join(", ")
+                    )
+                });
+            }
+
+            let record = new_record(package, &package.version, InstallStatus::Success);
+            state.installed.insert(package_name.clone(), record);
+        }
+
+        for package_name in &plan {
+            let record = state.installed[package_name].clone();
+            state.history.push(record);
+        }
+        state.explicit.insert(name.to_string());
+        Ok(plan)
+    }
+
+    pub fn remove(&self, name: &str, box_type: Option<&str>) -> Result<()> {
+        let mut state = self.state();
+        let record = state
+            .installed
+            .get(name)
+            .filter(|r| box_type.map_or(true, |b| r.box_type == b))
+            .ok_or_else(|| OmniError::PackageNotFound {
+                package: name.to_string(),
+            })?;
+        if let Some(dependent) = required_by(&self.packages, &state.installed, name) {
+            return Err(anyhow!("{} is required by {}", name, dependent));
+        }
+
+        let mut removed = record.clone();
+        self.simulate_work();
+        removed.id = Uuid::new_v4().to_string();
+        removed.installed_at = Utc::now();
+        removed.status = InstallStatus::Removed;
+        state.installed.remove(name);
+        state.explicit.remove(name);
+        state.history.push(removed);
+        Ok(())
+    }
+
+    /// Packages in the universe depending on `name`, or only installed ones
+    pub fn reverse_dependencies(&self, name: &str, installed_only: bool) -> Vec<String> {
+        let state = self.state();
+        self.packages
+            .values()
+            .filter(|p| p.depends.iter().any(|d| d == name))
+            .filter(|p| !installed_only || state.installed.contains_key(&p.name))
+            .map(|p| p.name.clone())
+            .collect()
+    }
+
+    /// Whether an installed package was asked for (true) or pulled in as a
+    /// dependency (false); None when it is not installed
+    pub fn install_reason(&self, name: &str) -> Option<bool> {
+        let state = self.state();
+        state
+            .installed
+            .contains_key(name)
+            .then(|| state.explicit.contains(name))
+    }
+
+    /// Dependencies no installed package needs any more
+    pub fn orphans(&self) -> Vec<String> {
+        let state = self.state();
+        let mut remaining = state.installed.clone();
+        let mut orphans = Vec::new();
+        // Removing one orphan can orphan its own dependencies
+        loop {
+            let found: Vec<String> = remaining
+                .keys()
+                .filter(|name| {
+                    !state.explicit.contains(*name)
+                        && required_by(&self.packages, &remaining, name).is_none()
+                })
+                .cloned()
+                .collect();
+            if found.is_empty() {
+                return orphans;
+            }
+            for name in found {
+                remaining.remove(&name);
+                orphans.push(name);
+            }
+        }
+    }
+
+    pub fn autoremove(&self) -> Result<Vec<String>> {
+        let orphans = self.orphans();
+        for name in &orphans {
+            self.remove(name, None)?;
+        }
+        Ok(orphans)
+    }
+
+    pub fn updates(&self) -> Vec<UpdateCandidate> {
+        self.state()
+            .installed
+            .values()
+            .filter_map(|record| {
+                let package = &self.packages[&record.package_name];
+                let available = package.update.as_ref()?;
+                (record.version.as_ref() != Some(available)).then(|| UpdateCandidate {
+                    package_name: record.package_name.clone(),
+                    box_type: record.box_type.clone(),
+                    current_version: record.version.clone(),
+                    available_version: Some(available.clone()),
+                    install_record: record.clone(),
+                    advisory: package.security.then(|| Advisory {
+                        id: "mock-security".to_string(),
+                        severity: None,
+                    }),
+                })
+            })
+            .collect()
+    }
+
+    /// Apply the available updates of `candidates`, stopping at the first failure
+    pub fn update(&self, candidates: &[UpdateCandidate]) -> Result<()> {
+        for candidate in candidates {
+            let package = self.packages.get(&candidate.package_name).ok_or_else(|| {
+                OmniError::PackageNotFound {
+                    package: candidate.package_name.clone(),
+                }
+            })?;
+            let Some(version) = &package.update else {
+                continue;
+            };
+
+            self.simulate_work();
+            let mut state = self.state();
+            if package.fail {
+                let failed = new_record(package, version, InstallStatus::Failed);
+                state.history.push(failed);
+                return Err(anyhow!("Simulated failure updating {}", package.name));
+            }
+            let record = new_record(package, version, InstallStatus::Updated);
+            state.installed.insert(package.name.clone(), record.clone());
+            state.history.push(record);
+        }
+        Ok(())
+    }
+
+    /// A step of `omni transaction plan`, from the mock universe
+    pub fn plan_step(
+        &self,
+        order: usize,
+        operation_type: OperationType,
+        name: &str,
+        box_type: Option<&str>,
+        version: Option<&str>,
+    ) -> PlannedStep {
+        let package = self.package(name);
+        let installed_version = self
+            .state()
+            .installed
+            .get(name)
+            .and_then(|record| record.version.clone());
+        let expected_version = match operation_type {
+            OperationType::RemovePackage => None,
+            OperationType::UpdatePackage => version
+                .map(str::to_string)
+                .or_else(|| package.and_then(|p| p.update.clone())),
+            _ => version
+                .map(str::to_string)
+                .or_else(|| package.map(|p| p.version.clone())),
+        };
+        let box_type = box_type
+            .map(str::to_string)
+            .or_else(|| package.map(|p| p.box_type.clone()));
+        PlannedStep {
+            order,
+            operation_type,
+            package: name.to_string(),
+            requires_root: box_type.as_deref() != Some("flatpak"),
+            box_type,
+            installed_version,
+            expected_version,
+            download_size: None,
+        }
+    }
+
+    pub fn installed(&self) -> Vec<InstallRecord> {
+        self.state().installed.values().cloned().collect()
+    }
+
+    /// Install history, newest first
+    pub fn history(&self, limit: usize) -> Vec<InstallRecord> {
+        self.state()
+            .history
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Reverse the last install or removal; returns what was done
+    pub fn undo_last(&self) -> Result<String> {
+        let last = self
+            .state()
+            .history
+            .last()
+            .cloned()
+            .ok_or_else(|| anyhow!("No installation history found"))?;
+
+        match last.status {
+            InstallStatus::Success => {
+                self.remove(&last.package_name, Some(&last.box_type))?;
+                Ok(format!("Removed {}", last.package_name))
+            }
+            InstallStatus::Removed => {
+                self.install(&last.package_name, Some(&last.box_type))?;
+                Ok(format!("Re-installed {}", last.package_name))
+            }
+            status => Err(anyhow!("Cannot undo operation with status: {:?}", status)),
+        }
+    }
+
+    pub fn create_snapshot(&self, name: &str, description: Option<&str>) -> String {
+        let mut state = self.state();
+        let snapshot = Snapshot {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            created_at: Utc::now(),
+            packages: state.installed.values().cloned().collect(),
+            backend: None,
+            backend_ref: None,
+        };
+        let id = snapshot.id.clone();
+        let explicit = state.explicit.clone();
+        state.snapshots.push((snapshot, explicit));
+        id
+    }
+
+    /// Snapshots, newest first
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.state()
+            .snapshots
+            .iter()
+            .rev()
+            .map(|(snapshot, _)| snapshot.clone())
+            .collect()
+    }
+
+    /// Restore the packages captured by the snapshot with this ID or name
+    pub fn revert(&self, snapshot: &str) -> Result<Snapshot> {
+        let mut state = self.state();
+        let (found, explicit) = state
+            .snapshots
+            .iter()
+            .rev()
+            .find(|(s, _)| s.id == snapshot || s.name == snapshot)
+            .cloned()
+            .ok_or_else(|| anyhow!("Snapshot not found: {}", snapshot))?;
+
+        self.simulate_work();
+        state.installed = found
+            .packages
+            .iter()
+            .map(|r| (r.package_name.clone(), r.clone()))
+            .collect();
+        state.explicit = explicit;
+        Ok(found)
+    }
+}
+
+/// An installed package that depends on `name`
+fn required_by<'a>(
+    packages: &BTreeMap<String, MockPackage>,
+    installed: &'a BTreeMap<String, InstallRecord>,
+    name: &str,
+) -> Option<&'a str> {
+    installed
+        .keys()
+        .find(|other| packages[*other].depends.iter().any(|d| d == name))
+        .map(String::as_str)
+}
+
+fn new_record(package: &MockPackage, version: &str, status: InstallStatus) -> InstallRecord {
+    InstallRecord {
+        id: Uuid::new_v4().to_string(),
+        package_name: package.name.clone(),
+        box_type: package.box_type.clone(),
+        version: Some(version.to_string()),
+        source_url: None,
+        install_path: None,
+        installed_at: Utc::now(),
+        status,
+        repository: Some("mock".to_string()),
+        mirror_url: None,
+        channel: None,
+        metadata: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIVERSE: &str = r#"
+packages:
+  - name: app
+    version: "1.0"
+    depends: [libfoo]
+  - name: libfoo
+    version: "2.0"
+    depends: [libbar]
+  - name: libbar
+    version: "3.0"
+    installed: true
+  - name: broken
+    version: "0.1"
+    depends: [libfoo]
+    fail: true
+"#;
+
+    #[test]
+    fn test_builtin_universe_loads() {
+        let backend = MockBackend::builtin();
+        assert!(backend.package("git").is_some());
+        assert!(!backend.installed().is_empty());
+        assert!(!backend.updates().is_empty());
+    }
+
+    #[test]
+    fn test_install_resolves_dependencies_and_rolls_back() {
+        let backend = MockBackend::from_yaml(UNIVERSE).unwrap();
+        assert_eq!(
+            backend.plan_install("app", None).unwrap(),
+            ["libfoo", "app"]
+        );
+
+        let err = backend.install("broken", None).unwrap_err();
+        assert!(err.to_string().ends_with("rolled back libfoo"));
+        assert_eq!(backend.installed().len(), 1);
+        assert!(matches!(
+            backend.history(1)[0].status,
+            InstallStatus::Failed
+        ));
+
+        backend.install("app", None).unwrap();
+        assert_eq!(backend.installed().len(), 3);
+        assert!(backend.remove("libfoo", None).is_err());
+        assert_eq!(backend.reverse_dependencies("libfoo", true), ["app"]);
+        backend.remove("app", None).unwrap();
+        assert_eq!(backend.orphans(), ["libfoo"]);
+        assert!(backend.reverse_dependencies("libfoo", true).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_revert_restores_state() {
+        let backend = MockBackend::from_yaml(UNIVERSE).unwrap();
+        let id = backend.create_snapshot("before", None);
+        backend.install("app", None).unwrap();
+        assert_eq!(backend.installed().len(), 3);
+
+        backend.revert(&id).unwrap();
+        let names: Vec<String> = backend
+            .installed()
+            .into_iter()
+            .map(|r| r.package_name)
+            .collect();
+        assert_eq!(names, ["libbar"]);
+    }
+
+    #[test]
+    fn test_invalid_universe() {
+        let missing = "packages:\n  - {name: app, version: '1', depends: [nope]}";
+        assert!(MockBackend::from_yaml(missing).is_err());
+        assert!(MockBackend::from_yaml(
+            "packages: [{name: a, version: '1'}, {name: a, version: '2'}]"
+        )
+        .is_err());
+    }
+}
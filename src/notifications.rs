@@ -0,0 +1,878 @@
+//! Notifications: package events, daemon update checks, audit entries and
+//! fleet results all go through one [`Dispatcher`], which hands each
+//! [`Notification`] to the [`Notifier`] sinks that `notifications.routes`
+//! pick by category and severity. The older settings are sinks with routes
+//! of their own: `notifications.webhooks` get package events as JSON,
+//! long-running operations and daemon updates pop a desktop notification, and
+//! audit entries go to `audit.email` and `audit.chat`. Delivery retries
+//! transient failures but never fails the operation itself.
+
+use crate::audit::{self, AuditEntry, AuditSeverity};
+use crate::config::{
+    ChatAlertConfig, EmailAlertConfig, NotificationRoute, NotificationSinkConfig,
+    NotificationsConfig, NotifyCategory, NotifyEvent, OmniConfig, WebhookConfig,
+};
+use crate::credentials;
+use crate::error_handling::{OmniError, RetryConfig, RetryHandler};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const SIGNATURE_HEADER: &str = "X-Omni-Signature";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageEvent {
+    pub event: NotifyEvent,
+    /// What was attempted; differs from `event` only for failures
+    pub operation: NotifyEvent,
+    pub package: String,
+    pub box_type: Option<String>,
+    pub version: Option<String>,
+    pub error: Option<String>,
+    /// How long the operation took, when it was timed
+    pub duration_secs: Option<u64>,
+    pub host: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl PackageEvent {
+    pub fn new(operation: NotifyEvent, package: &str) -> Self {
+        Self {
+            event: operation,
+            operation,
+            package: package.to_string(),
+            box_type: None,
+            version: None,
+            error: None,
+            duration_secs: None,
+            host: hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn failed(operation: NotifyEvent, package: &str, error: impl std::fmt::Display) -> Self {
+        Self {
+            event: NotifyEvent::Failure,
+            error: Some(error.to_string()),
+            ..Self::new(operation, package)
+        }
+    }
+
+    pub fn with_box_type(mut self, box_type: Option<&str>) -> Self {
+        self.box_type = box_type.map(str::to_string);
+        self
+    }
+
+    pub fn with_version(mut self, version: Option<String>) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration_secs = Some(duration.as_secs());
+        self
+    }
+
+    /// Title of the desktop notification, e.g. "firefox installed"
+    pub fn summary(&self) -> String {
+        let done = match self.operation {
+            NotifyEvent::Install => "installed",
+            NotifyEvent::Remove => "removed",
+            _ => "updated",
+        };
+        match self.event {
+            NotifyEvent::Failure => format!("{} was not {}", self.package, done),
+            _ => format!("{} {}", self.package, done),
+        }
+    }
+
+    fn details(&self) -> String {
+        if let Some(error) = &self.error {
+            return error.clone();
+        }
+        let via = self
+            .box_type
+            .as_ref()
+            .map(|b| format!("via {}", b))
+            .unwrap_or_default();
+        match &self.version {
+            Some(version) => format!("{} {}", version, via).trim_end().to_string(),
+            None => via,
+        }
+    }
+}
+
+/// Send `event` to the sinks the routes in the user's config pick
+pub async fn notify(event: PackageEvent) {
+    let config = OmniConfig::load().unwrap_or_default();
+    send(&config, &event).await;
+}
+
+/// Send `event` to the sinks the routes in `config` pick
+pub async fn send(config: &OmniConfig, event: &PackageEvent) {
+    Dispatcher::from_config(config)
+        .dispatch(&Notification::from(event))
+        .await;
+}
+
+/// Something a subsystem wants people to hear about
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub category: NotifyCategory,
+    pub severity: AuditSeverity,
+    /// One line, e.g. "firefox was not updated"
+    pub title: String,
+    pub body: String,
+    pub host: String,
+    pub timestamp: DateTime<Utc>,
+    /// What the notification was made from, for sinks that send that in a
+    /// format of its own
+    #[serde(skip)]
+    pub origin: Option<Origin>,
+}
+
+/// The package event or audit entry a notification describes
+#[derive(Debug, Clone)]
+pub enum Origin {
+    /// An install, removal or update, or its failure
+    Package(PackageEvent),
+    /// An entry recorded in the audit log
+    Audit(AuditEntry),
+}
+
+impl Notification {
+    pub fn new(
+        category: NotifyCategory,
+        severity: AuditSeverity,
+        title: impl Into<String>,
+        body: impl Into<String>,
+    ) -> Self {
+        Self {
+            category,
+            severity,
+            title: title.into(),
+            body: body.into(),
+            host: audit::host_name(),
+            timestamp: Utc::now(),
+            origin: None,
+        }
+    }
+}
+
+impl From<&PackageEvent> for Notification {
+    fn from(event: &PackageEvent) -> Self {
+        let severity = match event.event {
+            NotifyEvent::Failure => AuditSeverity::Warning,
+            _ => AuditSeverity::Info,
+        };
+        Self {
+            host: event.host.clone(),
+            timestamp: event.timestamp,
+            origin: Some(Origin::Package(event.clone())),
+            ..Self::new(
+                NotifyCategory::Package,
+                severity,
+                event.summary(),
+                event.details(),
+            )
+        }
+    }
+}
+
+impl From<&AuditEntry> for Notification {
+    fn from(entry: &AuditEntry) -> Self {
+        Self {
+            timestamp: entry.timestamp,
+            origin: Some(Origin::Audit(entry.clone())),
+            ..Self::new(
+                NotifyCategory::Audit,
+                entry.severity,
+                audit::alert_subject(entry),
+                audit::alert_body(entry),
+            )
+        }
+    }
+}
+
+/// A place notifications can be delivered to
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name routes refer to the sink by
+    fn name(&self) -> &str;
+
+    async fn notify(&self, notification: &Notification) -> Result<()>;
+}
+
+/// Prints notifications on stdout, or the daemon's log
+pub struct StdoutSink;
+
+#[async_trait]
+impl Notifier for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        println!("{}", format_line(notification));
+        Ok(())
+    }
+}
+
+/// Pops desktop notifications, unless `notifications.desktop` is off; of
+/// package events, only those of operations that took at least
+/// `notifications.desktop_min_seconds`
+pub struct DesktopSink {
+    config: NotificationsConfig,
+}
+
+#[async_trait]
+impl Notifier for DesktopSink {
+    fn name(&self) -> &str {
+        "desktop"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        if let Some(Origin::Package(event)) = &notification.origin {
+            if !event
+                .duration_secs
+                .is_some_and(|secs| secs >= self.config.desktop_min_seconds)
+            {
+                return Ok(());
+            }
+        }
+        desktop(&self.config, &notification.title, &notification.body).await;
+        Ok(())
+    }
+}
+
+/// A webhook under `notifications.webhooks`: POSTs the package events it
+/// subscribed to as `PackageEvent` JSON, and nothing else
+pub struct PackageWebhookSink {
+    name: String,
+    webhook: WebhookConfig,
+}
+
+#[async_trait]
+impl Notifier for PackageWebhookSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let Some(Origin::Package(event)) = &notification.origin else {
+            return Ok(());
+        };
+        if self.webhook.wants(event.event) {
+            deliver(&self.webhook, event).await?;
+            info!(
+                "Sent {} event for {} to {}",
+                event.event, event.package, self.webhook.url
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A chat channel under `audit.chat`, posted to in its service's format
+pub struct ChatSink {
+    name: String,
+    chat: ChatAlertConfig,
+}
+
+#[async_trait]
+impl Notifier for ChatSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let payload = match &notification.origin {
+            Some(Origin::Audit(entry)) => audit::chat_payload(self.chat.format, entry),
+            _ => audit::notification_chat_payload(self.chat.format, notification),
+        };
+        audit::post_chat(&self.chat, &payload).await
+    }
+}
+
+/// POSTs notifications as JSON, signed when there is a secret
+pub struct WebhookSink {
+    name: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    secret: Option<String>,
+}
+
+#[async_trait]
+impl Notifier for WebhookSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let body = serde_json::to_vec(notification)?;
+        post_json(&self.url, body, &self.headers, self.secret.as_deref()).await?;
+        Ok(())
+    }
+}
+
+/// Mails notifications through the SMTP server in `audit.email`
+pub struct EmailSink {
+    name: String,
+    email: EmailAlertConfig,
+}
+
+#[async_trait]
+impl Notifier for EmailSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        if let Some(Origin::Audit(entry)) = &notification.origin {
+            let subject = audit::alert_subject(entry);
+            return audit::send_email(&self.email, &subject, &audit::alert_body(entry)).await;
+        }
+        let subject = format!("[omni] {}", notification.title);
+        let body = format!(
+            "{}\n\nHost:     {}\nCategory: {}\nSeverity: {}\nTime:     {}\n",
+            notification.body,
+            notification.host,
+            notification.category,
+            notification.severity.as_str(),
+            notification.timestamp.to_rfc3339()
+        );
+        audit::send_email(&self.email, &subject, &body).await
+    }
+}
+
+/// One line describing `notification`, as the stdout sink prints it
+pub fn format_line(notification: &Notification) -> String {
+    let mut line = format!(
+        "🔔 [{} {}] {}",
+        notification.category,
+        notification.severity.as_str(),
+        notification.title
+    );
+    if !notification.body.is_empty() {
+        line.push_str(&format!(
+            ": {}",
+            notification.body.lines().next().unwrap_or_default()
+        ));
+    }
+    line
+}
+
+/// The sink `config` describes, which needs the SMTP settings of `email`
+/// when it mails
+pub fn sink_from_config(
+    config: &NotificationSinkConfig,
+    email: &EmailAlertConfig,
+) -> Result<Arc<dyn Notifier>> {
+    match (&config.webhook, config.email.is_empty()) {
+        (Some(url), true) => Ok(Arc::new(WebhookSink {
+            name: config.name.clone(),
+            url: url.clone(),
+            headers: config.headers.clone(),
+            secret: config.secret.clone(),
+        })),
+        (None, false) => Ok(Arc::new(EmailSink {
+            name: config.name.clone(),
+            email: EmailAlertConfig {
+                to: config.email.clone(),
+                ..email.clone()
+            },
+        })),
+        (Some(_), false) => Err(anyhow!(
+            "Notification sink '{}' sets both webhook and email",
+            config.name
+        )),
+        (None, true) => Err(anyhow!(
+            "Notification sink '{}' needs a webhook or email",
+            config.name
+        )),
+    }
+}
+
+/// Registered sinks and the routes that pick among them. Subsystems hand it
+/// every notification; nothing is sent without a matching route.
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    sinks: Vec<Arc<dyn Notifier>>,
+    routes: Vec<NotificationRoute>,
+}
+
+impl std::fmt::Debug for Dispatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sinks: Vec<&str> = self.sinks.iter().map(|s| s.name()).collect();
+        f.debug_struct("Dispatcher")
+            .field("sinks", &sinks)
+            .field("routes", &self.routes)
+            .finish()
+    }
+}
+
+impl Dispatcher {
+    pub fn new(routes: Vec<NotificationRoute>) -> Self {
+        Self {
+            sinks: Vec::new(),
+            routes,
+        }
+    }
+
+    /// The built-in sinks, one for each of `notifications.webhooks` and
+    /// `audit.chat`, the ones under `notifications.sinks`, and the routes of
+    /// `config` followed by [`default_routes`]. Sinks that are set up wrong
+    /// are left out with a warning.
+    pub fn from_config(config: &OmniConfig) -> Self {
+        let notifications = &config.notifications;
+        let mut routes = notifications.routes.clone();
+        routes.extend(default_routes(config));
+        let mut dispatcher = Self::new(routes)
+            .register(Arc::new(StdoutSink))
+            .register(Arc::new(DesktopSink {
+                config: notifications.clone(),
+            }))
+            .register(Arc::new(EmailSink {
+                name: "email".to_string(),
+                email: config.audit.email.clone(),
+            }));
+        for (i, webhook) in notifications.webhooks.iter().enumerate() {
+            dispatcher = dispatcher.register(Arc::new(PackageWebhookSink {
+                name: webhook_sink_name(i),
+                webhook: webhook.clone(),
+            }));
+        }
+        for (i, chat) in config.audit.chat.iter().enumerate() {
+            dispatcher = dispatcher.register(Arc::new(ChatSink {
+                name: chat_sink_name(i),
+                chat: chat.clone(),
+            }));
+        }
+        for sink in &notifications.sinks {
+            match sink_from_config(sink, &config.audit.email) {
+                Ok(sink) => dispatcher = dispatcher.register(sink),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        dispatcher
+    }
+
+    /// Add `sink`, replacing a registered one of the same name
+    pub fn register(mut self, sink: Arc<dyn Notifier>) -> Self {
+        self.sinks.retain(|s| s.name() != sink.name());
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Names of the sinks whose routes want `notification`, each once
+    pub fn targets(&self, notification: &Notification) -> Vec<&str> {
+        let mut targets: Vec<&str> = Vec::new();
+        for route in &self.routes {
+            if !route.wants(notification.category, notification.severity) {
+                continue;
+            }
+            for sink in &route.sinks {
+                if !targets.contains(&sink.as_str()) {
+                    targets.push(sink);
+                }
+            }
+        }
+        targets
+    }
+
+    /// Hand `notification` to the sinks its routes pick, warning about the
+    /// ones that fail or do not exist
+    pub async fn dispatch(&self, notification: &Notification) {
+        for name in self.targets(notification) {
+            let Some(sink) = self.sinks.iter().find(|s| s.name() == name) else {
+                warn!("No notification sink named '{}'", name);
+                continue;
+            };
+            match sink.notify(notification).await {
+                Ok(()) => debug!(
+                    "Sent {} notification '{}' to {}",
+                    notification.category, notification.title, name
+                ),
+                Err(e) => warn!("Could not notify {}: {:#}", name, e),
+            }
+        }
+    }
+}
+
+/// Routes for the settings that predate routes: package events to each of
+/// `notifications.webhooks`, package events and daemon updates to the
+/// desktop, and audit entries to `audit.email` (when enabled) and each of
+/// `audit.chat`, at their `min_severity`
+pub fn default_routes(config: &OmniConfig) -> Vec<NotificationRoute> {
+    let route = |categories: Vec<NotifyCategory>, min_severity, sink: String| NotificationRoute {
+        categories,
+        min_severity,
+        sinks: vec![sink],
+    };
+    let mut routes = vec![route(
+        vec![NotifyCategory::Package, NotifyCategory::Update],
+        AuditSeverity::Info,
+        "desktop".to_string(),
+    )];
+    for i in 0..config.notifications.webhooks.len() {
+        routes.push(route(
+            vec![NotifyCategory::Package],
+            AuditSeverity::Info,
+            webhook_sink_name(i),
+        ));
+    }
+    let email = &config.audit.email;
+    if email.enabled {
+        routes.push(route(
+            vec![NotifyCategory::Audit],
+            email.min_severity,
+            "email".to_string(),
+        ));
+    }
+    for (i, chat) in config.audit.chat.iter().enumerate() {
+        routes.push(route(
+            vec![NotifyCategory::Audit],
+            chat.min_severity,
+            chat_sink_name(i),
+        ));
+    }
+    routes
+}
+
+/// Name of the sink for the `i`th of `notifications.webhooks`, from 1
+fn webhook_sink_name(i: usize) -> String {
+    format!("webhook-{}", i + 1)
+}
+
+/// Name of the sink for the `i`th of `audit.chat`, from 1
+fn chat_sink_name(i: usize) -> String {
+    format!("chat-{}", i + 1)
+}
+
+/// Pop a desktop notification when `desktop` is on. Without a desktop
+/// session, or in a build without `desktop-notifications`, nothing shows.
+pub async fn desktop(config: &NotificationsConfig, summary: &str, body: &str) {
+    if !config.desktop {
+        return;
+    }
+    #[cfg(feature = "desktop-notifications")]
+    {
+        let mut notification = notify_rust::Notification::new();
+        notification
+            .appname("omni")
+            .summary(summary)
+            .body(body)
+            .icon("system-software-update");
+        // Talks to the notification daemon synchronously over D-Bus on Linux
+        let shown = tokio::task::spawn_blocking(move || {
+            notification.show().map(|_| ()).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|shown| shown);
+        if let Err(e) = shown {
+            debug!("No desktop notification: {}", e);
+        }
+    }
+    #[cfg(not(feature = "desktop-notifications"))]
+    debug!("Built without desktop notifications: {}: {}", summary, body);
+}
+
+async fn deliver(webhook: &WebhookConfig, event: &PackageEvent) -> Result<(), OmniError> {
+    let body = serde_json::to_vec(event).map_err(|e| OmniError::Unknown {
+        message: e.to_string(),
+    })?;
+    post_json(
+        &webhook.url,
+        body,
+        &webhook.headers,
+        webhook.secret.as_deref(),
+    )
+    .await
+}
+
+/// POST a JSON `body` to `url`, signed when there is a `secret`, retrying
+/// transient failures. The URL, header values and secret may be
+/// `keyring:<name>` references; errors name the reference, not the secret.
+pub async fn post_json(
+    url: &str,
+    body: Vec<u8>,
+    headers: &BTreeMap<String, String>,
+    secret: Option<&str>,
+) -> Result<(), OmniError> {
+    let network_error = |e: reqwest::Error| OmniError::NetworkError {
+        message: e.without_url().to_string(),
+        url: Some(url.to_string()),
+    };
+    let resolve = |value: &str| {
+        credentials::resolve(value).map_err(|e| OmniError::ConfigurationError {
+            message: e.to_string(),
+        })
+    };
+    let target = resolve(url)?;
+    let headers = headers
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), resolve(value)?)))
+        .collect::<Result<Vec<_>, OmniError>>()?;
+    let secret = secret.map(resolve).transpose()?;
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("omni/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .map_err(network_error)?;
+    let signature = secret.map(|secret| format!("sha256={}", sign(secret.as_bytes(), &body)));
+
+    let retry = RetryHandler::new(RetryConfig {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(5),
+        backoff_multiplier: 2.0,
+        jitter: true,
+    });
+    retry
+        .execute_with_context("webhook delivery", || {
+            let mut request = client
+                .post(&target)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            for (name, value) in &headers {
+                request = request.header(name, value);
+            }
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+            async move {
+                let response = request.send().await.map_err(network_error)?;
+                check_status(response.status(), url)
+            }
+        })
+        .await
+}
+
+/// Client errors will not go away by retrying, except timeouts and rate limits
+fn check_status(status: reqwest::StatusCode, url: &str) -> Result<(), OmniError> {
+    if status.is_success() {
+        return Ok(());
+    }
+    let message = format!("webhook answered {}", status);
+    let retryable = !status.is_client_error()
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+    if retryable {
+        Err(OmniError::NetworkError {
+            message,
+            url: Some(url.to_string()),
+        })
+    } else {
+        Err(OmniError::ConfigurationError { message })
+    }
+}
+
+/// Hex HMAC-SHA256 of `body`, for receivers to check the event came from us
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    const BLOCK: usize = 64;
+    let mut key = [0u8; BLOCK];
+    if secret.len() > BLOCK {
+        key[..32].copy_from_slice(&Sha256::digest(secret));
+    } else {
+        key[..secret.len()].copy_from_slice(secret);
+    }
+    let pad = |byte: u8| key.iter().map(|k| k ^ byte).collect::<Vec<u8>>();
+
+    let mut inner = Sha256::new();
+    inner.update(pad(0x36));
+    inner.update(body);
+    let mut outer = Sha256::new();
+    outer.update(pad(0x5c));
+    outer.update(inner.finalize());
+    hex::encode(outer.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_event() {
+        let event = PackageEvent::failed(NotifyEvent::Update, "firefox", "dnf exited with 1")
+            .with_box_type(Some("dnf"));
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "failure");
+        assert_eq!(json["operation"], "update");
+        assert_eq!(json["box_type"], "dnf");
+        assert_eq!(json["error"], "dnf exited with 1");
+        assert_eq!(event.summary(), "firefox was not updated");
+
+        let installed = PackageEvent::new(NotifyEvent::Install, "htop")
+            .with_box_type(Some("apt"))
+            .with_version(Some("3.3.0-4".to_string()))
+            .with_duration(Duration::from_secs(42));
+        assert_eq!(installed.summary(), "htop installed");
+        assert_eq!(installed.details(), "3.3.0-4 via apt");
+        assert_eq!(installed.duration_secs, Some(42));
+
+        let all = WebhookConfig::default();
+        assert!(all.wants(NotifyEvent::Remove));
+        let failures = WebhookConfig {
+            events: vec![NotifyEvent::Failure],
+            ..WebhookConfig::default()
+        };
+        assert!(failures.wants(event.event));
+        assert!(!failures.wants(NotifyEvent::Install));
+
+        assert!(
+            check_status(reqwest::StatusCode::BAD_GATEWAY, "http://hook")
+                .unwrap_err()
+                .is_retryable()
+        );
+        assert!(!check_status(reqwest::StatusCode::NOT_FOUND, "http://hook")
+            .unwrap_err()
+            .is_retryable());
+    }
+
+    /// Collects what it is handed
+    struct Recorder {
+        name: &'static str,
+        seen: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Notifier for Recorder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn notify(&self, notification: &Notification) -> Result<()> {
+            self.seen.lock().unwrap().push(notification.title.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_routes() {
+        let ops = Arc::new(Recorder {
+            name: "ops",
+            seen: Default::default(),
+        });
+        let dispatcher = Dispatcher::new(vec![
+            NotificationRoute {
+                categories: vec![NotifyCategory::Fleet, NotifyCategory::Audit],
+                min_severity: AuditSeverity::Warning,
+                sinks: vec!["ops".to_string()],
+            },
+            NotificationRoute {
+                sinks: vec!["ops".to_string(), "pager".to_string()],
+                min_severity: AuditSeverity::Critical,
+                ..NotificationRoute::default()
+            },
+        ])
+        .register(ops.clone());
+
+        let fleet = Notification::new(
+            NotifyCategory::Fleet,
+            AuditSeverity::Warning,
+            "fleet update nginx: 2 of 3 hosts succeeded",
+            "Failed on web3",
+        );
+        assert_eq!(dispatcher.targets(&fleet), vec!["ops"]);
+        let info = Notification::new(NotifyCategory::Audit, AuditSeverity::Info, "quiet", "");
+        assert!(dispatcher.targets(&info).is_empty());
+        let event = PackageEvent::failed(NotifyEvent::Install, "htop", "apt exited with 100");
+        let failed = Notification::from(&event);
+        assert_eq!(failed.severity, AuditSeverity::Warning);
+        assert!(dispatcher.targets(&failed).is_empty());
+        let critical =
+            Notification::new(NotifyCategory::Package, AuditSeverity::Critical, "bad", "");
+        assert_eq!(dispatcher.targets(&critical), vec!["ops", "pager"]);
+
+        // Unknown sinks are skipped with a warning
+        dispatcher.dispatch(&fleet).await;
+        dispatcher.dispatch(&info).await;
+        dispatcher.dispatch(&critical).await;
+        assert_eq!(
+            *ops.seen.lock().unwrap(),
+            vec!["fleet update nginx: 2 of 3 hosts succeeded", "bad"]
+        );
+        assert_eq!(
+            format_line(&fleet),
+            "🔔 [fleet warning] fleet update nginx: 2 of 3 hosts succeeded: Failed on web3"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_default_routes() {
+        let mut config = OmniConfig::default();
+        config.notifications.webhooks = vec![WebhookConfig {
+            url: "http://127.0.0.1:9/hook".to_string(),
+            events: vec![NotifyEvent::Failure],
+            ..WebhookConfig::default()
+        }];
+        config.audit.email.enabled = true;
+        config.audit.email.min_severity = AuditSeverity::Warning;
+        config.audit.chat = vec![ChatAlertConfig::default()];
+        let dispatcher = Dispatcher::from_config(&config);
+
+        let installed = Notification::from(&PackageEvent::new(NotifyEvent::Install, "htop"));
+        assert_eq!(dispatcher.targets(&installed), vec!["desktop", "webhook-1"]);
+        let update = Notification::new(NotifyCategory::Update, AuditSeverity::Info, "3", "");
+        assert_eq!(dispatcher.targets(&update), vec!["desktop"]);
+        let warning = Notification::new(NotifyCategory::Audit, AuditSeverity::Warning, "w", "");
+        assert_eq!(dispatcher.targets(&warning), vec!["email"]);
+        let critical = Notification::new(NotifyCategory::Audit, AuditSeverity::Critical, "c", "");
+        assert_eq!(dispatcher.targets(&critical), vec!["email", "chat-1"]);
+        let fleet = Notification::new(NotifyCategory::Fleet, AuditSeverity::Critical, "f", "");
+        assert!(dispatcher.targets(&fleet).is_empty());
+
+        // Without email enabled, audit entries are not mailed
+        config.audit.email.enabled = false;
+        let dispatcher = Dispatcher::from_config(&config);
+        assert_eq!(dispatcher.targets(&critical), vec!["chat-1"]);
+
+        // The webhook only gets the package events it subscribed to
+        let sink = PackageWebhookSink {
+            name: webhook_sink_name(0),
+            webhook: config.notifications.webhooks[0].clone(),
+        };
+        sink.notify(&installed).await.unwrap();
+        sink.notify(&update).await.unwrap();
+    }
+
+    #[test]
+    fn test_sink_from_config() {
+        let email = EmailAlertConfig::default();
+        let hook = NotificationSinkConfig {
+            name: "ops".to_string(),
+            webhook: Some("https://hooks.example.com/omni".to_string()),
+            ..NotificationSinkConfig::default()
+        };
+        assert_eq!(sink_from_config(&hook, &email).unwrap().name(), "ops");
+        let both = NotificationSinkConfig {
+            email: vec!["ops@example.com".to_string()],
+            ..hook.clone()
+        };
+        assert!(sink_from_config(&both, &email).is_err());
+        let neither = NotificationSinkConfig {
+            name: "empty".to_string(),
+            ..NotificationSinkConfig::default()
+        };
+        assert!(sink_from_config(&neither, &email).is_err());
+    }
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
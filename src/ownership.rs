@@ -0,0 +1,513 @@
+//! Permissions and ownership of the files omni writes outside its own state
+//! (`files` in the config). Modes follow the configured umask, and under sudo
+//! files in the invoking user's home go back to that user instead of staying
+//! owned by root. `omni permissions repair` fixes files earlier runs left.
+
+use crate::boxes::appimage::APPIMAGE_DIR;
+use crate::config::{file_settings, FilesConfig};
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where apt reads repository entries and keys; omni's own files there are
+/// named `omni-*`
+pub const APT_SOURCES_DIR: &str = "/etc/apt/sources.list.d";
+pub const APT_KEYRINGS_DIR: &str = "/etc/apt/keyrings";
+
+/// What a written file is for, which decides its mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// A file for the user, e.g. a desktop entry
+    Regular,
+    /// A program, e.g. an AppImage
+    Executable,
+    /// Repository entries and keyrings, which apt reads as an unprivileged
+    /// user; always 0644
+    Shared,
+}
+
+impl FileKind {
+    pub fn mode(self, umask: u32) -> u32 {
+        match self {
+            FileKind::Regular => 0o666 & !umask,
+            FileKind::Executable => 0o777 & !umask,
+            FileKind::Shared => 0o644,
+        }
+    }
+}
+
+pub fn dir_mode(umask: u32) -> u32 {
+    0o777 & !umask
+}
+
+/// A user files should belong to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+/// The user who ran `sudo omni`, when omni runs as root through sudo
+pub fn invoking_user() -> Option<Owner> {
+    #[cfg(unix)]
+    {
+        if unsafe { libc::geteuid() } != 0 {
+            return None;
+        }
+        let uid: u32 = std::env::var("SUDO_UID").ok()?.parse().ok()?;
+        let gid: u32 = std::env::var("SUDO_GID").ok()?.parse().ok()?;
+        if uid == 0 {
+            return None;
+        }
+        Some(Owner {
+            uid,
+            gid,
+            home: home_of(uid)?,
+        })
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+/// The invoking user under sudo, otherwise whoever runs omni
+fn expected_owner() -> Option<Owner> {
+    if let Some(owner) = invoking_user() {
+        return Some(owner);
+    }
+    #[cfg(unix)]
+    {
+        Some(Owner {
+            uid: unsafe { libc::geteuid() },
+            gid: unsafe { libc::getegid() },
+            home: dirs::home_dir()?,
+        })
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+/// Home directory of `uid` from the password database; `$HOME` may still be
+/// the invoking user's or already root's, depending on how sudo was set up
+#[cfg(unix)]
+fn home_of(uid: u32) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() || passwd.pw_dir.is_null() {
+        return None;
+    }
+    let dir = unsafe { std::ffi::CStr::from_ptr(passwd.pw_dir) };
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(dir.to_bytes())))
+}
+
+/// Write `contents` to `path` with the mode of `kind`, creating missing
+/// parents, and hand the file to the invoking user when it is in their home
+pub fn write(path: &Path, contents: &[u8], kind: FileKind) -> Result<()> {
+    write_with(file_settings(), path, contents, kind)
+}
+
+fn write_with(settings: &FilesConfig, path: &Path, contents: &[u8], kind: FileKind) -> Result<()> {
+    let umask = settings.umask_bits()?;
+    if let Some(parent) = path.parent() {
+        create_dir_all_with(settings, parent)?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    set_mode(path, kind.mode(umask))?;
+    hand_over_with(settings, path)
+}
+
+/// Create `path` and its missing parents with the umask's directory mode
+fn create_dir_all_with(settings: &FilesConfig, path: &Path) -> Result<()> {
+    let umask = settings.umask_bits()?;
+    let missing: Vec<PathBuf> = path
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    fs::create_dir_all(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    for dir in missing.iter().rev() {
+        set_mode(dir, dir_mode(umask))?;
+        hand_over_with(settings, dir)?;
+    }
+    Ok(())
+}
+
+/// Give `path` to the invoking user when omni runs under sudo and the path
+/// is in their home
+pub fn hand_over(path: &Path) -> Result<()> {
+    hand_over_with(file_settings(), path)
+}
+
+fn hand_over_with(settings: &FilesConfig, path: &Path) -> Result<()> {
+    if !settings.chown_to_invoking_user {
+        return Ok(());
+    }
+    match invoking_user() {
+        Some(owner) if path.starts_with(&owner.home) => chown(path, owner.uid, owner.gid),
+        _ => Ok(()),
+    }
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Failed to set the mode of {}", path.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+fn chown(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    #[cfg(unix)]
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+        .with_context(|| format!("Failed to give {} to uid {}", path.display(), uid))?;
+    #[cfg(not(unix))]
+    let _ = (path, uid, gid);
+    Ok(())
+}
+
+/// Something wrong with a file omni wrote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    Owner {
+        uid: u32,
+        expected_uid: u32,
+        expected_gid: u32,
+    },
+    Mode {
+        mode: u32,
+        expected: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub problem: Problem,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.problem {
+            Problem::Owner {
+                uid, expected_uid, ..
+            } => write!(
+                f,
+                "{}: owned by uid {}, should be uid {}",
+                self.path.display(),
+                uid,
+                expected_uid
+            ),
+            Problem::Mode { mode, expected } => write!(
+                f,
+                "{}: mode {:04o}, should be {:04o}",
+                self.path.display(),
+                mode,
+                expected
+            ),
+        }
+    }
+}
+
+/// How files below a scanned directory should look
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expectation {
+    uid: u32,
+    gid: u32,
+    /// Permission bits nothing may have
+    umask: u32,
+    /// Repository entries and keyrings: exactly 0644
+    shared: bool,
+}
+
+/// What is wrong with a file of `mode` owned by `uid`
+fn problems(mode: u32, uid: u32, is_dir: bool, expectation: &Expectation) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if uid != expectation.uid {
+        problems.push(Problem::Owner {
+            uid,
+            expected_uid: expectation.uid,
+            expected_gid: expectation.gid,
+        });
+    }
+    let mode = mode & 0o7777;
+    let expected = if expectation.shared && !is_dir {
+        FileKind::Shared.mode(0)
+    } else {
+        mode & !expectation.umask
+    };
+    if mode & 0o777 != expected & 0o777 {
+        problems.push(Problem::Mode { mode, expected });
+    }
+    problems
+}
+
+/// Files omni wrote with the wrong owner or a mode wider than the umask:
+/// its state, AppImages and desktop entries in the user's home, and its
+/// repository entries and keyrings under /etc/apt
+pub fn scan() -> Result<Vec<Finding>> {
+    let settings = file_settings();
+    let umask = settings.umask_bits()?;
+    let mut findings = Vec::new();
+
+    if let Some(owner) = expected_owner() {
+        let user = Expectation {
+            uid: owner.uid,
+            gid: owner.gid,
+            umask,
+            shared: false,
+        };
+        // Only the owner of the directories leading there; their modes are
+        // the user's business
+        let parents = Expectation {
+            umask: 0,
+            ..user.clone()
+        };
+        let mut checked = BTreeSet::new();
+        for root in user_paths(&owner.home) {
+            for parent in root.ancestors().skip(1) {
+                if parent == owner.home || !parent.starts_with(&owner.home) {
+                    break;
+                }
+                if checked.insert(parent.to_path_buf()) {
+                    check(parent, &parents, &mut findings);
+                }
+            }
+            scan_tree(&root, &user, &mut findings);
+        }
+    }
+
+    let system = Expectation {
+        uid: 0,
+        gid: 0,
+        umask,
+        shared: true,
+    };
+    for dir in [APT_SOURCES_DIR, APT_KEYRINGS_DIR] {
+        for path in omni_files(Path::new(dir)) {
+            scan_tree(&path, &system, &mut findings);
+        }
+    }
+    Ok(findings)
+}
+
+/// omni's directories in `home` with the default XDG layout, and the
+/// desktop entries of its AppImages
+fn user_paths(home: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = [".config", ".local/share", ".local/state", ".cache"]
+        .iter()
+        .map(|dir| home.join(dir).join("omni"))
+        .collect();
+    let appimages = home.join(APPIMAGE_DIR);
+    if let Ok(entries) = fs::read_dir(&appimages) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(name) = name.strip_suffix(".AppImage") {
+                paths.push(
+                    home.join(".local/share/applications")
+                        .join(format!("{}.desktop", name.to_lowercase())),
+                );
+            }
+        }
+    }
+    paths.push(appimages);
+    paths
+}
+
+fn omni_files(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("omni-"))
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Check `path` and, for a directory, everything below it
+fn scan_tree(path: &Path, expectation: &Expectation, findings: &mut Vec<Finding>) {
+    if check(path, expectation, findings) {
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                scan_tree(&entry.path(), expectation, findings);
+            }
+        }
+    }
+}
+
+/// Check `path` alone, returning whether it is a directory; symlinks are
+/// neither followed nor checked
+fn check(path: &Path, expectation: &Expectation, findings: &mut Vec<Finding>) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return false;
+        };
+        if metadata.file_type().is_symlink() {
+            return false;
+        }
+        for problem in problems(
+            metadata.mode(),
+            metadata.uid(),
+            metadata.is_dir(),
+            expectation,
+        ) {
+            findings.push(Finding {
+                path: path.to_path_buf(),
+                problem,
+            });
+        }
+        metadata.is_dir()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, expectation, findings);
+        false
+    }
+}
+
+/// Fix one finding; changing the owner needs root
+pub fn repair(finding: &Finding) -> Result<()> {
+    match finding.problem {
+        Problem::Owner {
+            expected_uid,
+            expected_gid,
+            ..
+        } => chown(&finding.path, expected_uid, expected_gid),
+        Problem::Mode { expected, .. } => set_mode(&finding.path, expected),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    fn settings(umask: &str) -> FilesConfig {
+        FilesConfig {
+            umask: umask.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().mode() & 0o777
+    }
+
+    #[test]
+    fn test_modes() {
+        assert_eq!(FileKind::Regular.mode(0o022), 0o644);
+        assert_eq!(FileKind::Executable.mode(0o077), 0o700);
+        assert_eq!(FileKind::Shared.mode(0o077), 0o644);
+        assert_eq!(dir_mode(0o027), 0o750);
+
+        assert_eq!(settings("0027").umask_bits().unwrap(), 0o027);
+        assert!(settings("0999").umask_bits().is_err());
+        assert!(settings("7777").umask_bits().is_err());
+    }
+
+    #[test]
+    fn test_write_applies_umask() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("apps/bin/tool.AppImage");
+        write_with(&settings("077"), &app, b"ELF", FileKind::Executable).unwrap();
+        assert_eq!(mode(&app), 0o700);
+        assert_eq!(mode(&dir.path().join("apps")), 0o700);
+        assert_eq!(mode(&dir.path().join("apps/bin")), 0o700);
+
+        let list = dir.path().join("omni-added-repo.list");
+        write_with(&settings("077"), &list, b"deb x y z\n", FileKind::Shared).unwrap();
+        assert_eq!(mode(&list), 0o644);
+    }
+
+    #[test]
+    fn test_problems() {
+        let user = Expectation {
+            uid: 1000,
+            gid: 1000,
+            umask: 0o022,
+            shared: false,
+        };
+        assert!(problems(0o100600, 1000, false, &user).is_empty());
+        assert_eq!(
+            problems(0o100666, 0, false, &user),
+            [
+                Problem::Owner {
+                    uid: 0,
+                    expected_uid: 1000,
+                    expected_gid: 1000
+                },
+                Problem::Mode {
+                    mode: 0o666,
+                    expected: 0o644
+                }
+            ]
+        );
+
+        let system = Expectation {
+            uid: 0,
+            gid: 0,
+            umask: 0o022,
+            shared: true,
+        };
+        assert!(problems(0o100644, 0, false, &system).is_empty());
+        assert!(problems(0o40755, 0, true, &system).is_empty());
+        assert_eq!(
+            problems(0o100600, 0, false, &system),
+            [Problem::Mode {
+                mode: 0o600,
+                expected: 0o644
+            }]
+        );
+    }
+
+    #[test]
+    fn test_scan_and_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join(".local/share/omni");
+        fs::create_dir_all(&state).unwrap();
+        let file = state.join("omni.db");
+        fs::write(&file, "").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o666)).unwrap();
+
+        let metadata = fs::metadata(&file).unwrap();
+        let expectation = Expectation {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            umask: 0o022,
+            shared: false,
+        };
+        let mut findings = Vec::new();
+        for path in user_paths(dir.path()) {
+            scan_tree(&path, &expectation, &mut findings);
+        }
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].to_string(),
+            format!("{}: mode 0666, should be 0644", file.display())
+        );
+
+        repair(&findings[0]).unwrap();
+        assert_eq!(mode(&file), 0o644);
+    }
+}
@@ -0,0 +1,286 @@
+//! Installs and removals the GUI batches up. The queue can be reordered and
+//! trimmed before it runs; running it is one transaction: a single snapshot
+//! first, then every operation in order, reverting to the snapshot when one
+//! fails.
+
+use crate::brain::OmniBrain;
+use crate::lock::OperationLock;
+use anyhow::{anyhow, Result};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueAction {
+    Install,
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedOperation {
+    pub action: QueueAction,
+    pub package: String,
+    pub box_type: Option<String>,
+}
+
+impl QueuedOperation {
+    pub fn install(package: &str, box_type: Option<&str>) -> Self {
+        Self {
+            action: QueueAction::Install,
+            package: package.to_string(),
+            box_type: box_type.map(str::to_string),
+        }
+    }
+
+    pub fn remove(package: &str) -> Self {
+        Self {
+            action: QueueAction::Remove,
+            package: package.to_string(),
+            box_type: None,
+        }
+    }
+
+    /// e.g. "Install firefox (flatpak)"
+    pub fn describe(&self) -> String {
+        let verb = match self.action {
+            QueueAction::Install => "Install",
+            QueueAction::Remove => "Remove",
+        };
+        match &self.box_type {
+            Some(box_type) => format!("{} {} ({})", verb, self.package, box_type),
+            None => format!("{} {}", verb, self.package),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageQueue {
+    operations: Vec<QueuedOperation>,
+}
+
+impl PackageQueue {
+    /// Queue `operation`; one already queued for the same package is replaced
+    /// in place, so queueing a removal after an install keeps only the removal
+    pub fn push(&mut self, operation: QueuedOperation) {
+        match self
+            .operations
+            .iter_mut()
+            .find(|queued| queued.package == operation.package)
+        {
+            Some(queued) => *queued = operation,
+            None => self.operations.push(operation),
+        }
+    }
+
+    pub fn operations(&self) -> &[QueuedOperation] {
+        &self.operations
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn move_up(&mut self, index: usize) {
+        if index > 0 && index < self.operations.len() {
+            self.operations.swap(index - 1, index);
+        }
+    }
+
+    pub fn move_down(&mut self, index: usize) {
+        if index + 1 < self.operations.len() {
+            self.operations.swap(index, index + 1);
+        }
+    }
+
+    pub fn cancel(&mut self, index: usize) -> Option<QueuedOperation> {
+        (index < self.operations.len()).then(|| self.operations.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.operations.clear();
+    }
+}
+
+/// How far a running queue has got, shared with the GUI while it runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueProgress {
+    pub total: usize,
+    pub done: usize,
+    /// The operation running now
+    pub current: Option<String>,
+    pub finished: bool,
+    pub error: Option<String>,
+}
+
+impl QueueProgress {
+    /// Share of the queue done, from 0.0 to 1.0
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        self.done as f32 / self.total as f32
+    }
+}
+
+/// Run `operations` on a thread of their own, with a brain of their own, so
+/// the GUI keeps drawing; poll the returned progress until it is finished
+pub fn spawn(operations: Vec<QueuedOperation>, mock: bool) -> Arc<Mutex<QueueProgress>> {
+    let progress = Arc::new(Mutex::new(QueueProgress {
+        total: operations.len(),
+        ..QueueProgress::default()
+    }));
+    let shared = Arc::clone(&progress);
+
+    std::thread::spawn(move || {
+        let result = tokio::runtime::Runtime::new()
+            .map_err(anyhow::Error::from)
+            .and_then(|runtime| {
+                runtime.block_on(async {
+                    let mut brain = OmniBrain::new_with_mock(mock);
+                    run(&mut brain, &operations, &shared, mock).await
+                })
+            });
+
+        let mut progress = shared.lock().unwrap_or_else(|e| e.into_inner());
+        progress.current = None;
+        progress.finished = true;
+        progress.error = result.err().map(|e| e.to_string());
+    });
+
+    progress
+}
+
+/// Run `operations` in order behind one snapshot, reverting to it when one
+/// of them fails
+pub async fn run(
+    brain: &mut OmniBrain,
+    operations: &[QueuedOperation],
+    progress: &Mutex<QueueProgress>,
+    mock: bool,
+) -> Result<()> {
+    crate::config::ensure_writable("running the package queue")?;
+    let update = |change: &dyn Fn(&mut QueueProgress)| {
+        change(&mut progress.lock().unwrap_or_else(|e| e.into_inner()));
+    };
+
+    // Held for the whole queue, so nothing else changes packages in between
+    let _lock = if mock {
+        None
+    } else {
+        Some(
+            OperationLock::try_acquire()?
+                .ok_or_else(|| anyhow!("Another omni process is busy; run the queue later"))?,
+        )
+    };
+
+    update(&|p| p.current = Some("Taking a snapshot".to_string()));
+    let subject = format!("{} queued operations", operations.len());
+    let snapshot = brain.auto_snapshot("queue", &subject).await;
+
+    for (index, operation) in operations.iter().enumerate() {
+        update(&|p| p.current = Some(operation.describe()));
+        let result = match operation.action {
+            QueueAction::Install => {
+                brain
+                    .install(&operation.package, operation.box_type.as_deref())
+                    .await
+            }
+            QueueAction::Remove => {
+                brain
+                    .remove(&operation.package, operation.box_type.as_deref())
+                    .await
+            }
+        };
+
+        if let Err(e) = result {
+            let failed = format!("{} failed: {}", operation.describe(), e);
+            if index == 0 {
+                return Err(anyhow!(failed));
+            }
+            let Some(snapshot) = snapshot else {
+                return Err(anyhow!(
+                    "{}; the {} operations before it stay applied, as no snapshot could be taken",
+                    failed,
+                    index
+                ));
+            };
+            update(&|p| p.current = Some("Reverting to the snapshot".to_string()));
+            return match brain.revert_to(&snapshot).await {
+                Ok(()) => Err(anyhow!("{}; reverted the queue's earlier changes", failed)),
+                Err(revert) => Err(anyhow!(
+                    "{}; reverting to snapshot {} failed too: {}",
+                    failed,
+                    snapshot,
+                    revert
+                )),
+            };
+        }
+        update(&|p| p.done = index + 1);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_editing() {
+        let mut queue = PackageQueue::default();
+        queue.push(QueuedOperation::install("firefox", None));
+        queue.push(QueuedOperation::install("gimp", Some("flatpak")));
+        queue.push(QueuedOperation::remove("vim"));
+        queue.push(QueuedOperation::remove("firefox"));
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.operations()[0].describe(), "Remove firefox");
+        assert_eq!(queue.operations()[1].describe(), "Install gimp (flatpak)");
+
+        queue.move_up(2);
+        queue.move_up(0);
+        queue.move_down(2);
+        let order: Vec<&str> = queue
+            .operations()
+            .iter()
+            .map(|o| o.package.as_str())
+            .collect();
+        assert_eq!(order, ["firefox", "vim", "gimp"]);
+
+        assert_eq!(queue.cancel(1).map(|o| o.package), Some("vim".to_string()));
+        assert_eq!(queue.cancel(5), None);
+        assert_eq!(queue.len(), 2);
+
+        let progress = QueueProgress {
+            total: 4,
+            done: 1,
+            ..QueueProgress::default()
+        };
+        assert_eq!(progress.fraction(), 0.25);
+    }
+
+    #[tokio::test]
+    async fn test_failed_queue_reverts() {
+        let mut brain = OmniBrain::new_with_mock(true);
+        // docker-ce fails in the mock universe
+        let operations = [
+            QueuedOperation::install("htop", None),
+            QueuedOperation::install("docker-ce", None),
+        ];
+        let progress = Mutex::new(QueueProgress::default());
+
+        let err = run(&mut brain, &operations, &progress, true)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Install docker-ce failed"));
+        assert!(err
+            .to_string()
+            .ends_with("reverted the queue's earlier changes"));
+        assert_eq!(progress.lock().unwrap().done, 1);
+        assert!(!crate::mock::backend()
+            .installed()
+            .iter()
+            .any(|r| r.package_name == "htop"));
+    }
+}
@@ -0,0 +1,327 @@
+//! Organisation policy: packages, box types and repositories an administrator
+//! allows or forbids, from a signed YAML file that installs, removals and
+//! repository additions are checked against
+
+use crate::audit::{AuditEntry, AuditEventType, AuditManager, AuditSeverity};
+use crate::config::{EnforcementConfig, OmniConfig};
+use crate::database::Database;
+use crate::error_handling::OmniError;
+use crate::keys::KeyStore;
+use crate::notifications::Dispatcher;
+use crate::self_update::parse_validsig;
+use crate::updater::matches_pattern;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// The policy file. Patterns may use `*`; empty lists restrict nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrgPolicy {
+    /// Only these packages may be installed
+    pub allowed_packages: Vec<String>,
+    /// Packages that may not be installed
+    pub blocked_packages: Vec<String>,
+    /// Packages that may not be removed
+    pub protected_packages: Vec<String>,
+    /// Installs must go through one of these box types
+    pub required_box_types: Vec<String>,
+    /// Repositories and download URLs that may not be used
+    pub forbidden_repositories: Vec<String>,
+}
+
+/// Something the policy is asked about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation<'a> {
+    Install {
+        package: &'a str,
+        /// Requested box type; auto-detection skips box types the policy rules out
+        box_type: Option<&'a str>,
+        /// Download URL or repository the package comes from, when known
+        source: Option<&'a str>,
+    },
+    Remove {
+        package: &'a str,
+    },
+    AddRepository {
+        repository: &'a str,
+    },
+}
+
+impl Operation<'_> {
+    fn package(&self) -> Option<&str> {
+        match self {
+            Operation::Install { package, .. } | Operation::Remove { package } => Some(package),
+            Operation::AddRepository { .. } => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Operation::Install { package, .. } => format!("installing {}", package),
+            Operation::Remove { package } => format!("removing {}", package),
+            Operation::AddRepository { repository } => format!("adding repository {}", repository),
+        }
+    }
+}
+
+impl OrgPolicy {
+    /// Why the policy forbids `operation`, if it does
+    pub fn check(&self, operation: &Operation) -> Option<String> {
+        let matches_any = |patterns: &[String], name: &str| {
+            patterns.iter().find(|p| matches_pattern(p, name)).cloned()
+        };
+
+        match *operation {
+            Operation::Install {
+                package,
+                box_type,
+                source,
+            } => {
+                if let Some(pattern) = matches_any(&self.blocked_packages, package) {
+                    return Some(format!("{} is blocked (matches {})", package, pattern));
+                }
+                if !self.allowed_packages.is_empty()
+                    && matches_any(&self.allowed_packages, package).is_none()
+                {
+                    return Some(format!(
+                        "{} is not on the list of allowed packages",
+                        package
+                    ));
+                }
+                if let Some(box_type) = box_type {
+                    if !self.allows_box(box_type) {
+                        return Some(format!(
+                            "installs must use {}, not {}",
+                            self.required_box_types.join(" or "),
+                            box_type
+                        ));
+                    }
+                }
+                source.and_then(|source| self.check_repository(source))
+            }
+            Operation::Remove { package } => matches_any(&self.protected_packages, package)
+                .map(|pattern| format!("{} is protected (matches {})", package, pattern)),
+            Operation::AddRepository { repository } => self.check_repository(repository),
+        }
+    }
+
+    pub fn allows_box(&self, box_type: &str) -> bool {
+        self.required_box_types.is_empty() || self.required_box_types.iter().any(|b| b == box_type)
+    }
+
+    fn check_repository(&self, repository: &str) -> Option<String> {
+        self.forbidden_repositories
+            .iter()
+            .find(|pattern| matches_pattern(pattern, repository))
+            .map(|pattern| {
+                format!(
+                    "{} is a forbidden repository (matches {})",
+                    repository, pattern
+                )
+            })
+    }
+}
+
+/// The policy in force, or `None` when no policy file exists. A policy whose
+/// signature does not check out is an error, so a tampered file stops omni
+/// rather than being ignored.
+pub fn load(settings: &EnforcementConfig) -> Result<Option<OrgPolicy>> {
+    let path = &settings.policy_file;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    if settings.require_signature {
+        let signer = verify_signature(path, &settings.signers)?;
+        info!("Policy {} is signed by {}", path.display(), signer);
+    } else {
+        warn!(
+            "Using policy {} without checking its signature",
+            path.display()
+        );
+    }
+
+    let policy = serde_yaml::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| anyhow!("Invalid policy file {}: {}", path.display(), e))?;
+    Ok(Some(policy))
+}
+
+/// Fingerprint of the trusted key that signed `path`
+fn verify_signature(path: &Path, signers: &[String]) -> Result<String> {
+    let signature = ["asc", "sig"]
+        .iter()
+        .map(|extension| signature_path(path, extension))
+        .find(|candidate| candidate.exists())
+        .ok_or_else(|| anyhow!("The policy {} has no signature file", path.display()))?;
+
+    let output = KeyStore::open()?
+        .gpg()
+        .args(["--status-fd", "1", "--verify"])
+        .arg(&signature)
+        .arg(path)
+        .output()?;
+    let signer = parse_validsig(&String::from_utf8_lossy(&output.stdout))
+        .filter(|_| output.status.success())
+        .ok_or_else(|| {
+            anyhow!(
+                "The signature of {} does not verify against omni's trusted keys",
+                path.display()
+            )
+        })?;
+
+    if !signers.is_empty() && !signers.iter().any(|s| s.eq_ignore_ascii_case(&signer)) {
+        return Err(anyhow!(
+            "{} is signed by {}, which is not in enforcement.signers",
+            path.display(),
+            signer
+        ));
+    }
+    Ok(signer)
+}
+
+fn signature_path(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Refuse `operation` when the policy forbids it or cannot be trusted,
+/// recording a security violation in the audit log. Returns the policy in force
+/// so callers can apply it further (box type selection).
+pub async fn enforce(
+    config: &OmniConfig,
+    db: Option<&Database>,
+    operation: &Operation<'_>,
+) -> Result<Option<OrgPolicy>> {
+    let reason = match load(&config.enforcement) {
+        Ok(None) => return Ok(None),
+        Ok(Some(policy)) => match policy.check(operation) {
+            None => return Ok(Some(policy)),
+            Some(reason) => reason,
+        },
+        Err(e) => e.to_string(),
+    };
+
+    let message = format!("Policy forbids {}: {}", operation.describe(), reason);
+    if let Some(db) = db {
+        let mut entry = AuditEntry::new(
+            AuditEventType::SecurityViolation,
+            AuditSeverity::Warning,
+            &message,
+        )
+        .with_success(false);
+        if let Some(package) = operation.package() {
+            entry = entry.with_package(package, None);
+        }
+        let audit = AuditManager::with_database(db.clone())
+            .with_alerts(config.audit.clone())
+            .with_notifier(Dispatcher::from_config(config));
+        if let Err(e) = audit.log_event(&entry).await {
+            warn!("Failed to record policy violation in audit log: {}", e);
+        }
+    }
+    Err(OmniError::SecurityViolation { message }.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install<'a>(package: &'a str, box_type: Option<&'a str>) -> Operation<'a> {
+        Operation::Install {
+            package,
+            box_type,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_policy_check() {
+        let policy: OrgPolicy = serde_yaml::from_str(
+            r#"
+blocked_packages: ["telnetd", "*-nonfree"]
+protected_packages: ["openssh-server"]
+required_box_types: ["apt", "flatpak"]
+forbidden_repositories: ["ppa:*", "*.example.net/*"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.check(&install("firefox", Some("apt"))), None);
+        assert_eq!(policy.check(&install("firefox", None)), None);
+        assert!(policy
+            .check(&install("telnetd", Some("apt")))
+            .unwrap()
+            .contains("blocked"));
+        assert!(policy.check(&install("unrar-nonfree", None)).is_some());
+        assert!(policy
+            .check(&install("firefox", Some("snap")))
+            .unwrap()
+            .contains("apt or flatpak"));
+        assert!(!policy.allows_box("snap"));
+
+        let appimage = Operation::Install {
+            package: "tool",
+            box_type: Some("flatpak"),
+            source: Some("https://downloads.example.net/tool.AppImage"),
+        };
+        assert!(policy
+            .check(&appimage)
+            .unwrap()
+            .contains("forbidden repository"));
+        let ppa = Operation::AddRepository {
+            repository: "ppa:someone/tools",
+        };
+        assert!(policy.check(&ppa).is_some());
+
+        let remove = |package| Operation::Remove { package };
+        assert!(policy
+            .check(&remove("openssh-server"))
+            .unwrap()
+            .contains("protected"));
+        assert_eq!(policy.check(&remove("telnetd")), None);
+    }
+
+    #[test]
+    fn test_allowed_packages() {
+        let policy = OrgPolicy {
+            allowed_packages: vec!["python3*".to_string()],
+            ..OrgPolicy::default()
+        };
+        assert_eq!(policy.check(&install("python3-venv", None)), None);
+        assert!(policy.check(&install("nodejs", None)).is_some());
+        assert_eq!(
+            OrgPolicy::default().check(&install("nodejs", Some("snap"))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = EnforcementConfig {
+            policy_file: dir.path().join("policy.yaml"),
+            ..EnforcementConfig::default()
+        };
+        assert_eq!(load(&settings).unwrap(), None);
+
+        fs::write(&settings.policy_file, "blocked_packages: [telnetd]\n").unwrap();
+        // Signed policies are the default; an unsigned one is refused
+        assert!(load(&settings).is_err());
+
+        let unsigned = EnforcementConfig {
+            require_signature: false,
+            ..settings
+        };
+        let policy = load(&unsigned).unwrap().unwrap();
+        assert_eq!(policy.blocked_packages, vec!["telnetd"]);
+        assert_eq!(
+            signature_path(&unsigned.policy_file, "asc"),
+            dir.path().join("policy.yaml.asc")
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use crate::config::OmniConfig;
+use crate::distro;
+use crate::downgrade;
+use std::cmp::Ordering;
+use std::process::Command;
+use tracing::{debug, info};
+
+/// Backends auto-detection knows how to install with
+const AUTO_BOX_TYPES: [&str; 5] = ["apt", "dnf", "pacman", "flatpak", "snap"];
+
+/// Backends to try when no box type was requested: `preferred_order` first, then
+/// the remaining ones. Disabled and unavailable backends are left out.
+pub fn box_order(config: &OmniConfig) -> Vec<String> {
+    let mut order: Vec<String> = config
+        .boxes
+        .preferred_order
+        .iter()
+        .filter(|bt| AUTO_BOX_TYPES.contains(&bt.as_str()))
+        .cloned()
+        .collect();
+    for box_type in AUTO_BOX_TYPES {
+        if !order.iter().any(|bt| bt == box_type) {
+            order.push(box_type.to_string());
+        }
+    }
+
+    order.retain(|bt| config.is_box_enabled(bt) && distro::command_exists(bt));
+    order
+}
+
+/// Sort key for a package source: backend priority, then repository priority.
+/// Unlisted backends and repositories rank after listed ones.
+pub fn source_rank(
+    config: &OmniConfig,
+    box_type: &str,
+    repository: Option<&str>,
+) -> (usize, usize) {
+    let box_rank = config
+        .get_box_priority(box_type)
+        .unwrap_or(config.boxes.preferred_order.len());
+    (box_rank, repository_rank(config, repository))
+}
+
+fn repository_rank(config: &OmniConfig, repository: Option<&str>) -> usize {
+    repository
+        .and_then(|repo| config.get_repository_priority(repo))
+        .unwrap_or(config.boxes.repository_priority.len())
+}
+
+/// Package argument for the backend's install command. When the repository
+/// priority picks a different version than the backend would, that version is
+/// requested explicitly.
+pub fn install_target(config: &OmniConfig, box_type: &str, package: &str) -> String {
+    match (box_type, preferred_version(config, box_type, package)) {
+        ("apt", Some(version)) => format!("{}={}", package, version),
+        ("dnf", Some(version)) => format!("{}-{}", package, version),
+        _ => package.to_string(),
+    }
+}
+
+/// Version of `package` offered by the highest-priority repository, or `None` when
+/// the backend's own choice already comes from there
+pub fn preferred_version(config: &OmniConfig, box_type: &str, package: &str) -> Option<String> {
+    if config.boxes.repository_priority.is_empty() {
+        return None;
+    }
+
+    let sources = match box_type {
+        "apt" => parse_apt_madison_sources(&run("apt-cache", &["madison", package])?),
+        "dnf" => parse_dnf_available_sources(
+            &run(
+                "dnf",
+                &[
+                    "list",
+                    "--quiet",
+                    "--showduplicates",
+                    "--available",
+                    package,
+                ],
+            )?,
+            package,
+        ),
+        _ => return None,
+    };
+    debug!("Sources of {} via {}: {:?}", package, box_type, sources);
+
+    let version = select_preferred(config, &sources)?;
+    info!(
+        "Repository priority selects {} {} via {}",
+        package, version, box_type
+    );
+    Some(version)
+}
+
+/// Pick the newest version from the best-ranked repository among `(version,
+/// repository)` pairs. Returns `None` when that is also the newest version
+/// overall, which the backend installs anyway.
+pub fn select_preferred(config: &OmniConfig, sources: &[(String, String)]) -> Option<String> {
+    let newest = sources
+        .iter()
+        .map(|(version, _)| version)
+        .max_by(|a, b| downgrade::compare_versions(a, b))?;
+
+    let (version, repository) = sources.iter().min_by(|(va, ra), (vb, rb)| {
+        repository_rank(config, Some(ra))
+            .cmp(&repository_rank(config, Some(rb)))
+            .then_with(|| downgrade::compare_versions(vb, va))
+    })?;
+
+    if config.get_repository_priority(repository).is_none()
+        || downgrade::compare_versions(version, newest) == Ordering::Equal
+    {
+        return None;
+    }
+    Some(version.clone())
+}
+
+fn run(command: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(command).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// `apt-cache madison`: `pkg | version | source` rows
+pub fn parse_apt_madison_sources(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('|').map(str::trim);
+            let (_, version, source) = (columns.next()?, columns.next()?, columns.next()?);
+            (!version.is_empty()).then(|| (version.to_string(), source.to_string()))
+        })
+        .collect()
+}
+
+/// `dnf list --showduplicates --available`: `name.arch version repo` rows
+pub fn parse_dnf_available_sources(output: &str, package: &str) -> Vec<(String, String)> {
+    let prefix = format!("{}.", package);
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let (name, version, repo) = (parts.next()?, parts.next()?, parts.next()?);
+            name.starts_with(&prefix)
+                .then(|| (version.to_string(), repo.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_repositories(repositories: &[&str]) -> OmniConfig {
+        let mut config = OmniConfig::default();
+        config.boxes.repository_priority = repositories.iter().map(|r| r.to_string()).collect();
+        config
+    }
+
+    #[test]
+    fn test_distro_repository_preferred_over_ppa() {
+        let madison = "   curl | 8.9.0-1~ppa1 | https://ppa.launchpadcontent.net/someone/curl/ubuntu noble/main amd64 Packages
+   curl | 8.5.0-2ubuntu10.4 | http://archive.ubuntu.com/ubuntu noble-updates/main amd64 Packages
+   curl | 8.5.0-2ubuntu10 | http://archive.ubuntu.com/ubuntu noble/main amd64 Packages
+";
+        let sources = parse_apt_madison_sources(madison);
+        assert_eq!(sources.len(), 3);
+
+        let config = config_with_repositories(&["archive.ubuntu.com", "ppa.launchpadcontent.net"]);
+        assert_eq!(
+            select_preferred(&config, &sources).as_deref(),
+            Some("8.5.0-2ubuntu10.4")
+        );
+
+        // Preferring the PPA matches what apt would install anyway
+        let config = config_with_repositories(&["ppa.launchpadcontent.net"]);
+        assert_eq!(select_preferred(&config, &sources), None);
+
+        // No listed repository offers the package
+        let config = config_with_repositories(&["packages.example.com"]);
+        assert_eq!(select_preferred(&config, &sources), None);
+    }
+
+    #[test]
+    fn test_dnf_sources_and_box_rank() {
+        let dnf = "Available Packages
+nodejs.x86_64    1:20.12.2-1.fc40    updates
+nodejs.x86_64    1:22.4.1-1.fc40     nodejs-upstream
+nodejs-docs.noarch 1:20.12.2-1.fc40  updates
+";
+        let sources = parse_dnf_available_sources(dnf, "nodejs");
+        assert_eq!(sources.len(), 2);
+
+        let config = config_with_repositories(&["updates"]);
+        assert_eq!(
+            select_preferred(&config, &sources).as_deref(),
+            Some("1:20.12.2-1.fc40")
+        );
+
+        let mut config = OmniConfig::default();
+        config.boxes.preferred_order = vec!["flatpak".to_string(), "snap".to_string()];
+        assert!(source_rank(&config, "flatpak", None) < source_rank(&config, "snap", None));
+        assert!(source_rank(&config, "snap", None) < source_rank(&config, "apt", None));
+    }
+}
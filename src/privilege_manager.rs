@@ -1,5 +1,7 @@
+use crate::error_handling::OmniError;
 use anyhow::Result;
-use std::process::Command;
+use std::io::Write;
+use std::process::{Command, Stdio};
 use tracing::{info, warn};
 
 pub struct PrivilegeManager {
@@ -78,7 +80,10 @@ impl PrivilegeManager {
                 return Err(anyhow::anyhow!("Sudo command failed: {}", stderr));
             }
         } else {
-            return Err(anyhow::anyhow!("Insufficient privileges to execute command"));
+            return Err(OmniError::PermissionDenied {
+                operation: format!("{} (insufficient privileges)", command),
+            }
+            .into());
         }
 
         Ok(())
@@ -111,8 +116,48 @@ impl PrivilegeManager {
 
             Ok(String::from_utf8_lossy(&output.stdout).to_string())
         } else {
-            Err(anyhow::anyhow!("Insufficient privileges to execute command"))
+            Err(OmniError::PermissionDenied {
+                operation: format!("{} (insufficient privileges)", command),
+            }
+            .into())
+        }
+    }
+
+    /// Execute a privileged command, feeding `input` to its stdin
+    pub fn execute_with_sudo_stdin(&self, command: &str, args: &[&str], input: &str) -> Result<()> {
+        let mut child = if self.is_root_user {
+            Command::new(command)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?
+        } else if self.has_sudo {
+            Command::new("sudo")
+                .args(&[command])
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?
+        } else {
+            return Err(OmniError::PermissionDenied {
+                operation: format!("{} (insufficient privileges)", command),
+            }
+            .into());
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes())?;
         }
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!("Command failed: {}", stderr));
+        }
+
+        Ok(())
     }
 
     fn check_is_root() -> bool {
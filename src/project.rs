@@ -0,0 +1,250 @@
+//! Project-local settings. Like direnv, omni walks up from the working
+//! directory to the nearest `.omni` directory: its `config.yaml` is laid over
+//! the user's configuration once allowed with `omni project allow`, and its
+//! `manifest.yaml` is what `omni sync` installs.
+
+use crate::config::{self, OmniConfig};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+pub const PROJECT_DIR: &str = ".omni";
+const TRUST_FILE: &str = "trusted-projects.yaml";
+
+/// A directory with a `.omni/config.yaml` or `.omni/manifest.yaml`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Project {
+    root: PathBuf,
+}
+
+impl Project {
+    /// The project around the working directory
+    pub fn discover() -> Option<Self> {
+        std::env::current_dir()
+            .ok()
+            .and_then(|dir| Self::discover_from(&dir))
+    }
+
+    pub fn discover_from(start: &Path) -> Option<Self> {
+        start
+            .ancestors()
+            .map(|dir| Self {
+                root: dir.to_path_buf(),
+            })
+            .find(|project| project.config_path().is_file() || project.manifest_path().is_file())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.root.join(PROJECT_DIR).join("config.yaml")
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.root.join(PROJECT_DIR).join("manifest.yaml")
+    }
+
+    fn config_digest(&self) -> Result<String> {
+        Ok(hex::encode(Sha256::digest(fs::read(self.config_path())?)))
+    }
+}
+
+/// Project configs allowed to apply, by path, with a hash of the contents that
+/// were allowed; an edited config has to be allowed again
+#[derive(Debug, Clone)]
+pub struct TrustStore {
+    path: PathBuf,
+}
+
+impl TrustStore {
+    pub fn open() -> Result<Self> {
+        Ok(Self::at(OmniConfig::state_dir()?.join(TRUST_FILE)))
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn is_allowed(&self, project: &Project) -> Result<bool> {
+        let key = project.config_path().display().to_string();
+        Ok(self.load()?.get(&key) == Some(&project.config_digest()?))
+    }
+
+    pub fn allow(&self, project: &Project) -> Result<()> {
+        let mut trusted = self.load()?;
+        trusted.insert(
+            project.config_path().display().to_string(),
+            project.config_digest()?,
+        );
+        self.save(&trusted)
+    }
+
+    /// Stop applying the project's config; `false` when it was not allowed
+    pub fn deny(&self, project: &Project) -> Result<bool> {
+        let mut trusted = self.load()?;
+        if trusted
+            .remove(&project.config_path().display().to_string())
+            .is_none()
+        {
+            return Ok(false);
+        }
+        self.save(&trusted)?;
+        Ok(true)
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, String>> {
+        #[derive(Deserialize, Default)]
+        struct Trusted {
+            #[serde(default)]
+            projects: BTreeMap<String, String>,
+        }
+
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let trusted: Trusted = serde_yaml::from_str(&fs::read_to_string(&self.path)?)
+            .map_err(|e| anyhow!("Invalid {}: {}", self.path.display(), e))?;
+        Ok(trusted.projects)
+    }
+
+    fn save(&self, projects: &BTreeMap<String, String>) -> Result<()> {
+        #[derive(Serialize)]
+        struct Trusted<'a> {
+            projects: &'a BTreeMap<String, String>,
+        }
+
+        config::ensure_writable("changing allowed project configs")?;
+        if let Some(parent) = self.path.parent() {
+            config::ensure_private_dir(parent)?;
+        }
+        config::write_private_file(
+            &self.path,
+            serde_yaml::to_string(&Trusted { projects })?.as_bytes(),
+        )
+    }
+}
+
+/// The config file and settings of the project around the working directory,
+/// when it has a config and it is allowed. A config that is not allowed is
+/// ignored with a warning, so cloning a repository cannot change omni's settings.
+pub fn overlay() -> Result<Option<(PathBuf, Value)>> {
+    let Some(project) = Project::discover() else {
+        return Ok(None);
+    };
+    let path = project.config_path();
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    if !TrustStore::open()?.is_allowed(&project)? {
+        static WARNED: Once = Once::new();
+        WARNED.call_once(|| {
+            eprintln!(
+                "⚠️  Ignoring {} until you run 'omni project allow' (again after each change)",
+                path.display()
+            );
+        });
+        return Ok(None);
+    }
+
+    let value = serde_yaml::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| anyhow!("Invalid project config {}: {}", path.display(), e))?;
+    Ok(Some((path, value)))
+}
+
+/// Lay `overlay` over `base`: mappings merge key by key, anything else replaces
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src/module");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(Project::discover_from(&nested), None);
+
+        // A bare .omni directory is not a project
+        fs::create_dir_all(dir.path().join("src").join(PROJECT_DIR)).unwrap();
+        assert_eq!(Project::discover_from(&nested), None);
+
+        fs::create_dir_all(dir.path().join(PROJECT_DIR)).unwrap();
+        fs::write(
+            dir.path().join(".omni/manifest.yaml"),
+            "project: demo\napps: []\n",
+        )
+        .unwrap();
+        let project = Project::discover_from(&nested).unwrap();
+        assert_eq!(project.root(), dir.path());
+        assert!(!project.config_path().exists());
+    }
+
+    #[test]
+    fn test_trust() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(PROJECT_DIR)).unwrap();
+        let project = Project {
+            root: dir.path().to_path_buf(),
+        };
+        fs::write(project.config_path(), "boxes:\n  disabled_boxes: [snap]\n").unwrap();
+
+        let store = TrustStore::at(dir.path().join(TRUST_FILE));
+        assert!(!store.is_allowed(&project).unwrap());
+        store.allow(&project).unwrap();
+        assert!(store.is_allowed(&project).unwrap());
+
+        // Edits need allowing again
+        fs::write(
+            project.config_path(),
+            "security:\n  verify_signatures: false\n",
+        )
+        .unwrap();
+        assert!(!store.is_allowed(&project).unwrap());
+
+        assert!(store.deny(&project).unwrap());
+        assert!(!store.deny(&project).unwrap());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut base: Value = serde_yaml::from_str(
+            "general:\n  log_level: info\n  fallback_enabled: true\nboxes:\n  disabled_boxes: [snap]\n",
+        )
+        .unwrap();
+        let overlay: Value = serde_yaml::from_str(
+            "general:\n  log_level: debug\nboxes:\n  disabled_boxes: [flatpak]\n",
+        )
+        .unwrap();
+        merge(&mut base, overlay);
+
+        assert_eq!(base["general"]["log_level"], Value::from("debug"));
+        assert_eq!(base["general"]["fallback_enabled"], Value::from(true));
+        assert_eq!(
+            base["boxes"]["disabled_boxes"],
+            serde_yaml::from_str::<Value>("[flatpak]").unwrap()
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::process::Command;
+use tracing::debug;
+
+/// Where an installed package actually came from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// apt origin suite/component, dnf repo id, pacman repository, flatpak remote or snap channel
+    pub repository: Option<String>,
+    /// Mirror or remote URL the package was fetched from
+    pub mirror_url: Option<String>,
+}
+
+impl Provenance {
+    pub fn is_empty(&self) -> bool {
+        self.repository.is_none() && self.mirror_url.is_none()
+    }
+}
+
+/// Ask the native package manager which repository/mirror an installed package came from.
+/// Never fails: provenance is best effort and an empty result is recorded as unknown.
+pub fn detect(package: &str, box_type: &str) -> Provenance {
+    let provenance = match box_type {
+        "apt" => run("apt-cache", &["policy", package])
+            .map(|out| parse_apt_policy(&out))
+            .unwrap_or_default(),
+        "dnf" => {
+            let repository = run("dnf", &["info", "--installed", package])
+                .and_then(|out| parse_field(&out, "From repo"));
+            let mirror_url = repository.as_deref().and_then(|repo| {
+                run("dnf", &["repoinfo", repo]).and_then(|out| parse_field(&out, "Repo-baseurl"))
+            });
+            Provenance {
+                repository,
+                mirror_url,
+            }
+        }
+        "pacman" => {
+            let repository =
+                run("pacman", &["-Si", package]).and_then(|out| parse_field(&out, "Repository"));
+            let mirror_url = repository.as_deref().and_then(|repo| {
+                run("pacman-conf", &["--repo", repo, "Server"])
+                    .and_then(|out| out.lines().next().map(|l| l.trim().to_string()))
+                    .filter(|l| !l.is_empty())
+            });
+            Provenance {
+                repository,
+                mirror_url,
+            }
+        }
+        "flatpak" => {
+            let repository =
+                run("flatpak", &["info", package]).and_then(|out| parse_field(&out, "Origin"));
+            let mirror_url = repository.as_deref().and_then(|remote| {
+                run("flatpak", &["remotes", "--columns=name,url"])
+                    .and_then(|out| parse_flatpak_remote_url(&out, remote))
+            });
+            Provenance {
+                repository,
+                mirror_url,
+            }
+        }
+        "snap" => Provenance {
+            repository: run("snap", &["info", package])
+                .and_then(|out| parse_field(&out, "tracking"))
+                .map(|channel| format!("snapcraft ({})", channel)),
+            mirror_url: Some("https://api.snapcraft.io".to_string()),
+        },
+        _ => Provenance::default(),
+    };
+
+    debug!(
+        "Provenance for {} via {}: {:?}",
+        package, box_type, provenance
+    );
+    provenance
+}
+
+fn run(command: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(command).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Value of a `Key : value` line as printed by dnf, pacman, flatpak and snap
+fn parse_field(output: &str, key: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (k, v) = line.split_once(':')?;
+        if k.trim() == key {
+            Some(v.trim().to_string()).filter(|v| !v.is_empty())
+        } else {
+            None
+        }
+    })
+}
+
+/// Pick the origin of the installed (`***`) version from `apt-cache policy` output
+pub fn parse_apt_policy(output: &str) -> Provenance {
+    let mut in_installed = false;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("***") {
+            in_installed = true;
+            continue;
+        }
+        if !in_installed {
+            continue;
+        }
+
+        let mut parts = trimmed.split_whitespace();
+        let (Some(priority), Some(source)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if priority.parse::<i32>().is_err() {
+            // Reached the next version in the version table
+            break;
+        }
+        if source == "/var/lib/dpkg/status" {
+            continue;
+        }
+
+        return Provenance {
+            repository: parts.next().map(|suite| suite.to_string()),
+            mirror_url: Some(source.to_string()),
+        };
+    }
+
+    // Installed from a local .deb or from a repository that is no longer configured
+    Provenance {
+        repository: in_installed.then(|| "local (dpkg status only)".to_string()),
+        mirror_url: None,
+    }
+}
+
+fn parse_flatpak_remote_url(output: &str, remote: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(url)) if name == remote => Some(url.to_string()),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_apt_policy() {
+        let output = "curl:
+  Installed: 7.81.0-1ubuntu1.15
+  Candidate: 7.81.0-1ubuntu1.15
+  Version table:
+ *** 7.81.0-1ubuntu1.15 500
+        500 http://archive.ubuntu.com/ubuntu jammy-updates/main amd64 Packages
+        500 http://security.ubuntu.com/ubuntu jammy-security/main amd64 Packages
+        100 /var/lib/dpkg/status
+     7.81.0-1 500
+        500 http://archive.ubuntu.com/ubuntu jammy/main amd64 Packages
+";
+        let provenance = parse_apt_policy(output);
+        assert_eq!(provenance.repository.as_deref(), Some("jammy-updates/main"));
+        assert_eq!(
+            provenance.mirror_url.as_deref(),
+            Some("http://archive.ubuntu.com/ubuntu")
+        );
+    }
+
+    #[test]
+    fn test_parse_apt_policy_local_only() {
+        let output = "foo:
+  Installed: 1.0
+  Candidate: 1.0
+  Version table:
+ *** 1.0 100
+        100 /var/lib/dpkg/status
+";
+        let provenance = parse_apt_policy(output);
+        assert_eq!(
+            provenance.repository.as_deref(),
+            Some("local (dpkg status only)")
+        );
+        assert!(provenance.mirror_url.is_none());
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let dnf = "Name         : curl\nVersion      : 8.2.1\nFrom repo    : updates\n";
+        assert_eq!(parse_field(dnf, "From repo").as_deref(), Some("updates"));
+
+        let remotes = "fedora\toci+https://registry.fedoraproject.org\nflathub\thttps://dl.flathub.org/repo/\n";
+        assert_eq!(
+            parse_flatpak_remote_url(remotes, "flathub").as_deref(),
+            Some("https://dl.flathub.org/repo/")
+        );
+    }
+}
@@ -1,12 +1,20 @@
+use crate::advanced_resolver;
+use crate::config::OmniConfig;
 use crate::database::Database;
 use crate::distro;
+use crate::downgrade;
+use crate::input_validation::InputValidator;
+use crate::interactive::{ConflictAction, ConflictResolution};
+use crate::priority;
 use crate::search::SearchEngine;
 use anyhow::Result;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
 use std::process::Command;
-use tracing::{error, info, warn};
+use tracing::{error, info, instrument, warn};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Dependency {
@@ -31,33 +39,61 @@ pub struct ResolvedPackage {
 #[derive(Debug)]
 pub struct ResolutionPlan {
     pub packages: Vec<ResolvedPackage>,
-    pub conflicts: Vec<String>,
+    /// Installed packages, with their box, to remove before installing
+    pub removals: Vec<(String, String)>,
+    pub conflicts: Vec<PackageConflict>,
     pub warnings: Vec<String>,
     pub total_size: Option<u64>,
 }
 
+/// A package in the plan that cannot be installed as planned
+#[derive(Debug, Clone)]
+pub struct PackageConflict {
+    pub package: String,
+    /// The installed package, and its box, that this one conflicts with
+    pub installed: Option<(String, String)>,
+    /// Other packages providing what `package` does
+    pub alternatives: Vec<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for PackageConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
 pub struct DependencyResolver {
     db: Database,
     search_engine: SearchEngine,
+    config: OmniConfig,
 }
 
 impl DependencyResolver {
     pub async fn new() -> Result<Self> {
         let db = Database::new().await?;
         let search_engine = SearchEngine::new().await?;
+        let config = OmniConfig::load().unwrap_or_default();
 
-        Ok(Self { db, search_engine })
+        Ok(Self {
+            db,
+            search_engine,
+            config,
+        })
     }
 
+    #[instrument(name = "resolve", skip(self))]
     pub async fn resolve_dependencies(
         &self,
         package_name: &str,
         box_type: Option<&str>,
     ) -> Result<ResolutionPlan> {
         info!("Resolving dependencies for package: {}", package_name);
+        let spec = InputValidator::parse_package_spec(package_name)?;
 
         let mut resolution_plan = ResolutionPlan {
             packages: Vec::new(),
+            removals: Vec::new(),
             conflicts: Vec::new(),
             warnings: Vec::new(),
             total_size: None,
@@ -65,10 +101,11 @@ impl DependencyResolver {
 
         let mut visited = HashSet::new();
         let mut resolved = HashMap::new();
+        let mut providers_of: HashMap<String, String> = HashMap::new();
         let mut queue = VecDeque::new();
 
         // Start with the requested package
-        queue.push_back((package_name.to_string(), box_type.map(|s| s.to_string()), 0));
+        queue.push_back((spec.name.clone(), box_type.map(|s| s.to_string()), 0));
 
         while let Some((pkg_name, preferred_box, depth)) = queue.pop_front() {
             if visited.contains(&pkg_name) {
@@ -77,27 +114,93 @@ impl DependencyResolver {
 
             visited.insert(pkg_name.clone());
 
+            // A dependency its own box lacks may come from another box when
+            // policies.cross_backend allows it
+            let preferred_box = match preferred_box {
+                Some(native) if depth > 0 => {
+                    match self.cross_backend_box(&pkg_name, &native).await {
+                        Some(other) => {
+                            resolution_plan.warnings.push(format!(
+                                "{} is not available from {}; using {}",
+                                pkg_name, native, other
+                            ));
+                            Some(other)
+                        }
+                        None => Some(native),
+                    }
+                }
+                preferred_box => preferred_box,
+            };
+
+            // Determine best box type if not specified
+            let selected_box = if let Some(box_type) = &preferred_box {
+                box_type.clone()
+            } else {
+                self.select_best_box(&pkg_name).await?
+            };
+
+            // A virtual package (mta, java-runtime) is met by an installed
+            // provider, or else the first one the box offers
+            let pkg_name = match advanced_resolver::virtual_providers(&selected_box, &pkg_name) {
+                Some(providers) => {
+                    let installed = self.db.get_installed_packages().await?;
+                    let installed: Vec<&str> =
+                        installed.iter().map(|i| i.package_name.as_str()).collect();
+                    let provider = preferred_provider(&providers, &installed).to_string();
+                    resolution_plan.warnings.push(format!(
+                        "{} is a virtual package; using {} (provided by {})",
+                        pkg_name,
+                        provider,
+                        providers.join(", ")
+                    ));
+                    providers_of.insert(pkg_name, provider.clone());
+                    if !visited.insert(provider.clone()) {
+                        continue;
+                    }
+                    provider
+                }
+                None => pkg_name,
+            };
+
             // Get package dependencies
             let dependencies = self
                 .get_package_dependencies(&pkg_name, preferred_box.as_deref())
                 .await?;
 
             // Check for conflicts
-            let conflicts = self.check_conflicts(&pkg_name, &dependencies).await?;
+            let conflicts = self
+                .check_conflicts(&pkg_name, &selected_box, &dependencies)
+                .await?;
             if !conflicts.is_empty() {
                 resolution_plan.conflicts.extend(conflicts);
             }
 
-            // Determine best box type if not specified
-            let selected_box = if let Some(box_type) = &preferred_box {
-                box_type.clone()
-            } else {
-                self.select_best_box(&pkg_name).await?
+            // Get package version; the requested package takes the newest
+            // version its constraint allows
+            let version = match &spec.constraint {
+                Some(constraint) if depth == 0 => {
+                    let newest = downgrade::query_versions(&pkg_name, &selected_box)
+                        .ok()
+                        .and_then(|listing| constraint.newest(&listing.available).cloned());
+                    match newest {
+                        Some(version) => version,
+                        None => {
+                            resolution_plan.conflicts.push(PackageConflict {
+                                package: pkg_name.clone(),
+                                installed: None,
+                                alternatives: Vec::new(),
+                                reason: format!(
+                                    "No version of {} from {} satisfies {}",
+                                    pkg_name, selected_box, constraint
+                                ),
+                            });
+                            self.get_package_version(&pkg_name, &selected_box).await?
+                        }
+                    }
+                }
+                _ => self.get_package_version(&pkg_name, &selected_box).await?,
             };
 
-            // Get package version
-            let version = self.get_package_version(&pkg_name, &selected_box).await?;
-
             let resolved_package = ResolvedPackage {
                 name: pkg_name.clone(),
                 version,
@@ -119,6 +222,7 @@ impl DependencyResolver {
 
         // Sort packages by install order (dependencies first)
         let mut packages: Vec<ResolvedPackage> = resolved.into_values().collect();
+        name_providers(&mut packages, &providers_of);
         packages.sort_by_key(|p| std::cmp::Reverse(p.install_order));
 
         resolution_plan.packages = packages;
@@ -398,30 +502,77 @@ impl DependencyResolver {
     async fn check_conflicts(
         &self,
         package_name: &str,
+        box_type: &str,
         dependencies: &[Dependency],
-    ) -> Result<Vec<String>> {
+    ) -> Result<Vec<PackageConflict>> {
         let mut conflicts = Vec::new();
 
         // Get currently installed packages
         let installed = self.db.get_installed_packages().await?;
-        let installed_names: HashSet<String> =
-            installed.iter().map(|p| p.package_name.clone()).collect();
+        let installed_boxes: HashMap<&str, &str> = installed
+            .iter()
+            .map(|p| (p.package_name.as_str(), p.box_type.as_str()))
+            .collect();
 
-        // Check for explicit conflicts
+        // Check for explicit conflicts; a conflict with a virtual package is
+        // one with whichever installed package provides it
         for dep in dependencies {
             for conflict in &dep.conflicts {
-                if installed_names.contains(conflict) {
-                    conflicts.push(format!(
-                        "Package {} conflicts with installed package {}",
-                        package_name, conflict
-                    ));
-                }
+                let clash = match installed_boxes.get(conflict.as_str()) {
+                    Some(installed_box) => Some((conflict.clone(), installed_box, None)),
+                    None => advanced_resolver::virtual_providers(box_type, conflict)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find_map(|provider| {
+                            let installed_box = installed_boxes.get(provider.as_str())?;
+                            Some((provider, installed_box, Some(conflict)))
+                        }),
+                };
+                let Some((installed_name, installed_box, provided)) = clash else {
+                    continue;
+                };
+
+                let alternatives = advanced_resolver::query_providers(box_type, package_name)
+                    .into_iter()
+                    .filter(|provider| provider != package_name && *provider != installed_name)
+                    .collect();
+                conflicts.push(PackageConflict {
+                    package: package_name.to_string(),
+                    reason: format!(
+                        "Package {} conflicts with installed package {}{}",
+                        package_name,
+                        installed_name,
+                        provided
+                            .map(|name| format!(" (which provides {})", name))
+                            .unwrap_or_default()
+                    ),
+                    installed: Some((installed_name, installed_box.to_string())),
+                    alternatives,
+                });
             }
         }
 
         Ok(conflicts)
     }
 
+    /// Settle each conflict in `plan` the way `choose` decides and change the
+    /// plan to match: remove the installed package first, install an
+    /// alternative provider in place of the conflicting package, or leave the
+    /// package out. Returns false when `choose` aborts.
+    pub async fn resolve_conflicts(
+        &self,
+        plan: &mut ResolutionPlan,
+        choose: impl FnMut(&PackageConflict) -> Result<ConflictResolution>,
+    ) -> Result<bool> {
+        let settled = settle_conflicts(plan, choose, |alternative, box_type| async move {
+            self.resolve_dependencies(&alternative, Some(&box_type))
+                .await
+        })
+        .await?;
+        plan.total_size = self.calculate_total_size(&plan.packages).await;
+        Ok(settled)
+    }
+
     async fn select_best_box(&self, package_name: &str) -> Result<String> {
         // Search across all available package managers and select the best one
         let search_results = self.search_engine.search_all(package_name).await?;
@@ -432,18 +583,12 @@ impl DependencyResolver {
             .filter(|r| r.name == package_name)
             .collect();
 
-        if !exact_matches.is_empty() {
-            // Priority order: apt > dnf > pacman > flatpak > snap > appimage
-            let priority_order = ["apt", "dnf", "pacman", "flatpak", "snap", "appimage"];
-
-            for box_type in &priority_order {
-                if let Some(result) = exact_matches.iter().find(|r| &r.box_type == box_type) {
-                    return Ok(result.box_type.clone());
-                }
-            }
-
-            // If no priority match, return the first exact match
-            return Ok(exact_matches[0].box_type.clone());
+        // Among exact name matches, follow the configured box and repository priority
+        if let Some(best) = exact_matches
+            .iter()
+            .min_by_key(|r| priority::source_rank(&self.config, &r.box_type, r.source.as_deref()))
+        {
+            return Ok(best.box_type.clone());
         }
 
         // Fallback to detecting distro default
@@ -457,6 +602,7 @@ impl DependencyResolver {
             "pacman" => self.get_pacman_version(package_name).await,
             "snap" => self.get_snap_version(package_name).await,
             "flatpak" => self.get_flatpak_version(package_name).await,
+            "nix" => self.get_nix_version(package_name).await,
             _ => Ok("unknown".to_string()),
         };
 
@@ -557,15 +703,79 @@ impl DependencyResolver {
         Ok("latest".to_string())
     }
 
+    async fn get_nix_version(&self, package_name: &str) -> Result<String> {
+        let output = Command::new("nix-env")
+            .args(["-qa", "--json", "-A"])
+            .arg(format!("nixpkgs.{}", package_name))
+            .output()?;
+
+        if output.status.success() {
+            let listing: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+            if let Some(version) = listing
+                .as_object()
+                .and_then(|packages| packages.values().next())
+                .and_then(|package| package["version"].as_str())
+            {
+                return Ok(version.to_string());
+            }
+        }
+
+        Ok("unknown".to_string())
+    }
+
+    /// The box to take `package_name` from when `native` does not have it,
+    /// if the cross-backend policy allows one
+    async fn cross_backend_box(&self, package_name: &str, native: &str) -> Option<String> {
+        let policy = &self.config.policies.cross_backend;
+        if !policy.enabled {
+            return None;
+        }
+        let boxes: Vec<String> = policy
+            .boxes
+            .iter()
+            .filter(|box_type| !self.config.boxes.disabled_boxes.contains(box_type))
+            .cloned()
+            .collect();
+        let other = cross_backend_choice(native, &boxes, |box_type| async move {
+            self.box_offers(package_name, &box_type).await
+        })
+        .await?;
+        info!(
+            "Taking {} from {} instead of {}",
+            package_name, other, native
+        );
+        Some(other)
+    }
+
+    /// Whether `box_type` has `package_name`; for flatpak, whether a remote
+    /// has a runtime by that name
+    async fn box_offers(&self, package_name: &str, box_type: &str) -> bool {
+        match box_type {
+            "flatpak" => {
+                distro::command_exists("flatpak")
+                    && Command::new("flatpak")
+                        .args(["remote-ls", "--runtime", "--columns=ref"])
+                        .output()
+                        .is_ok_and(|output| {
+                            parse_runtime_names(&String::from_utf8_lossy(&output.stdout))
+                                .contains(package_name)
+                        })
+            }
+            _ => !matches!(
+                self.get_package_version(package_name, box_type)
+                    .await
+                    .as_deref(),
+                Ok("unknown" | "latest") | Err(_)
+            ),
+        }
+    }
+
     async fn calculate_total_size(&self, packages: &[ResolvedPackage]) -> Option<u64> {
         let mut total_size = 0u64;
         let mut found_any_size = false;
 
         for package in packages {
-            if let Ok(size) = self
-                .get_package_size(&package.name, &package.box_type)
-                .await
-            {
+            if let Ok(size) = Self::get_package_size(&package.name, &package.box_type).await {
                 total_size += size;
                 found_any_size = true;
             }
@@ -578,16 +788,17 @@ impl DependencyResolver {
         }
     }
 
-    async fn get_package_size(&self, package_name: &str, box_type: &str) -> Result<u64> {
+    /// Download size in bytes, or 0 when the box does not say
+    pub async fn get_package_size(package_name: &str, box_type: &str) -> Result<u64> {
         match box_type {
-            "apt" => self.get_apt_size(package_name).await,
-            "dnf" => self.get_dnf_size(package_name).await,
-            "pacman" => self.get_pacman_size(package_name).await,
+            "apt" => Self::get_apt_size(package_name).await,
+            "dnf" => Self::get_dnf_size(package_name).await,
+            "pacman" => Self::get_pacman_size(package_name).await,
             _ => Ok(0), // Size information not available for other package types
         }
     }
 
-    async fn get_apt_size(&self, package_name: &str) -> Result<u64> {
+    async fn get_apt_size(package_name: &str) -> Result<u64> {
         let output = Command::new("apt").arg("show").arg(package_name).output()?;
 
         if output.status.success() {
@@ -605,7 +816,7 @@ impl DependencyResolver {
         Ok(0)
     }
 
-    async fn get_dnf_size(&self, package_name: &str) -> Result<u64> {
+    async fn get_dnf_size(package_name: &str) -> Result<u64> {
         let output = Command::new("dnf").arg("info").arg(package_name).output()?;
 
         if output.status.success() {
@@ -633,7 +844,7 @@ impl DependencyResolver {
         Ok(0)
     }
 
-    async fn get_pacman_size(&self, package_name: &str) -> Result<u64> {
+    async fn get_pacman_size(package_name: &str) -> Result<u64> {
         let output = Command::new("pacman")
             .arg("-Si")
             .arg(package_name)
@@ -681,3 +892,431 @@ impl DependencyResolver {
         }
     }
 }
+
+/// The provider of a virtual package to plan: an installed one, or else the
+/// first the box offers
+pub fn preferred_provider<'a>(providers: &'a [String], installed: &[&str]) -> &'a str {
+    providers
+        .iter()
+        .find(|provider| installed.contains(&provider.as_str()))
+        .unwrap_or(&providers[0])
+}
+
+/// Point dependencies on a virtual package at the provider planned for it
+pub fn name_providers(packages: &mut [ResolvedPackage], providers_of: &HashMap<String, String>) {
+    for dependency in packages.iter_mut().flat_map(|p| p.dependencies.iter_mut()) {
+        if let Some(provider) = providers_of.get(&dependency.name) {
+            dependency.name = provider.clone();
+        }
+    }
+}
+
+/// The first of `boxes` other than `native` that `offers` the package, or
+/// None when `native` has it itself
+pub async fn cross_backend_choice<O, F>(
+    native: &str,
+    boxes: &[String],
+    mut offers: O,
+) -> Option<String>
+where
+    O: FnMut(String) -> F,
+    F: Future<Output = bool>,
+{
+    if offers(native.to_string()).await {
+        return None;
+    }
+    for box_type in boxes {
+        if box_type != native && offers(box_type.clone()).await {
+            return Some(box_type.clone());
+        }
+    }
+    None
+}
+
+/// Runtime names from `flatpak remote-ls --runtime --columns=ref`, whose
+/// refs read `org.freedesktop.Platform/x86_64/23.08`
+pub fn parse_runtime_names(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split('/').next())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// `DependencyResolver::resolve_conflicts` with the resolution of an
+/// alternative provider, from the box of the package it replaces, left to
+/// `resolve`. Conflicts `choose` does not settle stay in the plan; those of
+/// packages an earlier choice took out of it are dropped.
+pub async fn settle_conflicts<C, R, F>(
+    plan: &mut ResolutionPlan,
+    mut choose: C,
+    mut resolve: R,
+) -> Result<bool>
+where
+    C: FnMut(&PackageConflict) -> Result<ConflictResolution>,
+    R: FnMut(String, String) -> F,
+    F: Future<Output = Result<ResolutionPlan>>,
+{
+    let mut pending: VecDeque<PackageConflict> = plan.conflicts.drain(..).collect();
+    let mut left_out = HashSet::new();
+    while let Some(conflict) = pending.pop_front() {
+        if !plan.packages.iter().any(|p| p.name == conflict.package) {
+            if !left_out.contains(&conflict.package) {
+                plan.conflicts.push(conflict);
+            }
+            continue;
+        }
+
+        let before: HashSet<String> = plan.packages.iter().map(|p| p.name.clone()).collect();
+        let resolution = choose(&conflict)?;
+        match resolution.action {
+            ConflictAction::Abort => {
+                plan.conflicts.push(conflict);
+                plan.conflicts.extend(pending);
+                return Ok(false);
+            }
+            ConflictAction::Force => {
+                plan.warnings
+                    .push(format!("Ignoring conflict: {}", conflict));
+            }
+            ConflictAction::RemoveInstalled => match conflict.installed.clone() {
+                Some(installed) => {
+                    if !plan.removals.contains(&installed) {
+                        plan.removals.push(installed);
+                    }
+                }
+                None => plan.conflicts.push(conflict),
+            },
+            ConflictAction::SelectAlternative => {
+                let replaced = plan.packages.iter().find(|p| p.name == conflict.package);
+                let (Some(alternative), Some(replaced)) = (resolution.selected_option, replaced)
+                else {
+                    plan.conflicts.push(conflict);
+                    continue;
+                };
+                let (box_type, order) = (replaced.box_type.clone(), replaced.install_order);
+
+                let replacement = resolve(alternative.clone(), box_type).await?;
+                plan.packages.retain(|p| p.name != conflict.package);
+                for package in &mut plan.packages {
+                    for dependency in &mut package.dependencies {
+                        if dependency.name == conflict.package {
+                            dependency.name = alternative.clone();
+                        }
+                    }
+                }
+                for mut package in replacement.packages {
+                    if !plan.packages.iter().any(|p| p.name == package.name) {
+                        package.install_order += order;
+                        plan.packages.push(package);
+                    }
+                }
+                pending.extend(replacement.conflicts);
+                plan.warnings.extend(replacement.warnings);
+                plan.packages
+                    .sort_by_key(|p| std::cmp::Reverse(p.install_order));
+                prune_unreachable(plan);
+            }
+            ConflictAction::Skip => {
+                plan.packages.retain(|p| p.name != conflict.package);
+                plan.warnings
+                    .push(format!("Skipped {}: {}", conflict.package, conflict));
+                prune_unreachable(plan);
+            }
+        }
+        left_out.extend(
+            before
+                .into_iter()
+                .filter(|name| !plan.packages.iter().any(|p| &p.name == name)),
+        );
+    }
+
+    Ok(true)
+}
+
+/// Drop packages no longer needed by the requested one (install order 0)
+/// after a package left the plan
+fn prune_unreachable(plan: &mut ResolutionPlan) {
+    let mut needed = HashSet::new();
+    let mut queue: VecDeque<&ResolvedPackage> = plan
+        .packages
+        .iter()
+        .filter(|p| p.install_order == 0)
+        .collect();
+    while let Some(package) = queue.pop_front() {
+        if !needed.insert(package.name.clone()) {
+            continue;
+        }
+        for dependency in package.dependencies.iter().filter(|d| !d.optional) {
+            if let Some(next) = plan.packages.iter().find(|p| p.name == dependency.name) {
+                queue.push_back(next);
+            }
+        }
+    }
+    plan.packages.retain(|p| needed.contains(&p.name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, install_order: usize, dependencies: &[&str]) -> ResolvedPackage {
+        ResolvedPackage {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            box_type: "apt".to_string(),
+            dependencies: dependencies
+                .iter()
+                .map(|dependency| Dependency {
+                    name: dependency.to_string(),
+                    version_req: None,
+                    box_type: "apt".to_string(),
+                    optional: false,
+                    conflicts: Vec::new(),
+                    provides: Vec::new(),
+                })
+                .collect(),
+            source_url: None,
+            install_order,
+        }
+    }
+
+    fn conflict(package: &str) -> PackageConflict {
+        PackageConflict {
+            package: package.to_string(),
+            installed: Some((format!("{}-old", package), "apt".to_string())),
+            alternatives: vec![format!("{}-ng", package)],
+            reason: format!("Package {} conflicts with installed package", package),
+        }
+    }
+
+    fn plan(packages: Vec<ResolvedPackage>, conflicts: Vec<PackageConflict>) -> ResolutionPlan {
+        ResolutionPlan {
+            packages,
+            removals: Vec::new(),
+            conflicts,
+            warnings: Vec::new(),
+            total_size: None,
+        }
+    }
+
+    /// app -> libfoo -> libbar, with libfoo conflicting
+    fn conflicting_plan() -> ResolutionPlan {
+        plan(
+            vec![
+                package("libbar", 2, &[]),
+                package("libfoo", 1, &["libbar"]),
+                package("app", 0, &["libfoo"]),
+            ],
+            vec![conflict("libfoo")],
+        )
+    }
+
+    fn choice(action: ConflictAction, selected_option: Option<&str>) -> ConflictResolution {
+        ConflictResolution {
+            action,
+            selected_option: selected_option.map(str::to_string),
+        }
+    }
+
+    async fn no_resolution(_: String, _: String) -> Result<ResolutionPlan> {
+        Err(anyhow::anyhow!("nothing to resolve"))
+    }
+
+    fn names(plan: &ResolutionPlan) -> Vec<&str> {
+        plan.packages.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_settle_by_selecting_alternative() {
+        let mut plan = conflicting_plan();
+        let settled = settle_conflicts(
+            &mut plan,
+            |_| Ok(choice(ConflictAction::SelectAlternative, Some("libfoo-ng"))),
+            |alternative, box_type| async move {
+                assert_eq!(
+                    (alternative.as_str(), box_type.as_str()),
+                    ("libfoo-ng", "apt")
+                );
+                Ok(self::plan(
+                    vec![
+                        package("libbaz", 1, &[]),
+                        package("libfoo-ng", 0, &["libbaz"]),
+                    ],
+                    Vec::new(),
+                ))
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(settled);
+        // libbar was only needed by libfoo
+        assert_eq!(names(&plan), ["libbaz", "libfoo-ng", "app"]);
+        let app = plan.packages.iter().find(|p| p.name == "app").unwrap();
+        assert_eq!(app.dependencies[0].name, "libfoo-ng");
+        assert_eq!(plan.packages[0].install_order, 2);
+        assert!(plan.conflicts.is_empty());
+        assert!(plan.removals.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_settle_by_removing_installed() {
+        let mut plan = conflicting_plan();
+        let settled = settle_conflicts(
+            &mut plan,
+            |_| Ok(choice(ConflictAction::RemoveInstalled, None)),
+            no_resolution,
+        )
+        .await
+        .unwrap();
+
+        assert!(settled);
+        assert_eq!(
+            plan.removals,
+            [("libfoo-old".to_string(), "apt".to_string())]
+        );
+        assert_eq!(names(&plan), ["libbar", "libfoo", "app"]);
+        assert!(plan.conflicts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_settle_by_skipping() {
+        let mut plan = conflicting_plan();
+        // Moot once libfoo is skipped, since only libfoo needs libbar
+        plan.conflicts.push(conflict("libbar"));
+        // Not in the plan to begin with, so nothing settles it
+        plan.conflicts.push(conflict("libqux"));
+        let mut asked = Vec::new();
+        let settled = settle_conflicts(
+            &mut plan,
+            |conflict| {
+                asked.push(conflict.package.clone());
+                Ok(choice(ConflictAction::Skip, None))
+            },
+            no_resolution,
+        )
+        .await
+        .unwrap();
+
+        assert!(settled);
+        assert_eq!(asked, ["libfoo"]);
+        assert_eq!(names(&plan), ["app"]);
+        assert!(plan.warnings[0].starts_with("Skipped libfoo"));
+        let unsettled: Vec<&str> = plan.conflicts.iter().map(|c| c.package.as_str()).collect();
+        assert_eq!(unsettled, ["libqux"]);
+    }
+
+    #[tokio::test]
+    async fn test_settle_keeps_conflicts_it_does_not_settle() {
+        let mut plan = conflicting_plan();
+        let settled = settle_conflicts(
+            &mut plan,
+            |_| Ok(choice(ConflictAction::SelectAlternative, None)),
+            no_resolution,
+        )
+        .await
+        .unwrap();
+        assert!(settled);
+        assert_eq!(plan.conflicts.len(), 1);
+        assert_eq!(names(&plan), ["libbar", "libfoo", "app"]);
+
+        let mut plan = conflicting_plan();
+        let settled = settle_conflicts(
+            &mut plan,
+            |_| Ok(choice(ConflictAction::Abort, None)),
+            no_resolution,
+        )
+        .await
+        .unwrap();
+        assert!(!settled);
+        assert_eq!(plan.conflicts.len(), 1);
+    }
+
+    fn boxes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_cross_backend_choice_takes_first_box_that_offers() {
+        let offered = ["nix", "flatpak"];
+        let choice =
+            cross_backend_choice("apt", &boxes(&["flatpak", "nix"]), |box_type| async move {
+                offered.contains(&box_type.as_str())
+            })
+            .await;
+        assert_eq!(choice.as_deref(), Some("flatpak"));
+
+        let choice = cross_backend_choice("apt", &boxes(&["snap", "nix"]), |box_type| async move {
+            offered.contains(&box_type.as_str())
+        })
+        .await;
+        assert_eq!(choice.as_deref(), Some("nix"));
+    }
+
+    #[tokio::test]
+    async fn test_cross_backend_choice_with_native_box() {
+        let mut asked = Vec::new();
+        let choice = cross_backend_choice("apt", &boxes(&["flatpak", "nix"]), |box_type| {
+            asked.push(box_type.clone());
+            async move { box_type == "apt" }
+        })
+        .await;
+        assert_eq!(choice, None);
+        assert_eq!(asked, ["apt"]);
+
+        let mut asked = Vec::new();
+        let choice = cross_backend_choice("nix", &boxes(&["nix"]), |box_type| {
+            asked.push(box_type);
+            async { false }
+        })
+        .await;
+        assert_eq!(choice, None);
+        assert_eq!(asked, ["nix"]);
+    }
+
+    #[test]
+    fn test_parse_runtime_names() {
+        let output = "org.freedesktop.Platform/x86_64/23.08\n\
+                      org.freedesktop.Platform.GL.default/x86_64/23.08\n\
+                      org.gnome.Platform/x86_64/45\n\
+                      \n";
+        let names = parse_runtime_names(output);
+        assert_eq!(names.len(), 3);
+        assert!(names.contains("org.freedesktop.Platform"));
+        assert!(names.contains("org.gnome.Platform"));
+        assert!(!names.contains("org.freedesktop.Platform/x86_64/23.08"));
+    }
+
+    #[test]
+    fn test_preferred_provider() {
+        let providers = ["exim4-daemon-light", "postfix", "sendmail-bin"].map(String::from);
+        assert_eq!(
+            preferred_provider(&providers, &["curl", "postfix"]),
+            "postfix"
+        );
+        assert_eq!(
+            preferred_provider(&providers, &["curl"]),
+            "exim4-daemon-light"
+        );
+    }
+
+    #[test]
+    fn test_name_providers() {
+        let mut packages = vec![
+            package("mutt", 0, &["mail-transport-agent", "libc6"]),
+            package("postfix", 1, &[]),
+        ];
+        let providers_of =
+            HashMap::from([("mail-transport-agent".to_string(), "postfix".to_string())]);
+        name_providers(&mut packages, &providers_of);
+        let dependencies: Vec<&str> = packages[0]
+            .dependencies
+            .iter()
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(dependencies, ["postfix", "libc6"]);
+        assert!(packages[1].dependencies.is_empty());
+    }
+}
@@ -1,15 +1,21 @@
 use anyhow::Result;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
+
+/// Lines of build output kept in the error when a script fails
+const SCRIPT_ERROR_LINES: usize = 20;
 
 pub struct Sandbox {
     network_access: bool,
+    writable_paths: Vec<PathBuf>,
 }
 
 impl Sandbox {
     pub fn new() -> Result<Self> {
         Ok(Self {
             network_access: true,
+            writable_paths: Vec::new(),
         })
     }
 
@@ -17,6 +23,65 @@ impl Sandbox {
         self.network_access = enabled;
     }
 
+    /// Let scripts write below `path`; everything else is read-only to them
+    pub fn allow_write(&mut self, path: &Path) {
+        self.writable_paths.push(path.to_path_buf());
+    }
+
+    /// Run a shell script in `dir`. With bubblewrap installed the script sees the
+    /// filesystem read-only apart from the writable paths and a private `/tmp`;
+    /// without it the script runs unconfined.
+    pub fn execute_script(&self, dir: &Path, script: &str, env: &[(&str, &str)]) -> Result<()> {
+        let mut command = if bubblewrap_available() {
+            let mut command = Command::new("bwrap");
+            command
+                .args(self.bubblewrap_args(dir))
+                .args(["sh", "-c", script]);
+            command
+        } else {
+            warn!("bubblewrap (bwrap) not found; running build steps without isolation");
+            let mut command = Command::new("sh");
+            command.args(["-c", script]).current_dir(dir);
+            command
+        };
+        debug!("Running in {}: {}", dir.display(), script);
+
+        let output = command.envs(env.iter().copied()).output()?;
+        if !output.status.success() {
+            let log = format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            let lines: Vec<&str> = log.lines().collect();
+            let tail = lines[lines.len().saturating_sub(SCRIPT_ERROR_LINES)..].join("\n");
+            return Err(anyhow::anyhow!("`{}` failed:\n{}", script, tail));
+        }
+
+        Ok(())
+    }
+
+    fn bubblewrap_args(&self, dir: &Path) -> Vec<String> {
+        let mut args: Vec<String> = "--ro-bind / / --dev /dev --proc /proc --tmpfs /tmp"
+            .split(' ')
+            .map(String::from)
+            .collect();
+        for path in &self.writable_paths {
+            let path = path.display().to_string();
+            args.extend(["--bind".to_string(), path.clone(), path]);
+        }
+        args.push("--unshare-all".to_string());
+        if self.network_access {
+            args.push("--share-net".to_string());
+        }
+        args.extend([
+            "--die-with-parent".to_string(),
+            "--chdir".to_string(),
+            dir.display().to_string(),
+        ]);
+        args
+    }
+
     pub fn execute(&self, command: &str, args: &[&str]) -> Result<()> {
         if !self.network_access {
             info!("Executing command in restricted network mode: {} {:?}", command, args);
@@ -51,3 +116,12 @@ impl Sandbox {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 }
+
+/// bubblewrap is installed and allowed to create namespaces here
+fn bubblewrap_available() -> bool {
+    Command::new("bwrap")
+        .args(["--ro-bind", "/", "/", "--unshare-all", "true"])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
@@ -0,0 +1,485 @@
+//! A CDCL SAT solver for dependency resolution: unit propagation over two
+//! watched literals, first-UIP clause learning with non-chronological
+//! backjumping, and activity-ordered decisions that take each variable's
+//! preferred value first.
+//!
+//! Solving under assumptions reports which assumptions could not hold
+//! together, which is what lets the resolver explain a failed request.
+
+use std::ops::Not;
+
+/// A variable, or its negation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Lit(u32);
+
+impl Lit {
+    pub fn positive(var: usize) -> Self {
+        Lit((var as u32) << 1)
+    }
+
+    pub fn negative(var: usize) -> Self {
+        Lit(((var as u32) << 1) | 1)
+    }
+
+    pub fn var(self) -> usize {
+        (self.0 >> 1) as usize
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 & 1 == 1
+    }
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Not for Lit {
+    type Output = Lit;
+
+    fn not(self) -> Lit {
+        Lit(self.0 ^ 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The value of every variable
+    Satisfiable(Vec<bool>),
+    /// The assumptions that cannot all hold; empty when the clauses alone
+    /// are unsatisfiable
+    Unsatisfiable(Vec<Lit>),
+}
+
+#[derive(Debug, Default)]
+pub struct Solver {
+    clauses: Vec<Vec<Lit>>,
+    /// Clauses watching each literal, indexed by `Lit::index`
+    watches: Vec<Vec<usize>>,
+    assigns: Vec<Option<bool>>,
+    level: Vec<usize>,
+    reason: Vec<Option<usize>>,
+    trail: Vec<Lit>,
+    trail_lim: Vec<usize>,
+    queue_head: usize,
+    activity: Vec<f64>,
+    activity_inc: f64,
+    preferred: Vec<bool>,
+    seen: Vec<bool>,
+    /// A clause was added that cannot hold whatever the assignment
+    contradiction: bool,
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Self {
+            activity_inc: 1.0,
+            ..Self::default()
+        }
+    }
+
+    /// A new variable, tried as `preferred` before the opposite value
+    pub fn new_var(&mut self, preferred: bool) -> usize {
+        let var = self.assigns.len();
+        self.assigns.push(None);
+        self.level.push(0);
+        self.reason.push(None);
+        self.activity.push(0.0);
+        self.preferred.push(preferred);
+        self.seen.push(false);
+        self.watches.push(Vec::new());
+        self.watches.push(Vec::new());
+        var
+    }
+
+    pub fn num_vars(&self) -> usize {
+        self.assigns.len()
+    }
+
+    /// Require at least one of `lits` to hold
+    pub fn add_clause(&mut self, lits: &[Lit]) {
+        if self.contradiction {
+            return;
+        }
+
+        let mut clause: Vec<Lit> = Vec::with_capacity(lits.len());
+        for &lit in lits {
+            match self.value(lit) {
+                Some(true) => return,
+                Some(false) => continue,
+                None if clause.contains(&!lit) => return,
+                None if clause.contains(&lit) => continue,
+                None => clause.push(lit),
+            }
+        }
+
+        match clause.len() {
+            0 => self.contradiction = true,
+            1 => {
+                self.enqueue(clause[0], None);
+                if self.propagate().is_some() {
+                    self.contradiction = true;
+                }
+            }
+            _ => {
+                self.attach(clause);
+            }
+        }
+    }
+
+    /// Search for an assignment satisfying every clause and every assumption
+    pub fn solve(&mut self, assumptions: &[Lit]) -> Outcome {
+        if self.contradiction {
+            return Outcome::Unsatisfiable(Vec::new());
+        }
+
+        let outcome = 'search: loop {
+            if let Some(conflict) = self.propagate() {
+                if self.decision_level() == 0 {
+                    self.contradiction = true;
+                    break Outcome::Unsatisfiable(Vec::new());
+                }
+                let (learnt, backjump) = self.analyze(conflict);
+                self.cancel_until(backjump);
+                self.learn(learnt);
+                self.activity_inc /= 0.95;
+                continue;
+            }
+
+            let mut next = None;
+            while self.decision_level() < assumptions.len() {
+                let assumption = assumptions[self.decision_level()];
+                match self.value(assumption) {
+                    Some(true) => self.trail_lim.push(self.trail.len()),
+                    Some(false) => {
+                        break 'search Outcome::Unsatisfiable(self.analyze_final(assumption))
+                    }
+                    None => {
+                        next = Some(assumption);
+                        break;
+                    }
+                }
+            }
+
+            let decision = match next.or_else(|| self.pick_branch()) {
+                Some(lit) => lit,
+                None => {
+                    break Outcome::Satisfiable(
+                        self.assigns.iter().map(|v| v.unwrap_or(false)).collect(),
+                    )
+                }
+            };
+            self.trail_lim.push(self.trail.len());
+            self.enqueue(decision, None);
+        };
+
+        self.cancel_until(0);
+        outcome
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        self.assigns[lit.var()].map(|value| value != lit.is_negative())
+    }
+
+    fn decision_level(&self) -> usize {
+        self.trail_lim.len()
+    }
+
+    fn attach(&mut self, clause: Vec<Lit>) -> usize {
+        let index = self.clauses.len();
+        self.watches[clause[0].index()].push(index);
+        self.watches[clause[1].index()].push(index);
+        self.clauses.push(clause);
+        index
+    }
+
+    fn enqueue(&mut self, lit: Lit, reason: Option<usize>) {
+        let var = lit.var();
+        self.assigns[var] = Some(!lit.is_negative());
+        self.level[var] = self.decision_level();
+        self.reason[var] = reason;
+        self.trail.push(lit);
+    }
+
+    /// Assign every literal the assignments so far imply; the clause that
+    /// became false if they contradict each other
+    fn propagate(&mut self) -> Option<usize> {
+        while self.queue_head < self.trail.len() {
+            let falsified = !self.trail[self.queue_head];
+            self.queue_head += 1;
+
+            let mut watchers = std::mem::take(&mut self.watches[falsified.index()]);
+            let mut conflict = None;
+            let mut i = 0;
+            while i < watchers.len() {
+                let index = watchers[i];
+                let clause = &mut self.clauses[index];
+                if clause[0] == falsified {
+                    clause.swap(0, 1);
+                }
+
+                let first = clause[0];
+                if self.assigns[first.var()].is_some_and(|v| v != first.is_negative()) {
+                    i += 1;
+                    continue;
+                }
+
+                let replacement = (2..clause.len()).find(|&k| {
+                    let lit = clause[k];
+                    self.assigns[lit.var()].is_none_or(|v| v != lit.is_negative())
+                });
+                if let Some(k) = replacement {
+                    clause.swap(1, k);
+                    let watched = clause[1];
+                    self.watches[watched.index()].push(index);
+                    watchers.swap_remove(i);
+                    continue;
+                }
+
+                if self.value(first) == Some(false) {
+                    conflict = Some(index);
+                    break;
+                }
+                self.enqueue(first, Some(index));
+                i += 1;
+            }
+            self.watches[falsified.index()].extend(watchers);
+
+            if conflict.is_some() {
+                self.queue_head = self.trail.len();
+                return conflict;
+            }
+        }
+        None
+    }
+
+    /// The first-UIP clause learnt from a conflict, and the level to jump
+    /// back to
+    fn analyze(&mut self, conflict: usize) -> (Vec<Lit>, usize) {
+        let mut learnt = vec![Lit(0)];
+        let mut pending = 0;
+        let mut implied: Option<Lit> = None;
+        let mut clause = conflict;
+        let mut index = self.trail.len();
+
+        loop {
+            for k in 0..self.clauses[clause].len() {
+                let lit = self.clauses[clause][k];
+                if Some(lit) == implied {
+                    continue;
+                }
+                let var = lit.var();
+                if !self.seen[var] && self.level[var] > 0 {
+                    self.seen[var] = true;
+                    self.bump(var);
+                    if self.level[var] >= self.decision_level() {
+                        pending += 1;
+                    } else {
+                        learnt.push(lit);
+                    }
+                }
+            }
+
+            loop {
+                index -= 1;
+                if self.seen[self.trail[index].var()] {
+                    break;
+                }
+            }
+            let lit = self.trail[index];
+            self.seen[lit.var()] = false;
+            implied = Some(lit);
+            pending -= 1;
+            if pending == 0 {
+                break;
+            }
+            clause = self.reason[lit.var()].expect("implied literal has a reason");
+        }
+
+        learnt[0] = !implied.expect("conflict has a literal at the current level");
+        for lit in &learnt[1..] {
+            self.seen[lit.var()] = false;
+        }
+
+        let mut backjump = 0;
+        if learnt.len() > 1 {
+            let deepest = (1..learnt.len())
+                .max_by_key(|&k| self.level[learnt[k].var()])
+                .unwrap_or(1);
+            learnt.swap(1, deepest);
+            backjump = self.level[learnt[1].var()];
+        }
+        (learnt, backjump)
+    }
+
+    /// The assumptions that imply `failed` is false
+    fn analyze_final(&mut self, failed: Lit) -> Vec<Lit> {
+        let mut core = vec![failed];
+        if self.decision_level() == 0 {
+            return core;
+        }
+
+        self.seen[failed.var()] = true;
+        for i in (self.trail_lim[0]..self.trail.len()).rev() {
+            let lit = self.trail[i];
+            let var = lit.var();
+            if !self.seen[var] {
+                continue;
+            }
+            match self.reason[var] {
+                None => core.push(lit),
+                Some(clause) => {
+                    for k in 1..self.clauses[clause].len() {
+                        let reason = self.clauses[clause][k].var();
+                        if self.level[reason] > 0 {
+                            self.seen[reason] = true;
+                        }
+                    }
+                }
+            }
+            self.seen[var] = false;
+        }
+        self.seen[failed.var()] = false;
+        core
+    }
+
+    fn learn(&mut self, learnt: Vec<Lit>) {
+        let asserting = learnt[0];
+        if learnt.len() == 1 {
+            self.enqueue(asserting, None);
+        } else {
+            let index = self.attach(learnt);
+            self.enqueue(asserting, Some(index));
+        }
+    }
+
+    fn cancel_until(&mut self, level: usize) {
+        if self.decision_level() <= level {
+            return;
+        }
+        let keep = self.trail_lim[level];
+        for lit in self.trail.drain(keep..) {
+            self.assigns[lit.var()] = None;
+            self.reason[lit.var()] = None;
+        }
+        self.trail_lim.truncate(level);
+        self.queue_head = keep;
+    }
+
+    fn bump(&mut self, var: usize) {
+        self.activity[var] += self.activity_inc;
+        if self.activity[var] > 1e100 {
+            self.activity.iter_mut().for_each(|a| *a *= 1e-100);
+            self.activity_inc *= 1e-100;
+        }
+    }
+
+    /// The unassigned variable with the highest activity, at its preferred
+    /// value
+    fn pick_branch(&self) -> Option<Lit> {
+        let mut best: Option<usize> = None;
+        for var in 0..self.assigns.len() {
+            if self.assigns[var].is_none()
+                && best.is_none_or(|b| self.activity[var] > self.activity[b])
+            {
+                best = Some(var);
+            }
+        }
+        best.map(|var| {
+            if self.preferred[var] {
+                Lit::positive(var)
+            } else {
+                Lit::negative(var)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn satisfies(clauses: &[Vec<Lit>], model: &[bool]) -> bool {
+        clauses.iter().all(|clause| {
+            clause
+                .iter()
+                .any(|lit| model[lit.var()] != lit.is_negative())
+        })
+    }
+
+    #[test]
+    fn test_pigeonhole() {
+        // Three pigeons fit in three holes but not in two
+        for (holes, expect_sat) in [(3, true), (2, false)] {
+            let mut solver = Solver::new();
+            let pigeons = 3;
+            let var = |p: usize, h: usize| p * holes + h;
+            for _ in 0..pigeons * holes {
+                solver.new_var(false);
+            }
+
+            let mut clauses = Vec::new();
+            for p in 0..pigeons {
+                clauses.push((0..holes).map(|h| Lit::positive(var(p, h))).collect());
+            }
+            for h in 0..holes {
+                for a in 0..pigeons {
+                    for b in a + 1..pigeons {
+                        clauses.push(vec![Lit::negative(var(a, h)), Lit::negative(var(b, h))]);
+                    }
+                }
+            }
+            for clause in &clauses {
+                solver.add_clause(clause);
+            }
+
+            match solver.solve(&[]) {
+                Outcome::Satisfiable(model) => {
+                    assert!(expect_sat);
+                    assert!(satisfies(&clauses, &model));
+                }
+                Outcome::Unsatisfiable(core) => {
+                    assert!(!expect_sat);
+                    assert!(core.is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_assumptions_and_preferences() {
+        let mut solver = Solver::new();
+        let a = solver.new_var(true);
+        let b = solver.new_var(false);
+        let c = solver.new_var(false);
+        let selector = solver.new_var(false);
+        // a conflicts with b, and the selector requires b
+        solver.add_clause(&[Lit::negative(a), Lit::negative(b)]);
+        solver.add_clause(&[Lit::negative(selector), Lit::positive(b)]);
+
+        // Preferred values are kept when nothing forces otherwise
+        assert_eq!(
+            solver.solve(&[]),
+            Outcome::Satisfiable(vec![true, false, false, false])
+        );
+        assert_eq!(
+            solver.solve(&[Lit::positive(selector)]),
+            Outcome::Satisfiable(vec![false, true, false, true])
+        );
+
+        // Only the assumptions involved in the contradiction are reported
+        match solver.solve(&[Lit::positive(c), Lit::positive(a), Lit::positive(selector)]) {
+            Outcome::Unsatisfiable(mut core) => {
+                core.sort_by_key(|lit| lit.var());
+                assert_eq!(core, vec![Lit::positive(a), Lit::positive(selector)]);
+            }
+            outcome => panic!("expected a contradiction, got {:?}", outcome),
+        }
+
+        // Learnt clauses from the failed solve leave later ones unaffected
+        assert!(matches!(
+            solver.solve(&[Lit::positive(c)]),
+            Outcome::Satisfiable(_)
+        ));
+    }
+}
@@ -0,0 +1,495 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::info;
+
+/// Prefix of launchd labels and Task Scheduler folder
+const LAUNCHD_LABEL: &str = "io.github.therealcoolnerd.omni";
+const TASK_FOLDER: &str = "Omni";
+
+/// Whose scheduler the tasks are installed into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Scope {
+    /// Run as the current user (systemd --user, LaunchAgents)
+    #[default]
+    User,
+    /// Run as root/SYSTEM for the whole machine (needs sudo or an elevated prompt)
+    System,
+}
+
+/// A periodic omni job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Task {
+    /// `omni daemon --once`, following the daemon's auto-download and auto-install policy
+    Update,
+    /// `omni snapshot create`
+    Snapshot,
+}
+
+impl Task {
+    pub const ALL: [Task; 2] = [Task::Update, Task::Snapshot];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Task::Update => "update",
+            Task::Snapshot => "snapshot",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Task::Update => "Omni: check for package updates",
+            Task::Snapshot => "Omni: snapshot installed packages",
+        }
+    }
+
+    /// Arguments to omni; machine-wide tasks work on the system scope's state
+    fn args(self, scope: Scope) -> Vec<&'static str> {
+        let mut args = match scope {
+            Scope::System => vec!["--system"],
+            Scope::User => Vec::new(),
+        };
+        args.extend_from_slice(match self {
+            Task::Update => &["daemon", "--once"],
+            Task::Snapshot => &["snapshot", "create", "--description", "Scheduled snapshot"],
+        });
+        args
+    }
+}
+
+/// A task and how often it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduledTask {
+    pub task: Task,
+    pub interval_minutes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Systemd,
+    Launchd,
+    TaskScheduler,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            Platform::Launchd
+        } else if cfg!(target_os = "windows") {
+            Platform::TaskScheduler
+        } else {
+            Platform::Systemd
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Files to write and scheduler commands to run, in order
+#[derive(Debug, Default)]
+pub struct SchedulePlan {
+    pub files: Vec<UnitFile>,
+    pub commands: Vec<Vec<String>>,
+    /// Makes the scheduler pick up changed files (`systemctl daemon-reload`)
+    pub reload: Option<Vec<String>>,
+}
+
+/// Units that run `exe` for each task; `enable` also starts the timers
+pub fn install_plan(
+    platform: Platform,
+    scope: Scope,
+    exe: &Path,
+    tasks: &[ScheduledTask],
+    enable: bool,
+) -> Result<SchedulePlan> {
+    let mut plan = SchedulePlan::default();
+
+    match platform {
+        Platform::Systemd => {
+            let dir = unit_dir(platform, scope)?;
+            for scheduled in tasks {
+                let unit = systemd_unit_name(scheduled.task);
+                plan.files.push(UnitFile {
+                    path: dir.join(format!("{}.service", unit)),
+                    contents: systemd_service(scheduled.task, scope, exe),
+                });
+                plan.files.push(UnitFile {
+                    path: dir.join(format!("{}.timer", unit)),
+                    contents: systemd_timer(scheduled),
+                });
+            }
+            plan.reload = Some(systemctl(scope, &["daemon-reload"]));
+            if enable {
+                for scheduled in tasks {
+                    let timer = format!("{}.timer", systemd_unit_name(scheduled.task));
+                    plan.commands
+                        .push(systemctl(scope, &["enable", "--now", &timer]));
+                }
+            }
+        }
+        Platform::Launchd => {
+            let dir = unit_dir(platform, scope)?;
+            for scheduled in tasks {
+                let path = dir.join(format!("{}.plist", launchd_label(scheduled.task)));
+                plan.files.push(UnitFile {
+                    path: path.clone(),
+                    contents: launchd_plist(scheduled, scope, exe),
+                });
+                if enable {
+                    plan.commands.push(vec![
+                        "launchctl".to_string(),
+                        "load".to_string(),
+                        "-w".to_string(),
+                        path.display().to_string(),
+                    ]);
+                }
+            }
+        }
+        Platform::TaskScheduler => {
+            for scheduled in tasks {
+                plan.commands.push(schtasks_create(scheduled, scope, exe));
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Stop the tasks and delete their units
+pub fn remove_plan(platform: Platform, scope: Scope, tasks: &[Task]) -> Result<SchedulePlan> {
+    let mut plan = SchedulePlan::default();
+
+    match platform {
+        Platform::Systemd => {
+            let dir = unit_dir(platform, scope)?;
+            for task in tasks {
+                let unit = systemd_unit_name(*task);
+                let timer = format!("{}.timer", unit);
+                plan.commands
+                    .push(systemctl(scope, &["disable", "--now", &timer]));
+                for suffix in ["service", "timer"] {
+                    plan.files.push(UnitFile {
+                        path: dir.join(format!("{}.{}", unit, suffix)),
+                        contents: String::new(),
+                    });
+                }
+            }
+            plan.reload = Some(systemctl(scope, &["daemon-reload"]));
+        }
+        Platform::Launchd => {
+            let dir = unit_dir(platform, scope)?;
+            for task in tasks {
+                let path = dir.join(format!("{}.plist", launchd_label(*task)));
+                plan.commands.push(vec![
+                    "launchctl".to_string(),
+                    "unload".to_string(),
+                    "-w".to_string(),
+                    path.display().to_string(),
+                ]);
+                plan.files.push(UnitFile {
+                    path,
+                    contents: String::new(),
+                });
+            }
+        }
+        Platform::TaskScheduler => {
+            for task in tasks {
+                plan.commands.push(vec![
+                    "schtasks".to_string(),
+                    "/Delete".to_string(),
+                    "/F".to_string(),
+                    "/TN".to_string(),
+                    task_scheduler_name(*task),
+                ]);
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Write the plan's files, then reload the scheduler and run the commands
+pub fn install(plan: &SchedulePlan) -> Result<()> {
+    crate::config::ensure_writable("installing scheduled tasks")?;
+
+    for file in &plan.files {
+        if let Some(parent) = file.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| permission_hint(e, parent))?;
+        }
+        fs::write(&file.path, &file.contents).map_err(|e| permission_hint(e, &file.path))?;
+        info!("Wrote {}", file.path.display());
+    }
+    if let Some(reload) = &plan.reload {
+        run(reload)?;
+    }
+    for command in &plan.commands {
+        run(command)?;
+    }
+
+    Ok(())
+}
+
+/// Run the plan's commands, tolerating tasks that are not loaded, then delete its
+/// files and reload the scheduler
+pub fn remove(plan: &SchedulePlan) -> Result<()> {
+    crate::config::ensure_writable("removing scheduled tasks")?;
+
+    for command in &plan.commands {
+        if let Err(e) = run(command) {
+            info!("{}", e);
+        }
+    }
+    for file in &plan.files {
+        match fs::remove_file(&file.path) {
+            Ok(()) => info!("Removed {}", file.path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(permission_hint(e, &file.path)),
+        }
+    }
+    if let Some(reload) = &plan.reload {
+        run(reload)?;
+    }
+
+    Ok(())
+}
+
+fn run(command: &[String]) -> Result<()> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty scheduler command"))?;
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            command.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+fn permission_hint(error: std::io::Error, path: &Path) -> anyhow::Error {
+    if error.kind() == std::io::ErrorKind::PermissionDenied {
+        anyhow!(
+            "Permission denied writing {}; system scope needs sudo",
+            path.display()
+        )
+    } else {
+        anyhow!("Failed to write {}: {}", path.display(), error)
+    }
+}
+
+fn unit_dir(platform: Platform, scope: Scope) -> Result<PathBuf> {
+    let home = || dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"));
+    Ok(match (platform, scope) {
+        (Platform::Systemd, Scope::User) => dirs::config_dir()
+            .ok_or_else(|| anyhow!("Could not find config directory"))?
+            .join("systemd/user"),
+        (Platform::Systemd, Scope::System) => PathBuf::from("/etc/systemd/system"),
+        (Platform::Launchd, Scope::User) => home()?.join("Library/LaunchAgents"),
+        (Platform::Launchd, Scope::System) => PathBuf::from("/Library/LaunchDaemons"),
+        (Platform::TaskScheduler, _) => {
+            return Err(anyhow!("Task Scheduler keeps tasks in its own store"))
+        }
+    })
+}
+
+fn systemctl(scope: Scope, args: &[&str]) -> Vec<String> {
+    let mut command = vec!["systemctl".to_string()];
+    if scope == Scope::User {
+        command.push("--user".to_string());
+    }
+    command.extend(args.iter().map(|a| a.to_string()));
+    command
+}
+
+fn systemd_unit_name(task: Task) -> String {
+    format!("omni-{}", task.name())
+}
+
+/// Quote an ExecStart word if it contains whitespace or quotes
+fn systemd_quote(word: &str) -> String {
+    if word.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        word.to_string()
+    }
+}
+
+pub fn systemd_service(task: Task, scope: Scope, exe: &Path) -> String {
+    let command: Vec<String> = std::iter::once(exe.display().to_string())
+        .chain(task.args(scope).iter().map(|a| a.to_string()))
+        .map(|word| systemd_quote(&word))
+        .collect();
+    let network = match task {
+        Task::Update => "After=network-online.target\nWants=network-online.target\n",
+        Task::Snapshot => "",
+    };
+
+    format!(
+        "[Unit]\n\
+         Description={}\n\
+         {}\n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={}\n",
+        task.description(),
+        network,
+        command.join(" ")
+    )
+}
+
+pub fn systemd_timer(scheduled: &ScheduledTask) -> String {
+    format!(
+        "[Unit]\n\
+         Description={} every {} minutes\n\
+         \n\
+         [Timer]\n\
+         OnBootSec=15min\n\
+         OnUnitActiveSec={}min\n\
+         RandomizedDelaySec=5min\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        scheduled.task.description(),
+        scheduled.interval_minutes,
+        scheduled.interval_minutes
+    )
+}
+
+fn launchd_label(task: Task) -> String {
+    format!("{}.{}", LAUNCHD_LABEL, task.name())
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn launchd_plist(scheduled: &ScheduledTask, scope: Scope, exe: &Path) -> String {
+    let arguments: String = std::iter::once(exe.display().to_string())
+        .chain(scheduled.task.args(scope).iter().map(|a| a.to_string()))
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+        .collect();
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \x20   <key>Label</key>\n\
+         \x20   <string>{}</string>\n\
+         \x20   <key>ProgramArguments</key>\n\
+         \x20   <array>\n\
+         {}\
+         \x20   </array>\n\
+         \x20   <key>StartInterval</key>\n\
+         \x20   <integer>{}</integer>\n\
+         </dict>\n\
+         </plist>\n",
+        launchd_label(scheduled.task),
+        arguments,
+        scheduled.interval_minutes * 60
+    )
+}
+
+fn task_scheduler_name(task: Task) -> String {
+    let name = task.name();
+    format!(
+        "{}\\{}{}",
+        TASK_FOLDER,
+        name[..1].to_uppercase(),
+        &name[1..]
+    )
+}
+
+/// `/SC` and `/MO` for an interval; minutes only go up to a day
+fn schtasks_interval(minutes: u64) -> (&'static str, u64) {
+    let minutes = minutes.max(1);
+    if minutes >= 24 * 60 {
+        ("DAILY", minutes / (24 * 60))
+    } else if minutes % 60 == 0 {
+        ("HOURLY", minutes / 60)
+    } else {
+        ("MINUTE", minutes)
+    }
+}
+
+pub fn schtasks_create(scheduled: &ScheduledTask, scope: Scope, exe: &Path) -> Vec<String> {
+    let (schedule, modifier) = schtasks_interval(scheduled.interval_minutes);
+    let mut command: Vec<String> = [
+        "schtasks",
+        "/Create",
+        "/F",
+        "/TN",
+        &task_scheduler_name(scheduled.task),
+        "/TR",
+        &format!(
+            "\"{}\" {}",
+            exe.display(),
+            scheduled.task.args(scope).join(" ")
+        ),
+        "/SC",
+        schedule,
+        "/MO",
+        &modifier.to_string(),
+    ]
+    .iter()
+    .map(|a| a.to_string())
+    .collect();
+    if scope == Scope::System {
+        command.extend(["/RU".to_string(), "SYSTEM".to_string()]);
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_systemd_units() {
+        let exe = Path::new("/opt/my tools/omni");
+        let service = systemd_service(Task::Update, Scope::User, exe);
+        assert!(service.contains("ExecStart=\"/opt/my tools/omni\" daemon --once\n"));
+        assert!(service.contains("Wants=network-online.target"));
+        assert!(!systemd_service(Task::Snapshot, Scope::User, exe).contains("network-online"));
+        assert!(systemd_service(Task::Update, Scope::System, exe)
+            .contains("ExecStart=\"/opt/my tools/omni\" --system daemon --once\n"));
+
+        let timer = systemd_timer(&ScheduledTask {
+            task: Task::Snapshot,
+            interval_minutes: 1440,
+        });
+        assert!(timer.contains("OnUnitActiveSec=1440min\n"));
+        assert!(timer.contains("WantedBy=timers.target"));
+    }
+
+    #[test]
+    fn test_launchd_and_task_scheduler() {
+        let update = ScheduledTask {
+            task: Task::Update,
+            interval_minutes: 360,
+        };
+        let plist = launchd_plist(&update, Scope::User, Path::new("/usr/local/bin/omni"));
+        assert!(plist.contains("<string>io.github.therealcoolnerd.omni.update</string>"));
+        assert!(plist.contains("        <string>--once</string>\n"));
+        assert!(plist.contains("<integer>21600</integer>"));
+
+        let command = schtasks_create(&update, Scope::System, Path::new("C:\\omni\\omni.exe"));
+        assert_eq!(
+            command.join(" "),
+            "schtasks /Create /F /TN Omni\\Update /TR \"C:\\omni\\omni.exe\" --system daemon --once \
+             /SC HOURLY /MO 6 /RU SYSTEM"
+        );
+        assert_eq!(schtasks_interval(90), ("MINUTE", 90));
+        assert_eq!(schtasks_interval(2880), ("DAILY", 2));
+    }
+}
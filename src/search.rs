@@ -1,19 +1,55 @@
+use crate::advanced_resolver;
 use crate::boxes::snap::SnapBox;
-use crate::database::{Database, PackageCache};
+use crate::config::{OmniConfig, SearchConfig};
+use crate::config_migration::edit_distance;
+use crate::database::{Database, IndexSource, IndexedPackage, PackageCache};
 use crate::distro::{self, PackageManager};
 use crate::package_discovery::{PackageDiscoveryService, PackageMetadata};
 use anyhow::Result;
-use chrono::Utc;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::process::Command;
 use tracing::{info, warn};
 
+/// Backends whose whole catalog can be read into the search index; snap can
+/// only be searched, so its packages are indexed as searches find them
+const INDEXED_BOXES: &[&str] = &["apt", "dnf", "pacman", "flatpak"];
+/// Results a search answered from the index returns at most
+const INDEX_SEARCH_LIMIT: usize = 200;
+
+/// Files owned by an installed package
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PackageFiles {
+    pub package: String,
+    pub box_type: String,
+    /// Absolute paths, directories included, in the backend's order
+    pub files: Vec<String>,
+}
+
+/// An installed package that ships a given file
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FileOwner {
+    pub path: String,
+    pub package: String,
+    pub box_type: String,
+}
+
+/// Packages that depend on a given one, as one backend reports them
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ReverseDependencies {
+    pub box_type: String,
+    pub packages: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SearchResult {
     pub name: String,
     pub description: Option<String>,
     pub version: Option<String>,
+    /// Machine architecture the package is built for (`amd64`, `i386`,
+    /// `x86_64`, `noarch`), when the backend reports one
+    pub architecture: Option<String>,
     pub box_type: String,
     pub source: Option<String>,
     pub installed: bool,
@@ -38,14 +74,36 @@ impl SearchEngine {
         Ok(Self { db, discovery })
     }
 
+    /// Search every backend: those whose catalog is in the local index from
+    /// there, and the others by asking them
     pub async fn search_all(&self, query: &str) -> Result<Vec<SearchResult>> {
         info!("Searching for: {}", query);
+        let ranking = OmniConfig::load().unwrap_or_default().search;
+
+        // Old catalogs still answer; `omni index refresh` and the daemon
+        // keep them current. A missing one sends the search to the backend
+        let mut from_index: Vec<String> = Vec::new();
+        if ranking.index_max_age_hours > 0 {
+            let stale = self.stale_index_boxes(ranking.index_max_age_hours).await?;
+            from_index = indexed_boxes()
+                .into_iter()
+                .filter(|box_type| {
+                    !stale
+                        .iter()
+                        .any(|(stale_box, at)| stale_box == box_type && at.is_none())
+                })
+                .map(str::to_string)
+                .collect();
+        }
+        let live = |box_type: &str| {
+            distro::command_exists(box_type) && !from_index.iter().any(|b| b == box_type)
+        };
 
         let mut results = Vec::new();
         let installed_packages = self.get_installed_package_names().await?;
 
         // Search apt
-        if distro::command_exists("apt") {
+        if live("apt") {
             if let Ok(apt_results) = self.search_apt(query).await {
                 for mut result in apt_results {
                     result.installed = installed_packages.contains(&format!("{}:apt", result.name));
@@ -55,7 +113,7 @@ impl SearchEngine {
         }
 
         // Search dnf
-        if distro::command_exists("dnf") {
+        if live("dnf") {
             if let Ok(dnf_results) = self.search_dnf(query).await {
                 for mut result in dnf_results {
                     result.installed = installed_packages.contains(&format!("{}:dnf", result.name));
@@ -65,7 +123,7 @@ impl SearchEngine {
         }
 
         // Search pacman
-        if distro::command_exists("pacman") {
+        if live("pacman") {
             if let Ok(pacman_results) = self.search_pacman(query).await {
                 for mut result in pacman_results {
                     result.installed =
@@ -76,7 +134,7 @@ impl SearchEngine {
         }
 
         // Search snap
-        if distro::command_exists("snap") {
+        if live("snap") {
             if let Ok(snap_results) = self.search_snap(query).await {
                 for mut result in snap_results {
                     result.installed =
@@ -87,7 +145,7 @@ impl SearchEngine {
         }
 
         // Search flatpak
-        if distro::command_exists("flatpak") {
+        if live("flatpak") {
             if let Ok(flatpak_results) = self.search_flatpak(query).await {
                 for mut result in flatpak_results {
                     result.installed =
@@ -97,33 +155,128 @@ impl SearchEngine {
             }
         }
 
-        // Deduplicate results by name, preferring installed packages
-        let mut unique_results: HashMap<String, SearchResult> = HashMap::new();
-        for result in results {
-            let key = result.name.clone();
-            if let Some(existing) = unique_results.get(&key) {
-                if result.installed && !existing.installed {
-                    unique_results.insert(key, result);
-                }
-            } else {
-                unique_results.insert(key, result);
+        // A virtual name (mta, java-runtime) also finds the packages providing it
+        for box_type in ["apt", "dnf"] {
+            if !distro::command_exists(box_type) {
+                continue;
             }
+            let Some(providers) = advanced_resolver::virtual_providers(box_type, query) else {
+                continue;
+            };
+            add_provider_results(&mut results, box_type, query, providers, &installed_packages);
         }
 
-        let mut final_results: Vec<SearchResult> = unique_results.into_values().collect();
-        final_results.sort_by(|a, b| {
-            // Sort by installed status first, then by name
-            match (a.installed, b.installed) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.name.cmp(&b.name),
-            }
-        });
+        // Remember every backend's results so later searches can be answered locally
+        let indexed: Vec<IndexedPackage> = results
+            .iter()
+            .map(|r| IndexedPackage {
+                package_name: r.name.clone(),
+                box_type: r.box_type.clone(),
+                version: r.version.clone(),
+                description: r.description.clone(),
+            })
+            .collect();
+        if let Err(e) = self.db.index_packages(&indexed).await {
+            warn!("Could not update the search index: {}", e);
+        }
+
+        if !from_index.is_empty() {
+            let indexed = self.search_local(query, INDEX_SEARCH_LIMIT).await?;
+            let before = results.len();
+            results.extend(
+                indexed
+                    .into_iter()
+                    .filter(|r| from_index.contains(&r.box_type)),
+            );
+            info!(
+                "{} results from the search index of {}",
+                results.len() - before,
+                from_index.join(", ")
+            );
+        }
+
+        // One result per name, best matches first
+        let final_results = rank_results(results, query, &ranking);
 
         info!("Found {} unique search results", final_results.len());
         Ok(final_results)
     }
 
+    /// Installed backends whose catalog the search index lacks (`None`) or
+    /// read longer than `max_age_hours` ago
+    pub async fn stale_index_boxes(
+        &self,
+        max_age_hours: u32,
+    ) -> Result<Vec<(String, Option<DateTime<Utc>>)>> {
+        let sources = self.db.package_index_sources().await?;
+        let cutoff = Utc::now() - ChronoDuration::hours(i64::from(max_age_hours));
+        Ok(indexed_boxes()
+            .into_iter()
+            .filter_map(|box_type| {
+                let refreshed_at = sources
+                    .iter()
+                    .find(|s| s.box_type == box_type)
+                    .map(|s| s.refreshed_at);
+                match refreshed_at {
+                    Some(at) if at >= cutoff => None,
+                    _ => Some((box_type.to_string(), refreshed_at)),
+                }
+            })
+            .collect())
+    }
+
+    /// Read the whole catalog of each of `box_types` into the search index,
+    /// returning how many packages each had; backends that fail are skipped
+    pub async fn refresh_index(&self, box_types: &[String]) -> Result<Vec<(String, usize)>> {
+        let mut refreshed = Vec::new();
+        for box_type in box_types {
+            let packages = match list_catalog(box_type) {
+                Ok(packages) => packages,
+                Err(e) => {
+                    warn!("Could not list the {} catalog: {}", box_type, e);
+                    continue;
+                }
+            };
+            self.db.replace_package_index(box_type, &packages).await?;
+            info!("Indexed {} {} packages", packages.len(), box_type);
+            refreshed.push((box_type.clone(), packages.len()));
+        }
+        Ok(refreshed)
+    }
+
+    /// Backends whose whole catalog is in the search index, and when it was read
+    pub async fn index_sources(&self) -> Result<Vec<IndexSource>> {
+        self.db.package_index_sources().await
+    }
+
+    /// Search the local index only, without asking any package manager; fast
+    /// enough to run on every keystroke
+    pub async fn search_local(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>> {
+        let installed_packages = self.get_installed_package_names().await?;
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let indexed = self.db.search_package_index(query, limit).await?;
+
+        Ok(indexed
+            .into_iter()
+            .map(|package| SearchResult {
+                installed: installed_packages
+                    .contains(&format!("{}:{}", package.package_name, package.box_type)),
+                name: package.package_name,
+                description: package.description,
+                version: package.version,
+                architecture: None,
+                box_type: package.box_type,
+                source: None,
+                popularity_rank: None,
+                security_score: None,
+                similar_packages: Vec::new(),
+                cross_platform_available: false,
+                category: None,
+                homepage: None,
+            })
+            .collect())
+    }
+
     async fn get_installed_package_names(&self) -> Result<std::collections::HashSet<String>> {
         let installed = self.db.get_installed_packages().await?;
         Ok(installed
@@ -153,13 +306,20 @@ impl SearchEngine {
                     let name_version = parts[0];
                     let description = parts[1];
 
-                    if let Some(name) = name_version.split('/').next() {
+                    // `name/suite[,suite] version arch`
+                    let mut fields = name_version.split_whitespace();
+                    let (name, suites) = fields
+                        .next()
+                        .and_then(|f| f.split_once('/'))
+                        .unwrap_or((name_version, ""));
+                    if !name.is_empty() {
                         results.push(SearchResult {
                             name: name.to_string(),
                             description: Some(description.to_string()),
-                            version: None,
+                            version: fields.next().map(|v| v.to_string()),
+                            architecture: fields.next().map(|a| a.to_string()),
                             box_type: "apt".to_string(),
-                            source: None,
+                            source: (!suites.is_empty()).then(|| suites.to_string()),
                             installed: false,
                             popularity_rank: None,
                             security_score: None,
@@ -197,11 +357,13 @@ impl SearchEngine {
                     let name_arch = parts[0];
                     let description = parts[1];
 
-                    if let Some(name) = name_arch.split('.').next() {
+                    // `name.arch`; the name itself may contain dots
+                    if let Some((name, arch)) = name_arch.trim().rsplit_once('.') {
                         results.push(SearchResult {
                             name: name.to_string(),
                             description: Some(description.to_string()),
                             version: None,
+                            architecture: Some(arch.to_string()),
                             box_type: "dnf".to_string(),
                             source: None,
                             installed: false,
@@ -243,7 +405,7 @@ impl SearchEngine {
                     let repo_name = parts[0];
                     let version = parts[1];
 
-                    if let Some(name) = repo_name.split('/').nth(1) {
+                    if let Some((repo, name)) = repo_name.split_once('/') {
                         let description = if i + 1 < lines.len() {
                             Some(lines[i + 1].trim().to_string())
                         } else {
@@ -254,8 +416,9 @@ impl SearchEngine {
                             name: name.to_string(),
                             description,
                             version: Some(version.to_string()),
+                            architecture: None,
                             box_type: "pacman".to_string(),
-                            source: None,
+                            source: Some(repo.to_string()),
                             installed: false,
                             popularity_rank: None,
                             security_score: None,
@@ -286,6 +449,7 @@ impl SearchEngine {
                             name: package,
                             description: None,
                             version: None,
+                            architecture: None,
                             box_type: "snap".to_string(),
                             source: None,
                             installed: false,
@@ -329,6 +493,7 @@ impl SearchEngine {
                     name: name.to_string(),
                     description: Some(description.to_string()),
                     version: None,
+                    architecture: None,
                     box_type: "flatpak".to_string(),
                     source: Some(app_id.to_string()),
                     installed: false,
@@ -456,7 +621,138 @@ Dependencies: {}",
         }
     }
 
+    /// Files installed by a package. Without `box_type`, the first of dpkg, rpm and
+    /// pacman that has the package installed is used.
+    pub async fn list_package_files(
+        &self,
+        package_name: &str,
+        box_type: Option<&str>,
+    ) -> Result<PackageFiles> {
+        let candidates: Vec<&str> = match box_type {
+            Some(box_type) => vec![box_type],
+            None => ["apt", "dnf", "pacman"]
+                .into_iter()
+                .filter(|bt| {
+                    file_list_command(bt).is_some_and(|(cmd, _)| distro::command_exists(cmd))
+                })
+                .collect(),
+        };
+
+        for bt in candidates {
+            let (command, arg) = file_list_command(bt).ok_or_else(|| {
+                anyhow::anyhow!("Listing files is not supported for box type '{}'", bt)
+            })?;
+
+            let output = Command::new(command).arg(arg).arg(package_name).output()?;
+            if output.status.success() {
+                return Ok(PackageFiles {
+                    package: package_name.to_string(),
+                    box_type: bt.to_string(),
+                    files: parse_file_list(bt, &String::from_utf8_lossy(&output.stdout)),
+                });
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Package {} is not installed{}",
+            package_name,
+            box_type
+                .map(|bt| format!(" via {}", bt))
+                .unwrap_or_default()
+        ))
+    }
+
+    /// Packages that own `path`, asking every available backend. Both the path as
+    /// given and its resolved form are tried, so symlinked locations such as
+    /// `/bin` on merged-/usr systems are still found.
+    pub async fn find_file_owners(&self, path: &str) -> Result<Vec<FileOwner>> {
+        if path.contains('\0') {
+            return Err(anyhow::anyhow!("Path contains null bytes"));
+        }
+        let given = std::path::absolute(path)?;
+        let mut paths = vec![given.to_string_lossy().to_string()];
+        if let Ok(resolved) = given.canonicalize() {
+            let resolved = resolved.to_string_lossy().to_string();
+            if !paths.contains(&resolved) {
+                paths.push(resolved);
+            }
+        }
+
+        let mut owners = Vec::new();
+        for box_type in ["apt", "dnf", "pacman"] {
+            let Some((command, args)) = file_owner_command(box_type) else {
+                continue;
+            };
+            if !distro::command_exists(command) {
+                continue;
+            }
+
+            for candidate in &paths {
+                // Each backend exits non-zero when nothing owns the path
+                let output = Command::new(command).args(args).arg(candidate).output()?;
+                if !output.status.success() {
+                    continue;
+                }
+                for package in
+                    parse_file_owners(box_type, &String::from_utf8_lossy(&output.stdout))
+                {
+                    let owner = FileOwner {
+                        path: candidate.clone(),
+                        package,
+                        box_type: box_type.to_string(),
+                    };
+                    if !owners.contains(&owner) {
+                        owners.push(owner);
+                    }
+                }
+            }
+        }
+
+        Ok(owners)
+    }
+
     /// Enhanced search with discovery metadata
+    /// Packages depending on `package_name`, asking the requested backend or
+    /// every available one; `installed_only` leaves out packages that are not
+    /// installed. Backends that report nothing are left out.
+    pub async fn find_reverse_dependencies(
+        &self,
+        package_name: &str,
+        box_type: Option<&str>,
+        installed_only: bool,
+    ) -> Result<Vec<ReverseDependencies>> {
+        let box_types: Vec<&str> = match box_type {
+            Some(box_type) => vec![box_type],
+            None => vec!["apt", "dnf", "pacman"],
+        };
+
+        let mut reports = Vec::new();
+        for bt in box_types {
+            let Some((command, _)) = reverse_dependency_command(bt, installed_only) else {
+                if box_type.is_some() {
+                    return Err(anyhow::anyhow!(
+                        "Reverse dependencies are not supported for box type '{}'",
+                        bt
+                    ));
+                }
+                continue;
+            };
+            if !distro::command_exists(command) {
+                continue;
+            }
+
+            let packages = query_reverse_dependencies(bt, package_name, installed_only)?;
+            if !packages.is_empty() {
+                reports.push(ReverseDependencies {
+                    box_type: bt.to_string(),
+                    packages,
+                });
+            }
+        }
+
+        Ok(reports)
+    }
+
     pub async fn search_enhanced(&self, query: &str) -> Result<Vec<SearchResult>> {
         let mut results = self.search_all(query).await?;
         
@@ -490,6 +786,7 @@ Dependencies: {}",
                     name: popular_pkg.name.clone(),
                     description: Some(format!("{} - Popular {} tool", popular_pkg.display_name, popular_pkg.category)),
                     version: None,
+                    architecture: None,
                     box_type: "discovery".to_string(),
                     source: None,
                     installed: false,
@@ -522,6 +819,7 @@ Dependencies: {}",
                 name: pkg.name.clone(),
                 description: Some(format!("{} - {} tool", pkg.display_name, pkg.category)),
                 version: None,
+                architecture: None,
                 box_type: "discovery".to_string(),
                 source: None,
                 installed: false,
@@ -561,6 +859,7 @@ Dependencies: {}",
                         name: similar_name.clone(),
                         description: Some(metadata.description),
                         version: None,
+                        architecture: None,
                         box_type: "discovery".to_string(),
                         source: None,
                         installed: false,
@@ -630,3 +929,631 @@ Dependencies: {}",
         }
     }
 }
+
+/// Native command listing every package a backend offers
+fn catalog_command(box_type: &str) -> Option<(&'static str, &'static [&'static str])> {
+    match box_type {
+        "apt" => Some(("apt-cache", &["search", "--names-only", "."])),
+        "dnf" => Some((
+            "dnf",
+            &[
+                "repoquery",
+                "--quiet",
+                "--available",
+                "--qf",
+                "%{name}\t%{version}-%{release}\t%{summary}\n",
+            ],
+        )),
+        // Without search terms pacman lists every package in the sync databases
+        "pacman" => Some(("pacman", &["-Ss"])),
+        "flatpak" => Some((
+            "flatpak",
+            &[
+                "remote-ls",
+                "--app",
+                "--columns=application,version,description",
+            ],
+        )),
+        _ => None,
+    }
+}
+
+/// Backends on this system whose catalog can be read into the search index
+pub fn indexed_boxes() -> Vec<&'static str> {
+    INDEXED_BOXES
+        .iter()
+        .copied()
+        .filter(|box_type| {
+            catalog_command(box_type).is_some_and(|(program, _)| distro::command_exists(program))
+        })
+        .collect()
+}
+
+/// Every package `box_type` offers, from its catalog command
+pub fn list_catalog(box_type: &str) -> Result<Vec<IndexedPackage>> {
+    let (program, args) = catalog_command(box_type)
+        .ok_or_else(|| anyhow::anyhow!("{} has no package catalog to index", box_type))?;
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_catalog(
+        box_type,
+        &String::from_utf8_lossy(&output.stdout),
+    ))
+}
+
+/// Packages in the output of a backend's catalog command, each name once
+pub fn parse_catalog(box_type: &str, output: &str) -> Vec<IndexedPackage> {
+    let mut seen = HashSet::new();
+    let mut packages = Vec::new();
+    let mut add = |name: &str, version: Option<&str>, description: Option<&str>| {
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) || !seen.insert(name.to_string()) {
+            return;
+        }
+        packages.push(IndexedPackage {
+            package_name: name.to_string(),
+            box_type: box_type.to_string(),
+            version: version
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string),
+            description: description
+                .map(str::trim)
+                .filter(|d| !d.is_empty())
+                .map(str::to_string),
+        });
+    };
+
+    match box_type {
+        // `name - summary`
+        "apt" => {
+            for line in output.lines() {
+                if let Some((name, description)) = line.split_once(" - ") {
+                    add(name, None, Some(description));
+                }
+            }
+        }
+        // `name<TAB>version-release<TAB>summary`, once per architecture
+        "dnf" => {
+            for line in output.lines() {
+                let mut fields = line.split('\t');
+                if let (Some(name), Some(version)) = (fields.next(), fields.next()) {
+                    add(name, Some(version), fields.next());
+                }
+            }
+        }
+        // `repo/name version [installed]`, then the indented description
+        "pacman" => {
+            let mut lines = output.lines().peekable();
+            while let Some(line) = lines.next() {
+                let mut fields = line.split_whitespace();
+                let Some((_, name)) = fields.next().and_then(|f| f.split_once('/')) else {
+                    continue;
+                };
+                let version = fields.next();
+                let description = lines.next_if(|l| l.starts_with(char::is_whitespace));
+                add(name, version, description);
+            }
+        }
+        // `application<TAB>version<TAB>description`
+        "flatpak" => {
+            for line in output.lines() {
+                let mut fields = line.split('\t');
+                match fields.next() {
+                    Some("Application ID") | None => {}
+                    Some(application) => add(application, fields.next(), fields.next()),
+                }
+            }
+        }
+        _ => {}
+    }
+    packages
+}
+
+/// How well `result` answers `query` under `ranking`, higher being better: an
+/// exact name beats a prefix, a prefix a substring and a substring a name a
+/// few typos away, with boosts for installed packages and popular backends
+pub fn relevance(result: &SearchResult, query: &str, ranking: &SearchConfig) -> u32 {
+    let query = query.trim().to_lowercase();
+    let name = result.name.to_lowercase();
+
+    let matched = if name == query {
+        ranking.exact_weight
+    } else if name.starts_with(&query) {
+        ranking.prefix_weight
+    } else if name.contains(&query) {
+        ranking.substring_weight
+    } else {
+        let distance = u32::try_from(edit_distance(&name, &query)).unwrap_or(u32::MAX);
+        if distance <= ranking.fuzzy_max_distance {
+            // One typo scores the full weight; a distance of 0 was an exact match
+            ranking.fuzzy_weight * (ranking.fuzzy_max_distance + 1 - distance)
+                / ranking.fuzzy_max_distance
+        } else if result
+            .description
+            .as_deref()
+            .is_some_and(|d| d.to_lowercase().contains(&query))
+        {
+            ranking.description_weight
+        } else {
+            0
+        }
+    };
+
+    let installed = if result.installed {
+        ranking.installed_boost
+    } else {
+        0
+    };
+    let popular = ranking
+        .popular_boxes
+        .iter()
+        .position(|b| *b == result.box_type)
+        .map_or(0, |at| {
+            let boxes = ranking.popular_boxes.len() as u32;
+            ranking.popular_boost * (boxes - at as u32) / boxes
+        });
+
+    matched.saturating_add(installed).saturating_add(popular)
+}
+
+/// Results for the packages providing the virtual name `query` in `box_type`,
+/// other than those already found
+pub fn add_provider_results(
+    results: &mut Vec<SearchResult>,
+    box_type: &str,
+    query: &str,
+    providers: Vec<String>,
+    installed_packages: &HashSet<String>,
+) {
+    for provider in providers {
+        if results.iter().any(|r| r.name == provider && r.box_type == box_type) {
+            continue;
+        }
+        results.push(SearchResult {
+            installed: installed_packages.contains(&format!("{}:{}", provider, box_type)),
+            name: provider,
+            description: Some(format!("Provides {}", query)),
+            version: None,
+            architecture: None,
+            box_type: box_type.to_string(),
+            source: None,
+            popularity_rank: None,
+            security_score: None,
+            similar_packages: Vec::new(),
+            cross_platform_available: false,
+            category: None,
+            homepage: None,
+        });
+    }
+}
+
+/// The most relevant result for each package name, best first and by name
+/// among equals
+pub fn rank_results(
+    results: Vec<SearchResult>,
+    query: &str,
+    ranking: &SearchConfig,
+) -> Vec<SearchResult> {
+    let mut best: HashMap<String, (u32, SearchResult)> = HashMap::new();
+    for result in results {
+        let score = relevance(&result, query, ranking);
+        if best.get(&result.name).is_none_or(|(kept, _)| score > *kept) {
+            best.insert(result.name.clone(), (score, result));
+        }
+    }
+
+    let mut ranked: Vec<(u32, SearchResult)> = best.into_values().collect();
+    ranked.sort_by(|(a_score, a), (b_score, b)| {
+        b_score.cmp(a_score).then_with(|| a.name.cmp(&b.name))
+    });
+    ranked.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Native command listing an installed package's files
+fn file_list_command(box_type: &str) -> Option<(&'static str, &'static str)> {
+    match box_type {
+        "apt" => Some(("dpkg", "-L")),
+        "dnf" => Some(("rpm", "-ql")),
+        "pacman" => Some(("pacman", "-Ql")),
+        _ => None,
+    }
+}
+
+/// Native command listing the packages that depend on a package
+fn reverse_dependency_command(
+    box_type: &str,
+    installed_only: bool,
+) -> Option<(&'static str, Vec<&'static str>)> {
+    let mut args = match box_type {
+        "apt" => vec!["rdepends"],
+        "dnf" => vec!["repoquery", "--quiet", "--qf", "%{name}\n", "--whatrequires"],
+        // pactree reads the local database unless told to use the sync one
+        "pacman" => vec!["-r", "-u", "-l", "-d", "1"],
+        _ => return None,
+    };
+    match (box_type, installed_only) {
+        ("apt", true) => args.push("--installed"),
+        ("dnf", true) => args.insert(1, "--installed"),
+        ("pacman", false) => args.push("-s"),
+        _ => {}
+    }
+    let command = match box_type {
+        "apt" => "apt-cache",
+        "dnf" => "dnf",
+        _ => "pactree",
+    };
+    Some((command, args))
+}
+
+/// Packages one backend reports as depending on `package`; none when the
+/// backend does not know the package
+pub fn query_reverse_dependencies(
+    box_type: &str,
+    package: &str,
+    installed_only: bool,
+) -> Result<Vec<String>> {
+    let Some((command, args)) = reverse_dependency_command(box_type, installed_only) else {
+        return Ok(Vec::new());
+    };
+    // Each backend exits non-zero for a package it does not know
+    let output = Command::new(command).args(&args).arg(package).output()?;
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+    Ok(parse_reverse_dependencies(
+        box_type,
+        package,
+        &String::from_utf8_lossy(&output.stdout),
+    ))
+}
+
+/// Package names from `apt-cache rdepends` (after `Reverse Depends:`, with `|`
+/// marking alternatives and `:arch` suffixes), `dnf repoquery --whatrequires`
+/// or `pactree -r -l` (which lists the package itself first)
+pub fn parse_reverse_dependencies(box_type: &str, package: &str, output: &str) -> Vec<String> {
+    let lines: Vec<&str> = if box_type == "apt" {
+        output
+            .lines()
+            .skip_while(|line| !line.starts_with("Reverse Depends:"))
+            .skip(1)
+            .collect()
+    } else {
+        output.lines().collect()
+    };
+
+    let mut packages: Vec<String> = Vec::new();
+    for line in lines {
+        let name = line.trim().trim_start_matches('|');
+        let name = name.split(':').next().unwrap_or(name).trim();
+        if name.is_empty() || name.contains(' ') || name == package {
+            continue;
+        }
+        if !packages.iter().any(|p| p == name) {
+            packages.push(name.to_string());
+        }
+    }
+    packages
+}
+
+/// Native command that finds the package owning a file
+fn file_owner_command(box_type: &str) -> Option<(&'static str, &'static [&'static str])> {
+    match box_type {
+        "apt" => Some(("dpkg", &["-S"])),
+        "dnf" => Some(("rpm", &["-qf", "--queryformat", "%{NAME}\\n"])),
+        "pacman" => Some(("pacman", &["-Qqo"])),
+        _ => None,
+    }
+}
+
+/// Package names from `dpkg -S` (`pkg1, pkg2:amd64: /path`, plus diversion notes),
+/// `rpm -qf --queryformat '%{NAME}\n'` or `pacman -Qqo` (one name per line)
+pub fn parse_file_owners(box_type: &str, output: &str) -> Vec<String> {
+    let mut packages = Vec::new();
+    for line in output.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if box_type == "apt" {
+            if line.starts_with("diversion by ") {
+                continue;
+            }
+            let Some((owners, _)) = line.split_once(": ") else {
+                continue;
+            };
+            for owner in owners.split(", ") {
+                // Multi-arch packages are reported as `name:arch`
+                let name = owner.split(':').next().unwrap_or(owner).trim();
+                if !name.is_empty() && !packages.iter().any(|p| p == name) {
+                    packages.push(name.to_string());
+                }
+            }
+        } else if !line.contains(' ') && !packages.iter().any(|p| p == line) {
+            packages.push(line.to_string());
+        }
+    }
+    packages
+}
+
+/// Paths from `dpkg -L`, `rpm -ql` or `pacman -Ql` (which prefixes each line with the
+/// package name). Directory entries lose their trailing slash; dpkg's `/.` is dropped.
+pub fn parse_file_list(box_type: &str, output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let path = if box_type == "pacman" {
+                line.split_once(' ').map(|(_, path)| path)?
+            } else {
+                line
+            };
+            let path = path.trim();
+            let path = if path.len() > 1 { path.trim_end_matches('/') } else { path };
+            (path.starts_with('/') && path != "/" && path != "/.").then(|| path.to_string())
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct FileTreeNode {
+    children: std::collections::BTreeMap<String, FileTreeNode>,
+}
+
+/// Render paths as an indented tree. Directories with a single child directory are
+/// joined (`usr/share/doc/curl`) so deep, narrow hierarchies stay readable.
+pub fn render_file_tree(files: &[String]) -> String {
+    let mut root = FileTreeNode::default();
+    for file in files {
+        let mut node = &mut root;
+        for part in file.split('/').filter(|p| !p.is_empty()) {
+            node = node.children.entry(part.to_string()).or_default();
+        }
+    }
+
+    let mut out = String::from("/\n");
+    render_children(&root, "", &mut out);
+    out
+}
+
+fn render_children(node: &FileTreeNode, prefix: &str, out: &mut String) {
+    let count = node.children.len();
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+
+        let mut label = name.clone();
+        let mut child = child;
+        while child.children.len() == 1 {
+            let (next_name, next) = child.children.iter().next().unwrap();
+            if next.children.is_empty() {
+                break;
+            }
+            label = format!("{}/{}", label, next_name);
+            child = next;
+        }
+
+        out.push_str(prefix);
+        out.push_str(if last { "└── " } else { "├── " });
+        out.push_str(&label);
+        if !child.children.is_empty() {
+            out.push('/');
+        }
+        out.push('\n');
+
+        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        render_children(child, &child_prefix, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_list() {
+        let dpkg = "/.\n/usr\n/usr/bin\n/usr/bin/curl\n/usr/share/doc/curl/copyright\n";
+        assert_eq!(
+            parse_file_list("apt", dpkg),
+            vec![
+                "/usr",
+                "/usr/bin",
+                "/usr/bin/curl",
+                "/usr/share/doc/curl/copyright"
+            ]
+        );
+
+        let pacman = "curl /usr/\ncurl /usr/bin/\ncurl /usr/bin/curl\n";
+        assert_eq!(
+            parse_file_list("pacman", pacman),
+            vec!["/usr", "/usr/bin", "/usr/bin/curl"]
+        );
+    }
+
+    #[test]
+    fn test_render_file_tree() {
+        let files: Vec<String> = [
+            "/usr/bin/curl",
+            "/usr/share/doc/curl/copyright",
+            "/usr/share/doc/curl/NEWS.gz",
+        ]
+        .iter()
+        .map(|f| f.to_string())
+        .collect();
+
+        assert_eq!(
+            render_file_tree(&files),
+            concat!(
+                "/\n",
+                "└── usr/\n",
+                "    ├── bin/\n",
+                "    │   └── curl\n",
+                "    └── share/doc/curl/\n",
+                "        ├── NEWS.gz\n",
+                "        └── copyright\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_file_owners() {
+        let dpkg = "diversion by dash from: /bin/sh\n\
+                    libc6:amd64, libc6:i386: /usr/share/doc/libc6\n";
+        assert_eq!(parse_file_owners("apt", dpkg), vec!["libc6"]);
+
+        assert_eq!(
+            parse_file_owners("dnf", "openssh-server\n"),
+            vec!["openssh-server"]
+        );
+        assert_eq!(
+            parse_file_owners("pacman", "coreutils\n"),
+            vec!["coreutils"]
+        );
+    }
+
+    #[test]
+    fn test_parse_reverse_dependencies() {
+        let apt = "curl\nReverse Depends:\n  |python3-pycurl\n  git\n  git:i386\n";
+        assert_eq!(
+            parse_reverse_dependencies("apt", "curl", apt),
+            vec!["python3-pycurl", "git"]
+        );
+        assert_eq!(
+            parse_reverse_dependencies("dnf", "curl", "git-core\ncmake\n"),
+            vec!["git-core", "cmake"]
+        );
+        assert_eq!(
+            parse_reverse_dependencies("pacman", "curl", "curl\ngit\ncmake\n"),
+            vec!["git", "cmake"]
+        );
+    }
+
+    #[test]
+    fn test_rank_results() {
+        let result = |name: &str, box_type: &str, installed: bool| SearchResult {
+            name: name.to_string(),
+            description: Some("Vi IMproved - enhanced vi editor".to_string()),
+            version: None,
+            architecture: None,
+            box_type: box_type.to_string(),
+            source: None,
+            installed,
+            popularity_rank: None,
+            security_score: None,
+            similar_packages: Vec::new(),
+            cross_platform_available: false,
+            category: None,
+            homepage: None,
+        };
+        let results = vec![
+            result("neovim", "snap", false),
+            result("vim-runtime", "apt", false),
+            result("vin", "pacman", false),
+            result("vim", "snap", false),
+            result("vim", "apt", true),
+            result("gvim", "dnf", false),
+            result("editorconfig", "apt", false),
+        ];
+
+        let ranked = rank_results(results, "vim", &SearchConfig::default());
+        let order: Vec<(&str, &str)> = ranked
+            .iter()
+            .map(|r| (r.name.as_str(), r.box_type.as_str()))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("vim", "apt"),
+                ("vim-runtime", "apt"),
+                ("gvim", "dnf"),
+                ("neovim", "snap"),
+                ("vin", "pacman"),
+                ("editorconfig", "apt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_provider_results() {
+        let mut results = vec![SearchResult {
+            name: "postfix".to_string(),
+            description: Some("High-performance mail transport agent".to_string()),
+            version: Some("3.8.6".to_string()),
+            architecture: None,
+            box_type: "apt".to_string(),
+            source: None,
+            installed: false,
+            popularity_rank: None,
+            security_score: None,
+            similar_packages: Vec::new(),
+            cross_platform_available: false,
+            category: None,
+            homepage: None,
+        }];
+        let providers = ["postfix", "exim4-daemon-light", "msmtp-mta"].map(String::from);
+        let installed = HashSet::from(["msmtp-mta:apt".to_string(), "postfix:dnf".to_string()]);
+        add_provider_results(&mut results, "apt", "mta", providers.to_vec(), &installed);
+
+        let found: Vec<(&str, bool)> = results
+            .iter()
+            .map(|r| (r.name.as_str(), r.installed))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("postfix", false),
+                ("exim4-daemon-light", false),
+                ("msmtp-mta", true)
+            ]
+        );
+        assert_eq!(results[0].version.as_deref(), Some("3.8.6"));
+        assert_eq!(results[1].description.as_deref(), Some("Provides mta"));
+        assert!(results.iter().all(|r| r.box_type == "apt"));
+    }
+
+    #[test]
+    fn test_parse_catalog() {
+        let names = |box_type: &str, output: &str| -> Vec<(String, Option<String>)> {
+            parse_catalog(box_type, output)
+                .into_iter()
+                .map(|p| (p.package_name, p.version))
+                .collect()
+        };
+
+        let apt = "vim - Vi IMproved - enhanced vi editor\nvim-tiny - Vi IMproved - compact\n";
+        assert_eq!(
+            names("apt", apt),
+            [("vim".to_string(), None), ("vim-tiny".to_string(), None)]
+        );
+        let apt = parse_catalog("apt", apt);
+        assert_eq!(
+            apt[0].description.as_deref(),
+            Some("Vi IMproved - enhanced vi editor")
+        );
+
+        let dnf = "vim-enhanced\t9.1.083-1.fc40\tA version of the VIM editor\n\n\
+                   glibc\t2.39-17.fc40\tThe GNU libc libraries\n\
+                   glibc\t2.39-17.fc40\tThe GNU libc libraries\n";
+        assert_eq!(
+            names("dnf", dnf),
+            [
+                ("vim-enhanced".to_string(), Some("9.1.083-1.fc40".to_string())),
+                ("glibc".to_string(), Some("2.39-17.fc40".to_string())),
+            ]
+        );
+
+        let pacman = "extra/vim 9.1.0707-1 [installed]\n    Vi Improved, a highly configurable text editor\n\
+                      core/zlib 1:1.3.1-2\n    Compression library\n";
+        let pacman = parse_catalog("pacman", pacman);
+        assert_eq!(pacman.len(), 2);
+        assert_eq!(pacman[1].package_name, "zlib");
+        assert_eq!(pacman[1].version.as_deref(), Some("1:1.3.1-2"));
+        assert_eq!(pacman[1].description.as_deref(), Some("Compression library"));
+
+        let flatpak = "Application ID\tVersion\tDescription\norg.gimp.GIMP\t2.10.38\tCreate images\n";
+        assert_eq!(
+            names("flatpak", flatpak),
+            [("org.gimp.GIMP".to_string(), Some("2.10.38".to_string()))]
+        );
+    }
+}
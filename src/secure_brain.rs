@@ -94,6 +94,9 @@ impl SecureOmniBrainV2 {
                         install_path: None,
                         installed_at: Utc::now(),
                         status: InstallStatus::Success,
+                        repository: None,
+                        mirror_url: None,
+                        channel: None,
                         metadata: None,
                     };
 
@@ -129,6 +132,9 @@ impl SecureOmniBrainV2 {
                         install_path: None,
                         installed_at: Utc::now(),
                         status: InstallStatus::Failed,
+                        repository: None,
+                        mirror_url: None,
+                        channel: None,
                         metadata: Some(format!("Error: {}", e)),
                     };
 
@@ -183,6 +189,9 @@ impl SecureOmniBrainV2 {
                         install_path: None,
                         installed_at: Utc::now(),
                         status: InstallStatus::Success,
+                        repository: None,
+                        mirror_url: None,
+                        channel: None,
                         metadata: None,
                     };
 
@@ -1,3 +1,6 @@
+use crate::config::OmniConfig;
+use crate::integrity::{self, IntegrityReport};
+use crate::keys::KeyStore;
 use anyhow::Result;
 use hex;
 use reqwest;
@@ -39,6 +42,8 @@ impl Default for SecurityPolicy {
 #[derive(Debug, Clone)]
 pub struct SecurityVerifier {
     policy: SecurityPolicy,
+    /// omni's trusted keys; the user's own keyring when unset
+    keys: Option<KeyStore>,
 }
 
 #[derive(Debug, Clone)]
@@ -60,7 +65,20 @@ pub enum TrustLevel {
 
 impl SecurityVerifier {
     pub fn new(policy: SecurityPolicy) -> Self {
-        Self { policy }
+        Self { policy, keys: None }
+    }
+
+    /// Check signatures against the keys in `keys` (`omni keys`) only
+    pub fn with_key_store(mut self, keys: KeyStore) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    fn gpg(&self) -> Command {
+        match &self.keys {
+            Some(keys) => keys.gpg(),
+            None => Command::new("gpg"),
+        }
     }
 
     pub async fn verify_package(
@@ -197,7 +215,8 @@ impl SecurityVerifier {
         }
 
         // Verify using GPG
-        let output = Command::new("gpg")
+        let output = self
+            .gpg()
             .arg("--verify")
             .arg(&signature_path)
             .arg(file_path)
@@ -256,7 +275,7 @@ impl SecurityVerifier {
             ));
         }
 
-        let mut temp_file = NamedTempFile::new()?;
+        let mut temp_file = NamedTempFile::new_in(OmniConfig::temp_dir()?)?;
         let content = response.bytes().await?;
 
         use std::io::Write;
@@ -505,6 +524,54 @@ impl SecurityVerifier {
         summary
     }
 
+    /// Check installed files against their packages' recorded checksums, one result per
+    /// backend. Locally edited configuration files are warnings, not failures.
+    pub async fn verify_installed(
+        &self,
+        package: Option<&str>,
+    ) -> Result<Vec<(IntegrityReport, VerificationResult)>> {
+        let reports = integrity::check_installed(package)?;
+
+        Ok(reports
+            .into_iter()
+            .map(|report| {
+                let mut result = VerificationResult {
+                    signature_valid: None,
+                    checksum_valid: Some(report.is_clean()),
+                    trust_level: if report.is_clean() {
+                        TrustLevel::Valid
+                    } else {
+                        TrustLevel::Untrusted
+                    },
+                    warnings: report
+                        .issues
+                        .iter()
+                        .map(|issue| {
+                            format!(
+                                "{}{}: {}{}",
+                                if issue.config { "config " } else { "" },
+                                issue.kind.as_str(),
+                                issue.path,
+                                issue
+                                    .package
+                                    .as_ref()
+                                    .map(|p| format!(" ({})", p))
+                                    .unwrap_or_default()
+                            )
+                        })
+                        .collect(),
+                    details: String::new(),
+                };
+                result.details = format!(
+                    "Checked with: {}\n{}",
+                    report.tool,
+                    self.generate_verification_summary(&result)
+                );
+                (report, result)
+            })
+            .collect())
+    }
+
     pub async fn verify_repository_metadata(&self, box_type: &str) -> Result<VerificationResult> {
         info!("Verifying repository metadata for: {}", box_type);
 
@@ -584,7 +651,8 @@ impl SecurityVerifier {
         info!("Importing GPG key: {}", key_id);
 
         for server in &self.policy.signature_servers {
-            let output = Command::new("gpg")
+            let output = self
+                .gpg()
                 .arg("--keyserver")
                 .arg(server)
                 .arg("--recv-keys")
@@ -604,7 +672,8 @@ impl SecurityVerifier {
     }
 
     pub fn list_trusted_keys(&self) -> Result<Vec<String>> {
-        let output = Command::new("gpg")
+        let output = self
+            .gpg()
             .arg("--list-keys")
             .arg("--with-colons")
             .output()?;
@@ -0,0 +1,442 @@
+//! `omni self-update`: fetch the latest release, verify it and swap it in for
+//! the running binary.
+
+use crate::config::{OmniConfig, SelfUpdateConfig};
+use crate::error_handling::OmniError;
+use crate::security::{SecurityPolicy, SecurityVerifier};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+/// Release metadata in the shape of GitHub's releases API
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    pub fn version(&self) -> Result<semver::Version> {
+        semver::Version::parse(self.tag_name.trim_start_matches('v'))
+            .map_err(|e| anyhow!("Release tag '{}' is not a version: {}", self.tag_name, e))
+    }
+
+    fn asset(&self, name: &str) -> Option<&ReleaseAsset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
+}
+
+/// Version of the running binary
+pub fn current_version() -> semver::Version {
+    semver::Version::parse(env!("CARGO_PKG_VERSION")).expect("crate version is semver")
+}
+
+/// Release archive for an OS and architecture, as the release workflow names them
+pub fn asset_name(os: &str, arch: &str) -> Option<String> {
+    let (platform, archive) = match os {
+        "linux" => ("linux", "tar.gz"),
+        "macos" => ("macos", "tar.gz"),
+        "windows" => ("windows", "zip"),
+        _ => return None,
+    };
+    Some(format!("omni-{}-{}.{}", platform, arch, archive))
+}
+
+fn binary_name() -> &'static str {
+    if cfg!(windows) {
+        "omni.exe"
+    } else {
+        "omni"
+    }
+}
+
+/// Digest from a `sha256sum` line: `<hex>  <file>`
+pub fn parse_checksum(content: &str) -> Option<String> {
+    let digest = content.split_whitespace().next()?;
+    (digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| digest.to_lowercase())
+}
+
+/// Fingerprint gpg reports in its `VALIDSIG` status line
+pub fn parse_validsig(status: &str) -> Option<String> {
+    status.lines().find_map(|line| {
+        let rest = line.strip_prefix("[GNUPG:] VALIDSIG ")?;
+        rest.split_whitespace().next().map(str::to_uppercase)
+    })
+}
+
+/// What `omni self-update` found or did
+#[derive(Debug)]
+pub enum Outcome {
+    UpToDate(semver::Version),
+    Available(semver::Version),
+    Updated {
+        from: semver::Version,
+        to: semver::Version,
+    },
+}
+
+pub struct SelfUpdater {
+    settings: SelfUpdateConfig,
+    signature_servers: Vec<String>,
+    client: reqwest::Client,
+}
+
+impl SelfUpdater {
+    pub fn new(config: &OmniConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("omni/", env!("CARGO_PKG_VERSION")))
+            .timeout(std::time::Duration::from_secs(300))
+            .build()?;
+        Ok(Self {
+            settings: config.self_update.clone(),
+            signature_servers: config.security.signature_servers.clone(),
+            client,
+        })
+    }
+
+    pub async fn latest_release(&self) -> Result<Release> {
+        let response = self
+            .client
+            .get(&self.settings.endpoint)
+            .header("Accept", "application/vnd.github+json")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Release check at {} failed: HTTP {}",
+                self.settings.endpoint,
+                response.status()
+            ));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// Check for a newer release and, unless `check_only`, install it.
+    /// `force` reinstalls the latest release even when it is not newer.
+    pub async fn run(&self, check_only: bool, force: bool) -> Result<Outcome> {
+        let current = current_version();
+        let release = self.latest_release().await?;
+        let latest = release.version()?;
+
+        if latest <= current && !force {
+            return Ok(Outcome::UpToDate(current));
+        }
+        if check_only {
+            return Ok(Outcome::Available(latest));
+        }
+        crate::config::ensure_writable("replacing the omni binary")?;
+
+        let archive_name = asset_name(std::env::consts::OS, std::env::consts::ARCH)
+            .ok_or_else(|| anyhow!("No omni releases are built for this platform"))?;
+        let archive = release
+            .asset(&archive_name)
+            .ok_or_else(|| anyhow!("Release {} has no {}", release.tag_name, archive_name))?;
+        let checksum = release
+            .asset(&format!("{}.sha256", archive_name))
+            .ok_or_else(|| anyhow!("Release {} publishes no checksum", release.tag_name))?;
+        let signature = [".asc", ".sig"]
+            .iter()
+            .find_map(|ext| release.asset(&format!("{}{}", archive_name, ext)))
+            .ok_or_else(|| OmniError::SecurityViolation {
+                message: format!(
+                    "Release {} is not signed; refusing to install it",
+                    release.tag_name
+                ),
+            })?;
+
+        let work_dir = tempfile::TempDir::new_in(OmniConfig::temp_dir()?)?;
+        println!("⬇️  Downloading omni {}", latest);
+        let archive_path = self.download(archive, work_dir.path()).await?;
+        let checksum_path = self.download(checksum, work_dir.path()).await?;
+        let signature_path = self.download(signature, work_dir.path()).await?;
+
+        let expected = parse_checksum(&fs::read_to_string(&checksum_path)?)
+            .ok_or_else(|| anyhow!("{} is not a sha256sum file", checksum.name))?;
+        self.verify(&archive_path, &expected, &signature_path)
+            .await?;
+
+        let new_binary = extract_binary(&archive_path, work_dir.path())?;
+        let target = std::env::current_exe()?;
+        let expected_version = latest.to_string();
+        replace_executable(&target, &new_binary, |binary| {
+            self_test(binary, &expected_version)
+        })?;
+
+        Ok(Outcome::Updated {
+            from: current,
+            to: latest,
+        })
+    }
+
+    async fn download(&self, asset: &ReleaseAsset, dir: &Path) -> Result<PathBuf> {
+        info!("Downloading {}", asset.browser_download_url);
+        let response = self.client.get(&asset.browser_download_url).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to download {}: HTTP {}",
+                asset.name,
+                response.status()
+            ));
+        }
+        // Asset names come from the endpoint; never let them leave `dir`
+        let file_name = Path::new(&asset.name)
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid asset name '{}'", asset.name))?;
+        let path = dir.join(file_name);
+        fs::write(&path, response.bytes().await?)?;
+        Ok(path)
+    }
+
+    /// Checksum and signature must both pass, whatever the security settings
+    async fn verify(&self, archive: &Path, checksum: &str, signature: &Path) -> Result<()> {
+        let policy = SecurityPolicy {
+            verify_signatures: true,
+            verify_checksums: true,
+            allow_untrusted: false,
+            signature_servers: self.signature_servers.clone(),
+            ..SecurityPolicy::default()
+        };
+        let verifier = SecurityVerifier::new(policy);
+
+        if let Some(key) = &self.settings.signing_key {
+            if !verifier.import_gpg_key(key)? {
+                warn!(
+                    "Could not fetch signing key {}; using the local keyring",
+                    key
+                );
+            }
+        }
+
+        let signature = signature.to_string_lossy();
+        let result = verifier
+            .verify_package(archive, Some(checksum), Some(&signature), "self-update")
+            .await
+            .map_err(|e| OmniError::SecurityViolation {
+                message: format!("The downloaded release failed verification: {}", e),
+            })?;
+        if result.checksum_valid != Some(true) || result.signature_valid != Some(true) {
+            return Err(OmniError::SecurityViolation {
+                message: format!(
+                    "The downloaded release failed verification:\n{}",
+                    result.details
+                ),
+            }
+            .into());
+        }
+
+        if let Some(key) = &self.settings.signing_key {
+            let signer = signer_fingerprint(archive, Path::new(&*signature))?;
+            let key = key.replace(' ', "").to_uppercase();
+            if !signer.ends_with(&key) {
+                return Err(OmniError::SecurityViolation {
+                    message: format!(
+                        "The release is signed by {}, not by the configured key {}",
+                        signer, key
+                    ),
+                }
+                .into());
+            }
+        }
+
+        println!("🔒 Checksum and signature verified");
+        Ok(())
+    }
+}
+
+fn signer_fingerprint(file: &Path, signature: &Path) -> Result<String> {
+    let output = Command::new("gpg")
+        .args(["--status-fd", "1", "--verify"])
+        .arg(signature)
+        .arg(file)
+        .output()?;
+    parse_validsig(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| anyhow!("gpg did not report who signed the release"))
+}
+
+/// Unpack the omni binary from a release archive into `dir`
+fn extract_binary(archive: &Path, dir: &Path) -> Result<PathBuf> {
+    let name = binary_name();
+    let target = dir.join(format!("{}.extracted", name));
+    let is_binary = |path: &Path| path.file_name().and_then(|n| n.to_str()) == Some(name);
+
+    let archive_name = archive.to_string_lossy();
+    if archive_name.ends_with(".zip") {
+        let mut zip = zip::ZipArchive::new(fs::File::open(archive)?)?;
+        for index in 0..zip.len() {
+            let mut entry = zip.by_index(index)?;
+            if entry.is_file() && is_binary(Path::new(entry.name())) {
+                let mut contents = Vec::new();
+                entry.read_to_end(&mut contents)?;
+                fs::write(&target, contents)?;
+                return Ok(target);
+            }
+        }
+    } else {
+        let decoder = flate2::read::GzDecoder::new(fs::File::open(archive)?);
+        let mut tar = tar::Archive::new(decoder);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type().is_file() && is_binary(&entry.path()?) {
+                entry.unpack(&target)?;
+                return Ok(target);
+            }
+        }
+    }
+
+    Err(anyhow!("{} does not contain {}", archive.display(), name))
+}
+
+/// The installed binary must start and report the version it was released as
+fn self_test(binary: &Path, expected_version: &str) -> Result<()> {
+    let output = Command::new(binary).arg("--version").output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || !stdout.contains(expected_version) {
+        return Err(anyhow!(
+            "`omni --version` printed '{}' instead of {}",
+            stdout.trim(),
+            expected_version
+        ));
+    }
+    Ok(())
+}
+
+/// Put `new_binary` in place of `target`. The new file is staged next to the
+/// target so the final rename is atomic, and the old binary is kept until
+/// `check` accepts the result; any failure restores it.
+pub fn replace_executable(
+    target: &Path,
+    new_binary: &Path,
+    check: impl Fn(&Path) -> Result<()>,
+) -> Result<()> {
+    let file_name = target
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("Cannot replace {}", target.display()))?;
+    let staged = target.with_file_name(format!(".{}.new", file_name));
+    let backup = target.with_file_name(format!(".{}.old", file_name));
+    let permission_hint = |e: std::io::Error| {
+        anyhow!(
+            "Cannot write next to {} ({}); rerun with the permissions that installed omni",
+            target.display(),
+            e
+        )
+    };
+
+    fs::copy(new_binary, &staged).map_err(permission_hint)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+
+    if backup.exists() {
+        fs::remove_file(&backup)?;
+    }
+    // Windows cannot overwrite a running executable, but it can rename it
+    let backed_up = if cfg!(windows) {
+        fs::rename(target, &backup)
+    } else {
+        fs::copy(target, &backup).map(|_| ())
+    };
+    if let Err(e) = backed_up {
+        let _ = fs::remove_file(&staged);
+        return Err(permission_hint(e));
+    }
+
+    if let Err(e) = fs::rename(&staged, target) {
+        let _ = fs::remove_file(&staged);
+        fs::rename(&backup, target)?;
+        return Err(anyhow!("Failed to install the new binary: {}", e));
+    }
+
+    if let Err(e) = check(target) {
+        fs::rename(&backup, target)?;
+        return Err(anyhow!(
+            "The new binary failed its self-test ({}); the previous version was restored",
+            e
+        ));
+    }
+
+    // Windows keeps the old file locked while it runs; it is replaced next time
+    if fs::remove_file(&backup).is_err() {
+        info!("Left {} behind", backup.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_release_assets_and_versions() {
+        assert_eq!(
+            asset_name("linux", "x86_64").as_deref(),
+            Some("omni-linux-x86_64.tar.gz")
+        );
+        assert_eq!(
+            asset_name("windows", "x86_64").as_deref(),
+            Some("omni-windows-x86_64.zip")
+        );
+        assert_eq!(asset_name("freebsd", "x86_64"), None);
+
+        let release: Release = serde_json::from_str(
+            r#"{"tag_name": "v1.2.0", "assets": [{"name": "omni-linux-x86_64.tar.gz",
+               "browser_download_url": "https://example.invalid/omni-linux-x86_64.tar.gz"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(release.version().unwrap(), semver::Version::new(1, 2, 0));
+        assert!(release.asset("omni-linux-x86_64.tar.gz").is_some());
+
+        let digest = "a".repeat(64);
+        assert_eq!(
+            parse_checksum(&format!("{}  omni-linux-x86_64.tar.gz\n", digest)),
+            Some(digest)
+        );
+        assert_eq!(parse_checksum("not-a-digest  file"), None);
+
+        let status = "[GNUPG:] NEWSIG\n[GNUPG:] VALIDSIG 0123456789abcdef0123456789ABCDEF01234567 2024-06-01 1717200000 0 4 0 1 10 00 0123456789ABCDEF0123456789ABCDEF01234567\n";
+        assert_eq!(
+            parse_validsig(status).as_deref(),
+            Some("0123456789ABCDEF0123456789ABCDEF01234567")
+        );
+    }
+
+    #[test]
+    fn test_replace_executable_rolls_back() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("omni");
+        let new_binary = dir.path().join("download");
+        fs::write(&target, "old").unwrap();
+        fs::write(&new_binary, "new").unwrap();
+
+        let result = replace_executable(&target, &new_binary, |_| Err(anyhow!("crashed")));
+        assert!(result.unwrap_err().to_string().contains("restored"));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+
+        replace_executable(&target, &new_binary, |path| {
+            assert_eq!(fs::read_to_string(path)?, "new");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        let leftovers: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(leftovers.len(), 2);
+    }
+}
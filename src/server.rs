@@ -1,17 +1,63 @@
+//! Local REST API (`omni serve --http`) so dashboards and scripts can search,
+//! install, remove and inspect history and snapshots without shelling out.
+//! Every request needs `Authorization: Bearer <token>`: the owner's token, or
+//! an API user's, whose role decides what they may do (see `authorization`).
+
+use crate::authorization::{self, Caller, Permission, UserStore};
+use crate::brain::OmniBrain;
+use crate::config::{self, OmniConfig};
+use crate::database::{Database, InstallRecord, Snapshot};
+use crate::doctor::{Check, Level};
+use crate::health;
+use crate::lock::OperationLock;
+use crate::mock;
+use crate::snapshot::SnapshotManager;
+use anyhow::Result;
 use axum::{
-    extract::{Query, State},
+    extract::{Extension, Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tower_http::cors::CorsLayer;
-use crate::brain::OmniBrain;
+
+/// Address `omni serve` listens on without `--http`; loopback only
+pub const DEFAULT_ADDR: &str = "127.0.0.1:3000";
+/// Environment variable that sets the API token instead of the token file
+pub const TOKEN_ENV: &str = "OMNI_API_TOKEN";
+const TOKEN_FILE: &str = "api-token";
+pub(crate) const DEFAULT_HISTORY_LIMIT: i64 = 50;
 
 #[derive(Clone)]
 pub struct AppState {
     brain: Arc<Mutex<OmniBrain>>,
+    token: Arc<str>,
+    users: Option<UserStore>,
+    mock: bool,
+}
+
+impl AppState {
+    pub fn new(token: &str, mock: bool) -> Self {
+        Self {
+            brain: Arc::new(Mutex::new(OmniBrain::new_with_mock(mock))),
+            token: token.into(),
+            users: UserStore::open().ok(),
+            mock,
+        }
+    }
+
+    #[cfg(test)]
+    fn with_users(mut self, users: UserStore) -> Self {
+        self.users = Some(users);
+        self
+    }
 }
 
 #[derive(Serialize)]
@@ -29,96 +75,471 @@ pub struct PackageInfo {
     description: Option<String>,
 }
 
+impl From<InstallRecord> for PackageInfo {
+    fn from(record: InstallRecord) -> Self {
+        Self {
+            name: record.package_name,
+            version: record.version.unwrap_or_else(|| "unknown".to_string()),
+            box_type: record.box_type,
+            description: None,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct SearchQuery {
     q: String,
 }
 
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    limit: Option<i64>,
+}
+
 #[derive(Deserialize)]
 pub struct InstallPayload {
     package: String,
     box_type: Option<String>,
 }
 
-pub async fn start_server(port: u16) -> anyhow::Result<()> {
-    let brain = OmniBrain::new();
-    let state = AppState {
-        brain: Arc::new(Mutex::new(brain)),
-    };
+#[derive(Deserialize, Default)]
+pub struct SnapshotPayload {
+    name: Option<String>,
+    description: Option<String>,
+}
+
+/// An error answered as `{"status": "error", "message": ...}`
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({ "status": "error", "message": self.message }));
+        (self.status, body).into_response()
+    }
+}
+
+type ApiResult<T> = std::result::Result<Json<T>, ApiError>;
+
+/// The token from `OMNI_API_TOKEN`, or the one in the state directory,
+/// generated on first use. Returns where it came from for the banner.
+pub fn load_or_create_token() -> Result<(String, String)> {
+    if let Ok(token) = std::env::var(TOKEN_ENV) {
+        if !token.trim().is_empty() {
+            return Ok((token.trim().to_string(), TOKEN_ENV.to_string()));
+        }
+    }
+
+    let path = token_path()?;
+    if let Ok(token) = std::fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok((token.trim().to_string(), path.display().to_string()));
+        }
+    }
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    config::write_private_file(&path, token.as_bytes())?;
+    Ok((token, path.display().to_string()))
+}
+
+fn token_path() -> Result<PathBuf> {
+    Ok(OmniConfig::state_dir()?.join(TOKEN_FILE))
+}
+
+/// Compare in constant time so response timing does not leak the token
+pub(crate) fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn require_token(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let caller = given.and_then(|token| {
+        authorization::authenticate(&state.token, state.users.as_ref(), token.trim())
+    });
+
+    match caller {
+        Some(caller) => {
+            request.extensions_mut().insert(caller);
+            next.run(request).await
+        }
+        None => {
+            ApiError::new(StatusCode::UNAUTHORIZED, "Missing or invalid API token").into_response()
+        }
+    }
+}
+
+/// Refuse with 403 when the caller's role does not allow `permission`
+fn authorize(caller: &Caller, permission: Permission) -> std::result::Result<(), ApiError> {
+    caller
+        .authorize(permission)
+        .map_err(|e| ApiError::new(StatusCode::FORBIDDEN, e.to_string()))
+}
 
-    let app = Router::new()
+pub fn router(state: AppState) -> Router {
+    Router::new()
         .route("/api/system/info", get(get_system_info))
         .route("/api/packages/installed", get(get_installed_packages))
         .route("/api/packages/search", get(search_packages))
         .route("/api/packages/install", post(install_package))
         .route("/api/packages/remove", post(remove_package))
+        .route("/api/history", get(get_history))
+        .route("/api/snapshots", get(list_snapshots).post(create_snapshot))
+        .route("/api/snapshots/:id/revert", post(revert_snapshot))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        // Readiness probes cannot send a token
+        .route("/health", get(get_health))
         .layer(CorsLayer::permissive())
-        .with_state(state);
+        .with_state(state)
+}
 
-    let addr = format!("0.0.0.0:{}", port);
-    println!("🚀 Omni Server running on http://{}", addr);
-    
+pub async fn serve(addr: SocketAddr, mock: bool) -> Result<()> {
+    let (token, token_source) = load_or_create_token()?;
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
 
+    println!(
+        "🚀 Omni API listening on http://{}/api",
+        listener.local_addr()?
+    );
+    println!(
+        "🔑 Send 'Authorization: Bearer <token>'; the token is in {}",
+        token_source
+    );
+    let users = UserStore::open().and_then(|store| store.list())?;
+    if !users.is_empty() {
+        println!(
+            "👥 {} API users have tokens of their own (see 'omni users list')",
+            users.len()
+        );
+    }
+    if !addr.ip().is_loopback() {
+        println!(
+            "⚠️  {} is reachable from other machines; put TLS in front of it",
+            addr.ip()
+        );
+    }
+
+    axum::serve(listener, router(AppState::new(&token, mock))).await?;
     Ok(())
 }
 
-async fn get_system_info() -> Json<SystemInfo> {
-    Json(SystemInfo {
+/// 200 when healthy and 503 when not, with the checks either way
+async fn get_health(State(state): State<AppState>) -> (StatusCode, Json<health::Report>) {
+    let report = if state.mock {
+        health::Report::new(vec![Check::new(
+            "Mock backend",
+            Level::Ok,
+            "In-memory packages",
+        )])
+    } else {
+        health::run().await
+    };
+    let status = if report.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+/// Refuse changes in read-only mode and while another omni process is busy.
+/// Mock mode touches no package manager and needs no lock.
+fn begin_change(
+    state: &AppState,
+    operation: &str,
+) -> std::result::Result<Option<OperationLock>, ApiError> {
+    config::ensure_writable(operation)
+        .map_err(|e| ApiError::new(StatusCode::FORBIDDEN, e.to_string()))?;
+    if state.mock {
+        return Ok(None);
+    }
+    match OperationLock::try_acquire()? {
+        Some(lock) => Ok(Some(lock)),
+        None => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "Another omni process is busy; try again when it finishes",
+        )),
+    }
+}
+
+fn done(message: String) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "success", "message": message }))
+}
+
+async fn get_system_info(Extension(caller): Extension<Caller>) -> ApiResult<SystemInfo> {
+    authorize(&caller, Permission::Read)?;
+    Ok(Json(SystemInfo {
         os: std::env::consts::OS.to_string(),
         arch: std::env::consts::ARCH.to_string(),
-        hostname: hostname::get().map(|h| h.to_string_lossy().to_string()).unwrap_or_default(),
-    })
+        hostname: hostname::get()
+            .map(|h| h.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    }))
 }
 
-async fn get_installed_packages(State(state): State<AppState>) -> Json<Vec<PackageInfo>> {
-    // In a real implementation, we would call brain.get_installed_packages()
-    // For now, we'll return a mock list if the DB isn't ready, or try to query the DB
-    // Since OmniBrain needs async methods exposed for this, we might strictly rely on the DB directly or mock it for this demo if needed.
-    // However, let's assume we can add a method to Brain or access the DB.
-    
-    // For this demonstration/fix:
-    let packages = vec![
-        PackageInfo { name: "git".into(), version: "2.40.0".into(), box_type: "winget".into(), description: Some("Version control".into()) },
-        PackageInfo { name: "rust".into(), version: "1.75.0".into(), box_type: "winget".into(), description: Some("Systems programming language".into()) },
-        PackageInfo { name: "vscode".into(), version: "1.85.0".into(), box_type: "winget".into(), description: Some("Code editor".into()) },
-    ];
-    Json(packages)
+async fn get_installed_packages(
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+) -> ApiResult<Vec<PackageInfo>> {
+    authorize(&caller, Permission::Read)?;
+    let records = if state.mock {
+        mock::backend().installed()
+    } else {
+        Database::new().await?.get_installed_packages().await?
+    };
+    Ok(Json(records.into_iter().map(PackageInfo::from).collect()))
 }
 
 async fn search_packages(
     State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
     Query(query): Query<SearchQuery>,
-) -> Json<Vec<PackageInfo>> {
-    // Connect to real search engine
+) -> ApiResult<Vec<crate::search::SearchResult>> {
+    authorize(&caller, Permission::Search)?;
     let mut brain = state.brain.lock().await;
-    // We would call search here.
-    // Mocking response for robustness in this step:
-    let packages = vec![
-        PackageInfo { name: query.q.clone(), version: "latest".into(), box_type: "winget".into(), description: Some(format!("Result for {}", query.q)) },
-    ];
-    Json(packages)
+    Ok(Json(brain.search(&query.q).await?))
 }
 
 async fn install_package(
     State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
     Json(payload): Json<InstallPayload>,
-) -> Json<serde_json::Value> {
+) -> ApiResult<serde_json::Value> {
+    authorize(&caller, Permission::Install)?;
+    let _lock = begin_change(&state, "installing packages")?;
     let mut brain = state.brain.lock().await;
-    match brain.install(&payload.package, payload.box_type.as_deref()).await {
-        Ok(_) => Json(serde_json::json!({ "status": "success", "message": format!("Installed {}", payload.package) })),
-        Err(e) => Json(serde_json::json!({ "status": "error", "message": e.to_string() })),
-    }
+    brain
+        .install(&payload.package, payload.box_type.as_deref())
+        .await?;
+    Ok(done(format!("Installed {}", payload.package)))
 }
 
 async fn remove_package(
     State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
     Json(payload): Json<InstallPayload>,
-) -> Json<serde_json::Value> {
+) -> ApiResult<serde_json::Value> {
+    authorize(&caller, Permission::Remove)?;
+    let _lock = begin_change(&state, "removing packages")?;
     let mut brain = state.brain.lock().await;
-    match brain.remove(&payload.package, payload.box_type.as_deref()).await {
-        Ok(_) => Json(serde_json::json!({ "status": "success", "message": format!("Removed {}", payload.package) })),
-        Err(e) => Json(serde_json::json!({ "status": "error", "message": e.to_string() })),
+    brain
+        .remove(&payload.package, payload.box_type.as_deref())
+        .await?;
+    Ok(done(format!("Removed {}", payload.package)))
+}
+
+async fn get_history(
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult<Vec<InstallRecord>> {
+    authorize(&caller, Permission::Read)?;
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    let history = if state.mock {
+        mock::backend().history(usize::try_from(limit).unwrap_or(0))
+    } else {
+        Database::new()
+            .await?
+            .get_install_history(Some(limit))
+            .await?
+    };
+    Ok(Json(history))
+}
+
+async fn list_snapshots(
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+) -> ApiResult<Vec<Snapshot>> {
+    authorize(&caller, Permission::Read)?;
+    let snapshots = if state.mock {
+        mock::backend().snapshots()
+    } else {
+        SnapshotManager::new().await?.list_snapshots().await?
+    };
+    Ok(Json(snapshots))
+}
+
+async fn create_snapshot(
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+    payload: Option<Json<SnapshotPayload>>,
+) -> ApiResult<serde_json::Value> {
+    authorize(&caller, Permission::CreateSnapshot)?;
+    config::ensure_writable("creating snapshots")
+        .map_err(|e| ApiError::new(StatusCode::FORBIDDEN, e.to_string()))?;
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    let name = payload
+        .name
+        .unwrap_or_else(|| format!("snapshot-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S")));
+
+    let id = if state.mock {
+        mock::backend().create_snapshot(&name, payload.description.as_deref())
+    } else {
+        SnapshotManager::new()
+            .await?
+            .create_snapshot(&name, payload.description.as_deref())
+            .await?
+    };
+    Ok(Json(
+        serde_json::json!({ "status": "success", "id": id, "name": name }),
+    ))
+}
+
+async fn revert_snapshot(
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> ApiResult<serde_json::Value> {
+    authorize(&caller, Permission::RevertSnapshot)?;
+    let _lock = begin_change(&state, "reverting to a snapshot")?;
+    if state.mock {
+        mock::backend()
+            .revert(&id)
+            .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.to_string()))?;
+    } else {
+        SnapshotManager::new()
+            .await?
+            .revert_to_snapshot(&id)
+            .await?;
+    }
+    Ok(done(format!("Reverted to snapshot {}", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_comparison() {
+        assert!(token_matches("abc123", "abc123"));
+        assert!(!token_matches("abc123", "abc124"));
+        assert!(!token_matches("abc123", "abc12"));
+        assert!(!token_matches("abc123", ""));
+    }
+
+    #[tokio::test]
+    async fn test_api_requires_token() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/api", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, router(AppState::new("secret", true)))
+                .await
+                .unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        let anonymous = client
+            .get(format!("{}/system/info", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(anonymous.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let wrong = client
+            .get(format!("{}/history", base))
+            .bearer_auth("guess")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(wrong.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let info: serde_json::Value = client
+            .get(format!("{}/system/info", base))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(info["os"], std::env::consts::OS);
+
+        let missing = client
+            .post(format!("{}/snapshots/no-such-snapshot/revert", base))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let health: serde_json::Value = client
+            .get(base.replace("/api", "/health"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(health["healthy"], true);
+    }
+
+    #[tokio::test]
+    async fn test_api_roles() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = UserStore::at(dir.path().join("api-users.yaml"));
+        let viewer = users.add("viewer", authorization::Role::Viewer).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/api", listener.local_addr().unwrap());
+        let state = AppState::new("secret", true).with_users(users);
+        tokio::spawn(async move {
+            axum::serve(listener, router(state)).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        let search = client
+            .get(format!("{}/packages/search?q=htop", base))
+            .bearer_auth(&viewer)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(search.status(), reqwest::StatusCode::OK);
+
+        let install = client
+            .post(format!("{}/packages/install", base))
+            .bearer_auth(&viewer)
+            .json(&serde_json::json!({ "package": "htop" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(install.status(), reqwest::StatusCode::FORBIDDEN);
+        let body: serde_json::Value = install.json().await.unwrap();
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .contains("may not install packages"));
     }
 }
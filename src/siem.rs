@@ -0,0 +1,285 @@
+//! Audit entries in the formats SIEMs ingest: ArcSight CEF, QRadar LEEF and
+//! JSON lines. Every format carries the same fields: who acted, the host and
+//! address the entry comes from, a 0-10 severity and the outcome.
+
+use crate::audit::{self, AuditEntry, AuditSeverity};
+use chrono::SecondsFormat;
+use serde_json::json;
+use std::net::{IpAddr, UdpSocket};
+
+const VENDOR: &str = "omni";
+const PRODUCT: &str = "omni";
+
+/// Output formats of `omni audit export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// ArcSight Common Event Format
+    Cef,
+    /// IBM QRadar Log Event Extended Format 1.0
+    Leef,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// The machine audit entries were recorded on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub host: String,
+    pub ip: Option<IpAddr>,
+}
+
+impl Device {
+    pub fn local() -> Self {
+        Self {
+            host: audit::host_name(),
+            ip: local_ip(),
+        }
+    }
+}
+
+/// Address of the interface that routes to the outside; connecting a UDP
+/// socket only picks the route, nothing is sent
+fn local_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    socket
+        .local_addr()
+        .ok()
+        .map(|addr| addr.ip())
+        .filter(|ip| !ip.is_unspecified())
+}
+
+/// Severity on the 0-10 scale CEF and LEEF share
+pub fn severity_level(severity: AuditSeverity) -> u8 {
+    match severity {
+        AuditSeverity::Info => 3,
+        AuditSeverity::Warning => 6,
+        AuditSeverity::Critical => 9,
+    }
+}
+
+/// `entry` as one line of `format`, without the trailing newline
+pub fn format_entry(format: ExportFormat, entry: &AuditEntry, device: &Device) -> String {
+    match format {
+        ExportFormat::Cef => cef(entry, device),
+        ExportFormat::Leef => leef(entry, device),
+        ExportFormat::Jsonl => jsonl(entry, device),
+    }
+}
+
+/// e.g. "Package install" for package_install
+fn event_name(entry: &AuditEntry) -> String {
+    let name = entry.event_type.as_str().replace('_', " ");
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+fn outcome(entry: &AuditEntry) -> &'static str {
+    if entry.success {
+        "success"
+    } else {
+        "failure"
+    }
+}
+
+fn cef(entry: &AuditEntry, device: &Device) -> String {
+    let mut extensions = vec![
+        ("rt", entry.timestamp.timestamp_millis().to_string()),
+        ("suser", entry.user.clone()),
+        ("dvchost", device.host.clone()),
+    ];
+    if let Some(ip) = device.ip {
+        extensions.push(("dvc", ip.to_string()));
+    }
+    extensions.push(("outcome", outcome(entry).to_string()));
+    extensions.push(("msg", entry.details.clone()));
+    extensions.push(("externalId", entry.id.clone()));
+    if let Some(package) = &entry.package_name {
+        extensions.push(("cs1Label", "package".to_string()));
+        extensions.push(("cs1", package.clone()));
+    }
+    if let Some(box_type) = &entry.box_type {
+        extensions.push(("cs2Label", "box".to_string()));
+        extensions.push(("cs2", box_type.clone()));
+    }
+    if let Some(trace_id) = &entry.trace_id {
+        extensions.push(("cs3Label", "traceId".to_string()));
+        extensions.push(("cs3", trace_id.clone()));
+    }
+    if let Some(hash) = &entry.hash {
+        extensions.push(("cs4Label", "chainHash".to_string()));
+        extensions.push(("cs4", hash.clone()));
+    }
+
+    let extensions: Vec<String> = extensions
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, cef_value(value)))
+        .collect();
+    format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+        VENDOR,
+        PRODUCT,
+        cef_header(env!("CARGO_PKG_VERSION")),
+        cef_header(entry.event_type.as_str()),
+        cef_header(&event_name(entry)),
+        severity_level(entry.severity),
+        extensions.join(" ")
+    )
+}
+
+fn cef_header(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\r', '\n'], " ")
+}
+
+fn cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn leef(entry: &AuditEntry, device: &Device) -> String {
+    let mut attributes = vec![
+        ("cat", entry.event_type.as_str().to_string()),
+        (
+            "devTime",
+            entry.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        ),
+        ("devTimeFormat", "yyyy-MM-dd'T'HH:mm:ss.SSSX".to_string()),
+        ("sev", severity_level(entry.severity).to_string()),
+        ("usrName", entry.user.clone()),
+        ("identHostName", device.host.clone()),
+    ];
+    if let Some(ip) = device.ip {
+        attributes.push(("src", ip.to_string()));
+    }
+    attributes.push(("outcome", outcome(entry).to_string()));
+    attributes.push(("msg", entry.details.clone()));
+    attributes.push(("externalId", entry.id.clone()));
+    if let Some(package) = &entry.package_name {
+        attributes.push(("package", package.clone()));
+    }
+    if let Some(box_type) = &entry.box_type {
+        attributes.push(("box", box_type.clone()));
+    }
+    if let Some(trace_id) = &entry.trace_id {
+        attributes.push(("traceId", trace_id.clone()));
+    }
+    if let Some(hash) = &entry.hash {
+        attributes.push(("chainHash", hash.clone()));
+    }
+
+    let attributes: Vec<String> = attributes
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value.replace(['\t', '\r', '\n'], " ")))
+        .collect();
+    format!(
+        "LEEF:1.0|{}|{}|{}|{}|{}",
+        VENDOR,
+        PRODUCT,
+        leef_header(env!("CARGO_PKG_VERSION")),
+        leef_header(entry.event_type.as_str()),
+        attributes.join("\t")
+    )
+}
+
+/// LEEF 1.0 has no escape for its delimiter
+fn leef_header(value: &str) -> String {
+    value.replace(['|', '\t', '\r', '\n'], " ")
+}
+
+fn jsonl(entry: &AuditEntry, device: &Device) -> String {
+    let mut value = json!(entry);
+    if let Some(fields) = value.as_object_mut() {
+        fields.insert("host".to_string(), json!(device.host));
+        fields.insert("ip".to_string(), json!(device.ip));
+        fields.insert(
+            "severity_level".to_string(),
+            json!(severity_level(entry.severity)),
+        );
+    }
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditEventType;
+    use chrono::TimeZone;
+
+    fn entry() -> AuditEntry {
+        let mut entry = AuditEntry::new(
+            AuditEventType::PackageInstall,
+            AuditSeverity::Warning,
+            "Installed nginx=1.24|a\\b\nsecond line",
+        )
+        .with_package("nginx", Some("apt"))
+        .with_success(false);
+        entry.id = "0b7d".to_string();
+        entry.user = "alice".to_string();
+        entry.timestamp = chrono::Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        entry
+    }
+
+    fn device() -> Device {
+        Device {
+            host: "web01".to_string(),
+            ip: Some("10.0.0.5".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_cef() {
+        let line = format_entry(ExportFormat::Cef, &entry(), &device());
+        assert!(line.starts_with(&format!(
+            "CEF:0|omni|omni|{}|package_install|Package install|6|rt=1714564800000 ",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(line.contains(" suser=alice dvchost=web01 dvc=10.0.0.5 outcome=failure "));
+        assert!(line.contains(" msg=Installed nginx\\=1.24|a\\\\b\\nsecond line "));
+        assert!(line.contains(" cs1Label=package cs1=nginx cs2Label=box cs2=apt"));
+        assert!(!line.contains('\n'));
+    }
+
+    #[test]
+    fn test_leef() {
+        let line = format_entry(ExportFormat::Leef, &entry(), &device());
+        let (header, attributes) = line.rsplit_once("package_install|").unwrap();
+        assert_eq!(
+            header,
+            format!("LEEF:1.0|omni|omni|{}|", env!("CARGO_PKG_VERSION"))
+        );
+
+        let attributes: Vec<&str> = attributes.split('\t').collect();
+        assert!(attributes.contains(&"devTime=2024-05-01T12:00:00.000Z"));
+        assert!(attributes.contains(&"sev=6"));
+        assert!(attributes.contains(&"usrName=alice"));
+        assert!(attributes.contains(&"src=10.0.0.5"));
+        assert!(attributes.contains(&"msg=Installed nginx=1.24|a\\b second line"));
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let line = format_entry(ExportFormat::Jsonl, &entry(), &device());
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["host"], "web01");
+        assert_eq!(value["ip"], "10.0.0.5");
+        assert_eq!(value["severity_level"], 6);
+        assert_eq!(value["user"], "alice");
+
+        let no_ip = Device {
+            ip: None,
+            ..device()
+        };
+        let line = format_entry(ExportFormat::Jsonl, &entry(), &no_ip);
+        assert!(line.contains("\"ip\":null"));
+    }
+}
@@ -4,22 +4,36 @@ use crate::boxes::dnf::DnfBox;
 use crate::boxes::flatpak::FlatpakBox;
 use crate::boxes::pacman::PacmanBox;
 use crate::boxes::snap::SnapBox;
+use crate::config::{OmniConfig, SnapshotRetention, SnapshotsConfig};
 use crate::database::{Database, InstallRecord, InstallStatus, Snapshot};
 use crate::distro::{self, PackageManager};
+use crate::snapshot_backends::{self, Revert, SnapshotBackend};
 use anyhow::Result;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use std::collections::HashMap;
-use tracing::{error, info, warn};
+use tracing::{info, warn};
 use uuid::Uuid;
 
+/// Start of the names `auto_snapshot` gives; only these snapshots are pruned
+pub const AUTO_PREFIX: &str = "auto-";
+
 pub struct SnapshotManager {
     db: Database,
+    config: SnapshotsConfig,
+    /// Takes a filesystem snapshot alongside each package-list snapshot
+    backend: Option<Box<dyn SnapshotBackend>>,
 }
 
 impl SnapshotManager {
     pub async fn new() -> Result<Self> {
         let db = Database::new().await?;
-        Ok(Self { db })
+        let config = OmniConfig::load().unwrap_or_default().snapshots;
+        let backend = snapshot_backends::from_config(&config);
+        Ok(Self {
+            db,
+            config,
+            backend,
+        })
     }
 
     pub async fn create_snapshot(&self, name: &str, description: Option<&str>) -> Result<String> {
@@ -27,6 +41,23 @@ impl SnapshotManager {
 
         let snapshot_id = self.db.create_snapshot(name, description).await?;
 
+        if let Some(backend) = &self.backend {
+            match backend.create(name) {
+                Ok(reference) => {
+                    self.db
+                        .set_snapshot_backend(&snapshot_id, backend.name(), &reference)
+                        .await?;
+                    info!("Took a {} snapshot at {}", backend.name(), reference);
+                }
+                Err(e) => warn!(
+                    "No {} snapshot for '{}', only the package list: {}",
+                    backend.name(),
+                    name,
+                    e
+                ),
+            }
+        }
+
         info!(
             "✅ Successfully created snapshot '{}' with ID: {}",
             name, snapshot_id
@@ -43,20 +74,73 @@ impl SnapshotManager {
 
         // First check if snapshot exists
         let snapshots = self.db.list_snapshots().await?;
-        let snapshot_exists = snapshots.iter().any(|s| s.id == snapshot_id);
+        let snapshot = snapshots
+            .iter()
+            .find(|s| s.id == snapshot_id)
+            .ok_or_else(|| anyhow::anyhow!("Snapshot not found: {}", snapshot_id))?;
+
+        self.delete_listed(snapshot).await?;
 
-        if !snapshot_exists {
-            return Err(anyhow::anyhow!("Snapshot not found: {}", snapshot_id));
+        info!("✅ Successfully deleted snapshot: {}", snapshot_id);
+        Ok(())
+    }
+
+    async fn delete_listed(&self, snapshot: &Snapshot) -> Result<()> {
+        if let (Some(name), Some(reference)) = (&snapshot.backend, &snapshot.backend_ref) {
+            match snapshot_backends::by_name(name, &self.config) {
+                Some(backend) => backend.delete(reference)?,
+                None => warn!("Leaving the {} snapshot at {}", name, reference),
+            }
         }
 
         // Delete from database
-        self.db.delete_snapshot(snapshot_id).await?;
+        self.db.delete_snapshot(&snapshot.id).await
+    }
 
-        info!("✅ Successfully deleted snapshot: {}", snapshot_id);
-        Ok(())
+    /// Retention from the config, applied after each automatic snapshot
+    pub fn retention(&self) -> &SnapshotRetention {
+        &self.config.retention
+    }
+
+    /// Delete the automatic snapshots `retention` does not keep, returning
+    /// them oldest first; with `dry_run`, only return them
+    pub async fn prune(
+        &self,
+        retention: &SnapshotRetention,
+        dry_run: bool,
+    ) -> Result<Vec<Snapshot>> {
+        let snapshots = self.db.list_snapshots().await?;
+
+        let mut sizes = HashMap::new();
+        if retention.max_disk_usage_mb.is_some() {
+            for snapshot in snapshots.iter().filter(|s| s.name.starts_with(AUTO_PREFIX)) {
+                if let Some(bytes) = self.disk_usage(snapshot) {
+                    sizes.insert(snapshot.id.clone(), bytes);
+                }
+            }
+        }
+
+        let pruned: Vec<Snapshot> = prune_plan(&snapshots, &sizes, retention, Utc::now())
+            .into_iter()
+            .cloned()
+            .collect();
+        if !dry_run {
+            for snapshot in &pruned {
+                self.delete_listed(snapshot).await?;
+                info!("Pruned snapshot '{}'", snapshot.name);
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Bytes the filesystem snapshot behind `snapshot` holds, if known
+    pub fn disk_usage(&self, snapshot: &Snapshot) -> Option<u64> {
+        let (name, reference) = (snapshot.backend.as_ref()?, snapshot.backend_ref.as_ref()?);
+        snapshot_backends::by_name(name, &self.config)?.disk_usage(reference)
     }
 
     pub async fn revert_to_snapshot(&self, snapshot_id: &str) -> Result<()> {
+        crate::config::ensure_writable("reverting a snapshot")?;
         info!("Reverting to snapshot: {}", snapshot_id);
 
         let snapshots = self.db.list_snapshots().await?;
@@ -65,6 +149,29 @@ impl SnapshotManager {
             .find(|s| s.id == snapshot_id)
             .ok_or_else(|| anyhow::anyhow!("Snapshot not found: {}", snapshot_id))?;
 
+        if let (Some(name), Some(reference)) =
+            (&target_snapshot.backend, &target_snapshot.backend_ref)
+        {
+            match snapshot_backends::by_name(name, &self.config) {
+                Some(backend) => match backend.revert(reference)? {
+                    Revert::Applied => info!("Restored the {} snapshot", name),
+                    Revert::AfterReboot(instructions) => {
+                        // Package changes now would land on the system being replaced
+                        println!("{}", instructions);
+                        info!(
+                            "✅ Snapshot '{}' is restored from the next boot",
+                            target_snapshot.name
+                        );
+                        return Ok(());
+                    }
+                },
+                None => warn!(
+                    "omni has no {} backend; reverting the package list only",
+                    name
+                ),
+            }
+        }
+
         let current_packages = self.db.get_installed_packages().await?;
         let target_packages = &target_snapshot.packages;
 
@@ -200,6 +307,9 @@ impl SnapshotManager {
             install_path: package.install_path.clone(),
             installed_at: Utc::now(),
             status: InstallStatus::Success,
+            repository: package.repository.clone(),
+            mirror_url: package.mirror_url.clone(),
+            channel: package.channel.clone(),
             metadata: package.metadata.clone(),
         };
 
@@ -271,6 +381,9 @@ impl SnapshotManager {
             install_path: package.install_path.clone(),
             installed_at: Utc::now(),
             status: InstallStatus::Removed,
+            repository: package.repository.clone(),
+            mirror_url: package.mirror_url.clone(),
+            channel: package.channel.clone(),
             metadata: package.metadata.clone(),
         };
 
@@ -281,7 +394,8 @@ impl SnapshotManager {
 
     pub async fn auto_snapshot(&self, operation: &str, package: &str) -> Result<Option<String>> {
         let snapshot_name = format!(
-            "auto-{}-{}-{}",
+            "{}{}-{}-{}",
+            AUTO_PREFIX,
             operation,
             package,
             Utc::now().format("%Y%m%d-%H%M%S")
@@ -297,6 +411,13 @@ impl SnapshotManager {
         {
             Ok(snapshot_id) => {
                 info!("Created automatic snapshot: {}", snapshot_name);
+                match self.prune(&self.config.retention, false).await {
+                    Ok(pruned) if !pruned.is_empty() => {
+                        info!("Pruned {} old automatic snapshots", pruned.len())
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to prune old snapshots: {}", e),
+                }
                 Ok(Some(snapshot_id))
             }
             Err(e) => {
@@ -306,3 +427,139 @@ impl SnapshotManager {
         }
     }
 }
+
+/// The automatic snapshots `retention` drops, oldest first. The newest one is
+/// always kept; past that, a snapshot is dropped when it is beyond `keep`,
+/// older than `max_age_days`, or when it and the newer ones kept would take
+/// more than `max_disk_usage_mb` (`sizes` are in bytes, by snapshot id).
+pub fn prune_plan<'a>(
+    snapshots: &'a [Snapshot],
+    sizes: &HashMap<String, u64>,
+    retention: &SnapshotRetention,
+    now: DateTime<Utc>,
+) -> Vec<&'a Snapshot> {
+    let mut auto: Vec<&Snapshot> = snapshots
+        .iter()
+        .filter(|s| s.name.starts_with(AUTO_PREFIX))
+        .collect();
+    auto.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+    let max_age = retention
+        .max_age_days
+        .map(|days| Duration::days(i64::from(days)));
+    let max_bytes = retention
+        .max_disk_usage_mb
+        .map(|mb| mb.saturating_mul(1024 * 1024));
+
+    let mut used = 0u64;
+    let mut over_disk = false;
+    let mut pruned = Vec::new();
+    for (index, snapshot) in auto.into_iter().enumerate() {
+        let size = sizes.get(&snapshot.id).copied().unwrap_or(0);
+        if index == 0 {
+            used = size;
+            continue;
+        }
+        over_disk |= max_bytes.is_some_and(|max| used + size > max);
+        let drop = over_disk
+            || retention.keep.is_some_and(|keep| index >= keep)
+            || max_age.is_some_and(|age| now - snapshot.created_at > age);
+        if drop {
+            pruned.push(snapshot);
+        } else {
+            used += size;
+        }
+    }
+    pruned.reverse();
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(name: &str, days_ago: i64) -> Snapshot {
+        Snapshot {
+            id: name.to_string(),
+            name: name.to_string(),
+            description: None,
+            created_at: Utc::now() - Duration::days(days_ago),
+            packages: Vec::new(),
+            backend: None,
+            backend_ref: None,
+        }
+    }
+
+    fn names(pruned: Vec<&Snapshot>) -> Vec<&str> {
+        pruned.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_prune_plan() {
+        let snapshots = vec![
+            snapshot("auto-install-a", 1),
+            snapshot("before-upgrade", 90),
+            snapshot("auto-remove-b", 10),
+            snapshot("auto-install-c", 40),
+            snapshot("auto-install-d", 60),
+        ];
+        let none = HashMap::new();
+        let retention = |keep, max_age_days, max_disk_usage_mb| SnapshotRetention {
+            keep,
+            max_age_days,
+            max_disk_usage_mb,
+        };
+
+        // Snapshots taken by hand are never pruned
+        assert_eq!(
+            names(prune_plan(
+                &snapshots,
+                &none,
+                &retention(Some(2), None, None),
+                Utc::now()
+            )),
+            vec!["auto-install-d", "auto-install-c"]
+        );
+        assert_eq!(
+            names(prune_plan(
+                &snapshots,
+                &none,
+                &retention(None, Some(30), None),
+                Utc::now()
+            )),
+            vec!["auto-install-d", "auto-install-c"]
+        );
+        assert!(prune_plan(&snapshots, &none, &retention(None, None, None), Utc::now()).is_empty());
+
+        // Newest first until the limit, then everything older
+        let mb = 1024 * 1024;
+        let sizes: HashMap<String, u64> = [
+            ("auto-install-a", 400 * mb),
+            ("auto-remove-b", 500 * mb),
+            ("auto-install-c", 200 * mb),
+            ("auto-install-d", 10 * mb),
+        ]
+        .into_iter()
+        .map(|(id, size)| (id.to_string(), size))
+        .collect();
+        assert_eq!(
+            names(prune_plan(
+                &snapshots,
+                &sizes,
+                &retention(None, None, Some(1000)),
+                Utc::now()
+            )),
+            vec!["auto-install-d", "auto-install-c"]
+        );
+        // The newest one stays even when it alone is over
+        assert_eq!(
+            names(prune_plan(
+                &snapshots,
+                &sizes,
+                &retention(None, None, Some(100)),
+                Utc::now()
+            )),
+            vec!["auto-install-d", "auto-install-c", "auto-remove-b"]
+        );
+    }
+}
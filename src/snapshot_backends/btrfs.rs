@@ -0,0 +1,279 @@
+//! Read-only Btrfs subvolume snapshots of `/` and `/home` (by default).
+//! Reverting makes a writable copy of each snapshot and sets the root one as
+//! the default subvolume, so the system boots into it.
+
+use super::{run, Revert, SnapshotBackend};
+use crate::config::BtrfsSnapshotConfig;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Suffix of the writable copies a revert makes
+const RESTORED_SUFFIX: &str = "-restored";
+
+/// Snapshots the configured subvolumes into `snapshot_dir`
+pub struct Btrfs {
+    config: BtrfsSnapshotConfig,
+}
+
+impl Btrfs {
+    pub fn new(config: BtrfsSnapshotConfig) -> Self {
+        Self { config }
+    }
+
+    fn is_subvolume(path: &Path) -> bool {
+        run("btrfs", &["subvolume", "show", &path.to_string_lossy()]).is_ok()
+    }
+
+    /// Snapshots under a snapshot directory, as (subvolume mount point, path)
+    fn snapshots_in(&self, dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+        self.config
+            .subvolumes
+            .iter()
+            .map(|subvolume| (subvolume.clone(), dir.join(subvolume_name(subvolume))))
+            .filter(|(_, snapshot)| snapshot.exists())
+            .collect()
+    }
+}
+
+impl SnapshotBackend for Btrfs {
+    fn name(&self) -> &'static str {
+        "btrfs"
+    }
+
+    fn create(&self, label: &str) -> Result<String> {
+        let dir = self
+            .config
+            .snapshot_dir
+            .join(Utc::now().format("%Y%m%d-%H%M%S-%f").to_string());
+        run("mkdir", &["-p", &dir.to_string_lossy()])?;
+
+        let mut taken = 0;
+        for subvolume in &self.config.subvolumes {
+            if !Self::is_subvolume(subvolume) {
+                warn!(
+                    "{} is not a Btrfs subvolume; not taking a snapshot of it",
+                    subvolume.display()
+                );
+                continue;
+            }
+            let target = dir.join(subvolume_name(subvolume));
+            run(
+                "btrfs",
+                &[
+                    "subvolume",
+                    "snapshot",
+                    "-r",
+                    &subvolume.to_string_lossy(),
+                    &target.to_string_lossy(),
+                ],
+            )?;
+            taken += 1;
+        }
+
+        if taken == 0 {
+            let _ = run("rmdir", &[&dir.to_string_lossy()]);
+            return Err(anyhow!(
+                "None of {} is a Btrfs subvolume (snapshots.btrfs.subvolumes)",
+                self.config
+                    .subvolumes
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        info!(
+            "Took {} Btrfs snapshots for '{}' in {}",
+            taken,
+            label,
+            dir.display()
+        );
+        Ok(dir.display().to_string())
+    }
+
+    fn revert(&self, reference: &str) -> Result<Revert> {
+        let dir = Path::new(reference);
+        let snapshots = self.snapshots_in(dir);
+        if snapshots.is_empty() {
+            return Err(anyhow!("No Btrfs snapshots in {}", reference));
+        }
+
+        let fstab = std::fs::read_to_string("/etc/fstab").unwrap_or_default();
+        if snapshots
+            .iter()
+            .any(|(subvolume, _)| subvolume == Path::new("/"))
+            && root_subvolume_pinned(&fstab)
+        {
+            return Err(anyhow!(
+                "/etc/fstab mounts / with subvol= or subvolid=, so a new default subvolume \
+                 would be ignored; remove that option or restore {} by hand",
+                reference
+            ));
+        }
+
+        let mut steps = Vec::new();
+        for (subvolume, snapshot) in &snapshots {
+            let restored = PathBuf::from(format!("{}{}", snapshot.display(), RESTORED_SUFFIX));
+            if !restored.exists() {
+                run(
+                    "btrfs",
+                    &[
+                        "subvolume",
+                        "snapshot",
+                        &snapshot.to_string_lossy(),
+                        &restored.to_string_lossy(),
+                    ],
+                )?;
+            }
+
+            if subvolume == Path::new("/") {
+                let shown = run("btrfs", &["subvolume", "show", &restored.to_string_lossy()])?;
+                let id = subvolume_id(&shown).ok_or_else(|| {
+                    anyhow!("Cannot read the subvolume id of {}", restored.display())
+                })?;
+                run("btrfs", &["subvolume", "set-default", &id.to_string(), "/"])?;
+                steps.push(format!(
+                    "/ boots from {} (subvolume {})",
+                    restored.display(),
+                    id
+                ));
+            } else {
+                steps.push(format!(
+                    "mount {} at {} to use the restored copy",
+                    restored.display(),
+                    subvolume.display()
+                ));
+            }
+        }
+
+        Ok(Revert::AfterReboot(format!(
+            "Reboot to finish the revert:\n  {}",
+            steps.join("\n  ")
+        )))
+    }
+
+    fn delete(&self, reference: &str) -> Result<()> {
+        let dir = Path::new(reference);
+        for (_, snapshot) in self.snapshots_in(dir) {
+            run(
+                "btrfs",
+                &["subvolume", "delete", &snapshot.to_string_lossy()],
+            )?;
+        }
+        // Writable copies of a revert are in use and stay
+        if std::fs::read_dir(dir).map_or(true, |mut entries| entries.next().is_none()) {
+            let _ = run("rmdir", &[&dir.to_string_lossy()]);
+        }
+        Ok(())
+    }
+
+    fn disk_usage(&self, reference: &str) -> Option<u64> {
+        self.snapshots_in(Path::new(reference))
+            .iter()
+            .map(|(_, snapshot)| {
+                run(
+                    "btrfs",
+                    &[
+                        "filesystem",
+                        "du",
+                        "-s",
+                        "--raw",
+                        &snapshot.to_string_lossy(),
+                    ],
+                )
+                .ok()
+                .and_then(|shown| exclusive_bytes(&shown))
+            })
+            .sum()
+    }
+}
+
+/// The Exclusive column of `btrfs filesystem du -s --raw`
+fn exclusive_bytes(shown: &str) -> Option<u64> {
+    shown
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// Name of a subvolume's snapshot: `root` for `/`, `var-lib` for `/var/lib`
+pub fn subvolume_name(mount_point: &Path) -> String {
+    let name = mount_point
+        .to_string_lossy()
+        .trim_matches('/')
+        .replace('/', "-");
+    if name.is_empty() {
+        "root".to_string()
+    } else {
+        name
+    }
+}
+
+/// The `Subvolume ID:` line of `btrfs subvolume show`
+fn subvolume_id(shown: &str) -> Option<u64> {
+    shown.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("Subvolume ID:")
+            .and_then(|id| id.trim().parse().ok())
+    })
+}
+
+/// Whether fstab mounts `/` from a fixed subvolume, which makes
+/// `btrfs subvolume set-default` have no effect
+pub fn root_subvolume_pinned(fstab: &str) -> bool {
+    fstab
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            (fields.len() >= 4 && fields[1] == "/").then(|| fields[3].to_string())
+        })
+        .any(|options| {
+            options
+                .split(',')
+                .any(|o| o.starts_with("subvol=") || o.starts_with("subvolid="))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subvolume_name() {
+        assert_eq!(subvolume_name(Path::new("/")), "root");
+        assert_eq!(subvolume_name(Path::new("/home")), "home");
+        assert_eq!(subvolume_name(Path::new("/var/lib/")), "var-lib");
+    }
+
+    #[test]
+    fn test_subvolume_id() {
+        let shown = "@snapshots/1/snapshot\n\tName: \t\t\tsnapshot\n\tSubvolume ID: \t\t267\n\tGeneration: \t\t1042\n";
+        assert_eq!(subvolume_id(shown), Some(267));
+        assert_eq!(subvolume_id("Name: x"), None);
+    }
+
+    #[test]
+    fn test_exclusive_bytes() {
+        let shown = "     Total   Exclusive  Set shared  Filename\n\
+                     8589934592    52428800  8537505792  /.snapshots/omni/x/root\n";
+        assert_eq!(exclusive_bytes(shown), Some(52428800));
+        assert_eq!(exclusive_bytes("ERROR: not a btrfs filesystem"), None);
+    }
+
+    #[test]
+    fn test_root_subvolume_pinned() {
+        assert!(root_subvolume_pinned(
+            "UUID=abcd / btrfs defaults,subvol=@ 0 0\nUUID=abcd /home btrfs subvol=@home 0 0\n"
+        ));
+        assert!(!root_subvolume_pinned(
+            "UUID=abcd / btrfs defaults,compress=zstd 0 0\n# UUID=abcd / btrfs subvol=@ 0 0\nUUID=abcd /home btrfs subvol=@home 0 0\n"
+        ));
+    }
+}
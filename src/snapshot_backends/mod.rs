@@ -0,0 +1,168 @@
+//! Filesystem snapshots behind omni's package-list snapshots. A backend takes
+//! a snapshot when `SnapshotManager` does, and reverting restores the
+//! filesystem instead of replaying package installs and removals.
+
+pub mod btrfs;
+pub mod snapper;
+pub mod timeshift;
+pub mod zfs;
+
+use crate::config::{SnapshotBackendKind, SnapshotsConfig};
+use crate::privilege_manager::PrivilegeManager;
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+/// What a revert did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Revert {
+    /// The filesystem is back as it was
+    Applied,
+    /// The restored state is used from the next boot; the text says what to do
+    AfterReboot(String),
+}
+
+/// A way of taking and restoring filesystem snapshots
+pub trait SnapshotBackend: Send + Sync {
+    /// Stored with each snapshot, e.g. `btrfs`
+    fn name(&self) -> &'static str;
+
+    /// Take a snapshot; returns the reference that finds it again
+    fn create(&self, label: &str) -> Result<String>;
+
+    /// Bring the filesystem back to the snapshot `reference` names
+    fn revert(&self, reference: &str) -> Result<Revert>;
+
+    /// Remove the snapshot `reference` names
+    fn delete(&self, reference: &str) -> Result<()>;
+
+    /// Bytes only the snapshot holds, if the backend can tell
+    fn disk_usage(&self, _reference: &str) -> Option<u64> {
+        None
+    }
+}
+
+/// The backend `config` asks for. With `auto`, snapper or Timeshift when the
+/// system already takes snapshots with them, so omni's go in the same place;
+/// otherwise the one the root filesystem supports, if any.
+pub fn from_config(config: &SnapshotsConfig) -> Option<Box<dyn SnapshotBackend>> {
+    let kind = match config.backend {
+        SnapshotBackendKind::Auto if snapper::is_set_up(&config.snapper) => {
+            SnapshotBackendKind::Snapper
+        }
+        SnapshotBackendKind::Auto if timeshift::is_set_up() => SnapshotBackendKind::Timeshift,
+        SnapshotBackendKind::Auto => {
+            let fs_type = std::fs::read_to_string("/proc/mounts")
+                .ok()
+                .and_then(|mounts| filesystem_type(&mounts, Path::new("/")))?;
+            debug!("Root filesystem is {}", fs_type);
+            match fs_type.as_str() {
+                "btrfs" => SnapshotBackendKind::Btrfs,
+                "zfs" => SnapshotBackendKind::Zfs,
+                _ => return None,
+            }
+        }
+        kind => kind,
+    };
+
+    match kind {
+        SnapshotBackendKind::Btrfs => Some(Box::new(btrfs::Btrfs::new(config.btrfs.clone()))),
+        SnapshotBackendKind::Zfs => Some(Box::new(zfs::Zfs::new(config.zfs.clone()))),
+        SnapshotBackendKind::Snapper => {
+            Some(Box::new(snapper::Snapper::new(config.snapper.clone())))
+        }
+        SnapshotBackendKind::Timeshift => Some(Box::new(timeshift::Timeshift)),
+        SnapshotBackendKind::Auto | SnapshotBackendKind::None => None,
+    }
+}
+
+/// The backend that took a stored snapshot, if it is one omni knows
+pub fn by_name(name: &str, config: &SnapshotsConfig) -> Option<Box<dyn SnapshotBackend>> {
+    match name {
+        "btrfs" => Some(Box::new(btrfs::Btrfs::new(config.btrfs.clone()))),
+        "zfs" => Some(Box::new(zfs::Zfs::new(config.zfs.clone()))),
+        "snapper" => Some(Box::new(snapper::Snapper::new(config.snapper.clone()))),
+        "timeshift" => Some(Box::new(timeshift::Timeshift)),
+        _ => None,
+    }
+}
+
+/// Filesystem type of the mount `path` is on, from `/proc/mounts` contents
+pub fn filesystem_type(mounts: &str, path: &Path) -> Option<String> {
+    mount_entry(mounts, path).map(|(_, fs_type)| fs_type)
+}
+
+/// Device of the mount `path` is on, e.g. the ZFS dataset
+pub fn mount_device(mounts: &str, path: &Path) -> Option<String> {
+    mount_entry(mounts, path).map(|(device, _)| device)
+}
+
+fn mount_entry(mounts: &str, path: &Path) -> Option<(String, String)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = unescape_mount_path(fields.next()?);
+            let mount_point = unescape_mount_path(fields.next()?);
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point)
+                .then(|| (mount_point.len(), device, fs_type.to_string()))
+        })
+        // The last, longest match is the mount in effect
+        .max_by_key(|(len, _, _)| *len)
+        .map(|(_, device, fs_type)| (device, fs_type))
+}
+
+/// `/proc/mounts` writes spaces and tabs in paths as octal escapes
+fn unescape_mount_path(path: &str) -> String {
+    path.replace("\\040", " ").replace("\\011", "\t")
+}
+
+/// Run a snapshot tool as root, returning its output
+pub(crate) fn run(command: &str, args: &[&str]) -> Result<String> {
+    debug!("Running {} {}", command, args.join(" "));
+    let output = if PrivilegeManager::is_root() {
+        Command::new(command).args(args).output()
+    } else {
+        Command::new("sudo").arg(command).args(args).output()
+    }
+    .map_err(|e| anyhow!("Cannot run {}: {}", command, e))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} {} failed: {}",
+            command,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filesystem_type() {
+        let mounts = "\
+/dev/nvme0n1p2 / btrfs rw,relatime,subvol=/@ 0 0
+proc /proc proc rw,nosuid 0 0
+/dev/nvme0n1p1 /boot/efi vfat rw 0 0
+tank/home /home zfs rw,xattr 0 0
+/dev/sdb1 /mnt/usb\\040stick ext4 rw 0 0
+";
+        let fs = |path: &str| filesystem_type(mounts, Path::new(path));
+        assert_eq!(fs("/").as_deref(), Some("btrfs"));
+        assert_eq!(fs("/usr/bin").as_deref(), Some("btrfs"));
+        assert_eq!(fs("/home/alex").as_deref(), Some("zfs"));
+        assert_eq!(fs("/boot/efi").as_deref(), Some("vfat"));
+        assert_eq!(fs("/mnt/usb stick/x").as_deref(), Some("ext4"));
+        assert_eq!(filesystem_type("", Path::new("/")), None);
+        assert_eq!(
+            mount_device(mounts, Path::new("/home/alex")).as_deref(),
+            Some("tank/home")
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! omni's snapshots as snapper snapshots, on systems that already use
+//! snapper. The stored reference is the snapper snapshot number.
+
+use super::{run, Revert, SnapshotBackend};
+use crate::config::SnapperSnapshotConfig;
+use crate::distro;
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use tracing::info;
+
+/// Takes and restores snapshots with `snapper`
+pub struct Snapper {
+    config: SnapperSnapshotConfig,
+}
+
+impl Snapper {
+    pub fn new(config: SnapperSnapshotConfig) -> Self {
+        Self { config }
+    }
+
+    fn snapper(&self, args: &[&str]) -> Result<String> {
+        let mut full = vec!["-c", self.config.config.as_str()];
+        full.extend_from_slice(args);
+        run("snapper", &full)
+    }
+}
+
+/// Whether snapper is installed and has the config omni would use
+pub fn is_set_up(config: &SnapperSnapshotConfig) -> bool {
+    distro::command_exists("snapper")
+        && Path::new("/etc/snapper/configs")
+            .join(&config.config)
+            .exists()
+}
+
+impl SnapshotBackend for Snapper {
+    fn name(&self) -> &'static str {
+        "snapper"
+    }
+
+    fn create(&self, label: &str) -> Result<String> {
+        let description = format!("omni: {}", label);
+        let output = self.snapper(&[
+            "create",
+            "--type",
+            "single",
+            "--cleanup-algorithm",
+            "number",
+            "--description",
+            &description,
+            "--print-number",
+        ])?;
+        let number = snapshot_number(&output)
+            .ok_or_else(|| anyhow!("snapper did not print a snapshot number: {}", output))?;
+        info!(
+            "Took snapper snapshot #{} in config {}",
+            number, self.config.config
+        );
+        Ok(number.to_string())
+    }
+
+    fn revert(&self, reference: &str) -> Result<Revert> {
+        let number = snapshot_number(reference)
+            .ok_or_else(|| anyhow!("'{}' is not a snapper snapshot number", reference))?;
+
+        if self.config.config == "root" {
+            // Makes a writable copy of the snapshot the default subvolume
+            self.snapper(&["rollback", &number.to_string()])?;
+            Ok(Revert::AfterReboot(format!(
+                "snapper rolled back to snapshot #{}; reboot to use it",
+                number
+            )))
+        } else {
+            self.snapper(&["undochange", &format!("{}..0", number)])?;
+            Ok(Revert::Applied)
+        }
+    }
+
+    fn delete(&self, reference: &str) -> Result<()> {
+        // snapper's own cleanup may have removed it already
+        let listed = self.snapper(&["list", "--columns", "number"])?;
+        if listed
+            .lines()
+            .any(|line| line.trim().trim_end_matches(['-', '+', '*']) == reference)
+        {
+            self.snapper(&["delete", reference])?;
+        }
+        Ok(())
+    }
+}
+
+/// The number `snapper create --print-number` prints
+fn snapshot_number(output: &str) -> Option<u32> {
+    output.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_number() {
+        assert_eq!(snapshot_number("42\n"), Some(42));
+        assert_eq!(snapshot_number("Creating snapshot failed"), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! omni's snapshots as Timeshift snapshots, on systems that already use
+//! Timeshift. The stored reference is the snapshot name, its creation time
+//! such as `2026-10-17_09-30-12`.
+
+use super::{run, Revert, SnapshotBackend};
+use crate::distro;
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use tracing::info;
+
+/// Where Timeshift keeps its settings once it has been set up
+const SETTINGS: &str = "/etc/timeshift/timeshift.json";
+
+/// Takes and restores snapshots with `timeshift`
+pub struct Timeshift;
+
+/// Whether Timeshift is installed and has a backup device set
+pub fn is_set_up() -> bool {
+    distro::command_exists("timeshift") && Path::new(SETTINGS).exists()
+}
+
+impl SnapshotBackend for Timeshift {
+    fn name(&self) -> &'static str {
+        "timeshift"
+    }
+
+    fn create(&self, label: &str) -> Result<String> {
+        let comments = format!("omni: {}", label);
+        let output = run(
+            "timeshift",
+            &[
+                "--create",
+                "--comments",
+                &comments,
+                "--tags",
+                "O",
+                "--scripted",
+            ],
+        )?;
+        let name = snapshot_name(&output)
+            .ok_or_else(|| anyhow!("timeshift did not name the snapshot it took"))?;
+        info!("Took Timeshift snapshot {}", name);
+        Ok(name)
+    }
+
+    fn revert(&self, reference: &str) -> Result<Revert> {
+        run(
+            "timeshift",
+            &["--restore", "--snapshot", reference, "--scripted", "--yes"],
+        )?;
+        Ok(Revert::AfterReboot(format!(
+            "Timeshift restored snapshot {}; reboot to use it if it has not rebooted already",
+            reference
+        )))
+    }
+
+    fn delete(&self, reference: &str) -> Result<()> {
+        run(
+            "timeshift",
+            &["--delete", "--snapshot", reference, "--scripted"],
+        )?;
+        Ok(())
+    }
+}
+
+/// The snapshot name in `timeshift --create` output, from the
+/// `Tagged snapshot '<name>': ondemand` line
+fn snapshot_name(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let rest = line.trim().strip_prefix("Tagged snapshot '")?;
+        rest.split_once('\'').map(|(name, _)| name.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_name() {
+        let output = "\
+Creating new snapshot...(RSYNC)
+Saving to device: /dev/sda2, mounted at path: /run/timeshift/backup
+Syncing files with rsync...
+Created control file: /run/timeshift/backup/timeshift/snapshots/2026-10-17_09-30-12/info.json
+RSYNC Snapshot saved successfully (41s)
+Tagged snapshot '2026-10-17_09-30-12': ondemand
+";
+        assert_eq!(
+            snapshot_name(output).as_deref(),
+            Some("2026-10-17_09-30-12")
+        );
+        assert_eq!(
+            snapshot_name("E: Selected snapshot device is not mounted"),
+            None
+        );
+    }
+}
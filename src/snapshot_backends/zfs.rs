@@ -0,0 +1,252 @@
+//! ZFS snapshots of the configured datasets, or of the one mounted at `/`.
+//! All datasets are snapshotted in one `zfs snapshot` call, so they are taken
+//! at the same instant, and rolled back with `zfs rollback`.
+
+use super::{mount_device, run, Revert, SnapshotBackend};
+use crate::config::ZfsSnapshotConfig;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use std::path::Path;
+use tracing::info;
+
+/// Start of the names of the snapshots omni takes
+const SNAPSHOT_PREFIX: &str = "omni-";
+
+/// Takes and rolls back ZFS snapshots
+pub struct Zfs {
+    config: ZfsSnapshotConfig,
+}
+
+impl Zfs {
+    pub fn new(config: ZfsSnapshotConfig) -> Self {
+        Self { config }
+    }
+
+    fn datasets(&self) -> Result<Vec<String>> {
+        if !self.config.datasets.is_empty() {
+            return Ok(self.config.datasets.clone());
+        }
+        root_dataset()
+            .map(|dataset| vec![dataset])
+            .ok_or_else(|| anyhow!("/ is not on ZFS; set snapshots.zfs.datasets"))
+    }
+
+    /// Refuse a rollback that would lose more than the changes since the
+    /// snapshot: one on a pool that is not healthy, or one that destroys
+    /// snapshots omni did not take
+    fn check_rollback(snapshot: &str) -> Result<Vec<String>> {
+        let (dataset, _) = snapshot
+            .split_once('@')
+            .ok_or_else(|| anyhow!("{} is not a ZFS snapshot name", snapshot))?;
+
+        run(
+            "zfs",
+            &["list", "-H", "-o", "name", "-t", "snapshot", snapshot],
+        )
+        .map_err(|_| anyhow!("The ZFS snapshot {} no longer exists", snapshot))?;
+
+        let pool = dataset.split('/').next().unwrap_or(dataset);
+        let health = run("zpool", &["list", "-H", "-o", "health", pool])?;
+        if health.trim() != "ONLINE" {
+            return Err(anyhow!(
+                "The pool {} is {}; repair it ('zpool status {}') before rolling back",
+                pool,
+                health.trim(),
+                pool
+            ));
+        }
+
+        let listed = run(
+            "zfs",
+            &[
+                "list",
+                "-H",
+                "-o",
+                "name",
+                "-t",
+                "snapshot",
+                "-s",
+                "createtxg",
+                "-d",
+                "1",
+                dataset,
+            ],
+        )?;
+        let later = later_snapshots(&listed, snapshot);
+        let foreign: Vec<&String> = later.iter().filter(|s| !is_omni_snapshot(s)).collect();
+        if !foreign.is_empty() {
+            return Err(anyhow!(
+                "Rolling back to {} would destroy snapshots omni did not take: {}",
+                snapshot,
+                foreign
+                    .iter()
+                    .map(|s| s.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        Ok(later)
+    }
+}
+
+impl SnapshotBackend for Zfs {
+    fn name(&self) -> &'static str {
+        "zfs"
+    }
+
+    fn create(&self, label: &str) -> Result<String> {
+        let name = format!(
+            "{}{}",
+            SNAPSHOT_PREFIX,
+            Utc::now().format("%Y%m%d-%H%M%S-%f")
+        );
+        let snapshots: Vec<String> = self
+            .datasets()?
+            .iter()
+            .map(|dataset| format!("{}@{}", dataset, name))
+            .collect();
+
+        let property = format!("omni:label={}", label);
+        let mut args = vec!["snapshot", "-o", property.as_str()];
+        args.extend(snapshots.iter().map(String::as_str));
+        run("zfs", &args)?;
+
+        info!("Took ZFS snapshots {}", snapshots.join(", "));
+        Ok(snapshots.join(","))
+    }
+
+    fn revert(&self, reference: &str) -> Result<Revert> {
+        let snapshots = snapshot_names(reference);
+        if snapshots.is_empty() {
+            return Err(anyhow!("No ZFS snapshots in '{}'", reference));
+        }
+
+        // Check every dataset before rolling any of them back
+        let mut destroyed = Vec::new();
+        for snapshot in &snapshots {
+            destroyed.push(Self::check_rollback(snapshot)?);
+        }
+
+        for (snapshot, later) in snapshots.iter().zip(&destroyed) {
+            if later.is_empty() {
+                run("zfs", &["rollback", snapshot])?;
+            } else {
+                info!(
+                    "Destroying later omni snapshots {} to roll back",
+                    later.join(", ")
+                );
+                run("zfs", &["rollback", "-r", snapshot])?;
+            }
+        }
+
+        let root = root_dataset();
+        let root_rolled_back = snapshots
+            .iter()
+            .any(|s| s.split_once('@').map(|(dataset, _)| dataset) == root.as_deref());
+        if root_rolled_back {
+            Ok(Revert::AfterReboot(
+                "The root dataset is rolled back; reboot now, since running programs \
+                 still use files from before the rollback"
+                    .to_string(),
+            ))
+        } else {
+            Ok(Revert::Applied)
+        }
+    }
+
+    fn delete(&self, reference: &str) -> Result<()> {
+        for snapshot in snapshot_names(reference) {
+            // Rolling back to an earlier snapshot destroys later ones
+            if run(
+                "zfs",
+                &["list", "-H", "-o", "name", "-t", "snapshot", snapshot],
+            )
+            .is_ok()
+            {
+                run("zfs", &["destroy", snapshot])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn disk_usage(&self, reference: &str) -> Option<u64> {
+        snapshot_names(reference)
+            .into_iter()
+            .map(|snapshot| {
+                run(
+                    "zfs",
+                    &["list", "-Hp", "-o", "used", "-t", "snapshot", snapshot],
+                )
+                .ok()
+                .and_then(|used| used.trim().parse::<u64>().ok())
+            })
+            .sum()
+    }
+}
+
+/// The dataset mounted at `/`, if `/` is on ZFS
+fn root_dataset() -> Option<String> {
+    let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+    (super::filesystem_type(&mounts, Path::new("/")).as_deref() == Some("zfs"))
+        .then(|| mount_device(&mounts, Path::new("/")))
+        .flatten()
+}
+
+/// The snapshots in a stored reference, `pool/a@omni-…,pool/b@omni-…`
+fn snapshot_names(reference: &str) -> Vec<&str> {
+    reference
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn is_omni_snapshot(snapshot: &str) -> bool {
+    snapshot
+        .split_once('@')
+        .is_some_and(|(_, name)| name.starts_with(SNAPSHOT_PREFIX))
+}
+
+/// Snapshots listed after `snapshot` in `zfs list -s createtxg` output
+fn later_snapshots(listed: &str, snapshot: &str) -> Vec<String> {
+    listed
+        .lines()
+        .map(str::trim)
+        .skip_while(|name| *name != snapshot)
+        .skip(1)
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_later_snapshots() {
+        let listed = "rpool/ROOT/ubuntu@install\n\
+                      rpool/ROOT/ubuntu@omni-20261001-100000-0\n\
+                      rpool/ROOT/ubuntu@autozsys_abc\n\
+                      rpool/ROOT/ubuntu@omni-20261002-100000-0\n";
+        let later = later_snapshots(listed, "rpool/ROOT/ubuntu@omni-20261001-100000-0");
+        assert_eq!(
+            later,
+            vec![
+                "rpool/ROOT/ubuntu@autozsys_abc",
+                "rpool/ROOT/ubuntu@omni-20261002-100000-0"
+            ]
+        );
+        assert!(!is_omni_snapshot(&later[0]));
+        assert!(is_omni_snapshot(&later[1]));
+        assert!(later_snapshots(listed, "rpool/ROOT/ubuntu@omni-20261002-100000-0").is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_names() {
+        assert_eq!(
+            snapshot_names("rpool/ROOT/ubuntu@omni-1,rpool/home@omni-1"),
+            vec!["rpool/ROOT/ubuntu@omni-1", "rpool/home@omni-1"]
+        );
+        assert!(snapshot_names("").is_empty());
+    }
+}
@@ -1,8 +1,9 @@
+use crate::credentials;
+use crate::events::{OutputForwarder, OutputStream};
+use crate::input_validation::InputValidator;
 use anyhow::{anyhow, Result};
-use async_trait::async_trait;
 use base64::prelude::*;
 use russh::*;
-use russh_keys::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -12,7 +13,7 @@ use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, instrument, warn};
 use uuid::Uuid;
 
 /// Real SSH client configuration
@@ -88,13 +89,12 @@ struct SshClientHandler {
     auth_method: RealAuthMethod,
 }
 
-#[async_trait]
 impl client::Handler for SshClientHandler {
     type Error = anyhow::Error;
 
     async fn check_server_key(
         &mut self,
-        _server_public_key: &key::PublicKey,
+        _server_public_key: &keys::PublicKey,
     ) -> Result<bool, Self::Error> {
         // In a real implementation, this would verify against known_hosts
         Ok(true)
@@ -132,6 +132,11 @@ impl RealSshSession {
     }
 
     /// Connect to the remote host using real SSH
+    #[instrument(
+        name = "ssh_connect",
+        skip_all,
+        fields(host = %self.config.host, port = self.config.port)
+    )]
     pub async fn connect(&mut self) -> Result<()> {
         info!(
             "Connecting to SSH host: {}@{}:{}",
@@ -179,6 +184,32 @@ impl RealSshSession {
 
     /// Execute a command on the remote host
     pub async fn execute_command(&mut self, command: &str) -> Result<RealSshCommandResult> {
+        self.execute_command_with_input(command, None).await
+    }
+
+    /// Execute a command, writing `input` to its stdin. The input is treated as a
+    /// secret: it is never logged and is masked in the returned output.
+    pub async fn execute_command_with_input(
+        &mut self,
+        command: &str,
+        input: Option<&str>,
+    ) -> Result<RealSshCommandResult> {
+        self.execute_command_forwarded(command, input, None).await
+    }
+
+    /// Execute a command, handing each chunk of output to `forwarder` as it arrives
+    /// in addition to collecting it into the result
+    #[instrument(
+        name = "ssh_exec",
+        skip_all,
+        fields(host = %self.config.host, command = %command)
+    )]
+    pub async fn execute_command_forwarded(
+        &mut self,
+        command: &str,
+        input: Option<&str>,
+        forwarder: Option<&mut OutputForwarder>,
+    ) -> Result<RealSshCommandResult> {
         if !self.connected {
             return Err(anyhow!("SSH session not connected"));
         }
@@ -196,15 +227,18 @@ impl RealSshSession {
 
         let result = tokio::time::timeout(
             self.config.command_timeout,
-            self.execute_command_internal(session, command),
+            self.execute_command_internal(session, command, input, forwarder),
         )
         .await
         .map_err(|_| anyhow!("Command execution timeout"))?;
 
         let duration = start_time.elapsed();
+        let secrets: Vec<&str> = input.map(|i| i.trim_end_matches('\n')).into_iter().collect();
 
         match result {
             Ok((exit_code, stdout, stderr)) => {
+                let stdout = credentials::mask_secrets(&stdout, &secrets);
+                let stderr = credentials::mask_secrets(&stderr, &secrets);
                 let result = RealSshCommandResult {
                     command: command.to_string(),
                     exit_code,
@@ -254,22 +288,27 @@ impl RealSshSession {
         Ok(results)
     }
 
-    /// Execute a command with privilege escalation (sudo)
+    /// Execute a command with privilege escalation (sudo).
+    /// The password is sent on stdin, never as part of the command line.
     pub async fn execute_privileged_command(
         &mut self,
         command: &str,
         sudo_password: Option<&str>,
+        forwarder: Option<&mut OutputForwarder>,
     ) -> Result<RealSshCommandResult> {
-        let privileged_command = if let Some(password) = sudo_password {
-            format!("echo '{}' | sudo -S {}", password, command)
-        } else {
-            format!("sudo {}", command)
-        };
+        let privileged_command = credentials::sudo_wrap(command, sudo_password.is_some());
+        let input = sudo_password.map(|password| format!("{}\n", password));
 
-        self.execute_command(&privileged_command).await
+        self.execute_command_forwarded(&privileged_command, input.as_deref(), forwarder)
+            .await
     }
 
     /// Upload a file to the remote host using SFTP
+    #[instrument(
+        name = "ssh_upload",
+        skip_all,
+        fields(host = %self.config.host, remote_path = %remote_path)
+    )]
     pub async fn upload_file(&mut self, local_path: &PathBuf, remote_path: &str) -> Result<()> {
         if !self.connected {
             return Err(anyhow!("SSH session not connected"));
@@ -344,6 +383,11 @@ impl RealSshSession {
     }
 
     /// Download a file from the remote host
+    #[instrument(
+        name = "ssh_download",
+        skip_all,
+        fields(host = %self.config.host, remote_path = %remote_path)
+    )]
     pub async fn download_file(&mut self, remote_path: &str, local_path: &PathBuf) -> Result<()> {
         if !self.connected {
             return Err(anyhow!("SSH session not connected"));
@@ -460,11 +504,12 @@ impl RealSshSession {
 
         match &self.config.auth_method {
             RealAuthMethod::Password { password } => {
+                let password = credentials::resolve(password)?;
                 let auth_result = session
                     .authenticate_password(&self.config.username, password)
                     .await?;
 
-                if !auth_result {
+                if !auth_result.success() {
                     return Err(anyhow!("Password authentication failed"));
                 }
                 info!("Password authentication successful");
@@ -479,16 +524,22 @@ impl RealSshSession {
                     .map_err(|e| anyhow!("Failed to read private key: {}", e))?;
 
                 let key = if let Some(passphrase) = passphrase {
-                    decode_secret_key(&key_data, Some(&passphrase))?
+                    let passphrase = credentials::resolve(passphrase)?;
+                    keys::decode_secret_key(&key_data, Some(&passphrase))?
                 } else {
-                    decode_secret_key(&key_data, None)?
+                    keys::decode_secret_key(&key_data, None)?
                 };
+                // RSA keys sign with the strongest hash the server accepts
+                let hash_alg = session.best_supported_rsa_hash().await?.flatten();
 
                 let auth_result = session
-                    .authenticate_publickey(&self.config.username, Arc::new(key))
+                    .authenticate_publickey(
+                        &self.config.username,
+                        keys::PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg),
+                    )
                     .await?;
 
-                if !auth_result {
+                if !auth_result.success() {
                     return Err(anyhow!("Public key authentication failed"));
                 }
                 info!("Public key authentication successful");
@@ -506,6 +557,8 @@ impl RealSshSession {
         &self,
         session: &Arc<Mutex<client::Handle<SshClientHandler>>>,
         command: &str,
+        input: Option<&str>,
+        mut forwarder: Option<&mut OutputForwarder>,
     ) -> Result<(i32, String, String)> {
         let session_guard = session.lock().await;
 
@@ -515,6 +568,11 @@ impl RealSshSession {
         // Execute the command
         channel.exec(true, command).await?;
 
+        if let Some(input) = input {
+            channel.data(input.as_bytes()).await?;
+            channel.eof().await?;
+        }
+
         let mut stdout = Vec::new();
         let mut stderr = Vec::new();
         let mut exit_code = 0;
@@ -523,9 +581,15 @@ impl RealSshSession {
         while let Some(msg) = channel.wait().await {
             match msg {
                 ChannelMsg::Data { data } => {
+                    if let Some(forwarder) = forwarder.as_deref_mut() {
+                        forwarder.on_data(OutputStream::Stdout, &data);
+                    }
                     stdout.extend_from_slice(&data);
                 }
                 ChannelMsg::ExtendedData { data, ext: 1 } => {
+                    if let Some(forwarder) = forwarder.as_deref_mut() {
+                        forwarder.on_data(OutputStream::Stderr, &data);
+                    }
                     stderr.extend_from_slice(&data);
                 }
                 ChannelMsg::ExitStatus { exit_status } => {
@@ -538,6 +602,10 @@ impl RealSshSession {
             }
         }
 
+        if let Some(forwarder) = forwarder {
+            forwarder.finish();
+        }
+
         let stdout_str = String::from_utf8_lossy(&stdout).to_string();
         let stderr_str = String::from_utf8_lossy(&stderr).to_string();
 
@@ -667,7 +735,10 @@ impl RealSshClient {
         }
     }
 
-    /// Execute a package management command on a remote host
+    /// Execute a package management command on a remote host.
+    /// `sudo_password` is passed to `sudo -S` on stdin when the host needs one;
+    /// output is streamed to `forwarder` while the command runs.
+    #[allow(clippy::too_many_arguments)]
     pub async fn execute_remote_package_command(
         &mut self,
         host: &str,
@@ -675,33 +746,78 @@ impl RealSshClient {
         box_type: &str,
         package_name: &str,
         operation: &str,
+        sudo_password: Option<&str>,
+        forwarder: Option<&mut OutputForwarder>,
     ) -> Result<RealSshCommandResult> {
+        InputValidator::validate_package_name(package_name)?;
         let session = self.pool.get_or_create_session(host, config).await?;
 
-        // Build package manager command
+        // Build package manager command; the whole chain runs under a single sudo
         let command = match box_type {
             "apt" => match operation {
-                "install" => format!("sudo apt update && sudo apt install -y {}", package_name),
-                "remove" => format!("sudo apt remove -y {}", package_name),
-                "update" => format!("sudo apt update && sudo apt upgrade -y {}", package_name),
+                "install" => format!("apt update && apt install -y {}", package_name),
+                "remove" => format!("apt remove -y {}", package_name),
+                "update" => format!("apt update && apt upgrade -y {}", package_name),
                 _ => return Err(anyhow!("Unsupported operation: {}", operation)),
             },
             "dnf" => match operation {
-                "install" => format!("sudo dnf install -y {}", package_name),
-                "remove" => format!("sudo dnf remove -y {}", package_name),
-                "update" => format!("sudo dnf upgrade -y {}", package_name),
+                "install" => format!("dnf install -y {}", package_name),
+                "remove" => format!("dnf remove -y {}", package_name),
+                "update" => format!("dnf upgrade -y {}", package_name),
                 _ => return Err(anyhow!("Unsupported operation: {}", operation)),
             },
             "pacman" => match operation {
-                "install" => format!("sudo pacman -S --noconfirm {}", package_name),
-                "remove" => format!("sudo pacman -R --noconfirm {}", package_name),
-                "update" => format!("sudo pacman -Syu --noconfirm {}", package_name),
+                "install" => format!("pacman -S --noconfirm {}", package_name),
+                "remove" => format!("pacman -R --noconfirm {}", package_name),
+                "update" => format!("pacman -Syu --noconfirm {}", package_name),
                 _ => return Err(anyhow!("Unsupported operation: {}", operation)),
             },
             _ => return Err(anyhow!("Unsupported box type: {}", box_type)),
         };
 
-        session.execute_command(&command).await
+        session
+            .execute_privileged_command(&command, sudo_password, forwarder)
+            .await
+    }
+
+    /// Execute a command on a remote host under sudo, like the package helper
+    pub async fn execute_remote_privileged_command(
+        &mut self,
+        host: &str,
+        config: RealSshConfig,
+        command: &str,
+        sudo_password: Option<&str>,
+        forwarder: Option<&mut OutputForwarder>,
+    ) -> Result<RealSshCommandResult> {
+        let session = self.pool.get_or_create_session(host, config).await?;
+        session
+            .execute_privileged_command(command, sudo_password, forwarder)
+            .await
+    }
+
+    /// Execute an arbitrary command on a remote host
+    pub async fn execute_remote_command(
+        &mut self,
+        host: &str,
+        config: RealSshConfig,
+        command: &str,
+    ) -> Result<RealSshCommandResult> {
+        self.execute_remote_command_forwarded(host, config, command, None)
+            .await
+    }
+
+    /// Execute an arbitrary command on a remote host, streaming its output to `forwarder`
+    pub async fn execute_remote_command_forwarded(
+        &mut self,
+        host: &str,
+        config: RealSshConfig,
+        command: &str,
+        forwarder: Option<&mut OutputForwarder>,
+    ) -> Result<RealSshCommandResult> {
+        let session = self.pool.get_or_create_session(host, config).await?;
+        session
+            .execute_command_forwarded(command, None, forwarder)
+            .await
     }
 
     /// Test connectivity to a remote host
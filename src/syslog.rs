@@ -0,0 +1,276 @@
+//! RFC 5424 syslog client. Messages go to a remote collector as single UDP
+//! datagrams, or as octet-counted frames over TCP or TLS, so multi-line
+//! messages survive; structured data carries the fields a SIEM filters on.
+
+use crate::config::{SyslogConfig, SyslogFacility, SyslogTransport};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+const APP_NAME: &str = "omni";
+/// SD-ID of omni's structured data element; 32473 is the enterprise number
+/// RFC 5612 reserves for documentation and private use
+pub const SD_ID: &str = "omni@32473";
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Severity levels from RFC 5424, section 6.2.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Emergency,
+    Alert,
+    Critical,
+    Error,
+    Warning,
+    Notice,
+    Informational,
+    Debug,
+}
+
+pub struct Message<'a> {
+    pub facility: SyslogFacility,
+    pub severity: Severity,
+    pub timestamp: DateTime<Utc>,
+    /// Type of the message, e.g. "package_install"
+    pub msg_id: &'a str,
+    /// Parameters of the `SD_ID` element; none leaves out structured data
+    pub params: Vec<(&'static str, String)>,
+    pub text: &'a str,
+}
+
+impl Message<'_> {
+    /// The message in the syslog protocol format, without framing
+    pub fn format(&self) -> String {
+        let priority = u16::from(self.facility.code()) * 8 + self.severity as u16;
+        let mut line = format!(
+            "<{}>1 {} {} {} {} {} ",
+            priority,
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+            header_field(&local_hostname(), 255),
+            APP_NAME,
+            std::process::id(),
+            header_field(self.msg_id, 32),
+        );
+
+        if self.params.is_empty() {
+            line.push('-');
+        } else {
+            line.push('[');
+            line.push_str(SD_ID);
+            for (name, value) in &self.params {
+                line.push_str(&format!(" {}=\"{}\"", name, escape_param(value)));
+            }
+            line.push(']');
+        }
+
+        if !self.text.is_empty() {
+            // The BOM marks the text as UTF-8 (section 6.4)
+            line.push_str(" \u{feff}");
+            line.push_str(self.text);
+        }
+        line
+    }
+}
+
+/// Printable ASCII without spaces, at most `max` long, or the nil value
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn local_hostname() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Deliver one formatted message to `target`
+pub async fn send(target: &SyslogConfig, message: &str) -> Result<()> {
+    if target.host.is_empty() {
+        return Err(anyhow!("Syslog target without a host (audit.syslog)"));
+    }
+
+    tokio::time::timeout(SEND_TIMEOUT, deliver(target, message))
+        .await
+        .map_err(|_| anyhow!("timed out after {}s", SEND_TIMEOUT.as_secs()))
+        .and_then(|sent| sent)
+        .with_context(|| {
+            format!(
+                "Sending to syslog at {}:{} over {} failed",
+                target.host,
+                target.port(),
+                target.transport
+            )
+        })
+}
+
+async fn deliver(target: &SyslogConfig, message: &str) -> Result<()> {
+    let address = (target.host.as_str(), target.port());
+    match target.transport {
+        SyslogTransport::Udp => {
+            let peer = tokio::net::lookup_host(address)
+                .await?
+                .next()
+                .ok_or_else(|| anyhow!("{} has no address", target.host))?;
+            let local = if peer.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(local).await?;
+            socket.send_to(message.as_bytes(), peer).await?;
+        }
+        SyslogTransport::Tcp => {
+            let mut stream = TcpStream::connect(address).await?;
+            write_frame(&mut stream, message).await?;
+        }
+        SyslogTransport::Tls => {
+            let connector = tls_connector(target.ca_file.as_deref())?;
+            let server_name = ServerName::try_from(target.host.clone())?;
+            let stream = TcpStream::connect(address).await?;
+            let mut stream = connector.connect(server_name, stream).await?;
+            write_frame(&mut stream, message).await?;
+            stream.shutdown().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Octet counting (RFC 6587, section 3.4.1): the length, a space, the message
+async fn write_frame<W: AsyncWrite + Unpin>(stream: &mut W, message: &str) -> Result<()> {
+    let frame = format!("{} {}", message.len(), message);
+    stream.write_all(frame.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+fn tls_connector(ca_file: Option<&Path>) -> Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            let certs = CertificateDer::pem_file_iter(path)
+                .with_context(|| format!("Cannot read CA file {}", path.display()))?;
+            for cert in certs {
+                roots.add(cert?)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    fn message(text: &str) -> String {
+        Message {
+            facility: SyslogFacility::Auth,
+            severity: Severity::Informational,
+            timestamp: "2026-03-01T12:00:00Z".parse().unwrap(),
+            msg_id: "package_install",
+            params: vec![
+                ("user", "alice".to_string()),
+                ("note", r#"a "b" \c]"#.to_string()),
+            ],
+            text,
+        }
+        .format()
+    }
+
+    #[test]
+    fn test_format() {
+        let expected = format!(
+            "<38>1 2026-03-01T12:00:00.000000Z {} omni {} package_install \
+             [omni@32473 user=\"alice\" note=\"a \\\"b\\\" \\\\c\\]\"] \u{feff}Installed firefox",
+            header_field(&local_hostname(), 255),
+            std::process::id()
+        );
+        assert_eq!(message("Installed firefox"), expected);
+
+        let bare = Message {
+            facility: SyslogFacility::Local3,
+            severity: Severity::Critical,
+            timestamp: Utc::now(),
+            msg_id: "",
+            params: Vec::new(),
+            text: "",
+        }
+        .format();
+        assert!(bare.starts_with("<154>1 "));
+        assert!(bare.ends_with(" - -"));
+    }
+
+    #[tokio::test]
+    async fn test_send_over_tcp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut frames = String::new();
+            stream.read_to_string(&mut frames).await.unwrap();
+            frames
+        });
+
+        let target = SyslogConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            transport: SyslogTransport::Tcp,
+            ..Default::default()
+        };
+        let line = message("first line\nsecond line");
+        send(&target, &line).await.unwrap();
+
+        assert_eq!(received.await.unwrap(), format!("{} {}", line.len(), line));
+    }
+
+    #[tokio::test]
+    async fn test_send_over_udp() {
+        let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = SyslogConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(collector.local_addr().unwrap().port()),
+            ..Default::default()
+        };
+        let line = message("Installed firefox");
+        send(&target, &line).await.unwrap();
+
+        let mut buf = vec![0; 2048];
+        let len = collector.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], line.as_bytes());
+        assert!(send(&SyslogConfig::default(), &line).await.is_err());
+    }
+}
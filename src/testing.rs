@@ -179,13 +179,14 @@ impl AsyncTestHelper {
             .map_err(|_| anyhow::anyhow!("Test timed out after {:?}", duration))?
     }
 
-    pub async fn retry_until_success<T, F>(
+    pub async fn retry_until_success<T, F, Fut>(
         max_attempts: u32,
         delay: Duration,
-        operation: F,
+        mut operation: F,
     ) -> Result<T>
     where
-        F: Fn() -> std::future::Future<Output = Result<T>>,
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
     {
         let mut attempts = 0;
         loop {
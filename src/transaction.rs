@@ -1,9 +1,24 @@
-use crate::database::Database;
+use crate::artifacts;
+use crate::boxes::{
+    appimage, apt::AptManager, brew::BrewBox, chocolatey::ChocolateyBox, dnf::DnfBox,
+    emerge::EmergeBox, flatpak::FlatpakBox, mas::MasBox, nix::NixBox, pacman::PacmanBox,
+    scoop::ScoopBox, snap::SnapBox, winget::WingetBox, zypper::ZypperBox,
+};
+use crate::config::OmniConfig;
+use crate::database::{Database, InstallRecord, InstallStatus};
+use crate::distro::PackageManager;
+use crate::downgrade;
 use crate::error_handling::OmniError;
+use crate::privilege_manager::PrivilegeManager;
+use crate::provenance;
+use crate::resolver::{DependencyResolver, ResolutionPlan};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use tokio::task::JoinSet;
 use tracing::{info, warn};
 use uuid::Uuid;
 
@@ -25,7 +40,7 @@ pub struct Transaction {
     pub rollback_data: Option<RollbackData>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransactionStatus {
     Pending,
     InProgress,
@@ -34,6 +49,32 @@ pub enum TransactionStatus {
     RolledBack,
 }
 
+impl TransactionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionStatus::Pending => "Pending",
+            TransactionStatus::InProgress => "InProgress",
+            TransactionStatus::Completed => "Completed",
+            TransactionStatus::Failed => "Failed",
+            TransactionStatus::RolledBack => "RolledBack",
+        }
+    }
+
+    /// Parse a status as given on the command line, e.g. `in-progress`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name: String = name.chars().filter(|c| *c != '-' && *c != '_').collect();
+        [
+            TransactionStatus::Pending,
+            TransactionStatus::InProgress,
+            TransactionStatus::Completed,
+            TransactionStatus::Failed,
+            TransactionStatus::RolledBack,
+        ]
+        .into_iter()
+        .find(|status| status.as_str().eq_ignore_ascii_case(&name))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransactionType {
     Install,
@@ -47,12 +88,21 @@ pub struct Operation {
     pub id: Uuid,
     pub operation_type: OperationType,
     pub package: String,
+    /// Box to run the operation with; the first available one when unset
+    #[serde(default)]
+    pub box_type: Option<String>,
     pub version: Option<String>,
+    /// Where to download the package from, for AppImages
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Operations that have to finish before this one starts
+    #[serde(default)]
+    pub depends_on: Vec<Uuid>,
     pub status: OperationStatus,
     pub error: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OperationType {
     InstallPackage,
     RemovePackage,
@@ -61,17 +111,59 @@ pub enum OperationType {
     ModifyConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OperationStatus {
     Pending,
     InProgress,
     Completed,
     Failed,
+    /// Completed, then undone when the transaction was rolled back
+    RolledBack,
+}
+
+/// What a transaction would do, shown before it runs
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionPlan {
+    pub transaction_id: Uuid,
+    pub steps: Vec<PlannedStep>,
+    /// Sum of the download sizes the boxes report
+    pub download_size: Option<u64>,
+    /// Whether any step runs as root, through sudo when omni is not root
+    pub requires_root: bool,
+}
+
+impl TransactionPlan {
+    pub fn new(transaction_id: Uuid, steps: Vec<PlannedStep>) -> Self {
+        let sizes: Vec<u64> = steps.iter().filter_map(|s| s.download_size).collect();
+        Self {
+            transaction_id,
+            requires_root: steps.iter().any(|s| s.requires_root),
+            download_size: (!sizes.is_empty()).then(|| sizes.iter().sum()),
+            steps,
+        }
+    }
+}
+
+/// One operation of a [`TransactionPlan`], in the order it runs
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedStep {
+    pub order: usize,
+    pub operation_type: OperationType,
+    pub package: String,
+    /// None when no box on this system can run it
+    pub box_type: Option<String>,
+    pub installed_version: Option<String>,
+    /// The version asked for; None means the latest the box has
+    pub expected_version: Option<String>,
+    pub requires_root: bool,
+    pub download_size: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RollbackData {
     pub snapshot_id: Option<String>,
+    /// Version of each package installed before the transaction, keyed by
+    /// `box:package`; for AppImages, the URL it came from
     pub previous_state: HashMap<String, String>,
     pub backup_files: Vec<String>,
 }
@@ -112,14 +204,38 @@ impl TransactionManager {
         operation_type: OperationType,
         package: String,
         version: Option<String>,
+    ) -> Result<Uuid> {
+        self.add_box_operation(transaction_id, operation_type, package, None, version)
+            .await
+    }
+
+    /// Add an operation that runs with a specific box, e.g. to reverse
+    /// something recorded in the install history
+    pub async fn add_box_operation(
+        &mut self,
+        transaction_id: Uuid,
+        operation_type: OperationType,
+        package: String,
+        box_type: Option<String>,
+        version: Option<String>,
     ) -> Result<Uuid> {
         let operation_id = Uuid::new_v4();
 
+        // An AppImage has no versions to pick from; what follows `=` in its
+        // spec is the URL to download it from
+        let (version, source) = match box_type.as_deref() {
+            Some("appimage") => (None, version),
+            _ => (version, None),
+        };
+
         let operation = Operation {
             id: operation_id,
             operation_type,
             package,
+            box_type,
             version,
+            source,
+            depends_on: Vec::new(),
             status: OperationStatus::Pending,
             error: None,
         };
@@ -136,88 +252,475 @@ impl TransactionManager {
         }
     }
 
-    /// Execute a transaction
+    /// Make `operation` wait for `depends_on` to finish
+    pub fn add_dependency(
+        &mut self,
+        transaction_id: Uuid,
+        operation: Uuid,
+        depends_on: Uuid,
+    ) -> Result<()> {
+        let transaction = self
+            .active_transactions
+            .get_mut(&transaction_id)
+            .ok_or_else(|| anyhow::anyhow!("Transaction not found: {}", transaction_id))?;
+        if !transaction.operations.iter().any(|o| o.id == depends_on) {
+            return Err(anyhow::anyhow!(
+                "Operation {} is not in transaction {}",
+                depends_on,
+                transaction_id
+            ));
+        }
+        let operation = transaction
+            .operations
+            .iter_mut()
+            .find(|o| o.id == operation)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Operation {} is not in transaction {}",
+                    operation,
+                    transaction_id
+                )
+            })?;
+        operation.depends_on.push(depends_on);
+        Ok(())
+    }
+
+    /// Add an install for each package the resolver planned, each waiting for
+    /// the installs of its dependencies and for the removals the plan needs
+    pub async fn add_resolution_plan(
+        &mut self,
+        transaction_id: Uuid,
+        plan: &ResolutionPlan,
+    ) -> Result<Vec<Uuid>> {
+        let mut removals = Vec::new();
+        for (name, box_type) in &plan.removals {
+            let id = self
+                .add_box_operation(
+                    transaction_id,
+                    OperationType::RemovePackage,
+                    name.clone(),
+                    Some(box_type.clone()),
+                    None,
+                )
+                .await?;
+            removals.push(id);
+        }
+
+        let mut ids = HashMap::new();
+        for package in &plan.packages {
+            let version = Some(package.version.clone()).filter(|v| v != "latest");
+            let id = self
+                .add_box_operation(
+                    transaction_id,
+                    OperationType::InstallPackage,
+                    package.name.clone(),
+                    Some(package.box_type.clone()),
+                    version,
+                )
+                .await?;
+            ids.insert(package.name.as_str(), id);
+        }
+        for package in &plan.packages {
+            for dependency in &package.dependencies {
+                if let Some(depends_on) = ids.get(dependency.name.as_str()) {
+                    self.add_dependency(transaction_id, ids[package.name.as_str()], *depends_on)?;
+                }
+            }
+            for removal in &removals {
+                self.add_dependency(transaction_id, ids[package.name.as_str()], *removal)?;
+            }
+        }
+        Ok(plan.packages.iter().map(|p| ids[p.name.as_str()]).collect())
+    }
+
+    /// Execute a transaction. Operations in different boxes run at the same
+    /// time, up to `general.max_parallel_jobs`; each box runs its operations
+    /// one at a time, in the order they were added, since package managers
+    /// hold a lock while they work (dpkg, rpm, pacman). If one fails, the
+    /// transaction is rolled back, undoing the operations that completed.
     pub async fn execute_transaction(&mut self, transaction_id: Uuid) -> Result<()> {
         // First, check if transaction exists and get its info
-        let operations = if let Some(transaction) = self.active_transactions.get(&transaction_id) {
-            info!(
-                "Executing transaction: {} with {} operations",
-                transaction_id,
-                transaction.operations.len()
-            );
+        let mut operations =
+            if let Some(transaction) = self.active_transactions.get(&transaction_id) {
+                info!(
+                    "Executing transaction: {} with {} operations",
+                    transaction_id,
+                    transaction.operations.len()
+                );
 
-            // Clone operations for processing
-            transaction.operations.clone()
-        } else {
-            return Err(anyhow::anyhow!("Transaction not found: {}", transaction_id));
-        };
+                // Clone operations for processing
+                transaction.operations.clone()
+            } else {
+                return Err(anyhow::anyhow!("Transaction not found: {}", transaction_id));
+            };
+        let lanes: Vec<String> = operations.iter().map(lane).collect();
+        run_order(&operations, &lanes)?;
 
-        // Create rollback data
-        let rollback_data = self.create_rollback_data().await?;
+        // Create rollback data, keeping that of an interrupted run being resumed
+        let rollback_data = match self
+            .active_transactions
+            .get(&transaction_id)
+            .and_then(|t| t.rollback_data.clone())
+        {
+            Some(rollback_data) => rollback_data,
+            None => {
+                self.create_rollback_data(transaction_id, &operations, &lanes)
+                    .await
+            }
+        };
 
         // Update transaction status
         if let Some(transaction) = self.active_transactions.get_mut(&transaction_id) {
             transaction.status = TransactionStatus::InProgress;
             transaction.rollback_data = Some(rollback_data);
         }
+        self.journal(transaction_id).await?;
+
+        let max_jobs = OmniConfig::load()
+            .unwrap_or_default()
+            .general
+            .max_parallel_jobs
+            .max(1);
+        // Operations a resumed run already finished
+        let mut done: HashSet<Uuid> = operations
+            .iter()
+            .filter(|o| o.status == OperationStatus::Completed)
+            .map(|o| o.id)
+            .collect();
+        let mut busy: HashSet<String> = HashSet::new();
+        let mut running = JoinSet::new();
+        let mut failure = None;
+        loop {
+            // Nothing new starts once an operation failed
+            if failure.is_none() {
+                let starting: Vec<usize> = ready(&operations, &lanes, &done, &busy)
+                    .into_iter()
+                    .take(max_jobs.saturating_sub(running.len()))
+                    .collect();
+                for &index in &starting {
+                    busy.insert(lanes[index].clone());
+                    operations[index].status = OperationStatus::InProgress;
+                }
 
-        // Execute each operation
-        let mut operation_results = Vec::new();
-        for operation in operations {
-            let mut op_copy = operation.clone();
-            op_copy.status = OperationStatus::InProgress;
+                // Journal the operations before they start, so a resume
+                // knows which ones may have run
+                if !starting.is_empty() {
+                    if let Err(e) = self.journal_operations(transaction_id, &operations).await {
+                        for &index in &starting {
+                            busy.remove(&lanes[index]);
+                            operations[index].status = OperationStatus::Pending;
+                        }
+                        failure = Some(("The transaction journal".to_string(), e));
+                        continue;
+                    }
+                }
+                for index in starting {
+                    let db = self.db.clone();
+                    let operation = operations[index].clone();
+                    running.spawn(async move {
+                        (index, Self::execute_operation(&db, &operation).await)
+                    });
+                }
+            }
 
-            match self.execute_operation(&op_copy).await {
-                Ok(_) => {
-                    op_copy.status = OperationStatus::Completed;
-                    info!("Operation {} completed successfully", op_copy.id);
-                    operation_results.push(op_copy);
+            let Some(joined) = running.join_next().await else {
+                break;
+            };
+            let (index, result) =
+                joined.map_err(|e| anyhow::anyhow!("Operation panicked: {}", e))?;
+            busy.remove(&lanes[index]);
+            let operation = &mut operations[index];
+            match result {
+                Ok(()) => {
+                    operation.status = OperationStatus::Completed;
+                    info!("Operation {} completed successfully", operation.id);
+                    done.insert(operation.id);
                 }
                 Err(e) => {
-                    op_copy.status = OperationStatus::Failed;
-                    op_copy.error = Some(e.to_string());
-                    warn!("Operation {} failed: {}", op_copy.id, e);
-
-                    // Rollback the transaction
-                    return self.rollback_transaction(transaction_id).await;
+                    operation.status = OperationStatus::Failed;
+                    operation.error = Some(e.to_string());
+                    warn!("Operation {} failed: {}", operation.id, e);
+                    if failure.is_none() {
+                        failure = Some((operation.package.clone(), e));
+                    }
                 }
             }
+            if let Err(e) = self.journal_operations(transaction_id, &operations).await {
+                warn!("Could not journal transaction {}: {}", transaction_id, e);
+            }
+        }
+
+        if let Some((package, e)) = failure {
+            // Rollback the transaction
+            return match self.rollback_transaction(transaction_id).await {
+                Ok(()) => Err(anyhow::anyhow!(
+                    "{} failed: {}; transaction {} was rolled back",
+                    package,
+                    e,
+                    transaction_id
+                )),
+                Err(rollback_error) => Err(anyhow::anyhow!(
+                    "{} failed: {}; rolling back transaction {} failed too: {}",
+                    package,
+                    e,
+                    transaction_id,
+                    rollback_error
+                )),
+            };
         }
 
         // Update transaction with results
         if let Some(transaction) = self.active_transactions.get_mut(&transaction_id) {
-            transaction.operations = operation_results;
             transaction.status = TransactionStatus::Completed;
             transaction.completed_at = Some(Utc::now());
 
             info!("Transaction {} completed successfully", transaction_id);
         }
+        self.journal(transaction_id).await?;
+        remove_backups(transaction_id);
 
         Ok(())
     }
 
-    /// Rollback a transaction
-    pub async fn rollback_transaction(&mut self, transaction_id: Uuid) -> Result<()> {
+    /// Finish a transaction that a killed omni left in progress. Operations
+    /// it had started count as done when the box shows their result, and run
+    /// again otherwise; failed ones are retried.
+    pub async fn resume(&mut self, transaction_id: Uuid) -> Result<()> {
+        let mut operations = self
+            .load_with_status(transaction_id, TransactionStatus::InProgress)
+            .await?
+            .operations;
+        settle_interrupted(&mut operations);
+        for operation in &mut operations {
+            if operation.status == OperationStatus::Failed {
+                operation.status = OperationStatus::Pending;
+                operation.error = None;
+            }
+        }
         if let Some(transaction) = self.active_transactions.get_mut(&transaction_id) {
-            info!("Rolling back transaction: {}", transaction_id);
-
-            // Implement rollback logic here
-            if let Some(rollback_data) = &transaction.rollback_data {
-                // Restore from snapshot if available
-                if let Some(snapshot_id) = &rollback_data.snapshot_id {
-                    info!("Restoring from snapshot: {}", snapshot_id);
-                    // Implementation would restore snapshot
+            transaction.operations = operations;
+        }
+
+        info!("Resuming transaction {}", transaction_id);
+        self.execute_transaction(transaction_id).await
+    }
+
+    /// Roll back a journaled transaction: a staged one is dropped, one that
+    /// ran or was interrupted has its operations reversed
+    pub async fn rollback(&mut self, transaction_id: Uuid) -> Result<()> {
+        let transaction = self.load(transaction_id).await?;
+        if transaction.status == TransactionStatus::RolledBack {
+            return Err(anyhow::anyhow!(
+                "Transaction {} is already rolled back",
+                transaction_id
+            ));
+        }
+        if transaction.status == TransactionStatus::InProgress {
+            let mut operations = transaction.operations.clone();
+            settle_interrupted(&mut operations);
+            self.journal_operations(transaction_id, &operations).await?;
+        }
+        self.rollback_transaction(transaction_id).await
+    }
+
+    /// Transactions that were in progress when omni stopped. Only meaningful
+    /// while holding the operations lock, since another omni may be running them.
+    pub async fn interrupted(&self) -> Result<Vec<Transaction>> {
+        self.list(Some(TransactionStatus::InProgress)).await
+    }
+
+    /// Journaled transactions, oldest first
+    pub async fn list(&self, status: Option<TransactionStatus>) -> Result<Vec<Transaction>> {
+        let journals = self
+            .db
+            .list_transactions(status.as_ref().map(TransactionStatus::as_str))
+            .await?;
+        Ok(journals
+            .iter()
+            .filter_map(|journal| match serde_json::from_str(journal) {
+                Ok(transaction) => Some(transaction),
+                Err(e) => {
+                    warn!("Skipping unreadable transaction journal: {}", e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Bring a transaction back from the journal
+    pub async fn load(&mut self, transaction_id: Uuid) -> Result<&Transaction> {
+        if !self.active_transactions.contains_key(&transaction_id) {
+            let journal = self
+                .db
+                .get_transaction(&transaction_id.to_string())
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Transaction not found: {}", transaction_id))?;
+            let transaction: Transaction = serde_json::from_str(&journal)?;
+            self.active_transactions.insert(transaction_id, transaction);
+        }
+        Ok(&self.active_transactions[&transaction_id])
+    }
+
+    /// Bring a transaction back from the journal, failing unless it is in `status`
+    pub async fn load_with_status(
+        &mut self,
+        transaction_id: Uuid,
+        status: TransactionStatus,
+    ) -> Result<Transaction> {
+        let transaction = self.load(transaction_id).await?;
+        if transaction.status != status {
+            return Err(anyhow::anyhow!(
+                "Transaction {} is {}, not {}",
+                transaction_id,
+                transaction.status.as_str(),
+                status.as_str()
+            ));
+        }
+        Ok(transaction.clone())
+    }
+
+    /// What running the transaction would do, without changing anything
+    pub async fn plan(&self, transaction_id: Uuid) -> Result<TransactionPlan> {
+        let transaction = self
+            .active_transactions
+            .get(&transaction_id)
+            .ok_or_else(|| anyhow::anyhow!("Transaction not found: {}", transaction_id))?;
+
+        let mut steps = Vec::new();
+        for (index, operation) in transaction.operations.iter().enumerate() {
+            let box_type = operation
+                .box_type
+                .clone()
+                .or_else(|| default_box().map(str::to_string));
+            let manager = box_type.as_deref().and_then(|b| box_manager(b).ok());
+
+            let installed_version = manager
+                .as_ref()
+                .and_then(|m| m.get_installed_version(&operation.package).ok().flatten());
+            let download_size = match (&operation.operation_type, &box_type) {
+                (OperationType::InstallPackage | OperationType::UpdatePackage, Some(box_type)) => {
+                    DependencyResolver::get_package_size(&operation.package, box_type)
+                        .await
+                        .ok()
+                        .filter(|size| *size > 0)
                 }
+                _ => None,
+            };
+
+            steps.push(PlannedStep {
+                order: index + 1,
+                operation_type: operation.operation_type.clone(),
+                package: operation.package.clone(),
+                box_type,
+                installed_version,
+                expected_version: match operation.operation_type {
+                    OperationType::RemovePackage => None,
+                    _ => operation.version.clone(),
+                },
+                requires_root: manager.as_ref().is_some_and(|m| m.needs_privilege()),
+                download_size,
+            });
+        }
+
+        Ok(TransactionPlan::new(transaction_id, steps))
+    }
+
+    /// Rollback a transaction: undo its completed operations, last first,
+    /// whichever box ran them. Operations that cannot be undone are reported
+    /// and leave the transaction Failed, so the rollback can be retried.
+    pub async fn rollback_transaction(&mut self, transaction_id: Uuid) -> Result<()> {
+        let Some(transaction) = self.active_transactions.get(&transaction_id) else {
+            return Err(anyhow::anyhow!("Transaction not found: {}", transaction_id));
+        };
+        info!("Rolling back transaction: {}", transaction_id);
+
+        let (previous_state, backup_files) = transaction
+            .rollback_data
+            .as_ref()
+            .map(|r| (r.previous_state.clone(), r.backup_files.clone()))
+            .unwrap_or_default();
+        let mut operations = transaction.operations.clone();
+        let lanes: Vec<String> = operations.iter().map(lane).collect();
+        let order = run_order(&operations, &lanes)?;
+
+        let mut failures = Vec::new();
+        for index in order.into_iter().rev() {
+            if operations[index].status != OperationStatus::Completed {
+                continue;
+            }
+            let previous = previous_state
+                .get(&state_key(&lanes[index], &operations[index].package))
+                .map(String::as_str);
+            let Some(reversal) = reversal(&operations[index], previous) else {
+                operations[index].status = OperationStatus::RolledBack;
+                continue;
+            };
+
+            let undo = reversal.operation(&operations[index], &lanes[index]);
+            let backup = match undo.operation_type {
+                OperationType::InstallPackage if lanes[index] == "appimage" => {
+                    appimage_backup(&backup_files, &undo.package)
+                }
+                _ => None,
+            };
+            let result = match backup {
+                Some(backup) => Self::restore_appimage(&self.db, &undo, backup).await,
+                None => Self::execute_box_operation(&self.db, &undo, &lanes[index]).await,
+            };
+            // A removed package whose version neither the cache nor the
+            // repositories have any more comes back at the current version
+            let result = match result {
+                Err(e)
+                    if undo.version.is_some()
+                        && operations[index].operation_type == OperationType::RemovePackage =>
+                {
+                    warn!(
+                        "Could not reinstall {} {}: {}; installing the current version",
+                        undo.package,
+                        undo.version.as_deref().unwrap_or_default(),
+                        e
+                    );
+                    let undo = Operation {
+                        version: None,
+                        ..undo
+                    };
+                    Self::execute_box_operation(&self.db, &undo, &lanes[index]).await
+                }
+                result => result,
+            };
+            match result {
+                Ok(()) => operations[index].status = OperationStatus::RolledBack,
+                Err(e) => {
+                    warn!("Could not undo {}: {}", operations[index].package, e);
+                    failures.push(format!("{} ({})", operations[index].package, e));
+                }
+            }
+            if let Err(e) = self.journal_operations(transaction_id, &operations).await {
+                warn!("Could not journal transaction {}: {}", transaction_id, e);
             }
+        }
 
-            transaction.status = TransactionStatus::RolledBack;
+        if let Some(transaction) = self.active_transactions.get_mut(&transaction_id) {
+            transaction.operations = operations;
+            transaction.status = if failures.is_empty() {
+                TransactionStatus::RolledBack
+            } else {
+                TransactionStatus::Failed
+            };
             transaction.completed_at = Some(Utc::now());
+        }
+        self.journal(transaction_id).await?;
 
-            info!("Transaction {} rolled back successfully", transaction_id);
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Transaction not found: {}", transaction_id))
+        if !failures.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Could not undo {}; retry with 'omni transaction rollback {}'",
+                failures.join(", "),
+                transaction_id
+            ));
         }
+        remove_backups(transaction_id);
+        info!("Transaction {} rolled back successfully", transaction_id);
+        Ok(())
     }
 
     /// Commit a transaction (finalize)
@@ -232,58 +735,21 @@ impl TransactionManager {
         }
     }
 
-    async fn execute_operation(&self, operation: &Operation) -> Result<()> {
-        use crate::boxes::{
-            apt::AptManager, brew::BrewBox, dnf::DnfBox, snap::SnapBox, winget::WingetBox,
-        };
-        use crate::distro::PackageManager;
+    async fn execute_operation(db: &Database, operation: &Operation) -> Result<()> {
+        if let Some(box_type) = &operation.box_type {
+            return Self::execute_box_operation(db, operation, box_type).await;
+        }
 
         match operation.operation_type {
             OperationType::InstallPackage => {
                 info!("Installing package: {}", operation.package);
 
-                // Detect and use appropriate package manager using trait methods
-                if AptManager::is_available() {
-                    let apt = AptManager::new()?;
-                    apt.install(&operation.package)
-                } else if DnfBox::is_available() {
-                    let dnf = DnfBox::new()?;
-                    dnf.install(&operation.package)
-                } else if WingetBox::is_available() {
-                    let winget = WingetBox::new()?;
-                    winget.install(&operation.package)
-                } else if BrewBox::is_available() {
-                    let brew = BrewBox::new()?;
-                    brew.install(&operation.package)
-                } else if SnapBox::is_available() {
-                    let snap = SnapBox::new()?;
-                    snap.install(&operation.package)
-                } else {
-                    Err(anyhow::anyhow!("No supported package manager found"))
-                }
+                default_box_manager()?.install(&operation.package)
             }
             OperationType::RemovePackage => {
                 info!("Removing package: {}", operation.package);
 
-                // Use trait methods for consistent interface
-                if AptManager::is_available() {
-                    let apt = AptManager::new()?;
-                    apt.remove(&operation.package)
-                } else if DnfBox::is_available() {
-                    let dnf = DnfBox::new()?;
-                    dnf.remove(&operation.package)
-                } else if WingetBox::is_available() {
-                    let winget = WingetBox::new()?;
-                    winget.remove(&operation.package)
-                } else if BrewBox::is_available() {
-                    let brew = BrewBox::new()?;
-                    brew.remove(&operation.package)
-                } else if SnapBox::is_available() {
-                    let snap = SnapBox::new()?;
-                    snap.remove(&operation.package)
-                } else {
-                    Err(anyhow::anyhow!("No supported package manager found"))
-                }
+                default_box_manager()?.remove(&operation.package)
             }
             OperationType::UpdatePackage => {
                 info!("Updating package: {}", operation.package);
@@ -303,19 +769,261 @@ impl TransactionManager {
         }
     }
 
-    async fn create_rollback_data(&self) -> Result<RollbackData> {
-        Ok(RollbackData {
-            snapshot_id: Some(format!("snapshot_{}", Uuid::new_v4())),
-            previous_state: HashMap::new(),
-            backup_files: Vec::new(),
-        })
+    /// Run `operation` with the box it names and record it in the install history
+    async fn execute_box_operation(
+        db: &Database,
+        operation: &Operation,
+        box_type: &str,
+    ) -> Result<()> {
+        let package = operation.package.as_str();
+        let (status, metadata) = if box_type == "appimage" {
+            Self::run_appimage_operation(operation).await?
+        } else {
+            Self::run_package_operation(operation, box_type)?
+        };
+        Self::record_operation(db, operation, box_type, status, metadata).await
     }
 
-    async fn persist_transaction(&self, _transaction: &Transaction) -> Result<()> {
-        // Implementation would persist to database
+    /// Put back the copy of an AppImage kept before the transaction replaced
+    /// or removed it
+    async fn restore_appimage(db: &Database, operation: &Operation, backup: &str) -> Result<()> {
+        appimage::install_appimage_file(Path::new(backup), &operation.package)?;
+        Self::record_operation(
+            db,
+            operation,
+            "appimage",
+            InstallStatus::Success,
+            "Restored by transaction rollback".to_string(),
+        )
+        .await
+    }
+
+    async fn record_operation(
+        db: &Database,
+        operation: &Operation,
+        box_type: &str,
+        status: InstallStatus,
+        metadata: String,
+    ) -> Result<()> {
+        let package = operation.package.as_str();
+        let provenance = provenance::detect(package, box_type);
+        db.record_install(&InstallRecord {
+            id: Uuid::new_v4().to_string(),
+            package_name: package.to_string(),
+            box_type: box_type.to_string(),
+            version: operation.version.clone(),
+            source_url: operation.source.clone(),
+            install_path: None,
+            installed_at: Utc::now(),
+            status: status.clone(),
+            repository: provenance.repository,
+            mirror_url: provenance.mirror_url,
+            channel: None,
+            metadata: Some(metadata),
+        })
+        .await?;
+
+        // What the package came from, for `omni verify` and for reinstalling
+        // an AppImage when a rollback undoes its removal
+        if matches!(status, InstallStatus::Removed) {
+            let _ = db.forget_artifact(package, box_type).await;
+        } else {
+            let artifact = artifacts::capture(
+                package,
+                box_type,
+                operation.version.as_deref(),
+                operation.source.as_deref(),
+            );
+            let _ = db.record_artifact(&artifact).await;
+        }
         Ok(())
     }
 
+    fn run_package_operation(
+        operation: &Operation,
+        box_type: &str,
+    ) -> Result<(InstallStatus, String)> {
+        let package = operation.package.as_str();
+        Ok(match (&operation.operation_type, &operation.version) {
+            (OperationType::InstallPackage | OperationType::UpdatePackage, Some(version)) => {
+                info!("Installing {} {} via {}", package, version, box_type);
+                let (command, args) =
+                    downgrade::install_version_command(package, box_type, version)?;
+                let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+                PrivilegeManager::new().execute_with_sudo(&command, &args)?;
+                let status = if operation.operation_type == OperationType::InstallPackage {
+                    InstallStatus::Success
+                } else {
+                    InstallStatus::Updated
+                };
+                (status, format!("Set to {} by transaction", version))
+            }
+            (OperationType::InstallPackage, None) => {
+                info!("Installing {} via {}", package, box_type);
+                box_manager(box_type)?.install(package)?;
+                (
+                    InstallStatus::Success,
+                    "Installed by transaction".to_string(),
+                )
+            }
+            (OperationType::RemovePackage, _) => {
+                info!("Removing {} via {}", package, box_type);
+                box_manager(box_type)?.remove(package)?;
+                (InstallStatus::Removed, "Removed by transaction".to_string())
+            }
+            (OperationType::UpdatePackage, None) => {
+                info!("Updating {} via {}", package, box_type);
+                box_manager(box_type)?.update(Some(package))?;
+                (InstallStatus::Updated, "Updated by transaction".to_string())
+            }
+            (OperationType::CreateSnapshot | OperationType::ModifyConfig, _) => {
+                return Err(anyhow::anyhow!(
+                    "{:?} does not run with a box",
+                    operation.operation_type
+                ));
+            }
+        })
+    }
+
+    /// AppImages are files omni downloads itself, from the operation's source
+    async fn run_appimage_operation(operation: &Operation) -> Result<(InstallStatus, String)> {
+        let package = operation.package.as_str();
+        match operation.operation_type {
+            OperationType::InstallPackage | OperationType::UpdatePackage => {
+                let url = operation.source.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "The AppImage {} needs a URL to download it from: appimage:{}=<url>",
+                        package,
+                        package
+                    )
+                })?;
+                info!("Installing AppImage {} from {}", package, url);
+                appimage::install_appimage(url, package).await?;
+                let status = if operation.operation_type == OperationType::InstallPackage {
+                    InstallStatus::Success
+                } else {
+                    InstallStatus::Updated
+                };
+                Ok((status, "Installed by transaction".to_string()))
+            }
+            OperationType::RemovePackage => {
+                info!("Removing AppImage {}", package);
+                appimage::remove_appimage(package)?;
+                Ok((InstallStatus::Removed, "Removed by transaction".to_string()))
+            }
+            OperationType::CreateSnapshot | OperationType::ModifyConfig => Err(anyhow::anyhow!(
+                "{:?} does not run with a box",
+                operation.operation_type
+            )),
+        }
+    }
+
+    /// Record what each package the transaction touches is at now, so a
+    /// rollback can put it back
+    async fn create_rollback_data(
+        &self,
+        transaction_id: Uuid,
+        operations: &[Operation],
+        lanes: &[String],
+    ) -> RollbackData {
+        let mut previous_state = HashMap::new();
+        for (operation, box_type) in operations.iter().zip(lanes) {
+            let key = state_key(box_type, &operation.package);
+            if previous_state.contains_key(&key) {
+                continue;
+            }
+            if let Some(version) = self.installed_state(&operation.package, box_type).await {
+                previous_state.insert(key, version);
+            }
+        }
+
+        // AppImages are in no package cache, so keep a copy of each one the
+        // transaction touches until it is done
+        let mut backup_files = Vec::new();
+        for (operation, box_type) in operations.iter().zip(lanes) {
+            if box_type != "appimage"
+                || appimage_backup(&backup_files, &operation.package).is_some()
+            {
+                continue;
+            }
+            match backup_appimage(transaction_id, &operation.package) {
+                Ok(Some(backup)) => backup_files.push(backup),
+                Ok(None) => {}
+                Err(e) => warn!(
+                    "Could not keep a copy of the AppImage {}: {}",
+                    operation.package, e
+                ),
+            }
+        }
+
+        RollbackData {
+            snapshot_id: None,
+            previous_state,
+            backup_files,
+        }
+    }
+
+    /// The installed version of a package, in the form the box can reinstall
+    /// (a snap revision, a flatpak commit); for an AppImage, its URL
+    async fn installed_state(&self, package: &str, box_type: &str) -> Option<String> {
+        if box_type == "appimage" {
+            if !appimage::appimage_path(package).ok()?.exists() {
+                return None;
+            }
+            let url = self
+                .db
+                .get_artifacts(Some(package))
+                .await
+                .ok()
+                .and_then(|artifacts| {
+                    artifacts
+                        .into_iter()
+                        .find(|a| a.box_type == "appimage")
+                        .and_then(|a| a.url)
+                });
+            return Some(url.unwrap_or_default());
+        }
+
+        match downgrade::query_versions(package, box_type) {
+            Ok(listing) => listing.installed,
+            Err(_) => box_manager(box_type)
+                .ok()?
+                .get_installed_version(package)
+                .ok()
+                .flatten(),
+        }
+    }
+
+    async fn persist_transaction(&self, transaction: &Transaction) -> Result<()> {
+        self.db
+            .save_transaction(
+                &transaction.id.to_string(),
+                transaction.status.as_str(),
+                &serde_json::to_string(transaction)?,
+                transaction.created_at,
+            )
+            .await
+    }
+
+    /// Write the journal of an active transaction
+    pub async fn journal(&self, transaction_id: Uuid) -> Result<()> {
+        match self.active_transactions.get(&transaction_id) {
+            Some(transaction) => self.persist_transaction(transaction).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn journal_operations(
+        &mut self,
+        transaction_id: Uuid,
+        operations: &[Operation],
+    ) -> Result<()> {
+        if let Some(transaction) = self.active_transactions.get_mut(&transaction_id) {
+            transaction.operations = operations.to_vec();
+        }
+        self.journal(transaction_id).await
+    }
+
     /// Get transaction status
     pub fn get_transaction_status(&self, transaction_id: Uuid) -> Option<TransactionStatus> {
         self.active_transactions
@@ -328,3 +1036,691 @@ impl TransactionManager {
         self.active_transactions.values().collect()
     }
 }
+
+/// Point out transactions a killed omni left in progress. Call it while
+/// holding the operations lock, when no other omni can be running them.
+pub async fn warn_interrupted() {
+    let interrupted = match TransactionManager::new().await {
+        Ok(manager) => manager.interrupted().await,
+        Err(e) => Err(e),
+    };
+    match interrupted {
+        Ok(transactions) => {
+            for transaction in transactions {
+                let done = transaction
+                    .operations
+                    .iter()
+                    .filter(|o| o.status == OperationStatus::Completed)
+                    .count();
+                eprintln!(
+                    "⚠️  Transaction {} was interrupted after {} of {} operations; \
+                     run 'omni transaction resume {}' or 'omni transaction rollback {}'",
+                    transaction.id,
+                    done,
+                    transaction.operations.len(),
+                    transaction.id,
+                    transaction.id
+                );
+            }
+        }
+        Err(e) => warn!("Could not check for interrupted transactions: {}", e),
+    }
+}
+
+/// Box an operation without one runs with: the first of these available
+fn default_box() -> Option<&'static str> {
+    if AptManager::is_available() {
+        Some("apt")
+    } else if DnfBox::is_available() {
+        Some("dnf")
+    } else if WingetBox::is_available() {
+        Some("winget")
+    } else if BrewBox::is_available() {
+        Some("brew")
+    } else if SnapBox::is_available() {
+        Some("snap")
+    } else {
+        None
+    }
+}
+
+fn default_box_manager() -> Result<Box<dyn PackageManager>> {
+    box_manager(default_box().ok_or_else(|| anyhow::anyhow!("No supported package manager found"))?)
+}
+
+/// Operations in the same lane run one at a time: those of one box, with
+/// the ones that name no box in the default box's lane
+fn lane(operation: &Operation) -> String {
+    operation
+        .box_type
+        .clone()
+        .or_else(|| default_box().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Settle the operations a killed omni had started: done when the box shows
+/// their result, otherwise still to run
+fn settle_interrupted(operations: &mut [Operation]) {
+    for operation in operations {
+        if operation.status != OperationStatus::InProgress {
+            continue;
+        }
+        let box_type = lane(operation);
+        let installed = if box_type == "appimage" {
+            appimage::appimage_path(&operation.package)
+                .map(|path| path.exists().then(|| "AppImage".to_string()))
+        } else {
+            box_manager(&box_type).and_then(|m| m.get_installed_version(&operation.package))
+        };
+        operation.status = match installed {
+            Ok(installed) => interrupted_status(operation, installed.as_deref()),
+            Err(_) => OperationStatus::Pending,
+        };
+    }
+}
+
+/// Where copies of AppImages are kept while a transaction runs
+fn backup_dir(transaction_id: Uuid) -> Result<PathBuf> {
+    Ok(OmniConfig::cache_dir()?
+        .join("transactions")
+        .join(transaction_id.to_string()))
+}
+
+/// Copy an installed AppImage into the transaction's backup directory
+fn backup_appimage(transaction_id: Uuid, package: &str) -> Result<Option<String>> {
+    let installed = appimage::appimage_path(package)?;
+    if !installed.exists() {
+        return Ok(None);
+    }
+    let dir = backup_dir(transaction_id)?;
+    std::fs::create_dir_all(&dir)?;
+    let backup = dir.join(format!("{}.AppImage", package));
+    std::fs::copy(&installed, &backup)?;
+    Ok(Some(backup.display().to_string()))
+}
+
+/// The kept copy of an AppImage, if there is one
+fn appimage_backup<'a>(backup_files: &'a [String], package: &str) -> Option<&'a str> {
+    let file_name = format!("{}.AppImage", package);
+    backup_files
+        .iter()
+        .map(String::as_str)
+        .find(|backup| Path::new(backup).file_name() == Some(OsStr::new(&file_name)))
+        .filter(|backup| Path::new(backup).exists())
+}
+
+/// Drop the copies kept for a transaction that no longer needs them; a
+/// later rollback downloads AppImages again
+fn remove_backups(transaction_id: Uuid) {
+    if let Ok(dir) = backup_dir(transaction_id) {
+        if dir.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                warn!("Could not remove {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+/// Key of a package in [`RollbackData::previous_state`]
+fn state_key(box_type: &str, package: &str) -> String {
+    format!("{}:{}", box_type, package)
+}
+
+/// What undoes a completed operation
+#[derive(Debug, PartialEq, Eq)]
+enum Reversal {
+    Remove,
+    /// Install this version again; for an AppImage, download it from this URL
+    Install(Option<String>),
+}
+
+impl Reversal {
+    /// The operation that performs the reversal of `operation`
+    fn operation(&self, operation: &Operation, box_type: &str) -> Operation {
+        let (operation_type, version) = match self {
+            Reversal::Remove => (OperationType::RemovePackage, None),
+            Reversal::Install(version) => (OperationType::InstallPackage, version.clone()),
+        };
+        let (version, source) = if box_type == "appimage" {
+            (None, version)
+        } else {
+            (version, None)
+        };
+        Operation {
+            id: Uuid::new_v4(),
+            operation_type,
+            package: operation.package.clone(),
+            box_type: Some(box_type.to_string()),
+            version,
+            source,
+            depends_on: Vec::new(),
+            status: OperationStatus::Pending,
+            error: None,
+        }
+    }
+}
+
+/// How to undo a completed operation, given what was installed before the
+/// transaction; None when it left the package as it was
+fn reversal(operation: &Operation, previous: Option<&str>) -> Option<Reversal> {
+    // An AppImage whose URL omni never recorded
+    let previous_version = previous.filter(|p| !p.is_empty()).map(str::to_string);
+    match (&operation.operation_type, previous) {
+        (OperationType::InstallPackage, None) => Some(Reversal::Remove),
+        // Installing what was already there changes nothing
+        (OperationType::InstallPackage, Some(_)) if operation.version.is_none() => None,
+        (OperationType::InstallPackage | OperationType::UpdatePackage, Some(previous)) => {
+            (operation.version.as_deref() != Some(previous))
+                .then(|| Reversal::Install(previous_version))
+        }
+        (OperationType::RemovePackage, Some(_)) => Some(Reversal::Install(previous_version)),
+        (OperationType::RemovePackage | OperationType::UpdatePackage, None)
+        | (OperationType::CreateSnapshot | OperationType::ModifyConfig, _) => None,
+    }
+}
+
+/// Whether an operation a killed omni had started got done, going by the
+/// version its box now reports
+fn interrupted_status(operation: &Operation, installed_version: Option<&str>) -> OperationStatus {
+    let done = match (&operation.operation_type, &operation.version) {
+        (OperationType::InstallPackage | OperationType::UpdatePackage, Some(version)) => {
+            installed_version == Some(version.as_str())
+        }
+        (OperationType::InstallPackage, None) => installed_version.is_some(),
+        (OperationType::RemovePackage, _) => installed_version.is_none(),
+        // Updating to the latest version again does no harm
+        _ => false,
+    };
+    if done {
+        OperationStatus::Completed
+    } else {
+        OperationStatus::Pending
+    }
+}
+
+/// Operations that can start: the first not yet started in each lane nothing
+/// is running in, once the operations it depends on are done
+fn ready(
+    operations: &[Operation],
+    lanes: &[String],
+    done: &HashSet<Uuid>,
+    busy: &HashSet<String>,
+) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    operations
+        .iter()
+        .enumerate()
+        .filter(|(_, o)| o.status == OperationStatus::Pending)
+        .filter(|(index, _)| seen.insert(lanes[*index].as_str()))
+        .filter(|(index, o)| {
+            !busy.contains(&lanes[*index]) && o.depends_on.iter().all(|d| done.contains(d))
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// An order the operations can run in, one after another, counting the order
+/// within each lane. Fails when an operation depends on one outside the
+/// transaction, or when operations wait on each other.
+fn run_order(operations: &[Operation], lanes: &[String]) -> Result<Vec<usize>> {
+    let index_of: HashMap<Uuid, usize> = operations
+        .iter()
+        .enumerate()
+        .map(|(index, o)| (o.id, index))
+        .collect();
+
+    let mut waits_for: Vec<Vec<usize>> = vec![Vec::new(); operations.len()];
+    let mut last_in_lane: HashMap<&str, usize> = HashMap::new();
+    for (index, operation) in operations.iter().enumerate() {
+        for dependency in &operation.depends_on {
+            let Some(&before) = index_of.get(dependency) else {
+                return Err(anyhow::anyhow!(
+                    "{} depends on operation {}, which is not in the transaction",
+                    operation.package,
+                    dependency
+                ));
+            };
+            waits_for[index].push(before);
+        }
+        if let Some(before) = last_in_lane.insert(lanes[index].as_str(), index) {
+            waits_for[index].push(before);
+        }
+    }
+
+    // Kahn's algorithm; whatever is left over is in a cycle
+    let mut blocking = vec![0; operations.len()];
+    let mut unblocks: Vec<Vec<usize>> = vec![Vec::new(); operations.len()];
+    for (index, befores) in waits_for.iter().enumerate() {
+        blocking[index] = befores.len();
+        for &before in befores {
+            unblocks[before].push(index);
+        }
+    }
+    let mut queue: VecDeque<usize> = (0..operations.len())
+        .filter(|i| blocking[*i] == 0)
+        .collect();
+    let mut ordered = Vec::with_capacity(operations.len());
+    while let Some(index) = queue.pop_front() {
+        ordered.push(index);
+        for &after in &unblocks[index] {
+            blocking[after] -= 1;
+            if blocking[after] == 0 {
+                queue.push_back(after);
+            }
+        }
+    }
+
+    if ordered.len() < operations.len() {
+        let stuck: Vec<&str> = operations
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| blocking[*index] > 0)
+            .map(|(_, o)| o.package.as_str())
+            .collect();
+        return Err(anyhow::anyhow!(
+            "Operations wait on each other and can never run: {}",
+            stuck.join(", ")
+        ));
+    }
+    Ok(ordered)
+}
+
+/// Parse `[box:]package[=version]`, as `omni transaction` takes operations.
+/// The part before the first `:` is only a box when it names one, so
+/// `libc6:i386` is a package.
+pub fn parse_operation_spec(spec: &str) -> (Option<String>, String, Option<String>) {
+    let (target, version) = match spec.split_once('=') {
+        Some((target, version)) if !version.is_empty() => (target, Some(version.to_string())),
+        _ => (spec.trim_end_matches('='), None),
+    };
+    match target.split_once(':') {
+        Some((box_type, package)) if BOXES.contains(&box_type) && !package.is_empty() => {
+            (Some(box_type.to_string()), package.to_string(), version)
+        }
+        _ => (None, target.to_string(), version),
+    }
+}
+
+/// Print a plan as `omni transaction plan` shows it
+pub fn print_plan(plan: &TransactionPlan) {
+    println!("📋 Transaction {}\n", plan.transaction_id);
+    for step in &plan.steps {
+        let action = action(&step.operation_type);
+        let versions = match (&step.installed_version, &step.expected_version) {
+            (Some(from), Some(to)) => format!("{} → {}", from, to),
+            (Some(from), None) if step.operation_type == OperationType::RemovePackage => {
+                from.clone()
+            }
+            (Some(from), None) => format!("{} → latest", from),
+            (None, Some(to)) => to.clone(),
+            (None, None) if step.operation_type == OperationType::RemovePackage => {
+                "not installed".to_string()
+            }
+            (None, None) => "latest".to_string(),
+        };
+        let mut notes = Vec::new();
+        if step.requires_root {
+            notes.push("root".to_string());
+        }
+        if let Some(size) = step.download_size {
+            notes.push(DependencyResolver::format_size(size));
+        }
+        println!(
+            "  {}. {} {} via {} ({}){}",
+            step.order,
+            action,
+            step.package,
+            step.box_type.as_deref().unwrap_or("no available box"),
+            versions,
+            if notes.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", notes.join(", "))
+            }
+        );
+    }
+
+    println!();
+    if let Some(size) = plan.download_size {
+        println!("Download: {}", DependencyResolver::format_size(size));
+    }
+    if plan.requires_root {
+        println!("Needs root: yes, omni runs those steps with sudo");
+    }
+    if plan.steps.iter().any(|s| s.box_type.is_none()) {
+        println!("⚠️  Some steps have no box to run them on this system");
+    }
+}
+
+/// Print a journaled transaction as `omni transaction show` shows it
+pub fn print_transaction(transaction: &Transaction) {
+    println!(
+        "📋 Transaction {} ({:?}, {})",
+        transaction.id,
+        transaction.transaction_type,
+        transaction.status.as_str()
+    );
+    println!(
+        "Created: {}",
+        transaction.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    if let Some(completed_at) = transaction.completed_at {
+        println!("Finished: {}", completed_at.format("%Y-%m-%d %H:%M:%S UTC"));
+    }
+    println!();
+
+    if transaction.operations.is_empty() {
+        println!(
+            "  No operations; add some with 'omni transaction add {}'",
+            transaction.id
+        );
+    }
+    for (index, operation) in transaction.operations.iter().enumerate() {
+        let version = match (&operation.operation_type, &operation.version) {
+            (OperationType::RemovePackage, _) | (_, None) => String::new(),
+            (_, Some(version)) => format!("={}", version),
+        };
+        println!(
+            "  {}. {} {}{} via {} [{:?}]",
+            index + 1,
+            action(&operation.operation_type),
+            operation.package,
+            version,
+            operation.box_type.as_deref().unwrap_or("the default box"),
+            operation.status
+        );
+        if let Some(error) = &operation.error {
+            println!("     {}", error);
+        }
+    }
+}
+
+fn action(operation_type: &OperationType) -> &'static str {
+    match operation_type {
+        OperationType::InstallPackage => "install",
+        OperationType::RemovePackage => "remove",
+        OperationType::UpdatePackage => "update",
+        OperationType::CreateSnapshot => "snapshot",
+        OperationType::ModifyConfig => "configure",
+    }
+}
+
+/// Boxes an operation can name
+pub const BOXES: &[&str] = &[
+    "appimage",
+    "apt",
+    "dnf",
+    "pacman",
+    "snap",
+    "flatpak",
+    "brew",
+    "mas",
+    "winget",
+    "chocolatey",
+    "scoop",
+    "zypper",
+    "emerge",
+    "nix",
+];
+
+/// The package manager behind `box_type`
+fn box_manager(box_type: &str) -> Result<Box<dyn PackageManager>> {
+    Ok(match box_type {
+        "apt" => Box::new(AptManager::new()?),
+        "dnf" => Box::new(DnfBox::new()?),
+        "pacman" => Box::new(PacmanBox::new()?),
+        "snap" => Box::new(SnapBox::new()?),
+        "flatpak" => Box::new(FlatpakBox::new()?),
+        "brew" => Box::new(BrewBox::new()?),
+        "mas" => Box::new(MasBox::new()?),
+        "winget" => Box::new(WingetBox::new()?),
+        "chocolatey" => Box::new(ChocolateyBox::new()?),
+        "scoop" => Box::new(ScoopBox::new()?),
+        "zypper" => Box::new(ZypperBox::new()?),
+        "emerge" => Box::new(EmergeBox::new()?),
+        "nix" => Box::new(NixBox::new()?),
+        other => return Err(anyhow::anyhow!("Unsupported box type: {}", other)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(package: &str, box_type: &str) -> Operation {
+        Operation {
+            id: Uuid::new_v4(),
+            operation_type: OperationType::InstallPackage,
+            package: package.to_string(),
+            box_type: Some(box_type.to_string()),
+            version: None,
+            source: None,
+            depends_on: Vec::new(),
+            status: OperationStatus::Pending,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_ready_runs_boxes_side_by_side() {
+        let mut operations = vec![
+            operation("libssl3", "apt"),
+            operation("nginx", "apt"),
+            operation("org.gimp.GIMP", "flatpak"),
+            operation("certbot", "snap"),
+        ];
+        let id = operations[1].id;
+        operations[3].depends_on.push(id);
+        let lanes: Vec<String> = operations.iter().map(lane).collect();
+        let mut done = HashSet::new();
+        let mut busy = HashSet::new();
+
+        // One per box, in order, and certbot waits for nginx
+        assert_eq!(ready(&operations, &lanes, &done, &busy), vec![0, 2]);
+
+        operations[0].status = OperationStatus::InProgress;
+        busy.insert("apt".to_string());
+        assert_eq!(ready(&operations, &lanes, &done, &busy), vec![2]);
+
+        operations[0].status = OperationStatus::Completed;
+        done.insert(operations[0].id);
+        busy.clear();
+        assert_eq!(ready(&operations, &lanes, &done, &busy), vec![1, 2]);
+
+        operations[1].status = OperationStatus::Completed;
+        done.insert(operations[1].id);
+        assert_eq!(ready(&operations, &lanes, &done, &busy), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_run_order() {
+        let mut operations = vec![
+            operation("certbot", "snap"),
+            operation("nginx", "apt"),
+            operation("libssl3", "apt"),
+        ];
+        let lanes: Vec<String> = operations.iter().map(lane).collect();
+        assert!(run_order(&operations, &lanes).is_ok());
+
+        // certbot after nginx, and libssl3 after nginx since apt runs them in order
+        let id = operations[1].id;
+        operations[0].depends_on.push(id);
+        let order = run_order(&operations, &lanes).unwrap();
+        let position = |index| order.iter().position(|i| *i == index).unwrap();
+        assert_eq!(order.len(), 3);
+        assert!(position(1) < position(0) && position(1) < position(2));
+
+        // nginx waiting for libssl3, which apt only runs after nginx, never runs
+        let id = operations[2].id;
+        operations[1].depends_on.push(id);
+        let message = run_order(&operations, &lanes).unwrap_err().to_string();
+        assert!(message.contains("nginx") && message.contains("libssl3"));
+
+        operations[1].depends_on = vec![Uuid::new_v4()];
+        assert!(run_order(&operations, &lanes).is_err());
+    }
+
+    #[test]
+    fn test_reversal() {
+        let install = operation("nginx", "apt");
+        assert_eq!(reversal(&install, None), Some(Reversal::Remove));
+        assert_eq!(reversal(&install, Some("1.22.1-9")), None);
+
+        let pinned = Operation {
+            version: Some("1.24.0-2".to_string()),
+            ..operation("nginx", "apt")
+        };
+        assert_eq!(
+            reversal(&pinned, Some("1.22.1-9")),
+            Some(Reversal::Install(Some("1.22.1-9".to_string())))
+        );
+        assert_eq!(reversal(&pinned, Some("1.24.0-2")), None);
+
+        let update = Operation {
+            operation_type: OperationType::UpdatePackage,
+            ..operation("nginx", "apt")
+        };
+        assert_eq!(
+            reversal(&update, Some("1.22.1-9")),
+            Some(Reversal::Install(Some("1.22.1-9".to_string())))
+        );
+
+        let remove = Operation {
+            operation_type: OperationType::RemovePackage,
+            ..operation("Obsidian", "appimage")
+        };
+        assert_eq!(reversal(&remove, None), None);
+        assert_eq!(
+            reversal(&remove, Some("https://example.com/Obsidian.AppImage")),
+            Some(Reversal::Install(Some(
+                "https://example.com/Obsidian.AppImage".to_string()
+            )))
+        );
+        // Removed, but omni never knew where it came from
+        assert_eq!(reversal(&remove, Some("")), Some(Reversal::Install(None)));
+
+        let undo = Reversal::Install(Some("https://example.com/Obsidian.AppImage".to_string()))
+            .operation(&remove, "appimage");
+        assert_eq!(undo.operation_type, OperationType::InstallPackage);
+        assert_eq!(undo.version, None);
+        assert_eq!(
+            undo.source.as_deref(),
+            Some("https://example.com/Obsidian.AppImage")
+        );
+    }
+
+    #[test]
+    fn test_appimage_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let kept = dir.path().join("Obsidian.AppImage");
+        std::fs::write(&kept, b"AppImage").unwrap();
+        let backups = vec![
+            dir.path().join("Gone.AppImage").display().to_string(),
+            kept.display().to_string(),
+        ];
+
+        assert_eq!(
+            appimage_backup(&backups, "Obsidian"),
+            Some(kept.to_str().unwrap())
+        );
+        assert_eq!(appimage_backup(&backups, "Obsid"), None);
+        // Listed, but no longer on disk
+        assert_eq!(appimage_backup(&backups, "Gone"), None);
+    }
+
+    #[test]
+    fn test_interrupted_status() {
+        let install = operation("nginx", "apt");
+        assert_eq!(
+            interrupted_status(&install, Some("1.24.0-2")),
+            OperationStatus::Completed
+        );
+        assert_eq!(interrupted_status(&install, None), OperationStatus::Pending);
+
+        let pinned = Operation {
+            version: Some("1.24.0-2".to_string()),
+            ..operation("nginx", "apt")
+        };
+        assert_eq!(
+            interrupted_status(&pinned, Some("1.22.1-9")),
+            OperationStatus::Pending
+        );
+
+        let remove = Operation {
+            operation_type: OperationType::RemovePackage,
+            ..operation("nginx", "apt")
+        };
+        assert_eq!(
+            interrupted_status(&remove, None),
+            OperationStatus::Completed
+        );
+        assert_eq!(
+            interrupted_status(&remove, Some("1.24.0-2")),
+            OperationStatus::Pending
+        );
+
+        let update = Operation {
+            operation_type: OperationType::UpdatePackage,
+            ..operation("nginx", "apt")
+        };
+        assert_eq!(
+            interrupted_status(&update, Some("1.24.0-2")),
+            OperationStatus::Pending
+        );
+    }
+
+    #[test]
+    fn test_status_from_name() {
+        assert_eq!(
+            TransactionStatus::from_name("in-progress"),
+            Some(TransactionStatus::InProgress)
+        );
+        assert_eq!(
+            TransactionStatus::from_name("RolledBack"),
+            Some(TransactionStatus::RolledBack)
+        );
+        assert_eq!(
+            TransactionStatus::from_name("pending"),
+            Some(TransactionStatus::Pending)
+        );
+        assert_eq!(TransactionStatus::from_name("done"), None);
+    }
+
+    #[test]
+    fn test_parse_operation_spec() {
+        assert_eq!(
+            parse_operation_spec("firefox"),
+            (None, "firefox".to_string(), None)
+        );
+        assert_eq!(
+            parse_operation_spec("apt:vim=2:9.1.0016-1"),
+            (
+                Some("apt".to_string()),
+                "vim".to_string(),
+                Some("2:9.1.0016-1".to_string())
+            )
+        );
+        assert_eq!(
+            parse_operation_spec("libc6:i386"),
+            (None, "libc6:i386".to_string(), None)
+        );
+        assert_eq!(
+            parse_operation_spec(
+                "appimage:Obsidian=https://example.com/get?file=Obsidian.AppImage"
+            ),
+            (
+                Some("appimage".to_string()),
+                "Obsidian".to_string(),
+                Some("https://example.com/get?file=Obsidian.AppImage".to_string())
+            )
+        );
+        assert_eq!(
+            parse_operation_spec("flatpak:org.gimp.GIMP"),
+            (
+                Some("flatpak".to_string()),
+                "org.gimp.GIMP".to_string(),
+                None
+            )
+        );
+    }
+}
@@ -3,12 +3,24 @@ use crate::boxes::dnf::DnfBox;
 use crate::boxes::flatpak::FlatpakBox;
 use crate::boxes::pacman::PacmanBox;
 use crate::boxes::snap::SnapBox;
-use crate::config::OmniConfig;
-use crate::database::{Database, InstallRecord, InstallStatus};
+use crate::changelog;
+use crate::channels::{self, Channel};
+use crate::config::{
+    AutoInstallPolicy, Impact, NotifyEvent, OmniConfig, Severity, UnattendedPolicy,
+};
+use crate::database::{Database, InstallRecord, InstallStatus, PinnedPackage};
 use crate::distro::{self, PackageManager};
+use crate::downgrade;
+use crate::health_checks::{self, LocalShell};
+use crate::impact;
+use crate::input_validation::VersionConstraint;
+use crate::notifications::{self, PackageEvent};
+use crate::privilege_manager::PrivilegeManager;
+use crate::provenance;
 use anyhow::Result;
 use chrono::Utc;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
 use std::process::Command;
 use tracing::{error, info, warn};
 use uuid::Uuid;
@@ -25,8 +37,102 @@ pub struct UpdateCandidate {
     pub current_version: Option<String>,
     pub available_version: Option<String>,
     pub install_record: InstallRecord,
+    /// Security advisory the update fixes, from apt's security archive or
+    /// `dnf updateinfo`
+    pub advisory: Option<Advisory>,
 }
 
+/// A security advisory covering an update
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advisory {
+    /// Advisory ID (`FEDORA-2024-1a2b3c4d5e`), or on apt the archive the fix
+    /// comes from (`noble-security`)
+    pub id: String,
+    /// Advisory severity, when the backend publishes one
+    pub severity: Option<Severity>,
+}
+
+impl std::fmt::Display for Advisory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Some(severity) => write!(f, "{}, {}", self.id, severity),
+            None => f.write_str(&self.id),
+        }
+    }
+}
+
+/// What the backend's advisory metadata says about an update
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SecurityInfo {
+    pub security: bool,
+    /// Advisory severity, when the backend publishes one
+    pub severity: Option<Severity>,
+}
+
+impl From<&Advisory> for SecurityInfo {
+    fn from(advisory: &Advisory) -> Self {
+        Self {
+            security: true,
+            severity: advisory.severity,
+        }
+    }
+}
+
+/// An update an unattended run left for the user, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeferredUpdate {
+    pub package_name: String,
+    pub box_type: String,
+    pub current_version: Option<String>,
+    pub available_version: Option<String>,
+    pub reason: String,
+}
+
+/// Available updates split by the unattended policy
+#[derive(Debug, Default)]
+pub struct UnattendedPlan {
+    /// Every candidate with its advisory metadata
+    pub updates: Vec<(UpdateCandidate, SecurityInfo)>,
+    pub install: Vec<UpdateCandidate>,
+    pub deferred: Vec<DeferredUpdate>,
+}
+
+/// A pacman update that would leave other pending upgrades behind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialUpgrade {
+    /// pacman packages the update asks for
+    pub requested: Vec<String>,
+    /// Packages with an upgrade in the sync database that would stay old
+    pub left_behind: Vec<String>,
+}
+
+/// How to go on with a partial upgrade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialUpgradeChoice {
+    /// Upgrade the whole system with `pacman -Syu` instead
+    FullSync,
+    /// Update the requested packages only
+    Partial,
+    Cancel,
+}
+
+/// Kernel packages across distributions; installing them needs a reboot
+const KERNEL_PACKAGES: &[&str] = &[
+    "linux",
+    "linux-lts",
+    "linux-zen",
+    "linux-hardened",
+    "linux-image-*",
+    "linux-headers-*",
+    "linux-modules-*",
+    "linux-generic*",
+    "kernel",
+    "kernel-core",
+    "kernel-modules*",
+    "kernel-devel",
+    "kernel-headers",
+];
+
 impl UpdateManager {
     pub async fn new(config: OmniConfig) -> Result<Self> {
         let db = Database::new().await?;
@@ -37,24 +143,76 @@ impl UpdateManager {
         info!("Checking for available updates");
 
         let installed_packages = self.db.get_installed_packages().await?;
+        let pinned = self.db.get_pinned_packages().await?;
         let mut candidates = Vec::new();
 
         for package in installed_packages {
+            let pin = pinned
+                .iter()
+                .find(|p| p.package_name == package.package_name && p.box_type == package.box_type);
+            let constraint = match pin.map(pin_constraint) {
+                Some(Some(constraint)) => Some(constraint),
+                Some(None) => {
+                    info!("Skipping pinned package {}", package.package_name);
+                    continue;
+                }
+                None => None,
+            };
+
             if let Ok(candidate) = self.check_package_update(&package).await {
                 if let Some(candidate) = candidate {
-                    candidates.push(candidate);
+                    let candidate = match &constraint {
+                        Some(constraint) => constrained_update(candidate, constraint),
+                        None => Some(candidate),
+                    };
+                    candidates.extend(candidate);
                 }
             }
         }
 
+        self.attach_advisories(&mut candidates);
         info!("Found {} packages with available updates", candidates.len());
         Ok(candidates)
     }
 
+    /// Fill in the advisories of apt and dnf updates, with one query per backend
+    fn attach_advisories(&self, candidates: &mut [UpdateCandidate]) {
+        for box_type in ["apt", "dnf"] {
+            if !candidates.iter().any(|c| c.box_type == box_type) {
+                continue;
+            }
+            let output = match box_type {
+                "apt" => Command::new("apt").args(["list", "--upgradable"]).output(),
+                _ => Command::new("dnf")
+                    .args(["updateinfo", "list", "--security", "--available"])
+                    .output(),
+            };
+            let output = match output {
+                Ok(output) => output,
+                Err(e) => {
+                    warn!("Could not read {} security advisories: {}", box_type, e);
+                    continue;
+                }
+            };
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            for candidate in candidates.iter_mut().filter(|c| c.box_type == box_type) {
+                candidate.advisory = parse_advisory(box_type, &stdout, &candidate.package_name);
+            }
+        }
+    }
+
     async fn check_package_update(
         &self,
         package: &InstallRecord,
     ) -> Result<Option<UpdateCandidate>> {
+        if let Some(channel) = tracked_channel(package) {
+            if let Some((command, args)) =
+                channels::check_command(&package.box_type, &package.package_name, &channel)?
+            {
+                return self.check_channel_update(package, &command, &args);
+            }
+        }
+
         match package.box_type.as_str() {
             "apt" if distro::command_exists("apt") => self.check_apt_update(package).await,
             "dnf" if distro::command_exists("dnf") => self.check_dnf_update(package).await,
@@ -67,6 +225,30 @@ impl UpdateManager {
         }
     }
 
+    /// Look for an update within the channel the package was installed from
+    fn check_channel_update(
+        &self,
+        package: &InstallRecord,
+        command: &str,
+        args: &[String],
+    ) -> Result<Option<UpdateCandidate>> {
+        let output = Command::new(command).args(args).output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        Ok(
+            parse_channel_check(&package.box_type, &stdout, &package.package_name).map(
+                |available| UpdateCandidate {
+                    package_name: package.package_name.clone(),
+                    box_type: package.box_type.clone(),
+                    current_version: package.version.clone(),
+                    available_version: Some(available),
+                    install_record: package.clone(),
+                    advisory: None,
+                },
+            ),
+        )
+    }
+
     async fn check_apt_update(&self, package: &InstallRecord) -> Result<Option<UpdateCandidate>> {
         let output = Command::new("apt")
             .arg("list")
@@ -87,6 +269,7 @@ impl UpdateManager {
                             current_version: package.version.clone(),
                             available_version: Some(parts[1].to_string()),
                             install_record: package.clone(),
+                            advisory: None,
                         }));
                     }
                 }
@@ -115,6 +298,7 @@ impl UpdateManager {
                             current_version: package.version.clone(),
                             available_version: Some(parts[1].to_string()),
                             install_record: package.clone(),
+                            advisory: None,
                         }));
                     }
                 }
@@ -145,6 +329,7 @@ impl UpdateManager {
                             current_version: Some(parts[1].to_string()),
                             available_version: Some(parts[3].to_string()),
                             install_record: package.clone(),
+                            advisory: None,
                         }));
                     }
                 }
@@ -169,6 +354,7 @@ impl UpdateManager {
                         current_version: Some(parts[2].to_string()),
                         available_version: Some(parts[4].to_string()),
                         install_record: package.clone(),
+                        advisory: None,
                     }));
                 }
             }
@@ -201,6 +387,7 @@ impl UpdateManager {
                 current_version: package.version.clone(),
                 available_version: Some("latest".to_string()),
                 install_record: package.clone(),
+                advisory: None,
             }));
         }
 
@@ -208,11 +395,14 @@ impl UpdateManager {
     }
 
     pub async fn update_package(&self, candidate: &UpdateCandidate) -> Result<()> {
+        crate::config::ensure_writable("updating packages")?;
+        health_checks::validate(&self.config.health_checks.checks)?;
         info!(
             "Updating package: {} via {}",
             candidate.package_name, candidate.box_type
         );
 
+        let started = std::time::Instant::now();
         let pb = ProgressBar::new_spinner();
         if let Ok(style) = ProgressStyle::default_spinner().template("{spinner:.green} {msg}") {
             pb.set_style(style);
@@ -220,12 +410,34 @@ impl UpdateManager {
         pb.set_message(format!("Updating {}...", candidate.package_name));
         pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
-        let result = match candidate.box_type.as_str() {
-            "apt" => self.update_apt_package(&candidate.package_name).await,
-            "dnf" => self.update_dnf_package(&candidate.package_name).await,
-            "pacman" => self.update_pacman_package(&candidate.package_name).await,
-            "snap" => self.update_snap_package(&candidate.package_name).await,
-            "flatpak" => self.update_flatpak_package(&candidate).await,
+        let constrained = self
+            .db
+            .get_pinned_packages()
+            .await?
+            .iter()
+            .find(|p| p.package_name == candidate.package_name && p.box_type == candidate.box_type)
+            .and_then(pin_constraint)
+            .is_some();
+
+        let result = match (
+            tracked_channel(&candidate.install_record),
+            candidate.box_type.as_str(),
+        ) {
+            // Move to the version the constraint allows, not the newest one
+            (None, box_type) if constrained && downgrade::VERSIONED_BOXES.contains(&box_type) => {
+                match &candidate.available_version {
+                    Some(version) => {
+                        run_version_install(&candidate.package_name, box_type, version)
+                    }
+                    None => Err(anyhow::anyhow!("No version to update to")),
+                }
+            }
+            (Some(channel), _) => self.update_from_channel(candidate, &channel),
+            (None, "apt") => self.update_apt_package(&candidate.package_name).await,
+            (None, "dnf") => self.update_dnf_package(&candidate.package_name).await,
+            (None, "pacman") => self.update_pacman_package(&candidate.package_name).await,
+            (None, "snap") => self.update_snap_package(&candidate.package_name).await,
+            (None, "flatpak") => self.update_flatpak_package(&candidate).await,
             _ => {
                 error!("Unsupported box type for update: {}", candidate.box_type);
                 Err(anyhow::anyhow!("Unsupported box type"))
@@ -237,33 +449,204 @@ impl UpdateManager {
         match result {
             Ok(_) => {
                 info!("✅ Successfully updated {}", candidate.package_name);
-
-                // Record the update
-                let update_record = InstallRecord {
-                    id: Uuid::new_v4().to_string(),
-                    package_name: candidate.package_name.clone(),
-                    box_type: candidate.box_type.clone(),
-                    version: candidate.available_version.clone(),
-                    source_url: candidate.install_record.source_url.clone(),
-                    install_path: candidate.install_record.install_path.clone(),
-                    installed_at: Utc::now(),
-                    status: InstallStatus::Updated,
-                    metadata: Some(format!(
-                        "Updated from version {:?}",
-                        candidate.current_version
-                    )),
-                };
-
-                self.db.record_install(&update_record).await?;
-                Ok(())
+                self.record_update(
+                    candidate,
+                    candidate.available_version.clone(),
+                    Some(started.elapsed()),
+                )
+                .await?;
+                self.verify_health(candidate).await
             }
             Err(e) => {
                 error!("❌ Failed to update {}: {}", candidate.package_name, e);
+                let event = PackageEvent::failed(NotifyEvent::Update, &candidate.package_name, &e)
+                    .with_box_type(Some(&candidate.box_type))
+                    .with_duration(started.elapsed());
+                notifications::send(&self.config, &event).await;
                 Err(e)
             }
         }
     }
 
+    /// Run the health checks configured for a package that was just updated
+    /// and downgrade it again when one of them fails
+    async fn verify_health(&self, candidate: &UpdateCandidate) -> Result<()> {
+        let checks = health_checks::applicable(
+            &self.config.health_checks.checks,
+            &candidate.package_name,
+            health_checks::LOCAL_HOST,
+        );
+        if checks.is_empty() {
+            return Ok(());
+        }
+
+        let outcomes = health_checks::run(&checks, &mut LocalShell, "localhost").await;
+        let Some(failure) = health_checks::failures(&outcomes) else {
+            return Ok(());
+        };
+        warn!(
+            "Health checks failed after updating {}: {}",
+            candidate.package_name, failure
+        );
+
+        let rollback = if !self.config.health_checks.rollback {
+            "left in place (health_checks.rollback is off)".to_string()
+        } else {
+            match self
+                .downgrade_package(
+                    &candidate.package_name,
+                    &candidate.box_type,
+                    candidate.current_version.as_deref(),
+                )
+                .await
+            {
+                Ok(record) => format!(
+                    "rolled back to {}",
+                    record.version.as_deref().unwrap_or("the previous version")
+                ),
+                Err(e) => format!("rollback failed: {}", e),
+            }
+        };
+
+        let error = anyhow::anyhow!(
+            "Health checks failed after updating {} ({}); {}",
+            candidate.package_name,
+            failure,
+            rollback
+        );
+        let event = PackageEvent::failed(NotifyEvent::Update, &candidate.package_name, &error)
+            .with_box_type(Some(&candidate.box_type));
+        notifications::send(&self.config, &event).await;
+        Err(error)
+    }
+
+    async fn record_update(
+        &self,
+        candidate: &UpdateCandidate,
+        version: Option<String>,
+        duration: Option<std::time::Duration>,
+    ) -> Result<()> {
+        let provenance = provenance::detect(&candidate.package_name, &candidate.box_type);
+        let mut metadata = format!("Updated from version {:?}", candidate.current_version);
+        // Kept with the record so digests can list the CVEs updates fixed
+        let fixed = self
+            .changelog(candidate)
+            .map(|text| crate::watchlist::advisory_ids(&text))
+            .unwrap_or_default();
+        if !fixed.is_empty() {
+            metadata.push_str(&format!("; fixes {}", fixed.join(", ")));
+        }
+        let update_record = InstallRecord {
+            id: Uuid::new_v4().to_string(),
+            package_name: candidate.package_name.clone(),
+            box_type: candidate.box_type.clone(),
+            version,
+            source_url: candidate.install_record.source_url.clone(),
+            install_path: candidate.install_record.install_path.clone(),
+            installed_at: Utc::now(),
+            status: InstallStatus::Updated,
+            repository: provenance.repository,
+            mirror_url: provenance.mirror_url,
+            channel: candidate.install_record.channel.clone(),
+            metadata: Some(metadata),
+        };
+
+        self.db.record_install(&update_record).await?;
+        let mut event = PackageEvent::new(NotifyEvent::Update, &candidate.package_name)
+            .with_box_type(Some(&candidate.box_type))
+            .with_version(update_record.version);
+        if let Some(duration) = duration {
+            event = event.with_duration(duration);
+        }
+        notifications::send(&self.config, &event).await;
+        Ok(())
+    }
+
+    /// The pacman packages among `candidates` and the upgrades updating only
+    /// them would leave behind; `None` when it is not a partial upgrade
+    pub fn partial_upgrade(
+        &self,
+        candidates: &[UpdateCandidate],
+    ) -> Result<Option<PartialUpgrade>> {
+        let requested: Vec<String> = candidates
+            .iter()
+            .filter(|c| c.box_type == "pacman")
+            .map(|c| c.package_name.clone())
+            .collect();
+        if requested.is_empty() || !distro::command_exists("pacman") {
+            return Ok(None);
+        }
+
+        // Exits with 1 when nothing is pending, which is no partial upgrade either
+        let output = Command::new("pacman").arg("-Qu").output()?;
+        let left_behind =
+            partial_upgrade_leftovers(&String::from_utf8_lossy(&output.stdout), &requested);
+        Ok((!left_behind.is_empty()).then_some(PartialUpgrade {
+            requested,
+            left_behind,
+        }))
+    }
+
+    /// Upgrade the whole system with `pacman -Syu` and record the pacman
+    /// `candidates` at the versions it installed
+    pub async fn full_sync(&self, candidates: &[UpdateCandidate]) -> Result<()> {
+        crate::config::ensure_writable("updating packages")?;
+        println!("🔄 Upgrading the whole system with pacman -Syu");
+        PrivilegeManager::new().execute_with_sudo("pacman", &["-Syu", "--noconfirm"])?;
+
+        for candidate in candidates.iter().filter(|c| c.box_type == "pacman") {
+            let version = Command::new("pacman")
+                .arg("-Q")
+                .arg(&candidate.package_name)
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+                .and_then(|o| {
+                    String::from_utf8_lossy(&o.stdout)
+                        .split_whitespace()
+                        .nth(1)
+                        .map(str::to_string)
+                })
+                .or_else(|| candidate.available_version.clone());
+            self.record_update(candidate, version, None).await?;
+        }
+        println!("✅ System upgrade complete");
+        Ok(())
+    }
+
+    /// Upgrade within the channel the package was installed from, so a beta
+    /// install does not fall back to the stable release
+    fn update_from_channel(&self, candidate: &UpdateCandidate, channel: &Channel) -> Result<()> {
+        let package = match candidate.box_type.as_str() {
+            "flatpak" => candidate
+                .install_record
+                .source_url
+                .as_deref()
+                .unwrap_or(&candidate.package_name),
+            _ => &candidate.package_name,
+        };
+        let (command, args) = channels::update_command(&candidate.box_type, package, channel)?;
+        info!(
+            "Updating {} from the {} channel via {}",
+            candidate.package_name, channel, candidate.box_type
+        );
+
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        if channels::needs_privilege(&candidate.box_type) {
+            return PrivilegeManager::new().execute_with_sudo(&command, &args);
+        }
+        let output = Command::new(&command).args(&args).output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "{} update failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr)
+            ))
+        }
+    }
+
     async fn update_apt_package(&self, package_name: &str) -> Result<()> {
         let output = Command::new("apt")
             .arg("install")
@@ -340,6 +723,7 @@ impl UpdateManager {
     }
 
     pub async fn update_all(&self) -> Result<()> {
+        crate::config::ensure_writable("updating packages")?;
         info!("Starting system-wide update");
 
         let candidates = self.check_updates().await?;
@@ -349,6 +733,146 @@ impl UpdateManager {
             return Ok(());
         }
 
+        self.update_candidates(&candidates).await
+    }
+
+    /// Whether the backend marks the update as a security fix. Backends without
+    /// security metadata report `false`.
+    pub fn is_security_update(&self, candidate: &UpdateCandidate) -> bool {
+        candidate.advisory.is_some()
+    }
+
+    /// Security classification and advisory severity of an update
+    pub fn security_info(&self, candidate: &UpdateCandidate) -> SecurityInfo {
+        candidate
+            .advisory
+            .as_ref()
+            .map(SecurityInfo::from)
+            .unwrap_or_default()
+    }
+
+    /// Split `candidates` into updates `daemon.auto_install` and
+    /// `policies.unattended` allow to install now and updates left for the user
+    pub async fn plan_unattended(&self, candidates: &[UpdateCandidate]) -> UnattendedPlan {
+        let policy = &self.config.policies.unattended;
+        let mut plan = UnattendedPlan::default();
+        for candidate in candidates {
+            let info = self.security_info(candidate);
+            let mut decision = unattended_decision(
+                self.config.daemon.auto_install,
+                policy,
+                &candidate.package_name,
+                info,
+            );
+            if let (Ok(()), Some(max)) = (&decision, policy.max_impact) {
+                let impact = self.impact(candidate).await;
+                if impact > max {
+                    decision = Err(format!(
+                        "{} update; review it with 'omni update --review'",
+                        impact
+                    ));
+                }
+            }
+            if decision.is_ok() && self.critical_major(candidate).await.is_some() {
+                decision = Err(
+                    "major update of a critical package; confirm it with 'omni update'".to_string(),
+                );
+            }
+            match decision {
+                Ok(()) => plan.install.push(candidate.clone()),
+                Err(reason) => {
+                    info!("Deferring update of {}: {}", candidate.package_name, reason);
+                    plan.deferred.push(DeferredUpdate {
+                        package_name: candidate.package_name.clone(),
+                        box_type: candidate.box_type.clone(),
+                        current_version: candidate.current_version.clone(),
+                        available_version: candidate.available_version.clone(),
+                        reason,
+                    });
+                }
+            }
+            plan.updates.push((candidate.clone(), info));
+        }
+        plan
+    }
+
+    /// How much the update is likely to change, from its version delta and
+    /// the distribution's phasing and testing metadata
+    pub async fn impact(&self, candidate: &UpdateCandidate) -> Impact {
+        let hints = impact::hints(
+            &candidate.box_type,
+            &candidate.package_name,
+            candidate.available_version.as_deref(),
+        )
+        .await;
+        impact::classify(
+            candidate.current_version.as_deref(),
+            candidate.available_version.as_deref(),
+            hints,
+        )
+    }
+
+    /// The impact of the update when it is a major one of a package in
+    /// `policies.critical`, which needs an explicit confirmation
+    pub async fn critical_major(&self, candidate: &UpdateCandidate) -> Option<Impact> {
+        let critical = self
+            .config
+            .policies
+            .critical
+            .iter()
+            .any(|pattern| matches_pattern(pattern, &candidate.package_name));
+        if !critical {
+            return None;
+        }
+        let impact = self.impact(candidate).await;
+        (impact >= Impact::Major).then_some(impact)
+    }
+
+    /// Fetch updates into the package caches without installing them
+    pub fn download_updates(&self, candidates: &[UpdateCandidate]) -> Result<()> {
+        crate::config::ensure_writable("downloading updates")?;
+
+        for box_type in ["apt", "dnf", "pacman"] {
+            let packages: Vec<&str> = candidates
+                .iter()
+                // Channel installs fetch from their own release when they update
+                .filter(|c| c.box_type == box_type && tracked_channel(&c.install_record).is_none())
+                .map(|c| c.package_name.as_str())
+                .collect();
+            if packages.is_empty() {
+                continue;
+            }
+
+            let (command, mut args) = match box_type {
+                "apt" => (
+                    "apt-get",
+                    vec!["install", "--only-upgrade", "--download-only", "-y"],
+                ),
+                "dnf" => ("dnf", vec!["upgrade", "--downloadonly", "-y"]),
+                _ => ("pacman", vec!["-Sw", "--noconfirm"]),
+            };
+            args.extend(&packages);
+
+            info!("Downloading {} {} updates", packages.len(), box_type);
+            PrivilegeManager::new().execute_with_sudo(command, &args)?;
+        }
+
+        Ok(())
+    }
+
+    /// Changelog entries the update to `candidate` brings, if the backend has any
+    pub fn changelog(&self, candidate: &UpdateCandidate) -> Result<String> {
+        changelog::fetch(
+            &candidate.box_type,
+            &candidate.package_name,
+            candidate.current_version.as_deref(),
+            changelog::DEFAULT_MAX_ENTRIES,
+        )
+    }
+
+    /// Update the given candidates, e.g. after the user reviewed them
+    pub async fn update_candidates(&self, candidates: &[UpdateCandidate]) -> Result<()> {
+        crate::config::ensure_writable("updating packages")?;
         info!("Updating {} packages", candidates.len());
 
         let pb = ProgressBar::new(candidates.len() as u64);
@@ -374,7 +898,221 @@ impl UpdateManager {
         Ok(())
     }
 
+    /// Install an older version of a package and record it so `omni history undo` can re-upgrade
+    pub async fn downgrade_package(
+        &self,
+        package_name: &str,
+        box_type: &str,
+        to_version: Option<&str>,
+    ) -> Result<InstallRecord> {
+        crate::config::ensure_writable("downgrading a package")?;
+        let pin = self
+            .db
+            .get_pinned_packages()
+            .await?
+            .into_iter()
+            .find(|p| p.package_name == package_name && p.box_type == box_type);
+        let constraint = pin.as_ref().and_then(pin_constraint);
+        if pin.is_some() && constraint.is_none() {
+            return Err(anyhow::anyhow!(
+                "{} is pinned; run 'omni unpin {}' before downgrading",
+                package_name,
+                package_name
+            ));
+        }
+
+        let listing = downgrade::query_versions(package_name, box_type)?;
+        let target = listing.downgrade_target(package_name, to_version)?;
+        if let Some(constraint) = constraint.filter(|c| !c.allows(&target)) {
+            return Err(anyhow::anyhow!(
+                "{} {} is outside the version constraint {}; run 'omni unpin {}' first",
+                package_name,
+                target,
+                constraint,
+                package_name
+            ));
+        }
+        let previous = listing.installed.clone().unwrap_or_default();
+
+        info!(
+            "Downgrading {} from {} to {} via {}",
+            package_name, previous, target, box_type
+        );
+
+        self.install_exact_version(
+            package_name,
+            box_type,
+            &target,
+            InstallStatus::Downgraded,
+            downgrade::downgrade_note(&previous),
+        )
+        .await
+    }
+
+    /// Reinstall a specific (usually newer) version, e.g. when undoing a downgrade
+    pub async fn restore_version(
+        &self,
+        package_name: &str,
+        box_type: &str,
+        version: &str,
+    ) -> Result<InstallRecord> {
+        crate::config::ensure_writable("restoring a package version")?;
+        info!("Restoring {} {} via {}", package_name, version, box_type);
+
+        self.install_exact_version(
+            package_name,
+            box_type,
+            version,
+            InstallStatus::Updated,
+            format!("Restored version {} (undo of downgrade)", version),
+        )
+        .await
+    }
+
+    async fn install_exact_version(
+        &self,
+        package_name: &str,
+        box_type: &str,
+        version: &str,
+        status: InstallStatus,
+        metadata: String,
+    ) -> Result<InstallRecord> {
+        run_version_install(package_name, box_type, version)?;
+
+        let previous = self
+            .db
+            .get_package_history(package_name)
+            .await?
+            .into_iter()
+            .find(|r| r.box_type == box_type);
+        let provenance = provenance::detect(package_name, box_type);
+
+        let record = InstallRecord {
+            id: Uuid::new_v4().to_string(),
+            package_name: package_name.to_string(),
+            box_type: box_type.to_string(),
+            version: Some(version.to_string()),
+            source_url: previous.as_ref().and_then(|r| r.source_url.clone()),
+            install_path: previous.as_ref().and_then(|r| r.install_path.clone()),
+            installed_at: Utc::now(),
+            status,
+            repository: provenance.repository,
+            mirror_url: provenance.mirror_url,
+            channel: previous.as_ref().and_then(|r| r.channel.clone()),
+            metadata: Some(metadata),
+        };
+        self.db.record_install(&record).await?;
+
+        Ok(record)
+    }
+
+    /// Hold a package at its current version, both in omni and in the native package manager
+    pub async fn pin_package(
+        &self,
+        package_name: &str,
+        box_type: &str,
+        reason: Option<&str>,
+    ) -> Result<PinnedPackage> {
+        let version = self
+            .db
+            .get_package_history(package_name)
+            .await?
+            .into_iter()
+            .find(|r| r.box_type == box_type)
+            .and_then(|r| r.version);
+
+        self.set_native_hold(package_name, box_type, true);
+
+        let pin = PinnedPackage {
+            package_name: package_name.to_string(),
+            box_type: box_type.to_string(),
+            version,
+            reason: reason.map(|r| r.to_string()),
+            pinned_at: Utc::now(),
+            constraint: None,
+        };
+        self.db.pin_package(&pin).await?;
+
+        info!("📌 Pinned {} [{}]", package_name, box_type);
+        Ok(pin)
+    }
+
+    pub async fn unpin_package(
+        &self,
+        package_name: &str,
+        box_type: Option<&str>,
+    ) -> Result<Vec<PinnedPackage>> {
+        let removed = self.db.unpin_package(package_name, box_type).await?;
+
+        for pin in &removed {
+            self.set_native_hold(&pin.package_name, &pin.box_type, false);
+            info!("Unpinned {} [{}]", pin.package_name, pin.box_type);
+        }
+
+        Ok(removed)
+    }
+
+    pub async fn list_pinned(&self) -> Result<Vec<PinnedPackage>> {
+        self.db.get_pinned_packages().await
+    }
+
+    /// Mirror a pin into the native package manager where it supports holds.
+    /// Failures are only logged: omni's own pin list is always enforced.
+    fn set_native_hold(&self, package_name: &str, box_type: &str, hold: bool) {
+        let privilege_manager = PrivilegeManager::new();
+
+        let result = match box_type {
+            "apt" if distro::command_exists("apt-mark") => privilege_manager.execute_with_sudo(
+                "apt-mark",
+                &[if hold { "hold" } else { "unhold" }, package_name],
+            ),
+            "dnf" if distro::command_exists("dnf") => privilege_manager.execute_with_sudo(
+                "dnf",
+                &[
+                    "versionlock",
+                    if hold { "add" } else { "delete" },
+                    package_name,
+                ],
+            ),
+            "snap" if distro::command_exists("snap") => {
+                let action = if hold { "--hold" } else { "--unhold" };
+                privilege_manager.execute_with_sudo("snap", &["refresh", action, package_name])
+            }
+            "flatpak" if distro::command_exists("flatpak") => {
+                let args: &[&str] = if hold {
+                    &["mask", package_name]
+                } else {
+                    &["mask", "--remove", package_name]
+                };
+                privilege_manager.execute_with_sudo("flatpak", args)
+            }
+            "pacman" => {
+                // pacman only supports holds through IgnorePkg in pacman.conf, which omni
+                // does not rewrite; `omni update` still skips the package
+                if hold {
+                    println!(
+                        "💡 Add 'IgnorePkg = {}' to /etc/pacman.conf to hold it for pacman -Syu as well",
+                        package_name
+                    );
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            warn!(
+                "Could not {} {} in {}: {}",
+                if hold { "hold" } else { "release" },
+                package_name,
+                box_type,
+                e
+            );
+        }
+    }
+
     pub async fn refresh_repositories(&self) -> Result<()> {
+        crate::config::ensure_writable("refreshing repositories")?;
         info!("Refreshing package repositories");
 
         // Update apt repositories
@@ -412,3 +1150,398 @@ impl UpdateManager {
         self.db.get_installed_packages().await
     }
 }
+
+/// Non-default release channel the package was installed from
+/// Install exactly `version` of a package, in either direction
+fn run_version_install(package_name: &str, box_type: &str, version: &str) -> Result<()> {
+    let (command, args) = downgrade::install_version_command(package_name, box_type, version)?;
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+    PrivilegeManager::new()
+        .execute_with_sudo(&command, &args)
+        .map_err(|e| anyhow::anyhow!("Failed to install {} {}: {}", package_name, version, e))?;
+    Ok(())
+}
+
+/// The constraint a pin lets updates move within; `None` for a pin that
+/// holds the package where it is
+fn pin_constraint(pin: &PinnedPackage) -> Option<VersionConstraint> {
+    let constraint = pin.constraint.as_deref()?;
+    match VersionConstraint::parse(constraint) {
+        Ok(constraint) => Some(constraint),
+        Err(e) => {
+            warn!(
+                "Ignoring the version constraint of {}: {}",
+                pin.package_name, e
+            );
+            None
+        }
+    }
+}
+
+/// `candidate`, retargeted to the newest version `constraint` allows when
+/// the newest available one is outside it; `None` when no allowed version
+/// is newer than the installed one
+fn constrained_update(
+    mut candidate: UpdateCandidate,
+    constraint: &VersionConstraint,
+) -> Option<UpdateCandidate> {
+    if candidate
+        .available_version
+        .as_deref()
+        .is_some_and(|v| constraint.allows(v))
+    {
+        return Some(candidate);
+    }
+
+    let available = downgrade::query_versions(&candidate.package_name, &candidate.box_type)
+        .map(|listing| listing.available)
+        .unwrap_or_default();
+    let allowed = constraint.newest(&available)?;
+    let newer = candidate.current_version.as_deref().is_none_or(|current| {
+        downgrade::compare_versions(allowed, current) == std::cmp::Ordering::Greater
+    });
+    if !newer {
+        info!("Keeping {} within {}", candidate.package_name, constraint);
+        return None;
+    }
+
+    candidate.available_version = Some(allowed.clone());
+    Some(candidate)
+}
+
+fn tracked_channel(package: &InstallRecord) -> Option<Channel> {
+    package
+        .channel
+        .as_deref()
+        .map(Channel::parse)
+        .filter(|c| !c.is_stable())
+}
+
+/// Version a channel update check offers for `package`: an apt simulated
+/// upgrade (`Inst pkg [old] (new release [arch])`) or `dnf check-update` output
+pub fn parse_channel_check(box_type: &str, output: &str, package: &str) -> Option<String> {
+    output.lines().find_map(|line| match box_type {
+        "apt" => {
+            let rest = line.strip_prefix("Inst ")?;
+            let (name, rest) = rest.split_once(' ')?;
+            if name != package {
+                return None;
+            }
+            let (_, available) = rest.split_once('(')?;
+            available.split_whitespace().next().map(str::to_string)
+        }
+        "dnf" => {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let name = parts.first()?;
+            let name = name.rsplit_once('.').map_or(*name, |(name, _arch)| name);
+            (name == package && parts.len() >= 3).then(|| parts[1].to_string())
+        }
+        _ => None,
+    })
+}
+
+/// Packages in `pacman -Qu` output (`name 1.0-1 -> 1.1-1`) other than
+/// `requested`. Upgrades pacman marks `[ignored]` stay behind on `-Syu` as
+/// well, so they do not count.
+pub fn partial_upgrade_leftovers(output: &str, requested: &[String]) -> Vec<String> {
+    output
+        .lines()
+        .filter(|line| !line.trim_end().ends_with("[ignored]"))
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| !requested.iter().any(|r| r == name))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `output` marks the update of `package` as a security fix
+pub fn parse_security_updates(box_type: &str, output: &str, package: &str) -> bool {
+    parse_security_info(box_type, output, package).security
+}
+
+/// What `output` says about the update of `package`
+pub fn parse_security_info(box_type: &str, output: &str, package: &str) -> SecurityInfo {
+    parse_advisory(box_type, output, package)
+        .as_ref()
+        .map(SecurityInfo::from)
+        .unwrap_or_default()
+}
+
+/// `apt list --upgradable` names the archive (`noble-security`) an update comes
+/// from; `dnf updateinfo list --security` only lists security advisories, with
+/// their severity (`Important/Sec.`)
+pub fn parse_advisory(box_type: &str, output: &str, package: &str) -> Option<Advisory> {
+    match box_type {
+        "apt" => output.lines().find_map(|line| {
+            let (name, rest) = line.split_once('/')?;
+            if name != package {
+                return None;
+            }
+            let archives = rest.split_whitespace().next()?;
+            archives
+                .split(',')
+                .find(|a| a.ends_with("-security"))
+                .map(|archive| Advisory {
+                    id: archive.to_string(),
+                    severity: None,
+                })
+        }),
+        "dnf" => output
+            .lines()
+            .filter_map(|line| {
+                let mut columns = line.split_whitespace();
+                let id = columns.next()?;
+                let severity = columns.next()?;
+                // `openssl-devel-3.2.2-3` must not count as `openssl`
+                columns
+                    .next()
+                    .and_then(|nevra| nevra.strip_prefix(package)?.strip_prefix('-'))
+                    .filter(|version| version.starts_with(|c: char| c.is_ascii_digit()))?;
+                Some(Advisory {
+                    id: id.to_string(),
+                    severity: parse_severity(severity),
+                })
+            })
+            // Several advisories can cover one update; the most severe counts
+            .reduce(|most, next| {
+                if next.severity > most.severity {
+                    next
+                } else {
+                    most
+                }
+            }),
+        _ => None,
+    }
+}
+
+fn parse_severity(column: &str) -> Option<Severity> {
+    let name = column.split('/').next().unwrap_or_default();
+    match name.to_ascii_lowercase().as_str() {
+        "low" => Some(Severity::Low),
+        "moderate" => Some(Severity::Moderate),
+        "important" => Some(Severity::Important),
+        "critical" => Some(Severity::Critical),
+        _ => None,
+    }
+}
+
+/// Whether an unattended run may install an update of `package`, or why it is
+/// left for the user
+pub fn unattended_decision(
+    auto_install: AutoInstallPolicy,
+    policy: &UnattendedPolicy,
+    package: &str,
+    info: SecurityInfo,
+) -> std::result::Result<(), String> {
+    if auto_install == AutoInstallPolicy::Never {
+        return Err("automatic installs are off".to_string());
+    }
+    if policy.exclude_kernels && is_kernel_package(package) {
+        return Err("kernel updates are excluded".to_string());
+    }
+    if let Some(pattern) = policy.exclude.iter().find(|p| matches_pattern(p, package)) {
+        return Err(format!("excluded by '{}'", pattern));
+    }
+    if !policy.include.is_empty() && !policy.include.iter().any(|p| matches_pattern(p, package)) {
+        return Err("not in the include list".to_string());
+    }
+    if auto_install == AutoInstallPolicy::Security && !info.security {
+        return Err("not a security update".to_string());
+    }
+    if let (true, Some(min)) = (info.security, policy.min_severity) {
+        match info.severity {
+            Some(severity) if severity >= min => {}
+            Some(severity) => {
+                return Err(format!("severity {} is below {}", severity, min));
+            }
+            None => return Err(format!("severity is unknown, {} required", min)),
+        }
+    }
+    Ok(())
+}
+
+pub fn is_kernel_package(package: &str) -> bool {
+    KERNEL_PACKAGES
+        .iter()
+        .any(|pattern| matches_pattern(pattern, package))
+}
+
+/// Match `name` against `pattern`, where `*` stands for any run of characters
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No `*` in the pattern
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_security_updates() {
+        let apt = "Listing...
+openssl/noble-updates,noble-security 3.0.13-0ubuntu3.4 amd64 [upgradable from: 3.0.13-0ubuntu3.1]
+openssl-dev/noble-updates 3.0.13-0ubuntu3.4 amd64 [upgradable from: 3.0.13-0ubuntu3.1]
+";
+        assert!(parse_security_updates("apt", apt, "openssl"));
+        assert!(!parse_security_updates("apt", apt, "openssl-dev"));
+
+        let dnf = "FEDORA-2024-1a2b3c4d5e Important/Sec. openssl-1:3.2.2-3.fc40.x86_64\n";
+        assert!(parse_security_updates("dnf", dnf, "openssl"));
+        assert!(!parse_security_updates("dnf", dnf, "open"));
+        assert!(!parse_security_updates("dnf", "", "openssl"));
+        assert!(!parse_security_updates("snap", apt, "openssl"));
+    }
+
+    #[test]
+    fn test_parse_security_severity() {
+        let dnf = "FEDORA-2024-1a2b3c4d5e Moderate/Sec.  openssl-1:3.2.2-3.fc40.x86_64
+FEDORA-2024-6f7a8b9c0d Critical/Sec.  openssl-1:3.2.2-3.fc40.x86_64
+FEDORA-2024-0d9c8b7a6f Low/Sec.       curl-8.6.0-8.fc40.x86_64
+";
+        let info = parse_security_info("dnf", dnf, "openssl");
+        assert!(info.security);
+        assert_eq!(info.severity, Some(Severity::Critical));
+        assert_eq!(
+            parse_security_info("dnf", dnf, "curl").severity,
+            Some(Severity::Low)
+        );
+        assert!(!parse_security_info("dnf", dnf, "wget").security);
+    }
+
+    #[test]
+    fn test_package_patterns() {
+        assert!(matches_pattern("postgresql*", "postgresql-16"));
+        assert!(matches_pattern("*-dev", "libssl-dev"));
+        assert!(matches_pattern("lib*ssl*", "libssl3"));
+        assert!(matches_pattern("nginx", "nginx"));
+        assert!(!matches_pattern("nginx", "nginx-common"));
+        assert!(!matches_pattern("*-dev", "libssl3"));
+
+        assert!(is_kernel_package("linux-image-6.8.0-45-generic"));
+        assert!(is_kernel_package("kernel-core"));
+        assert!(is_kernel_package("linux-lts"));
+        assert!(!is_kernel_package("linux-firmware"));
+        assert!(!is_kernel_package("util-linux"));
+    }
+
+    #[test]
+    fn test_unattended_decision() {
+        let policy = UnattendedPolicy::default();
+        let plain = SecurityInfo::default();
+        let security = SecurityInfo {
+            security: true,
+            severity: Some(Severity::Moderate),
+        };
+
+        let (never, security_only, all) = (
+            AutoInstallPolicy::Never,
+            AutoInstallPolicy::Security,
+            AutoInstallPolicy::All,
+        );
+
+        assert!(unattended_decision(never, &policy, "curl", security).is_err());
+        assert!(unattended_decision(all, &policy, "curl", plain).is_ok());
+        assert!(unattended_decision(security_only, &policy, "curl", plain).is_err());
+        assert!(unattended_decision(security_only, &policy, "curl", security).is_ok());
+        assert!(unattended_decision(all, &policy, "linux-image-generic", security).is_err());
+
+        let policy = UnattendedPolicy {
+            include: vec!["curl".to_string(), "openssl*".to_string()],
+            exclude: vec!["openssl-devel".to_string()],
+            exclude_kernels: false,
+            min_severity: Some(Severity::Important),
+            max_impact: None,
+        };
+        assert!(unattended_decision(all, &policy, "curl", plain).is_ok());
+        assert!(unattended_decision(all, &policy, "wget", plain).is_err());
+        assert!(unattended_decision(all, &policy, "openssl-devel", plain).is_err());
+        assert!(unattended_decision(all, &policy, "openssl", security).is_err());
+        let critical = SecurityInfo {
+            security: true,
+            severity: Some(Severity::Critical),
+        };
+        assert!(unattended_decision(all, &policy, "openssl", critical).is_ok());
+    }
+
+    #[test]
+    fn test_parse_channel_check() {
+        let apt = "Reading package lists...
+Building dependency tree...
+The following packages will be upgraded:
+  firefox
+1 upgraded, 0 newly installed, 0 to remove and 12 not upgraded.
+Inst firefox [128.0-1] (129.0~b3-1 Debian:testing [amd64])
+Conf firefox (129.0~b3-1 Debian:testing [amd64])
+";
+        assert_eq!(
+            parse_channel_check("apt", apt, "firefox").as_deref(),
+            Some("129.0~b3-1")
+        );
+        assert_eq!(parse_channel_check("apt", apt, "fire"), None);
+
+        let dnf = "
+Last metadata expiration check: 0:12:03 ago.
+
+python3.12.x86_64     3.12.5-1.fc40     updates-testing
+";
+        assert_eq!(
+            parse_channel_check("dnf", dnf, "python3.12").as_deref(),
+            Some("3.12.5-1.fc40")
+        );
+        assert_eq!(parse_channel_check("dnf", dnf, "python3"), None);
+        assert_eq!(parse_channel_check("dnf", "", "python3.12"), None);
+    }
+
+    #[test]
+    fn test_partial_upgrade_leftovers() {
+        let output = "firefox 128.0-1 -> 129.0-1
+glibc 2.39-4 -> 2.40-1
+linux 6.9.7.arch1-1 -> 6.10.2.arch1-1 [ignored]
+";
+        let requested = vec!["firefox".to_string()];
+        assert_eq!(partial_upgrade_leftovers(output, &requested), vec!["glibc"]);
+
+        let everything = vec!["firefox".to_string(), "glibc".to_string()];
+        assert!(partial_upgrade_leftovers(output, &everything).is_empty());
+        assert!(partial_upgrade_leftovers("", &requested).is_empty());
+    }
+
+    #[test]
+    fn test_parse_advisory() {
+        let apt = "Listing...
+openssl/noble-updates,noble-security 3.0.13-0ubuntu3.4 amd64 [upgradable from: 3.0.13-0ubuntu3.1]
+curl/noble-updates 8.5.0-2ubuntu10.2 amd64 [upgradable from: 8.5.0-2ubuntu10.1]
+";
+        assert_eq!(
+            parse_advisory("apt", apt, "openssl"),
+            Some(Advisory {
+                id: "noble-security".to_string(),
+                severity: None,
+            })
+        );
+        assert_eq!(parse_advisory("apt", apt, "curl"), None);
+
+        let dnf = "RLSA-2024:3501 Moderate/Sec.  openssl-1:3.0.7-27.el9.x86_64
+RLSA-2024:4312 Important/Sec. openssl-1:3.0.7-27.el9.x86_64
+";
+        let advisory = parse_advisory("dnf", dnf, "openssl").unwrap();
+        assert_eq!(advisory.id, "RLSA-2024:4312");
+        assert_eq!(advisory.to_string(), "RLSA-2024:4312, important");
+        assert_eq!(parse_advisory("dnf", dnf, "openssl-libs"), None);
+    }
+}
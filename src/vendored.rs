@@ -0,0 +1,531 @@
+//! Libraries bundled inside snaps, flatpaks and AppImages. These formats ship
+//! their own copies of libraries such as OpenSSL, which distribution security
+//! updates never reach; this module finds them by the shared objects in each
+//! bundle and the version strings compiled into those.
+
+use crate::boxes::appimage;
+use anyhow::{anyhow, Result};
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, warn};
+
+/// A library recognised by the file names of its shared objects and the
+/// version string compiled into them
+struct KnownLibrary {
+    name: &'static str,
+    file_prefixes: &'static [&'static str],
+    version_pattern: &'static str,
+}
+
+const KNOWN_LIBRARIES: &[KnownLibrary] = &[
+    KnownLibrary {
+        name: "openssl",
+        file_prefixes: &["libssl.so", "libcrypto.so"],
+        version_pattern: r"OpenSSL (\d+\.\d+\.\d+[a-z]?)",
+    },
+    KnownLibrary {
+        name: "zlib",
+        file_prefixes: &["libz.so"],
+        version_pattern: r" deflate (\d+\.\d+(?:\.\d+)*) Copyright",
+    },
+    KnownLibrary {
+        name: "libpng",
+        file_prefixes: &["libpng"],
+        version_pattern: r"libpng version (\d+\.\d+\.\d+)",
+    },
+    KnownLibrary {
+        name: "expat",
+        file_prefixes: &["libexpat.so"],
+        version_pattern: r"expat_(\d+\.\d+\.\d+)",
+    },
+    KnownLibrary {
+        name: "glibc",
+        file_prefixes: &["libc.so.6", "libc-2."],
+        version_pattern: r"GNU C Library [^\n]*?version (\d+\.\d+)",
+    },
+];
+
+/// Bundles deeper than this are not searched further
+const MAX_DEPTH: usize = 12;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum VendoredFormat {
+    #[default]
+    Text,
+    Json,
+    /// CycloneDX 1.5 SBOM, libraries nested under the bundle shipping them
+    Cyclonedx,
+}
+
+/// An installed snap, flatpak (app or runtime) or AppImage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    pub name: String,
+    pub box_type: String,
+    pub version: Option<String>,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BundledLibrary {
+    pub name: String,
+    pub version: String,
+    /// Where in the bundle the library was found
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleReport {
+    pub name: String,
+    pub box_type: String,
+    pub version: Option<String>,
+    pub libraries: Vec<BundledLibrary>,
+    /// Why the bundle could not be looked into
+    pub error: Option<String>,
+}
+
+/// Snaps, flatpaks and AppImages on this machine
+pub fn installed_bundles() -> Vec<Bundle> {
+    let mut bundles = snaps(Path::new("/snap"));
+
+    let mut flatpak_dirs = vec![PathBuf::from("/var/lib/flatpak")];
+    if let Some(home) = dirs::home_dir() {
+        flatpak_dirs.push(home.join(".local/share/flatpak"));
+    }
+    for dir in flatpak_dirs {
+        bundles.extend(flatpaks(&dir));
+    }
+
+    for name in appimage::list_appimages().unwrap_or_default() {
+        if let Ok(path) = appimage::appimage_path(&name) {
+            bundles.push(Bundle {
+                name,
+                box_type: "appimage".to_string(),
+                version: None,
+                path,
+            });
+        }
+    }
+    bundles
+}
+
+fn snaps(root: &Path) -> Vec<Bundle> {
+    subdirs(root)
+        .into_iter()
+        .filter(|(name, _)| name != "bin")
+        .filter_map(|(name, dir)| {
+            let current = dir.join("current");
+            let yaml = fs::read_to_string(current.join("meta/snap.yaml")).ok()?;
+            Some(Bundle {
+                name,
+                box_type: "snap".to_string(),
+                version: snap_version(&yaml),
+                path: current,
+            })
+        })
+        .collect()
+}
+
+/// `version:` of a snap's `meta/snap.yaml`
+pub fn snap_version(yaml: &str) -> Option<String> {
+    yaml.lines()
+        .find_map(|line| line.strip_prefix("version:"))
+        .map(|version| version.trim().trim_matches(['\'', '"']).to_string())
+        .filter(|version| !version.is_empty())
+}
+
+/// Apps (`app/<id>/current`) and runtimes (`runtime/<id>/<arch>/<branch>`)
+/// of a flatpak installation; a runtime's branch serves as its version
+fn flatpaks(installation: &Path) -> Vec<Bundle> {
+    let mut bundles = Vec::new();
+    for (id, dir) in subdirs(&installation.join("app")) {
+        let files = dir.join("current/active/files");
+        if files.is_dir() {
+            bundles.push(Bundle {
+                name: id,
+                box_type: "flatpak".to_string(),
+                version: None,
+                path: files,
+            });
+        }
+    }
+    for (id, dir) in subdirs(&installation.join("runtime")) {
+        for (_, arch) in subdirs(&dir) {
+            for (branch, branch_dir) in subdirs(&arch) {
+                let files = branch_dir.join("active/files");
+                if files.is_dir() {
+                    bundles.push(Bundle {
+                        name: id.clone(),
+                        box_type: "flatpak".to_string(),
+                        version: Some(branch),
+                        path: files,
+                    });
+                }
+            }
+        }
+    }
+    bundles
+}
+
+fn subdirs(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut dirs: Vec<(String, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| {
+            (
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+            )
+        })
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Look into every installed bundle, or only those named `package`
+pub fn scan(package: Option<&str>) -> Vec<BundleReport> {
+    installed_bundles()
+        .into_iter()
+        .filter(|bundle| package.is_none_or(|package| bundle.name == package))
+        .map(|bundle| {
+            debug!(
+                "Scanning {} {} for bundled libraries",
+                bundle.box_type, bundle.name
+            );
+            let libraries = if bundle.box_type == "appimage" {
+                scan_appimage(&bundle.path)
+            } else {
+                Ok(scan_tree(&bundle.path))
+            };
+            let (libraries, error) = match libraries {
+                Ok(libraries) => (libraries, None),
+                Err(e) => {
+                    warn!("Could not scan {}: {}", bundle.name, e);
+                    (Vec::new(), Some(e.to_string()))
+                }
+            };
+            BundleReport {
+                name: bundle.name,
+                box_type: bundle.box_type,
+                version: bundle.version,
+                libraries,
+                error,
+            }
+        })
+        .collect()
+}
+
+/// The known libraries among the shared objects under `root`; symlinked
+/// sonames are skipped so each file counts once
+pub fn scan_tree(root: &Path) -> Vec<BundledLibrary> {
+    let mut found = BTreeSet::new();
+    let mut pending = vec![(root.to_path_buf(), 0)];
+
+    while let Some((dir, depth)) = pending.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() && depth < MAX_DEPTH {
+                pending.push((path, depth + 1));
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if library_of(&file_name).is_none() {
+                continue;
+            }
+            let Ok(contents) = fs::read(&path) else {
+                continue;
+            };
+            if let Some((name, version)) = identify(&file_name, &contents) {
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                found.insert(BundledLibrary {
+                    name,
+                    version,
+                    path: format!("/{}", relative.display()),
+                });
+            }
+        }
+    }
+
+    found.into_iter().collect()
+}
+
+/// The known library a shared object file name belongs to
+fn library_of(file_name: &str) -> Option<&'static KnownLibrary> {
+    if !file_name.contains(".so") {
+        return None;
+    }
+    KNOWN_LIBRARIES.iter().find(|library| {
+        library
+            .file_prefixes
+            .iter()
+            .any(|prefix| file_name.starts_with(prefix))
+    })
+}
+
+/// Library name and version of a shared object, from its file name and the
+/// version string in its contents
+pub fn identify(file_name: &str, contents: &[u8]) -> Option<(String, String)> {
+    let library = library_of(file_name)?;
+    let pattern = Regex::new(library.version_pattern).expect("valid version pattern");
+    let captures = pattern.captures(contents)?;
+    let version = String::from_utf8_lossy(&captures[1]).into_owned();
+    Some((library.name.to_string(), version))
+}
+
+/// Unpack an AppImage's squashfs with `unsquashfs`, without running the
+/// AppImage, and scan it
+fn scan_appimage(path: &Path) -> Result<Vec<BundledLibrary>> {
+    let mut header = vec![0u8; 64];
+    let read = {
+        use std::io::Read;
+        fs::File::open(path)?.read(&mut header)?
+    };
+    header.truncate(read);
+    let offset =
+        elf_size(&header).ok_or_else(|| anyhow!("{} is not an ELF AppImage", path.display()))?;
+
+    let target = tempfile::TempDir::new()?;
+    let root = target.path().join("squashfs-root");
+    let output = Command::new("unsquashfs")
+        .arg("-no-progress")
+        .arg("-no-xattrs")
+        .arg("-o")
+        .arg(offset.to_string())
+        .arg("-d")
+        .arg(&root)
+        .arg(path)
+        .output()
+        .map_err(|e| anyhow!("unsquashfs is needed to look into AppImages: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "unsquashfs failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(scan_tree(&root))
+}
+
+/// End of the ELF runtime at the start of an AppImage, where its squashfs
+/// begins: the section header table comes last
+pub fn elf_size(header: &[u8]) -> Option<u64> {
+    if header.len() < 52 || &header[..4] != b"\x7fELF" {
+        return None;
+    }
+    let little_endian = header[5] == 1;
+    let read = |offset: usize, width: usize| -> Option<u64> {
+        let bytes = header.get(offset..offset + width)?;
+        let mut value = 0u64;
+        for i in 0..width {
+            let byte = if little_endian {
+                bytes[width - 1 - i]
+            } else {
+                bytes[i]
+            };
+            value = (value << 8) | u64::from(byte);
+        }
+        Some(value)
+    };
+
+    match header[4] {
+        // 32-bit
+        1 => Some(read(32, 4)? + read(46, 2)? * read(48, 2)?),
+        // 64-bit
+        2 => Some(read(40, 8)? + read(58, 2)? * read(60, 2)?),
+        _ => None,
+    }
+}
+
+impl BundleReport {
+    pub fn to_text(reports: &[BundleReport]) -> String {
+        let mut text = String::new();
+        for report in reports {
+            text.push_str(&format!(
+                "📦 {} ({}{})\n",
+                report.name,
+                report.box_type,
+                report
+                    .version
+                    .as_deref()
+                    .map(|version| format!(" {}", version))
+                    .unwrap_or_default()
+            ));
+            if let Some(error) = &report.error {
+                text.push_str(&format!("  ⚠️  {}\n", error));
+            } else if report.libraries.is_empty() {
+                text.push_str("  No known libraries bundled\n");
+            }
+            for library in &report.libraries {
+                text.push_str(&format!(
+                    "  {} {}  {}\n",
+                    library.name, library.version, library.path
+                ));
+            }
+        }
+        text
+    }
+
+    /// A CycloneDX SBOM of the bundles, each with the libraries it ships as
+    /// nested components
+    pub fn to_cyclonedx(reports: &[BundleReport]) -> serde_json::Value {
+        let components: Vec<serde_json::Value> = reports
+            .iter()
+            .map(|report| {
+                let version = report.version.clone().unwrap_or_default();
+                // Runtimes can be installed in several branches at once
+                let bundle_ref = match &report.version {
+                    Some(version) => format!("{}/{}@{}", report.box_type, report.name, version),
+                    None => format!("{}/{}", report.box_type, report.name),
+                };
+                let libraries: Vec<serde_json::Value> = report
+                    .libraries
+                    .iter()
+                    .map(|library| {
+                        serde_json::json!({
+                            "type": "library",
+                            "bom-ref": format!("{}{}", bundle_ref, library.path),
+                            "name": library.name,
+                            "version": library.version,
+                            "purl": format!("pkg:generic/{}@{}", library.name, library.version),
+                            "properties": [{ "name": "omni:path", "value": library.path }],
+                        })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "type": "application",
+                    "bom-ref": bundle_ref,
+                    "name": report.name,
+                    "version": version,
+                    "properties": [{ "name": "omni:box_type", "value": report.box_type }],
+                    "components": libraries,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{}", uuid::Uuid::new_v4()),
+            "version": 1,
+            "metadata": {
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "tools": { "components": [{
+                    "type": "application",
+                    "name": "omni",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }] },
+            },
+            "components": components,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identify() {
+        let libcrypto = b"\x00\x00OpenSSL 3.0.2 15 Mar 2022\x00more";
+        assert_eq!(
+            identify("libcrypto.so.3", libcrypto),
+            Some(("openssl".to_string(), "3.0.2".to_string()))
+        );
+        let zlib = b" deflate 1.2.13 Copyright 1995-2022 Jean-loup Gailly and Mark Adler ";
+        assert_eq!(
+            identify("libz.so.1.2.13", zlib).map(|(_, version)| version),
+            Some("1.2.13".to_string())
+        );
+        // Right file, no version string: not reported
+        assert!(identify("libssl.so.3", b"nothing here").is_none());
+        // Version string in a file that is not a known library
+        assert!(identify("libfoo.so", libcrypto).is_none());
+        assert!(identify("libcrypto.a", libcrypto).is_none());
+    }
+
+    #[test]
+    fn test_scan_tree() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let lib = dir.path().join("usr/lib/x86_64-linux-gnu");
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(
+            lib.join("libssl.so.1.1"),
+            b"..OpenSSL 1.1.1f  31 Mar 2020..",
+        )
+        .unwrap();
+        fs::write(lib.join("libpng16.so.16.37.0"), b"libpng version 1.6.37").unwrap();
+        fs::write(lib.join("libother.so"), b"OpenSSL 9.9.9").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("libpng16.so.16.37.0", lib.join("libpng16.so.16")).unwrap();
+
+        let found = scan_tree(dir.path());
+        assert_eq!(
+            found,
+            [
+                BundledLibrary {
+                    name: "libpng".to_string(),
+                    version: "1.6.37".to_string(),
+                    path: "/usr/lib/x86_64-linux-gnu/libpng16.so.16.37.0".to_string(),
+                },
+                BundledLibrary {
+                    name: "openssl".to_string(),
+                    version: "1.1.1f".to_string(),
+                    path: "/usr/lib/x86_64-linux-gnu/libssl.so.1.1".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_elf_size() {
+        // 64-bit little-endian header: e_shoff 0x1000, 5 headers of 64 bytes
+        let mut header = vec![0u8; 64];
+        header[..4].copy_from_slice(b"\x7fELF");
+        header[4] = 2;
+        header[5] = 1;
+        header[40..48].copy_from_slice(&0x1000u64.to_le_bytes());
+        header[58..60].copy_from_slice(&64u16.to_le_bytes());
+        header[60..62].copy_from_slice(&5u16.to_le_bytes());
+        assert_eq!(elf_size(&header), Some(0x1000 + 320));
+
+        assert!(elf_size(b"#!/bin/sh\n").is_none());
+    }
+
+    #[test]
+    fn test_reports() {
+        assert_eq!(
+            snap_version("name: core22\nversion: '20240111'\n").as_deref(),
+            Some("20240111")
+        );
+
+        let reports = vec![BundleReport {
+            name: "firefox".to_string(),
+            box_type: "snap".to_string(),
+            version: Some("128.0".to_string()),
+            libraries: vec![BundledLibrary {
+                name: "openssl".to_string(),
+                version: "3.0.2".to_string(),
+                path: "/usr/lib/libssl.so.3".to_string(),
+            }],
+            error: None,
+        }];
+        let bom = BundleReport::to_cyclonedx(&reports);
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        let library = &bom["components"][0]["components"][0];
+        assert_eq!(library["purl"], "pkg:generic/openssl@3.0.2");
+        assert_eq!(library["bom-ref"], "snap/firefox@128.0/usr/lib/libssl.so.3");
+        assert!(BundleReport::to_text(&reports).contains("openssl 3.0.2  /usr/lib/libssl.so.3"));
+    }
+}
@@ -0,0 +1,682 @@
+//! Known vulnerabilities in installed distribution packages, from the OSV
+//! database (osv.dev), which lists the CVE IDs NVD publishes as aliases of
+//! the distribution advisories. Answers are kept in omni's database for
+//! `vulnerabilities.cache_hours`, and used past that when OSV cannot be
+//! reached.
+
+use crate::config::VulnerabilityConfig;
+use crate::database::Database;
+use crate::lifecycle::os_release_field;
+use anyhow::{anyhow, Result};
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::process::Command;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const API_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Queries OSV accepts in one batch
+const BATCH_SIZE: usize = 1000;
+
+/// The OSV ecosystem of the running distribution and the backend that
+/// installs its packages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ecosystem {
+    /// e.g. `Debian:12` or `Rocky Linux:9`
+    pub osv: String,
+    pub box_type: &'static str,
+}
+
+impl Ecosystem {
+    /// The running distribution's, or `None` for distributions OSV does not cover
+    pub fn detect() -> Option<Self> {
+        Self::from_os_release(&std::fs::read_to_string("/etc/os-release").ok()?)
+    }
+
+    pub fn from_os_release(contents: &str) -> Option<Self> {
+        let field = |name: &str| os_release_field(contents, name);
+        let id = field("ID")?;
+        let version = field("VERSION_ID")?;
+        let major = version.split('.').next().unwrap_or(&version).to_string();
+
+        let (osv, box_type) = match id.as_str() {
+            "debian" => (format!("Debian:{}", major), "apt"),
+            "ubuntu" => {
+                let lts = field("VERSION").is_some_and(|v| v.contains("LTS"));
+                let suffix = if lts { ":LTS" } else { "" };
+                (format!("Ubuntu:{}{}", version, suffix), "apt")
+            }
+            "rocky" => (format!("Rocky Linux:{}", major), "dnf"),
+            "almalinux" => (format!("AlmaLinux:{}", major), "dnf"),
+            "opensuse-leap" => (format!("openSUSE:Leap {}", version), "zypper"),
+            _ => return None,
+        };
+        Some(Self { osv, box_type })
+    }
+}
+
+/// An installed package as OSV knows it: source packages on Debian and
+/// Ubuntu, binary packages on RPM distributions
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PackageVersion {
+    pub name: String,
+    pub version: String,
+}
+
+/// A vulnerability affecting one installed package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vulnerability {
+    /// OSV ID, e.g. `DSA-5764-1` or `RLSA-2024:3501`
+    pub id: String,
+    /// CVE IDs among the ID and its aliases
+    pub cves: Vec<String>,
+    pub summary: String,
+    /// Severity or urgency as the advisory states it
+    pub severity: Option<String>,
+    /// First version of the package that fixes it, if one was released
+    pub fixed_version: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finding {
+    pub package: PackageVersion,
+    pub vulnerabilities: Vec<Vulnerability>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub ecosystem: String,
+    pub scanned: usize,
+    pub findings: Vec<Finding>,
+    /// OSV could not be reached and older cached answers were used
+    pub stale: bool,
+}
+
+impl ScanReport {
+    pub fn vulnerability_count(&self) -> usize {
+        self.findings.iter().map(|f| f.vulnerabilities.len()).sum()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for finding in &self.findings {
+            text.push_str(&format!(
+                "⚠️  {} {}\n",
+                finding.package.name, finding.package.version
+            ));
+            for vulnerability in &finding.vulnerabilities {
+                text.push_str(&format!("   {}\n", describe(vulnerability)));
+            }
+        }
+        if self.findings.is_empty() {
+            text.push_str(&format!(
+                "✅ No known vulnerabilities in {} {} packages\n",
+                self.scanned, self.ecosystem
+            ));
+        } else {
+            text.push_str(&format!(
+                "\n{} known vulnerabilities in {} of {} {} packages\n",
+                self.vulnerability_count(),
+                self.findings.len(),
+                self.scanned,
+                self.ecosystem
+            ));
+        }
+        if self.stale {
+            text.push_str("ℹ️  OSV could not be reached; results are from the local cache\n");
+        }
+        text
+    }
+}
+
+/// One line for `vulnerability`, e.g.
+/// "DSA-5764-1 (CVE-2024-6119) [high]: openssl security update; fixed in 3.0.14-1~deb12u2"
+pub fn describe(vulnerability: &Vulnerability) -> String {
+    let mut line = vulnerability.id.clone();
+    let cves: Vec<&String> = vulnerability
+        .cves
+        .iter()
+        .filter(|cve| **cve != vulnerability.id)
+        .collect();
+    if !cves.is_empty() {
+        let cves: Vec<&str> = cves.iter().map(|c| c.as_str()).collect();
+        line.push_str(&format!(" ({})", cves.join(", ")));
+    }
+    if let Some(severity) = &vulnerability.severity {
+        line.push_str(&format!(" [{}]", severity.to_lowercase()));
+    }
+    if !vulnerability.summary.is_empty() {
+        line.push_str(&format!(": {}", vulnerability.summary));
+    }
+    match &vulnerability.fixed_version {
+        Some(fixed) => line.push_str(&format!("; fixed in {}", fixed)),
+        None => line.push_str("; no fix released"),
+    }
+    line
+}
+
+/// Installed packages of `ecosystem`, read from the system package database
+pub fn installed_packages(ecosystem: &Ecosystem) -> Result<Vec<PackageVersion>> {
+    let (program, args): (&str, &[&str]) = match ecosystem.box_type {
+        "apt" => (
+            "dpkg-query",
+            &[
+                "-W",
+                "-f",
+                "${source:Package}\t${source:Version}\t${db:Status-Abbrev}\n",
+            ],
+        ),
+        _ => (
+            "rpm",
+            &["-qa", "--qf", "%{NAME}\t%{EPOCH}:%{VERSION}-%{RELEASE}\n"],
+        ),
+    };
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("Cannot run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(match ecosystem.box_type {
+        "apt" => parse_dpkg(&stdout),
+        _ => parse_rpm(&stdout),
+    })
+}
+
+/// Source packages from `dpkg-query` lines of name, version and status,
+/// once each and only for installed binaries
+pub fn parse_dpkg(output: &str) -> Vec<PackageVersion> {
+    let packages: BTreeSet<PackageVersion> = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?.trim();
+            let version = fields.next()?.trim();
+            let status = fields.next().unwrap_or("ii").trim();
+            (!name.is_empty() && !version.is_empty() && status.starts_with("ii")).then(|| {
+                PackageVersion {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                }
+            })
+        })
+        .collect();
+    packages.into_iter().collect()
+}
+
+/// Packages from `rpm -qa` lines of name and epoch:version-release; rpm
+/// prints `(none)` for packages without an epoch
+pub fn parse_rpm(output: &str) -> Vec<PackageVersion> {
+    let packages: BTreeSet<PackageVersion> = output
+        .lines()
+        .filter_map(|line| {
+            let (name, version) = line.split_once('\t')?;
+            let version = version.trim().trim_start_matches("(none):");
+            (!name.starts_with("gpg-pubkey") && !version.is_empty()).then(|| PackageVersion {
+                name: name.trim().to_string(),
+                version: version.to_string(),
+            })
+        })
+        .collect();
+    packages.into_iter().collect()
+}
+
+/// The parts of an OSV record omni shows
+#[derive(Debug, Deserialize)]
+struct OsvRecord {
+    id: String,
+    #[serde(default)]
+    summary: String,
+    #[serde(default)]
+    details: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    affected: Vec<OsvAffected>,
+    #[serde(default)]
+    database_specific: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvAffected {
+    package: OsvPackage,
+    #[serde(default)]
+    ranges: Vec<OsvRange>,
+    #[serde(default)]
+    ecosystem_specific: Option<serde_json::Value>,
+    #[serde(default)]
+    database_specific: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvPackage {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvRange {
+    #[serde(default)]
+    events: Vec<BTreeMap<String, String>>,
+}
+
+/// What the OSV record `json` says about `package`
+pub fn parse_vulnerability(json: &str, package: &str) -> Result<Vulnerability> {
+    let record: OsvRecord = serde_json::from_str(json)?;
+    let affected: Vec<&OsvAffected> = record
+        .affected
+        .iter()
+        .filter(|a| a.package.name == package)
+        .collect();
+
+    let fixed_version = affected
+        .iter()
+        .flat_map(|a| &a.ranges)
+        .flat_map(|r| &r.events)
+        .filter_map(|event| event.get("fixed"))
+        .last()
+        .cloned();
+    let text_field = |value: &Option<serde_json::Value>, name: &str| {
+        value
+            .as_ref()
+            .and_then(|v| v.get(name))
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+    };
+    // GHSA and most distributions state a severity, Debian an urgency
+    let severity = text_field(&record.database_specific, "severity").or_else(|| {
+        affected.iter().find_map(|a| {
+            text_field(&a.ecosystem_specific, "severity")
+                .or_else(|| text_field(&a.ecosystem_specific, "urgency"))
+                .or_else(|| text_field(&a.database_specific, "severity"))
+        })
+    });
+
+    let mut cves: Vec<String> = Vec::new();
+    for id in std::iter::once(&record.id).chain(&record.aliases) {
+        if id.starts_with("CVE-") && !cves.contains(id) {
+            cves.push(id.clone());
+        }
+    }
+    let summary = if record.summary.is_empty() {
+        record
+            .details
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    } else {
+        record.summary
+    };
+
+    Ok(Vulnerability {
+        id: record.id,
+        cves,
+        summary,
+        severity,
+        fixed_version,
+    })
+}
+
+/// Vulnerability IDs per query in an OSV `querybatch` answer
+pub fn parse_batch(json: &str) -> Result<Vec<Vec<String>>> {
+    #[derive(Deserialize)]
+    struct Batch {
+        results: Vec<BatchResult>,
+    }
+    #[derive(Deserialize)]
+    struct BatchResult {
+        #[serde(default)]
+        vulns: Vec<BatchVuln>,
+    }
+    #[derive(Deserialize)]
+    struct BatchVuln {
+        id: String,
+    }
+
+    let batch: Batch = serde_json::from_str(json)?;
+    Ok(batch
+        .results
+        .into_iter()
+        .map(|r| r.vulns.into_iter().map(|v| v.id).collect())
+        .collect())
+}
+
+/// Matches package versions against OSV, through the cache in omni's database
+pub struct Scanner {
+    db: Database,
+    config: VulnerabilityConfig,
+    ecosystem: Ecosystem,
+    client: reqwest::Client,
+}
+
+impl Scanner {
+    pub fn new(db: Database, config: VulnerabilityConfig, ecosystem: Ecosystem) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("omni/", env!("CARGO_PKG_VERSION")))
+            .timeout(API_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            db,
+            config,
+            ecosystem,
+            client,
+        })
+    }
+
+    /// Scan `packages`, asking OSV again for everything with `refresh`
+    pub async fn scan(&self, packages: &[PackageVersion], refresh: bool) -> Result<ScanReport> {
+        let fresh_after = Utc::now() - ChronoDuration::hours(self.config.cache_hours as i64);
+        let ecosystem = &self.ecosystem.osv;
+
+        let mut ids: Vec<Option<Vec<String>>> = Vec::with_capacity(packages.len());
+        let mut stale: Vec<Option<Vec<String>>> = Vec::with_capacity(packages.len());
+        for package in packages {
+            let cached = self
+                .db
+                .cached_vulnerability_ids(ecosystem, &package.name, &package.version)
+                .await?;
+            match cached {
+                Some((cached, queried_at)) if !refresh && queried_at >= fresh_after => {
+                    ids.push(Some(cached));
+                    stale.push(None);
+                }
+                cached => {
+                    ids.push(None);
+                    stale.push(cached.map(|(cached, _)| cached));
+                }
+            }
+        }
+
+        let missing: Vec<usize> = (0..packages.len()).filter(|&i| ids[i].is_none()).collect();
+        let mut used_stale = false;
+        for chunk in missing.chunks(BATCH_SIZE) {
+            let queries: Vec<&PackageVersion> = chunk.iter().map(|&i| &packages[i]).collect();
+            match self.query_batch(&queries).await {
+                Ok(results) => {
+                    for (&i, found) in chunk.iter().zip(results) {
+                        let package = &packages[i];
+                        self.db
+                            .cache_vulnerability_ids(
+                                ecosystem,
+                                &package.name,
+                                &package.version,
+                                &found,
+                            )
+                            .await?;
+                        ids[i] = Some(found);
+                    }
+                }
+                Err(e) => {
+                    warn!("Cannot query OSV: {:#}", e);
+                    for &i in chunk {
+                        if let Some(cached) = stale[i].take() {
+                            used_stale = true;
+                            ids[i] = Some(cached);
+                        }
+                    }
+                }
+            }
+        }
+        if missing.iter().any(|&i| ids[i].is_none()) {
+            return Err(anyhow!(
+                "OSV could not be reached and the cache does not cover every package"
+            ));
+        }
+
+        let mut findings = Vec::new();
+        for (package, found) in packages.iter().zip(ids) {
+            let mut vulnerabilities = Vec::new();
+            for id in found.unwrap_or_default() {
+                match self.record(&id, fresh_after, refresh).await {
+                    Ok(json) => match parse_vulnerability(&json, &package.name) {
+                        Ok(vulnerability) => vulnerabilities.push(vulnerability),
+                        Err(e) => debug!("Unreadable OSV record {}: {}", id, e),
+                    },
+                    Err(e) => {
+                        warn!("Cannot fetch {} from OSV: {:#}", id, e);
+                        used_stale = true;
+                        vulnerabilities.push(Vulnerability {
+                            id: id.clone(),
+                            cves: Vec::new(),
+                            summary: String::new(),
+                            severity: None,
+                            fixed_version: None,
+                        });
+                    }
+                }
+            }
+            if !vulnerabilities.is_empty() {
+                findings.push(Finding {
+                    package: package.clone(),
+                    vulnerabilities,
+                });
+            }
+        }
+
+        Ok(ScanReport {
+            ecosystem: ecosystem.clone(),
+            scanned: packages.len(),
+            findings,
+            stale: used_stale,
+        })
+    }
+
+    async fn query_batch(&self, packages: &[&PackageVersion]) -> Result<Vec<Vec<String>>> {
+        let queries: Vec<serde_json::Value> = packages
+            .iter()
+            .map(|p| {
+                json!({
+                    "package": {"name": p.name, "ecosystem": self.ecosystem.osv},
+                    "version": p.version,
+                })
+            })
+            .collect();
+        let body = self
+            .client
+            .post(format!("{}/v1/querybatch", self.config.osv_url))
+            .json(&json!({ "queries": queries }))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let results = parse_batch(&body)?;
+        if results.len() != packages.len() {
+            return Err(anyhow!(
+                "OSV answered {} of {} queries",
+                results.len(),
+                packages.len()
+            ));
+        }
+        Ok(results)
+    }
+
+    /// The OSV record of `id`, from the cache while it is fresh
+    async fn record(
+        &self,
+        id: &str,
+        fresh_after: chrono::DateTime<Utc>,
+        refresh: bool,
+    ) -> Result<String> {
+        let cached = self.db.cached_vulnerability(id).await?;
+        if let Some((json, fetched_at)) = &cached {
+            if !refresh && *fetched_at >= fresh_after {
+                return Ok(json.clone());
+            }
+        }
+        let fetched = async {
+            self.client
+                .get(format!("{}/v1/vulns/{}", self.config.osv_url, id))
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await;
+        match (fetched, cached) {
+            (Ok(json), _) => {
+                self.db.cache_vulnerability(id, &json).await?;
+                Ok(json)
+            }
+            (Err(e), Some((json, _))) => {
+                debug!("Using the cached record of {}: {}", id, e);
+                Ok(json)
+            }
+            (Err(e), None) => Err(e.into()),
+        }
+    }
+}
+
+/// Scan the packages installed on this machine, or only the ones named in
+/// `only`
+pub async fn scan_installed(
+    config: &VulnerabilityConfig,
+    only: Option<&[String]>,
+    refresh: bool,
+) -> Result<ScanReport> {
+    let ecosystem = Ecosystem::detect().ok_or_else(|| {
+        anyhow!("This distribution is not covered by OSV; scans need Debian, Ubuntu, Rocky Linux, AlmaLinux or openSUSE Leap")
+    })?;
+    let mut packages = installed_packages(&ecosystem)?;
+    if let Some(only) = only {
+        packages.retain(|p| only.contains(&p.name));
+    }
+    let db = Database::new().await?;
+    Scanner::new(db, config.clone(), ecosystem)?
+        .scan(&packages, refresh)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DSA: &str = r#"{
+        "id": "DSA-5764-1",
+        "summary": "openssl - security update",
+        "aliases": ["CVE-2024-6119"],
+        "upstream": ["CVE-2024-6119"],
+        "affected": [{
+            "package": {"ecosystem": "Debian:12", "name": "openssl"},
+            "ranges": [{"type": "ECOSYSTEM", "events": [{"introduced": "0"}, {"fixed": "3.0.14-1~deb12u2"}]}],
+            "ecosystem_specific": {"urgency": "high"}
+        }]
+    }"#;
+
+    #[test]
+    fn test_ecosystem_from_os_release() {
+        let debian =
+            "PRETTY_NAME=\"Debian GNU/Linux 12 (bookworm)\"\nVERSION_ID=\"12\"\nID=debian\n";
+        assert_eq!(
+            Ecosystem::from_os_release(debian),
+            Some(Ecosystem {
+                osv: "Debian:12".to_string(),
+                box_type: "apt"
+            })
+        );
+        let ubuntu = "VERSION=\"24.04.1 LTS (Noble Numbat)\"\nVERSION_ID=\"24.04\"\nID=ubuntu\n";
+        assert_eq!(
+            Ecosystem::from_os_release(ubuntu).unwrap().osv,
+            "Ubuntu:24.04:LTS"
+        );
+        let rocky = "NAME=\"Rocky Linux\"\nVERSION_ID=\"9.4\"\nID=\"rocky\"\n";
+        assert_eq!(
+            Ecosystem::from_os_release(rocky).unwrap(),
+            Ecosystem {
+                osv: "Rocky Linux:9".to_string(),
+                box_type: "dnf"
+            }
+        );
+        assert_eq!(
+            Ecosystem::from_os_release("ID=arch\nVERSION_ID=rolling\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_installed() {
+        let dpkg =
+            "openssl\t3.0.14-1~deb12u1\tii \nopenssl\t3.0.14-1~deb12u1\tii \nbash\t5.2.15-2\trc \n";
+        assert_eq!(
+            parse_dpkg(dpkg),
+            vec![PackageVersion {
+                name: "openssl".to_string(),
+                version: "3.0.14-1~deb12u1".to_string(),
+            }]
+        );
+        let rpm = "openssl\t1:3.0.7-25.el9_3\nbash\t(none):5.1.8-9.el9\ngpg-pubkey\t(none):350d275d-6279f1be\n";
+        let packages = parse_rpm(rpm);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].version, "5.1.8-9.el9");
+        assert_eq!(packages[1].version, "1:3.0.7-25.el9_3");
+    }
+
+    #[test]
+    fn test_parse_vulnerability() {
+        let vulnerability = parse_vulnerability(DSA, "openssl").unwrap();
+        assert_eq!(vulnerability.cves, vec!["CVE-2024-6119"]);
+        assert_eq!(vulnerability.severity.as_deref(), Some("high"));
+        assert_eq!(
+            vulnerability.fixed_version.as_deref(),
+            Some("3.0.14-1~deb12u2")
+        );
+        assert_eq!(
+            describe(&vulnerability),
+            "DSA-5764-1 (CVE-2024-6119) [high]: openssl - security update; fixed in 3.0.14-1~deb12u2"
+        );
+
+        let batch = r#"{"results": [{"vulns": [{"id": "DSA-5764-1", "modified": "2024-09-03T00:00:00Z"}]}, {}]}"#;
+        assert_eq!(
+            parse_batch(batch).unwrap(),
+            vec![vec!["DSA-5764-1".to_string()], Vec::new()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_uses_the_cache() {
+        let db = Database::new_in_memory().await.unwrap();
+        let ecosystem = Ecosystem {
+            osv: "Debian:12".to_string(),
+            box_type: "apt",
+        };
+        let openssl = PackageVersion {
+            name: "openssl".to_string(),
+            version: "3.0.14-1~deb12u1".to_string(),
+        };
+        db.cache_vulnerability_ids(
+            "Debian:12",
+            &openssl.name,
+            &openssl.version,
+            &["DSA-5764-1".to_string()],
+        )
+        .await
+        .unwrap();
+        db.cache_vulnerability("DSA-5764-1", DSA).await.unwrap();
+
+        // An unreachable OSV is never asked while the cache is fresh
+        let config = VulnerabilityConfig {
+            osv_url: "http://127.0.0.1:9".to_string(),
+            ..VulnerabilityConfig::default()
+        };
+        let scanner = Scanner::new(db, config, ecosystem).unwrap();
+        let report = scanner.scan(&[openssl.clone()], false).await.unwrap();
+        assert_eq!(report.vulnerability_count(), 1);
+        assert!(!report.stale);
+        assert_eq!(report.findings[0].vulnerabilities[0].id, "DSA-5764-1");
+
+        // Asked again, it falls back to the cached answers
+        let report = scanner.scan(&[openssl], true).await.unwrap();
+        assert!(report.stale);
+        assert_eq!(report.vulnerability_count(), 1);
+    }
+}
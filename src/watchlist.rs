@@ -0,0 +1,274 @@
+use crate::changelog;
+use crate::config::OmniConfig;
+use crate::database::{Database, WatchedPackage};
+use crate::distro;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use tracing::{info, warn};
+
+/// Backends asked, in order, when a package is watched without `--box-type`
+const WATCH_BOX_TYPES: [&str; 5] = ["apt", "dnf", "pacman", "flatpak", "snap"];
+
+/// Changelog entries scanned for advisories when a new version shows up
+const ADVISORY_SCAN_ENTRIES: usize = 50;
+
+/// Something new about a watched package since the previous check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchChange {
+    pub package: String,
+    pub box_type: String,
+    pub previous_version: Option<String>,
+    pub current_version: Option<String>,
+    /// Advisory IDs mentioned in the changelog since the previous version
+    pub new_advisories: Vec<String>,
+}
+
+impl WatchChange {
+    pub fn version_changed(&self) -> bool {
+        self.previous_version.is_some() && self.previous_version != self.current_version
+    }
+}
+
+/// Start watching `package`. The package does not need to be installed; it only
+/// has to be known to the backend.
+pub async fn add(db: &Database, package: &str, box_type: Option<&str>) -> Result<WatchedPackage> {
+    let (box_type, version) = match box_type {
+        Some(box_type) => (box_type.to_string(), available_version(box_type, package)?),
+        None => WATCH_BOX_TYPES
+            .iter()
+            .filter(|bt| distro::command_exists(backend_command(bt)))
+            .find_map(|bt| match available_version(bt, package) {
+                Ok(Some(version)) => Some((bt.to_string(), Some(version))),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("No available package manager knows {}", package))?,
+    };
+
+    let watch = WatchedPackage {
+        package_name: package.to_string(),
+        box_type,
+        last_version: version,
+        known_advisories: Vec::new(),
+        added_at: Utc::now(),
+        checked_at: Some(Utc::now()),
+    };
+    db.watch_package(&watch).await?;
+    Ok(watch)
+}
+
+/// Compare every watched package against its backend and report what changed.
+/// The new state is stored unless omni runs in read-only mode, so each change is
+/// reported once.
+pub async fn check(db: &Database) -> Result<Vec<WatchChange>> {
+    let mut changes = Vec::new();
+
+    for mut watch in db.get_watched_packages().await? {
+        let current = match available_version(&watch.box_type, &watch.package_name) {
+            Ok(current) => current,
+            Err(e) => {
+                warn!(
+                    "Could not check {} via {}: {}",
+                    watch.package_name, watch.box_type, e
+                );
+                continue;
+            }
+        };
+
+        let mut new_advisories = Vec::new();
+        if current.is_some() && current != watch.last_version {
+            match changelog::fetch(
+                &watch.box_type,
+                &watch.package_name,
+                watch.last_version.as_deref(),
+                ADVISORY_SCAN_ENTRIES,
+            ) {
+                Ok(text) => {
+                    new_advisories = advisory_ids(&text)
+                        .into_iter()
+                        .filter(|id| !watch.known_advisories.contains(id))
+                        .collect();
+                }
+                Err(e) => info!("No changelog to scan for {}: {}", watch.package_name, e),
+            }
+        }
+
+        let change = WatchChange {
+            package: watch.package_name.clone(),
+            box_type: watch.box_type.clone(),
+            previous_version: watch.last_version.clone(),
+            current_version: current.clone().or_else(|| watch.last_version.clone()),
+            new_advisories,
+        };
+        if change.version_changed() || !change.new_advisories.is_empty() {
+            changes.push(change.clone());
+        }
+
+        if !OmniConfig::is_read_only() {
+            watch.last_version = change.current_version;
+            watch.known_advisories.extend(change.new_advisories);
+            watch.checked_at = Some(Utc::now());
+            db.watch_package(&watch).await?;
+        }
+    }
+
+    Ok(changes)
+}
+
+fn backend_command(box_type: &str) -> &str {
+    match box_type {
+        "apt" => "apt-cache",
+        other => other,
+    }
+}
+
+/// Newest version the backend offers for `package`, installed or not
+pub fn available_version(box_type: &str, package: &str) -> Result<Option<String>> {
+    let args: Vec<String> = match box_type {
+        "apt" => vec!["policy".into(), package.into()],
+        "dnf" => vec![
+            "repoquery".into(),
+            "--latest-limit".into(),
+            "1".into(),
+            "--queryformat".into(),
+            "%{evr}\\n".into(),
+            package.into(),
+        ],
+        "pacman" => vec!["-Si".into(), package.into()],
+        "flatpak" => vec![
+            "remote-info".into(),
+            changelog::flatpak_origin(package).unwrap_or_else(|| "flathub".to_string()),
+            package.into(),
+        ],
+        "snap" => vec!["info".into(), package.into()],
+        other => {
+            return Err(anyhow!(
+                "Watching is not supported for box type '{}'",
+                other
+            ))
+        }
+    };
+
+    let command = backend_command(box_type);
+    let output = Command::new(command)
+        .args(&args)
+        .output()
+        .map_err(|e| anyhow!("Failed to run {}: {}", command, e))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    Ok(parse_available_version(
+        box_type,
+        &String::from_utf8_lossy(&output.stdout),
+    ))
+}
+
+/// Version from `apt-cache policy`, `dnf repoquery`, `pacman -Si`,
+/// `flatpak remote-info` or `snap info` output
+pub fn parse_available_version(box_type: &str, output: &str) -> Option<String> {
+    let field = |name: &str| {
+        output.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == name).then(|| value.trim().to_string())
+        })
+    };
+
+    let version = match box_type {
+        "apt" => field("Candidate").filter(|v| v != "(none)"),
+        "dnf" => output
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.contains(' '))
+            .last()
+            .map(str::to_string),
+        "pacman" | "flatpak" => field("Version"),
+        // `  latest/stable:    1.2.3 2024-01-01 (123) 10MB -`
+        "snap" => output.lines().find_map(|line| {
+            let rest = line.trim().strip_prefix("latest/stable:")?;
+            rest.split_whitespace().next().map(str::to_string)
+        }),
+        _ => None,
+    };
+    version.filter(|v| !v.is_empty() && v != "--" && v != "^")
+}
+
+/// CVE identifiers mentioned in `text`, in order of first appearance
+pub fn advisory_ids(text: &str) -> Vec<String> {
+    let pattern = Regex::new(r"CVE-\d{4}-\d{4,}").expect("valid CVE pattern");
+    let mut ids: Vec<String> = Vec::new();
+    for found in pattern.find_iter(text) {
+        if !ids.iter().any(|id| id == found.as_str()) {
+            ids.push(found.as_str().to_string());
+        }
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_available_version() {
+        let apt = "openssl:
+  Installed: (none)
+  Candidate: 3.0.13-0ubuntu3.4
+  Version table:
+";
+        assert_eq!(
+            parse_available_version("apt", apt).as_deref(),
+            Some("3.0.13-0ubuntu3.4")
+        );
+        assert_eq!(
+            parse_available_version("apt", "foo:\n  Installed: (none)\n  Candidate: (none)\n"),
+            None
+        );
+
+        let pacman =
+            "Repository      : core\nName            : openssl\nVersion         : 3.3.1-1\n";
+        assert_eq!(
+            parse_available_version("pacman", pacman).as_deref(),
+            Some("3.3.1-1")
+        );
+
+        let dnf = "Last metadata expiration check: 0:12:01 ago.\n1:3.1.1-4.fc40\n";
+        assert_eq!(
+            parse_available_version("dnf", dnf).as_deref(),
+            Some("1:3.1.1-4.fc40")
+        );
+
+        let snap = "name:      firefox\nchannels:\n  latest/stable:    128.0 2024-07-09 (4630) 281MB -\n  latest/candidate: 129.0 2024-07-30 (4650) 282MB -\n";
+        assert_eq!(
+            parse_available_version("snap", snap).as_deref(),
+            Some("128.0")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watchlist_state_roundtrip() {
+        let db = Database::new_in_memory().await.unwrap();
+        let watch = WatchedPackage {
+            package_name: "openssl".to_string(),
+            box_type: "apt".to_string(),
+            last_version: Some("3.0.13-0ubuntu3.4".to_string()),
+            known_advisories: advisory_ids("Fixes CVE-2024-5535 and CVE-2024-5535, CVE-2024-4741"),
+            added_at: Utc::now(),
+            checked_at: None,
+        };
+        assert_eq!(
+            watch.known_advisories,
+            vec!["CVE-2024-5535", "CVE-2024-4741"]
+        );
+
+        db.watch_package(&watch).await.unwrap();
+        let watched = db.get_watched_packages().await.unwrap();
+        assert_eq!(watched.len(), 1);
+        assert_eq!(watched[0].known_advisories, watch.known_advisories);
+
+        assert_eq!(db.unwatch_package("openssl", None).await.unwrap(), 1);
+        assert!(db.get_watched_packages().await.unwrap().is_empty());
+    }
+}
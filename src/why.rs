@@ -0,0 +1,301 @@
+use crate::database::{Database, InstallRecord, InstallStatus};
+use crate::distro;
+use crate::mock;
+use crate::search;
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::process::Command;
+
+/// Packages looked at while following reverse dependencies up to one that was
+/// installed on purpose
+const MAX_CHAIN_VISITS: usize = 64;
+
+/// How a package came to be installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallReason {
+    /// Asked for by name, with omni or the native tool
+    Explicit,
+    /// Installed from the named manifest
+    Manifest(Option<String>),
+    /// Pulled in by another package
+    Dependency,
+}
+
+/// Answer to `omni why <package>`
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub package: String,
+    pub box_type: String,
+    pub reason: InstallReason,
+    /// The latest install of the package omni recorded
+    pub record: Option<InstallRecord>,
+    /// From the package up to the explicitly installed package that needs it;
+    /// empty when no such package was found
+    pub chain: Vec<String>,
+}
+
+impl fmt::Display for InstallReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallReason::Explicit => write!(f, "installed explicitly"),
+            InstallReason::Manifest(Some(project)) => {
+                write!(f, "installed from manifest '{}'", project)
+            }
+            InstallReason::Manifest(None) => write!(f, "installed from a manifest"),
+            InstallReason::Dependency => write!(f, "pulled in as a dependency"),
+        }
+    }
+}
+
+/// Explain why `package` is installed from install history, manifest
+/// provenance and the native package manager's install reason, following
+/// reverse dependencies for packages installed as dependencies
+pub async fn explain(db: &Database, package: &str, box_type: Option<&str>) -> Result<Explanation> {
+    let record = db
+        .get_package_history(package)
+        .await?
+        .into_iter()
+        .filter(|r| box_type.is_none_or(|bt| r.box_type == bt))
+        .find(|r| !matches!(r.status, InstallStatus::Failed));
+    let record = record.filter(|r| !matches!(r.status, InstallStatus::Removed));
+
+    let box_type = match (box_type, &record) {
+        (Some(box_type), _) => box_type.to_string(),
+        (None, Some(record)) => record.box_type.clone(),
+        (None, None) => ["apt", "dnf", "pacman"]
+            .into_iter()
+            .find(|bt| native_reason(bt, package).is_some())
+            .ok_or_else(|| anyhow!("{} is not installed", package))?
+            .to_string(),
+    };
+    let native = native_reason(&box_type, package);
+    if record.is_none() && native.is_none() {
+        return Err(anyhow!("{} is not installed via {}", package, box_type));
+    }
+
+    let reason = match (record.as_ref().and_then(manifest_reason), &record, native) {
+        (Some(reason), _, _) => reason,
+        (None, Some(_), _) | (None, None, Some(true)) => InstallReason::Explicit,
+        _ => InstallReason::Dependency,
+    };
+
+    let chain = if reason == InstallReason::Dependency {
+        let recorded: HashSet<String> = db
+            .get_installed_packages()
+            .await?
+            .into_iter()
+            .map(|r| r.package_name)
+            .collect();
+        dependency_chain(
+            package,
+            |name| search::query_reverse_dependencies(&box_type, name, true).unwrap_or_default(),
+            |name| recorded.contains(name) || native_reason(&box_type, name) == Some(true),
+        )
+    } else {
+        Vec::new()
+    };
+
+    Ok(Explanation {
+        package: package.to_string(),
+        box_type,
+        reason,
+        record,
+        chain,
+    })
+}
+
+/// `explain` against the mock universe
+pub fn explain_mock(package: &str) -> Result<Explanation> {
+    let backend = mock::backend();
+    let explicit = backend
+        .install_reason(package)
+        .ok_or_else(|| anyhow!("{} is not installed", package))?;
+    let record = backend
+        .installed()
+        .into_iter()
+        .find(|r| r.package_name == package);
+
+    let (reason, chain) = if explicit {
+        (InstallReason::Explicit, Vec::new())
+    } else {
+        let chain = dependency_chain(
+            package,
+            |name| backend.reverse_dependencies(name, true),
+            |name| backend.install_reason(name) == Some(true),
+        );
+        (InstallReason::Dependency, chain)
+    };
+
+    Ok(Explanation {
+        package: package.to_string(),
+        box_type: record
+            .as_ref()
+            .map_or_else(|| "mock".to_string(), |r| r.box_type.clone()),
+        reason,
+        record,
+        chain,
+    })
+}
+
+/// `InstallReason::Manifest` for a recorded install that came from a manifest
+fn manifest_reason(record: &InstallRecord) -> Option<InstallReason> {
+    let rest = record
+        .metadata
+        .as_deref()?
+        .strip_prefix("Installed via manifest")?;
+    let project = rest.trim().trim_matches('\'');
+    Some(InstallReason::Manifest(
+        (!project.is_empty()).then(|| project.to_string()),
+    ))
+}
+
+/// Whether the native package manager marks an installed package as
+/// explicitly installed (true) or installed as a dependency (false); None
+/// when it is not installed or the backend cannot tell
+pub fn native_reason(box_type: &str, package: &str) -> Option<bool> {
+    match box_type {
+        "apt" if distro::command_exists("apt-mark") => {
+            let listed = |which: &str| {
+                Command::new("apt-mark")
+                    .args([which, package])
+                    .output()
+                    .is_ok_and(|o| {
+                        String::from_utf8_lossy(&o.stdout)
+                            .lines()
+                            .any(|l| l.trim() == package)
+                    })
+            };
+            if listed("showmanual") {
+                Some(true)
+            } else if listed("showauto") {
+                Some(false)
+            } else {
+                None
+            }
+        }
+        "dnf" if distro::command_exists("dnf") => {
+            let output = Command::new("dnf")
+                .args([
+                    "repoquery",
+                    "--installed",
+                    "--quiet",
+                    "--qf",
+                    "%{reason}\n",
+                    package,
+                ])
+                .output()
+                .ok()?;
+            parse_install_reason("dnf", &String::from_utf8_lossy(&output.stdout))
+        }
+        "pacman" if distro::command_exists("pacman") => {
+            let output = Command::new("pacman")
+                .args(["-Qi", package])
+                .output()
+                .ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            parse_install_reason("pacman", &String::from_utf8_lossy(&output.stdout))
+        }
+        _ => None,
+    }
+}
+
+/// Install reason from `dnf repoquery --qf '%{reason}'` (`user`, `group`,
+/// `dependency`, `weak-dependency`) or the `Install Reason` line of
+/// `pacman -Qi`
+pub fn parse_install_reason(box_type: &str, output: &str) -> Option<bool> {
+    match box_type {
+        "dnf" => match output.lines().map(str::trim).find(|l| !l.is_empty())? {
+            "user" | "group" => Some(true),
+            "dependency" | "weak-dependency" => Some(false),
+            _ => None,
+        },
+        "pacman" => {
+            let reason = output.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key.trim() == "Install Reason").then(|| value.trim())
+            })?;
+            Some(reason.starts_with("Explicitly"))
+        }
+        _ => None,
+    }
+}
+
+/// Shortest chain of installed reverse dependencies from `package` to a
+/// package `is_root` accepts, `package` first; empty when none is found
+pub fn dependency_chain(
+    package: &str,
+    mut reverse_dependencies: impl FnMut(&str) -> Vec<String>,
+    mut is_root: impl FnMut(&str) -> bool,
+) -> Vec<String> {
+    let mut parents: HashMap<String, String> = HashMap::new();
+    let mut seen = HashSet::from([package.to_string()]);
+    let mut queue = VecDeque::from([package.to_string()]);
+
+    while let Some(current) = queue.pop_front() {
+        if seen.len() > MAX_CHAIN_VISITS {
+            break;
+        }
+        for dependent in reverse_dependencies(&current) {
+            if !seen.insert(dependent.clone()) {
+                continue;
+            }
+            parents.insert(dependent.clone(), current.clone());
+            if is_root(&dependent) {
+                let mut chain = vec![dependent];
+                while let Some(parent) = parents.get(chain.last().unwrap()) {
+                    chain.push(parent.clone());
+                }
+                chain.reverse();
+                return chain;
+            }
+            queue.push_back(dependent);
+        }
+    }
+
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_install_reason() {
+        let pacman = "Name            : libcurl\nInstall Reason  : Installed as a dependency for another package\n";
+        assert_eq!(parse_install_reason("pacman", pacman), Some(false));
+        let pacman = "Name            : git\nInstall Reason  : Explicitly installed\n";
+        assert_eq!(parse_install_reason("pacman", pacman), Some(true));
+        assert_eq!(parse_install_reason("dnf", "user\n"), Some(true));
+        assert_eq!(parse_install_reason("dnf", "dependency\n"), Some(false));
+        assert_eq!(parse_install_reason("dnf", ""), None);
+    }
+
+    #[test]
+    fn test_dependency_chain() {
+        let graph: HashMap<&str, Vec<&str>> = HashMap::from([
+            ("libz", vec!["libcurl", "libpng"]),
+            ("libcurl", vec!["curl"]),
+            ("libpng", vec!["gimp"]),
+            ("curl", vec!["git"]),
+        ]);
+        let reverse = |name: &str| {
+            graph
+                .get(name)
+                .map(|deps| deps.iter().map(|d| d.to_string()).collect())
+                .unwrap_or_default()
+        };
+
+        assert_eq!(
+            dependency_chain("libz", reverse, |name| name == "gimp" || name == "git"),
+            ["libz", "libpng", "gimp"]
+        );
+        assert_eq!(
+            dependency_chain("libcurl", reverse, |name| name == "git"),
+            ["libcurl", "curl", "git"]
+        );
+        assert!(dependency_chain("libcurl", reverse, |_| false).is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+/// Package managers omni drives on remote Windows hosts
+pub const WINDOWS_BOX_TYPES: [&str; 3] = ["winget", "choco", "scoop"];
+
+// winget exits with HRESULTs; these are the ones that are not plain failures
+const WINGET_UPDATE_NOT_APPLICABLE: u32 = 0x8A15_002B;
+const WINGET_PACKAGE_ALREADY_INSTALLED: u32 = 0x8A15_0061;
+const WINGET_NO_APPLICATIONS_FOUND: u32 = 0x8A15_0014;
+
+pub fn is_windows_box(box_type: &str) -> bool {
+    WINDOWS_BOX_TYPES.contains(&box_type)
+}
+
+/// Command line that runs `operation` (install/remove/update) for `package`.
+/// Works unchanged under cmd.exe and PowerShell, so it suits both WinRM and
+/// Windows OpenSSH whatever its default shell is.
+pub fn package_command(box_type: &str, operation: &str, package: &str) -> Result<String> {
+    let command = match (box_type, operation) {
+        ("winget", "install") => format!(
+            "winget install --id {} --exact --silent --accept-package-agreements --accept-source-agreements --disable-interactivity",
+            package
+        ),
+        ("winget", "remove") => format!(
+            "winget uninstall --id {} --exact --silent --disable-interactivity",
+            package
+        ),
+        ("winget", "update") => format!(
+            "winget upgrade --id {} --exact --silent --accept-package-agreements --accept-source-agreements --disable-interactivity",
+            package
+        ),
+        ("choco", "install") => format!("choco install {} -y --no-progress", package),
+        ("choco", "remove") => format!("choco uninstall {} -y --no-progress", package),
+        ("choco", "update") => format!("choco upgrade {} -y --no-progress", package),
+        ("scoop", "install") => format!("scoop install {}", package),
+        ("scoop", "remove") => format!("scoop uninstall {}", package),
+        ("scoop", "update") => format!("scoop update {}", package),
+        (box_type, operation) if is_windows_box(box_type) => {
+            return Err(anyhow!("Unsupported operation: {}", operation))
+        }
+        (box_type, _) => {
+            return Err(anyhow!(
+                "'{}' is not a Windows package manager",
+                box_type
+            ))
+        }
+    };
+
+    Ok(command)
+}
+
+/// Command that prints the installed packages in the form parsed by [`parse_package_list`]
+pub fn list_command(box_type: &str) -> Option<&'static str> {
+    match box_type {
+        "winget" => Some("winget list --accept-source-agreements --disable-interactivity"),
+        // Chocolatey 2.x lists local packages only; `--limit-output` prints `name|version`
+        "choco" => Some("choco list --limit-output"),
+        "scoop" => Some("scoop export"),
+        _ => None,
+    }
+}
+
+/// Probe that succeeds when `box_type` is on the host's PATH (`where.exe`, not the
+/// PowerShell `where` alias)
+pub fn probe_command(box_type: &str) -> String {
+    format!("where.exe {}", box_type)
+}
+
+/// Turn a finished package command into a summary, or an error carrying the
+/// manager's own explanation
+pub fn interpret_result(
+    box_type: &str,
+    operation: &str,
+    package: &str,
+    exit_code: i32,
+    stdout: &str,
+    stderr: &str,
+) -> Result<String> {
+    let done = format!("{} {} via {}", operation, package, box_type);
+
+    match box_type {
+        "winget" => match exit_code as u32 {
+            0 => Ok(done),
+            WINGET_UPDATE_NOT_APPLICABLE => Ok(format!("{} is already up to date", package)),
+            WINGET_PACKAGE_ALREADY_INSTALLED => Ok(format!("{} is already installed", package)),
+            WINGET_NO_APPLICATIONS_FOUND => Err(anyhow!(
+                "winget found no package with id {} (ids are case-sensitive, e.g. Git.Git)",
+                package
+            )),
+            code => Err(anyhow!(
+                "winget exited with 0x{:08X}: {}",
+                code,
+                failure_reason(stdout, stderr)
+            )),
+        },
+        // Chocolatey documents these as success; 1641/3010 mean a reboot is pending
+        "choco" => match exit_code {
+            0 | 1605 | 1614 => Ok(done),
+            1641 | 3010 => Ok(format!("{} (reboot required)", done)),
+            code => Err(anyhow!(
+                "choco exited with {}: {}",
+                code,
+                failure_reason(stdout, stderr)
+            )),
+        },
+        // scoop often exits 0 after printing an error, so check its output as well
+        "scoop" => {
+            let failed = stdout
+                .lines()
+                .chain(stderr.lines())
+                .any(|line| line.contains("Couldn't find manifest") || line.starts_with("ERROR"));
+            if exit_code == 0 && !failed {
+                if stdout.contains("is already installed") {
+                    Ok(format!("{} is already installed", package))
+                } else {
+                    Ok(done)
+                }
+            } else {
+                Err(anyhow!(
+                    "scoop {} failed: {}",
+                    operation,
+                    failure_reason(stdout, stderr)
+                ))
+            }
+        }
+        other => Err(anyhow!("'{}' is not a Windows package manager", other)),
+    }
+}
+
+/// Installed packages (`id → version`) from the output of [`list_command`]
+pub fn parse_package_list(box_type: &str, output: &str) -> BTreeMap<String, String> {
+    match box_type {
+        "winget" => parse_winget_list(output),
+        "choco" => parse_choco_list(output),
+        "scoop" => parse_scoop_export(output),
+        _ => BTreeMap::new(),
+    }
+}
+
+/// `choco list --limit-output`: one `name|version` per line
+pub fn parse_choco_list(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once('|'))
+        .filter(|(name, version)| !name.is_empty() && !version.is_empty())
+        .map(|(name, version)| (name.to_string(), version.to_string()))
+        .collect()
+}
+
+/// `scoop export` prints JSON (`{"apps":[{"Name":..,"Version":..}]}`) on current
+/// releases and `name (v:version) [bucket]` lines on older ones
+pub fn parse_scoop_export(output: &str) -> BTreeMap<String, String> {
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(output) {
+        return json["apps"]
+            .as_array()
+            .map(|apps| {
+                apps.iter()
+                    .filter_map(|app| {
+                        Some((
+                            app["Name"].as_str()?.to_string(),
+                            app["Version"].as_str()?.to_string(),
+                        ))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, rest) = line.trim().split_once(" (v:")?;
+            let (version, _) = rest.split_once(')')?;
+            Some((name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+/// `winget list` prints a fixed-width table; columns are located from the header
+/// line, and rows are keyed by package id
+pub fn parse_winget_list(output: &str) -> BTreeMap<String, String> {
+    // winget redraws its progress spinner with carriage returns
+    let lines: Vec<&str> = output
+        .split(['\r', '\n'])
+        .filter(|l| !l.trim().is_empty())
+        .collect();
+
+    let Some(header_index) = lines.iter().position(|l| {
+        let words: Vec<&str> = l.split_whitespace().collect();
+        words.contains(&"Id") && words.contains(&"Version")
+    }) else {
+        return BTreeMap::new();
+    };
+
+    let header: Vec<char> = lines[header_index].chars().collect();
+    let column = |name: &str| -> Option<usize> {
+        let name: Vec<char> = name.chars().collect();
+        (0..header.len()).find(|&i| {
+            header[i..].starts_with(&name)
+                && (i == 0 || header[i - 1] == ' ')
+                && header.get(i + name.len()).is_none_or(|c| *c == ' ')
+        })
+    };
+    let (Some(id_col), Some(version_col)) = (column("Id"), column("Version")) else {
+        return BTreeMap::new();
+    };
+    let version_end = column("Available").or_else(|| column("Source"));
+
+    let cell = |row: &[char], start: usize, end: Option<usize>| -> String {
+        let end = end.unwrap_or(row.len()).min(row.len());
+        if start >= end {
+            return String::new();
+        }
+        row[start..end]
+            .iter()
+            .collect::<String>()
+            .trim()
+            .to_string()
+    };
+
+    lines[header_index + 1..]
+        .iter()
+        .filter(|l| !l.trim_start().starts_with('-'))
+        .filter_map(|line| {
+            let row: Vec<char> = line.chars().collect();
+            let id = cell(&row, id_col, Some(version_col));
+            let version = cell(&row, version_col, version_end);
+            (!id.is_empty() && !version.is_empty() && !id.contains(' ')).then_some((id, version))
+        })
+        .collect()
+}
+
+/// Most useful line explaining a failure: stderr if there is any, else the last
+/// line of stdout that mentions an error, else the last line of stdout
+fn failure_reason(stdout: &str, stderr: &str) -> String {
+    let stderr = stderr.trim();
+    if !stderr.is_empty() {
+        return stderr.to_string();
+    }
+
+    let lines: Vec<&str> = stdout
+        .split(['\r', '\n'])
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    lines
+        .iter()
+        .rev()
+        .find(|l| l.to_lowercase().contains("error") || l.to_lowercase().contains("failed"))
+        .or_else(|| lines.last())
+        .map(|l| l.to_string())
+        .unwrap_or_else(|| "no output".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_command() {
+        assert_eq!(
+            package_command("choco", "install", "git").unwrap(),
+            "choco install git -y --no-progress"
+        );
+        assert!(package_command("winget", "update", "Git.Git")
+            .unwrap()
+            .starts_with("winget upgrade --id Git.Git --exact"));
+        assert!(package_command("scoop", "purge", "git").is_err());
+        assert!(package_command("apt", "install", "git").is_err());
+    }
+
+    #[test]
+    fn test_interpret_result() {
+        assert!(interpret_result("choco", "install", "git", 3010, "", "")
+            .unwrap()
+            .contains("reboot required"));
+        assert_eq!(
+            interpret_result(
+                "winget",
+                "update",
+                "Git.Git",
+                WINGET_UPDATE_NOT_APPLICABLE as i32,
+                "",
+                ""
+            )
+            .unwrap(),
+            "Git.Git is already up to date"
+        );
+
+        let err = interpret_result(
+            "choco",
+            "install",
+            "nope",
+            1,
+            "Installing the following packages:\r\nnope\r\nnope not installed. The package was not found with the source(s) listed.\r\nChocolatey installed 0/1 packages. 1 packages failed.\r\n",
+            "",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("1 packages failed"));
+
+        // scoop reports a missing manifest with exit code 0
+        assert!(interpret_result(
+            "scoop",
+            "install",
+            "nope",
+            0,
+            "Couldn't find manifest for 'nope'.",
+            ""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_winget_list() {
+        let output = "\r   - \r   \\ \r\
+Name                       Id                       Version       Available Source\r\n\
+----------------------------------------------------------------------------------\r\n\
+Git                        Git.Git                  2.43.0        2.44.0    winget\r\n\
+Microsoft Edge             Microsoft.Edge           122.0.2365.59           winget\r\n\
+Some Legacy App            ARP\\Machine\\X64\\{1234}   1.0\r\n";
+
+        let packages = parse_winget_list(output);
+        assert_eq!(packages.get("Git.Git").map(String::as_str), Some("2.43.0"));
+        assert_eq!(
+            packages.get("Microsoft.Edge").map(String::as_str),
+            Some("122.0.2365.59")
+        );
+        assert_eq!(packages.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_choco_and_scoop_lists() {
+        let choco = parse_choco_list("chocolatey|2.2.2\r\ngit|2.44.0\r\n");
+        assert_eq!(choco.get("git").map(String::as_str), Some("2.44.0"));
+
+        let scoop_json =
+            r#"{"buckets":[],"apps":[{"Name":"7zip","Version":"23.01","Source":"main"}]}"#;
+        assert_eq!(
+            parse_scoop_export(scoop_json)
+                .get("7zip")
+                .map(String::as_str),
+            Some("23.01")
+        );
+        let scoop_text = "7zip (v:23.01) [main]\ngit (v:2.44.0) [main]\n";
+        assert_eq!(parse_scoop_export(scoop_text).len(), 2);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::events::{OutputForwarder, OutputStream};
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use regex::Regex;
+use std::time::Duration;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Default WinRM listener ports; 5985 is plain HTTP, everything else is treated as HTTPS
+pub const WINRM_HTTP_PORT: u16 = 5985;
+pub const WINRM_HTTPS_PORT: u16 = 5986;
+
+const SHELL_RESOURCE_URI: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/cmd";
+const ACTION_CREATE: &str = "http://schemas.xmlsoap.org/ws/2004/09/transfer/Create";
+const ACTION_DELETE: &str = "http://schemas.xmlsoap.org/ws/2004/09/transfer/Delete";
+const ACTION_COMMAND: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/Command";
+const ACTION_RECEIVE: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/Receive";
+const ACTION_SIGNAL: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/Signal";
+const SIGNAL_TERMINATE: &str =
+    "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/signal/terminate";
+
+/// Seconds the server may hold a Receive open before answering with a timeout fault
+const OPERATION_TIMEOUT_SECS: u64 = 60;
+
+/// Output of a command run through a WinRM shell
+#[derive(Debug, Clone, Default)]
+pub struct WinRmOutput {
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl WinRmOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+/// One Receive response: decoded stream data and, once the command is done, its exit code
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ReceiveChunk {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_code: Option<i32>,
+}
+
+/// Minimal WS-Management client that runs cmd.exe commands on a Windows host.
+///
+/// Authenticates with Basic auth, so the listener needs `Basic="true"` and a local
+/// account; use HTTPS (port 5986) so the password is not sent in the clear.
+pub struct WinRmClient {
+    endpoint: String,
+    username: String,
+    password: String,
+    http: reqwest::Client,
+}
+
+impl WinRmClient {
+    pub fn new(address: &str, port: u16, username: &str, password: &str) -> Result<Self> {
+        let endpoint = endpoint_url(address, port);
+        if endpoint.starts_with("http://") {
+            warn!(
+                "WinRM to {} uses plain HTTP; credentials are only base64-encoded",
+                address
+            );
+        }
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(OPERATION_TIMEOUT_SECS + 30))
+            .build()?;
+
+        Ok(Self {
+            endpoint,
+            username: username.to_string(),
+            password: password.to_string(),
+            http,
+        })
+    }
+
+    /// Run `command` through cmd.exe in a fresh remote shell and collect its output
+    pub async fn run_command(&self, command: &str) -> Result<WinRmOutput> {
+        self.run_command_forwarded(command, None).await
+    }
+
+    /// Like [`run_command`](Self::run_command), also handing each chunk of output
+    /// to `forwarder` as the server returns it
+    pub async fn run_command_forwarded(
+        &self,
+        command: &str,
+        forwarder: Option<&mut OutputForwarder>,
+    ) -> Result<WinRmOutput> {
+        let shell_id = self.open_shell().await?;
+        let result = self.run_in_shell(&shell_id, command, forwarder).await;
+
+        if let Err(e) = self.close_shell(&shell_id).await {
+            debug!("Failed to delete WinRM shell {}: {}", shell_id, e);
+        }
+
+        result
+    }
+
+    async fn open_shell(&self) -> Result<String> {
+        let options = concat!(
+            "<w:OptionSet>",
+            "<w:Option Name=\"WINRS_NOPROFILE\">FALSE</w:Option>",
+            "<w:Option Name=\"WINRS_CODEPAGE\">65001</w:Option>",
+            "</w:OptionSet>"
+        );
+        let body = concat!(
+            "<rsp:Shell>",
+            "<rsp:InputStreams>stdin</rsp:InputStreams>",
+            "<rsp:OutputStreams>stdout stderr</rsp:OutputStreams>",
+            "</rsp:Shell>"
+        );
+
+        let response = self
+            .send(&self.envelope(ACTION_CREATE, None, options, body))
+            .await?;
+        extract_element(&response, "ShellId")
+            .ok_or_else(|| anyhow!("WinRM did not return a shell id"))
+    }
+
+    async fn run_in_shell(
+        &self,
+        shell_id: &str,
+        command: &str,
+        mut forwarder: Option<&mut OutputForwarder>,
+    ) -> Result<WinRmOutput> {
+        let options = concat!(
+            "<w:OptionSet>",
+            "<w:Option Name=\"WINRS_CONSOLEMODE_STDIN\">TRUE</w:Option>",
+            "<w:Option Name=\"WINRS_SKIP_CMD_SHELL\">FALSE</w:Option>",
+            "</w:OptionSet>"
+        );
+        let body = format!(
+            "<rsp:CommandLine><rsp:Command>{}</rsp:Command></rsp:CommandLine>",
+            xml_escape(command)
+        );
+
+        let response = self
+            .send(&self.envelope(ACTION_COMMAND, Some(shell_id), options, &body))
+            .await?;
+        let command_id = extract_element(&response, "CommandId")
+            .ok_or_else(|| anyhow!("WinRM did not return a command id"))?;
+
+        let receive = format!(
+            "<rsp:Receive><rsp:DesiredStream CommandId=\"{}\">stdout stderr</rsp:DesiredStream></rsp:Receive>",
+            command_id
+        );
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+
+        let exit_code = loop {
+            let response = match self
+                .send(&self.envelope(ACTION_RECEIVE, Some(shell_id), "", &receive))
+                .await
+            {
+                Ok(response) => response,
+                // Nothing was written within the operation timeout; keep waiting
+                Err(e) if e.to_string().contains("TimedOut") => continue,
+                Err(e) => return Err(e),
+            };
+
+            let chunk = parse_receive(&response)?;
+            if let Some(forwarder) = forwarder.as_deref_mut() {
+                forwarder.on_data(OutputStream::Stdout, &chunk.stdout);
+                forwarder.on_data(OutputStream::Stderr, &chunk.stderr);
+            }
+            stdout.extend(chunk.stdout);
+            stderr.extend(chunk.stderr);
+            if let Some(code) = chunk.exit_code {
+                break code;
+            }
+        };
+
+        if let Some(forwarder) = forwarder {
+            forwarder.finish();
+        }
+
+        let signal = format!(
+            "<rsp:Signal CommandId=\"{}\"><rsp:Code>{}</rsp:Code></rsp:Signal>",
+            command_id, SIGNAL_TERMINATE
+        );
+        if let Err(e) = self
+            .send(&self.envelope(ACTION_SIGNAL, Some(shell_id), "", &signal))
+            .await
+        {
+            debug!("Failed to signal WinRM command {}: {}", command_id, e);
+        }
+
+        Ok(WinRmOutput {
+            exit_code,
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        })
+    }
+
+    async fn close_shell(&self, shell_id: &str) -> Result<()> {
+        self.send(&self.envelope(ACTION_DELETE, Some(shell_id), "", ""))
+            .await
+            .map(|_| ())
+    }
+
+    fn envelope(&self, action: &str, shell_id: Option<&str>, options: &str, body: &str) -> String {
+        let selector = shell_id
+            .map(|id| {
+                format!(
+                    "<w:SelectorSet><w:Selector Name=\"ShellId\">{}</w:Selector></w:SelectorSet>",
+                    xml_escape(id)
+                )
+            })
+            .unwrap_or_default();
+
+        format!(
+            concat!(
+                "<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" ",
+                "xmlns:a=\"http://schemas.xmlsoap.org/ws/2004/08/addressing\" ",
+                "xmlns:w=\"http://schemas.dmtf.org/wbem/wsman/1/wsman.xsd\" ",
+                "xmlns:rsp=\"http://schemas.microsoft.com/wbem/wsman/1/windows/shell\">",
+                "<s:Header>",
+                "<a:To>{endpoint}</a:To>",
+                "<a:ReplyTo><a:Address s:mustUnderstand=\"true\">",
+                "http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous",
+                "</a:Address></a:ReplyTo>",
+                "<w:MaxEnvelopeSize s:mustUnderstand=\"true\">153600</w:MaxEnvelopeSize>",
+                "<a:MessageID>uuid:{message_id}</a:MessageID>",
+                "<w:Locale xml:lang=\"en-US\" s:mustUnderstand=\"false\"/>",
+                "<w:OperationTimeout>PT{timeout}S</w:OperationTimeout>",
+                "<w:ResourceURI s:mustUnderstand=\"true\">{resource}</w:ResourceURI>",
+                "<a:Action s:mustUnderstand=\"true\">{action}</a:Action>",
+                "{selector}{options}",
+                "</s:Header>",
+                "<s:Body>{body}</s:Body>",
+                "</s:Envelope>"
+            ),
+            endpoint = xml_escape(&self.endpoint),
+            message_id = Uuid::new_v4(),
+            timeout = OPERATION_TIMEOUT_SECS,
+            resource = SHELL_RESOURCE_URI,
+            action = action,
+            selector = selector,
+            options = options,
+            body = body,
+        )
+    }
+
+    async fn send(&self, envelope: &str) -> Result<String> {
+        let response = self
+            .http
+            .post(&self.endpoint)
+            .basic_auth(&self.username, Some(&self.password))
+            .header("Content-Type", "application/soap+xml;charset=UTF-8")
+            .body(envelope.to_string())
+            .send()
+            .await
+            .map_err(|e| anyhow!("WinRM request to {} failed: {}", self.endpoint, e))?;
+
+        let status = response.status();
+        let text = response.text().await?;
+
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(anyhow!(
+                "WinRM rejected the credentials for {} (Basic auth must be enabled for a local account)",
+                self.username
+            ));
+        }
+        if !status.is_success() {
+            return Err(anyhow!(
+                "WinRM fault ({}): {}",
+                status,
+                fault_message(&text)
+            ));
+        }
+
+        Ok(text)
+    }
+}
+
+/// `http(s)://address:port/wsman`, bracketing bare IPv6 addresses
+pub fn endpoint_url(address: &str, port: u16) -> String {
+    let scheme = if port == WINRM_HTTP_PORT {
+        "http"
+    } else {
+        "https"
+    };
+    let host = if address.contains(':') && !address.starts_with('[') {
+        format!("[{}]", address)
+    } else {
+        address.to_string()
+    };
+    format!("{}://{}:{}/wsman", scheme, host, port)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Text of the first element named `local_name` (any namespace prefix), or of a
+/// selector with that name
+fn extract_element(xml: &str, local_name: &str) -> Option<String> {
+    let element = Regex::new(&format!(
+        r"<(?:\w+:)?{name}(?:\s[^>]*)?>([^<]+)</(?:\w+:)?{name}>",
+        name = local_name
+    ))
+    .ok()?;
+    let selector = Regex::new(&format!(
+        r#"<(?:\w+:)?Selector\s+Name="{}"[^>]*>([^<]+)<"#,
+        local_name
+    ))
+    .ok()?;
+
+    element
+        .captures(xml)
+        .or_else(|| selector.captures(xml))
+        .map(|c| c[1].trim().to_string())
+}
+
+/// Decode the base64 stream data and completion state of a Receive response
+fn parse_receive(xml: &str) -> Result<ReceiveChunk> {
+    let stream =
+        Regex::new(r#"<(?:\w+:)?Stream\s[^>]*?Name="(\w+)"[^>]*>([^<]*)</(?:\w+:)?Stream>"#)?;
+    let done = Regex::new(r#"State="[^"]*CommandState/Done""#)?;
+
+    let mut chunk = ReceiveChunk::default();
+    for capture in stream.captures_iter(xml) {
+        let data = capture[2].trim();
+        if data.is_empty() {
+            continue;
+        }
+        let bytes = STANDARD
+            .decode(data)
+            .map_err(|e| anyhow!("Invalid WinRM stream data: {}", e))?;
+        match &capture[1] {
+            "stdout" => chunk.stdout.extend(bytes),
+            "stderr" => chunk.stderr.extend(bytes),
+            _ => {}
+        }
+    }
+
+    if done.is_match(xml) {
+        // Windows reports HRESULT exit codes (winget) as unsigned 32-bit values
+        let code = extract_element(xml, "ExitCode")
+            .and_then(|c| c.parse::<i64>().ok())
+            .unwrap_or(0);
+        chunk.exit_code = Some(code as i32);
+    }
+
+    Ok(chunk)
+}
+
+/// Human-readable reason from a SOAP fault body
+fn fault_message(xml: &str) -> String {
+    // The innermost (last) fault code value is the most specific one
+    let code = Regex::new(r"<(?:\w+:)?Value>([^<]+)</")
+        .ok()
+        .and_then(|re| {
+            re.captures_iter(xml)
+                .last()
+                .map(|c| c[1].trim().to_string())
+        })
+        .unwrap_or_default();
+    let message = extract_element(xml, "Message")
+        .or_else(|| extract_element(xml, "Text"))
+        .unwrap_or_else(|| xml.chars().take(200).collect());
+
+    // The timeout fault is how a Receive with no new output ends
+    if xml.contains("w:TimedOut") || xml.contains("2150858793") {
+        format!("TimedOut: {}", message)
+    } else if code.is_empty() {
+        message
+    } else {
+        format!("{}: {}", code, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_url() {
+        assert_eq!(
+            endpoint_url("win1.example.com", WINRM_HTTPS_PORT),
+            "https://win1.example.com:5986/wsman"
+        );
+        assert_eq!(
+            endpoint_url("10.0.0.5", WINRM_HTTP_PORT),
+            "http://10.0.0.5:5985/wsman"
+        );
+        assert_eq!(
+            endpoint_url("fe80::1", 5986),
+            "https://[fe80::1]:5986/wsman"
+        );
+    }
+
+    #[test]
+    fn test_parse_receive() {
+        let running = r#"<s:Envelope><s:Body><rsp:ReceiveResponse>
+<rsp:Stream Name="stdout" CommandId="C1">aGVsbG8g</rsp:Stream>
+<rsp:Stream Name="stderr" CommandId="C1"></rsp:Stream>
+<rsp:CommandState CommandId="C1" State="http://schemas.microsoft.com/wbem/wsman/1/windows/shell/CommandState/Running"/>
+</rsp:ReceiveResponse></s:Body></s:Envelope>"#;
+        let chunk = parse_receive(running).unwrap();
+        assert_eq!(chunk.stdout, b"hello ");
+        assert_eq!(chunk.exit_code, None);
+
+        let done = r#"<rsp:ReceiveResponse>
+<rsp:Stream Name="stdout" CommandId="C1">d29ybGQ=</rsp:Stream>
+<rsp:Stream Name="stdout" CommandId="C1" End="true"></rsp:Stream>
+<rsp:Stream Name="stderr" CommandId="C1" End="true"/>
+<rsp:CommandState CommandId="C1" State="http://schemas.microsoft.com/wbem/wsman/1/windows/shell/CommandState/Done">
+<rsp:ExitCode>2316632107</rsp:ExitCode></rsp:CommandState>
+</rsp:ReceiveResponse>"#;
+        let chunk = parse_receive(done).unwrap();
+        assert_eq!(chunk.stdout, b"world");
+        assert!(chunk.stderr.is_empty());
+        assert_eq!(chunk.exit_code, Some(0x8A15_002Bu32 as i32));
+    }
+
+    #[test]
+    fn test_extract_shell_id_and_fault() {
+        let created = r#"<s:Body><x:ResourceCreated><a:ReferenceParameters><w:SelectorSet>
+<w:Selector Name="ShellId">11111111-2222-3333-4444-555555555555</w:Selector>
+</w:SelectorSet></a:ReferenceParameters></x:ResourceCreated>
+<rsp:Shell><rsp:ShellId>11111111-2222-3333-4444-555555555555</rsp:ShellId></rsp:Shell></s:Body>"#;
+        assert_eq!(
+            extract_element(created, "ShellId").as_deref(),
+            Some("11111111-2222-3333-4444-555555555555")
+        );
+
+        let fault = r#"<s:Fault><s:Code><s:Value>s:Receiver</s:Value><s:Subcode><s:Value>w:TimedOut</s:Value></s:Subcode></s:Code>
+<s:Reason><s:Text xml:lang="en-US">The WS-Management service cannot complete the operation within the time specified in OperationTimeout.</s:Text></s:Reason></s:Fault>"#;
+        assert!(fault_message(fault).starts_with("TimedOut"));
+        assert_eq!(xml_escape("a & <b>"), "a &amp; &lt;b&gt;");
+    }
+}
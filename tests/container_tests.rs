@@ -0,0 +1,227 @@
+//! End-to-end tests of the box backends inside disposable containers.
+//!
+//! Each test starts a container for one distribution, mounts the freshly built
+//! `omni` binary into it and runs real search, install, list and remove flows
+//! against the distribution's package manager. They need Docker or Podman and
+//! network access, so they only run when `OMNI_CONTAINER_TESTS` is set:
+//!
+//! ```text
+//! OMNI_CONTAINER_TESTS=1 cargo test --test container_tests
+//! ```
+//!
+//! The host binary is dynamically linked against glibc, so the images must ship
+//! a glibc at least as new as the host's. Alpine uses musl and needs a static
+//! build passed in `OMNI_CONTAINER_MUSL_BINARY`.
+
+use omni::docker::{
+    DockerClient, DockerCommandResult, DockerConfig, ResourceLimits, SecurityOptions,
+    VolumeMapping, VolumeMode,
+};
+use std::path::{Path, PathBuf};
+
+struct Distro {
+    image: &'static str,
+    tag: &'static str,
+    box_type: &'static str,
+    /// Refreshes package metadata, which minimal images ship without
+    prepare: &'static str,
+    /// Succeeds while the test package is installed
+    query: &'static str,
+}
+
+/// Small package every distribution carries
+const PACKAGE: &str = "tree";
+
+const UBUNTU: Distro = Distro {
+    image: "ubuntu",
+    tag: "24.04",
+    box_type: "apt",
+    prepare: "apt-get update",
+    query: "dpkg -s tree",
+};
+
+const FEDORA: Distro = Distro {
+    image: "fedora",
+    tag: "40",
+    box_type: "dnf",
+    prepare: "dnf makecache",
+    query: "rpm -q tree",
+};
+
+const ARCH: Distro = Distro {
+    image: "archlinux",
+    tag: "latest",
+    box_type: "pacman",
+    prepare: "pacman -Sy --noconfirm",
+    query: "pacman -Q tree",
+};
+
+fn container_tests_enabled() -> bool {
+    if std::env::var("OMNI_CONTAINER_TESTS").is_ok() {
+        return true;
+    }
+    println!("Skipping container tests - set OMNI_CONTAINER_TESTS to enable");
+    false
+}
+
+/// A running container with omni mounted at /usr/local/bin/omni, removed on drop
+struct TestContainer {
+    client: DockerClient,
+    id: String,
+}
+
+impl TestContainer {
+    async fn start(image: &str, tag: &str, binary: &Path) -> Self {
+        let client = DockerClient::new()
+            .await
+            .expect("container tests need Docker or Podman");
+        assert!(
+            client.check_daemon().await.unwrap_or(false),
+            "the {} daemon is not reachable",
+            client.runtime()
+        );
+        client
+            .pull_image(image, tag)
+            .await
+            .expect("failed to pull image");
+
+        let config = DockerConfig {
+            image: image.to_string(),
+            tag: tag.to_string(),
+            volumes: vec![VolumeMapping {
+                host_path: binary.to_path_buf(),
+                container_path: "/usr/local/bin/omni".to_string(),
+                mode: VolumeMode::ReadOnly,
+            }],
+            // Package managers chown, setuid and bind sockets as root
+            security_options: SecurityOptions {
+                capabilities_drop: Vec::new(),
+                capabilities_add: Vec::new(),
+                ..SecurityOptions::default()
+            },
+            resources: ResourceLimits {
+                memory_mb: Some(2048),
+                cpu_cores: Some(2.0),
+                disk_space_mb: None,
+            },
+            command: vec!["sleep".to_string(), "infinity".to_string()],
+            ..DockerConfig::default()
+        };
+        let id = client
+            .create_container(&config)
+            .await
+            .expect("failed to start container");
+
+        let container = Self { client, id };
+        // omni expects its data directory to exist on first run
+        container
+            .sh("mkdir -p /root/.local/share/omni && touch /root/.local/share/omni/omni.db")
+            .await;
+        container
+    }
+
+    async fn sh(&self, command: &str) -> DockerCommandResult {
+        self.client
+            .execute_command(&self.id, command, Some("root"))
+            .await
+            .expect("failed to exec in container")
+    }
+
+    async fn omni(&self, args: &str) -> DockerCommandResult {
+        self.sh(&format!("omni {}", args)).await
+    }
+}
+
+impl Drop for TestContainer {
+    fn drop(&mut self) {
+        let _ = std::process::Command::new(self.client.runtime())
+            .args(["rm", "-f", &self.id])
+            .output();
+    }
+}
+
+fn assert_success(result: &DockerCommandResult) {
+    assert!(
+        result.success(),
+        "`{}` exited with {}\nstdout:\n{}\nstderr:\n{}",
+        result.command,
+        result.exit_code,
+        result.stdout,
+        result.stderr
+    );
+}
+
+/// Search for, install, list and remove the test package via `distro`'s box
+async fn check_box_flows(distro: &Distro) {
+    if !container_tests_enabled() {
+        return;
+    }
+
+    let binary = PathBuf::from(env!("CARGO_BIN_EXE_omni"));
+    let container = TestContainer::start(distro.image, distro.tag, &binary).await;
+    assert_success(&container.sh(distro.prepare).await);
+
+    let search = container.omni(&format!("search {}", PACKAGE)).await;
+    assert_success(&search);
+    assert!(
+        search.stdout.contains(PACKAGE),
+        "search found no {}:\n{}",
+        PACKAGE,
+        search.stdout
+    );
+
+    let install = format!("install {} --box-type {}", PACKAGE, distro.box_type);
+    assert_success(&container.omni(&install).await);
+    assert_success(&container.sh(distro.query).await);
+
+    let list = container.omni("list").await;
+    assert_success(&list);
+    assert!(
+        list.stdout.contains(PACKAGE),
+        "list is missing {}:\n{}",
+        PACKAGE,
+        list.stdout
+    );
+
+    let remove = format!("remove {} --box-type {}", PACKAGE, distro.box_type);
+    assert_success(&container.omni(&remove).await);
+    assert!(
+        !container.sh(distro.query).await.success(),
+        "{} is still installed after omni remove",
+        PACKAGE
+    );
+}
+
+#[tokio::test]
+async fn test_apt_box_in_ubuntu_container() {
+    check_box_flows(&UBUNTU).await;
+}
+
+#[tokio::test]
+async fn test_dnf_box_in_fedora_container() {
+    check_box_flows(&FEDORA).await;
+}
+
+#[tokio::test]
+async fn test_pacman_box_in_arch_container() {
+    check_box_flows(&ARCH).await;
+}
+
+/// omni has no apk box; on Alpine it must fail cleanly instead of panicking
+#[tokio::test]
+async fn test_unsupported_distro_in_alpine_container() {
+    if !container_tests_enabled() {
+        return;
+    }
+    let Ok(binary) = std::env::var("OMNI_CONTAINER_MUSL_BINARY") else {
+        println!("Skipping Alpine - set OMNI_CONTAINER_MUSL_BINARY to a static omni build");
+        return;
+    };
+
+    let container = TestContainer::start("alpine", "latest", Path::new(&binary)).await;
+
+    let install = container.omni(&format!("install {}", PACKAGE)).await;
+    assert!(!install.success(), "install succeeded without a backend");
+    assert!(!install.stderr.contains("panicked"), "{}", install.stderr);
+    assert_success(&container.omni("list").await);
+}
@@ -160,6 +160,9 @@ mod database_tests {
             install_path: Some("/usr/bin/test-package".to_string()),
             installed_at: Utc::now(),
             status: InstallStatus::Success,
+            repository: None,
+            mirror_url: None,
+            channel: None,
             metadata: Some("{\"test\": true}".to_string()),
         };
 
@@ -204,6 +207,9 @@ mod database_tests {
                 install_path: None,
                 installed_at: Utc::now(),
                 status: InstallStatus::Success,
+                repository: None,
+                mirror_url: None,
+                channel: None,
                 metadata: None,
             },
             InstallRecord {
@@ -215,6 +221,9 @@ mod database_tests {
                 install_path: None,
                 installed_at: Utc::now(),
                 status: InstallStatus::Success,
+                repository: None,
+                mirror_url: None,
+                channel: None,
                 metadata: None,
             },
         ];
@@ -225,6 +234,8 @@ mod database_tests {
             description: Some("Test snapshot for unit tests".to_string()),
             created_at: Utc::now(),
             packages,
+            backend: None,
+            backend_ref: None,
         };
 
         assert_eq!(snapshot.name, "test-snapshot");
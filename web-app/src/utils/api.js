@@ -6,9 +6,15 @@
 
 const API_BASE = 'http://localhost:3000/api';
 
+// `omni serve` requires the token from ~/.local/state/omni/api-token
+const authHeaders = () => {
+    const token = localStorage.getItem('omniApiToken');
+    return token ? { Authorization: `Bearer ${token}` } : {};
+};
+
 const isBackendAvailable = async () => {
     try {
-        await fetch(`${API_BASE}/system/info`, { method: 'HEAD' });
+        await fetch(`${API_BASE}/system/info`, { method: 'HEAD', headers: authHeaders() });
         return true;
     } catch (e) {
         return false;
@@ -18,7 +24,7 @@ const isBackendAvailable = async () => {
 export const api = {
     getSystemInfo: async () => {
         try {
-            const res = await fetch(`${API_BASE}/system/info`);
+            const res = await fetch(`${API_BASE}/system/info`, { headers: authHeaders() });
             if (!res.ok) throw new Error('Failed to fetch');
             return await res.json();
         } catch (e) {
@@ -33,7 +39,7 @@ export const api = {
 
     getInstalledPackages: async () => {
         try {
-            const res = await fetch(`${API_BASE}/packages/installed`);
+            const res = await fetch(`${API_BASE}/packages/installed`, { headers: authHeaders() });
             if (!res.ok) throw new Error('Failed to fetch');
             return await res.json();
         } catch (e) {
@@ -49,7 +55,7 @@ export const api = {
 
     searchPackages: async (query) => {
         try {
-            const res = await fetch(`${API_BASE}/packages/search?q=${encodeURIComponent(query)}`);
+            const res = await fetch(`${API_BASE}/packages/search?q=${encodeURIComponent(query)}`, { headers: authHeaders() });
             if (!res.ok) throw new Error('Failed to fetch');
             return await res.json();
         } catch (e) {